@@ -0,0 +1,5 @@
+extern crate plank_ir;
+
+mod printer;
+
+pub use printer::emit_program;
@@ -0,0 +1,1013 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::io::prelude::*;
+use plank_ir::ir::{
+    self, BinaryOp, BitOp, BlockEnd, Instruction, IntOp, Layout, Overflow, Program, Reg,
+    Signedness, Size, Symbol, UnaryOp, Value,
+};
+use plank_ir::target::{Arch, CallingConvention};
+
+
+// `stdlib.h` is not included, so that programs can declare functions
+// like `exit` themselves. Runtime errors jump back to `main` instead.
+// `clock_gettime` is POSIX, so it has to be asked for in strict C99.
+const PRELUDE: &str = "#define _POSIX_C_SOURCE 199309L
+#include <setjmp.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+#include <time.h>
+
+static jmp_buf plank_exit;
+static uint32_t plank_rand_state = 2463534242u;
+static uint32_t plank_time_start;
+
+static uint32_t plank_time_now(void) {
+    struct timespec now;
+    clock_gettime(CLOCK_MONOTONIC, &now);
+    return (uint32_t)now.tv_sec * 1000u + (uint32_t)(now.tv_nsec / 1000000);
+}
+
+static void plank_trap(const char *message) {
+    fflush(stdout);
+    fprintf(stderr, \"%s\\n\", message);
+    longjmp(plank_exit, 1);
+}
+
+static uint8_t plank_read8(const void *p) { uint8_t v; memcpy(&v, p, 1); return v; }
+static uint16_t plank_read16(const void *p) { uint16_t v; memcpy(&v, p, 2); return v; }
+static uint32_t plank_read32(const void *p) { uint32_t v; memcpy(&v, p, 4); return v; }
+static uint64_t plank_read64(const void *p) { uint64_t v; memcpy(&v, p, 8); return v; }
+
+static uint64_t plank_div_u(uint64_t a, uint64_t b) {
+    if (b == 0) plank_trap(\"division by zero\");
+    return a / b;
+}
+
+static uint64_t plank_mod_u(uint64_t a, uint64_t b) {
+    if (b == 0) plank_trap(\"division by zero\");
+    return a % b;
+}
+
+static uint64_t plank_div_s(int64_t a, int64_t b) {
+    if (b == 0) plank_trap(\"division by zero\");
+    if (b == -1) return 0 - (uint64_t)a;
+    return (uint64_t)(a / b);
+}
+
+static uint64_t plank_mod_s(int64_t a, int64_t b) {
+    if (b == 0) plank_trap(\"division by zero\");
+    if (b == -1) return 0;
+    return (uint64_t)(a % b);
+}
+
+static uint64_t plank_checked_u(char op, int saturate, int bits, uint64_t a, uint64_t b) {
+    uint64_t max = bits == 64 ? UINT64_MAX : ((uint64_t)1 << bits) - 1;
+    uint64_t result = 0, limit = max;
+    int overflow = 0;
+    switch (op) {
+    case '+': overflow = a > max - b; result = a + b; break;
+    case '-': overflow = a < b; result = a - b; limit = 0; break;
+    case '*': overflow = a != 0 && b > max / a; result = a * b; break;
+    default: result = plank_div_u(a, b); break;
+    }
+    if (!overflow) return result;
+    if (saturate) return limit;
+    fflush(stdout);
+    fprintf(stderr, \"arithmetic overflow: %llu %c %llu in u%d\\n\",
+            (unsigned long long)a, op, (unsigned long long)b, bits);
+    longjmp(plank_exit, 1);
+}
+
+static uint64_t plank_checked_s(char op, int saturate, int bits, int64_t a, int64_t b) {
+    int64_t max = bits == 64 ? INT64_MAX : ((int64_t)1 << (bits - 1)) - 1;
+    int64_t min = -max - 1;
+    int64_t result = 0, limit = max;
+    int overflow = 0;
+    switch (op) {
+    case '+':
+        overflow = b > 0 ? a > max - b : a < min - b;
+        limit = b > 0 ? max : min;
+        if (!overflow) result = a + b;
+        break;
+    case '-':
+        overflow = b < 0 ? a > max + b : a < min + b;
+        limit = b < 0 ? max : min;
+        if (!overflow) result = a - b;
+        break;
+    case '*':
+        if (a > 0) overflow = b > 0 ? a > max / b : b < min / a;
+        else overflow = b > 0 ? a < min / b : a != 0 && b < max / a;
+        limit = (a < 0) == (b < 0) ? max : min;
+        if (!overflow) result = a * b;
+        break;
+    default:
+        overflow = a == min && b == -1;
+        if (!overflow) result = (int64_t)plank_div_s(a, b);
+        break;
+    }
+    if (!overflow) return (uint64_t)result;
+    if (saturate) return (uint64_t)limit;
+    fflush(stdout);
+    fprintf(stderr, \"arithmetic overflow: %lld %c %lld in i%d\\n\",
+            (long long)a, op, (long long)b, bits);
+    longjmp(plank_exit, 1);
+}
+";
+
+/// Escape a symbol to a valid C identifier, in the same way as the x86
+/// backend escapes labels. `_` is escaped too, so escaped names never
+/// collide with each other or with names used by the prelude.
+fn mangle(name: &str) -> String {
+    let mut result = String::new();
+    for ch in name.chars() {
+        match ch {
+            'a'..='z' |
+            'A'..='Z' |
+            '0'..='9' => result.push(ch),
+            '_' => result.push_str("_u"),
+            ',' => result.push_str("_c"),
+            '<' => result.push_str("_l"),
+            '>' => result.push_str("_g"),
+            ':' => result.push_str("_o"),
+            '*' => result.push_str("_s"),
+            '(' => result.push_str("_p"),
+            ')' => result.push_str("_r"),
+            '-' => result.push_str("_d"),
+            '[' => result.push_str("_b"),
+            ']' => result.push_str("_e"),
+            c => result.push_str(&format!("_x{:x}_", c as u32)),
+        }
+    }
+    result
+}
+
+/// Name of the C function for given symbol. Functions without a body
+/// keep their plank name, so that they can be implemented in C.
+fn function_name(name: &Symbol, f: &ir::Function) -> String {
+    if f.start_block.is_none() && !is_builtin(name) && name.0.starts_with("fn_") {
+        let plain = &name.0[3..];
+        if plain.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return plain.to_string();
+        }
+    }
+    mangle(&name.0)
+}
+
+fn int_type(size: u32) -> &'static str {
+    match size {
+        1 => "uint8_t",
+        2 => "uint16_t",
+        4 => "uint32_t",
+        8 => "uint64_t",
+        _ => panic!("no integer type of size {}", size),
+    }
+}
+
+fn signed_type(size: Size) -> &'static str {
+    match size {
+        Size::Bit8 => "int8_t",
+        Size::Bit16 => "int16_t",
+        Size::Bit32 => "int32_t",
+        Size::Bit64 => "int64_t",
+    }
+}
+
+fn bits(size: Size) -> u32 {
+    size.in_bytes() * 8
+}
+
+fn is_integer(layout: Layout) -> bool {
+    layout.atomic && matches!(layout.size, 1 | 2 | 4 | 8)
+}
+
+/// C type used for values of given layout. Composite values are
+/// stored in byte arrays wrapped in structs, so that they can be
+/// passed to and returned from functions.
+fn layout_type(layout: Layout) -> String {
+    if is_integer(layout) {
+        int_type(layout.size).to_string()
+    } else {
+        format!("plank_blob_{}", layout.size)
+    }
+}
+
+fn literal(value: u64, size: u32) -> String {
+    if size == 8 {
+        format!("UINT64_C({})", value)
+    } else {
+        format!("{}u", value)
+    }
+}
+
+struct Context<'a> {
+    program: &'a Program,
+    strings: HashMap<&'a [u8], usize>,
+}
+
+impl<'a> Context<'a> {
+    fn pointer_size(&self) -> u32 {
+        self.program.target.pointer_size.in_bytes()
+    }
+
+    fn atomic_layout(&self, size: u32) -> Layout {
+        Layout {
+            size,
+            align: self.program.target.scalar_align(size),
+            atomic: true,
+        }
+    }
+
+    fn function_name(&self, name: &Symbol) -> String {
+        function_name(name, &self.program.functions[name])
+    }
+
+    fn pointer_type(&self) -> &'static str {
+        int_type(self.pointer_size())
+    }
+
+    fn value_layout(&self, f: &ir::Function, value: &Value) -> Option<Layout> {
+        match *value {
+            Value::Reg(reg) => Some(f.registers[&reg]),
+            Value::Int(_, size) => Some(self.atomic_layout(size.in_bytes())),
+            Value::Symbol(_) | Value::Bytes(_) => Some(self.atomic_layout(self.pointer_size())),
+            Value::Undef => None,
+        }
+    }
+
+    /// Expression for a pointer-sized value holding given address.
+    fn address_of(&self, expr: &str) -> String {
+        format!("({})(uintptr_t){}", self.pointer_type(), expr)
+    }
+
+    /// Value as an integer of given size.
+    fn int_value(&self, f: &ir::Function, value: &Value, size: u32) -> String {
+        match *value {
+            Value::Reg(reg) if is_integer(f.registers[&reg]) => format!("r{}", reg.0),
+            Value::Reg(reg) => format!("plank_read{}(&r{})", size * 8, reg.0),
+            Value::Int(value, _) => literal(value, size),
+            Value::Symbol(ref sym) => {
+                self.address_of(&format!("&{}", self.function_name(sym)))
+            }
+            Value::Bytes(ref bytes) => {
+                self.address_of(&format!("string_{}", self.strings[&bytes[..]]))
+            }
+            Value::Undef => "0".to_string(),
+        }
+    }
+
+    /// Pointer to bytes of given value.
+    fn value_bytes(&self, f: &ir::Function, value: &Value) -> String {
+        match *value {
+            Value::Reg(reg) => format!("&r{}", reg.0),
+            Value::Undef => "plank_undef".to_string(),
+            _ => {
+                let size = self.pointer_size();
+                let size = self.value_layout(f, value).map(|l| l.size).unwrap_or(size);
+                let value = self.int_value(f, value, size);
+                format!("&({}){{{}}}", int_type(size), value)
+            }
+        }
+    }
+
+    /// Value converted to the C type of given layout.
+    fn typed_value(&self, f: &ir::Function, value: &Value, layout: Layout) -> String {
+        if is_integer(layout) {
+            return self.int_value(f, value, layout.size);
+        }
+        match *value {
+            Value::Reg(reg) if layout_type(f.registers[&reg]) == layout_type(layout) => {
+                format!("r{}", reg.0)
+            }
+            _ => {
+                let bytes = self.value_bytes(f, value);
+                format!("*(const {} *){}", layout_type(layout), bytes)
+            }
+        }
+    }
+
+    /// Statement that assigns expression `expr` of type `layout` to a register.
+    fn assign(&self, f: &ir::Function, reg: Reg, expr: &str, layout: Layout) -> String {
+        let reg_layout = f.registers[&reg];
+        if layout_type(reg_layout) == layout_type(layout) {
+            format!("r{} = {};", reg.0, expr)
+        } else {
+            let ty = layout_type(layout);
+            format!("{{ {} t = {}; memcpy(&r{}, &t, {}); }}", ty, expr, reg.0, reg_layout.size)
+        }
+    }
+
+    fn copy_value(&self, f: &ir::Function, to: &str, value: &Value) -> Option<String> {
+        let size = self.value_layout(f, value)?.size;
+        if size == 0 {
+            return None;
+        }
+        let from = self.value_bytes(f, value);
+        Some(format!("memcpy({}, {}, {});", to, from, size))
+    }
+
+    fn pointer_from(&self, f: &ir::Function, value: &Value, offset: u32) -> String {
+        let address = self.int_value(f, value, self.pointer_size());
+        format!("(uint8_t *)(uintptr_t){} + {}", address, offset)
+    }
+
+    fn args(&self, f: &ir::Function, callee: &ir::Function, args: &[Value]) -> String {
+        callee.parameters
+            .iter()
+            .zip(args)
+            .map(|(param, arg)| self.typed_value(f, arg, callee.registers[param]))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Cast value to a function pointer whose type is inferred from
+    /// arguments and the register receiving the result.
+    fn virtual_callee(&self, f: &ir::Function, value: &Value, ret: Option<Reg>, args: &[Value])
+        -> String
+    {
+        let ret = match ret {
+            Some(reg) => layout_type(f.registers[&reg]),
+            None => "void".to_string(),
+        };
+        let params = args
+            .iter()
+            .map(|arg| match self.value_layout(f, arg) {
+                Some(layout) => layout_type(layout),
+                None => "uint8_t".to_string(),
+            })
+            .collect::<Vec<_>>();
+        let params = if params.is_empty() {
+            "void".to_string()
+        } else {
+            params.join(", ")
+        };
+        let address = self.int_value(f, value, self.pointer_size());
+        format!("(({} (*)({}))(uintptr_t){})", ret, params, address)
+    }
+
+    fn virtual_args(&self, f: &ir::Function, args: &[Value]) -> String {
+        args
+            .iter()
+            .map(|arg| match self.value_layout(f, arg) {
+                Some(layout) => self.typed_value(f, arg, layout),
+                None => "0".to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn binary_op(&self, f: &ir::Function, op: BinaryOp, a: &Value, b: &Value) -> String {
+        match op {
+            BinaryOp::IntOp(op, sign, size) => {
+                let ty = int_type(size.in_bytes());
+                let a = self.int_value(f, a, size.in_bytes());
+                let b = self.int_value(f, b, size.in_bytes());
+                let (sa, sb) = match sign {
+                    Signedness::Signed => {
+                        let signed = signed_type(size);
+                        (format!("({}){}", signed, a), format!("({}){}", signed, b))
+                    }
+                    Signedness::Unsigned => (a.clone(), b.clone()),
+                };
+                match op {
+                    IntOp::Add => format!("({})((uint64_t){} + {})", ty, a, b),
+                    IntOp::Sub => format!("({})((uint64_t){} - {})", ty, a, b),
+                    IntOp::Mul => format!("({})((uint64_t){} * {})", ty, a, b),
+                    IntOp::Div | IntOp::Mod => {
+                        let helper = match (op, sign) {
+                            (IntOp::Div, Signedness::Unsigned) => "plank_div_u",
+                            (IntOp::Div, Signedness::Signed) => "plank_div_s",
+                            (_, Signedness::Unsigned) => "plank_mod_u",
+                            (_, Signedness::Signed) => "plank_mod_s",
+                        };
+                        format!("({}){}({}, {})", ty, helper, sa, sb)
+                    }
+                    IntOp::Less => format!("(uint8_t)({} < {})", sa, sb),
+                    IntOp::LessEq => format!("(uint8_t)({} <= {})", sa, sb),
+                    IntOp::Greater => format!("(uint8_t)({} > {})", sa, sb),
+                    IntOp::GreaterEq => format!("(uint8_t)({} >= {})", sa, sb),
+                }
+            }
+            BinaryOp::Checked(op, overflow, sign, size) => {
+                let ty = int_type(size.in_bytes());
+                let a = self.int_value(f, a, size.in_bytes());
+                let b = self.int_value(f, b, size.in_bytes());
+                let symbol = match op {
+                    IntOp::Add => '+',
+                    IntOp::Sub => '-',
+                    IntOp::Mul => '*',
+                    _ => '/',
+                };
+                let saturate = match overflow {
+                    Overflow::Trap => 0,
+                    Overflow::Saturate => 1,
+                };
+                let (helper, a, b) = match sign {
+                    Signedness::Signed => {
+                        let signed = signed_type(size);
+                        let (a, b) = (format!("({}){}", signed, a), format!("({}){}", signed, b));
+                        ("plank_checked_s", a, b)
+                    }
+                    Signedness::Unsigned => ("plank_checked_u", a, b),
+                };
+                let bits = size.in_bytes() * 8;
+                let args = format!("'{}', {}, {}, {}, {}", symbol, saturate, bits, a, b);
+                format!("({}){}({})", ty, helper, args)
+            }
+            BinaryOp::BitOp(op, size) => {
+                let ty = int_type(size.in_bytes());
+                let a = self.int_value(f, a, size.in_bytes());
+                let b = self.int_value(f, b, size.in_bytes());
+                // shift amount wraps around like in the interpreter
+                let mask = bits(size) - 1;
+                match op {
+                    BitOp::And => format!("({})({} & {})", ty, a, b),
+                    BitOp::Or => format!("({})({} | {})", ty, a, b),
+                    BitOp::Xor => format!("({})({} ^ {})", ty, a, b),
+                    BitOp::Shl => format!("({})((uint64_t){} << ({} & {}))", ty, a, b, mask),
+                    BitOp::Shr => format!("({})({} >> ({} & {}))", ty, a, b, mask),
+                }
+            }
+            BinaryOp::Eq | BinaryOp::Neq => {
+                let layout = self.value_layout(f, a)
+                    .or_else(|| self.value_layout(f, b))
+                    .expect("comparing two undefined values");
+                let equal = if is_integer(layout) {
+                    let a = self.int_value(f, a, layout.size);
+                    let b = self.int_value(f, b, layout.size);
+                    format!("{} == {}", a, b)
+                } else {
+                    let a = self.value_bytes(f, a);
+                    let b = self.value_bytes(f, b);
+                    format!("memcmp({}, {}, {}) == 0", a, b, layout.size)
+                };
+                match op {
+                    BinaryOp::Eq => format!("(uint8_t)({})", equal),
+                    _ => format!("(uint8_t)!({})", equal),
+                }
+            }
+        }
+    }
+
+    fn unary_op(&self, f: &ir::Function, op: UnaryOp, value: &Value) -> String {
+        match op {
+            UnaryOp::Negate(_, size) => {
+                let ty = int_type(size.in_bytes());
+                let value = self.int_value(f, value, size.in_bytes());
+                format!("({})(0 - (uint64_t){})", ty, value)
+            }
+            UnaryOp::Extend(sign, from, to) => {
+                let ty = int_type(to.in_bytes());
+                let value = self.int_value(f, value, from.in_bytes());
+                match sign {
+                    Signedness::Signed => {
+                        format!("({})(int64_t)({}){}", ty, signed_type(from), value)
+                    }
+                    Signedness::Unsigned => format!("({}){}", ty, value),
+                }
+            }
+        }
+    }
+
+    fn emit_instruction<W: Write>(&self, f: &ir::Function, i: &Instruction, out: &mut W)
+        -> io::Result<()>
+    {
+        let statement = match *i {
+            Instruction::Init(_) |
+            Instruction::Drop(_) |
+            Instruction::Nop |
+            Instruction::Assign(_, Value::Undef) |
+            Instruction::CastAssign(_, Value::Undef) => None,
+            Instruction::Unreachable => {
+                Some("plank_trap(\"executed unreachable code\");".to_string())
+            }
+            Instruction::BinaryOp(reg, op, ref a, ref b) => {
+                let expr = self.binary_op(f, op, a, b);
+                let size = match op {
+                    BinaryOp::IntOp(IntOp::Add, _, size) |
+                    BinaryOp::IntOp(IntOp::Sub, _, size) |
+                    BinaryOp::IntOp(IntOp::Mul, _, size) |
+                    BinaryOp::IntOp(IntOp::Div, _, size) |
+                    BinaryOp::IntOp(IntOp::Mod, _, size) |
+                    BinaryOp::Checked(_, _, _, size) |
+                    BinaryOp::BitOp(_, size) => size.in_bytes(),
+                    BinaryOp::IntOp(_, _, _) |
+                    BinaryOp::Eq |
+                    BinaryOp::Neq => 1,
+                };
+                Some(self.assign(f, reg, &expr, self.atomic_layout(size)))
+            }
+            Instruction::UnaryOp(reg, op, ref value) => {
+                let expr = self.unary_op(f, op, value);
+                let size = match op {
+                    UnaryOp::Negate(_, size) |
+                    UnaryOp::Extend(_, _, size) => size.in_bytes(),
+                };
+                Some(self.assign(f, reg, &expr, self.atomic_layout(size)))
+            }
+            Instruction::Call(reg, ref sym, ref args) => {
+                let callee = &self.program.functions[sym];
+                let args = self.args(f, callee, args);
+                let expr = format!("{}({})", self.function_name(sym), args);
+                let layout = callee.output_layout.expect("called procedure as a function");
+                Some(self.assign(f, reg, &expr, layout))
+            }
+            Instruction::CallProc(ref sym, ref args) => {
+                let callee = &self.program.functions[sym];
+                let args = self.args(f, callee, args);
+                Some(format!("{}({});", self.function_name(sym), args))
+            }
+            Instruction::CallVirt(reg, ref value, ref args) => {
+                let callee = self.virtual_callee(f, value, Some(reg), args);
+                let args = self.virtual_args(f, args);
+                Some(format!("r{} = {}({});", reg.0, callee, args))
+            }
+            Instruction::CallProcVirt(ref value, ref args) => {
+                let callee = self.virtual_callee(f, value, None, args);
+                let args = self.virtual_args(f, args);
+                Some(format!("{}({});", callee, args))
+            }
+            Instruction::DerefStore(ref address, offset, ref value) => {
+                let to = self.pointer_from(f, address, offset);
+                self.copy_value(f, &to, value)
+            }
+            Instruction::DerefLoad(reg, ref address, offset) => {
+                let size = f.registers[&reg].size;
+                let from = self.pointer_from(f, address, offset);
+                if size == 0 {
+                    None
+                } else {
+                    Some(format!("memcpy(&r{}, {}, {});", reg.0, from, size))
+                }
+            }
+            Instruction::Store(reg, offset, ref value) => {
+                let to = format!("(uint8_t *)&r{} + {}", reg.0, offset);
+                self.copy_value(f, &to, value)
+            }
+            Instruction::Load(to, from, offset) => {
+                let size = f.registers[&to].size;
+                if size == 0 {
+                    None
+                } else {
+                    Some(format!(
+                        "memcpy(&r{}, (uint8_t *)&r{} + {}, {});",
+                        to.0,
+                        from.0,
+                        offset,
+                        size,
+                    ))
+                }
+            }
+            Instruction::TakeAddress(to, reg, offset) => {
+                let address = self.address_of(&format!("((uint8_t *)&r{} + {})", reg.0, offset));
+                let layout = self.atomic_layout(self.pointer_size());
+                Some(self.assign(f, to, &address, layout))
+            }
+            Instruction::Assign(reg, ref value) |
+            Instruction::CastAssign(reg, ref value) => {
+                let layout = f.registers[&reg];
+                if is_integer(layout) {
+                    Some(format!("r{} = {};", reg.0, self.int_value(f, value, layout.size)))
+                } else {
+                    self.copy_value(f, &format!("&r{}", reg.0), value)
+                }
+            }
+        };
+        if let Some(statement) = statement {
+            writeln!(out, "    {}", statement)?;
+        }
+        Ok(())
+    }
+
+    fn emit_block_end<W: Write>(&self, f: &ir::Function, end: &BlockEnd, out: &mut W)
+        -> io::Result<()>
+    {
+        match *end {
+            BlockEnd::Return(ref value) => {
+                let layout = f.output_layout.expect("returned value from a procedure");
+                writeln!(out, "    return {};", self.typed_value(f, value, layout))
+            }
+            BlockEnd::ReturnProc => writeln!(out, "    return;"),
+            BlockEnd::Jump(block) => writeln!(out, "    goto label_{};", block.0),
+            BlockEnd::Branch(ref value, a, b) => {
+                let value = self.int_value(f, value, 1);
+                writeln!(out, "    if ({}) goto label_{}; else goto label_{};", value, a.0, b.0)
+            }
+            BlockEnd::Unreachable => {
+                writeln!(out, "    plank_trap(\"executed unreachable code\");")
+            }
+        }
+    }
+
+    fn emit_function<W: Write>(&self, name: &Symbol, f: &ir::Function, out: &mut W)
+        -> io::Result<()>
+    {
+        let start = match f.start_block {
+            Some(start) => start,
+            None => return Ok(()),
+        };
+        writeln!(out, "{} {{", signature(name, f))?;
+        let mut registers = f.registers
+            .iter()
+            .filter(|&(reg, _)| !f.parameters.contains(reg))
+            .collect::<Vec<_>>();
+        registers.sort_by_key(|&(&reg, _)| reg);
+        for (reg, &layout) in registers {
+            writeln!(out, "    {} r{};", layout_type(layout), reg.0)?;
+        }
+
+        let mut targets = HashSet::new();
+        for block in f.blocks.values() {
+            match block.end {
+                BlockEnd::Jump(a) => {
+                    targets.insert(a);
+                }
+                BlockEnd::Branch(_, a, b) => {
+                    targets.insert(a);
+                    targets.insert(b);
+                }
+                BlockEnd::Return(_) |
+                BlockEnd::ReturnProc |
+                BlockEnd::Unreachable => {}
+            }
+        }
+        let mut blocks = f.blocks.keys().cloned().collect::<Vec<_>>();
+        blocks.sort_by_key(|&id| (id != start, id));
+        for id in blocks {
+            let block = &f.blocks[&id];
+            if targets.contains(&id) {
+                writeln!(out, "label_{}:;", id.0)?;
+            }
+            for i in &block.ops {
+                self.emit_instruction(f, i, out)?;
+            }
+            self.emit_block_end(f, &block.end, out)?;
+        }
+        writeln!(out, "}}")?;
+        writeln!(out)
+    }
+}
+
+fn signature(name: &Symbol, f: &ir::Function) -> String {
+    let ret = match f.output_layout {
+        Some(layout) => layout_type(layout),
+        None => "void".to_string(),
+    };
+    let params = f.parameters
+        .iter()
+        .map(|reg| format!("{} r{}", layout_type(f.registers[reg]), reg.0))
+        .collect::<Vec<_>>();
+    let params = if params.is_empty() {
+        "void".to_string()
+    } else {
+        params.join(", ")
+    };
+    // functions without a body are left for the linker
+    let linkage = if f.start_block.is_some() || is_builtin(name) {
+        "static "
+    } else {
+        ""
+    };
+    format!("{}{} {}({})", linkage, ret, function_name(name, f), params)
+}
+
+/// Attribute that selects the calling convention of a function. C
+/// compilers use the `C` one by default, and ignore `stdcall` on x86-64.
+fn convention_attribute(program: &Program, f: &ir::Function) -> &'static str {
+    match (f.calling_convention, program.target.arch) {
+        (CallingConvention::Stdcall, Arch::X86) => " __attribute__((stdcall))",
+        _ => "",
+    }
+}
+
+fn is_builtin(name: &Symbol) -> bool {
+    matches!(
+        &*name.0,
+        "builtin_getc" | "builtin_putc" | "builtin_alloc" | "builtin_free" | "builtin_realloc" |
+        "builtin_bounds_fail" | "builtin_print_i32" | "builtin_print_u32" | "builtin_write" |
+        "builtin_read_i32" | "builtin_read_line" | "builtin_str_eq" | "builtin_str_copy" |
+        "builtin_str_find" | "builtin_assert_fail" | "builtin_panic_fail" | "builtin_rand_seed" |
+        "builtin_rand_u32" | "builtin_time_ms"
+    )
+}
+
+fn emit_builtin<W: Write>(
+    program: &Program,
+    name: &Symbol,
+    f: &ir::Function,
+    out: &mut W,
+) -> io::Result<()> {
+    writeln!(out, "{} {{", signature(name, f))?;
+    let param = |index: usize| format!("r{}", f.parameters[index].0);
+    let pointer = |index: usize| format!("(void *)(uintptr_t)r{}", f.parameters[index].0);
+    // declared here, because `stdlib.h` is not included
+    match &*name.0 {
+        "builtin_getc" => {
+            writeln!(out, "    int c = getchar();")?;
+            writeln!(out, "    return c == EOF ? UINT32_MAX : (uint32_t)c;")?;
+        }
+        "builtin_putc" => writeln!(out, "    putchar({});", param(0))?,
+        "builtin_alloc" => {
+            let ret = layout_type(f.output_layout.expect("alloc returns a pointer"));
+            writeln!(out, "    void *malloc(size_t);")?;
+            writeln!(out, "    return ({})(uintptr_t)malloc({});", ret, param(0))?;
+        }
+        "builtin_free" => {
+            writeln!(out, "    void free(void *);")?;
+            writeln!(out, "    free({});", pointer(0))?;
+        }
+        "builtin_bounds_fail" => {
+            let format = "index out of bounds: the index is %lu but the length is %lu\\n";
+            let args = format!("(unsigned long){}, (unsigned long){}", param(0), param(1));
+            writeln!(out, "    fflush(stdout);")?;
+            writeln!(out, "    fprintf(stderr, \"{}\", {});", format, args)?;
+            writeln!(out, "    longjmp(plank_exit, 1);")?;
+        }
+        "builtin_assert_fail" | "builtin_panic_fail" => {
+            let prefix = if &*name.0 == "builtin_panic_fail" {
+                "panicked"
+            } else {
+                "assertion failed"
+            };
+            let message = format!("(int){}, (const char *){}", param(1), pointer(0));
+            writeln!(out, "    fflush(stdout);")?;
+            writeln!(out, "    fprintf(stderr, \"{}: %.*s\", {});", prefix, message)?;
+            // lines count in all source files joined together,
+            // so find the file that the line is in, last one first
+            let files = program
+                .files
+                .iter()
+                .rev()
+                .map(|file| (Some(&file.path), file.start_line))
+                .collect::<Vec<_>>();
+            let files = if files.is_empty() { vec![(None, 0)] } else { files };
+            for (index, &(path, start)) in files.iter().enumerate() {
+                let branch = if index == 0 { "    if" } else { "    } else if" };
+                writeln!(out, "{} ({} > {}) {{", branch, param(2), start)?;
+                let line = match start {
+                    0 => format!("(unsigned long){}", param(2)),
+                    _ => format!("(unsigned long){} - {}", param(2), start),
+                };
+                let args = format!("{}, (unsigned long){}", line, param(3));
+                match path {
+                    Some(path) => {
+                        let path = c_string(path);
+                        let format = "\" at %s:%lu:%lu\"";
+                        writeln!(out, "        fprintf(stderr, {}, {}, {});", format, path, args)?;
+                    }
+                    None => writeln!(out, "        fprintf(stderr, \" at %lu:%lu\", {});", args)?,
+                }
+            }
+            writeln!(out, "    }}")?;
+            writeln!(out, "    fprintf(stderr, \"\\n\");")?;
+            writeln!(out, "    longjmp(plank_exit, 1);")?;
+        }
+        "builtin_print_i32" => {
+            writeln!(out, "    printf(\"%ld\", (long)(int32_t){});", param(0))?;
+        }
+        "builtin_print_u32" => {
+            writeln!(out, "    printf(\"%lu\", (unsigned long){});", param(0))?;
+        }
+        "builtin_rand_seed" => {
+            writeln!(out, "    plank_rand_state = {0} == 0 ? 2463534242u : {0};", param(0))?;
+        }
+        "builtin_rand_u32" => {
+            writeln!(out, "    uint32_t x = plank_rand_state;")?;
+            writeln!(out, "    x ^= x << 13;")?;
+            writeln!(out, "    x ^= x >> 17;")?;
+            writeln!(out, "    x ^= x << 5;")?;
+            writeln!(out, "    plank_rand_state = x;")?;
+            writeln!(out, "    return x;")?;
+        }
+        "builtin_time_ms" => writeln!(out, "    return plank_time_now() - plank_time_start;")?,
+        "builtin_write" => writeln!(out, "    fwrite({}, 1, {}, stdout);", pointer(0), param(1))?,
+        "builtin_read_i32" => {
+            writeln!(out, "    int c = getchar();")?;
+            writeln!(out, "    while (c == ' ' || c == '\\t' || c == '\\n' || c == '\\r') {{")?;
+            writeln!(out, "        c = getchar();")?;
+            writeln!(out, "    }}")?;
+            writeln!(out, "    int negative = c == '-';")?;
+            writeln!(out, "    if (negative) {{")?;
+            writeln!(out, "        c = getchar();")?;
+            writeln!(out, "    }}")?;
+            writeln!(out, "    uint32_t value = 0;")?;
+            writeln!(out, "    while (c >= '0' && c <= '9') {{")?;
+            writeln!(out, "        value = value * 10 + (uint32_t)(c - '0');")?;
+            writeln!(out, "        c = getchar();")?;
+            writeln!(out, "    }}")?;
+            writeln!(out, "    return negative ? 0 - value : value;")?;
+        }
+        "builtin_read_line" => {
+            writeln!(out, "    uint8_t *buffer = {};", pointer(0))?;
+            writeln!(out, "    int c = getchar();")?;
+            writeln!(out, "    if (c == EOF) {{")?;
+            writeln!(out, "        return UINT32_MAX;")?;
+            writeln!(out, "    }}")?;
+            writeln!(out, "    uint32_t stored = 0;")?;
+            writeln!(out, "    while (c != EOF && c != '\\n') {{")?;
+            writeln!(out, "        if (stored < {}) {{", param(1))?;
+            writeln!(out, "            buffer[stored++] = (uint8_t)c;")?;
+            writeln!(out, "        }}")?;
+            writeln!(out, "        c = getchar();")?;
+            writeln!(out, "    }}")?;
+            writeln!(out, "    return stored;")?;
+        }
+        "builtin_str_eq" => {
+            let compare = format!("memcmp({}, {}, {})", pointer(0), pointer(2), param(1));
+            writeln!(out, "    if ({} != {}) {{", param(1), param(3))?;
+            writeln!(out, "        return 0;")?;
+            writeln!(out, "    }}")?;
+            writeln!(out, "    return {} == 0 || {} == 0;", param(1), compare)?;
+        }
+        "builtin_str_copy" => {
+            writeln!(out, "    uint32_t len = {} < {} ? {0} : {1};", param(1), param(3))?;
+            writeln!(out, "    if (len > 0) {{")?;
+            writeln!(out, "        memmove({}, {}, len);", pointer(0), pointer(2))?;
+            writeln!(out, "    }}")?;
+            writeln!(out, "    return len;")?;
+        }
+        "builtin_str_find" => {
+            writeln!(out, "    const uint8_t *string = {};", pointer(0))?;
+            writeln!(out, "    for (uint32_t i = 0; i + {} <= {}; i++) {{", param(3), param(1))?;
+            let compare = format!("memcmp(string + i, {}, {})", pointer(2), param(3));
+            writeln!(out, "        if ({} == 0 || {} == 0) {{", param(3), compare)?;
+            writeln!(out, "            return i;")?;
+            writeln!(out, "        }}")?;
+            writeln!(out, "    }}")?;
+            writeln!(out, "    return UINT32_MAX;")?;
+        }
+        _ => {
+            let ret = layout_type(f.output_layout.expect("realloc returns a pointer"));
+            writeln!(out, "    void *realloc(void *, size_t);")?;
+            let call = format!("realloc({}, {})", pointer(0), param(1));
+            writeln!(out, "    return ({})(uintptr_t){};", ret, call)?;
+        }
+    }
+    writeln!(out, "}}")?;
+    writeln!(out)
+}
+
+/// C string literal with given contents.
+fn c_string(value: &str) -> String {
+    let mut literal = String::from("\"");
+    for byte in value.bytes() {
+        match byte {
+            b'"' | b'\\' => {
+                literal.push('\\');
+                literal.push(byte as char);
+            }
+            b' '..=b'~' => literal.push(byte as char),
+            _ => literal.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+fn collect_strings<'a>(f: &'a ir::Function, strings: &mut Vec<&'a [u8]>) {
+    let mut add = |value: &'a Value| {
+        if let Value::Bytes(ref bytes) = *value {
+            if !strings.contains(&&bytes[..]) {
+                strings.push(bytes);
+            }
+        }
+    };
+    let mut blocks = f.blocks.iter().collect::<Vec<_>>();
+    blocks.sort_by_key(|&(&id, _)| id);
+    for (_, block) in blocks {
+        for i in &block.ops {
+            match i.instruction {
+                Instruction::Init(_) |
+                Instruction::Drop(_) |
+                Instruction::Nop |
+                Instruction::Unreachable |
+                Instruction::Load(_, _, _) |
+                Instruction::TakeAddress(_, _, _) => {}
+                Instruction::BinaryOp(_, _, ref a, ref b) |
+                Instruction::DerefStore(ref a, _, ref b) => {
+                    add(a);
+                    add(b);
+                }
+                Instruction::UnaryOp(_, _, ref a) |
+                Instruction::DerefLoad(_, ref a, _) |
+                Instruction::Store(_, _, ref a) |
+                Instruction::Assign(_, ref a) |
+                Instruction::CastAssign(_, ref a) => add(a),
+                Instruction::Call(_, _, ref args) |
+                Instruction::CallProc(_, ref args) => args.iter().for_each(&mut add),
+                Instruction::CallVirt(_, ref a, ref args) |
+                Instruction::CallProcVirt(ref a, ref args) => {
+                    add(a);
+                    args.iter().for_each(&mut add);
+                }
+            }
+        }
+        match block.end {
+            BlockEnd::Return(ref a) |
+            BlockEnd::Branch(ref a, _, _) => add(a),
+            BlockEnd::ReturnProc |
+            BlockEnd::Jump(_) |
+            BlockEnd::Unreachable => {}
+        }
+    }
+}
+
+/// Translate program to C99. Generated code expects the host
+/// pointer size to match the one used by the program.
+pub fn emit_program<W: Write>(program: &Program, mut out: W) -> io::Result<()> {
+    let out = &mut out;
+    let mut functions = program.functions.iter().collect::<Vec<_>>();
+    functions.sort_by_key(|&(name, _)| name.0.clone());
+
+    let mut strings = Vec::new();
+    for &(_, f) in &functions {
+        collect_strings(f, &mut strings);
+    }
+
+    writeln!(out, "{}", PRELUDE)?;
+    writeln!(
+        out,
+        "typedef char plank_pointer_size_check[sizeof(void *) == {} ? 1 : -1];",
+        program.target.pointer_size.in_bytes(),
+    )?;
+    writeln!(out)?;
+
+    let mut blobs = Vec::new();
+    let mut max_size = 1;
+    for &(_, f) in &functions {
+        for &layout in f.registers.values().chain(&f.output_layout) {
+            max_size = ::std::cmp::max(max_size, layout.size);
+            if !is_integer(layout) && !blobs.contains(&layout.size) {
+                blobs.push(layout.size);
+            }
+        }
+    }
+    blobs.sort();
+    for size in blobs {
+        let len = ::std::cmp::max(size, 1);
+        writeln!(out, "typedef struct {{ uint8_t data[{}]; }} plank_blob_{};", len, size)?;
+    }
+    writeln!(out, "static const uint8_t plank_undef[{}];", max_size)?;
+    writeln!(out)?;
+
+    for (index, bytes) in strings.iter().enumerate() {
+        if bytes.is_empty() {
+            writeln!(out, "static uint8_t string_{}[1];", index)?;
+        } else {
+            let bytes = bytes.iter().map(|b| b.to_string()).collect::<Vec<_>>();
+            writeln!(out, "static uint8_t string_{}[] = {{{}}};", index, bytes.join(", "))?;
+        }
+    }
+    writeln!(out)?;
+
+    for &(name, f) in &functions {
+        writeln!(out, "{}{};", signature(name, f), convention_attribute(program, f))?;
+    }
+    writeln!(out)?;
+
+    let ctx = Context {
+        program,
+        strings: strings.iter().enumerate().map(|(i, &s)| (s, i)).collect(),
+    };
+    for &(name, f) in &functions {
+        if f.start_block.is_none() && is_builtin(name) {
+            emit_builtin(program, name, f, out)?;
+        } else {
+            ctx.emit_function(name, f, out)?;
+        }
+    }
+
+    let main = Symbol("fn_main".into());
+    if let Some(f) = program.functions.get(&main) {
+        // `main` takes either nothing, or `argc` and `argv`
+        let (params, args) = if f.parameters.is_empty() {
+            ("void", String::new())
+        } else {
+            let args = format!("(uint32_t)argc, ({})(uintptr_t)argv", ctx.pointer_type());
+            ("int argc, char **argv", args)
+        };
+        writeln!(out, "int main({}) {{", params)?;
+        writeln!(out, "    plank_time_start = plank_time_now();")?;
+        writeln!(out, "    if (setjmp(plank_exit)) {{")?;
+        writeln!(out, "        return 1;")?;
+        writeln!(out, "    }}")?;
+        match f.output_layout {
+            Some(layout) if is_integer(layout) => {
+                let value = format!("{}({})", mangle(&main.0), args);
+                let signed = match layout.size {
+                    1 => Size::Bit8,
+                    2 => Size::Bit16,
+                    4 => Size::Bit32,
+                    _ => Size::Bit64,
+                };
+                writeln!(out, "    return (int)({}){};", signed_type(signed), value)?;
+            }
+            _ => {
+                writeln!(out, "    {}({});", mangle(&main.0), args)?;
+                writeln!(out, "    return 0;")?;
+            }
+        }
+        writeln!(out, "}}")?;
+    }
+    Ok(())
+}
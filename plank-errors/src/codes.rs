@@ -0,0 +1,140 @@
+//! Error codes of diagnostics reported by the compiler.
+//!
+//! Every code has a long explanation that can be shown with `--explain`.
+//! Codes are never reused: if a diagnostic is removed, its code is retired.
+
+macro_rules! error_codes {
+    ($($(#[$attr:meta])* $name:ident = $code:expr;)*) => {
+        $(
+            $(#[$attr])*
+            pub const $name: &str = $code;
+        )*
+
+        /// All known error codes, in ascending order.
+        pub const ALL: &[&str] = &[$($code),*];
+
+        /// Return the long explanation of the given error code, or `None`
+        /// if the code is unknown.
+        ///
+        /// # Examples
+        ///
+        /// ```rust
+        /// use plank_errors::codes;
+        ///
+        /// assert!(codes::explain(codes::UNKNOWN_VALUE).is_some());
+        /// assert!(codes::explain("E9999").is_none());
+        /// ```
+        pub fn explain(code: &str) -> Option<&'static str> {
+            match code {
+                $($code => Some(include_str!(concat!("explanations/", $code, ".md"))),)*
+                _ => None,
+            }
+        }
+    }
+}
+
+error_codes! {
+    /// Source contains chars that cannot start a token.
+    UNKNOWN_CHAR = "E0001";
+    /// Block comment is not closed.
+    UNTERMINATED_COMMENT = "E0002";
+    /// Number literal is malformed.
+    INVALID_NUMBER = "E0003";
+    /// Escape sequence in a char or string literal is malformed.
+    INVALID_ESCAPE = "E0004";
+    /// Char or string literal contains a char that is not printable ASCII.
+    INVALID_LITERAL_CHAR = "E0005";
+    /// Char or string literal is not closed.
+    UNTERMINATED_LITERAL = "E0006";
+    /// Char literal does not have exactly one char.
+    CHAR_LITERAL_LENGTH = "E0007";
+    /// Parser found a token it did not expect.
+    UNEXPECTED_TOKEN = "E0008";
+    /// Expressions, types or blocks are nested too deeply.
+    NESTED_TOO_DEEPLY = "E0009";
+    /// Assignment is used as an `if` or `while` condition.
+    ASSIGN_IN_CONDITION = "E0010";
+    /// A built-in type or function is redefined.
+    BUILTIN_REDEFINED = "E0011";
+    /// A struct or function is defined multiple times.
+    DUPLICATE_DEFINITION = "E0012";
+    /// A name is listed multiple times in a parameter or field list.
+    DUPLICATE_NAME = "E0013";
+    /// A parameter without a default value follows one with it.
+    MISSING_DEFAULT_VALUE = "E0014";
+    /// Type name does not refer to any type.
+    UNKNOWN_TYPE = "E0015";
+    /// Name does not refer to any value.
+    UNKNOWN_VALUE = "E0016";
+    /// `main` function has an invalid signature.
+    INVALID_MAIN = "E0017";
+    /// Lambdas are used where they are not allowed.
+    INVALID_LAMBDA = "E0018";
+    /// Function call arguments do not match function parameters.
+    INVALID_CALL_PARAMS = "E0019";
+    /// `extern` function has type parameters.
+    EXTERN_TYPE_PARAMS = "E0020";
+    /// Wrong number of type parameters is given.
+    WRONG_TYPE_PARAM_COUNT = "E0021";
+    /// Wildcard type is used in an item signature.
+    WILDCARD_IN_ITEM = "E0022";
+    /// Struct contains itself.
+    RECURSIVE_STRUCT = "E0023";
+    /// Types do not match.
+    TYPE_MISMATCH = "E0024";
+    /// Function is called with wrong number of arguments.
+    WRONG_ARG_COUNT = "E0025";
+    /// Type cannot be inferred.
+    CANNOT_INFER = "E0026";
+    /// Called value is not a function.
+    NOT_CALLABLE = "E0027";
+    /// Indexed value is not a pointer, slice or string.
+    NOT_INDEXABLE = "E0028";
+    /// Accessed field does not exist.
+    UNKNOWN_FIELD = "E0029";
+    /// Int literal does not fit into its type.
+    LITERAL_OUT_OF_RANGE = "E0030";
+    /// Cast between incompatible types.
+    INVALID_CAST = "E0031";
+    /// Expression cannot be assigned to or borrowed.
+    INVALID_LVALUE = "E0032";
+    /// Value that is not `mut` is modified.
+    MODIFIED_CONST = "E0033";
+    /// `break` or `continue` is used outside a loop.
+    OUTSIDE_LOOP = "E0034";
+    /// Function can reach its end without returning a value.
+    MISSING_RETURN = "E0035";
+    /// Variable is used before it is assigned.
+    UNINITIALIZED_VAR = "E0036";
+    /// Variable that is not `mut` is assigned twice.
+    ASSIGNED_TWICE = "E0037";
+    /// Code can never be executed.
+    DEAD_CODE = "E0038";
+    /// Item attribute is not known or is malformed.
+    INVALID_ATTRIBUTE = "E0039";
+    /// Lint attribute refers to a lint that does not exist.
+    UNKNOWN_LINT = "E0040";
+    /// Function or struct is never used.
+    UNUSED_ITEM = "E0041";
+    /// Pointer to a local variable outlives the function.
+    DANGLING_POINTER = "E0042";
+    /// Match does not handle every possible value.
+    NON_EXHAUSTIVE_MATCH = "E0043";
+    /// Match arm can never be taken.
+    UNREACHABLE_PATTERN = "E0044";
+    /// Type parameter bound does not exist or is not allowed.
+    INVALID_BOUND = "E0045";
+    /// Type does not satisfy the bound of a type parameter.
+    UNSATISFIED_BOUND = "E0046";
+    /// Generic functions instantiate each other without end.
+    INSTANTIATION_LIMIT = "E0047";
+    /// Calling convention in `extern "..."` is not known.
+    UNKNOWN_CALLING_CONVENTION = "E0048";
+    /// Function with a foreign calling convention has a body, or a
+    /// parameter or return type that cannot be passed to foreign code.
+    INVALID_FOREIGN_FUNCTION = "E0049";
+    /// Files import each other in a cycle.
+    IMPORT_CYCLE = "E0050";
+    /// Imported file does not exist or cannot be read.
+    UNREADABLE_IMPORT = "E0051";
+}
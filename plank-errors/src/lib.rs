@@ -2,9 +2,13 @@
 
 //! A library to build and format diagnostics for use in plank compiler.
 
+pub mod codes;
 pub mod position;
 pub mod reporter;
 pub mod printer;
+pub mod sarif;
+pub mod sink;
 
 pub use reporter::Reporter;
-pub use printer::{print_diagnostic, print_diagnostics};
+pub use printer::{print_diagnostic, print_diagnostics, print_diagnostics_with_colors, TerminalSink};
+pub use sink::DiagnosticSink;
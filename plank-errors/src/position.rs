@@ -1,17 +1,56 @@
 //! Types to represent positions inside source file.
 
+use std::ops::Range;
+
 /// Represents a position inside a source file. Both lines and columns start
-/// from zero.
+/// from zero. Columns are counted in chars, not bytes.
 #[derive(PartialEq, PartialOrd, Eq, Ord, Hash, Debug, Copy, Clone)]
 pub struct Position {
     #[allow(missing_docs)] pub line: u32,
     #[allow(missing_docs)] pub column: u32,
+    /// Byte offset from the start of the source file.
+    pub offset: u32,
 }
 
 impl Position {
-    /// Create a new position with given line and column.
+    /// Create a new position with given line and column. Byte offset is
+    /// set to zero, use `with_offset` if it is known.
     pub fn new(line: u32, column: u32) -> Position {
-        Position { line, column }
+        Position::with_offset(line, column, 0)
+    }
+
+    /// Create a new position with given line, column and byte offset.
+    pub fn with_offset(line: u32, column: u32, offset: u32) -> Position {
+        Position {
+            line,
+            column,
+            offset,
+        }
+    }
+
+    /// Return the column of this position counted in UTF-16 code units, as
+    /// required by editors using the language server protocol. `source`
+    /// must be the text this position points into.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use plank_errors::position::Position;
+    ///
+    /// let source = "let x = 1;\nlet 𝑥 = 2;";
+    /// assert_eq!(Position::new(1, 4).utf16_column(source), 4);
+    /// assert_eq!(Position::new(1, 5).utf16_column(source), 6);
+    /// ```
+    pub fn utf16_column(self, source: &str) -> u32 {
+        let source = source.trim_start_matches('\u{feff}');
+        let line = source.split('\n').nth(self.line as usize).unwrap_or("");
+        let mut chars = line.chars();
+        let mut column = 0;
+        for _ in 0..self.column {
+            // positions past the end of line are used for some notes
+            column += chars.next().map(|ch| ch.len_utf16() as u32).unwrap_or(1);
+        }
+        column
     }
 
     /// Create a span that starts here, and goes to given position. If
@@ -24,9 +63,11 @@ impl Position {
         Span::new(self, to)
     }
 
-    /// Create a new position that is `amount` columns to the right.
+    /// Create a new position that is `amount` columns to the right. Byte
+    /// offset is moved assuming that skipped chars are one byte long.
     pub fn forward(mut self, amount: u32) -> Position {
         self.column += amount;
+        self.offset += amount;
         self
     }
 
@@ -38,6 +79,7 @@ impl Position {
     pub fn backwards(mut self, amount: u32) -> Position {
         assert!(self.column >= amount, "going back too far");
         self.column -= amount;
+        self.offset -= amount;
         self
     }
 }
@@ -68,4 +110,14 @@ impl Span {
         let end = cmp::max(self.end, other.end);
         Span::new(start, end)
     }
+
+    /// Check if `other` span is inside this span.
+    pub fn contains(self, other: Span) -> bool {
+        self.start <= other.start && other.end <= self.end
+    }
+
+    /// Return the byte range of source covered by this span.
+    pub fn byte_range(self) -> Range<usize> {
+        self.start.offset as usize..self.end.offset as usize
+    }
 }
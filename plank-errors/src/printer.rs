@@ -1,7 +1,8 @@
 //! Functions to pretty-print diagnostics.
 
 use std::collections::{BTreeMap, HashSet};
-use reporter::{Diagnostic, Note, Severity};
+use reporter::{Diagnostic, Note, NoteStyle, Severity};
+use sink::DiagnosticSink;
 
 
 #[derive(PartialEq, PartialOrd, Eq, Ord, Hash, Debug, Copy, Clone)]
@@ -13,11 +14,10 @@ enum MarkerStyle {
 }
 
 impl MarkerStyle {
-    fn from_note_index(index: usize) -> MarkerStyle {
-        if index == 0 {
-            MarkerStyle::Primary
-        } else {
-            MarkerStyle::Secondary
+    fn from_note_style(style: NoteStyle) -> MarkerStyle {
+        match style {
+            NoteStyle::Primary => MarkerStyle::Primary,
+            NoteStyle::Secondary => MarkerStyle::Secondary,
         }
     }
 
@@ -37,6 +37,37 @@ impl MarkerStyle {
     }
 }
 
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+enum Color {
+    Red,
+    Yellow,
+    Blue,
+    Cyan,
+    Bold,
+}
+
+impl Color {
+    fn for_severity(severity: Severity) -> Color {
+        match severity {
+            Severity::Error => Color::Red,
+            Severity::Warning => Color::Yellow,
+            Severity::Note => Color::Cyan,
+        }
+    }
+
+    fn ansi_code(self) -> &'static str {
+        match self {
+            Color::Red => "\x1b[1;31m",
+            Color::Yellow => "\x1b[1;33m",
+            Color::Blue => "\x1b[1;34m",
+            Color::Cyan => "\x1b[1;36m",
+            Color::Bold => "\x1b[1m",
+        }
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+
 #[derive(PartialEq, PartialOrd, Eq, Ord, Hash, Debug, Copy, Clone)]
 enum MarkerPart {
     None,
@@ -62,6 +93,10 @@ impl MarkerPart {
             MarkerPart::SingleColumn => '|',
         }
     }
+
+    fn is_primary(self) -> bool {
+        matches!(self, MarkerPart::PrimaryMarker | MarkerPart::ArrowPrimary)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -87,6 +122,12 @@ impl<'a> LineMarker<'a> {
         }
     }
 
+    fn style(&self) -> MarkerStyle {
+        match *self {
+            LineMarker::FromStart { style, .. } | LineMarker::FromTo { style, .. } => style,
+        }
+    }
+
     fn end_col(&self) -> u32 {
         match *self {
             LineMarker::FromStart { arrow_col, .. } => arrow_col + 1,
@@ -109,28 +150,58 @@ struct Printer<'a> {
     next_connect_col: u32,
     full_connection_cols: HashSet<u32>,
     number_space: usize,
+    colors: bool,
+    severity: Severity,
 }
 
 impl<'a> Printer<'a> {
-    fn new(source: &'a str) -> Self {
+    fn new(source: &'a str, colors: bool) -> Self {
         Printer {
-            lines: source.lines().map(str::trim_right).collect(),
+            lines: source
+                .trim_start_matches('\u{feff}')
+                .lines()
+                .map(str::trim_right)
+                .collect(),
             line_markers: BTreeMap::new(),
             next_connect_col: 0,
             full_connection_cols: HashSet::new(),
             number_space: 0,
+            colors,
+            severity: Severity::Error,
         }
     }
 
-    fn pretty_print(&mut self, diagnostic: &'a Diagnostic) {
-        match diagnostic.severity {
-            Severity::Error => {
-                println!("error: {}", diagnostic.message);
-            }
-            Severity::Warning => {
-                println!("warning: {}", diagnostic.message);
-            }
+    fn paint<T: ::std::fmt::Display>(&self, color: Color, text: T) -> String {
+        if self.colors {
+            format!("{}{}{}", color.ansi_code(), text, RESET)
+        } else {
+            text.to_string()
         }
+    }
+
+    fn marker_color(&self, style: MarkerStyle) -> Color {
+        match style {
+            MarkerStyle::Primary => Color::for_severity(self.severity),
+            MarkerStyle::Secondary => Color::Blue,
+        }
+    }
+
+    fn pretty_print(&mut self, diagnostic: &'a Diagnostic) {
+        self.severity = diagnostic.severity;
+        let severity = match diagnostic.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        };
+        let label = match diagnostic.code {
+            Some(code) => format!("{}[{}]", severity, code),
+            None => severity.to_string(),
+        };
+        println!(
+            "{}{}",
+            self.paint(Color::for_severity(diagnostic.severity), label),
+            self.paint(Color::Bold, format!(": {}", diagnostic.message)),
+        );
         if !diagnostic.notes.is_empty() {
             self.print_notes(&diagnostic.notes);
         }
@@ -140,8 +211,8 @@ impl<'a> Printer<'a> {
         assert!(self.line_markers.is_empty());
         assert_eq!(self.next_connect_col, 0);
         assert!(self.full_connection_cols.is_empty());
-        for (index, note) in notes.iter().enumerate() {
-            self.add_note_markers(note, MarkerStyle::from_note_index(index));
+        for note in notes {
+            self.add_note_markers(note, MarkerStyle::from_note_style(note.style));
         }
         for markers in self.line_markers.values_mut() {
             markers.sort_by_key(LineMarker::end_col);
@@ -225,12 +296,10 @@ impl<'a> Printer<'a> {
         assert!(!markers.is_empty(), "line has no markers");
         self.print_line(line);
         self.print_immediate_markers(&markers);
+        print!(" ");
         // last span has its message printed inline
-        println!(
-            " {}",
-            markers.iter().next_back().unwrap().message().unwrap_or("")
-        );
-        markers.pop();
+        let last = markers.pop().unwrap();
+        self.print_message(&last);
 
         // non-arrow markers without messages don't extend below first line
         markers.retain(|m| m.message().is_some() || m.is_arrow());
@@ -261,22 +330,25 @@ impl<'a> Printer<'a> {
         for i in (1..(markers.len() + 1)).rev() {
             let markers = &markers[0..i];
             match markers[i - 1] {
-                LineMarker::FromTo {
-                    end_col, message, ..
-                } => {
+                LineMarker::FromTo { end_col, .. } => {
                     self.print_markers(markers, end_col - 1);
-                    println!("{}", message.unwrap_or(""));
+                    self.print_message(&markers[i - 1]);
                 }
-                LineMarker::FromStart {
-                    arrow_col, message, ..
-                } => {
+                LineMarker::FromStart { arrow_col, .. } => {
                     self.print_markers(markers, arrow_col + 2);
-                    println!("{}", message.unwrap_or(""));
+                    self.print_message(&markers[i - 1]);
                 }
             }
         }
     }
 
+    fn print_message(&self, marker: &LineMarker) {
+        match marker.message() {
+            Some(message) => println!("{}", self.paint(self.marker_color(marker.style()), message)),
+            None => println!(),
+        }
+    }
+
     fn print_line(&mut self, line: u32) {
         self.print_line_header(Some(line), None);
         for ch in self.lines.get((line - 1) as usize).unwrap_or(&"").chars() {
@@ -291,17 +363,18 @@ impl<'a> Printer<'a> {
     }
 
     fn print_line_header(&mut self, line: Option<u32>, arrow_from: Option<u32>) {
-        if let Some(line) = line {
-            print!("{: >width$} |  ", line, width = self.number_space);
+        let header = if let Some(line) = line {
+            format!("{: >width$} |", line, width = self.number_space)
         } else {
-            print!("{: >width$} |  ", "", width = self.number_space);
-        }
+            format!("{: >width$} |", "", width = self.number_space)
+        };
+        print!("{}  ", self.paint(Color::Blue, header));
         for col in 0..self.next_connect_col {
             if self.full_connection_cols.contains(&col) {
-                print!("|");
+                print!("{}", self.paint(Color::Blue, '|'));
             } else {
                 match arrow_from {
-                    Some(c) if c < col => print!("_"),
+                    Some(c) if c < col => print!("{}", self.paint(Color::Blue, '_')),
                     _ => print!(" "),
                 }
             }
@@ -316,10 +389,11 @@ impl<'a> Printer<'a> {
     }
 
     fn print_gap_line(&mut self) {
-        print!("{: <width$}", "...", width = self.number_space + 4);
+        let gap = format!("{: <width$}", "...", width = self.number_space + 4);
+        print!("{}", self.paint(Color::Blue, gap));
         for col in 0..self.next_connect_col {
             if self.full_connection_cols.contains(&col) {
-                print!("|");
+                print!("{}", self.paint(Color::Blue, '|'));
             } else {
                 print!(" ");
             }
@@ -338,6 +412,7 @@ impl<'a> Printer<'a> {
         };
         self.print_line_header(None, connect);
         let last_col = markers.iter().next_back().unwrap().end_col();
+        let mut parts = Vec::new();
         for col in 1..last_col {
             let mut part = if col <= arrow_end {
                 MarkerPart::ArrowBottom
@@ -372,7 +447,36 @@ impl<'a> Printer<'a> {
                     }
                 }
             }
-            print!("{}", part.to_char());
+            parts.push(part);
+        }
+        self.print_marker_parts(&parts);
+    }
+
+    fn print_marker_parts(&self, parts: &[MarkerPart]) {
+        let color = |part: MarkerPart| if part == MarkerPart::None {
+            None
+        } else if part.is_primary() {
+            Some(Color::for_severity(self.severity))
+        } else {
+            Some(Color::Blue)
+        };
+        // paint runs of same colored parts at once
+        let mut start = 0;
+        while start < parts.len() {
+            let run_color = color(parts[start]);
+            let len = parts[start..]
+                .iter()
+                .take_while(|&&part| color(part) == run_color)
+                .count();
+            let run = parts[start..(start + len)]
+                .iter()
+                .map(|part| part.to_char())
+                .collect::<String>();
+            match run_color {
+                Some(color) => print!("{}", self.paint(color, run)),
+                None => print!("{}", run),
+            }
+            start += len;
         }
     }
 
@@ -386,6 +490,7 @@ impl<'a> Printer<'a> {
             _ => (None, 0),
         };
         self.print_line_header(None, connect);
+        let mut parts = Vec::new();
         for col in 1..last_col {
             let mut part = if col <= arrow_end {
                 MarkerPart::ArrowBottom
@@ -404,8 +509,9 @@ impl<'a> Printer<'a> {
                     },
                 }
             }
-            print!("{}", part.to_char());
+            parts.push(part);
         }
+        self.print_marker_parts(&parts);
     }
 }
 
@@ -418,16 +524,45 @@ fn number_length(mut num: u32) -> u32 {
     len
 }
 
+/// A diagnostic sink that pretty-prints diagnostics to stdout.
+pub struct TerminalSink<'a> {
+    source: &'a str,
+    colors: bool,
+}
+
+impl<'a> TerminalSink<'a> {
+    /// Create a sink for diagnostics in the given source, optionally
+    /// highlighting them with ANSI color codes.
+    pub fn new(source: &'a str, colors: bool) -> Self {
+        TerminalSink { source, colors }
+    }
+}
+
+impl<'a> DiagnosticSink for TerminalSink<'a> {
+    fn emit(&mut self, diagnostic: &Diagnostic) {
+        Printer::new(self.source, self.colors).pretty_print(diagnostic);
+        println!();
+    }
+}
+
 /// Print a single diagnostic to stdout.
 pub fn print_diagnostic(source: &str, diagnostic: &Diagnostic) {
-    Printer::new(source).pretty_print(diagnostic)
+    Printer::new(source, false).pretty_print(diagnostic)
 }
 
 /// Print all diagnostics to stdout.
 ///
 /// Diagnostics will be printed in the given order.
 pub fn print_diagnostics(source: &str, diagnostics: &[Diagnostic]) {
-    let mut printer = Printer::new(source);
+    print_diagnostics_with_colors(source, diagnostics, false);
+}
+
+/// Print all diagnostics to stdout, optionally highlighting severity labels,
+/// markers and notes with ANSI color codes.
+///
+/// Diagnostics will be printed in the given order.
+pub fn print_diagnostics_with_colors(source: &str, diagnostics: &[Diagnostic], colors: bool) {
+    let mut printer = Printer::new(source, colors);
     for diagnostic in diagnostics {
         printer.pretty_print(diagnostic);
         println!("");
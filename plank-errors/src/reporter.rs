@@ -1,17 +1,67 @@
 //! Helpers to build and aggregate diagnostics.
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 use position::Span;
+use sink::DiagnosticSink;
 
 
+// identifies diagnostics that are reported only once
+type DiagnosticKey = (Severity, Option<Span>, String);
+
+fn diagnostic_key(diagnostic: &Diagnostic) -> DiagnosticKey {
+    (diagnostic.severity, diagnostic.primary_span, diagnostic.message.clone())
+}
+
+#[derive(Debug, Clone)]
+struct LintScope {
+    span: Span,
+    lint: String,
+    level: Level,
+}
+
+/// Number of errors that a new reporter keeps before suppressing the rest.
+pub const DEFAULT_ERROR_LIMIT: usize = 50;
+
 /// Reporter aggregates and allows building diagnostics.
 ///
+/// Identical diagnostics reported at the same span are only kept once, and
+/// errors past the error limit are dropped, so that badly broken input does
+/// not produce overwhelming cascades.
+///
 /// Note that reporters created by cloning will share diagnostic list with the
 /// original reporter.
-#[derive(Default, Debug, Clone)]
+#[derive(Debug, Clone)]
 pub struct Reporter {
+    // diagnostics that are reported with their current severity, without
+    // allowed lints and errors past the limit
     diagnostics: Rc<RefCell<Vec<Diagnostic>>>,
+    // keys of all diagnostics in `diagnostics`
+    reported: Rc<RefCell<HashSet<DiagnosticKey>>>,
+    lint_levels: Rc<RefCell<HashMap<String, Level>>>,
+    lint_scopes: Rc<RefCell<Vec<LintScope>>>,
+    // every diagnostic together with the severity it was made with, kept
+    // so that `diagnostics` can be recomputed when lint levels change
+    all_diagnostics: Rc<RefCell<Vec<(Severity, Diagnostic)>>>,
+    error_limit: Rc<Cell<Option<usize>>>,
+    suppressed_errors: Rc<Cell<usize>>,
+    deny_warnings: Rc<Cell<bool>>,
+}
+
+impl Default for Reporter {
+    fn default() -> Self {
+        Reporter {
+            diagnostics: Default::default(),
+            reported: Default::default(),
+            lint_levels: Default::default(),
+            lint_scopes: Default::default(),
+            all_diagnostics: Default::default(),
+            error_limit: Rc::new(Cell::new(Some(DEFAULT_ERROR_LIMIT))),
+            suppressed_errors: Default::default(),
+            deny_warnings: Default::default(),
+        }
+    }
 }
 
 impl Reporter {
@@ -20,6 +70,30 @@ impl Reporter {
         Default::default()
     }
 
+    /// Set the maximum number of errors to keep, or `None` to keep all of
+    /// them. Defaults to [`DEFAULT_ERROR_LIMIT`](constant.DEFAULT_ERROR_LIMIT.html).
+    ///
+    /// When some errors are dropped, an additional error without a span
+    /// saying how many were dropped is added to the end of diagnostic list.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use plank_errors::reporter::Reporter;
+    ///
+    /// let reporter = Reporter::new();
+    /// reporter.set_error_limit(Some(2));
+    /// for i in 0..5 {
+    ///     reporter.global_error(format!("error {}", i));
+    /// }
+    /// let diagnostics = reporter.get_diagnostics();
+    /// assert_eq!(diagnostics.len(), 3);
+    /// assert_eq!(diagnostics[2].message, "too many errors, 3 more were not reported");
+    /// ```
+    pub fn set_error_limit(&self, limit: Option<usize>) {
+        self.error_limit.set(limit);
+    }
+
     /// Returns if the reporter has any errors.
     ///
     /// This function will return false even if reporter has any warnings.
@@ -32,12 +106,37 @@ impl Reporter {
     /// let mut reporter = Reporter::new();
     /// // empty reporter should not have any errors
     /// assert!(!reporter.has_errors());
+    ///
+    /// // errors dropped because of the error limit still count
+    /// reporter.set_error_limit(Some(0));
+    /// reporter.global_error("dropped");
+    /// assert!(reporter.has_errors());
     /// ```
     pub fn has_errors(&self) -> bool {
-        self.diagnostics
+        self.error_count() > 0
+    }
+
+    /// Return the number of errors reported so far, including the ones
+    /// that were dropped because of the error limit.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use plank_errors::reporter::Reporter;
+    ///
+    /// let reporter = Reporter::new();
+    /// reporter.set_error_limit(Some(1));
+    /// reporter.global_error("first");
+    /// reporter.global_error("second");
+    /// assert_eq!(reporter.error_count(), 2);
+    /// ```
+    pub fn error_count(&self) -> usize {
+        let stored = self.diagnostics
             .borrow()
             .iter()
-            .any(|d| d.severity == Severity::Error)
+            .filter(|d| d.severity == Severity::Error)
+            .count();
+        stored + self.suppressed_errors.get()
     }
 
     /// Return the list of diagnostics collected with this reporter.
@@ -45,7 +144,141 @@ impl Reporter {
     /// The diagnosics are returned in arbitrary order. Depending on how they
     /// will be displayed, you might want to sort them.
     pub fn get_diagnostics(&self) -> Vec<Diagnostic> {
-        self.diagnostics.borrow().clone()
+        let mut diagnostics = self.diagnostics.borrow().clone();
+        let suppressed = self.suppressed_errors.get();
+        if suppressed > 0 {
+            let msg = format!(
+                "too many errors, {} more {} not reported",
+                suppressed,
+                if suppressed == 1 { "was" } else { "were" },
+            );
+            diagnostics.push(Diagnostic {
+                message: msg,
+                primary_span: None,
+                notes: Vec::new(),
+                severity: Severity::Error,
+                lint: None,
+                code: None,
+            });
+        }
+        diagnostics
+    }
+
+    /// Send collected diagnostics to the given sink, ordered by their
+    /// position in the source. Diagnostics without a span go last.
+    pub fn emit_to(&self, sink: &mut DiagnosticSink) {
+        let mut diagnostics = self.get_diagnostics();
+        diagnostics.sort_by_key(|d| (d.primary_span.is_none(), d.primary_span.map(|s| s.start)));
+        for diagnostic in &diagnostics {
+            sink.emit(diagnostic);
+        }
+        sink.finish();
+    }
+
+    /// Report all warnings as errors, including warnings of lints and
+    /// warnings that were already reported.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use plank_errors::reporter::Reporter;
+    /// use plank_errors::position::{Position, Span};
+    ///
+    /// let reporter = Reporter::new();
+    /// # let span = Span::new(Position::new(1, 1), Position::new(1, 1));
+    /// reporter.warning("unused value", span).span(span).build();
+    /// assert!(!reporter.has_errors());
+    /// reporter.set_deny_warnings(true);
+    /// assert!(reporter.has_errors());
+    /// reporter.set_deny_warnings(false);
+    /// assert!(!reporter.has_errors());
+    /// ```
+    ///
+    /// Promoted warnings count towards the error limit:
+    ///
+    /// ```rust
+    /// use plank_errors::reporter::{Reporter, Severity};
+    /// use plank_errors::position::Position;
+    ///
+    /// let reporter = Reporter::new();
+    /// reporter.set_error_limit(Some(2));
+    /// for line in 0..5 {
+    ///     let span = Position::new(line, 0).span_to(Position::new(line, 1));
+    ///     reporter.warning("unused value", span).span(span).build();
+    /// }
+    /// reporter.set_deny_warnings(true);
+    /// let diagnostics = reporter.get_diagnostics();
+    /// assert_eq!(diagnostics.len(), 3);
+    /// assert_eq!(diagnostics[2].message, "too many errors, 3 more were not reported");
+    ///
+    /// reporter.set_deny_warnings(false);
+    /// let diagnostics = reporter.get_diagnostics();
+    /// assert_eq!(diagnostics.len(), 5);
+    /// assert!(diagnostics.iter().all(|d| d.severity == Severity::Warning));
+    /// ```
+    pub fn set_deny_warnings(&self, deny: bool) {
+        self.deny_warnings.set(deny);
+        self.update_lint_levels();
+    }
+
+    /// Report a diagnostic that was made earlier, like one that was
+    /// stored together with a cached program.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use plank_errors::reporter::Reporter;
+    /// use plank_errors::position::{Position, Span};
+    ///
+    /// let first = Reporter::new();
+    /// # let span = Span::new(Position::new(1, 1), Position::new(1, 1));
+    /// first.warning("unused value", span).span(span).build();
+    /// let second = Reporter::new();
+    /// for diagnostic in first.get_diagnostics() {
+    ///     second.report(diagnostic);
+    /// }
+    /// assert_eq!(second.get_diagnostics()[0].message, "unused value");
+    /// ```
+    pub fn report(&self, diagnostic: Diagnostic) {
+        self.add_diagnostic(diagnostic);
+    }
+
+    fn add_diagnostic(&self, diagnostic: Diagnostic) {
+        self.all_diagnostics
+            .borrow_mut()
+            .push((diagnostic.severity, diagnostic.clone()));
+        self.show_diagnostic(diagnostic);
+    }
+
+    /// Apply lint levels, denied warnings and the error limit to a
+    /// diagnostic, and keep it if it is still reported after that.
+    fn show_diagnostic(&self, mut diagnostic: Diagnostic) {
+        match self.lint_level_of(&diagnostic) {
+            Some(Level::Allow) => return,
+            Some(Level::Warn) => diagnostic.severity = Severity::Warning,
+            Some(Level::Deny) => diagnostic.severity = Severity::Error,
+            None => {}
+        }
+        if self.deny_warnings.get() && diagnostic.severity == Severity::Warning {
+            diagnostic.severity = Severity::Error;
+        }
+        let mut diagnostics = self.diagnostics.borrow_mut();
+        let key = diagnostic_key(&diagnostic);
+        if self.reported.borrow().contains(&key) {
+            return;
+        }
+        if diagnostic.severity == Severity::Error {
+            let errors = diagnostics
+                .iter()
+                .filter(|d| d.severity == Severity::Error)
+                .count();
+            if self.error_limit.get().is_some_and(|limit| errors >= limit) {
+                self.suppressed_errors.set(self.suppressed_errors.get() + 1);
+                return;
+            }
+        }
+        self.reported.borrow_mut().insert(key);
+        diagnostics.push(diagnostic);
     }
 
     /// Create a new error without associated span.
@@ -64,8 +297,10 @@ impl Reporter {
             primary_span: None,
             notes: Vec::new(),
             severity: Severity::Error,
+            lint: None,
+            code: None,
         };
-        self.diagnostics.borrow_mut().push(diagnostic);
+        self.add_diagnostic(diagnostic);
     }
 
     /// Create a builder for a new error.
@@ -116,6 +351,136 @@ impl Reporter {
         self.diagnostic(Severity::Warning, msg, span)
     }
 
+    /// Create a builder for a new informational note.
+    pub fn note<T>(&self, msg: T, span: Span) -> Builder
+    where
+        T: Into<String>,
+    {
+        self.diagnostic(Severity::Note, msg, span)
+    }
+
+    /// Set the level of the given lint. Lints that were not configured
+    /// are reported as warnings.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use plank_errors::reporter::{Level, Reporter};
+    ///
+    /// let reporter = Reporter::new();
+    /// reporter.set_lint_level("dead_code", Level::Allow);
+    /// assert_eq!(reporter.lint_level("dead_code"), Level::Allow);
+    /// assert_eq!(reporter.lint_level("other_lint"), Level::Warn);
+    /// ```
+    pub fn set_lint_level<T: Into<String>>(&self, lint: T, level: Level) {
+        self.lint_levels.borrow_mut().insert(lint.into(), level);
+        self.update_lint_levels();
+    }
+
+    /// Set the level of the given lint for diagnostics inside `span`. This
+    /// overrides the level set with `set_lint_level`, and levels set for
+    /// spans that contain the given one.
+    ///
+    /// Diagnostics that were already reported inside the span are updated
+    /// too, so the order of reporting and setting levels does not matter.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use plank_errors::reporter::{Level, Reporter};
+    /// use plank_errors::position::Position;
+    ///
+    /// let reporter = Reporter::new();
+    /// let function = Position::new(0, 0).span_to(Position::new(5, 1));
+    /// let inside = Position::new(2, 4).span_to(Position::new(2, 8));
+    /// let outside = Position::new(7, 4).span_to(Position::new(7, 8));
+    /// reporter.lint("dead_code", "dead code", inside).span(inside).build();
+    /// reporter.lint("dead_code", "dead code", outside).span(outside).build();
+    /// reporter.set_lint_level_in(function, "dead_code", Level::Allow);
+    ///
+    /// assert_eq!(reporter.lint_level_at("dead_code", inside), Level::Allow);
+    /// assert_eq!(reporter.lint_level_at("dead_code", outside), Level::Warn);
+    /// assert_eq!(reporter.get_diagnostics().len(), 1);
+    /// ```
+    pub fn set_lint_level_in<T: Into<String>>(&self, span: Span, lint: T, level: Level) {
+        self.lint_scopes.borrow_mut().push(LintScope {
+            span,
+            lint: lint.into(),
+            level,
+        });
+        self.update_lint_levels();
+    }
+
+    /// Return the current level of the given lint.
+    pub fn lint_level(&self, lint: &str) -> Level {
+        self.lint_levels
+            .borrow()
+            .get(lint)
+            .cloned()
+            .unwrap_or(Level::Warn)
+    }
+
+    /// Return the level of the given lint for diagnostics at given span,
+    /// taking levels set with `set_lint_level_in` into account.
+    pub fn lint_level_at(&self, lint: &str, span: Span) -> Level {
+        self.lint_scopes
+            .borrow()
+            .iter()
+            .filter(|scope| scope.lint == lint && scope.span.contains(span))
+            // innermost scope wins
+            .max_by_key(|scope| scope.span.start)
+            .map(|scope| scope.level)
+            .unwrap_or_else(|| self.lint_level(lint))
+    }
+
+    fn lint_level_of(&self, diagnostic: &Diagnostic) -> Option<Level> {
+        let lint = diagnostic.lint.as_ref()?;
+        Some(match diagnostic.primary_span {
+            Some(span) => self.lint_level_at(lint, span),
+            None => self.lint_level(lint),
+        })
+    }
+
+    fn update_lint_levels(&self) {
+        self.diagnostics.borrow_mut().clear();
+        self.reported.borrow_mut().clear();
+        self.suppressed_errors.set(0);
+        let all = self.all_diagnostics.borrow().clone();
+        for (severity, mut diagnostic) in all {
+            diagnostic.severity = severity;
+            self.show_diagnostic(diagnostic);
+        }
+    }
+
+    /// Create a builder for a diagnostic reported by a lint. Its severity
+    /// depends on the lint level: allowed lints are not reported at all,
+    /// and denied lints are reported as errors.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use plank_errors::reporter::{Level, Reporter};
+    /// use plank_errors::position::{Position, Span};
+    ///
+    /// let reporter = Reporter::new();
+    /// # let span = Span::new(Position::new(1, 1), Position::new(1, 1));
+    /// reporter.set_lint_level("dead_code", Level::Deny);
+    /// reporter
+    ///     .lint("dead_code", "dead code detected", span)
+    ///     .span(span)
+    ///     .build();
+    /// assert!(reporter.has_errors());
+    /// ```
+    pub fn lint<T>(&self, lint: &str, msg: T, span: Span) -> Builder
+    where
+        T: Into<String>,
+    {
+        // the severity is decided by the lint level when it is reported
+        let mut builder = self.diagnostic(Severity::Warning, msg, span);
+        builder.diagnostic.lint = Some(lint.into());
+        builder
+    }
+
     /// Create a builder for a new diagnostic.
     ///
     /// # Examples
@@ -139,12 +504,25 @@ impl Reporter {
 }
 
 /// Diagnostics severity.
-#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
 pub enum Severity {
     /// Represents a fatal error.
     Error,
     /// Represents a non-fatal error.
     Warning,
+    /// Represents additional information that is not an error.
+    Note,
+}
+
+/// Lint level, decides how diagnostics of a lint are reported.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum Level {
+    /// Lint is not reported.
+    Allow,
+    /// Lint is reported as a warning.
+    Warn,
+    /// Lint is reported as an error.
+    Deny,
 }
 
 #[allow(missing_docs)]
@@ -154,6 +532,10 @@ pub struct Diagnostic {
     pub primary_span: Option<Span>,
     pub severity: Severity,
     pub notes: Vec<Note>,
+    /// Name of the lint that produced this diagnostic.
+    pub lint: Option<String>,
+    /// Error code, see the [`codes`](../codes/index.html) module.
+    pub code: Option<&'static str>,
 }
 
 #[allow(missing_docs)]
@@ -161,6 +543,17 @@ pub struct Diagnostic {
 pub struct Note {
     pub span: Span,
     pub message: Option<String>,
+    pub style: NoteStyle,
+}
+
+/// Determines how a note is rendered.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum NoteStyle {
+    /// Note points at the location of the problem.
+    Primary,
+    /// Note gives additional context, for example where the expected type
+    /// came from.
+    Secondary,
 }
 
 /// A helper for building a diagnostic.
@@ -179,6 +572,8 @@ impl Builder {
                 severity,
                 primary_span: Some(primary_span),
                 notes: Vec::new(),
+                lint: None,
+                code: None,
             },
         }
     }
@@ -195,24 +590,84 @@ impl Builder {
             !self.diagnostic.notes.is_empty(),
             "built a diagnostic without any notes"
         );
-        self.reporter.diagnostics.borrow_mut().push(self.diagnostic);
+        self.reporter.add_diagnostic(self.diagnostic);
+    }
+
+    /// Set the error code of this diagnostic.
+    pub fn code(mut self, code: &'static str) -> Self {
+        self.diagnostic.code = Some(code);
+        self
     }
 
     /// Add a new note that has only a span.
+    ///
+    /// The first note of a diagnostic is primary, the rest are secondary.
     pub fn span(self, span: Span) -> Self {
-        self.note(None, span)
+        let style = self.next_note_style();
+        self.note(None, span, style)
     }
 
     /// Add a new note that has a message and a span.
+    ///
+    /// The first note of a diagnostic is primary, the rest are secondary.
     pub fn span_note<T>(self, msg: T, span: Span) -> Self
     where
         T: Into<String>,
     {
-        self.note(Some(msg.into()), span)
+        let style = self.next_note_style();
+        self.note(Some(msg.into()), span, style)
+    }
+
+    /// Add a primary label, which points at the location of the problem.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use plank_errors::reporter::{NoteStyle, Reporter};
+    /// use plank_errors::position::Position;
+    ///
+    /// let reporter = Reporter::new();
+    /// let return_type = Position::new(0, 12).span_to(Position::new(0, 15));
+    /// let value = Position::new(1, 11).span_to(Position::new(1, 15));
+    /// reporter
+    ///     .error("cannot return `bool` from function returning `i32`", value)
+    ///     .secondary_label("expected `i32` because of return type", return_type)
+    ///     .primary_label("found `bool`", value)
+    ///     .build();
+    ///
+    /// let diagnostic = &reporter.get_diagnostics()[0];
+    /// assert_eq!(diagnostic.notes[0].style, NoteStyle::Secondary);
+    /// assert_eq!(diagnostic.notes[1].style, NoteStyle::Primary);
+    /// ```
+    pub fn primary_label<T>(self, msg: T, span: Span) -> Self
+    where
+        T: Into<String>,
+    {
+        self.note(Some(msg.into()), span, NoteStyle::Primary)
+    }
+
+    /// Add a secondary label, which gives additional context for the problem.
+    pub fn secondary_label<T>(self, msg: T, span: Span) -> Self
+    where
+        T: Into<String>,
+    {
+        self.note(Some(msg.into()), span, NoteStyle::Secondary)
+    }
+
+    fn next_note_style(&self) -> NoteStyle {
+        if self.diagnostic.notes.is_empty() {
+            NoteStyle::Primary
+        } else {
+            NoteStyle::Secondary
+        }
     }
 
-    fn note(mut self, msg: Option<String>, span: Span) -> Self {
-        self.diagnostic.notes.push(Note { span, message: msg });
+    fn note(mut self, msg: Option<String>, span: Span, style: NoteStyle) -> Self {
+        self.diagnostic.notes.push(Note {
+            span,
+            message: msg,
+            style,
+        });
         self
     }
 }
@@ -0,0 +1,256 @@
+//! Export diagnostics in [SARIF 2.1.0] format, so that they can be consumed
+//! by code scanning tools.
+//!
+//! [SARIF 2.1.0]: https://docs.oasis-open.org/sarif/sarif/v2.1.0/sarif-v2.1.0.html
+
+use std::fmt;
+use codes;
+use position::Span;
+use reporter::{Diagnostic, NoteStyle, Severity};
+
+
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+const VERSION: &str = "2.1.0";
+const TOOL_NAME: &str = "plank";
+
+/// A minimal JSON value, enough to write out a SARIF log.
+enum Json {
+    Number(u64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl Json {
+    fn str<S: Into<String>>(s: S) -> Json {
+        Json::String(s.into())
+    }
+
+    fn write(&self, f: &mut fmt::Formatter, indent: usize) -> fmt::Result {
+        match *self {
+            Json::Number(n) => write!(f, "{}", n),
+            Json::String(ref s) => write_string(f, s),
+            Json::Array(ref items) if items.is_empty() => write!(f, "[]"),
+            Json::Array(ref items) => {
+                writeln!(f, "[")?;
+                for (index, item) in items.iter().enumerate() {
+                    write_indent(f, indent + 1)?;
+                    item.write(f, indent + 1)?;
+                    if index + 1 < items.len() {
+                        write!(f, ",")?;
+                    }
+                    writeln!(f)?;
+                }
+                write_indent(f, indent)?;
+                write!(f, "]")
+            }
+            Json::Object(ref fields) if fields.is_empty() => write!(f, "{{}}"),
+            Json::Object(ref fields) => {
+                writeln!(f, "{{")?;
+                for (index, &(key, ref value)) in fields.iter().enumerate() {
+                    write_indent(f, indent + 1)?;
+                    write_string(f, key)?;
+                    write!(f, ": ")?;
+                    value.write(f, indent + 1)?;
+                    if index + 1 < fields.len() {
+                        write!(f, ",")?;
+                    }
+                    writeln!(f)?;
+                }
+                write_indent(f, indent)?;
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+fn write_indent(f: &mut fmt::Formatter, indent: usize) -> fmt::Result {
+    for _ in 0..indent {
+        write!(f, "  ")?;
+    }
+    Ok(())
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for ch in s.chars() {
+        match ch {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            ch if (ch as u32) < 0x20 => write!(f, "\\u{:04x}", ch as u32)?,
+            ch => write!(f, "{}", ch)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Format diagnostics as a SARIF log with a single run. `uri` is used as
+/// the artifact location of every result, and `source` must be the text
+/// that diagnostic spans point into.
+///
+/// Diagnostics are identified by their error code if they have one, and by
+/// lint name otherwise.
+///
+/// # Examples
+///
+/// ```rust
+/// use plank_errors::Reporter;
+/// use plank_errors::position::Position;
+///
+/// let source = "fn main() {}";
+/// let reporter = Reporter::new();
+/// let span = Position::new(0, 3).span_to(Position::new(0, 7));
+/// reporter
+///     .error("something went wrong", span)
+///     .span(span)
+///     .code("E0001")
+///     .build();
+///
+/// let log = plank_errors::sarif::to_sarif("main.plk", source, &reporter.get_diagnostics());
+/// assert!(log.contains("\"ruleId\": \"E0001\""));
+/// assert!(log.contains("\"startColumn\": 4"));
+/// ```
+pub fn to_sarif(uri: &str, source: &str, diagnostics: &[Diagnostic]) -> String {
+    to_sarif_files(&[(uri, source, diagnostics)])
+}
+
+/// Format diagnostics of a program made of several files as a SARIF log
+/// with a single run. Each item has the uri and text of a file, and the
+/// diagnostics whose spans point into it.
+pub fn to_sarif_files(files: &[(&str, &str, &[Diagnostic])]) -> String {
+    let mut rules = Vec::new();
+    for &(_, _, diagnostics) in files {
+        for diagnostic in diagnostics {
+            if let Some(id) = rule_id(diagnostic) {
+                if !rules.contains(&id) {
+                    rules.push(id);
+                }
+            }
+        }
+    }
+    let rules = rules.into_iter().map(rule).collect();
+    let results = files
+        .iter()
+        .flat_map(|&(uri, source, diagnostics)| {
+            diagnostics.iter().map(move |d| result(uri, source, d))
+        })
+        .collect();
+
+    let driver = Json::Object(vec![
+        ("name", Json::str(TOOL_NAME)),
+        ("informationUri", Json::str("https://github.com/jDomantas/plank")),
+        ("rules", Json::Array(rules)),
+    ]);
+    let run = Json::Object(vec![
+        ("tool", Json::Object(vec![("driver", driver)])),
+        ("columnKind", Json::str("utf16CodeUnits")),
+        ("results", Json::Array(results)),
+    ]);
+    let log = Json::Object(vec![
+        ("$schema", Json::str(SCHEMA)),
+        ("version", Json::str(VERSION)),
+        ("runs", Json::Array(vec![run])),
+    ]);
+    format!("{}\n", log)
+}
+
+fn rule_id(diagnostic: &Diagnostic) -> Option<&str> {
+    diagnostic.code.or(diagnostic.lint.as_deref())
+}
+
+fn rule(id: &str) -> Json {
+    let mut fields = vec![("id", Json::str(id))];
+    if let Some(explanation) = codes::explain(id) {
+        // first paragraph of the explanation is a short description
+        let short = explanation
+            .split("\n\n")
+            .next()
+            .unwrap_or("")
+            .replace('\n', " ");
+        fields.push((
+            "shortDescription",
+            Json::Object(vec![("text", Json::str(short))]),
+        ));
+        fields.push((
+            "fullDescription",
+            Json::Object(vec![("markdown", Json::str(explanation))]),
+        ));
+    }
+    Json::Object(fields)
+}
+
+fn result(uri: &str, source: &str, diagnostic: &Diagnostic) -> Json {
+    let level = match diagnostic.severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Note => "note",
+    };
+    let mut fields = Vec::new();
+    if let Some(id) = rule_id(diagnostic) {
+        fields.push(("ruleId", Json::str(id)));
+    }
+    fields.push(("level", Json::str(level)));
+    fields.push(("message", message(&diagnostic.message)));
+    let mut locations = diagnostic
+        .notes
+        .iter()
+        .filter(|n| n.style == NoteStyle::Primary)
+        .map(|n| location(uri, source, n.span, n.message.as_deref()))
+        .collect::<Vec<_>>();
+    match diagnostic.primary_span {
+        Some(span) if locations.is_empty() => locations.push(location(uri, source, span, None)),
+        _ => {}
+    }
+    if !locations.is_empty() {
+        fields.push(("locations", Json::Array(locations)));
+    }
+    let related = diagnostic
+        .notes
+        .iter()
+        .filter(|n| n.style == NoteStyle::Secondary)
+        .enumerate()
+        .map(|(index, note)| {
+            let mut location = location(uri, source, note.span, note.message.as_deref());
+            if let Json::Object(ref mut fields) = location {
+                fields.insert(0, ("id", Json::Number(index as u64)));
+            }
+            location
+        })
+        .collect::<Vec<_>>();
+    if !related.is_empty() {
+        fields.push(("relatedLocations", Json::Array(related)));
+    }
+    Json::Object(fields)
+}
+
+fn message(text: &str) -> Json {
+    Json::Object(vec![("text", Json::str(text))])
+}
+
+fn location(uri: &str, source: &str, span: Span, label: Option<&str>) -> Json {
+    // SARIF lines and columns are 1-based, and end column is exclusive
+    let region = Json::Object(vec![
+        ("startLine", Json::Number(u64::from(span.start.line) + 1)),
+        ("startColumn", Json::Number(u64::from(span.start.utf16_column(source)) + 1)),
+        ("endLine", Json::Number(u64::from(span.end.line) + 1)),
+        ("endColumn", Json::Number(u64::from(span.end.utf16_column(source)) + 1)),
+    ]);
+    let physical = Json::Object(vec![
+        ("artifactLocation", Json::Object(vec![("uri", Json::str(uri))])),
+        ("region", region),
+    ]);
+    let mut fields = vec![("physicalLocation", physical)];
+    if let Some(label) = label {
+        fields.push(("message", message(label)));
+    }
+    Json::Object(fields)
+}
@@ -0,0 +1,45 @@
+//! Destinations for reported diagnostics.
+
+use reporter::Diagnostic;
+
+
+/// A destination for diagnostics collected by a
+/// [`Reporter`](../reporter/struct.Reporter.html).
+///
+/// Implement this to collect diagnostics into custom structures, for example
+/// to convert them for a language server, instead of printing them.
+///
+/// # Examples
+///
+/// ```rust
+/// use plank_errors::Reporter;
+/// use plank_errors::reporter::Diagnostic;
+/// use plank_errors::sink::DiagnosticSink;
+///
+/// struct MessageSink(Vec<String>);
+///
+/// impl DiagnosticSink for MessageSink {
+///     fn emit(&mut self, diagnostic: &Diagnostic) {
+///         self.0.push(diagnostic.message.clone());
+///     }
+/// }
+///
+/// let reporter = Reporter::new();
+/// reporter.global_error("`main` function is missing");
+/// let mut sink = MessageSink(Vec::new());
+/// reporter.emit_to(&mut sink);
+/// assert_eq!(sink.0, vec!["`main` function is missing"]);
+/// ```
+pub trait DiagnosticSink {
+    /// Receive a single diagnostic.
+    fn emit(&mut self, diagnostic: &Diagnostic);
+
+    /// Called after all diagnostics were emitted.
+    fn finish(&mut self) {}
+}
+
+impl DiagnosticSink for Vec<Diagnostic> {
+    fn emit(&mut self, diagnostic: &Diagnostic) {
+        self.push(diagnostic.clone());
+    }
+}
@@ -1,25 +1,44 @@
-use std::collections::{HashMap, HashSet};
-use plank_syntax::position::Spanned;
-use ast::cfg::{Program, Function, Block, Reg, Instruction, Value, BlockId, BlockEnd};
+use std::collections::{HashMap, HashSet, VecDeque};
+use plank_errors::codes;
+use plank_syntax::position::{Span, Spanned};
+use ast::cfg::{Program, Function, Block, Reg, Instruction, Value, BlockId, BlockEnd, Symbol, Type};
+use ast::typed::{Struct, StructKind};
 use CompileCtx;
 
 
 struct Context<'a> {
     ctx: &'a mut CompileCtx,
     function: &'a Function,
+    structs: &'a HashMap<Symbol, Struct>,
     reported_regs: HashSet<Reg>,
     assign_position: HashMap<(Reg, BlockId), usize>,
-    reachable_labels: HashSet<(Reg, BlockId)>,
+    /// Registers that might be uninitialized at the start of each block.
+    uninitialized: HashMap<BlockId, HashSet<Reg>>,
+    predecessors: HashMap<BlockId, Vec<BlockId>>,
+}
+
+fn successors(block: &Block) -> Vec<BlockId> {
+    match block.end {
+        BlockEnd::Jump(to) => vec![to],
+        BlockEnd::Branch(_, a, b) => vec![a, b],
+        BlockEnd::Return(_) | BlockEnd::Unreachable | BlockEnd::Error => vec![],
+    }
 }
 
 impl<'a> Context<'a> {
-    fn new(function: &'a Function, ctx: &'a mut CompileCtx) -> Self {
+    fn new(
+        function: &'a Function,
+        structs: &'a HashMap<Symbol, Struct>,
+        ctx: &'a mut CompileCtx,
+    ) -> Self {
         Context {
             ctx,
             function,
+            structs,
             reported_regs: HashSet::new(),
             assign_position: HashMap::new(),
-            reachable_labels: HashSet::new(),
+            uninitialized: HashMap::new(),
+            predecessors: HashMap::new(),
         }
     }
 
@@ -35,6 +54,11 @@ impl<'a> Context<'a> {
                 Instruction::CastAssign(reg, _) => {
                     self.assign_position.entry((reg, id)).or_insert(index);
                 }
+                Instruction::FieldStore(reg, ref fields, _)
+                    if self.is_union_write(*reg, fields) =>
+                {
+                    self.assign_position.entry((*reg, id)).or_insert(index);
+                }
                 Instruction::Error |
                 Instruction::Drop(_) |
                 Instruction::DerefStore(_, _, _, _) |
@@ -52,10 +76,12 @@ impl<'a> Context<'a> {
                 Instruction::CastAssign(_, ref val) => {
                     self.check_value(val, id, index);
                 }
-                Instruction::FieldStore(reg, _, ref val) => {
+                Instruction::FieldStore(reg, ref fields, ref val) => {
                     self.check_value(val, id, index);
-                    let reg = Spanned::map(reg, Value::Reg);
-                    self.check_value(&reg, id, index);
+                    if !self.is_union_write(*reg, fields) {
+                        let reg = Spanned::map(reg, Value::Reg);
+                        self.check_value(&reg, id, index);
+                    }
                 }
                 Instruction::DerefStore(ref a, _, _, ref b) |
                 Instruction::BinaryOp(_, _, ref a, ref b) => {
@@ -78,13 +104,22 @@ impl<'a> Context<'a> {
                 Instruction::Init(_) => {}
             }
         }
+        let end = block.ops.len();
+        match block.end {
+            BlockEnd::Return(ref val) | BlockEnd::Branch(ref val, _, _) => {
+                self.check_value(val, id, end);
+            }
+            BlockEnd::Jump(_) | BlockEnd::Unreachable | BlockEnd::Error => {}
+        }
     }
 
     fn check_value(&mut self, value: &Spanned<Value>, block: BlockId, pos: usize) {
         match **value {
             Value::Int(_, _) |
+            Value::NumericInt(_, _) |
             Value::Symbol(_, _) |
             Value::Bytes(_) |
+            Value::Null |
             Value::Unit |
             Value::Error => {}
             Value::Reg(reg) if self.reported_regs.contains(&reg) => {}
@@ -93,67 +128,205 @@ impl<'a> Context<'a> {
                 if assign_pos.map(|p| p < pos) == Some(true) {
                     return;
                 }
-                if !self.reachable_labels.contains(&(reg, block)) {
+                if !self.might_be_uninitialized(reg, block) {
                     return;
                 }
                 // occasionally temporary registers happen to be uninitialized
                 // (in cases of constness mismatch when taking references),
                 // so don't report about that
                 if let Some(&var_symbol) = self.function.register_symbols.get(&reg) {
-                    let name = self.ctx.symbols.get_name(var_symbol);
+                    let name = self.ctx.symbols.get_name(var_symbol).to_string();
                     let msg = format!("var `{}` might be uninitialized here", name);
                     let span = Spanned::span(value);
-                    self.ctx
+                    let mut builder = self.ctx
                         .reporter
                         .error(msg, span)
-                        .span(span)
-                        .build();
+                        .code(codes::UNINITIALIZED_VAR)
+                        .primary_label(format!("`{}` is read here", name), span);
+                    if let Some((cond, taken)) = self.skipping_branch(reg, block) {
+                        let note = format!(
+                            "`{}` is not initialized when this condition is {}",
+                            name,
+                            taken,
+                        );
+                        builder = builder.secondary_label(note, cond);
+                    }
+                    if let Some(&declared) = self.function.declared_uninit.get(&reg) {
+                        let note = format!("`{}` is declared here without a value", name);
+                        builder = builder.secondary_label(note, declared);
+                    }
+                    builder.build();
                     self.reported_regs.insert(reg);
                 }
             }
         }
     }
 
-    fn visit_label(&mut self, reg: Reg, block: BlockId) {
-        if !self.reachable_labels.contains(&(reg, block)) {
-            self.reachable_labels.insert((reg, block));
-            if self.assign_position.contains_key(&(reg, block)) {
-                return;
+    /// Writing a field of a union initializes the whole union.
+    fn is_union_write(&self, reg: Reg, fields: &[usize]) -> bool {
+        match self.function.registers[&reg] {
+            Type::Concrete(name, _) if fields.len() == 1 => {
+                self.structs.get(&name).map(|s| s.kind) == Some(StructKind::Union)
             }
-            match self.function.blocks[&block].end {
-                BlockEnd::Jump(to) => {
-                    self.visit_label(reg, to);
+            _ => false,
+        }
+    }
+
+    fn might_be_uninitialized(&self, reg: Reg, block: BlockId) -> bool {
+        self.uninitialized
+            .get(&block)
+            .is_some_and(|regs| regs.contains(&reg))
+    }
+
+    fn assigns(&self, reg: Reg, block: BlockId) -> bool {
+        self.assign_position.contains_key(&(reg, block))
+    }
+
+    /// Forward dataflow: a register might be uninitialized at the start of
+    /// a block if it might be uninitialized at the end of any predecessor.
+    fn find_uninitialized(&mut self) {
+        let start = match self.function.start_block {
+            Some(block) => block,
+            None => return,
+        };
+        let parameters = self.function.parameters.iter().cloned().collect::<HashSet<_>>();
+        let initial = self.function
+            .registers
+            .keys()
+            .cloned()
+            .filter(|reg| !parameters.contains(reg))
+            .collect();
+        self.uninitialized.insert(start, initial);
+        let mut queue = VecDeque::new();
+        queue.push_back(start);
+        while let Some(id) = queue.pop_front() {
+            let block = &self.function.blocks[&id];
+            let out = self.uninitialized[&id]
+                .iter()
+                .cloned()
+                .filter(|&reg| !self.assigns(reg, id))
+                .collect::<Vec<_>>();
+            for next in successors(block) {
+                let visited = self.uninitialized.contains_key(&next);
+                let regs = self.uninitialized.entry(next).or_default();
+                let before = regs.len();
+                regs.extend(out.iter().cloned());
+                if !visited || regs.len() > before {
+                    queue.push_back(next);
                 }
-                BlockEnd::Branch(_, a, b) => {
-                    self.visit_label(reg, a);
-                    self.visit_label(reg, b);
+            }
+        }
+    }
+
+    /// Find the branch closest to `block` such that one of its arms reaches
+    /// `block` leaving `reg` uninitialized, and the other one reaches it
+    /// only after initializing `reg`.
+    fn skipping_branch(&self, reg: Reg, block: BlockId) -> Option<(Span, bool)> {
+        // blocks that can reach `block` without initializing `reg`
+        let mut skipping = HashSet::new();
+        let mut order = Vec::new();
+        let mut queue = VecDeque::new();
+        skipping.insert(block);
+        queue.push_back(block);
+        while let Some(id) = queue.pop_front() {
+            order.push(id);
+            for &pred in self.predecessors.get(&id).into_iter().flatten() {
+                let uninit_at_end = self.might_be_uninitialized(reg, pred) && !self.assigns(reg, pred);
+                if uninit_at_end && skipping.insert(pred) {
+                    queue.push_back(pred);
                 }
-                BlockEnd::Return(_) | BlockEnd::Error => {}
             }
         }
+        for id in order {
+            if let BlockEnd::Branch(ref cond, a, b) = self.function.blocks[&id].end {
+                let (taken, other) = match (skipping.contains(&a), skipping.contains(&b)) {
+                    (true, false) => (true, b),
+                    (false, true) => (false, a),
+                    _ => continue,
+                };
+                if self.can_reach(other, block) {
+                    return Some((Spanned::span(cond), taken));
+                }
+            }
+        }
+        None
     }
 
-    fn check_function(&mut self) {
+    fn can_reach(&self, from: BlockId, to: BlockId) -> bool {
+        let mut visited = HashSet::new();
+        let mut stack = vec![from];
+        while let Some(id) = stack.pop() {
+            if id == to {
+                return true;
+            }
+            if visited.insert(id) {
+                stack.extend(successors(&self.function.blocks[&id]));
+            }
+        }
+        false
+    }
+
+    fn check_deferred_init(&mut self, reg: Reg) {
+        let mut assigns = Vec::new();
         for (&id, block) in &self.function.blocks {
-            self.store_assigns(id, block);
+            for (index, op) in block.ops.iter().enumerate() {
+                if let Instruction::Assign(target, _) = **op {
+                    if target == reg {
+                        assigns.push((id, index, Spanned::span(op)));
+                    }
+                }
+            }
         }
-        let parameters = self.function.parameters.iter().cloned().collect::<HashSet<_>>();
-        if let Some(block) = self.function.start_block {
-            for &reg in self.function.registers.keys() {
-                if !parameters.contains(&reg) {
-                    self.visit_label(reg, block);
+        assigns.sort_by_key(|&(id, index, _)| (id, index));
+        // find blocks that can be reached after some assignment
+        let mut reachable = HashSet::new();
+        let mut stack = assigns.iter().map(|&(id, _, _)| id).collect::<Vec<_>>();
+        while let Some(id) = stack.pop() {
+            for block in successors(&self.function.blocks[&id]) {
+                if reachable.insert(block) {
+                    stack.push(block);
                 }
             }
         }
+        for (i, &(id, _, span)) in assigns.iter().enumerate() {
+            let assigned_in_block = i > 0 && assigns[i - 1].0 == id;
+            if assigned_in_block || reachable.contains(&id) {
+                let name = self.ctx.symbols.get_name(self.function.register_symbols[&reg]);
+                let msg = format!("var `{}` might be assigned twice", name);
+                let note = format!("`{}` is not `mut`, so it can only be assigned once", name);
+                self.ctx
+                    .reporter
+                    .error(msg, span)
+                    .code(codes::ASSIGNED_TWICE)
+                    .span_note(note, span)
+                    .build();
+                return;
+            }
+        }
+    }
+
+    fn check_function(&mut self) {
+        for (&id, block) in &self.function.blocks {
+            self.store_assigns(id, block);
+            for next in successors(block) {
+                self.predecessors.entry(next).or_default().push(id);
+            }
+        }
+        self.find_uninitialized();
         for (&id, block) in &self.function.blocks {
             self.check_block(id, block);
         }
+        let mut deferred_init = self.function.deferred_init.iter().cloned().collect::<Vec<_>>();
+        deferred_init.sort();
+        for reg in deferred_init {
+            self.check_deferred_init(reg);
+        }
     }
 }
 
 pub(crate) fn check_program(program: &Program, ctx: &mut CompileCtx) {
     for f in program.functions.values() {
-        let mut ctx = Context::new(f, ctx);
+        let mut ctx = Context::new(f, &program.structs, ctx);
         ctx.check_function();
     }
 }
@@ -1,7 +1,7 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use plank_syntax::position::{Span, Spanned};
 use ast::typed;
-pub use ast::typed::{Mutability, Signedness, Size, Symbol, Type};
+pub use ast::typed::{CallingConvention, Inline, Mutability, Signedness, Size, Symbol, Type};
 
 
 #[derive(Debug, Clone)]
@@ -13,13 +13,22 @@ pub struct Program {
 #[derive(Debug, Clone)]
 pub struct Function {
     pub complete_span: Span,
+    /// Span of function header, from `fn` to the return type.
+    pub signature_span: Span,
     pub type_params: Vec<Symbol>,
     pub parameters: Vec<Reg>,
     pub out_type: Type,
     pub registers: HashMap<Reg, Type>,
     pub register_symbols: HashMap<Reg, Symbol>,
+    /// Registers of non-mut variables that were declared
+    /// without a value, and so can be assigned once later.
+    pub deferred_init: HashSet<Reg>,
+    /// Spans of names of variables that were declared without a value.
+    pub declared_uninit: HashMap<Reg, Span>,
     pub blocks: HashMap<BlockId, Block>,
     pub start_block: Option<BlockId>,
+    pub inline: Inline,
+    pub calling_convention: CallingConvention,
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Copy, Clone)]
@@ -67,9 +76,13 @@ pub enum Instruction {
 #[derive(Debug, Clone)]
 pub enum Value {
     Int(u64, Size),
+    /// Int literal of a `Numeric` type parameter.
+    NumericInt(u64, Type),
     Reg(Reg),
     Symbol(Symbol, Vec<Type>),
     Bytes(Vec<u8>),
+    /// Null pointer, used as the environment of functions that don't have one.
+    Null,
     Unit,
     Error,
 }
@@ -79,10 +92,12 @@ pub enum BlockEnd {
     Return(Spanned<Value>),
     Jump(BlockId),
     Branch(Spanned<Value>, BlockId, BlockId),
+    /// Block ends with a call to a `#[noreturn]` function.
+    Unreachable,
     Error,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub enum BinaryOp {
     Add(Signedness, Size),
     Sub(Signedness, Size),
@@ -95,17 +110,26 @@ pub enum BinaryOp {
     GreaterEq(Signedness, Size),
     Eq,
     Neq,
+    /// Int operation on a `Numeric` type parameter, which
+    /// becomes one of the above after monomorphization.
+    Numeric(typed::BinaryOp, Type),
 }
 
 #[derive(Debug, Clone)]
 pub enum UnaryOp {
     Negate(Signedness, Size),
+    /// Negation of a `Numeric` type parameter.
+    NumericNegate(Type),
+    /// Conversion of an int to a wider int with the same signedness.
+    Extend(Signedness, Size, Size),
     Not,
     DerefLoad,
     /// <arg>.field1.field2...
     FieldLoad(Type, Vec<usize>),
     /// &(*<arg>).field1.field2...
     OffsetAddress(Type, Vec<usize>),
+    /// <arg> * size_of::<T>()
+    ElementOffset(Type),
 }
 
 
@@ -139,7 +163,9 @@ pub(crate) mod printer {
     ) -> ::std::fmt::Result {
         match *ty {
             Type::Unit => write!(f, "unit"),
+            Type::Never => write!(f, "never"),
             Type::Bool => write!(f, "bool"),
+            Type::Str => write!(f, "str"),
             Type::Concrete(sym, ref params) => {
                 write!(f, "{}", ctx.symbols.get_name(sym))?;
                 write_type_list(f, params, ctx)
@@ -155,6 +181,14 @@ pub(crate) mod printer {
                 write!(f, "*mut ")?;
                 write_type(f, to, ctx)
             }
+            Type::Slice(Mutability::Const, ref to) => {
+                write!(f, "[]")?;
+                write_type(f, to, ctx)
+            }
+            Type::Slice(Mutability::Mut, ref to) => {
+                write!(f, "[]mut ")?;
+                write_type(f, to, ctx)
+            }
             Type::Var(_) => panic!("found type var"),
         }
     }
@@ -190,13 +224,14 @@ pub(crate) mod printer {
             match *self.value {
                 Value::Error => write!(f, "?"),
                 Value::Unit => write!(f, "unit"),
-                Value::Int(i, _) => write!(f, "{}", i),
+                Value::Int(i, _) | Value::NumericInt(i, _) => write!(f, "{}", i),
                 Value::Reg(reg) => write!(f, "r{}", reg.0),
                 Value::Symbol(sym, ref params) => {
                     write!(f, "{}", self.ctx.symbols.get_name(sym))?;
                     write_type_list(f, params, self.ctx)
                 }
                 Value::Bytes(_) => write!(f, "<bytes>"),
+                Value::Null => write!(f, "null"),
             }
         }
     }
@@ -246,6 +281,7 @@ pub(crate) mod printer {
                     write_int(f, sign, size)
                 }
                 BinaryOp::Neq => write!(f, "neq"),
+                BinaryOp::Numeric(op, _) => write!(f, "numeric_{:?}", op),
                 BinaryOp::Sub(sign, size) => {
                     write!(f, "sub_")?;
                     write_int(f, sign, size)
@@ -277,6 +313,13 @@ pub(crate) mod printer {
                     write!(f, "neg_")?;
                     write_int(f, sign, size)
                 }
+                UnaryOp::NumericNegate(_) => write!(f, "numeric_neg"),
+                UnaryOp::Extend(sign, from, to) => {
+                    write!(f, "extend_")?;
+                    write_int(f, sign, from)?;
+                    write!(f, "_")?;
+                    write_int(f, sign, to)
+                }
                 UnaryOp::Not => write!(f, "not"),
                 UnaryOp::OffsetAddress(_, ref fields) => {
                     write!(f, "field_offset ")?;
@@ -285,6 +328,7 @@ pub(crate) mod printer {
                     }
                     Ok(())
                 }
+                UnaryOp::ElementOffset(_) => write!(f, "element_offset"),
             }
         }
     }
@@ -402,6 +446,9 @@ pub(crate) mod printer {
             BlockEnd::Return(ref value) => {
                 println!("    return {}", d(value, ctx));
             }
+            BlockEnd::Unreachable => {
+                println!("    unreachable");
+            }
         }
     }
 }
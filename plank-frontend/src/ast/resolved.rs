@@ -1,5 +1,7 @@
 use std::collections::HashMap;
-pub use plank_syntax::ast::{BinaryOp, FunctionType, Literal, Number, Signedness, Size, UnaryOp, Mutability};
+pub use plank_syntax::ast::{BinaryOp, FunctionType, Literal, Number, Signedness, Size, StructKind,
+                            UnaryOp, Mutability};
+pub use plank_ir::target::CallingConvention;
 use plank_syntax::position::{Span, Spanned};
 
 
@@ -12,12 +14,26 @@ pub enum Expr {
     Unary(Spanned<UnaryOp>, Box<Spanned<Expr>>),
     Call(Box<Spanned<Expr>>, Vec<Spanned<Expr>>),
     Field(Box<Spanned<Expr>>, Spanned<String>),
+    Index(Box<Spanned<Expr>>, Box<Spanned<Expr>>),
+    Slice(Box<Spanned<Expr>>, Box<Spanned<Expr>>, Box<Spanned<Expr>>),
     Name(Spanned<Symbol>, Vec<Spanned<Type>>),
     Literal(Literal),
     Cast(Box<Spanned<Expr>>, Spanned<Type>),
+    Lambda(Box<Lambda>),
     Error,
 }
 
+#[derive(Debug, Clone)]
+pub struct Lambda {
+    pub name: Symbol,
+    pub params: Vec<FnParam>,
+    pub return_type: Spanned<Type>,
+    pub body: Spanned<Statement>,
+    /// Captured variables, as `(outer, inner)` pairs: `outer` is the
+    /// variable in enclosing function, and `inner` is its copy in lambda body.
+    pub captures: Vec<(Symbol, Symbol)>,
+}
+
 #[derive(Debug, Clone)]
 pub enum Statement {
     If(
@@ -32,10 +48,23 @@ pub enum Statement {
     Return(Spanned<Expr>),
     Let(Mutability, Spanned<Symbol>, Spanned<Type>, Option<Spanned<Expr>>),
     Block(Vec<Spanned<Statement>>),
+    Match(Spanned<Expr>, Vec<MatchArm>),
     Expr(Spanned<Expr>),
     Error,
 }
 
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Spanned<Pattern>,
+    pub body: Spanned<Statement>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Wildcard,
+    Value(Spanned<Expr>),
+}
+
 #[derive(Debug, Clone)]
 pub enum Type {
     Wildcard,
@@ -47,8 +76,10 @@ pub enum Type {
     U32,
     Bool,
     Unit,
+    Str,
     Concrete(Spanned<Symbol>, Vec<Spanned<Type>>),
     Pointer(Mutability, Box<Spanned<Type>>),
+    Slice(Mutability, Box<Spanned<Type>>),
     Function(Vec<Spanned<Type>>, Box<Spanned<Type>>),
     Error,
 }
@@ -57,6 +88,14 @@ pub enum Type {
 pub struct ItemName {
     pub name: Spanned<Symbol>,
     pub type_params: Vec<Spanned<Symbol>>,
+    pub bounds: Vec<Option<Spanned<Bound>>>,
+}
+
+/// Built-in requirement on a type parameter.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum Bound {
+    /// Any integer type, or another `Numeric` type parameter.
+    Numeric,
 }
 
 #[derive(Debug, Clone)]
@@ -64,6 +103,8 @@ pub struct FnParam {
     pub mutability: Mutability,
     pub name: Spanned<Symbol>,
     pub typ: Spanned<Type>,
+    /// Default value, which is also copied into every call that omits it.
+    pub default: Option<Spanned<Expr>>,
 }
 
 #[derive(Debug, Clone)]
@@ -74,6 +115,18 @@ pub struct Function {
     pub params: Vec<FnParam>,
     pub return_type: Spanned<Type>,
     pub body: Option<Spanned<Statement>>,
+    /// Function has `#[noreturn]` attribute, so calls to it never return.
+    pub noreturn: bool,
+    pub inline: Inline,
+    pub calling_convention: CallingConvention,
+}
+
+/// Inlining requested with `#[inline]` or `#[inline(never)]`.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum Inline {
+    Auto,
+    Always,
+    Never,
 }
 
 #[derive(Debug, Clone)]
@@ -85,6 +138,7 @@ pub struct Field {
 #[derive(Debug, Clone)]
 pub struct Struct {
     pub complete_span: Span,
+    pub kind: StructKind,
     pub name: ItemName,
     pub fields: Vec<Field>,
 }
@@ -94,3 +148,395 @@ pub struct Program {
     pub structs: HashMap<Symbol, Struct>,
     pub functions: Vec<Function>,
 }
+
+pub(crate) mod printer {
+    use std::fmt::Write;
+    use super::*;
+    use CompileCtx;
+
+
+    /// Formats resolved programs like source code, where every name is
+    /// followed by the symbol it was resolved to, like `x#80`, so that
+    /// names that shadow each other can be told apart.
+    struct Printer<'a> {
+        output: String,
+        indent: usize,
+        ctx: &'a CompileCtx,
+    }
+
+    impl<'a> Printer<'a> {
+        fn symbol(&mut self, symbol: Symbol) {
+            let name = self.ctx.symbols.get_name(symbol);
+            write!(self.output, "{}#{}", name, symbol.0).unwrap();
+        }
+
+        fn line(&mut self) {
+            self.output.push('\n');
+            for _ in 0..self.indent {
+                self.output.push_str("    ");
+            }
+        }
+
+        fn item_name(&mut self, name: &ItemName) {
+            self.symbol(*name.name);
+            if name.type_params.is_empty() {
+                return;
+            }
+            self.output.push('<');
+            for (index, (param, bound)) in name.type_params.iter().zip(&name.bounds).enumerate() {
+                if index > 0 {
+                    self.output.push_str(", ");
+                }
+                self.symbol(**param);
+                if let Some(ref bound) = *bound {
+                    match **bound {
+                        Bound::Numeric => self.output.push_str(": Numeric"),
+                    }
+                }
+            }
+            self.output.push('>');
+        }
+
+        fn structure(&mut self, s: &Struct) {
+            self.output.push_str(match s.kind {
+                StructKind::Struct => "struct ",
+                StructKind::Union => "union ",
+            });
+            self.item_name(&s.name);
+            self.output.push_str(" {");
+            self.indent += 1;
+            for field in &s.fields {
+                self.line();
+                self.symbol(*field.name);
+                self.output.push_str(": ");
+                self.typ(&field.typ);
+                self.output.push(',');
+            }
+            self.indent -= 1;
+            self.line();
+            self.output.push_str("}\n");
+        }
+
+        fn function(&mut self, f: &Function) {
+            if let FunctionType::Extern = f.fn_type {
+                write!(self.output, "extern \"{}\" ", f.calling_convention.name()).unwrap();
+            }
+            self.output.push_str("fn ");
+            self.item_name(&f.name);
+            self.params(&f.params);
+            self.output.push_str(" -> ");
+            self.typ(&f.return_type);
+            match f.body {
+                Some(ref body) => {
+                    self.output.push(' ');
+                    self.statement(body);
+                    self.output.push('\n');
+                }
+                None => self.output.push_str(";\n"),
+            }
+        }
+
+        fn params(&mut self, params: &[FnParam]) {
+            self.output.push('(');
+            for (index, param) in params.iter().enumerate() {
+                if index > 0 {
+                    self.output.push_str(", ");
+                }
+                if let Mutability::Mut = param.mutability {
+                    self.output.push_str("mut ");
+                }
+                self.symbol(*param.name);
+                self.output.push_str(": ");
+                self.typ(&param.typ);
+            }
+            self.output.push(')');
+        }
+
+        fn types(&mut self, types: &[Spanned<Type>]) {
+            for (index, typ) in types.iter().enumerate() {
+                if index > 0 {
+                    self.output.push_str(", ");
+                }
+                self.typ(typ);
+            }
+        }
+
+        fn typ(&mut self, typ: &Type) {
+            let name = match *typ {
+                Type::Wildcard => "_",
+                Type::I8 => "i8",
+                Type::U8 => "u8",
+                Type::I16 => "i16",
+                Type::U16 => "u16",
+                Type::I32 => "i32",
+                Type::U32 => "u32",
+                Type::Bool => "bool",
+                Type::Unit => "unit",
+                Type::Str => "str",
+                Type::Error => "?",
+                Type::Concrete(ref symbol, ref params) => {
+                    self.symbol(**symbol);
+                    if !params.is_empty() {
+                        self.output.push('<');
+                        self.types(params);
+                        self.output.push('>');
+                    }
+                    return;
+                }
+                Type::Pointer(mutability, ref to) | Type::Slice(mutability, ref to) => {
+                    self.output.push_str(match *typ {
+                        Type::Pointer(..) => "*",
+                        _ => "[]",
+                    });
+                    if let Mutability::Mut = mutability {
+                        self.output.push_str("mut ");
+                    }
+                    self.typ(to);
+                    return;
+                }
+                Type::Function(ref params, ref out) => {
+                    self.output.push_str("fn(");
+                    self.types(params);
+                    self.output.push_str(") -> ");
+                    self.typ(out);
+                    return;
+                }
+            };
+            self.output.push_str(name);
+        }
+
+        fn statement(&mut self, s: &Statement) {
+            match *s {
+                Statement::Block(ref statements) => {
+                    self.output.push('{');
+                    self.indent += 1;
+                    for s in statements {
+                        self.line();
+                        self.statement(s);
+                    }
+                    self.indent -= 1;
+                    self.line();
+                    self.output.push('}');
+                }
+                Statement::If(ref cond, ref then, ref else_) => {
+                    self.output.push_str("if ");
+                    self.expr(cond);
+                    self.output.push(' ');
+                    self.statement(then);
+                    if let Some(ref else_) = *else_ {
+                        self.output.push_str(" else ");
+                        self.statement(else_);
+                    }
+                }
+                Statement::Loop(ref body) => {
+                    self.output.push_str("loop ");
+                    self.statement(body);
+                }
+                Statement::While(ref cond, ref body) => {
+                    self.output.push_str("while ");
+                    self.expr(cond);
+                    self.output.push(' ');
+                    self.statement(body);
+                }
+                Statement::Break => self.output.push_str("break;"),
+                Statement::Continue => self.output.push_str("continue;"),
+                Statement::Return(ref value) => {
+                    self.output.push_str("return ");
+                    self.expr(value);
+                    self.output.push(';');
+                }
+                Statement::Let(mutability, ref name, ref typ, ref value) => {
+                    self.output.push_str(match mutability {
+                        Mutability::Const => "let ",
+                        Mutability::Mut => "let mut ",
+                    });
+                    self.symbol(**name);
+                    self.output.push_str(": ");
+                    self.typ(typ);
+                    if let Some(ref value) = *value {
+                        self.output.push_str(" = ");
+                        self.expr(value);
+                    }
+                    self.output.push(';');
+                }
+                Statement::Match(ref value, ref arms) => {
+                    self.output.push_str("match ");
+                    self.expr(value);
+                    self.output.push_str(" {");
+                    self.indent += 1;
+                    for arm in arms {
+                        self.line();
+                        match *arm.pattern {
+                            Pattern::Wildcard => self.output.push('_'),
+                            Pattern::Value(ref value) => self.expr(value),
+                        }
+                        self.output.push_str(" => ");
+                        self.statement(&arm.body);
+                    }
+                    self.indent -= 1;
+                    self.line();
+                    self.output.push('}');
+                }
+                Statement::Expr(ref expr) => {
+                    self.expr(expr);
+                    self.output.push(';');
+                }
+                Statement::Error => self.output.push('?'),
+            }
+        }
+
+        /// Binary operations are always put in parentheses,
+        /// so that they show how the expression was parsed.
+        fn expr(&mut self, e: &Expr) {
+            match *e {
+                Expr::Binary(ref lhs, op, ref rhs) => {
+                    self.output.push('(');
+                    self.expr(lhs);
+                    self.output.push_str(match *op {
+                        BinaryOp::Add => " + ",
+                        BinaryOp::Subtract => " - ",
+                        BinaryOp::Multiply => " * ",
+                        BinaryOp::Divide => " / ",
+                        BinaryOp::Modulo => " % ",
+                        BinaryOp::Less => " < ",
+                        BinaryOp::LessEqual => " <= ",
+                        BinaryOp::Greater => " > ",
+                        BinaryOp::GreaterEqual => " >= ",
+                        BinaryOp::Equal => " == ",
+                        BinaryOp::NotEqual => " != ",
+                        BinaryOp::And => " && ",
+                        BinaryOp::Or => " || ",
+                        BinaryOp::Assign => " = ",
+                    });
+                    self.expr(rhs);
+                    self.output.push(')');
+                }
+                Expr::Unary(op, ref value) => {
+                    self.output.push_str(match *op {
+                        UnaryOp::Not => "!",
+                        UnaryOp::Minus => "-",
+                        UnaryOp::Plus => "+",
+                        UnaryOp::Deref => "*",
+                        UnaryOp::AddressOf => "&",
+                        UnaryOp::MutAddressOf => "&mut ",
+                    });
+                    self.expr(value);
+                }
+                Expr::Call(ref value, ref params) => {
+                    self.expr(value);
+                    self.output.push('(');
+                    for (index, param) in params.iter().enumerate() {
+                        if index > 0 {
+                            self.output.push_str(", ");
+                        }
+                        self.expr(param);
+                    }
+                    self.output.push(')');
+                }
+                Expr::Field(ref value, ref field) => {
+                    self.expr(value);
+                    write!(self.output, ".{}", **field).unwrap();
+                }
+                Expr::Index(ref value, ref index) => {
+                    self.expr(value);
+                    self.output.push('[');
+                    self.expr(index);
+                    self.output.push(']');
+                }
+                Expr::Slice(ref value, ref start, ref end) => {
+                    self.expr(value);
+                    self.output.push('[');
+                    self.expr(start);
+                    self.output.push_str("..");
+                    self.expr(end);
+                    self.output.push(']');
+                }
+                Expr::Name(ref name, ref type_params) => {
+                    self.symbol(**name);
+                    if !type_params.is_empty() {
+                        self.output.push_str("::<");
+                        self.types(type_params);
+                        self.output.push('>');
+                    }
+                }
+                Expr::Literal(ref literal) => self.literal(literal),
+                Expr::Cast(ref value, ref typ) => {
+                    self.output.push('(');
+                    self.expr(value);
+                    self.output.push_str(" as ");
+                    self.typ(typ);
+                    self.output.push(')');
+                }
+                Expr::Lambda(ref lambda) => {
+                    self.output.push_str("fn ");
+                    self.symbol(lambda.name);
+                    if !lambda.captures.is_empty() {
+                        self.output.push('[');
+                        for (index, &(outer, inner)) in lambda.captures.iter().enumerate() {
+                            if index > 0 {
+                                self.output.push_str(", ");
+                            }
+                            self.symbol(inner);
+                            self.output.push_str(" = ");
+                            self.symbol(outer);
+                        }
+                        self.output.push(']');
+                    }
+                    self.params(&lambda.params);
+                    self.output.push_str(" -> ");
+                    self.typ(&lambda.return_type);
+                    self.output.push(' ');
+                    self.statement(&lambda.body);
+                }
+                Expr::Error => self.output.push('?'),
+            }
+        }
+
+        fn literal(&mut self, literal: &Literal) {
+            match *literal {
+                Literal::Number(number) => write!(self.output, "{}", number).unwrap(),
+                Literal::Bool(value) => write!(self.output, "{}", value).unwrap(),
+                Literal::Char(value) => {
+                    self.output.push('\'');
+                    self.output.extend(::std::ascii::escape_default(value).map(char::from));
+                    self.output.push('\'');
+                }
+                Literal::Str(ref bytes) => {
+                    self.output.push('"');
+                    for &byte in bytes {
+                        self.output.extend(::std::ascii::escape_default(byte).map(char::from));
+                    }
+                    self.output.push('"');
+                }
+                Literal::Unit => self.output.push_str("unit"),
+            }
+        }
+    }
+
+    pub(crate) fn format_program(program: &Program, ctx: &CompileCtx) -> String {
+        let mut printer = Printer {
+            output: String::new(),
+            indent: 0,
+            ctx,
+        };
+        let mut structs = program.structs.iter().collect::<Vec<_>>();
+        structs.sort_by_key(|&(&symbol, _)| symbol);
+        for (index, (_, s)) in structs.into_iter().enumerate() {
+            if index > 0 {
+                printer.output.push('\n');
+            }
+            printer.structure(s);
+        }
+        for f in &program.functions {
+            // builtins have no source, so they would only be noise
+            if f.complete_span.start == f.complete_span.end {
+                continue;
+            }
+            if !printer.output.is_empty() {
+                printer.output.push('\n');
+            }
+            printer.function(f);
+        }
+        printer.output
+    }
+}
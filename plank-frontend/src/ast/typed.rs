@@ -1,8 +1,8 @@
 use std::collections::HashMap;
-use std::rc::Rc;
+use std::sync::Arc;
 pub use plank_syntax::ast::{BinaryOp, FunctionType, Literal, Number, Signedness, Size, UnaryOp};
 use plank_syntax::position::{Span, Spanned};
-pub use ast::resolved::{Mutability, Symbol};
+pub use ast::resolved::{CallingConvention, Inline, Mutability, StructKind, Symbol};
 
 
 #[derive(Debug, Clone)]
@@ -11,12 +11,29 @@ pub enum Expr {
     Unary(Spanned<UnaryOp>, TypedExpr),
     Call(TypedExpr, Vec<TypedExpr>),
     Field(TypedExpr, Spanned<usize>),
+    Index(TypedExpr, TypedExpr),
+    Slice(TypedExpr, TypedExpr, TypedExpr),
     Name(Spanned<Symbol>, Vec<Spanned<Type>>),
     Literal(Literal),
     Cast(TypedExpr, Spanned<Type>),
+    /// Implicit conversion of an int to the wider int type of the
+    /// expression, added when implicit widening is enabled.
+    Widen(TypedExpr),
+    Lambda(Lambda),
     Error,
 }
 
+#[derive(Debug, Clone)]
+pub struct Lambda {
+    pub name: Symbol,
+    /// Span of lambda header, from `fn` to the return type.
+    pub signature_span: Span,
+    pub params: Vec<FnParam>,
+    pub return_type: Type,
+    pub body: Spanned<Statement>,
+    pub captures: Vec<(Symbol, Symbol)>,
+}
+
 #[derive(Debug, Clone)]
 pub struct TypedExpr {
     pub expr: Box<Expr>,
@@ -38,10 +55,23 @@ pub enum Statement {
     Return(TypedExpr),
     Let(Mutability, Spanned<Symbol>, Spanned<Type>, Option<TypedExpr>),
     Block(Vec<Spanned<Statement>>),
+    Match(TypedExpr, Vec<MatchArm>),
     Expr(TypedExpr),
     Error,
 }
 
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Spanned<Pattern>,
+    pub body: Spanned<Statement>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Wildcard,
+    Value(TypedExpr),
+}
+
 #[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Copy, Clone)]
 pub struct TypeVar(pub u32);
 
@@ -51,16 +81,27 @@ pub enum Type {
     Bool,
     Unit,
     Int(Signedness, Size),
-    Concrete(Symbol, Rc<[Type]>),
-    Pointer(Mutability, Rc<Type>),
-    Function(Rc<[Type]>, Rc<Type>),
+    Str,
+    Concrete(Symbol, Arc<[Type]>),
+    Pointer(Mutability, Arc<Type>),
+    Slice(Mutability, Arc<Type>),
+    Function(Arc<[Type]>, Arc<Type>),
+    /// Type of calls to `#[noreturn]` functions. It can be
+    /// used where any other type is expected.
+    Never,
     Error,
 }
 
 impl Type {
     pub fn replace(&self, mapping: &HashMap<Symbol, Type>) -> Type {
         match *self {
-            Type::Bool | Type::Error | Type::Int(_, _) | Type::Var(_) | Type::Unit => self.clone(),
+            Type::Bool |
+            Type::Error |
+            Type::Int(_, _) |
+            Type::Var(_) |
+            Type::Unit |
+            Type::Never |
+            Type::Str => self.clone(),
             Type::Concrete(sym, ref params) => if let Some(typ) = mapping.get(&sym).cloned() {
                 typ
             } else {
@@ -78,11 +119,15 @@ impl Type {
                     .collect::<Vec<_>>()
                     .into();
                 let out = out.replace(mapping);
-                Type::Function(params, Rc::new(out))
+                Type::Function(params, Arc::new(out))
             }
             Type::Pointer(mutability, ref to) => {
                 let to = to.replace(mapping);
-                Type::Pointer(mutability, Rc::new(to))
+                Type::Pointer(mutability, Arc::new(to))
+            }
+            Type::Slice(mutability, ref to) => {
+                let to = to.replace(mapping);
+                Type::Slice(mutability, Arc::new(to))
             }
         }
     }
@@ -90,11 +135,14 @@ impl Type {
     pub fn is_atomic(&self) -> bool {
         match *self {
             Type::Bool |
-            Type::Function(_, _) |
             Type::Int(_, _) |
             Type::Pointer(_, _) |
-            Type::Unit => true,
-            Type::Concrete(_, _) => false,
+            Type::Unit |
+            Type::Never => true,
+            Type::Concrete(_, _) |
+            Type::Function(_, _) |
+            Type::Slice(_, _) |
+            Type::Str => false,
             Type::Error |
             Type::Var(_) => panic!("cannot say atomicity of {:?}", self),
         }
@@ -111,12 +159,16 @@ pub struct FnParam {
 #[derive(Debug, Clone)]
 pub struct Function {
     pub complete_span: Span,
+    /// Span of function header, from `fn` to the return type.
+    pub signature_span: Span,
     pub fn_type: FunctionType,
     pub name: Symbol,
     pub type_params: Vec<Symbol>,
     pub params: Vec<FnParam>,
     pub return_type: Type,
     pub body: Option<Spanned<Statement>>,
+    pub inline: Inline,
+    pub calling_convention: CallingConvention,
 }
 
 #[derive(Debug, Clone)]
@@ -128,6 +180,7 @@ pub struct Field {
 #[derive(Debug, Clone)]
 pub struct Struct {
     pub complete_span: Span,
+    pub kind: StructKind,
     pub name: Symbol,
     pub type_params: Vec<Symbol>,
     pub fields: Vec<Field>,
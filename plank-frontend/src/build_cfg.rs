@@ -1,4 +1,6 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use plank_errors::codes;
 use plank_syntax::position::{Span, Spanned};
 use ast::typed::{self as t, Mutability as Mut};
 use ast::cfg;
@@ -45,7 +47,19 @@ impl RValue {
     }
 }
 
+/// Items generated while building function bodies.
+#[derive(Default)]
+struct Generated {
+    /// For every function used as a value, a function that takes
+    /// environment pointer as first parameter and ignores it.
+    thunks: HashMap<t::Symbol, t::Symbol>,
+    functions: Vec<(t::Symbol, cfg::Function)>,
+    structs: Vec<t::Struct>,
+}
+
 struct Builder<'a> {
+    type_params: Vec<t::Symbol>,
+    generated: &'a mut Generated,
     parameters: Vec<cfg::Reg>,
     registers: HashMap<cfg::Reg, cfg::Type>,
     blocks: HashMap<cfg::BlockId, cfg::Block>,
@@ -57,11 +71,19 @@ struct Builder<'a> {
     register_vars: HashMap<cfg::Reg, t::Symbol>,
     current_block: Option<(cfg::BlockId, Vec<Spanned<cfg::Instruction>>)>,
     var_mutability: HashMap<t::Symbol, Mut>,
+    deferred_init: HashSet<cfg::Reg>,
+    declared_uninit: HashMap<cfg::Reg, Span>,
 }
 
 impl<'a> Builder<'a> {
-    fn new(ctx: &'a mut CompileCtx) -> Self {
+    fn new(
+        ctx: &'a mut CompileCtx,
+        generated: &'a mut Generated,
+        type_params: Vec<t::Symbol>,
+    ) -> Self {
         Builder {
+            type_params,
+            generated,
             parameters: Vec::new(),
             registers: HashMap::new(),
             blocks: HashMap::new(),
@@ -73,6 +95,8 @@ impl<'a> Builder<'a> {
             register_vars: HashMap::new(),
             current_block: None,
             var_mutability: HashMap::new(),
+            deferred_init: HashSet::new(),
+            declared_uninit: HashMap::new(),
         }
     }
 
@@ -117,15 +141,23 @@ impl<'a> Builder<'a> {
                 self.ctx
                     .reporter
                     .error("invalid lvalue", target_span)
+                    .code(codes::INVALID_LVALUE)
                     .span(target_span)
                     .build();
             }
             LValue::Error => {}
+            LValue::Reg(Mut::Const, reg, ref fields)
+                if fields.is_empty() && self.deferred_init.contains(&reg) =>
+            {
+                // assign_check makes sure that this is the only assignment
+                self.emit_instruction(cfg::Instruction::Assign(reg, value), op_span);
+            }
             LValue::Deref(Mut::Const, _, _, _) |
             LValue::Reg(Mut::Const, _, _) => {
                 self.ctx
                     .reporter
                     .error("cannot modify non-mut value", target_span)
+                    .code(codes::MODIFIED_CONST)
                     .span(target_span)
                     .build();
             }
@@ -164,6 +196,7 @@ impl<'a> Builder<'a> {
                 self.ctx
                     .reporter
                     .error("invalid lvalue", value_span)
+                    .code(codes::INVALID_LVALUE)
                     .span(value_span)
                     .build();
             }
@@ -174,6 +207,7 @@ impl<'a> Builder<'a> {
                 self.ctx
                     .reporter
                     .error("cannot take mutable reference to non-mut value", span)
+                    .code(codes::MODIFIED_CONST)
                     .span(span)
                     .build();
             }
@@ -243,6 +277,83 @@ impl<'a> Builder<'a> {
         }
     }
 
+    fn build_lambda_function(
+        &mut self,
+        lambda: &t::Lambda,
+        env_type: &t::Type,
+        capture_types: Vec<t::Type>,
+    ) -> cfg::BlockId {
+        let env_ptr_type = t::Type::Pointer(Mut::Const, Arc::new(env_type.clone()));
+        let env_ptr = self.new_register(env_ptr_type);
+        self.parameters.push(env_ptr);
+        for var in &lambda.params {
+            let param = self.new_var_register(var.name, var.typ.clone());
+            self.var_mutability.insert(var.name, var.mutability);
+            self.parameters.push(param);
+        }
+        let body_block = self.new_block();
+        self.start_block(body_block);
+        let span = Spanned::span(&lambda.body);
+        if !lambda.captures.is_empty() {
+            let env = self.new_register(env_type.clone());
+            self.emit_instruction(
+                cfg::Instruction::UnaryOp(
+                    env,
+                    cfg::UnaryOp::DerefLoad,
+                    Spanned::new(cfg::Value::Reg(env_ptr), span),
+                ),
+                span,
+            );
+            for (index, (&(_, inner), typ)) in lambda.captures.iter().zip(capture_types).enumerate() {
+                // captured variables are copies, so modifying them
+                // would be misleading
+                self.var_mutability.insert(inner, Mut::Const);
+                let var = self.new_var_register(inner, typ);
+                self.emit_instruction(
+                    cfg::Instruction::UnaryOp(
+                        var,
+                        cfg::UnaryOp::FieldLoad(env_type.clone(), vec![index]),
+                        Spanned::new(cfg::Value::Reg(env), span),
+                    ),
+                    span,
+                );
+            }
+            self.emit_instruction(cfg::Instruction::Drop(env), span);
+        }
+        self.build_statement(&lambda.body);
+        if self.current_block.is_some() {
+            self.end_block(cfg::BlockEnd::Error, cfg::BlockLink::None);
+        }
+        body_block
+    }
+
+    fn into_function(
+        self,
+        complete_span: Span,
+        signature_span: Span,
+        out_type: t::Type,
+        start_block: Option<cfg::BlockId>,
+        inline: cfg::Inline,
+        calling_convention: cfg::CallingConvention,
+    ) -> cfg::Function {
+        debug_assert!(self.current_block.is_none());
+        cfg::Function {
+            parameters: self.parameters,
+            complete_span,
+            signature_span,
+            type_params: self.type_params,
+            registers: self.registers,
+            register_symbols: self.register_vars,
+            deferred_init: self.deferred_init,
+            declared_uninit: self.declared_uninit,
+            out_type,
+            blocks: self.blocks,
+            start_block,
+            inline,
+            calling_convention,
+        }
+    }
+
     fn build_statement(&mut self, s: &Spanned<t::Statement>) {
         let span = Spanned::span(s);
         match **s {
@@ -272,6 +383,7 @@ impl<'a> Builder<'a> {
                     self.ctx
                         .reporter
                         .error("cannot use `break` outside loop", span)
+                        .code(codes::OUTSIDE_LOOP)
                         .span(span)
                         .build();
                 }
@@ -288,6 +400,7 @@ impl<'a> Builder<'a> {
                     self.ctx
                         .reporter
                         .error("cannot use `continue` outside loop", span)
+                        .code(codes::OUTSIDE_LOOP)
                         .span(span)
                         .build();
                 }
@@ -343,6 +456,62 @@ impl<'a> Builder<'a> {
                 self.start_block(after);
                 self.drop_value(&c, cond.span);
             }
+            t::Statement::Match(ref value, ref arms) => {
+                let scrutinee = self.build_expr(value);
+                let after = self.new_block();
+                let mut matched_all = false;
+                for (index, arm) in arms.iter().enumerate() {
+                    let pattern = match *arm.pattern {
+                        t::Pattern::Value(ref pattern) if index + 1 < arms.len() => pattern,
+                        // match is known to be exhaustive, so the last arm
+                        // gets every value that previous arms did not
+                        _ => {
+                            self.drop_value(&scrutinee, value.span);
+                            self.build_statement(&arm.body);
+                            matched_all = true;
+                            break;
+                        }
+                    };
+                    let built_pattern = self.build_expr(pattern);
+                    let c = self.new_register(t::Type::Bool);
+                    self.emit_instruction(
+                        cfg::Instruction::BinaryOp(
+                            c,
+                            cfg::BinaryOp::Eq,
+                            Spanned::new(scrutinee.as_value(), value.span),
+                            Spanned::new(built_pattern.as_value(), pattern.span),
+                        ),
+                        Spanned::span(&arm.pattern),
+                    );
+                    self.drop_value(&built_pattern, pattern.span);
+                    let c = RValue::Temp(cfg::Value::Reg(c));
+                    let body = self.new_block();
+                    let next = self.new_block();
+                    let link = cfg::BlockLink::Weak(body);
+                    self.end_block(
+                        cfg::BlockEnd::Branch(
+                            Spanned::new(c.as_value(), pattern.span),
+                            body,
+                            next,
+                        ),
+                        link,
+                    );
+                    self.start_block(body);
+                    self.drop_value(&c, pattern.span);
+                    self.drop_value(&scrutinee, value.span);
+                    self.build_statement(&arm.body);
+                    let link = cfg::BlockLink::Weak(next);
+                    self.end_block(cfg::BlockEnd::Jump(after), link);
+                    self.start_block(next);
+                    self.drop_value(&c, pattern.span);
+                }
+                if !matched_all {
+                    self.drop_value(&scrutinee, value.span);
+                }
+                let link = cfg::BlockLink::Weak(after);
+                self.end_block(cfg::BlockEnd::Jump(after), link);
+                self.start_block(after);
+            }
             t::Statement::Let(mutability, name, ref typ, Some(ref value)) => {
                 self.var_mutability.insert(*name, mutability);
                 let typ = (**typ).clone();
@@ -360,7 +529,11 @@ impl<'a> Builder<'a> {
             t::Statement::Let(mutability, name, ref typ, None) => {
                 self.var_mutability.insert(*name, mutability);
                 // give it a register, but don't initialize it
-                self.new_var_register(Spanned::into_value(name), (**typ).clone());
+                let reg = self.new_var_register(Spanned::into_value(name), (**typ).clone());
+                if mutability == Mut::Const {
+                    self.deferred_init.insert(reg);
+                }
+                self.declared_uninit.insert(reg, Spanned::span(&name));
             }
             t::Statement::Loop(ref body) => {
                 let start = self.new_block();
@@ -458,7 +631,32 @@ impl<'a> Builder<'a> {
                 },
             },
             t::Expr::Call(ref name, ref params) => {
-                let callee = self.build_expr(name);
+                if let t::Expr::Name(sym, _) = *name.expr {
+                    // direct calls know where they are, so they can report it
+                    if *sym == ::builtins::ASSERT && params.len() == 2 {
+                        return self.build_assert(&params[0], &params[1], e.span);
+                    } else if *sym == ::builtins::PANIC && params.len() == 1 {
+                        self.emit_fail(::builtins::PANIC_FAIL, &params[0], e.span);
+                        let new = self.new_block();
+                        self.end_block(cfg::BlockEnd::Unreachable, cfg::BlockLink::Strong(new));
+                        self.start_block(new);
+                        return RValue::Temp(cfg::Value::Reg(self.new_register(e.typ.clone())));
+                    }
+                }
+                let callee = match *name.expr {
+                    // global functions are called directly
+                    t::Expr::Name(sym, ref type_params)
+                        if !self.var_registers.contains_key(&sym) =>
+                    {
+                        let type_params = type_params
+                            .iter()
+                            .map(Spanned::value)
+                            .cloned()
+                            .collect();
+                        RValue::Temp(cfg::Value::Symbol(*sym, type_params))
+                    }
+                    _ => self.build_expr(name),
+                };
                 let params = params
                     .iter()
                     .map(|p| Spanned::new(self.build_expr(p), p.span))
@@ -478,6 +676,12 @@ impl<'a> Builder<'a> {
                 for param in &params {
                     self.drop_value(param, name.span);
                 }
+                if let t::Type::Never = e.typ {
+                    // rest of the code would be reached only if the call returned
+                    let new = self.new_block();
+                    self.end_block(cfg::BlockEnd::Unreachable, cfg::BlockLink::Strong(new));
+                    self.start_block(new);
+                }
                 RValue::Temp(cfg::Value::Reg(target))
             }
             t::Expr::Error => RValue::Temp(cfg::Value::Error),
@@ -495,6 +699,101 @@ impl<'a> Builder<'a> {
                 self.drop_value(&built_expr, expr.span);
                 RValue::Temp(cfg::Value::Reg(target))
             }
+            t::Expr::Index(ref expr, ref index) => {
+                let (ptr, ptr_type, len) = self.build_data_pointer(expr);
+                if let t::Type::Error = ptr_type {
+                    return ptr;
+                }
+                let built_index = self.build_expr(index);
+                if let Some(len) = len {
+                    self.emit_bounds_check(
+                        Spanned::new(built_index.as_value(), index.span),
+                        Spanned::new(cfg::Value::Reg(len), expr.span),
+                        false,
+                        e.span,
+                    );
+                    self.emit_instruction(cfg::Instruction::Drop(len), e.span);
+                }
+                let address = self.emit_element_address(
+                    Spanned::new(ptr, expr.span),
+                    &ptr_type,
+                    Spanned::new(built_index.as_value(), index.span),
+                );
+                self.drop_value(&built_index, index.span);
+                let target = self.new_register(e.typ.clone());
+                self.emit_instruction(
+                    cfg::Instruction::UnaryOp(
+                        target,
+                        cfg::UnaryOp::DerefLoad,
+                        Spanned::new(address.as_value(), e.span),
+                    ),
+                    e.span,
+                );
+                self.drop_value(&address, e.span);
+                RValue::Temp(cfg::Value::Reg(target))
+            }
+            t::Expr::Slice(ref expr, ref start, ref end) => {
+                let (ptr, ptr_type, len) = self.build_data_pointer(expr);
+                if let t::Type::Error = ptr_type {
+                    return ptr;
+                }
+                let built_start = self.build_expr(start);
+                let built_end = self.build_expr(end);
+                if let Some(len) = len {
+                    self.emit_bounds_check(
+                        Spanned::new(built_end.as_value(), end.span),
+                        Spanned::new(cfg::Value::Reg(len), expr.span),
+                        true,
+                        e.span,
+                    );
+                    self.emit_bounds_check(
+                        Spanned::new(built_start.as_value(), start.span),
+                        Spanned::new(built_end.as_value(), end.span),
+                        true,
+                        e.span,
+                    );
+                    self.emit_instruction(cfg::Instruction::Drop(len), e.span);
+                }
+                let address = self.emit_element_address(
+                    Spanned::new(ptr, expr.span),
+                    &ptr_type,
+                    Spanned::new(built_start.as_value(), start.span),
+                );
+                let len = self.new_register(t::Type::Int(t::Signedness::Unsigned, t::Size::Bit32));
+                self.emit_instruction(
+                    cfg::Instruction::BinaryOp(
+                        len,
+                        cfg::BinaryOp::Sub(t::Signedness::Unsigned, t::Size::Bit32),
+                        Spanned::new(built_end.as_value(), end.span),
+                        Spanned::new(built_start.as_value(), start.span),
+                    ),
+                    e.span,
+                );
+                self.drop_value(&built_start, start.span);
+                self.drop_value(&built_end, end.span);
+                let target = self.new_register(e.typ.clone());
+                self.emit_instruction(cfg::Instruction::Init(target), e.span);
+                self.emit_instruction(
+                    cfg::Instruction::FieldStore(
+                        Spanned::new(target, e.span),
+                        vec![0],
+                        Spanned::new(address.as_value(), expr.span),
+                    ),
+                    e.span,
+                );
+                self.emit_instruction(
+                    cfg::Instruction::FieldStore(
+                        Spanned::new(target, e.span),
+                        vec![1],
+                        Spanned::new(cfg::Value::Reg(len), e.span),
+                    ),
+                    e.span,
+                );
+                self.drop_value(&address, expr.span);
+                self.emit_instruction(cfg::Instruction::Drop(len), e.span);
+                RValue::Temp(cfg::Value::Reg(target))
+            }
+            t::Expr::Literal(t::Literal::Str(ref bytes)) => self.build_string(bytes, e),
             t::Expr::Literal(ref literal) => RValue::Temp(match *literal {
                 t::Literal::Unit => cfg::Value::Unit,
                 t::Literal::Bool(b) => if b {
@@ -506,17 +805,16 @@ impl<'a> Builder<'a> {
                 t::Literal::Number(n) => {
                     let size = match e.typ {
                         t::Type::Int(_, size) => size,
+                        t::Type::Concrete(_, _) => {
+                            let value = cfg::Value::NumericInt(n.value, e.typ.clone());
+                            return RValue::Temp(value);
+                        }
                         t::Type::Error => return RValue::Temp(cfg::Value::Error),
                         _ => panic!("bad int type"),
                     };
                     cfg::Value::Int(n.value, size)
                 }
-                t::Literal::Str(ref bytes) => {
-                    let mut bytes = bytes.clone();
-                    // add null terminator
-                    bytes.push(0);
-                    cfg::Value::Bytes(bytes)
-                }
+                t::Literal::Str(_) => unreachable!(),
             }),
             t::Expr::Name(name, ref type_params) => {
                 let name = Spanned::into_value(name);
@@ -528,9 +826,13 @@ impl<'a> Builder<'a> {
                         .map(Spanned::value)
                         .cloned()
                         .collect();
-                    RValue::Temp(cfg::Value::Symbol(name, type_params))
+                    let thunk = self.get_thunk(name);
+                    let function = cfg::Value::Symbol(thunk, type_params);
+                    let env = cfg::Value::Null;
+                    self.build_function_value(function, env, e)
                 }
             }
+            t::Expr::Lambda(ref lambda) => self.build_lambda(lambda, e),
             t::Expr::Unary(op, ref expr) => {
                 let op = Spanned::into_value(op);
                 if op == t::UnaryOp::AddressOf || op == t::UnaryOp::MutAddressOf {
@@ -573,6 +875,24 @@ impl<'a> Builder<'a> {
                 self.drop_value(&value, expr.span);
                 RValue::Temp(cfg::Value::Reg(result))
             }
+            t::Expr::Widen(ref expr) => {
+                let (sign, from, to) = match (&expr.typ, &e.typ) {
+                    (&t::Type::Int(sign, from), &t::Type::Int(_, to)) => (sign, from, to),
+                    _ => panic!("widening non-int types"),
+                };
+                let value = self.build_expr(expr);
+                let result = self.new_register(e.typ.clone());
+                self.emit_instruction(
+                    cfg::Instruction::UnaryOp(
+                        result,
+                        cfg::UnaryOp::Extend(sign, from, to),
+                        Spanned::new(value.as_value(), expr.span),
+                    ),
+                    e.span,
+                );
+                self.drop_value(&value, expr.span);
+                RValue::Temp(cfg::Value::Reg(result))
+            }
         }
     }
 
@@ -581,13 +901,40 @@ impl<'a> Builder<'a> {
             t::Expr::Binary(_, _, _) |
             t::Expr::Call(_, _) |
             t::Expr::Literal(_) |
-            t::Expr::Cast(_, _) => LValue::Invalid,
+            t::Expr::Lambda(_) |
+            t::Expr::Cast(_, _) |
+            t::Expr::Widen(_) => LValue::Invalid,
             t::Expr::Error => LValue::Error,
             t::Expr::Field(ref expr, index) => {
                 let mut lvalue = self.build_expr_lvalue(expr);
                 lvalue.add_field(Spanned::into_value(index));
                 lvalue
             }
+            t::Expr::Index(ref expr, ref index) => {
+                let (ptr, ptr_type, len) = self.build_data_pointer(expr);
+                let mutability = match ptr_type {
+                    t::Type::Pointer(mutability, _) => mutability,
+                    _ => return LValue::Error,
+                };
+                let built_index = self.build_expr(index);
+                if let Some(len) = len {
+                    self.emit_bounds_check(
+                        Spanned::new(built_index.as_value(), index.span),
+                        Spanned::new(cfg::Value::Reg(len), expr.span),
+                        false,
+                        e.span,
+                    );
+                    self.emit_instruction(cfg::Instruction::Drop(len), e.span);
+                }
+                let address = self.emit_element_address(
+                    Spanned::new(ptr, expr.span),
+                    &ptr_type,
+                    Spanned::new(built_index.as_value(), index.span),
+                );
+                self.drop_value(&built_index, index.span);
+                LValue::Deref(mutability, address, ptr_type, Vec::new())
+            }
+            t::Expr::Slice(_, _, _) => LValue::Invalid,
             t::Expr::Name(ref name, _) => {
                 let mutability = self.var_mutability[name];
                 if let Some(reg) = self.var_registers.get(&**name).cloned() {
@@ -610,6 +957,348 @@ impl<'a> Builder<'a> {
         }
     }
 
+    fn get_thunk(&mut self, function: t::Symbol) -> t::Symbol {
+        if let Some(&thunk) = self.generated.thunks.get(&function) {
+            return thunk;
+        }
+        let name = format!(
+            "{}::thunk",
+            self.ctx.symbols.get_name(function).trim_start_matches('@'),
+        );
+        let thunk = self.ctx.symbols.new_symbol(name);
+        self.generated.thunks.insert(function, thunk);
+        thunk
+    }
+
+    fn build_function_value(
+        &mut self,
+        function: cfg::Value,
+        env: cfg::Value,
+        e: &t::TypedExpr,
+    ) -> RValue {
+        // function value is a pair of function and environment pointer
+        let target = self.new_register(e.typ.clone());
+        self.emit_instruction(cfg::Instruction::Init(target), e.span);
+        self.emit_instruction(
+            cfg::Instruction::FieldStore(
+                Spanned::new(target, e.span),
+                vec![0],
+                Spanned::new(function, e.span),
+            ),
+            e.span,
+        );
+        self.emit_instruction(
+            cfg::Instruction::FieldStore(
+                Spanned::new(target, e.span),
+                vec![1],
+                Spanned::new(env, e.span),
+            ),
+            e.span,
+        );
+        RValue::Temp(cfg::Value::Reg(target))
+    }
+
+    fn build_lambda(&mut self, lambda: &t::Lambda, e: &t::TypedExpr) -> RValue {
+        let type_params = self.type_params
+            .iter()
+            .map(|&param| t::Type::Concrete(param, Vec::new().into()))
+            .collect::<Vec<_>>();
+        let captured = lambda
+            .captures
+            .iter()
+            .map(|&(outer, _)| self.var_registers[&outer])
+            .collect::<Vec<_>>();
+        let capture_types = captured
+            .iter()
+            .map(|reg| self.registers[reg].clone())
+            .collect::<Vec<_>>();
+
+        // captured values are copied into environment struct, which
+        // lives in the frame of enclosing function
+        let (env_type, env) = if captured.is_empty() {
+            (t::Type::Unit, None)
+        } else {
+            let name = format!("{}::env", self.ctx.symbols.get_name(lambda.name));
+            let env_name = self.ctx.symbols.new_symbol(name);
+            let fields = lambda
+                .captures
+                .iter()
+                .zip(capture_types.iter())
+                .map(|(&(_, inner), typ)| {
+                    t::Field {
+                        name: inner,
+                        typ: typ.clone(),
+                    }
+                })
+                .collect();
+            self.generated.structs.push(t::Struct {
+                complete_span: e.span,
+                kind: t::StructKind::Struct,
+                name: env_name,
+                type_params: self.type_params.clone(),
+                fields,
+            });
+            let env_type = t::Type::Concrete(env_name, type_params.clone().into());
+            let env = self.new_register(env_type.clone());
+            let values = captured
+                .iter()
+                .map(|&reg| Spanned::new(cfg::Value::Reg(reg), e.span))
+                .collect();
+            let constructor = cfg::Value::Symbol(env_name, type_params.clone());
+            self.emit_instruction(
+                cfg::Instruction::Call(env, Spanned::new(constructor, e.span), values),
+                e.span,
+            );
+            (env_type, Some(env))
+        };
+
+        let function = {
+            let type_params = self.type_params.clone();
+            let mut builder = Builder::new(self.ctx, self.generated, type_params);
+            let start = builder.build_lambda_function(lambda, &env_type, capture_types);
+            builder.into_function(
+                e.span,
+                lambda.signature_span,
+                lambda.return_type.clone(),
+                Some(start),
+                cfg::Inline::Auto,
+                cfg::CallingConvention::Stack,
+            )
+        };
+        self.generated.functions.push((lambda.name, function));
+
+        let function = cfg::Value::Symbol(lambda.name, type_params);
+        match env {
+            Some(env) => {
+                let env_ptr_type = t::Type::Pointer(Mut::Const, Arc::new(env_type));
+                let env_ptr = self.new_register(env_ptr_type);
+                self.emit_instruction(
+                    cfg::Instruction::TakeAddress(env_ptr, Spanned::new(env, e.span), Vec::new()),
+                    e.span,
+                );
+                let value = self.build_function_value(function, cfg::Value::Reg(env_ptr), e);
+                self.emit_instruction(cfg::Instruction::Drop(env_ptr), e.span);
+                value
+            }
+            None => {
+                let env = cfg::Value::Null;
+                self.build_function_value(function, env, e)
+            }
+        }
+    }
+
+    fn build_string(&mut self, bytes: &[u8], e: &t::TypedExpr) -> RValue {
+        let mut data = bytes.to_vec();
+        // add null terminator
+        data.push(0);
+        match e.typ {
+            t::Type::Str | t::Type::Slice(_, _) => {}
+            _ => return RValue::Temp(cfg::Value::Bytes(data)),
+        }
+        // terminator is not included in the length of `str` and `[]u8`
+        let len = bytes.len() as u64;
+        let target = self.new_register(e.typ.clone());
+        self.emit_instruction(cfg::Instruction::Init(target), e.span);
+        self.emit_instruction(
+            cfg::Instruction::FieldStore(
+                Spanned::new(target, e.span),
+                vec![0],
+                Spanned::new(cfg::Value::Bytes(data), e.span),
+            ),
+            e.span,
+        );
+        self.emit_instruction(
+            cfg::Instruction::FieldStore(
+                Spanned::new(target, e.span),
+                vec![1],
+                Spanned::new(cfg::Value::Int(len, cfg::Size::Bit32), e.span),
+            ),
+            e.span,
+        );
+        RValue::Temp(cfg::Value::Reg(target))
+    }
+
+    /// Build a pointer to the data of a pointer, slice or string. With
+    /// bounds checks, the length of slices and strings is returned too.
+    fn build_data_pointer(&mut self, e: &t::TypedExpr) -> (RValue, t::Type, Option<cfg::Reg>) {
+        let ptr_type = match e.typ {
+            t::Type::Pointer(_, _) => return (self.build_expr(e), e.typ.clone(), None),
+            t::Type::Slice(mutability, ref typ) => t::Type::Pointer(mutability, typ.clone()),
+            t::Type::Str => {
+                let byte = t::Type::Int(t::Signedness::Unsigned, t::Size::Bit8);
+                t::Type::Pointer(Mut::Const, Arc::new(byte))
+            }
+            t::Type::Error => return (RValue::Temp(cfg::Value::Error), t::Type::Error, None),
+            _ => panic!("cannot index {:?}", e.typ),
+        };
+        let built_expr = self.build_expr(e);
+        let len = if self.ctx.options.bounds_checks {
+            let len = self.new_register(t::Type::Int(t::Signedness::Unsigned, t::Size::Bit32));
+            self.emit_instruction(
+                cfg::Instruction::UnaryOp(
+                    len,
+                    cfg::UnaryOp::FieldLoad(e.typ.clone(), vec![1]),
+                    Spanned::new(built_expr.as_value(), e.span),
+                ),
+                e.span,
+            );
+            Some(len)
+        } else {
+            None
+        };
+        let ptr = self.new_register(ptr_type.clone());
+        self.emit_instruction(
+            cfg::Instruction::UnaryOp(
+                ptr,
+                cfg::UnaryOp::FieldLoad(e.typ.clone(), vec![0]),
+                Spanned::new(built_expr.as_value(), e.span),
+            ),
+            e.span,
+        );
+        self.drop_value(&built_expr, e.span);
+        (RValue::Temp(cfg::Value::Reg(ptr)), ptr_type, len)
+    }
+
+    /// Stop the program with `message` and the position of the assert
+    /// unless `condition` is true. The message is built only if it fails.
+    fn build_assert(
+        &mut self,
+        condition: &t::TypedExpr,
+        message: &t::TypedExpr,
+        span: Span,
+    ) -> RValue {
+        let built_condition = self.build_expr(condition);
+        let fail_block = self.new_block();
+        let ok_block = self.new_block();
+        self.end_block(
+            cfg::BlockEnd::Branch(
+                Spanned::new(built_condition.as_value(), condition.span),
+                ok_block,
+                fail_block,
+            ),
+            cfg::BlockLink::Weak(fail_block),
+        );
+        self.start_block(fail_block);
+        self.drop_value(&built_condition, condition.span);
+        self.emit_fail(::builtins::ASSERT_FAIL, message, span);
+        self.end_block(cfg::BlockEnd::Unreachable, cfg::BlockLink::Weak(ok_block));
+        self.start_block(ok_block);
+        self.drop_value(&built_condition, condition.span);
+        RValue::Temp(cfg::Value::Unit)
+    }
+
+    /// Call runtime builtin `fail` with `message` and the position of
+    /// `span`. The call doesn't return, but the block is not ended.
+    fn emit_fail(&mut self, fail: t::Symbol, message: &t::TypedExpr, span: Span) {
+        let built_message = self.build_expr(message);
+        let byte = t::Type::Int(t::Signedness::Unsigned, t::Size::Bit8);
+        let field_types = vec![
+            t::Type::Pointer(Mut::Const, Arc::new(byte)),
+            t::Type::Int(t::Signedness::Unsigned, t::Size::Bit32),
+        ];
+        let mut fields = Vec::new();
+        for (field, typ) in field_types.into_iter().enumerate() {
+            let reg = self.new_register(typ);
+            self.emit_instruction(
+                cfg::Instruction::UnaryOp(
+                    reg,
+                    cfg::UnaryOp::FieldLoad(message.typ.clone(), vec![field]),
+                    Spanned::new(built_message.as_value(), message.span),
+                ),
+                message.span,
+            );
+            fields.push(Spanned::new(cfg::Value::Reg(reg), message.span));
+        }
+        self.drop_value(&built_message, message.span);
+        let position = |value: u32| {
+            Spanned::new(cfg::Value::Int(u64::from(value) + 1, t::Size::Bit32), span)
+        };
+        fields.push(position(span.start.line));
+        fields.push(position(span.start.column));
+        let result = self.new_register(t::Type::Unit);
+        let fail = cfg::Value::Symbol(fail, Vec::new());
+        self.emit_instruction(
+            cfg::Instruction::Call(result, Spanned::new(fail, span), fields),
+            span,
+        );
+    }
+
+    /// Stop the program with `index` and `len` unless `index` is less
+    /// than `len`, or for ends of slice ranges, at most `len`.
+    fn emit_bounds_check(
+        &mut self,
+        index: Spanned<cfg::Value>,
+        len: Spanned<cfg::Value>,
+        is_range_end: bool,
+        span: Span,
+    ) {
+        let sign = t::Signedness::Unsigned;
+        let op = if is_range_end {
+            cfg::BinaryOp::LessEq(sign, t::Size::Bit32)
+        } else {
+            cfg::BinaryOp::Less(sign, t::Size::Bit32)
+        };
+        let in_bounds = self.new_register(t::Type::Bool);
+        self.emit_instruction(
+            cfg::Instruction::BinaryOp(in_bounds, op, index.clone(), len.clone()),
+            span,
+        );
+        let fail_block = self.new_block();
+        let ok_block = self.new_block();
+        self.end_block(
+            cfg::BlockEnd::Branch(
+                Spanned::new(cfg::Value::Reg(in_bounds), span),
+                ok_block,
+                fail_block,
+            ),
+            cfg::BlockLink::Weak(fail_block),
+        );
+        self.start_block(fail_block);
+        self.emit_instruction(cfg::Instruction::Drop(in_bounds), span);
+        let result = self.new_register(t::Type::Unit);
+        let fail = cfg::Value::Symbol(::builtins::BOUNDS_FAIL, Vec::new());
+        self.emit_instruction(
+            cfg::Instruction::Call(result, Spanned::new(fail, span), vec![index, len]),
+            span,
+        );
+        self.end_block(cfg::BlockEnd::Unreachable, cfg::BlockLink::Weak(ok_block));
+        self.start_block(ok_block);
+        self.emit_instruction(cfg::Instruction::Drop(in_bounds), span);
+    }
+
+    fn emit_element_address(
+        &mut self,
+        ptr: Spanned<RValue>,
+        ptr_type: &t::Type,
+        index: Spanned<cfg::Value>,
+    ) -> RValue {
+        let typ = match *ptr_type {
+            t::Type::Pointer(_, ref typ) => (**typ).clone(),
+            _ => panic!("cannot index {:?}", ptr_type),
+        };
+        let ptr_span = Spanned::span(&ptr);
+        let index_span = Spanned::span(&index);
+        // offset is added to a pointer, so it has to be pointer sized
+        let offset = self.new_register(ptr_type.clone());
+        self.emit_instruction(
+            cfg::Instruction::UnaryOp(offset, cfg::UnaryOp::ElementOffset(typ), index),
+            index_span,
+        );
+        let address = self.new_register(ptr_type.clone());
+        self.emit_instruction(
+            cfg::Instruction::BinaryOp(
+                address,
+                cfg::BinaryOp::Add(t::Signedness::Unsigned, t::Size::Bit32),
+                Spanned::new(ptr.as_value(), ptr_span),
+                Spanned::new(cfg::Value::Reg(offset), index_span),
+            ),
+            index_span,
+        );
+        self.drop_value(&ptr, ptr_span);
+        self.emit_instruction(cfg::Instruction::Drop(offset), index_span);
+        RValue::Temp(cfg::Value::Reg(address))
+    }
+
     fn build_and(&mut self, lhs: &t::TypedExpr, rhs: &t::TypedExpr) -> RValue {
         let built_lhs = self.build_expr(lhs);
         let rhs_block = self.new_block();
@@ -705,9 +1394,17 @@ impl<'a> Builder<'a> {
     }
 }
 
-fn binop_to_instruction(op: t::BinaryOp, arg_type: &t::Type) -> Option<cfg::BinaryOp> {
+pub(crate) fn binop_to_instruction(
+    op: t::BinaryOp,
+    arg_type: &t::Type,
+) -> Option<cfg::BinaryOp> {
     let int = match *arg_type {
         t::Type::Int(sign, size) => Some((sign, size)),
+        // type checker only allows int operations
+        // on type parameters that are `Numeric`
+        t::Type::Concrete(_, _) if op != t::BinaryOp::Equal && op != t::BinaryOp::NotEqual => {
+            return Some(cfg::BinaryOp::Numeric(op, arg_type.clone()));
+        }
         _ => None,
     };
     match op {
@@ -735,36 +1432,118 @@ fn unop_to_instruction(op: t::UnaryOp, arg_type: &t::Type) -> Option<cfg::UnaryO
     };
     match op {
         t::UnaryOp::Deref => Some(cfg::UnaryOp::DerefLoad),
-        t::UnaryOp::Minus => int.map(|(sign, size)| cfg::UnaryOp::Negate(sign, size)),
+        t::UnaryOp::Minus => match *arg_type {
+            t::Type::Concrete(_, _) => Some(cfg::UnaryOp::NumericNegate(arg_type.clone())),
+            _ => int.map(|(sign, size)| cfg::UnaryOp::Negate(sign, size)),
+        },
         t::UnaryOp::Not => Some(cfg::UnaryOp::Not),
         t::UnaryOp::Plus | t::UnaryOp::AddressOf | t::UnaryOp::MutAddressOf => panic!("invalid unary op"),
     }
 }
 
-fn compile_fn(f: &t::Function, ctx: &mut CompileCtx) -> cfg::Function {
-    let mut builder = Builder::new(ctx);
+fn compile_fn(f: &t::Function, ctx: &mut CompileCtx, generated: &mut Generated) -> cfg::Function {
+    let mut builder = Builder::new(ctx, generated, f.type_params.clone());
     let start_block = builder.build_function(f);
-    debug_assert!(builder.current_block.is_none());
-    cfg::Function {
-        parameters: builder.parameters,
-        complete_span: f.complete_span,
-        type_params: f.type_params.clone(),
-        registers: builder.registers,
-        register_symbols: builder.register_vars,
-        out_type: f.return_type.clone(),
-        blocks: builder.blocks,
+    builder.into_function(
+        f.complete_span,
+        f.signature_span,
+        f.return_type.clone(),
         start_block,
+        f.inline,
+        f.calling_convention,
+    )
+}
+
+fn compile_thunk(
+    function: t::Symbol,
+    type_params: &[t::Symbol],
+    params: &[t::Type],
+    out_type: &t::Type,
+    span: Span,
+) -> cfg::Function {
+    let env = cfg::Reg(0);
+    let result = cfg::Reg(params.len() as u32 + 1);
+    let mut registers = HashMap::new();
+    let unit = Arc::new(t::Type::Unit);
+    registers.insert(env, t::Type::Pointer(Mut::Const, unit));
+    registers.insert(result, out_type.clone());
+    let mut parameters = vec![env];
+    for (index, typ) in params.iter().enumerate() {
+        let reg = cfg::Reg(index as u32 + 1);
+        registers.insert(reg, typ.clone());
+        parameters.push(reg);
+    }
+    let type_params_values = type_params
+        .iter()
+        .map(|&param| t::Type::Concrete(param, Vec::new().into()))
+        .collect();
+    let callee = cfg::Value::Symbol(function, type_params_values);
+    let call = cfg::Instruction::Call(
+        result,
+        Spanned::new(callee, span),
+        parameters[1..]
+            .iter()
+            .map(|&reg| Spanned::new(cfg::Value::Reg(reg), span))
+            .collect(),
+    );
+    let block = cfg::Block {
+        ops: vec![Spanned::new(call, span)],
+        end: cfg::BlockEnd::Return(Spanned::new(cfg::Value::Reg(result), span)),
+        link: cfg::BlockLink::None,
+    };
+    let mut blocks = HashMap::new();
+    blocks.insert(cfg::BlockId(0), block);
+    cfg::Function {
+        complete_span: span,
+        signature_span: span,
+        type_params: type_params.to_vec(),
+        parameters,
+        out_type: out_type.clone(),
+        registers,
+        register_symbols: HashMap::new(),
+        deferred_init: HashSet::new(),
+        declared_uninit: HashMap::new(),
+        blocks,
+        start_block: Some(cfg::BlockId(0)),
+        inline: cfg::Inline::Auto,
+        calling_convention: cfg::CallingConvention::Stack,
     }
 }
 
 pub(crate) fn build_cfg(program: &t::Program, ctx: &mut CompileCtx) -> cfg::Program {
-    let functions = program
+    let mut generated = Generated::default();
+    let mut functions = program
         .functions
         .iter()
-        .map(|f| (f.name, compile_fn(f, ctx)))
-        .collect();
+        .map(|f| (f.name, compile_fn(f, ctx, &mut generated)))
+        .collect::<HashMap<_, _>>();
 
-    let structs = program.structs.clone();
+    let mut structs = program.structs.clone();
+
+    functions.extend(generated.functions);
+    for s in generated.structs {
+        structs.insert(s.name, s);
+    }
+    for (function, thunk) in generated.thunks {
+        let thunk_fn = if let Some(f) = program.functions.iter().find(|f| f.name == function) {
+            let params = f.params.iter().map(|p| p.typ.clone()).collect::<Vec<_>>();
+            compile_thunk(function, &f.type_params, &params, &f.return_type, f.complete_span)
+        } else {
+            // struct constructor
+            let s = &program.structs[&function];
+            let params = s.fields.iter().map(|f| f.typ.clone()).collect::<Vec<_>>();
+            let out_type = t::Type::Concrete(
+                s.name,
+                s.type_params
+                    .iter()
+                    .map(|&param| t::Type::Concrete(param, Vec::new().into()))
+                    .collect::<Vec<_>>()
+                    .into(),
+            );
+            compile_thunk(function, &s.type_params, &params, &out_type, s.complete_span)
+        };
+        functions.insert(thunk, thunk_fn);
+    }
 
     cfg::Program { structs, functions }
 }
@@ -1,18 +1,40 @@
 use std::collections::HashMap;
-use plank_ir::ir;
+use plank_ir::{debug, ir};
+use plank_errors::codes;
+use plank_syntax::position::{Span, Spanned};
 use ast::cfg;
+use build_cfg::binop_to_instruction;
 use struct_layout::LayoutEngine;
+use symbols::Symbols;
 use CompileCtx;
 
 
+/// How long a chain of generic functions instantiating each other can be.
+/// Longer chains are almost certainly caused by a function instantiating
+/// itself with ever growing type parameters, which would never stop.
+const INSTANTIATION_LIMIT: u32 = 64;
+/// How long the symbol of an instantiation can be. This limits the size
+/// of type parameters, which can double at every step of a chain, and
+/// would get huge long before the chain reaches `INSTANTIATION_LIMIT`.
+const SYMBOL_LENGTH_LIMIT: usize = 1024;
+
+
 struct Builder<'a> {
     layouts: &'a LayoutEngine<'a>,
-    ctx: &'a CompileCtx,
+    symbols: &'a Symbols,
     function_name: cfg::Symbol,
     function: &'a cfg::Function,
     type_params: HashMap<cfg::Symbol, cfg::Type>,
-    dependencies: HashMap<ir::Symbol, (cfg::Symbol, Vec<cfg::Type>)>,
+    /// Instantiations used by this function, with where they were used.
+    dependencies: HashMap<ir::Symbol, (cfg::Symbol, Vec<cfg::Type>, Span)>,
+    /// Span of the instruction that is being built.
+    current_span: Span,
     registers: HashMap<ir::Reg, ir::Layout>,
+    next_reg: u32,
+    /// Compound types used in debug info of this function.
+    debug_types: HashMap<String, debug::Composite>,
+    /// Overflow behavior of checked arithmetic, if it is checked.
+    overflow: Option<ir::Overflow>,
 }
 
 impl<'a> Builder<'a> {
@@ -20,8 +42,9 @@ impl<'a> Builder<'a> {
         function_name: cfg::Symbol,
         function: &'a cfg::Function,
         type_params: HashMap<cfg::Symbol, cfg::Type>,
-        ctx: &'a CompileCtx,
+        symbols: &'a Symbols,
         layouts: &'a LayoutEngine<'a>,
+        overflow: Option<ir::Overflow>,
     ) -> Self {
         let registers = function
             .registers
@@ -38,14 +61,19 @@ impl<'a> Builder<'a> {
                 (reg, layout)
             })
             .collect();
+        let next_reg = function.registers.keys().map(|r| r.0 + 1).max().unwrap_or(0);
         Builder {
-            ctx,
+            next_reg,
+            symbols,
             type_params,
             layouts,
             function_name,
             function,
             dependencies: HashMap::new(),
+            current_span: function.complete_span,
             registers,
+            debug_types: HashMap::new(),
+            overflow,
         }
     }
 
@@ -80,7 +108,9 @@ impl<'a> Builder<'a> {
             })
         };
         self.registers.retain(|_, layout| layout.size > 0);
-        // cheat with size_of and align_of - insert an appropriate implementation
+        let debug = self.debug_info(&out_type);
+        // cheat with size_of, align_of, len and builtins that take slices - insert an
+        // appropriate implementation
         let start_block = if self.function_name == ::builtins::SIZE_OF {
             debug_assert_eq!(self.type_params.len(), 1);
             let param = self.type_params.values().next().unwrap();
@@ -90,6 +120,7 @@ impl<'a> Builder<'a> {
                 ir::Block {
                     ops: Vec::new(),
                     end: ir::BlockEnd::Return(ir::Value::Int(size, ir::Size::Bit32)),
+                    end_span: Some(self.function.signature_span),
                 },
             );
             Some(ir::BlockId(0))
@@ -102,11 +133,147 @@ impl<'a> Builder<'a> {
                 ir::Block {
                     ops: Vec::new(),
                     end: ir::BlockEnd::Return(ir::Value::Int(align, ir::Size::Bit32)),
+                    end_span: Some(self.function.signature_span),
+                },
+            );
+            Some(ir::BlockId(0))
+        } else if self.function_name == ::builtins::LEN
+            || self.function_name == ::builtins::STR_LEN
+        {
+            debug_assert_eq!(self.function.parameters.len(), 1);
+            let slice = self.function.parameters[0];
+            let slice_type = self.function.registers[&slice].replace(&self.type_params);
+            let (offset, _) = self.layouts.field_info(&slice_type, 1);
+            let len = self.new_register(ir::Layout {
+                size: 4,
+                align: 4,
+                atomic: true,
+            });
+            let span = Some(self.function.signature_span);
+            let load = ir::Instruction::Load(len, ir::Reg(slice.0), offset);
+            blocks.insert(
+                ir::BlockId(0),
+                ir::Block {
+                    ops: vec![ir::Op::new(load, span)],
+                    end: ir::BlockEnd::Return(ir::Value::Reg(len)),
+                    end_span: span,
+                },
+            );
+            Some(ir::BlockId(0))
+        } else if self.function_name == ::builtins::ASSERT {
+            // calls through function pointers don't know where
+            // they came from, direct calls are built in place
+            debug_assert_eq!(self.function.parameters.len(), 2);
+            let span = Some(self.function.signature_span);
+            let condition = ir::Value::Reg(ir::Reg(self.function.parameters[0].0));
+            let message = self.function.parameters[1];
+            let ops = self.fail_ops(message, "builtin_assert_fail");
+            let block = |ops, end| ir::Block {
+                ops,
+                end,
+                end_span: span,
+            };
+            let end = ir::BlockEnd::Branch(condition, ir::BlockId(1), ir::BlockId(2));
+            blocks.insert(ir::BlockId(0), block(Vec::new(), end));
+            blocks.insert(ir::BlockId(1), block(Vec::new(), ir::BlockEnd::ReturnProc));
+            blocks.insert(ir::BlockId(2), block(ops, ir::BlockEnd::Unreachable));
+            Some(ir::BlockId(0))
+        } else if self.function_name == ::builtins::PANIC {
+            debug_assert_eq!(self.function.parameters.len(), 1);
+            let message = self.function.parameters[0];
+            let ops = self.fail_ops(message, "builtin_panic_fail");
+            blocks.insert(
+                ir::BlockId(0),
+                ir::Block {
+                    ops,
+                    end: ir::BlockEnd::Unreachable,
+                    end_span: Some(self.function.signature_span),
+                },
+            );
+            Some(ir::BlockId(0))
+        } else if let Some((runtime, output)) = slice_builtin(self.function_name) {
+            // backends only know how to work with pointers and lengths
+            let span = Some(self.function.signature_span);
+            let mut ops = Vec::new();
+            let mut fields = Vec::new();
+            for &slice in &self.function.parameters {
+                let slice_type = self.function.registers[&slice].replace(&self.type_params);
+                for field in 0..2 {
+                    let (offset, field_type) = self.layouts.field_info(&slice_type, field);
+                    let (size, align) = self.layouts.size_align(&field_type).unwrap();
+                    let reg = self.new_register(ir::Layout {
+                        size,
+                        align,
+                        atomic: true,
+                    });
+                    let load = ir::Instruction::Load(reg, ir::Reg(slice.0), offset);
+                    ops.push(ir::Op::new(load, span));
+                    fields.push(ir::Value::Reg(reg));
+                }
+            }
+            let symbol = ir::Symbol(runtime.into());
+            let end = match output {
+                Some(size) => {
+                    let result = self.new_register(ir::Layout {
+                        size,
+                        align: size,
+                        atomic: true,
+                    });
+                    ops.push(ir::Op::new(ir::Instruction::Call(result, symbol, fields), span));
+                    ir::BlockEnd::Return(ir::Value::Reg(result))
+                }
+                None => {
+                    ops.push(ir::Op::new(ir::Instruction::CallProc(symbol, fields), span));
+                    ir::BlockEnd::ReturnProc
+                }
+            };
+            blocks.insert(
+                ir::BlockId(0),
+                ir::Block {
+                    ops,
+                    end,
+                    end_span: span,
                 },
             );
             Some(ir::BlockId(0))
         } else {
-            self.function.start_block.map(|b| ir::BlockId(b.0))
+            self.function.start_block.map(|b| {
+                let start = ir::BlockId(b.0);
+                // unions are only ever written one field at a time, so mark
+                // them as initialized before anything else happens
+                let mut unions = self.function
+                    .registers
+                    .iter()
+                    .filter(|&(&reg, ty)| {
+                        !self.function.parameters.contains(&reg)
+                            && self.registers.contains_key(&ir::Reg(reg.0))
+                            && self.layouts.is_union(&ty.replace(&self.type_params))
+                    })
+                    .map(|(&reg, _)| reg.0)
+                    .collect::<Vec<_>>();
+                // registers come from a hash map, sort them to keep output stable
+                unions.sort();
+                let ops = unions
+                    .into_iter()
+                    .map(|reg| {
+                        let init = ir::Instruction::Init(ir::Reg(reg));
+                        ir::Op::new(init, Some(self.function.signature_span))
+                    })
+                    .collect::<Vec<_>>();
+                if ops.is_empty() {
+                    return start;
+                }
+                let entry = ir::BlockId(blocks.keys().map(|b| b.0 + 1).max().unwrap_or(0));
+                blocks.insert(
+                    entry,
+                    ir::Block {
+                        ops,
+                        end: ir::BlockEnd::Jump(start),
+                        end_span: Some(self.function.signature_span),
+                    },
+                );
+                entry
+            })
         };
         ir::Function {
             blocks,
@@ -114,29 +281,208 @@ impl<'a> Builder<'a> {
             start_block,
             parameters,
             registers: ::std::mem::replace(&mut self.registers, HashMap::new()),
+            inline: match self.function.inline {
+                cfg::Inline::Auto => ir::Inline::Auto,
+                cfg::Inline::Always => ir::Inline::Always,
+                cfg::Inline::Never => ir::Inline::Never,
+            },
+            calling_convention: self.function.calling_convention,
+            debug: Some(debug),
+        }
+    }
+
+    /// Describe the function and its named variables for debug info.
+    fn debug_info(&mut self, out_type: &cfg::Type) -> debug::Function {
+        let mut name = String::from(self.symbols.get_name(self.function_name));
+        if !self.function.type_params.is_empty() {
+            name.push('<');
+            for (index, param) in self.function.type_params.iter().enumerate() {
+                if index > 0 {
+                    name.push(',');
+                }
+                self.write_type(&mut name, &self.type_params[param]);
+            }
+            name.push('>');
+        }
+        let parameters = &self.function.parameters;
+        let mut locals = self.function
+            .register_symbols
+            .keys()
+            .filter(|reg| !parameters.contains(reg))
+            .cloned()
+            .collect::<Vec<_>>();
+        locals.sort();
+        let mut variables = Vec::new();
+        for reg in parameters.iter().chain(&locals) {
+            let symbol = match self.function.register_symbols.get(reg) {
+                Some(&symbol) => symbol,
+                None => continue,
+            };
+            // zero sized variables are not stored anywhere
+            if !self.registers.contains_key(&ir::Reg(reg.0)) {
+                continue;
+            }
+            let typ = self.function.registers[reg].replace(&self.type_params);
+            variables.push(debug::Variable {
+                name: self.symbols.get_name(symbol).into(),
+                typ: self.debug_type(&typ),
+                reg: ir::Reg(reg.0),
+                is_parameter: parameters.contains(reg),
+            });
+        }
+        // builtins are made without source, so their spans are empty
+        let span = self.function.signature_span;
+        debug::Function {
+            name,
+            span: if span.start == span.end { None } else { Some(span) },
+            return_type: self.debug_type(out_type),
+            variables,
         }
     }
 
+    /// Describe a type without type parameters for debug info.
+    fn debug_type(&mut self, typ: &cfg::Type) -> debug::Type {
+        match *typ {
+            cfg::Type::Unit | cfg::Type::Never => debug::Type::Unit,
+            cfg::Type::Bool => debug::Type::Bool,
+            cfg::Type::Int(sign, size) => debug::Type::Int(conv_sign(sign), conv_size(size)),
+            cfg::Type::Pointer(_, ref to) => debug::Type::Pointer(Box::new(self.debug_type(to))),
+            cfg::Type::Error => panic!("cannot build ir with errors"),
+            cfg::Type::Var(_) => panic!("cannot build ir with type vars"),
+            cfg::Type::Str |
+            cfg::Type::Slice(_, _) |
+            cfg::Type::Function(_, _) |
+            cfg::Type::Concrete(_, _) => {
+                let mut name = String::new();
+                self.write_type(&mut name, typ);
+                if !self.debug_types.contains_key(&name) {
+                    // add the type before its fields, as they can point to it
+                    let composite = debug::Composite {
+                        is_union: self.layouts.is_union(typ),
+                        size: self.layouts.size_of(typ).unwrap(),
+                        fields: Vec::new(),
+                    };
+                    self.debug_types.insert(name.clone(), composite);
+                    let fields = self.debug_fields(typ);
+                    self.debug_types.get_mut(&name).unwrap().fields = fields;
+                }
+                debug::Type::Composite(name)
+            }
+        }
+    }
+
+    fn debug_fields(&mut self, typ: &cfg::Type) -> Vec<debug::Field> {
+        let names = match *typ {
+            cfg::Type::Str | cfg::Type::Slice(_, _) => vec!["ptr".into(), "len".into()],
+            cfg::Type::Function(_, _) => vec!["code".into(), "env".into()],
+            cfg::Type::Concrete(name, _) => {
+                self.layouts
+                    .get_struct(name)
+                    .fields
+                    .iter()
+                    .map(|field| String::from(self.symbols.get_name(field.name)))
+                    .collect()
+            }
+            _ => panic!("type has no fields"),
+        };
+        names
+            .into_iter()
+            .enumerate()
+            .map(|(index, name)| {
+                let (offset, field_type) = self.layouts.field_info(typ, index);
+                debug::Field {
+                    name,
+                    offset,
+                    typ: self.debug_type(&field_type),
+                }
+            })
+            .collect()
+    }
+
+    fn new_register(&mut self, layout: ir::Layout) -> ir::Reg {
+        let reg = ir::Reg(self.next_reg);
+        self.next_reg += 1;
+        self.registers.insert(reg, layout);
+        reg
+    }
+
+    /// Call runtime builtin `fail` with the pointer and length of
+    /// `message`, and zeros for the unknown position.
+    fn fail_ops(&mut self, message: cfg::Reg, fail: &str) -> Vec<ir::Op> {
+        let span = Some(self.function.signature_span);
+        let message_type = self.function.registers[&message].replace(&self.type_params);
+        let mut ops = Vec::new();
+        let mut args = Vec::new();
+        for field in 0..2 {
+            let (offset, field_type) = self.layouts.field_info(&message_type, field);
+            let (size, align) = self.layouts.size_align(&field_type).unwrap();
+            let reg = self.new_register(ir::Layout {
+                size,
+                align,
+                atomic: true,
+            });
+            let load = ir::Instruction::Load(reg, ir::Reg(message.0), offset);
+            ops.push(ir::Op::new(load, span));
+            args.push(ir::Value::Reg(reg));
+        }
+        args.push(ir::Value::Int(0, ir::Size::Bit32));
+        args.push(ir::Value::Int(0, ir::Size::Bit32));
+        let fail = ir::Instruction::CallProc(ir::Symbol(fail.into()), args);
+        ops.push(ir::Op::new(fail, span));
+        ops
+    }
+
     fn build_block(&mut self, block: &cfg::Block) -> ir::Block {
         let mut ops = Vec::new();
         for op in &block.ops {
-            if let Some(op) = self.build_instruction(op) {
-                ops.push(op);
-            }
+            self.current_span = Spanned::span(op);
+            let mut built = Vec::new();
+            self.build_instructions(op, &mut built);
+            let span = Some(self.current_span);
+            ops.extend(built.into_iter().map(|i| ir::Op::new(i, span)));
         }
+        // jumps don't come from any particular source code, and
+        // `unreachable` comes from the preceding call
+        let end_span = match block.end {
+            cfg::BlockEnd::Return(ref val) | cfg::BlockEnd::Branch(ref val, _, _) => {
+                self.current_span = Spanned::span(val);
+                Some(self.current_span)
+            }
+            cfg::BlockEnd::Unreachable => block.ops.last().map(Spanned::span),
+            cfg::BlockEnd::Jump(_) | cfg::BlockEnd::Error => None,
+        };
         let end = match block.end {
             cfg::BlockEnd::Branch(ref val, a, b) => {
                 ir::BlockEnd::Branch(self.convert_value(val), ir::BlockId(a.0), ir::BlockId(b.0))
             }
             cfg::BlockEnd::Error => panic!("cannot build ir with errors"),
             cfg::BlockEnd::Jump(id) => ir::BlockEnd::Jump(ir::BlockId(id.0)),
+            cfg::BlockEnd::Unreachable => ir::BlockEnd::Unreachable,
             cfg::BlockEnd::Return(ref val) => if self.is_zero_sized_value(val) {
                 ir::BlockEnd::ReturnProc
             } else {
                 ir::BlockEnd::Return(self.convert_value(val))
             },
         };
-        ir::Block { ops, end }
+        ir::Block { ops, end, end_span }
+    }
+
+    /// Build IR for one instruction, which can take several IR instructions.
+    fn build_instructions(&mut self, op: &cfg::Instruction, ops: &mut Vec<ir::Instruction>) {
+        if let cfg::Instruction::Call(dest, ref callee, ref params) = *op {
+            if let cfg::Value::Reg(function) = **callee {
+                self.build_virtual_call(dest, function, params, ops);
+                return;
+            }
+        }
+        if let cfg::Instruction::UnaryOp(dest, cfg::UnaryOp::ElementOffset(ref typ), ref val) = *op
+        {
+            self.build_element_offset(dest, typ, val, ops);
+            return;
+        }
+        if let Some(op) = self.build_instruction(op) {
+            ops.push(op);
+        }
     }
 
     fn build_instruction(&mut self, i: &cfg::Instruction) -> Option<ir::Instruction> {
@@ -159,11 +505,24 @@ impl<'a> Builder<'a> {
                 let value = ir::Value::Int(0, ir::Size::Bit8);
                 Some(ir::Instruction::Assign(ir::Reg(dest.0), value))
             }
-            cfg::Instruction::BinaryOp(dest, op, ref a, ref b) => {
+            cfg::Instruction::BinaryOp(dest, ref op, ref a, ref b) => {
                 debug_assert!(!self.is_zero_sized(dest));
                 let a = self.convert_value(a);
                 let b = self.convert_value(b);
-                let op = convert_binop(op);
+                let op = match *op {
+                    cfg::BinaryOp::Numeric(op, ref typ) => {
+                        let typ = typ.replace(&self.type_params);
+                        binop_to_instruction(op, &typ).expect("non-numeric type parameter")
+                    }
+                    ref op => op.clone(),
+                };
+                let op = match convert_binop(op) {
+                    // pointer arithmetic from indexing is done in pointer size
+                    ir::BinaryOp::IntOp(op, sign, _) if self.is_pointer(dest) => {
+                        ir::BinaryOp::IntOp(op, sign, self.pointer_size())
+                    }
+                    op => self.checked(op),
+                };
                 Some(ir::Instruction::BinaryOp(ir::Reg(dest.0), op, a, b))
             }
             cfg::Instruction::Call(dest, ref callee, ref params) => {
@@ -184,22 +543,7 @@ impl<'a> Builder<'a> {
                             ir::Instruction::Call(ir::Reg(dest.0), f, params)
                         })
                     }
-                    ref val => {
-                        let f = self.convert_value(val);
-                        let params = params
-                            .iter()
-                            .filter_map(|p| if !self.is_zero_sized_value(p) {
-                                Some(self.convert_value(p))
-                            } else {
-                                None
-                            })
-                            .collect();
-                        Some(if self.is_zero_sized(dest) {
-                            ir::Instruction::CallProcVirt(f, params)
-                        } else {
-                            ir::Instruction::CallVirt(ir::Reg(dest.0), f, params)
-                        })
-                    }
+                    _ => panic!("function values are called with `build_virtual_call`"),
                 }
             }
             cfg::Instruction::DerefStore(ref address, ref typ, ref fields, ref value) => {
@@ -243,7 +587,7 @@ impl<'a> Builder<'a> {
             cfg::Instruction::TakeAddress(dest, reg, ref fields) => {
                 let dest = ir::Reg(dest.0);
                 if self.is_zero_sized(*reg) {
-                    let value = ir::Value::Int(0, ir::Size::Bit32);
+                    let value = ir::Value::Int(0, self.pointer_size());
                     Some(ir::Instruction::Assign(dest, value))
                 } else {
                     let offset = {
@@ -254,6 +598,15 @@ impl<'a> Builder<'a> {
                     Some(ir::Instruction::TakeAddress(dest, reg, offset))
                 }
             }
+            cfg::Instruction::UnaryOp(dest, cfg::UnaryOp::NumericNegate(ref typ), ref val) => {
+                let (sign, size) = match typ.replace(&self.type_params) {
+                    cfg::Type::Int(sign, size) => (sign, size),
+                    _ => panic!("non-numeric type parameter"),
+                };
+                let op = cfg::UnaryOp::Negate(sign, size);
+                let instruction = cfg::Instruction::UnaryOp(dest, op, val.clone());
+                self.build_instruction(&instruction)
+            }
             cfg::Instruction::UnaryOp(dest, cfg::UnaryOp::Negate(sign, size), ref val) => {
                 debug_assert!(!self.is_zero_sized(dest));
                 debug_assert!(!self.is_zero_sized_value(val));
@@ -268,12 +621,36 @@ impl<'a> Builder<'a> {
                     cfg::Size::Bit16 => ir::Size::Bit16,
                     cfg::Size::Bit32 => ir::Size::Bit32,
                 };
+                if let Some(overflow) = self.overflow {
+                    // only subtraction from zero is checked
+                    let op = ir::BinaryOp::Checked(ir::IntOp::Sub, overflow, sign, size);
+                    let zero = ir::Value::Int(0, size);
+                    return Some(ir::Instruction::BinaryOp(dest, op, zero, val));
+                }
                 Some(ir::Instruction::UnaryOp(
                     dest,
                     ir::UnaryOp::Negate(sign, size),
                     val,
                 ))
             }
+            cfg::Instruction::UnaryOp(dest, cfg::UnaryOp::Extend(sign, from, to), ref val) => {
+                let dest = ir::Reg(dest.0);
+                let val = self.convert_value(val);
+                let sign = match sign {
+                    cfg::Signedness::Signed => ir::Signedness::Signed,
+                    cfg::Signedness::Unsigned => ir::Signedness::Unsigned,
+                };
+                let convert_size = |size| match size {
+                    cfg::Size::Bit8 => ir::Size::Bit8,
+                    cfg::Size::Bit16 => ir::Size::Bit16,
+                    cfg::Size::Bit32 => ir::Size::Bit32,
+                };
+                Some(ir::Instruction::UnaryOp(
+                    dest,
+                    ir::UnaryOp::Extend(sign, convert_size(from), convert_size(to)),
+                    val,
+                ))
+            }
             cfg::Instruction::UnaryOp(dest, cfg::UnaryOp::DerefLoad, ref val) => {
                 if self.is_zero_sized(dest) {
                     None
@@ -318,11 +695,14 @@ impl<'a> Builder<'a> {
                 };
                 let offset = self.find_offset(typ, fields);
                 let val = self.convert_value(val);
-                let op =
-                    ir::BinaryOp::IntOp(ir::IntOp::Add, ir::Signedness::Unsigned, ir::Size::Bit32);
-                let arg = ir::Value::Int(u64::from(offset), ir::Size::Bit32);
+                let size = self.pointer_size();
+                let op = ir::BinaryOp::IntOp(ir::IntOp::Add, ir::Signedness::Unsigned, size);
+                let arg = ir::Value::Int(u64::from(offset), size);
                 Some(ir::Instruction::BinaryOp(dest, op, val, arg))
             }
+            cfg::Instruction::UnaryOp(_, cfg::UnaryOp::ElementOffset(_), _) => {
+                panic!("element offsets are built with `build_element_offset`")
+            }
             cfg::Instruction::Error => panic!("cannot build ir with errors"),
             cfg::Instruction::CastAssign(to, ref val) => if self.is_zero_sized(to) {
                 None
@@ -333,6 +713,96 @@ impl<'a> Builder<'a> {
         }
     }
 
+    fn build_element_offset(
+        &mut self,
+        dest: cfg::Reg,
+        typ: &cfg::Type,
+        index: &cfg::Value,
+        ops: &mut Vec<ir::Instruction>,
+    ) {
+        let dest = ir::Reg(dest.0);
+        let typ = typ.replace(&self.type_params);
+        let size = self.layouts.size_of(&typ).unwrap();
+        let pointer_size = self.pointer_size();
+        let index = self.convert_value(index);
+        let op = ir::BinaryOp::IntOp(ir::IntOp::Mul, ir::Signedness::Unsigned, pointer_size);
+        let arg = ir::Value::Int(u64::from(size), pointer_size);
+        if pointer_size == ir::Size::Bit32 {
+            ops.push(ir::Instruction::BinaryOp(dest, op, index, arg));
+            return;
+        }
+        // index is always `u32`, so it has to be widened to pointer size first
+        let widened = self.new_register(ir::Layout {
+            size: pointer_size.in_bytes(),
+            align: self.layouts.pointer_align(),
+            atomic: true,
+        });
+        let extend = ir::UnaryOp::Extend(ir::Signedness::Unsigned, ir::Size::Bit32, pointer_size);
+        ops.push(ir::Instruction::UnaryOp(widened, extend, index));
+        ops.push(ir::Instruction::BinaryOp(dest, op, ir::Value::Reg(widened), arg));
+        ops.push(ir::Instruction::Drop(widened));
+    }
+
+    fn build_virtual_call(
+        &mut self,
+        dest: cfg::Reg,
+        function: cfg::Reg,
+        params: &[Spanned<cfg::Value>],
+        ops: &mut Vec<ir::Instruction>,
+    ) {
+        // function value contains the function itself and pointer to its
+        // environment, which is passed as a hidden first parameter
+        let function_type = self.function.registers[&function].clone();
+        let env_offset = self.find_offset(&function_type, &[1]);
+        let pointer_layout = ir::Layout {
+            size: self.layouts.pointer_size(),
+            align: self.layouts.pointer_align(),
+            atomic: true,
+        };
+        let code = self.new_register(pointer_layout);
+        let env = self.new_register(pointer_layout);
+        let function = ir::Reg(function.0);
+        ops.push(ir::Instruction::Load(code, function, 0));
+        ops.push(ir::Instruction::Load(env, function, env_offset));
+        let mut values = vec![ir::Value::Reg(env)];
+        for param in params {
+            if !self.is_zero_sized_value(param) {
+                values.push(self.convert_value(param));
+            }
+        }
+        ops.push(if self.is_zero_sized(dest) {
+            ir::Instruction::CallProcVirt(ir::Value::Reg(code), values)
+        } else {
+            ir::Instruction::CallVirt(ir::Reg(dest.0), ir::Value::Reg(code), values)
+        });
+        ops.push(ir::Instruction::Drop(code));
+        ops.push(ir::Instruction::Drop(env));
+    }
+
+    fn pointer_size(&self) -> ir::Size {
+        self.layouts.target().pointer_size
+    }
+
+    /// Make arithmetic that can overflow checked, if it should be.
+    fn checked(&self, op: ir::BinaryOp) -> ir::BinaryOp {
+        match (op, self.overflow) {
+            (ir::BinaryOp::IntOp(op, sign, size), Some(overflow)) => match (op, sign) {
+                (ir::IntOp::Add, _) |
+                (ir::IntOp::Sub, _) |
+                (ir::IntOp::Mul, _) |
+                (ir::IntOp::Div, ir::Signedness::Signed) => {
+                    ir::BinaryOp::Checked(op, overflow, sign, size)
+                }
+                _ => ir::BinaryOp::IntOp(op, sign, size),
+            },
+            (op, _) => op,
+        }
+    }
+
+    fn is_pointer(&self, reg: cfg::Reg) -> bool {
+        matches!(self.function.registers[&reg], cfg::Type::Pointer(_, _))
+    }
+
     fn is_zero_sized(&self, reg: cfg::Reg) -> bool {
         let ir_reg = ir::Reg(reg.0);
         self.registers[&ir_reg].size == 0
@@ -342,7 +812,9 @@ impl<'a> Builder<'a> {
         match *value {
             cfg::Value::Unit => true,
             cfg::Value::Bytes(_) |
+            cfg::Value::Null |
             cfg::Value::Int(_, _) |
+            cfg::Value::NumericInt(_, _) |
             cfg::Value::Symbol(_, _) => false,
             cfg::Value::Reg(reg) => self.is_zero_sized(reg),
             cfg::Value::Error => panic!("cannot build ir with errors"),
@@ -350,16 +822,16 @@ impl<'a> Builder<'a> {
     }
 
     fn make_symbol(&mut self, id: cfg::Symbol, type_params: &[cfg::Type]) -> ir::Symbol {
-        match self.ctx.symbols.get_name(id) {
-            "@getc" => return ir::Symbol("builtin_getc".into()),
-            "@putc" => return ir::Symbol("builtin_putc".into()),
-            _ => {}
+        // functions that backends implement are named `@name`
+        let name = self.symbols.get_name(id);
+        if let Some(builtin) = name.strip_prefix('@') {
+            return ir::Symbol(format!("builtin_{}", builtin).into());
         }
         let type_params = type_params
             .iter()
             .map(|ty| ty.replace(&self.type_params))
             .collect::<Vec<_>>();
-        let mut symbol: String = format!("fn_{}", self.ctx.symbols.get_name(id));
+        let mut symbol: String = format!("fn_{}", self.symbols.get_name(id));
         if !type_params.is_empty() {
             symbol.push_str("::<");
             let mut first = true;
@@ -373,22 +845,29 @@ impl<'a> Builder<'a> {
             symbol.push('>');
         }
         let symbol = ir::Symbol(symbol.into());
-        self.dependencies.insert(symbol.clone(), (id, type_params));
+        self.dependencies
+            .insert(symbol.clone(), (id, type_params, self.current_span));
         symbol
     }
 
     fn write_type(&self, to: &mut String, typ: &cfg::Type) {
         match *typ {
             cfg::Type::Unit => to.push_str("unit"),
+            cfg::Type::Never => to.push_str("never"),
             cfg::Type::Bool => to.push_str("bool"),
+            cfg::Type::Str => to.push_str("str"),
             cfg::Type::Error => panic!("cannot build ir with errors"),
             cfg::Type::Var(_) => panic!("cannot build ir with type vars"),
             cfg::Type::Pointer(_, ref ty) => {
                 to.push('*');
                 self.write_type(to, ty);
             }
+            cfg::Type::Slice(_, ref ty) => {
+                to.push_str("[]");
+                self.write_type(to, ty);
+            }
             cfg::Type::Concrete(name, ref params) => {
-                to.push_str(self.ctx.symbols.get_name(name));
+                to.push_str(self.symbols.get_name(name));
                 if !params.is_empty() {
                     to.push('<');
                     let mut first = true;
@@ -428,6 +907,7 @@ impl<'a> Builder<'a> {
         match *value {
             cfg::Value::Unit => panic!("cannot convert zero sized value"),
             cfg::Value::Bytes(ref bytes) => ir::Value::Bytes(bytes.clone()),
+            cfg::Value::Null => ir::Value::Int(0, self.pointer_size()),
             cfg::Value::Int(value, size) => {
                 let size = match size {
                     cfg::Size::Bit8 => ir::Size::Bit8,
@@ -436,6 +916,10 @@ impl<'a> Builder<'a> {
                 };
                 ir::Value::Int(size.truncate(value), size)
             }
+            cfg::Value::NumericInt(value, ref typ) => match typ.replace(&self.type_params) {
+                cfg::Type::Int(_, size) => self.convert_value(&cfg::Value::Int(value, size)),
+                _ => panic!("non-numeric type parameter"),
+            },
             cfg::Value::Reg(reg) => ir::Value::Reg(ir::Reg(reg.0)),
             cfg::Value::Symbol(sym, ref types) => ir::Value::Symbol(self.make_symbol(sym, types)),
             cfg::Value::Error => panic!("cannot build ir with errors"),
@@ -454,20 +938,35 @@ impl<'a> Builder<'a> {
     }
 }
 
-fn convert_binop(op: cfg::BinaryOp) -> ir::BinaryOp {
-    fn conv_sign(sign: cfg::Signedness) -> ir::Signedness {
-        match sign {
-            cfg::Signedness::Signed => ir::Signedness::Signed,
-            cfg::Signedness::Unsigned => ir::Signedness::Unsigned,
-        }
+/// Builtins that pass the pointer and the length of each slice parameter
+/// to a runtime builtin, and the size of its result.
+fn slice_builtin(function: cfg::Symbol) -> Option<(&'static str, Option<u32>)> {
+    match function {
+        ::builtins::PRINT_STR => Some(("builtin_write", None)),
+        ::builtins::READ_LINE => Some(("builtin_read_line", Some(4))),
+        ::builtins::STR_EQ => Some(("builtin_str_eq", Some(1))),
+        ::builtins::STR_COPY => Some(("builtin_str_copy", Some(4))),
+        ::builtins::STR_FIND => Some(("builtin_str_find", Some(4))),
+        _ => None,
     }
-    fn conv_size(size: cfg::Size) -> ir::Size {
-        match size {
-            cfg::Size::Bit8 => ir::Size::Bit8,
-            cfg::Size::Bit16 => ir::Size::Bit16,
-            cfg::Size::Bit32 => ir::Size::Bit32,
-        }
+}
+
+fn conv_sign(sign: cfg::Signedness) -> ir::Signedness {
+    match sign {
+        cfg::Signedness::Signed => ir::Signedness::Signed,
+        cfg::Signedness::Unsigned => ir::Signedness::Unsigned,
+    }
+}
+
+fn conv_size(size: cfg::Size) -> ir::Size {
+    match size {
+        cfg::Size::Bit8 => ir::Size::Bit8,
+        cfg::Size::Bit16 => ir::Size::Bit16,
+        cfg::Size::Bit32 => ir::Size::Bit32,
     }
+}
+
+fn convert_binop(op: cfg::BinaryOp) -> ir::BinaryOp {
     match op {
         cfg::BinaryOp::Add(sign, size) => {
             ir::BinaryOp::IntOp(ir::IntOp::Add, conv_sign(sign), conv_size(size))
@@ -498,42 +997,142 @@ fn convert_binop(op: cfg::BinaryOp) -> ir::BinaryOp {
         }
         cfg::BinaryOp::Eq => ir::BinaryOp::Eq,
         cfg::BinaryOp::Neq => ir::BinaryOp::Neq,
+        cfg::BinaryOp::Numeric(_, _) => panic!("numeric op was not monomorphized"),
     }
 }
 
-pub(crate) fn build_ir(program: &cfg::Program, ctx: &CompileCtx) -> ir::Program {
-    let layout = LayoutEngine::new(&program.structs);
+pub(crate) fn build_ir(program: &cfg::Program, ctx: &CompileCtx) -> Result<ir::Program, ()> {
+    let layout = LayoutEngine::new(&program.structs, ctx.options.target);
+    let symbols = &ctx.symbols;
     let mut functions = HashMap::new();
-    let mut queue = HashMap::new();
+    let mut debug_types = HashMap::new();
+    let mut queue = Vec::new();
+    // how deep in the instantiation chain each function is,
+    // and which function instantiated it
+    let mut depths = HashMap::new();
+    let mut instantiated_by = HashMap::new();
+    let overflow = ctx.options.overflow;
     for (&id, f) in &program.functions {
         if f.type_params.is_empty() {
-            let symbol = ir::Symbol(format!("fn_{}", ctx.symbols.get_name(id)).into());
-            queue.insert(symbol, (id, Vec::new()));
+            let symbol = ir::Symbol(format!("fn_{}", symbols.get_name(id)).into());
+            depths.insert(symbol.clone(), 0);
+            queue.push((symbol, id, Vec::new()));
         }
     }
-    while let Some(symbol) = queue.keys().next().cloned() {
-        let (sym, types) = queue.remove(&symbol).unwrap();
-        let symbol = match &*symbol.0 {
-            "fn_@getc" => ir::Symbol("builtin_getc".into()),
-            "fn_@putc" => ir::Symbol("builtin_putc".into()),
-            s => ir::Symbol(s.into()),
-        };
-        if functions.contains_key(&symbol) {
-            continue;
+    // functions in the queue are built in parallel, and
+    // instantiations they use make up the next queue
+    while !queue.is_empty() {
+        // sort so that results don't depend on hash map order
+        queue.sort_by(|a, b| (a.0).0.cmp(&(b.0).0));
+        let built = ::plank_ir::parallel::map(&queue, |&(_, sym, ref types)| {
+            let function = &program.functions[&sym];
+            debug_assert_eq!(types.len(), function.type_params.len());
+            let type_params = function
+                .type_params
+                .iter()
+                .cloned()
+                .zip(types.iter().cloned())
+                .collect();
+            let mut builder =
+                Builder::new(sym, function, type_params, symbols, &layout, overflow);
+            let function = builder.build();
+            let mut dependencies = builder.dependencies.into_iter().collect::<Vec<_>>();
+            dependencies.sort_by(|a, b| (a.0).0.cmp(&(b.0).0));
+            (function, dependencies, builder.debug_types)
+        });
+        let mut next_queue = Vec::new();
+        for ((symbol, _, _), (function, dependencies, types)) in queue.into_iter().zip(built) {
+            debug_types.extend(types);
+            let depth = depths[&symbol];
+            let symbol = if let Some(builtin) = symbol.0.strip_prefix("fn_@") {
+                ir::Symbol(format!("builtin_{}", builtin).into())
+            } else {
+                symbol
+            };
+            for (dependency, (id, types, span)) in dependencies {
+                if depths.contains_key(&dependency) {
+                    continue;
+                }
+                instantiated_by.insert(dependency.clone(), (symbol.clone(), span));
+                if depth + 1 > INSTANTIATION_LIMIT || dependency.0.len() > SYMBOL_LENGTH_LIMIT {
+                    report_instantiation_limit(&dependency, &instantiated_by, ctx);
+                    return Err(());
+                }
+                depths.insert(dependency.clone(), depth + 1);
+                next_queue.push((dependency, id, types));
+            }
+            functions.insert(symbol, function);
         }
-        let function = &program.functions[&sym];
-        debug_assert_eq!(types.len(), function.type_params.len());
-        let type_params = function
-            .type_params
-            .iter()
-            .cloned()
-            .zip(types.into_iter())
-            .collect();
-        let mut builder = Builder::new(sym, function, type_params, ctx, &layout);
-        let function = builder.build();
-        queue.extend(builder.dependencies);
-        functions.insert(symbol, function);
+        queue = next_queue;
     }
 
-    ir::Program { functions }
+    let program = ir::Program {
+        functions,
+        target: ctx.options.target,
+        debug_types,
+        files: Vec::new(),
+    };
+    ::plank_ir::validation::check_valid(&program, || "after construction".into());
+    Ok(program)
+}
+
+fn report_instantiation_limit(
+    symbol: &ir::Symbol,
+    instantiated_by: &HashMap<ir::Symbol, (ir::Symbol, Span)>,
+    ctx: &CompileCtx,
+) {
+    fn name(symbol: &ir::Symbol) -> &str {
+        // body of `main` is wrapped when `main` returns unit
+        symbol.0.trim_start_matches("fn_").trim_end_matches("::body")
+    }
+    fn function_name(symbol: &ir::Symbol) -> String {
+        let name = name(symbol);
+        let end = name.find("::<").unwrap_or(name.len());
+        format!("`{}`", &name[..end])
+    }
+    let mut chain = vec![symbol];
+    while let Some((parent, _)) = instantiated_by.get(chain[chain.len() - 1]) {
+        chain.push(parent);
+    }
+    chain.reverse();
+    let functions = chain.iter().map(|s| function_name(s)).collect::<Vec<_>>();
+    // list functions until the first one that is instantiated again,
+    // after that the chain keeps repeating the same functions
+    let repeated = (1..functions.len())
+        .filter_map(|end| {
+            let start = functions[..end].iter().position(|f| *f == functions[end])?;
+            Some((start, end))
+        })
+        .next();
+    let listed = match repeated {
+        Some((start, end)) => format!(
+            "{}, then {} repeats",
+            functions[..end].join(" -> "),
+            functions[start..end].join(" -> "),
+        ),
+        None => functions.join(" -> "),
+    };
+    let span = instantiated_by[symbol].1;
+    let start_span = instantiated_by[chain[1]].1;
+    let mut symbol_name = name(symbol).to_string();
+    if symbol_name.len() > 80 {
+        let mut end = 80;
+        while !symbol_name.is_char_boundary(end) {
+            end -= 1;
+        }
+        symbol_name.truncate(end);
+        symbol_name.push_str("...");
+    }
+    let msg = format!("recursion limit reached while instantiating `{}`", symbol_name);
+    let note = format!(
+        "chain of {} instantiations starts here: {}",
+        chain.len() - 1,
+        listed,
+    );
+    ctx.reporter
+        .error(msg, span)
+        .code(codes::INSTANTIATION_LIMIT)
+        .primary_label("type parameters keep growing here", span)
+        .span_note(note, start_span)
+        .build();
 }
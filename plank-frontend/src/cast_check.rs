@@ -1,11 +1,12 @@
+use plank_errors::codes;
 use plank_syntax::position::Spanned;
-use ast::typed::{Expr, Function, Program, Statement, Type, TypedExpr};
+use ast::typed::{Expr, Function, Pattern, Program, Statement, Type, TypedExpr};
 use struct_layout::{LayoutEngine, LayoutResult};
 use CompileCtx;
 
 
 pub(crate) fn check_casts(program: &mut Program, ctx: &mut CompileCtx) {
-    let layouts = LayoutEngine::new(&program.structs);
+    let layouts = LayoutEngine::new(&program.structs, ctx.options.target);
     let mut ctx = Context::new(ctx, layouts);
     for f in &mut program.functions {
         ctx.check_function(f);
@@ -53,6 +54,15 @@ impl<'a> Context<'a> {
                 self.check_expr(cond);
                 self.check_statement(body);
             }
+            Statement::Match(ref mut value, ref mut arms) => {
+                self.check_expr(value);
+                for arm in arms {
+                    if let Pattern::Value(ref mut pattern) = *arm.pattern {
+                        self.check_expr(pattern);
+                    }
+                    self.check_statement(&mut arm.body);
+                }
+            }
         }
     }
 
@@ -70,8 +80,25 @@ impl<'a> Context<'a> {
                 }
                 return;
             }
-            Expr::Field(ref mut expr, _) | Expr::Unary(_, ref mut expr) => {
+            Expr::Field(ref mut expr, _) |
+            Expr::Unary(_, ref mut expr) |
+            Expr::Widen(ref mut expr) => {
+                self.check_expr(expr);
+                return;
+            }
+            Expr::Index(ref mut expr, ref mut index) => {
+                self.check_expr(expr);
+                self.check_expr(index);
+                return;
+            }
+            Expr::Slice(ref mut expr, ref mut start, ref mut end) => {
                 self.check_expr(expr);
+                self.check_expr(start);
+                self.check_expr(end);
+                return;
+            }
+            Expr::Lambda(ref mut lambda) => {
+                self.check_statement(&mut lambda.body);
                 return;
             }
             Expr::Error | Expr::Literal(_) | Expr::Name(_, _) => return,
@@ -87,6 +114,7 @@ impl<'a> Context<'a> {
                         self.ctx
                             .reporter
                             .error("cannot cast between types of different sizes", expr.span)
+                            .code(codes::INVALID_CAST)
                             .span(expr.span)
                             .build();
                     }
@@ -95,6 +123,7 @@ impl<'a> Context<'a> {
                         self.ctx
                             .reporter
                             .error("both types must have known fixed sizes", expr.span)
+                            .code(codes::INVALID_CAST)
                             .span(expr.span)
                             .build();
                     }
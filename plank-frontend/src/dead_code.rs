@@ -1,18 +1,20 @@
+use std::cmp::Reverse;
 use std::collections::{HashSet, VecDeque};
-use plank_syntax::position::Spanned;
+use plank_errors::codes;
+use plank_syntax::position::{Span, Spanned};
 use ast::cfg::{Block, BlockEnd, BlockId, BlockLink, Function, Instruction, Program};
 use CompileCtx;
 
 
-fn function_block_chain(f: &Function) -> VecDeque<BlockId> {
-    let mut blocks = VecDeque::new();
+fn function_block_chain(f: &Function) -> Vec<BlockId> {
+    let mut blocks = Vec::new();
     let mut current = if let Some(block) = f.start_block {
         block
     } else {
         return blocks;
     };
     loop {
-        blocks.push_back(current);
+        blocks.push(current);
         match f.blocks[&current].link {
             BlockLink::Strong(next) | BlockLink::Weak(next) => current = next,
             BlockLink::None => break,
@@ -21,9 +23,43 @@ fn function_block_chain(f: &Function) -> VecDeque<BlockId> {
     blocks
 }
 
-fn report_unreachable(block: &Block, ctx: &mut CompileCtx) {
-    let mut span = Spanned::span(&block.ops[0]);
-    for i in &block.ops {
+fn statement_spans<'a, I>(blocks: I, f: &'a Function) -> impl Iterator<Item = Span> + 'a
+where
+    I: IntoIterator<Item = &'a BlockId>,
+    I::IntoIter: 'a,
+{
+    blocks
+        .into_iter()
+        .flat_map(move |block| f.blocks[block].ops.iter())
+        .filter(|op| matches!(***op, Instruction::StartStatement))
+        .map(Spanned::span)
+}
+
+/// Operations of a block that could be reported as dead. If the block
+/// follows a call to a `#[noreturn]` function, the rest of the statement
+/// containing that call is skipped.
+fn reportable_ops(block: &Block, after_noreturn: bool) -> &[Spanned<Instruction>] {
+    if !after_noreturn {
+        return &block.ops;
+    }
+    let start = block
+        .ops
+        .iter()
+        .position(|op| matches!(**op, Instruction::StartStatement))
+        .unwrap_or(block.ops.len());
+    &block.ops[start..]
+}
+
+fn report_unreachable(
+    f: &Function,
+    preceding: &[BlockId],
+    dead: &[BlockId],
+    after_noreturn: bool,
+    ctx: &mut CompileCtx,
+) {
+    let ops = reportable_ops(&f.blocks[&dead[0]], after_noreturn);
+    let mut span = Spanned::span(&ops[0]);
+    for i in ops {
         match **i {
             Instruction::StartStatement => {
                 span = Spanned::span(i);
@@ -34,14 +70,32 @@ fn report_unreachable(block: &Block, ctx: &mut CompileCtx) {
             }
         }
     }
-    ctx.reporter
-        .warning("dead code detected", span)
-        .span(span)
-        .build();
+    // statement that makes the code dead is the last one to end before it,
+    // which is `return`, `break`, `continue`, or the statement containing them
+    let cause = statement_spans(preceding, f)
+        .filter(|s| s.end <= span.start)
+        .max_by_key(|s| (s.end, Reverse(s.start)));
+    // code after the enclosing statement might only be reachable from this
+    // dead code, but it is reported as a part of a different region
+    let enclosing = statement_spans(preceding, f)
+        .filter(|&s| s != span && s.contains(span))
+        .max_by_key(|s| s.start);
+    let region = statement_spans(dead, f)
+        .filter(|s| s.start >= span.start)
+        .filter(|&s| enclosing.is_none_or(|e| e.contains(s)))
+        .fold(span, Span::merge);
+    let mut builder = ctx.reporter
+        .lint("dead_code", "dead code detected", region)
+        .code(codes::DEAD_CODE)
+        .primary_label("unreachable code", region);
+    if let Some(cause) = cause {
+        builder = builder.secondary_label("any code following this statement is unreachable", cause);
+    }
+    builder.build();
 }
 
-fn can_be_dead(block: &Block) -> bool {
-    for op in &block.ops {
+fn can_be_dead(block: &Block, after_noreturn: bool) -> bool {
+    for op in reportable_ops(block, after_noreturn) {
         match **op {
             Instruction::Drop(_) => {}
             _ => return true,
@@ -50,51 +104,60 @@ fn can_be_dead(block: &Block) -> bool {
     false
 }
 
+/// Mark blocks that can be reached from `start` as reachable, and return
+/// the ones that were not marked before.
+fn mark_reachable(
+    f: &Function,
+    start: BlockId,
+    follow_strong: bool,
+    reachable: &mut HashSet<BlockId>,
+) -> Vec<BlockId> {
+    let mut reached = Vec::new();
+    let mut queue = VecDeque::new();
+    queue.push_back(start);
+    while let Some(block) = queue.pop_front() {
+        if !reachable.insert(block) {
+            continue;
+        }
+        reached.push(block);
+        let block = &f.blocks[&block];
+        match block.end {
+            BlockEnd::Branch(_, a, b) => {
+                queue.push_back(a);
+                queue.push_back(b);
+            }
+            BlockEnd::Jump(next) => {
+                queue.push_back(next);
+            }
+            BlockEnd::Return(_) | BlockEnd::Unreachable | BlockEnd::Error => {}
+        }
+        if follow_strong {
+            if let BlockLink::Strong(next) = block.link {
+                queue.push_back(next);
+            }
+        }
+    }
+    reached
+}
+
 fn analyze_function(f: &mut Function, ctx: &mut CompileCtx) {
-    let mut blocks = function_block_chain(f);
+    let blocks = function_block_chain(f);
     debug_assert_eq!(blocks.len(), f.blocks.len());
     let mut reachable = HashSet::new();
-    let mut strong_reachable = None;
-    let mut queue = VecDeque::new();
     if let Some(block) = f.start_block {
-        queue.push_back(block);
+        mark_reachable(f, block, false, &mut reachable);
     }
-    let mut follow_strong = false;
-    while !queue.is_empty() {
-        while let Some(block) = queue.pop_front() {
-            if !reachable.contains(&block) {
-                reachable.insert(block);
-                let block = &f.blocks[&block];
-                match block.end {
-                    BlockEnd::Branch(_, a, b) => {
-                        queue.push_back(a);
-                        queue.push_back(b);
-                    }
-                    BlockEnd::Jump(next) => {
-                        queue.push_back(next);
-                    }
-                    BlockEnd::Return(_) | BlockEnd::Error => {}
-                }
-                if follow_strong {
-                    if let BlockLink::Strong(next) = block.link {
-                        queue.push_back(next);
-                    }
-                }
-            }
-        }
-        if strong_reachable.is_none() {
-            strong_reachable = Some(reachable.clone());
-        }
-        follow_strong = true;
-        while let Some(block) = blocks.pop_front() {
-            if !reachable.contains(&block) && can_be_dead(&f.blocks[&block]) {
-                report_unreachable(&f.blocks[&block], ctx);
-                queue.push_back(block);
-                break;
-            }
+    let strong_reachable = reachable.clone();
+    for (index, &block) in blocks.iter().enumerate() {
+        let after_noreturn =
+            index > 0 && matches!(f.blocks[&blocks[index - 1]].end, BlockEnd::Unreachable);
+        if !reachable.contains(&block) && can_be_dead(&f.blocks[&block], after_noreturn) {
+            // code reachable from dead code is dead too,
+            // so it should not get reported separately
+            let dead = mark_reachable(f, block, true, &mut reachable);
+            report_unreachable(f, &blocks[..index], &dead, after_noreturn, ctx);
         }
     }
-    let strong_reachable = strong_reachable.unwrap_or_default();
     f.blocks.retain(|k, _| strong_reachable.contains(k));
 }
 
@@ -0,0 +1,133 @@
+use std::collections::{HashMap, HashSet};
+use plank_errors::codes;
+use plank_syntax::position::{Span, Spanned};
+use ast::cfg::{BlockEnd, Function, Instruction, Program, Reg, Value};
+use CompileCtx;
+
+
+struct Context<'a> {
+    ctx: &'a mut CompileCtx,
+    function: &'a Function,
+    /// Registers that might hold the address of a local variable, with
+    /// that variable and where its address was taken.
+    local_pointers: HashMap<Reg, Spanned<Reg>>,
+    /// Registers that hold a copy of some parameter.
+    parameters: HashSet<Reg>,
+}
+
+impl<'a> Context<'a> {
+    fn new(function: &'a Function, ctx: &'a mut CompileCtx) -> Self {
+        Context {
+            ctx,
+            function,
+            local_pointers: HashMap::new(),
+            parameters: function.parameters.iter().cloned().collect(),
+        }
+    }
+
+    fn ops(&self) -> impl Iterator<Item = &'a Spanned<Instruction>> {
+        let function = self.function;
+        function.blocks.values().flat_map(|block| block.ops.iter())
+    }
+
+    fn find_local_pointers(&mut self) {
+        for op in self.ops() {
+            if let Instruction::TakeAddress(target, var, _) = **op {
+                if self.function.register_symbols.contains_key(&var) {
+                    self.local_pointers.insert(target, var);
+                }
+            }
+        }
+        // pointers can be copied around before escaping,
+        // so follow assignments until nothing changes
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for op in self.ops() {
+                let (target, source) = match **op {
+                    Instruction::Assign(target, ref value) |
+                    Instruction::CastAssign(target, ref value) => match **value {
+                        Value::Reg(source) => (target, source),
+                        _ => continue,
+                    },
+                    _ => continue,
+                };
+                if self.parameters.contains(&source) && self.parameters.insert(target) {
+                    changed = true;
+                }
+                if self.local_pointers.contains_key(&target) {
+                    continue;
+                }
+                if let Some(&var) = self.local_pointers.get(&source) {
+                    self.local_pointers.insert(target, var);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    fn local_pointer(&self, value: &Value) -> Option<Spanned<Reg>> {
+        match *value {
+            Value::Reg(reg) => self.local_pointers.get(&reg).cloned(),
+            _ => None,
+        }
+    }
+
+    fn check_function(&mut self) {
+        self.find_local_pointers();
+        if self.local_pointers.is_empty() {
+            return;
+        }
+        let mut blocks = self.function.blocks.iter().collect::<Vec<_>>();
+        blocks.sort_by_key(|&(&id, _)| id);
+        for (_, block) in blocks {
+            for op in &block.ops {
+                if let Instruction::DerefStore(ref target, _, _, ref value) = **op {
+                    let through_param = match **target {
+                        Value::Reg(reg) => self.parameters.contains(&reg),
+                        _ => false,
+                    };
+                    if let (true, Some(var)) = (through_param, self.local_pointer(value)) {
+                        let msg = format!(
+                            "pointer to local variable `{}` is stored through a parameter",
+                            self.var_name(var),
+                        );
+                        self.report(msg, var, Spanned::span(value));
+                    }
+                }
+            }
+            if let BlockEnd::Return(ref value) = block.end {
+                if let Some(var) = self.local_pointer(value) {
+                    let msg = format!(
+                        "function returns a pointer to local variable `{}`",
+                        self.var_name(var),
+                    );
+                    self.report(msg, var, Spanned::span(value));
+                }
+            }
+        }
+    }
+
+    fn var_name(&self, var: Spanned<Reg>) -> String {
+        let symbol = self.function.register_symbols[&var];
+        self.ctx.symbols.get_name(symbol).to_string()
+    }
+
+    fn report(&mut self, msg: String, var: Spanned<Reg>, span: Span) {
+        let note = format!("`{}` is dropped when the function returns", self.var_name(var));
+        self.ctx
+            .reporter
+            .lint("dangling_pointer", msg, span)
+            .code(codes::DANGLING_POINTER)
+            .primary_label("pointer escapes the function here", span)
+            .secondary_label(note, Spanned::span(&var))
+            .build();
+    }
+}
+
+pub(crate) fn check_program(program: &Program, ctx: &mut CompileCtx) {
+    for f in program.functions.values() {
+        let mut ctx = Context::new(f, ctx);
+        ctx.check_function();
+    }
+}
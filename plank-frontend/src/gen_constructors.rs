@@ -1,6 +1,7 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use plank_syntax::position::Spanned;
-use ast::cfg::{Block, BlockEnd, BlockId, BlockLink, Function, Instruction, Program, Reg, Value};
+use ast::cfg::{Block, BlockEnd, BlockId, BlockLink, CallingConvention, Function, Inline,
+               Instruction, Program, Reg, Value};
 use ast::typed::{self as t, Struct};
 
 
@@ -43,18 +44,26 @@ fn generate_constructor(s: &Struct) -> Function {
     blocks.insert(BlockId(0), block);
     Function {
         complete_span: s.complete_span,
+        signature_span: s.complete_span,
         parameters,
         registers,
         register_symbols: HashMap::new(),
+        deferred_init: HashSet::new(),
+        declared_uninit: HashMap::new(),
         type_params: s.type_params.clone(),
         out_type: complete_type,
         start_block: Some(BlockId(0)),
         blocks,
+        inline: Inline::Auto,
+        calling_convention: CallingConvention::Stack,
     }
 }
 
 pub(crate) fn add_constructors(program: &mut Program) {
     for (id, s) in &program.structs {
+        if s.kind == t::StructKind::Union {
+            continue;
+        }
         let ctor = generate_constructor(s);
         program.functions.insert(*id, ctor);
     }
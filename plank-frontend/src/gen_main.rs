@@ -0,0 +1,85 @@
+use std::collections::{HashMap, HashSet};
+use plank_syntax::position::Spanned;
+use ast::cfg::{Block, BlockEnd, BlockId, BlockLink, CallingConvention, Function, Inline,
+               Instruction, Program, Reg, Signedness, Size, Symbol, Type, Value};
+use CompileCtx;
+
+
+fn generate_wrapper(main: Symbol, f: &Function) -> Function {
+    let span = f.complete_span;
+    let mut registers = HashMap::new();
+    registers.insert(Reg(0), Type::Unit);
+    // `argc` and `argv` are passed on if `main` takes them
+    let parameters = (1..=f.parameters.len() as u32).map(Reg).collect::<Vec<_>>();
+    for (&param, &reg) in parameters.iter().zip(&f.parameters) {
+        registers.insert(param, f.registers[&reg].clone());
+    }
+    let args = parameters
+        .iter()
+        .map(|&reg| Spanned::new(Value::Reg(reg), span))
+        .collect();
+    let call = Instruction::Call(
+        Reg(0),
+        Spanned::new(Value::Symbol(main, Vec::new()), span),
+        args,
+    );
+    let block = Block {
+        ops: vec![Spanned::new(call, span)],
+        link: BlockLink::None,
+        end: BlockEnd::Return(Spanned::new(Value::Int(0, Size::Bit32), span)),
+    };
+    let mut blocks = HashMap::new();
+    blocks.insert(BlockId(0), block);
+    Function {
+        complete_span: span,
+        signature_span: f.signature_span,
+        parameters,
+        registers,
+        register_symbols: HashMap::new(),
+        deferred_init: HashSet::new(),
+        declared_uninit: HashMap::new(),
+        type_params: Vec::new(),
+        out_type: Type::Int(Signedness::Signed, Size::Bit32),
+        start_block: Some(BlockId(0)),
+        blocks,
+        inline: Inline::Auto,
+        calling_convention: CallingConvention::Stack,
+    }
+}
+
+/// Backends expect `main` to return exit code. If `main` returns
+/// `unit` instead, it is renamed and called from generated `main`
+/// that always returns 0.
+pub(crate) fn wrap_unit_main(program: &mut Program, ctx: &mut CompileCtx) {
+    let main = program
+        .functions
+        .iter()
+        .find(|&(&sym, f)| match f.out_type {
+            Type::Unit => ctx.symbols.get_name(sym) == "main",
+            _ => false,
+        })
+        .map(|(&sym, _)| sym);
+    if let Some(main) = main {
+        let wrapper = generate_wrapper(main, &program.functions[&main]);
+        ctx.symbols.rename(main, "main::body");
+        let symbol = ctx.symbols.new_symbol("main");
+        program.functions.insert(symbol, wrapper);
+    }
+}
+
+/// Add an entry point for every test, named `test::NAME`, that calls
+/// the test and returns 0, so that tests can be run like `main`.
+pub(crate) fn add_test_entries(program: &mut Program, tests: &[String], ctx: &mut CompileCtx) {
+    let found = program
+        .functions
+        .iter()
+        .filter(|&(&sym, _)| tests.iter().any(|test| test == ctx.symbols.get_name(sym)))
+        .map(|(&sym, _)| sym)
+        .collect::<Vec<_>>();
+    for test in found {
+        let wrapper = generate_wrapper(test, &program.functions[&test]);
+        let name = format!("test::{}", ctx.symbols.get_name(test));
+        let symbol = ctx.symbols.new_symbol(name);
+        program.functions.insert(symbol, wrapper);
+    }
+}
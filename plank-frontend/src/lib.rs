@@ -8,6 +8,10 @@ mod ast {
     pub mod cfg;
 }
 mod symbols;
+mod suggest;
+mod lint_attributes;
+
+pub use lint_attributes::KNOWN_LINTS;
 mod resolve_symbols;
 mod type_param_check;
 mod wildcard_check;
@@ -17,11 +21,19 @@ mod build_cfg;
 mod dead_code;
 mod return_check;
 mod gen_constructors;
+mod gen_main;
 mod struct_layout;
 mod build_ir;
 mod assign_check;
+mod escape_check;
 mod struct_check;
 mod literal_size_check;
+mod match_check;
+mod unused_items;
+mod symbol_table;
+
+pub use ast::resolved::Symbol;
+pub use symbol_table::{Definition, SymbolKind, SymbolTable};
 
 mod builtins {
     use ast::resolved::Symbol;
@@ -29,43 +41,316 @@ mod builtins {
     pub const ALIGN_OF: Symbol = Symbol(1);
     pub const GETC: Symbol = Symbol(2);
     pub const PUTC: Symbol = Symbol(3);
+    pub const LEN: Symbol = Symbol(4);
+
+    pub const SIZE_OF_TYPE_PARAM: Symbol = Symbol(5);
+    pub const ALIGN_OF_TYPE_PARAM: Symbol = Symbol(6);
+    pub const PUTC_PARAM: Symbol = Symbol(7);
+    pub const LEN_TYPE_PARAM: Symbol = Symbol(8);
+    pub const LEN_PARAM: Symbol = Symbol(9);
+
+    pub const ALLOC: Symbol = Symbol(10);
+    pub const FREE: Symbol = Symbol(11);
+    pub const REALLOC: Symbol = Symbol(12);
+    pub const ALLOC_PARAM: Symbol = Symbol(13);
+    pub const FREE_PARAM: Symbol = Symbol(14);
+    pub const REALLOC_PTR_PARAM: Symbol = Symbol(15);
+    pub const REALLOC_SIZE_PARAM: Symbol = Symbol(16);
+
+    /// Called by failed bounds checks, programs can't call it directly.
+    pub const BOUNDS_FAIL: Symbol = Symbol(17);
+    pub const BOUNDS_FAIL_INDEX_PARAM: Symbol = Symbol(18);
+    pub const BOUNDS_FAIL_LEN_PARAM: Symbol = Symbol(19);
+
+    pub const PRINT_I32: Symbol = Symbol(20);
+    pub const PRINT_U32: Symbol = Symbol(21);
+    pub const PRINT_STR: Symbol = Symbol(22);
+    pub const PRINT_I32_PARAM: Symbol = Symbol(23);
+    pub const PRINT_U32_PARAM: Symbol = Symbol(24);
+    pub const PRINT_STR_PARAM: Symbol = Symbol(25);
+
+    /// Writes bytes to standard output for `print_str`,
+    /// programs can't call it directly.
+    pub const WRITE: Symbol = Symbol(26);
+    pub const WRITE_PTR_PARAM: Symbol = Symbol(27);
+    pub const WRITE_LEN_PARAM: Symbol = Symbol(28);
+
+    pub const READ_I32: Symbol = Symbol(29);
+    pub const READ_LINE: Symbol = Symbol(30);
+    pub const READ_LINE_PARAM: Symbol = Symbol(31);
+
+    /// Reads a line from standard input into memory for `read_line`,
+    /// programs can't call it directly.
+    pub const READ: Symbol = Symbol(32);
+    pub const READ_PTR_PARAM: Symbol = Symbol(33);
+    pub const READ_LEN_PARAM: Symbol = Symbol(34);
+
+    pub const STR_LEN: Symbol = Symbol(35);
+    pub const STR_EQ: Symbol = Symbol(36);
+    pub const STR_COPY: Symbol = Symbol(37);
+    pub const STR_FIND: Symbol = Symbol(38);
+    pub const STR_LEN_PARAM: Symbol = Symbol(39);
+    pub const STR_EQ_A_PARAM: Symbol = Symbol(40);
+    pub const STR_EQ_B_PARAM: Symbol = Symbol(41);
+    pub const STR_COPY_TO_PARAM: Symbol = Symbol(42);
+    pub const STR_COPY_FROM_PARAM: Symbol = Symbol(43);
+    pub const STR_FIND_STRING_PARAM: Symbol = Symbol(44);
+    pub const STR_FIND_PATTERN_PARAM: Symbol = Symbol(45);
+
+    /// Runtime parts of `str_eq`, `str_copy` and `str_find`, which get
+    /// pointers and lengths of both slices. Programs can't call them
+    /// directly, and they share parameters.
+    pub const RUNTIME_STR_EQ: Symbol = Symbol(46);
+    pub const RUNTIME_STR_COPY: Symbol = Symbol(47);
+    pub const RUNTIME_STR_FIND: Symbol = Symbol(48);
+    pub const RUNTIME_STR_A_PTR_PARAM: Symbol = Symbol(49);
+    pub const RUNTIME_STR_A_LEN_PARAM: Symbol = Symbol(50);
+    pub const RUNTIME_STR_B_PTR_PARAM: Symbol = Symbol(51);
+    pub const RUNTIME_STR_B_LEN_PARAM: Symbol = Symbol(52);
 
-    pub const SIZE_OF_TYPE_PARAM: Symbol = Symbol(4);
-    pub const ALIGN_OF_TYPE_PARAM: Symbol = Symbol(5);
-    pub const PUTC_PARAM: Symbol = Symbol(6);
+    pub const ASSERT: Symbol = Symbol(53);
+    pub const ASSERT_CONDITION_PARAM: Symbol = Symbol(54);
+    pub const ASSERT_MESSAGE_PARAM: Symbol = Symbol(55);
+
+    /// Called by failed asserts with the message and the line and column
+    /// of the assert, or zeros if it is not known. Programs can't call
+    /// it directly.
+    pub const ASSERT_FAIL: Symbol = Symbol(56);
+    pub const ASSERT_FAIL_PTR_PARAM: Symbol = Symbol(57);
+    pub const ASSERT_FAIL_LEN_PARAM: Symbol = Symbol(58);
+    pub const ASSERT_FAIL_LINE_PARAM: Symbol = Symbol(59);
+    pub const ASSERT_FAIL_COLUMN_PARAM: Symbol = Symbol(60);
+
+    pub const PANIC: Symbol = Symbol(61);
+    pub const PANIC_MESSAGE_PARAM: Symbol = Symbol(62);
+
+    /// Like `ASSERT_FAIL`, but for `panic`.
+    pub const PANIC_FAIL: Symbol = Symbol(63);
+    pub const PANIC_FAIL_PTR_PARAM: Symbol = Symbol(64);
+    pub const PANIC_FAIL_LEN_PARAM: Symbol = Symbol(65);
+    pub const PANIC_FAIL_LINE_PARAM: Symbol = Symbol(66);
+    pub const PANIC_FAIL_COLUMN_PARAM: Symbol = Symbol(67);
+
+    pub const RAND_SEED: Symbol = Symbol(68);
+    pub const RAND_SEED_PARAM: Symbol = Symbol(69);
+    pub const RAND_U32: Symbol = Symbol(70);
+
+    pub const TIME_MS: Symbol = Symbol(71);
 }
 
 use plank_errors::Reporter;
+use plank_errors::reporter::Level;
 use plank_syntax::ast::Program;
 use symbols::Symbols;
 
 
+/// Options that change how a program is compiled.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Wrap integer literals that don't fit into their type, and report a
+    /// warning instead of an error for them.
+    pub wrapping_literals: bool,
+    /// Implicitly convert ints to wider int types of the same signedness
+    /// in assignments, function arguments and returns.
+    pub implicit_widening: bool,
+    /// Machine to compile for. Decides the size and alignment of
+    /// types, and is recorded in generated IR for backends.
+    pub target: plank_ir::Target,
+    /// Check that indexes and slice ranges are within the length of
+    /// slices and strings, and stop the program if they are not.
+    pub bounds_checks: bool,
+    /// What arithmetic does when the result doesn't fit in its type,
+    /// instead of wrapping around.
+    pub overflow: Option<plank_ir::ir::Overflow>,
+    /// Levels of lints in the whole program, applied in order. Lint
+    /// attributes override them for single items.
+    pub lint_levels: Vec<(String, Level)>,
+    /// Report all warnings as errors.
+    pub deny_warnings: bool,
+    /// Add an entry point `test::NAME` for every `#[test]` function,
+    /// that calls it and returns 0 like `main` does.
+    pub tests: bool,
+}
+
 struct CompileCtx {
     symbols: Symbols,
     reporter: Reporter,
+    options: Options,
 }
 
 pub fn compile(program: &Program, reporter: Reporter) -> Result<plank_ir::Program, ()> {
+    compile_with_options(program, &Options::default(), reporter)
+}
+
+pub fn compile_with_options(
+    program: &Program,
+    options: &Options,
+    reporter: Reporter,
+) -> Result<plank_ir::Program, ()> {
     let mut ctx = CompileCtx {
         symbols: Symbols::new(),
         reporter,
+        options: options.clone(),
     };
 
+    lint_attributes::apply_lint_attributes(program, &mut ctx);
+    let tests = lint_attributes::test_functions(program);
     let mut resolved = resolve_symbols::resolve_program(program, &mut ctx);
     type_param_check::check_type_params(&mut resolved, &mut ctx);
     wildcard_check::check_for_wildcards(&resolved, &mut ctx);
     struct_check::check_program(&mut resolved, &mut ctx);
+    unused_items::check_program(&resolved, &tests, &mut ctx);
     let mut typed = type_check::type_check(&resolved, &mut ctx);
     literal_size_check::check_program(&mut typed, &mut ctx);
+    match_check::check_program(&typed, &mut ctx);
     cast_check::check_casts(&mut typed, &mut ctx);
     let mut cfg = build_cfg::build_cfg(&typed, &mut ctx);
     dead_code::remove_dead_code(&mut cfg, &mut ctx);
     assign_check::check_program(&cfg, &mut ctx);
+    escape_check::check_program(&cfg, &mut ctx);
     return_check::check_returns(&mut cfg, &mut ctx);
     gen_constructors::add_constructors(&mut cfg);
+    gen_main::wrap_unit_main(&mut cfg, &mut ctx);
+    if ctx.options.tests {
+        gen_main::add_test_entries(&mut cfg, &tests, &mut ctx);
+    }
     if ctx.reporter.has_errors() {
         Err(())
     } else {
-        Ok(build_ir::build_ir(&cfg, &ctx))
+        build_ir::build_ir(&cfg, &ctx)
+    }
+}
+
+/// Resolve names in the program, and format it like source code where
+/// every name is followed by the symbol that it refers to, like `x#80`.
+/// Returns `None` if names could not be resolved.
+pub fn format_resolved(
+    program: &Program,
+    options: &Options,
+    reporter: Reporter,
+) -> Option<String> {
+    let mut ctx = CompileCtx {
+        symbols: Symbols::new(),
+        reporter,
+        options: options.clone(),
+    };
+    lint_attributes::apply_lint_attributes(program, &mut ctx);
+    let resolved = resolve_symbols::resolve_program(program, &mut ctx);
+    if ctx.reporter.has_errors() {
+        None
+    } else {
+        Some(ast::resolved::printer::format_program(&resolved, &ctx))
     }
 }
+
+/// Resolve names in the program, and return what each of them refers
+/// to. The program is also type checked, so that field accesses can be
+/// resolved too. Unlike compiling, this never fails: names that could
+/// not be resolved are left out, so that editors can still use the rest.
+///
+/// # Examples
+///
+/// ```rust
+/// # extern crate plank_errors;
+/// # extern crate plank_frontend;
+/// # extern crate plank_syntax;
+/// use plank_errors::Reporter;
+/// use plank_frontend::{Options, SymbolKind};
+/// use plank_syntax::position::Position;
+///
+/// let source = "fn double(x: i32) -> i32 {\n    return x * 2;\n}";
+/// let reporter = Reporter::new();
+/// let tokens = plank_syntax::lex(source, reporter.clone());
+/// let program = plank_syntax::parse(tokens, reporter.clone());
+/// let table = plank_frontend::resolve_names(&program, &Options::default(), reporter);
+///
+/// let definition = table.definition_at(Position::new(1, 11)).unwrap();
+/// assert_eq!(definition.name, "x");
+/// assert_eq!(definition.kind, SymbolKind::Parameter);
+/// assert_eq!(definition.span.unwrap().start, Position::with_offset(0, 10, 10));
+///
+/// let references = table.references_at(Position::new(0, 10));
+/// assert_eq!(references.len(), 2);
+/// assert_eq!(references[1].start, Position::with_offset(1, 11, 38));
+/// ```
+///
+/// Every kind of name, found from both its definition and its uses:
+///
+/// ```rust
+/// # extern crate plank_errors;
+/// # extern crate plank_frontend;
+/// # extern crate plank_syntax;
+/// use plank_errors::Reporter;
+/// use plank_frontend::{Options, SymbolKind};
+/// use plank_syntax::position::Position;
+///
+/// let source = "\
+/// struct Pair<T> { first: T, second: T }
+/// union Bits { word: u32, byte: u8 }
+/// fn swap<T>(pair: Pair<T>) -> Pair<T> {
+///     let mut bits: Bits;
+///     bits.word = 0;
+///     return Pair(pair.second, pair.first);
+/// }
+/// fn main() {
+///     let pair = swap(Pair(1, 2));
+///     putc('a');
+/// }";
+/// let reporter = Reporter::new();
+/// let tokens = plank_syntax::lex(source, reporter.clone());
+/// let program = plank_syntax::parse(tokens, reporter.clone());
+/// let table = plank_frontend::resolve_names(&program, &Options::default(), reporter);
+/// let definition_at = |line, column| {
+///     let definition = table.definition_at(Position::new(line, column)).unwrap();
+///     let span = definition.span.map(|span| (span.start.line, span.start.column));
+///     (definition.name.as_str(), definition.kind, span)
+/// };
+///
+/// let pair = ("Pair", SymbolKind::Struct, Some((0, 7)));
+/// assert_eq!(definition_at(0, 7), pair);
+/// assert_eq!(definition_at(2, 17), pair);
+/// assert_eq!(definition_at(5, 11), pair);
+///
+/// let bits = ("Bits", SymbolKind::Union, Some((1, 6)));
+/// assert_eq!(definition_at(1, 6), bits);
+/// assert_eq!(definition_at(3, 18), bits);
+///
+/// let word = ("word", SymbolKind::Field, Some((1, 13)));
+/// assert_eq!(definition_at(1, 13), word);
+/// assert_eq!(definition_at(4, 9), word);
+/// let second = ("second", SymbolKind::Field, Some((0, 27)));
+/// assert_eq!(definition_at(5, 21), second);
+///
+/// // each generic item has its own type parameters
+/// let struct_param = ("T", SymbolKind::TypeParameter, Some((0, 12)));
+/// assert_eq!(definition_at(0, 24), struct_param);
+/// let fn_param = ("T", SymbolKind::TypeParameter, Some((2, 8)));
+/// assert_eq!(definition_at(2, 8), fn_param);
+/// assert_eq!(definition_at(2, 22), fn_param);
+/// assert_eq!(definition_at(2, 34), fn_param);
+///
+/// let swap = ("swap", SymbolKind::Function, Some((2, 3)));
+/// assert_eq!(definition_at(8, 15), swap);
+/// assert_eq!(definition_at(3, 12), ("bits", SymbolKind::Variable, Some((3, 12))));
+/// assert_eq!(definition_at(4, 4), ("bits", SymbolKind::Variable, Some((3, 12))));
+/// assert_eq!(definition_at(5, 16), ("pair", SymbolKind::Parameter, Some((2, 11))));
+/// assert_eq!(definition_at(8, 8), ("pair", SymbolKind::Variable, Some((8, 8))));
+///
+/// // builtins have no source, so they have no span
+/// assert_eq!(definition_at(9, 4), ("putc", SymbolKind::Builtin, None));
+/// ```
+pub fn resolve_names(program: &Program, options: &Options, reporter: Reporter) -> SymbolTable {
+    let mut ctx = CompileCtx {
+        symbols: Symbols::new(),
+        reporter,
+        options: options.clone(),
+    };
+    lint_attributes::apply_lint_attributes(program, &mut ctx);
+    let (mut resolved, mut table) = resolve_symbols::resolve_with_table(program, &mut ctx);
+    type_param_check::check_type_params(&mut resolved, &mut ctx);
+    wildcard_check::check_for_wildcards(&resolved, &mut ctx);
+    struct_check::check_program(&mut resolved, &mut ctx);
+    let typed = type_check::type_check(&resolved, &mut ctx);
+    table.add_field_uses(&typed);
+    table
+}
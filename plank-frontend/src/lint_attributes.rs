@@ -0,0 +1,179 @@
+use plank_errors::codes;
+use plank_errors::reporter::Level;
+use plank_syntax::ast::{Attribute, Function, FunctionType, Program, Type};
+use plank_syntax::position::{Span, Spanned};
+use CompileCtx;
+
+
+/// Lints that can be configured with `allow`, `warn` and `deny` attributes.
+pub const KNOWN_LINTS: &[&str] = &[
+    "assign_in_condition",
+    "dangling_pointer",
+    "dead_code",
+    "unknown_lints",
+    "unreachable_patterns",
+    "unused_items",
+];
+
+pub(crate) fn apply_lint_attributes(program: &Program, ctx: &mut CompileCtx) {
+    // levels from options apply to the whole program, attributes
+    // are scoped to their items so they take precedence anyway
+    for &(ref lint, level) in &ctx.options.lint_levels {
+        ctx.reporter.set_lint_level(lint.clone(), level);
+    }
+    ctx.reporter.set_deny_warnings(ctx.options.deny_warnings);
+    for struct_ in &program.structs {
+        apply_attributes(&struct_.attributes, struct_.complete_span, None, ctx);
+    }
+    for fn_ in &program.functions {
+        apply_attributes(&fn_.attributes, fn_.complete_span, Some(fn_), ctx);
+    }
+}
+
+fn apply_attributes(
+    attributes: &[Spanned<Attribute>],
+    item_span: Span,
+    function: Option<&Function>,
+    ctx: &mut CompileCtx,
+) {
+    // lint levels also apply to the attributes themselves,
+    // so that `#[allow(unknown_lints)]` would work
+    let scope = attributes
+        .iter()
+        .fold(item_span, |span, attr| span.merge(Spanned::span(attr)));
+    for attr in attributes {
+        let name_span = Spanned::span(&attr.name);
+        let level = match (attr.name.0).as_str() {
+            "allow" => Level::Allow,
+            "warn" => Level::Warn,
+            "deny" => Level::Deny,
+            "noreturn" => {
+                check_noreturn(attr, function, ctx);
+                continue;
+            }
+            "inline" => {
+                check_inline(attr, function, ctx);
+                continue;
+            }
+            "test" => {
+                check_test(attr, function, ctx);
+                continue;
+            }
+            name => {
+                let msg = format!("unknown attribute `{}`", name);
+                ctx.reporter
+                    .error(msg, name_span)
+                    .code(codes::INVALID_ATTRIBUTE)
+                    .span(name_span)
+                    .build();
+                continue;
+            }
+        };
+        if attr.args.is_empty() {
+            let msg = format!("`{}` attribute requires a list of lints", attr.name.0);
+            let span = Spanned::span(attr);
+            ctx.reporter
+                .error(msg, span)
+                .code(codes::INVALID_ATTRIBUTE)
+                .span_note("expected lints in parentheses", name_span)
+                .build();
+            continue;
+        }
+        for lint in &attr.args {
+            if KNOWN_LINTS.contains(&lint.0.as_str()) {
+                ctx.reporter.set_lint_level_in(scope, lint.0.as_str(), level);
+            } else {
+                let span = Spanned::span(lint);
+                let msg = format!("unknown lint: `{}`", lint.0);
+                ctx.reporter
+                    .lint("unknown_lints", msg, span)
+                    .code(codes::UNKNOWN_LINT)
+                    .span(span)
+                    .build();
+            }
+        }
+    }
+}
+
+/// Check that `#[noreturn]` is only put on functions that could never be
+/// checked to diverge, because they have no body.
+fn check_noreturn(attr: &Spanned<Attribute>, function: Option<&Function>, ctx: &mut CompileCtx) {
+    let span = Spanned::span(attr);
+    let error = if !attr.args.is_empty() {
+        "`noreturn` attribute does not take arguments"
+    } else {
+        match function {
+            None => "`noreturn` attribute can only be used on functions",
+            Some(f) if f.body.is_some() => "`noreturn` function cannot have a body",
+            Some(f) if !matches!(*f.return_type, Type::Unit) => {
+                "`noreturn` function cannot return a value"
+            }
+            Some(_) => return,
+        }
+    };
+    ctx.reporter
+        .error(error, span)
+        .code(codes::INVALID_ATTRIBUTE)
+        .span(span)
+        .build();
+}
+
+/// Check that `#[inline]` and `#[inline(never)]` are only put on functions
+/// that have a body to inline.
+fn check_inline(attr: &Spanned<Attribute>, function: Option<&Function>, ctx: &mut CompileCtx) {
+    let span = Spanned::span(attr);
+    let error = match attr.args.first() {
+        Some(arg) if attr.args.len() > 1 || arg.0 != "never" => {
+            "`inline` attribute only takes `never` as an argument"
+        }
+        _ => match function {
+            None => "`inline` attribute can only be used on functions",
+            Some(f) if f.body.is_none() => "`inline` function must have a body",
+            Some(_) => return,
+        },
+    };
+    ctx.reporter
+        .error(error, span)
+        .code(codes::INVALID_ATTRIBUTE)
+        .span(span)
+        .build();
+}
+
+/// Check that `#[test]` is only put on functions that the test runner
+/// can call, which take nothing and return nothing.
+fn check_test(attr: &Spanned<Attribute>, function: Option<&Function>, ctx: &mut CompileCtx) {
+    let span = Spanned::span(attr);
+    let error = if !attr.args.is_empty() {
+        "`test` attribute does not take arguments"
+    } else {
+        match function {
+            None => "`test` attribute can only be used on functions",
+            Some(f) if f.fn_type == FunctionType::Extern || f.body.is_none() => {
+                "test function must have a body"
+            }
+            Some(f) if !f.name.type_params.is_empty() => {
+                "test function cannot have type parameters"
+            }
+            Some(f) if !f.params.is_empty() => "test function cannot take parameters",
+            Some(f) if !matches!(*f.return_type, Type::Unit) => {
+                "test function cannot return a value"
+            }
+            Some(_) => return,
+        }
+    };
+    ctx.reporter
+        .error(error, span)
+        .code(codes::INVALID_ATTRIBUTE)
+        .span(span)
+        .build();
+}
+
+/// Names of functions that have a `#[test]` attribute.
+pub(crate) fn test_functions(program: &Program) -> Vec<String> {
+    program
+        .functions
+        .iter()
+        .filter(|f| f.attributes.iter().any(|attr| attr.name.0 == "test"))
+        .map(|f| f.name.name.0.clone())
+        .collect()
+}
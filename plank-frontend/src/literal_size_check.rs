@@ -1,5 +1,7 @@
+use plank_errors::codes;
 use plank_errors::position::Span;
-use ast::typed::{Program, Expr, TypedExpr, Statement, Literal, UnaryOp, Type, Signedness, Size, Number};
+use ast::typed::{Program, Expr, TypedExpr, Statement, Literal, UnaryOp, Type, Signedness, Size, Number,
+                 Pattern};
 use CompileCtx;
 
 
@@ -16,21 +18,13 @@ impl<'a> Context<'a> {
         let mut replace_with = None;
         match *expr.expr.as_mut() {
             Expr::Literal(Literal::Number(ref mut n)) => {
-                let val = if n.value > ::std::i64::MAX as u64 {
-                    ::std::i64::MAX
-                } else {
-                    n.value as i64
-                };
+                let val = i128::from(n.value);
                 n.value = check_literal(val, &expr.typ, expr.span, self.ctx) as u64;
             }
             Expr::Unary(op, ref mut value) => {
                 match (*op, value.expr.as_mut()) {
                     (UnaryOp::Minus, &mut Expr::Literal(Literal::Number(n))) => {
-                        let val = if n.value > ::std::i64::MAX as u64 {
-                            ::std::i64::MIN
-                        } else {
-                            -(n.value as i64)
-                        };
+                        let val = -i128::from(n.value);
                         let value = check_literal(val, &expr.typ, expr.span, self.ctx);
                         replace_with = Some(Expr::Literal(Literal::Number(Number {
                             value: value as u64,
@@ -51,7 +45,18 @@ impl<'a> Context<'a> {
                 }
             }
             Expr::Cast(ref mut e, _) |
+            Expr::Widen(ref mut e) |
             Expr::Field(ref mut e, _) => self.check_expr(e),
+            Expr::Index(ref mut e, ref mut index) => {
+                self.check_expr(e);
+                self.check_expr(index);
+            }
+            Expr::Slice(ref mut e, ref mut start, ref mut end) => {
+                self.check_expr(e);
+                self.check_expr(start);
+                self.check_expr(end);
+            }
+            Expr::Lambda(ref mut lambda) => self.check_statement(&mut lambda.body),
             Expr::Error |
             Expr::Name(_, _) |
             Expr::Literal(_) => {}
@@ -91,40 +96,89 @@ impl<'a> Context<'a> {
                 self.check_expr(expr);
                 self.check_statement(body);
             }
+            Statement::Match(ref mut value, ref mut arms) => {
+                self.check_expr(value);
+                for arm in arms {
+                    if let Pattern::Value(ref mut pattern) = *arm.pattern {
+                        self.check_expr(pattern);
+                    }
+                    self.check_statement(&mut arm.body);
+                }
+            }
         }
     }
 }
 
-fn check_literal(value: i64, typ: &Type, span: Span, ctx: &mut CompileCtx) -> i64 {
+fn check_literal(value: i128, typ: &Type, span: Span, ctx: &mut CompileCtx) -> i64 {
     use self::Signedness::*;
     use self::Size::*;
-    use std::{i8, i16, i32, i64, u8, u16, u32};
-    let (low, high) = match *typ {
-        Type::Int(Signed, Bit8) => (i8::MIN as i64, i8::MAX as i64),
-        Type::Int(Signed, Bit16) => (i16::MIN as i64, i16::MAX as i64),
-        Type::Int(Signed, Bit32) => (i32::MIN as i64, i32::MAX as i64),
-        Type::Int(Unsigned, Bit8) => (u8::MIN as i64, u8::MAX as i64),
-        Type::Int(Unsigned, Bit16) => (u16::MIN as i64, u16::MAX as i64),
-        Type::Int(Unsigned, Bit32) => (u32::MIN as i64, u32::MAX as i64),
-        _ => (i64::MIN, i64::MAX),
+    let (signedness, bits) = match *typ {
+        Type::Int(signedness, Bit8) => (signedness, 8),
+        Type::Int(signedness, Bit16) => (signedness, 16),
+        Type::Int(signedness, Bit32) => (signedness, 32),
+        // type is a `Numeric` type parameter, so
+        // literal has to fit into every int type
+        Type::Concrete(_, _) => {
+            let high = i128::from(i8::MAX);
+            return if check_range(value, 0, high, None, span, ctx) { value as i64 } else { 0 };
+        }
+        _ => return value as i64,
+    };
+    let (low, high) = match signedness {
+        Signed => (-(1 << (bits - 1)), (1 << (bits - 1)) - 1),
+        Unsigned => (0, (1 << bits) - 1),
     };
-    if value < low {
-        let msg = format!("should not be below {}", low);
-        ctx.reporter
-            .error("int literal is out of bounds", span)
-            .span_note(msg, span)
-            .build();
-        0
+    let wrapped = if ctx.options.wrapping_literals {
+        let value = value & ((1 << bits) - 1);
+        if signedness == Signed && value > high {
+            Some(value - (1 << bits))
+        } else {
+            Some(value)
+        }
+    } else {
+        None
+    };
+    if check_range(value, low, high, wrapped, span, ctx) {
+        value as i64
+    } else {
+        wrapped.unwrap_or(0) as i64
+    }
+}
+
+// reports literal that is not in `low..=high`, returns if it was in range
+fn check_range(
+    value: i128,
+    low: i128,
+    high: i128,
+    wrapped: Option<i128>,
+    span: Span,
+    ctx: &mut CompileCtx,
+) -> bool {
+    let msg = if value < low {
+        format!("should not be below {}", low)
     } else if value > high {
-        let msg = format!("should not be above {}", high);
-        ctx.reporter
-            .error("int literal is out of bounds", span)
-            .span_note(msg, span)
-            .build();
-        0
+        format!("should not be above {}", high)
     } else {
-        value
+        return true;
+    };
+    match wrapped {
+        Some(wrapped) => {
+            let msg = format!("{}, so it wraps around to {}", msg, wrapped);
+            ctx.reporter
+                .warning("int literal is out of bounds", span)
+                .code(codes::LITERAL_OUT_OF_RANGE)
+                .span_note(msg, span)
+                .build();
+        }
+        None => {
+            ctx.reporter
+                .error("int literal is out of bounds", span)
+                .code(codes::LITERAL_OUT_OF_RANGE)
+                .span_note(msg, span)
+                .build();
+        }
     }
+    false
 }
 
 pub(crate) fn check_program(program: &mut Program, ctx: &mut CompileCtx) {
@@ -0,0 +1,270 @@
+use std::collections::BTreeMap;
+use plank_errors::codes;
+use plank_syntax::position::{Span, Spanned};
+use ast::typed::{Expr, Literal, MatchArm, Pattern, Program, Signedness, Size, Statement, Type,
+                 TypedExpr};
+use CompileCtx;
+
+
+/// How many missing values or ranges are listed before the rest are
+/// summarized as "and N more".
+const MAX_LISTED_MISSING: usize = 3;
+
+/// Values that can be matched, all mapped to `i64`. Bools are `0` and `1`.
+#[derive(Copy, Clone)]
+enum Domain {
+    Bool,
+    Int(i64, i64),
+    /// Values that can only be matched by `_`.
+    Opaque,
+}
+
+impl Domain {
+    fn of(typ: &Type) -> Option<Domain> {
+        use self::Signedness::*;
+        use self::Size::*;
+        let domain = match *typ {
+            Type::Bool => Domain::Bool,
+            Type::Int(Signed, Bit8) => Domain::Int(i8::MIN as i64, i8::MAX as i64),
+            Type::Int(Signed, Bit16) => Domain::Int(i16::MIN as i64, i16::MAX as i64),
+            Type::Int(Signed, Bit32) => Domain::Int(i32::MIN as i64, i32::MAX as i64),
+            Type::Int(Unsigned, Bit8) => Domain::Int(u8::MIN as i64, u8::MAX as i64),
+            Type::Int(Unsigned, Bit16) => Domain::Int(u16::MIN as i64, u16::MAX as i64),
+            Type::Int(Unsigned, Bit32) => Domain::Int(u32::MIN as i64, u32::MAX as i64),
+            Type::Error | Type::Var(_) => return None,
+            _ => Domain::Opaque,
+        };
+        Some(domain)
+    }
+
+    fn bounds(self) -> Option<(i64, i64)> {
+        match self {
+            Domain::Bool => Some((0, 1)),
+            Domain::Int(low, high) => Some((low, high)),
+            Domain::Opaque => None,
+        }
+    }
+
+    fn format_value(self, value: i64) -> String {
+        match self {
+            Domain::Bool => format!("`{}`", value != 0),
+            _ => format!("`{}`", value),
+        }
+    }
+
+    fn format_range(self, low: i64, high: i64) -> String {
+        if low == high {
+            self.format_value(low)
+        } else {
+            format!("`{}..={}`", low, high)
+        }
+    }
+}
+
+/// Patterns seen so far in a match, used to decide whether the next arm
+/// is useful.
+struct Coverage {
+    domain: Domain,
+    wildcard: Option<Span>,
+    values: BTreeMap<i64, Span>,
+}
+
+impl Coverage {
+    fn new(domain: Domain) -> Self {
+        Coverage {
+            domain,
+            wildcard: None,
+            values: BTreeMap::new(),
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        if self.wildcard.is_some() {
+            return true;
+        }
+        match self.domain.bounds() {
+            Some((low, high)) => self.values.len() as i64 == high - low + 1,
+            None => false,
+        }
+    }
+
+    /// Values and ranges of values that are not matched by any pattern.
+    fn missing(&self) -> Vec<String> {
+        let (low, high) = match self.domain.bounds() {
+            Some(bounds) => bounds,
+            None => return vec![String::from("`_`")],
+        };
+        let mut missing = Vec::new();
+        let mut next = low;
+        for &value in self.values.keys() {
+            if value > next {
+                missing.push(self.domain.format_range(next, value - 1));
+            }
+            next = value + 1;
+        }
+        if next <= high {
+            missing.push(self.domain.format_range(next, high));
+        }
+        missing
+    }
+}
+
+/// Value of a pattern, or `None` if the pattern did not type check.
+fn pattern_value(pattern: &TypedExpr, domain: Domain) -> Option<i64> {
+    match (domain, &*pattern.expr) {
+        (Domain::Bool, &Expr::Literal(Literal::Bool(value))) => Some(value as i64),
+        (Domain::Int(_, _), &Expr::Literal(Literal::Char(value))) => Some(value as i64),
+        // negative literals are stored in two's complement
+        (Domain::Int(_, _), &Expr::Literal(Literal::Number(n))) => Some(n.value as i64),
+        _ => None,
+    }
+}
+
+struct Context<'a> {
+    ctx: &'a mut CompileCtx,
+}
+
+impl<'a> Context<'a> {
+    fn new(ctx: &'a mut CompileCtx) -> Self {
+        Context { ctx }
+    }
+
+    fn check_match(&mut self, value: &TypedExpr, arms: &[MatchArm]) {
+        // type errors were already reported
+        let domain = match Domain::of(&value.typ) {
+            Some(domain) => domain,
+            None => return,
+        };
+        let mut coverage = Coverage::new(domain);
+        for arm in arms {
+            let span = Spanned::span(&arm.pattern);
+            let value = match *arm.pattern {
+                Pattern::Wildcard => None,
+                Pattern::Value(ref pattern) => match pattern_value(pattern, domain) {
+                    Some(value) => Some(value),
+                    None => return,
+                },
+            };
+            if coverage.is_complete() {
+                let cause = coverage
+                    .wildcard
+                    .map(|span| ("this pattern matches all values", span));
+                self.report_unreachable(span, cause);
+                continue;
+            }
+            match value {
+                None => coverage.wildcard = Some(span),
+                Some(value) => if let Some(&previous) = coverage.values.get(&value) {
+                    let cause = ("this pattern matches the same value", previous);
+                    self.report_unreachable(span, Some(cause));
+                } else {
+                    coverage.values.insert(value, span);
+                },
+            }
+        }
+        if !coverage.is_complete() {
+            let missing = coverage.missing();
+            let mut listed = missing
+                .iter()
+                .take(MAX_LISTED_MISSING)
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(", ");
+            if missing.len() > MAX_LISTED_MISSING {
+                listed += &format!(" and {} more", missing.len() - MAX_LISTED_MISSING);
+            }
+            let msg = format!("non-exhaustive match: {} not covered", listed);
+            self.ctx
+                .reporter
+                .error(msg, value.span)
+                .code(codes::NON_EXHAUSTIVE_MATCH)
+                .primary_label("not every value is matched by some arm", value.span)
+                .build();
+        }
+    }
+
+    fn report_unreachable(&mut self, span: Span, cause: Option<(&str, Span)>) {
+        let mut builder = self.ctx
+            .reporter
+            .lint("unreachable_patterns", "unreachable pattern", span)
+            .code(codes::UNREACHABLE_PATTERN)
+            .primary_label("earlier arms already match every value here", span);
+        if let Some((msg, cause_span)) = cause {
+            builder = builder.secondary_label(msg, cause_span);
+        }
+        builder.build();
+    }
+
+    fn check_statement(&mut self, stmt: &Statement) {
+        match *stmt {
+            Statement::Block(ref stmts) => for stmt in stmts {
+                self.check_statement(stmt);
+            },
+            Statement::Break |
+            Statement::Continue |
+            Statement::Error |
+            Statement::Let(_, _, _, None) => {}
+            Statement::Expr(ref expr) |
+            Statement::Let(_, _, _, Some(ref expr)) |
+            Statement::Return(ref expr) => self.check_expr(expr),
+            Statement::If(ref cond, ref then, ref else_) => {
+                self.check_expr(cond);
+                self.check_statement(then);
+                if let Some(ref stmt) = *else_ {
+                    self.check_statement(stmt);
+                }
+            }
+            Statement::Loop(ref stmt) => self.check_statement(stmt),
+            Statement::While(ref cond, ref body) => {
+                self.check_expr(cond);
+                self.check_statement(body);
+            }
+            Statement::Match(ref value, ref arms) => {
+                self.check_expr(value);
+                self.check_match(value, arms);
+                for arm in arms {
+                    self.check_statement(&arm.body);
+                }
+            }
+        }
+    }
+
+    fn check_expr(&mut self, expr: &TypedExpr) {
+        match *expr.expr {
+            Expr::Binary(ref a, _, ref b) | Expr::Index(ref a, ref b) => {
+                self.check_expr(a);
+                self.check_expr(b);
+            }
+            Expr::Call(ref f, ref params) => {
+                self.check_expr(f);
+                for param in params {
+                    self.check_expr(param);
+                }
+            }
+            Expr::Unary(_, ref e) |
+            Expr::Cast(ref e, _) |
+            Expr::Widen(ref e) |
+            Expr::Field(ref e, _) => {
+                self.check_expr(e);
+            }
+            Expr::Slice(ref e, ref start, ref end) => {
+                self.check_expr(e);
+                self.check_expr(start);
+                self.check_expr(end);
+            }
+            Expr::Lambda(ref lambda) => self.check_statement(&lambda.body),
+            Expr::Error | Expr::Name(_, _) | Expr::Literal(_) => {}
+        }
+    }
+}
+
+/// Check that every match covers all possible values, and warn about arms
+/// that can never be taken.
+pub(crate) fn check_program(program: &Program, ctx: &mut CompileCtx) {
+    let mut ctx = Context::new(ctx);
+    for f in &program.functions {
+        if let Some(ref body) = f.body {
+            ctx.check_statement(body);
+        }
+    }
+}
@@ -1,24 +1,88 @@
 use std::collections::{HashMap, HashSet};
 use std::collections::hash_map::Entry;
+use plank_errors::codes;
 use plank_syntax::ast as p;
 use plank_syntax::position::{Position, Span, Spanned};
 use ast::resolved::{self as r, Symbol};
+use suggest;
+use symbol_table::{SymbolKind, SymbolTable};
 use CompileCtx;
 
 
 pub(crate) fn resolve_program(program: &p::Program, ctx: &mut CompileCtx) -> r::Program {
+    resolve_with_table(program, ctx).0
+}
+
+/// Resolve the program, and also return what every name in it refers to.
+pub(crate) fn resolve_with_table(
+    program: &p::Program,
+    ctx: &mut CompileCtx,
+) -> (r::Program, SymbolTable) {
     let mut resolver = Resolver::new(ctx);
-    resolver.resolve_program(program)
+    let program = resolver.resolve_program(program);
+    resolver.add_named_param_uses(&program);
+    (program, resolver.table)
 }
 
+/// Bounds that type parameters of functions can have.
+const BOUNDS: &[(&str, r::Bound)] = &[("Numeric", r::Bound::Numeric)];
+
+/// Names that can be given to `extern`, suggested for unknown ones.
+const CALLING_CONVENTIONS: &[&str] = &["C", "stdcall", "plank"];
+
+/// Functions that programs cannot define themselves.
+const BUILTIN_NAMES: &[&str] = &[
+    "size_of",
+    "align_of",
+    "putc",
+    "getc",
+    "len",
+    "alloc",
+    "free",
+    "realloc",
+    "print_i32",
+    "print_u32",
+    "print_str",
+    "read_i32",
+    "read_line",
+    "str_len",
+    "str_eq",
+    "str_copy",
+    "str_find",
+    "assert",
+    "panic",
+    "rand_seed",
+    "rand_u32",
+    "time_ms",
+];
+
 struct Resolver<'a> {
     ctx: &'a mut CompileCtx,
     global_structs: HashMap<String, (Symbol, Span)>,
+    struct_type_params: HashMap<Symbol, usize>,
+    /// Unions, which don't have constructors.
+    unions: HashSet<Symbol>,
     global_functions: HashMap<String, Function>,
     possible_structs: HashSet<String>,
     possible_functions: HashSet<String>,
     type_vars: HashMap<String, Symbol>,
     scopes: Vec<HashMap<String, Symbol>>,
+    lambdas: Vec<LambdaScope>,
+    function_name: String,
+    lambda_count: u32,
+    table: SymbolTable,
+    /// Named call parameters, as the called function, index of the
+    /// parameter and span of its name. Parameters get their symbols
+    /// only when the function is resolved, so these are added to the
+    /// symbol table after the whole program.
+    named_params: Vec<(Symbol, usize, Span)>,
+}
+
+struct LambdaScope {
+    /// Number of scopes that were open when lambda started,
+    /// variables from them have to be captured.
+    outer_scopes: usize,
+    captures: Vec<(Symbol, Symbol)>,
 }
 
 impl<'a> Resolver<'a> {
@@ -26,17 +90,25 @@ impl<'a> Resolver<'a> {
         Resolver {
             ctx,
             global_structs: HashMap::new(),
+            struct_type_params: HashMap::new(),
+            unions: HashSet::new(),
             global_functions: HashMap::new(),
             possible_structs: HashSet::new(),
             possible_functions: HashSet::new(),
             type_vars: HashMap::new(),
             scopes: Vec::new(),
+            lambdas: Vec::new(),
+            function_name: String::new(),
+            lambda_count: 0,
+            table: SymbolTable::new(),
+            named_params: Vec::new(),
         }
     }
 
     fn resolve_program(&mut self, program: &p::Program) -> r::Program {
         self.collect_globals(program);
         self.add_builtins();
+        self.resolve_defaults(program);
 
         let structs = program
             .structs
@@ -57,6 +129,48 @@ impl<'a> Resolver<'a> {
         functions.push(make_builtin_align_of());
         functions.push(make_builtin_getc());
         functions.push(make_builtin_putc());
+        functions.push(make_builtin_len());
+        functions.push(make_builtin_alloc());
+        functions.push(make_builtin_free());
+        functions.push(make_builtin_realloc());
+        functions.push(make_builtin_bounds_fail());
+        functions.push(make_builtin_print_i32());
+        functions.push(make_builtin_print_u32());
+        functions.push(make_builtin_print_str());
+        functions.push(make_builtin_write());
+        functions.push(make_builtin_read_i32());
+        functions.push(make_builtin_read_line());
+        functions.push(make_builtin_read());
+        functions.push(make_builtin_str_len());
+        functions.push(make_builtin_str_eq());
+        functions.push(make_builtin_str_copy());
+        functions.push(make_builtin_str_find());
+        functions.push(make_runtime_str_builtin(::builtins::RUNTIME_STR_EQ, r::Type::Bool));
+        functions.push(make_runtime_str_builtin(::builtins::RUNTIME_STR_COPY, r::Type::U32));
+        functions.push(make_runtime_str_builtin(::builtins::RUNTIME_STR_FIND, r::Type::I32));
+        functions.push(make_builtin_assert());
+        functions.push(make_builtin_fail(
+            ::builtins::ASSERT_FAIL,
+            [
+                ::builtins::ASSERT_FAIL_PTR_PARAM,
+                ::builtins::ASSERT_FAIL_LEN_PARAM,
+                ::builtins::ASSERT_FAIL_LINE_PARAM,
+                ::builtins::ASSERT_FAIL_COLUMN_PARAM,
+            ],
+        ));
+        functions.push(make_builtin_panic());
+        functions.push(make_builtin_rand_seed());
+        functions.push(make_builtin_rand_u32());
+        functions.push(make_builtin_time_ms());
+        functions.push(make_builtin_fail(
+            ::builtins::PANIC_FAIL,
+            [
+                ::builtins::PANIC_FAIL_PTR_PARAM,
+                ::builtins::PANIC_FAIL_LEN_PARAM,
+                ::builtins::PANIC_FAIL_LINE_PARAM,
+                ::builtins::PANIC_FAIL_COLUMN_PARAM,
+            ],
+        ));
 
         r::Program { structs, functions }
     }
@@ -69,6 +183,16 @@ impl<'a> Resolver<'a> {
             // because otherwise we will get "struct defined multiple times"
             // AND "function defined multiple times" on same positions
             if self.add_struct(name, span).is_ok() {
+                let symbol = self.global_structs[name].0;
+                let type_params = struct_.name.type_params.len();
+                self.struct_type_params.insert(symbol, type_params);
+                let kind = if struct_.kind == p::StructKind::Union {
+                    self.unions.insert(symbol);
+                    SymbolKind::Union
+                } else {
+                    SymbolKind::Struct
+                };
+                self.table.define(symbol, name.as_str(), kind, Some(span));
                 let params = struct_.fields.iter().map(|f| f.name.0.clone());
                 self.add_function(name, span, params);
             }
@@ -91,7 +215,16 @@ impl<'a> Resolver<'a> {
         }
     }
 
-    fn add_struct(&mut self, name: &str, mut span: Span) -> Result<(), ()> {
+    fn add_struct(&mut self, name: &str, span: Span) -> Result<(), ()> {
+        // still add the struct, so that its definition can be resolved
+        if name == "str" {
+            self.ctx
+                .reporter
+                .error("`str` is a built-in type", span)
+                .code(codes::BUILTIN_REDEFINED)
+                .span(span)
+                .build();
+        }
         match self.global_structs.entry(name.into()) {
             Entry::Vacant(entry) => {
                 let symbol = self.ctx.symbols.new_symbol(name);
@@ -99,53 +232,29 @@ impl<'a> Resolver<'a> {
                 Ok(())
             }
             Entry::Occupied(entry) => {
-                let mut prev_span = entry.get().1;
-                // always have previous item going before current
-                if prev_span.start > span.start {
-                    ::std::mem::swap(&mut prev_span, &mut span);
-                }
+                let prev_span = entry.get().1;
                 let msg = format!("struct `{}` is defined multiple times", name);
-                let short_msg = format!("struct `{}` is defined here", name);
-                self.ctx
-                    .reporter
-                    .error(msg, span)
-                    .span_note("and again defined here", span)
-                    .span_note(short_msg, prev_span)
-                    .build();
+                self.report_duplicate(
+                    msg,
+                    codes::DUPLICATE_DEFINITION,
+                    name,
+                    ("struct", prev_span),
+                    ("struct", span),
+                );
                 Err(())
             }
         }
     }
 
-    fn add_function<I>(&mut self, name: &str, mut span: Span, param_names: I)
+    fn add_function<I>(&mut self, name: &str, span: Span, param_names: I)
     where
         I: Iterator<Item = String>,
     {
-        if name == "size_of" {
+        if BUILTIN_NAMES.contains(&name) {
             self.ctx
                 .reporter
-                .error("`size_of` is a built-in function", span)
-                .span(span)
-                .build();
-            return;
-        } else if name == "align_of" {
-            self.ctx
-                .reporter
-                .error("`align_of` is a built-in function", span)
-                .span(span)
-                .build();
-            return;
-        } else if name == "putc" {
-            self.ctx
-                .reporter
-                .error("`putc` is a built-in function", span)
-                .span(span)
-                .build();
-            return;
-        } else if name == "getc" {
-            self.ctx
-                .reporter
-                .error("`getc` is a built-in function", span)
+                .error(format!("`{}` is a built-in function", name), span)
+                .code(codes::BUILTIN_REDEFINED)
                 .span(span)
                 .build();
             return;
@@ -154,38 +263,75 @@ impl<'a> Resolver<'a> {
             Entry::Vacant(entry) => {
                 // symbol might have already be defined for struct,
                 // so check for that
-                let symbol = self.global_structs
-                    .get(name)
-                    .map(|&(s, _)| s)
-                    .unwrap_or(self.ctx.symbols.new_symbol(name));
+                let symbol = match self.global_structs.get(name) {
+                    Some(&(symbol, _)) => symbol,
+                    None => {
+                        let symbol = self.ctx.symbols.new_symbol(name);
+                        self.table.define(symbol, name, SymbolKind::Function, Some(span));
+                        symbol
+                    }
+                };
                 entry.insert(Function {
                     name: symbol,
                     name_span: span,
                     param_names: param_names.collect(),
+                    defaults: Vec::new(),
                 });
             }
             Entry::Occupied(entry) => {
-                let mut prev_span = entry.get().name_span;
-                // always have previous item going before current
-                if prev_span.start > span.start {
-                    ::std::mem::swap(&mut prev_span, &mut span);
-                }
-                let msg = format!("function `{}` is defined multiple times", name);
-                let short_msg = format!("function `{}` is defined here", name);
-                self.ctx
-                    .reporter
-                    .error(msg, span)
-                    .span_note("and again defined here", span)
-                    .span_note(short_msg, prev_span)
-                    .build();
+                let prev_span = entry.get().name_span;
+                // previous definition might be a struct constructor
+                let prev_kind = match self.global_structs.get(name) {
+                    Some(&(_, struct_span)) if struct_span == prev_span => "struct",
+                    _ => "function",
+                };
+                let msg = if prev_kind == "function" {
+                    format!("function `{}` is defined multiple times", name)
+                } else {
+                    format!("`{}` is defined multiple times", name)
+                };
+                self.report_duplicate(
+                    msg,
+                    codes::DUPLICATE_DEFINITION,
+                    name,
+                    (prev_kind, prev_span),
+                    ("function", span),
+                );
             }
         }
     }
 
+    /// Report a name that is defined twice. Both definitions are given
+    /// as a kind of item and a span, and the error is reported at the
+    /// one that comes later in the source.
+    fn report_duplicate(
+        &mut self,
+        msg: String,
+        code: &'static str,
+        name: &str,
+        first: (&str, Span),
+        second: (&str, Span),
+    ) {
+        let (first, second) = if first.1.start > second.1.start {
+            (second, first)
+        } else {
+            (first, second)
+        };
+        let label = format!("{} `{}` redefined here", second.0, name);
+        let prev_label = format!("previous definition of {} `{}` here", first.0, name);
+        self.ctx
+            .reporter
+            .error(msg, second.1)
+            .code(code)
+            .primary_label(label, second.1)
+            .secondary_label(prev_label, first.1)
+            .build();
+    }
+
     fn add_builtins(&mut self) {
         let dummy_span = Span {
-            start: Position { line: 0, column: 0 },
-            end: Position { line: 0, column: 0 },
+            start: Position::new(0, 0),
+            end: Position::new(0, 0),
         };
         self.global_functions.insert(
             "size_of".into(),
@@ -193,6 +339,7 @@ impl<'a> Resolver<'a> {
                 name: ::builtins::SIZE_OF,
                 name_span: dummy_span,
                 param_names: Vec::new(),
+                defaults: Vec::new(),
             },
         );
         self.global_functions.insert(
@@ -201,6 +348,7 @@ impl<'a> Resolver<'a> {
                 name: ::builtins::ALIGN_OF,
                 name_span: dummy_span,
                 param_names: Vec::new(),
+                defaults: Vec::new(),
             },
         );
         self.global_functions.insert(
@@ -209,6 +357,7 @@ impl<'a> Resolver<'a> {
                 name: ::builtins::PUTC,
                 name_span: dummy_span,
                 param_names: vec!["ch".into()],
+                defaults: Vec::new(),
             },
         );
         self.global_functions.insert(
@@ -217,49 +366,316 @@ impl<'a> Resolver<'a> {
                 name: ::builtins::GETC,
                 name_span: dummy_span,
                 param_names: Vec::new(),
+                defaults: Vec::new(),
+            },
+        );
+        self.global_functions.insert(
+            "len".into(),
+            Function {
+                name: ::builtins::LEN,
+                name_span: dummy_span,
+                param_names: vec!["slice".into()],
+                defaults: Vec::new(),
+            },
+        );
+        self.global_functions.insert(
+            "alloc".into(),
+            Function {
+                name: ::builtins::ALLOC,
+                name_span: dummy_span,
+                param_names: vec!["size".into()],
+                defaults: Vec::new(),
+            },
+        );
+        self.global_functions.insert(
+            "free".into(),
+            Function {
+                name: ::builtins::FREE,
+                name_span: dummy_span,
+                param_names: vec!["ptr".into()],
+                defaults: Vec::new(),
+            },
+        );
+        self.global_functions.insert(
+            "realloc".into(),
+            Function {
+                name: ::builtins::REALLOC,
+                name_span: dummy_span,
+                param_names: vec!["ptr".into(), "size".into()],
+                defaults: Vec::new(),
+            },
+        );
+        self.global_functions.insert(
+            "print_i32".into(),
+            Function {
+                name: ::builtins::PRINT_I32,
+                name_span: dummy_span,
+                param_names: vec!["value".into()],
+                defaults: Vec::new(),
+            },
+        );
+        self.global_functions.insert(
+            "print_u32".into(),
+            Function {
+                name: ::builtins::PRINT_U32,
+                name_span: dummy_span,
+                param_names: vec!["value".into()],
+                defaults: Vec::new(),
+            },
+        );
+        self.global_functions.insert(
+            "print_str".into(),
+            Function {
+                name: ::builtins::PRINT_STR,
+                name_span: dummy_span,
+                param_names: vec!["string".into()],
+                defaults: Vec::new(),
+            },
+        );
+        self.global_functions.insert(
+            "read_i32".into(),
+            Function {
+                name: ::builtins::READ_I32,
+                name_span: dummy_span,
+                param_names: Vec::new(),
+                defaults: Vec::new(),
+            },
+        );
+        self.global_functions.insert(
+            "read_line".into(),
+            Function {
+                name: ::builtins::READ_LINE,
+                name_span: dummy_span,
+                param_names: vec!["buffer".into()],
+                defaults: Vec::new(),
+            },
+        );
+        self.global_functions.insert(
+            "str_len".into(),
+            Function {
+                name: ::builtins::STR_LEN,
+                name_span: dummy_span,
+                param_names: vec!["string".into()],
+                defaults: Vec::new(),
+            },
+        );
+        self.global_functions.insert(
+            "str_eq".into(),
+            Function {
+                name: ::builtins::STR_EQ,
+                name_span: dummy_span,
+                param_names: vec!["a".into(), "b".into()],
+                defaults: Vec::new(),
             },
         );
+        self.global_functions.insert(
+            "str_copy".into(),
+            Function {
+                name: ::builtins::STR_COPY,
+                name_span: dummy_span,
+                param_names: vec!["to".into(), "from".into()],
+                defaults: Vec::new(),
+            },
+        );
+        self.global_functions.insert(
+            "str_find".into(),
+            Function {
+                name: ::builtins::STR_FIND,
+                name_span: dummy_span,
+                param_names: vec!["string".into(), "pattern".into()],
+                defaults: Vec::new(),
+            },
+        );
+        self.global_functions.insert(
+            "assert".into(),
+            Function {
+                name: ::builtins::ASSERT,
+                name_span: dummy_span,
+                param_names: vec!["condition".into(), "message".into()],
+                defaults: Vec::new(),
+            },
+        );
+        self.global_functions.insert(
+            "panic".into(),
+            Function {
+                name: ::builtins::PANIC,
+                name_span: dummy_span,
+                param_names: vec!["message".into()],
+                defaults: Vec::new(),
+            },
+        );
+        self.global_functions.insert(
+            "rand_seed".into(),
+            Function {
+                name: ::builtins::RAND_SEED,
+                name_span: dummy_span,
+                param_names: vec!["seed".into()],
+                defaults: Vec::new(),
+            },
+        );
+        self.global_functions.insert(
+            "rand_u32".into(),
+            Function {
+                name: ::builtins::RAND_U32,
+                name_span: dummy_span,
+                param_names: Vec::new(),
+                defaults: Vec::new(),
+            },
+        );
+        self.global_functions.insert(
+            "time_ms".into(),
+            Function {
+                name: ::builtins::TIME_MS,
+                name_span: dummy_span,
+                param_names: Vec::new(),
+                defaults: Vec::new(),
+            },
+        );
+        for &name in BUILTIN_NAMES {
+            let symbol = self.global_functions[name].name;
+            self.table.define(symbol, name, SymbolKind::Builtin, None);
+        }
+    }
+
+    fn resolve_defaults(&mut self, program: &p::Program) {
+        // default values are resolved once, outside of any function, and
+        // then copied into every call that omits them
+        debug_assert!(self.scopes.is_empty() && self.type_vars.is_empty());
+        for fn_ in &program.functions {
+            let name = &fn_.name.name.0;
+            match self.global_functions.get(name) {
+                Some(f) if f.name_span == Spanned::span(&fn_.name.name) => {}
+                _ => continue,
+            }
+            let mut defaults = Vec::new();
+            let mut prev_default = None;
+            for param in &fn_.params {
+                match param.default {
+                    Some(ref expr) => {
+                        prev_default = Some(&param.name);
+                        defaults.push(Some(self.resolve_expr(expr)));
+                    }
+                    None => {
+                        if let Some(prev) = prev_default {
+                            let msg = format!(
+                                "parameter `{}` must have a default value",
+                                param.name.0,
+                            );
+                            let short_msg = format!("`{}` has a default value", prev.0);
+                            let span = Spanned::span(&param.name);
+                            self.ctx
+                                .reporter
+                                .error(msg, span)
+                                .code(codes::MISSING_DEFAULT_VALUE)
+                                .span(span)
+                                .span_note(short_msg, Spanned::span(prev))
+                                .build();
+                        }
+                        defaults.push(None);
+                    }
+                }
+            }
+            self.global_functions.get_mut(name).unwrap().defaults = defaults;
+        }
+    }
+
+    fn add_named_param_uses(&mut self, program: &r::Program) {
+        for &(callee, index, span) in &self.named_params {
+            let param = match program.structs.get(&callee) {
+                Some(struct_) => struct_.fields.get(index).map(|field| field.name),
+                None => program
+                    .functions
+                    .iter()
+                    .find(|f| *f.name.name == callee)
+                    .and_then(|f| f.params.get(index))
+                    .map(|param| param.name),
+            };
+            if let Some(param) = param {
+                self.table.add_use(Spanned::into_value(param), span);
+            }
+        }
     }
 
     fn resolve_struct(&mut self, struct_: &p::Struct) -> r::Struct {
-        let name = self.resolve_item_name(&struct_.name);
+        let name = self.resolve_item_name(&struct_.name, false);
         let fields = self.resolve_field_list(&struct_.fields);
         r::Struct {
             name,
+            kind: struct_.kind,
             fields,
             complete_span: struct_.complete_span,
         }
     }
 
-    fn resolve_item_name(&mut self, name: &p::ItemName) -> r::ItemName {
+    fn resolve_item_name(&mut self, name: &p::ItemName, allow_bounds: bool) -> r::ItemName {
         let symbol = self.global_functions[&name.name.0].name;
         let mut type_var_spans = HashMap::new();
         let mut type_params = Vec::new();
         for var in &name.type_params {
-            let symbol = if let Some(&span) = type_var_spans.get(&var.0) {
+            let symbol = if let Some(&prev_span) = type_var_spans.get(&var.0) {
                 let msg = format!("type parameter `{}` is listed multiple times", &var.0);
-                let short_msg = format!("`{}` is defined here", &var.0);
-                self.ctx
-                    .reporter
-                    .error(msg, Spanned::span(var))
-                    .span_note(short_msg, Spanned::span(var))
-                    .span_note("and here", span)
-                    .build();
+                self.report_duplicate(
+                    msg,
+                    codes::DUPLICATE_NAME,
+                    &var.0,
+                    ("type parameter", prev_span),
+                    ("type parameter", Spanned::span(var)),
+                );
                 self.ctx.symbols.new_symbol("?")
             } else {
                 type_var_spans.insert(&var.0, Spanned::span(var));
                 let symbol = self.ctx.symbols.new_symbol(var.0.clone());
+                let span = Some(Spanned::span(var));
+                self.table.define(symbol, var.0.clone(), SymbolKind::TypeParameter, span);
                 self.type_vars.insert(var.0.clone(), symbol);
                 symbol
             };
             type_params.push(Spanned::new(symbol, Spanned::span(var)));
         }
+        let bounds = name.bounds
+            .iter()
+            .map(|bound| match *bound {
+                Some(ref bound) if allow_bounds => self.resolve_bound(bound),
+                Some(ref bound) => {
+                    let span = Spanned::span(bound);
+                    self.ctx
+                        .reporter
+                        .error("bounds are not allowed on struct type parameters", span)
+                        .code(codes::INVALID_BOUND)
+                        .primary_label("only function type parameters can have bounds", span)
+                        .build();
+                    None
+                }
+                None => None,
+            })
+            .collect();
         r::ItemName {
             name: Spanned::new(symbol, Spanned::span(&name.name)),
             type_params,
+            bounds,
         }
     }
 
+    fn resolve_bound(&mut self, bound: &Spanned<p::Ident>) -> Option<Spanned<r::Bound>> {
+        let span = Spanned::span(bound);
+        if let Some(&(_, resolved)) = BOUNDS.iter().find(|&&(name, _)| name == bound.0) {
+            return Some(Spanned::new(resolved, span));
+        }
+        let msg = format!("unknown bound `{}`", bound.0);
+        let candidates = BOUNDS.iter().map(|&(name, _)| name);
+        let note = match suggest::find_similar(&bound.0, candidates) {
+            Some(similar) => format!("help: did you mean `{}`?", similar),
+            None => "help: the only bound is `Numeric`".into(),
+        };
+        self.ctx
+            .reporter
+            .error(msg, span)
+            .code(codes::INVALID_BOUND)
+            .span_note(note, span)
+            .build();
+        None
+    }
+
     fn resolve_param_list(&mut self, params: &[p::FnParam]) -> Vec<r::FnParam> {
         let mut result_params = Vec::new();
         let mut param_spans = HashMap::new();
@@ -268,22 +684,24 @@ impl<'a> Resolver<'a> {
             let span = Spanned::span(&param.name);
             if let Some(&prev_span) = param_spans.get(name) {
                 let msg = format!("parameter `{}` is listed multiple times", name);
-                let short_msg = format!("`{}` is defined here", name);
-                self.ctx
-                    .reporter
-                    .error(msg, span)
-                    .span_note("and again here", span)
-                    .span_note(short_msg, prev_span)
-                    .build();
+                self.report_duplicate(
+                    msg,
+                    codes::DUPLICATE_NAME,
+                    name,
+                    ("parameter", prev_span),
+                    ("parameter", span),
+                );
             } else {
                 param_spans.insert(name, span);
             }
             let symbol = self.ctx.symbols.new_symbol(name.clone());
+            self.table.define(symbol, name.clone(), SymbolKind::Parameter, Some(span));
             let param_type = self.resolve_type(&param.typ);
             let param = r::FnParam {
                 mutability: param.mutability,
                 name: Spanned::new(symbol, span),
                 typ: param_type,
+                default: None,
             };
             result_params.push(param);
         }
@@ -298,17 +716,18 @@ impl<'a> Resolver<'a> {
             let span = Spanned::span(&field.name);
             if let Some(&prev_span) = field_spans.get(name) {
                 let msg = format!("field `{}` is listed multiple times", name);
-                let short_msg = format!("`{}` is defined here", name);
-                self.ctx
-                    .reporter
-                    .error(msg, span)
-                    .span_note("and again here", span)
-                    .span_note(short_msg, prev_span)
-                    .build();
+                self.report_duplicate(
+                    msg,
+                    codes::DUPLICATE_NAME,
+                    name,
+                    ("field", prev_span),
+                    ("field", span),
+                );
             } else {
                 field_spans.insert(name, span);
             }
             let symbol = self.ctx.symbols.new_symbol(name.clone());
+            self.table.define(symbol, name.clone(), SymbolKind::Field, Some(span));
             let field_type = self.resolve_type(&field.typ);
             let field = r::Field {
                 name: Spanned::new(symbol, span),
@@ -336,6 +755,10 @@ impl<'a> Resolver<'a> {
                 let typ = self.resolve_type(typ);
                 r::Type::Pointer(mutability, Box::new(typ))
             }
+            p::Type::Slice(mutability, ref typ) => {
+                let typ = self.resolve_type(typ);
+                r::Type::Slice(mutability, Box::new(typ))
+            }
             p::Type::Function(ref params, ref out) => {
                 let params = params.iter().map(|typ| self.resolve_type(typ)).collect();
                 let out = self.resolve_type(out);
@@ -344,19 +767,42 @@ impl<'a> Resolver<'a> {
             p::Type::Concrete(ref name, ref params) => {
                 let params = params.iter().map(|typ| self.resolve_type(typ)).collect();
                 if let Some(&sym) = self.type_vars.get(&name.0) {
+                    self.table.add_use(sym, Spanned::span(name));
                     let name = Spanned::new(sym, Spanned::span(name));
                     r::Type::Concrete(name, params)
+                } else if name.0 == "str" {
+                    if !params.is_empty() {
+                        let span = Spanned::span(name);
+                        self.ctx
+                            .reporter
+                            .error("type `str` does not take type parameters", span)
+                            .code(codes::WRONG_TYPE_PARAM_COUNT)
+                            .span_note("did not expect type parameters", span)
+                            .build();
+                    }
+                    r::Type::Str
                 } else if let Some(sym) = self.global_structs.get(&name.0) {
+                    self.table.add_use(sym.0, Spanned::span(name));
                     let name = Spanned::new(sym.0, Spanned::span(name));
                     r::Type::Concrete(name, params)
                 } else if self.possible_structs.contains(&name.0) {
                     r::Type::Error
                 } else {
                     let msg = format!("unknown type `{}`", &name.0);
+                    let candidates = self.global_structs
+                        .keys()
+                        .chain(self.type_vars.keys())
+                        .map(String::as_str)
+                        .chain(Some("str"));
+                    let note = match suggest::find_similar(&name.0, candidates) {
+                        Some(similar) => format!("help: did you mean `{}`?", similar),
+                        None => "unknown type".into(),
+                    };
                     self.ctx
                         .reporter
                         .error(msg, Spanned::span(name))
-                        .span_note("unknown type", Spanned::span(name))
+                        .code(codes::UNKNOWN_TYPE)
+                        .span_note(note, Spanned::span(name))
                         .build();
                     r::Type::Error
                 }
@@ -366,11 +812,21 @@ impl<'a> Resolver<'a> {
     }
 
     fn resolve_function(&mut self, f: &p::Function) -> r::Function {
-        let name = self.resolve_item_name(&f.name);
-        let params = self.resolve_param_list(&f.params);
+        let name = self.resolve_item_name(&f.name, true);
+        let mut params = self.resolve_param_list(&f.params);
+        match self.global_functions.get(&f.name.name.0) {
+            Some(function) if function.name_span == Spanned::span(&f.name.name) => {
+                for (param, default) in params.iter_mut().zip(&function.defaults) {
+                    param.default = default.clone();
+                }
+            }
+            _ => {}
+        }
         let return_type = self.resolve_type(&f.return_type);
 
-        debug_assert!(self.scopes.is_empty());
+        debug_assert!(self.scopes.is_empty() && self.lambdas.is_empty());
+        self.function_name = f.name.name.0.clone();
+        self.lambda_count = 0;
         self.scopes.push(HashMap::new());
         for (name, sym) in f.params.iter().zip(params.iter()) {
             self.add_local(&name.name.0, Spanned::into_value(sym.name));
@@ -384,25 +840,30 @@ impl<'a> Resolver<'a> {
                 self.ctx
                     .reporter
                     .error("`main` cannot have type parameters", span)
+                    .code(codes::INVALID_MAIN)
                     .span(span)
                     .build();
             }
-            if !f.params.is_empty() {
+            if !f.params.is_empty() && !is_main_args(&f.params) {
                 self.ctx
                     .reporter
-                    .error("`main` cannot take parameters", span)
+                    .error("`main` must take no parameters, or `argc: i32` and `argv: **u8`", span)
+                    .code(codes::INVALID_MAIN)
                     .span(span)
                     .build();
             }
-            if let p::Type::I32 = *f.return_type {} else {
+            if !matches!(*f.return_type, p::Type::I32 | p::Type::Unit) {
                 self.ctx
                     .reporter
-                    .error("`main` must return `i32`", span)
+                    .error("`main` must return `i32` or `unit`", span)
+                    .code(codes::INVALID_MAIN)
                     .span(span)
                     .build();
             }
         }
 
+        let calling_convention = self.resolve_calling_convention(f, &params, &return_type);
+
         r::Function {
             complete_span: f.complete_span,
             name,
@@ -410,20 +871,124 @@ impl<'a> Resolver<'a> {
             return_type,
             body,
             fn_type: f.fn_type,
+            noreturn: f.attributes.iter().any(|attr| attr.name.0 == "noreturn"),
+            inline: inline_hint(&f.attributes),
+            calling_convention,
         }
     }
 
+    /// Convention named in `extern "..."`. Functions with a foreign
+    /// convention are only declared, and can only pass scalars.
+    fn resolve_calling_convention(
+        &mut self,
+        f: &p::Function,
+        params: &[r::FnParam],
+        return_type: &Spanned<r::Type>,
+    ) -> r::CallingConvention {
+        let name = match f.calling_convention {
+            Some(ref name) => name,
+            None => return r::CallingConvention::Stack,
+        };
+        let span = Spanned::span(name);
+        let convention = match r::CallingConvention::from_name(name) {
+            Some(convention) => convention,
+            None => {
+                let msg = format!("unknown calling convention `{}`", **name);
+                let note = match suggest::find_similar(name, CALLING_CONVENTIONS.iter().cloned()) {
+                    Some(similar) => format!("help: did you mean `{}`?", similar),
+                    None => "unknown calling convention".into(),
+                };
+                self.ctx
+                    .reporter
+                    .error(msg, span)
+                    .code(codes::UNKNOWN_CALLING_CONVENTION)
+                    .span_note(note, span)
+                    .build();
+                return r::CallingConvention::Stack;
+            }
+        };
+        if convention == r::CallingConvention::Stack {
+            return convention;
+        }
+        if f.body.is_some() {
+            let msg = format!("`extern \"{}\"` function cannot have a body", **name);
+            self.ctx
+                .reporter
+                .error(msg, span)
+                .code(codes::INVALID_FOREIGN_FUNCTION)
+                .span(span)
+                .build();
+        }
+        for param in params {
+            let help = match *param.typ {
+                r::Type::Bool |
+                r::Type::U8 |
+                r::Type::U16 |
+                r::Type::I32 |
+                r::Type::U32 |
+                r::Type::Pointer(_, _) |
+                r::Type::Error => continue,
+                r::Type::I8 | r::Type::I16 => {
+                    "help: narrow signed integers are not supported, use `i32`"
+                }
+                _ => "help: pass a pointer instead",
+            };
+            let msg = format!(
+                "`extern \"{}\"` function can only take integers, `bool` and pointers",
+                **name,
+            );
+            let type_span = Spanned::span(&param.typ);
+            self.ctx
+                .reporter
+                .error(msg, type_span)
+                .code(codes::INVALID_FOREIGN_FUNCTION)
+                .span_note(help, type_span)
+                .build();
+        }
+        match **return_type {
+            r::Type::Bool |
+            r::Type::I8 |
+            r::Type::U8 |
+            r::Type::I16 |
+            r::Type::U16 |
+            r::Type::I32 |
+            r::Type::U32 |
+            r::Type::Unit |
+            r::Type::Pointer(_, _) |
+            r::Type::Error => {}
+            _ => {
+                let msg = format!(
+                    "`extern \"{}\"` function can only return integers, `bool` and pointers",
+                    **name,
+                );
+                let type_span = Spanned::span(return_type);
+                self.ctx
+                    .reporter
+                    .error(msg, type_span)
+                    .code(codes::INVALID_FOREIGN_FUNCTION)
+                    .span_note("help: return it through a pointer instead", type_span)
+                    .build();
+            }
+        }
+        convention
+    }
+
     fn resolve_statement(&mut self, s: &Spanned<p::Statement>) -> Spanned<r::Statement> {
         let span = Spanned::span(s);
         let statement: r::Statement = match **s {
             p::Statement::Block(ref statements) => {
                 self.scopes.push(HashMap::new());
-                let statements = statements
-                    .iter()
-                    .map(|s| self.resolve_statement(s))
-                    .collect();
+                let mut resolved = Vec::new();
+                for statement in statements {
+                    if let p::Statement::LetPattern(ref pattern, ref value) = **statement {
+                        let span = Spanned::span(statement);
+                        self.resolve_let_pattern(pattern, value, span, &mut resolved);
+                    } else {
+                        resolved.push(self.resolve_statement(statement));
+                    }
+                }
                 self.scopes.pop().expect("missing scope");
-                r::Statement::Block(statements)
+                r::Statement::Block(resolved)
             }
             p::Statement::Break => r::Statement::Break,
             p::Statement::Continue => r::Statement::Continue,
@@ -444,14 +1009,40 @@ impl<'a> Resolver<'a> {
                     .unwrap_or_else(|| Spanned::new(r::Type::Wildcard, name_span));
                 let value = value.as_ref().map(|value| self.resolve_expr(value));
                 let symbol = self.ctx.symbols.new_symbol(name.0.clone());
+                self.table.define(symbol, name.0.clone(), SymbolKind::Variable, Some(name_span));
                 self.add_local(&name.0, symbol);
                 let symbol = Spanned::new(symbol, name_span);
                 r::Statement::Let(mutability, symbol, typ, value)
             }
+            p::Statement::LetPattern(ref pattern, ref value) => {
+                // a pattern that isn't directly inside a block (as a lone
+                // statement from `parse_statement`) gets a block of its own,
+                // nothing after it could use the bindings anyway
+                self.scopes.push(HashMap::new());
+                let mut resolved = Vec::new();
+                self.resolve_let_pattern(pattern, value, span, &mut resolved);
+                self.scopes.pop().expect("missing scope");
+                r::Statement::Block(resolved)
+            }
             p::Statement::Loop(ref statement) => {
                 let statement = self.resolve_statement(statement);
                 r::Statement::Loop(Box::new(statement))
             }
+            p::Statement::Match(ref value, ref arms) => {
+                let value = self.resolve_expr(value);
+                let arms = arms
+                    .iter()
+                    .map(|arm| {
+                        let pattern = Spanned::map_ref(&arm.pattern, |pattern| match *pattern {
+                            p::Pattern::Wildcard => r::Pattern::Wildcard,
+                            p::Pattern::Value(ref value) => r::Pattern::Value(self.resolve_expr(value)),
+                        });
+                        let body = self.resolve_statement(&arm.body);
+                        r::MatchArm { pattern, body }
+                    })
+                    .collect();
+                r::Statement::Match(value, arms)
+            }
             p::Statement::Return(ref expr) => {
                 let expr = self.resolve_expr(expr);
                 r::Statement::Return(expr)
@@ -466,6 +1057,59 @@ impl<'a> Resolver<'a> {
         Spanned::new(statement, span)
     }
 
+    /// Expands `let Foo { a, b: c } = value;` into
+    /// `let tmp: Foo<_> = value; let a = tmp.a; let c = tmp.b;`
+    fn resolve_let_pattern(
+        &mut self,
+        pattern: &p::StructPattern,
+        value: &Spanned<p::Expr>,
+        span: Span,
+        statements: &mut Vec<Spanned<r::Statement>>,
+    ) {
+        let name_span = Spanned::span(&pattern.name);
+        let value = self.resolve_expr(value);
+        let type_params = self.global_structs
+            .get(&pattern.name.0)
+            .map(|&(symbol, _)| self.struct_type_params[&symbol])
+            .unwrap_or(0);
+        let wildcards = (0..type_params)
+            .map(|_| Spanned::new(p::Type::Wildcard, name_span))
+            .collect();
+        let typ = p::Type::Concrete(pattern.name.clone(), wildcards);
+        let typ = self.resolve_type(&Spanned::new(typ, name_span));
+        let value_symbol = self.ctx.symbols.new_symbol(pattern.name.0.clone());
+        let stmt = r::Statement::Let(
+            r::Mutability::Const,
+            Spanned::new(value_symbol, name_span),
+            typ,
+            Some(value),
+        );
+        statements.push(Spanned::new(stmt, span));
+        let mut bindings = Vec::new();
+        for field in &pattern.fields {
+            let field_span = Spanned::span(&field.field);
+            let binding_span = Spanned::span(&field.binding);
+            let value = r::Expr::Name(Spanned::new(value_symbol, name_span), Vec::new());
+            let field_name = Spanned::new(field.field.0.clone(), field_span);
+            let access = r::Expr::Field(Box::new(Spanned::new(value, name_span)), field_name);
+            let symbol = self.ctx.symbols.new_symbol(field.binding.0.clone());
+            let binding = field.binding.0.clone();
+            self.table.define(symbol, binding, SymbolKind::Variable, Some(binding_span));
+            let stmt = r::Statement::Let(
+                field.mutability,
+                Spanned::new(symbol, binding_span),
+                Spanned::new(r::Type::Wildcard, binding_span),
+                Some(Spanned::new(access, field_span)),
+            );
+            statements.push(Spanned::new(stmt, span));
+            bindings.push((&field.binding.0, symbol));
+        }
+        // bindings come into scope only after the whole pattern
+        for (name, symbol) in bindings {
+            self.add_local(name, symbol);
+        }
+    }
+
     fn resolve_expr(&mut self, e: &Spanned<p::Expr>) -> Spanned<r::Expr> {
         let span = Spanned::span(e);
         let expr = match **e {
@@ -487,11 +1131,32 @@ impl<'a> Resolver<'a> {
                 let field = Spanned::new(field.0.clone(), Spanned::span(field));
                 r::Expr::Field(Box::new(expr), field)
             }
+            p::Expr::Index(ref expr, ref index) => {
+                let expr = self.resolve_expr(expr);
+                let index = self.resolve_expr(index);
+                r::Expr::Index(Box::new(expr), Box::new(index))
+            }
+            p::Expr::Slice(ref expr, ref start, ref end) => {
+                let expr = self.resolve_expr(expr);
+                let start = self.resolve_expr(start);
+                let end = self.resolve_expr(end);
+                r::Expr::Slice(Box::new(expr), Box::new(start), Box::new(end))
+            }
             p::Expr::Literal(ref lit) => r::Expr::Literal(lit.clone()),
             p::Expr::Name(ref name, ref params) => {
                 let params = params.iter().map(|t| self.resolve_type(t)).collect();
                 let name_span = Spanned::span(name);
                 match self.resolve_var(&name.0, name_span) {
+                    Some(symbol) if self.unions.contains(&symbol) => {
+                        let msg = format!("union `{}` does not have a constructor", name.0);
+                        self.ctx
+                            .reporter
+                            .error(msg, name_span)
+                            .code(codes::UNKNOWN_VALUE)
+                            .span_note("help: assign one of its fields instead", name_span)
+                            .build();
+                        r::Expr::Error
+                    }
                     Some(symbol) => {
                         let name = Spanned::new(symbol, name_span);
                         r::Expr::Name(name, params)
@@ -508,58 +1173,154 @@ impl<'a> Resolver<'a> {
                 let typ = self.resolve_type(typ);
                 r::Expr::Cast(Box::new(expr), typ)
             }
+            p::Expr::Lambda(ref params, ref return_type, ref body) => {
+                r::Expr::Lambda(Box::new(self.resolve_lambda(params, return_type, body)))
+            }
             p::Expr::Error => r::Expr::Error,
         };
         Spanned::new(expr, span)
     }
 
+    fn resolve_lambda(
+        &mut self,
+        params: &[p::FnParam],
+        return_type: &Spanned<p::Type>,
+        body: &Spanned<p::Statement>,
+    ) -> r::Lambda {
+        if self.scopes.is_empty() {
+            // default values are copied into every call site,
+            // so lambda would be lifted out multiple times
+            let span = Spanned::span(body);
+            self.ctx
+                .reporter
+                .error("lambdas cannot be used in default values", span)
+                .code(codes::INVALID_LAMBDA)
+                .span(span)
+                .build();
+        }
+        let name = format!("{}::lambda{}", self.function_name, self.lambda_count);
+        self.lambda_count += 1;
+        let name = self.ctx.symbols.new_symbol(name);
+        for param in params {
+            if let Some(ref default) = param.default {
+                let span = Spanned::span(default);
+                self.ctx
+                    .reporter
+                    .error("lambda parameters cannot have default values", span)
+                    .code(codes::INVALID_LAMBDA)
+                    .span(span)
+                    .build();
+            }
+        }
+        let resolved_params = self.resolve_param_list(params);
+        let return_type = self.resolve_type(return_type);
+
+        self.lambdas.push(LambdaScope {
+            outer_scopes: self.scopes.len(),
+            captures: Vec::new(),
+        });
+        self.scopes.push(HashMap::new());
+        for (param, resolved) in params.iter().zip(resolved_params.iter()) {
+            self.add_local(&param.name.0, Spanned::into_value(resolved.name));
+        }
+        let body = self.resolve_statement(body);
+        self.scopes.pop().expect("missing scope");
+        let lambda = self.lambdas.pop().expect("missing lambda scope");
+
+        r::Lambda {
+            name,
+            params: resolved_params,
+            return_type,
+            body,
+            captures: lambda.captures,
+        }
+    }
+
+    fn capture_var(&mut self, var: &str, mut symbol: Symbol, scope: usize) -> Symbol {
+        // every lambda between variable declaration and its use has to
+        // capture it, and each of them gets its own copy
+        for lambda in &mut self.lambdas {
+            if lambda.outer_scopes <= scope {
+                continue;
+            }
+            let existing = lambda
+                .captures
+                .iter()
+                .find(|&&(outer, _)| outer == symbol)
+                .map(|&(_, inner)| inner);
+            symbol = match existing {
+                Some(inner) => inner,
+                None => {
+                    let inner = self.ctx.symbols.new_symbol(var);
+                    lambda.captures.push((symbol, inner));
+                    inner
+                }
+            };
+        }
+        symbol
+    }
+
     fn resolve_call_params(
         &mut self,
         callee: &r::Expr,
         params: &[p::CallParam],
     ) -> Option<Vec<Spanned<r::Expr>>> {
         match params.get(0) {
-            None => Some(Vec::new()),
             Some(&p::CallParam::Named(_, _)) => self.resolve_named_params(callee, params),
-            Some(&p::CallParam::Unnamed(_)) => self.resolve_unnamed_params(params),
+            Some(&p::CallParam::Unnamed(_)) | None => self.resolve_unnamed_params(callee, params),
         }
     }
 
-    fn resolve_named_params(
-        &mut self,
-        callee: &r::Expr,
-        params: &[p::CallParam],
-    ) -> Option<Vec<Spanned<r::Expr>>> {
-        let name_and_pos = match *callee {
+    fn callee_function(&self, callee: &r::Expr) -> Option<(&str, &Function)> {
+        match *callee {
             r::Expr::Name(name, _) => {
                 let name = Spanned::into_value(name);
                 // TODO: fix this, this is horrible hack
                 let name_str = match self.ctx.symbols.get_name(name) {
-                    "@putc" => "putc",
-                    "@getc" => "getc",
+                    s if s.starts_with('@') => &s[1..],
                     s => s,
                 };
-                if let Some(f) = self.global_functions.get(name_str) {
-                    let positions = f.param_names
-                        .iter()
-                        .cloned()
-                        .enumerate()
-                        .map(|(index, name)| (name, index))
-                        .collect::<HashMap<_, _>>();
-                    Some((name_str.to_string(), positions))
-                } else {
-                    None
-                }
+                self.global_functions
+                    .get_key_value(name_str)
+                    .map(|(name, f)| (name.as_str(), f))
             }
             _ => None,
-        };
+        }
+    }
+
+    fn resolve_named_params(
+        &mut self,
+        callee: &r::Expr,
+        params: &[p::CallParam],
+    ) -> Option<Vec<Spanned<r::Expr>>> {
+        let name_and_pos = self.callee_function(callee).map(|(name, f)| {
+            let positions = f.param_names
+                .iter()
+                .cloned()
+                .enumerate()
+                .map(|(index, name)| (name, index))
+                .collect::<HashMap<_, _>>();
+            (name.to_string(), f.name, positions, f.defaults.clone())
+        });
 
-        if let Some((fn_name, positions)) = name_and_pos {
-            self.resolve_and_sort_params(fn_name, params, positions)
+        if let Some((fn_name, symbol, positions, defaults)) = name_and_pos {
+            for param in params {
+                if let p::CallParam::Named(ref ident, _) = *param {
+                    if let Some(&index) = positions.get(&ident.0) {
+                        self.named_params.push((symbol, index, Spanned::span(ident)));
+                    }
+                }
+            }
+            self.resolve_and_sort_params(fn_name, params, positions, defaults)
         } else {
             let span = param_list_span(params);
             let msg = "named parameters can only be used for global functions";
-            self.ctx.reporter.error(msg, span).span(span).build();
+            self.ctx
+                .reporter
+                .error(msg, span)
+                .code(codes::INVALID_CALL_PARAMS)
+                .span(span)
+                .build();
             self.resolve_remaining_params(params);
             None
         }
@@ -570,6 +1331,7 @@ impl<'a> Resolver<'a> {
         fn_name: String,
         params: &[p::CallParam],
         mut positions: HashMap<String, usize>,
+        mut defaults: Vec<Option<Spanned<r::Expr>>>,
     ) -> Option<Vec<Spanned<r::Expr>>> {
         let mut resolved = Vec::new();
         let mut iterator = params.iter();
@@ -586,7 +1348,12 @@ impl<'a> Resolver<'a> {
                                 ident.0,
                             );
                             let span = Spanned::span(ident);
-                            self.ctx.reporter.error(msg, span).span(span).build();
+                            self.ctx
+                                .reporter
+                                .error(msg, span)
+                                .code(codes::INVALID_CALL_PARAMS)
+                                .span(span)
+                                .build();
                             self.resolve_remaining_params(iterator.as_slice());
                             return None;
                         }
@@ -594,7 +1361,12 @@ impl<'a> Resolver<'a> {
                             // good name, but already used
                             let msg = format!("parameter `{}` is supplied twice", ident.0,);
                             let span = Spanned::span(ident);
-                            self.ctx.reporter.error(msg, span).span(span).build();
+                            self.ctx
+                                .reporter
+                                .error(msg, span)
+                                .code(codes::INVALID_CALL_PARAMS)
+                                .span(span)
+                                .build();
                             self.resolve_remaining_params(iterator.as_slice());
                             return None;
                         }
@@ -611,7 +1383,12 @@ impl<'a> Resolver<'a> {
                 p::CallParam::Unnamed(ref expr) => {
                     let span = param_list_span(params);
                     let msg = "cannot mix named and positional parameters";
-                    self.ctx.reporter.error(msg, span).span(span).build();
+                    self.ctx
+                        .reporter
+                        .error(msg, span)
+                        .code(codes::INVALID_CALL_PARAMS)
+                        .span(span)
+                        .build();
                     self.resolve_expr(expr);
                     self.resolve_remaining_params(iterator.as_slice());
                     return None;
@@ -620,16 +1397,32 @@ impl<'a> Resolver<'a> {
         }
         for (name, pos) in positions {
             if pos != ::std::usize::MAX {
+                if let Some(default) = defaults.get_mut(pos).and_then(Option::take) {
+                    while resolved.len() <= pos {
+                        resolved.push(None);
+                    }
+                    resolved[pos] = Some(default);
+                    continue;
+                }
                 let msg = format!("missing parameter `{}`", name);
                 let span = param_list_span(params);
-                self.ctx.reporter.error(msg, span).span(span).build();
+                self.ctx
+                    .reporter
+                    .error(msg, span)
+                    .code(codes::INVALID_CALL_PARAMS)
+                    .span(span)
+                    .build();
                 return None;
             }
         }
         Some(resolved.into_iter().map(Option::unwrap).collect())
     }
 
-    fn resolve_unnamed_params(&mut self, params: &[p::CallParam]) -> Option<Vec<Spanned<r::Expr>>> {
+    fn resolve_unnamed_params(
+        &mut self,
+        callee: &r::Expr,
+        params: &[p::CallParam],
+    ) -> Option<Vec<Spanned<r::Expr>>> {
         let mut resolved = Vec::new();
         let mut iterator = params.iter();
         while let Some(param) = iterator.next() {
@@ -637,7 +1430,12 @@ impl<'a> Resolver<'a> {
                 p::CallParam::Named(_, ref expr) => {
                     let span = param_list_span(params);
                     let msg = "cannot mix named and positional parameters";
-                    self.ctx.reporter.error(msg, span).span(span).build();
+                    self.ctx
+                        .reporter
+                        .error(msg, span)
+                        .code(codes::INVALID_CALL_PARAMS)
+                        .span(span)
+                        .build();
                     self.resolve_expr(expr);
                     self.resolve_remaining_params(iterator.as_slice());
                     return None;
@@ -647,6 +1445,13 @@ impl<'a> Resolver<'a> {
                 }
             }
         }
+        // fill in omitted trailing parameters, if all of them have defaults
+        if let Some((_, f)) = self.callee_function(callee) {
+            let missing = f.defaults.iter().skip(resolved.len());
+            if missing.clone().all(Option::is_some) {
+                resolved.extend(missing.cloned().map(Option::unwrap));
+            }
+        }
         Some(resolved)
     }
 
@@ -661,12 +1466,20 @@ impl<'a> Resolver<'a> {
     }
 
     fn resolve_var(&mut self, var: &str, span: Span) -> Option<Symbol> {
-        for scope in self.scopes.iter().rev() {
-            if let Some(&symbol) = scope.get(var) {
-                return Some(symbol);
-            }
+        let found = self.scopes
+            .iter()
+            .enumerate()
+            .rev()
+            .filter_map(|(index, scope)| scope.get(var).map(|&symbol| (index, symbol)))
+            .next();
+        if let Some((index, symbol)) = found {
+            // lambdas refer to their own copies of captured variables,
+            // but the name still refers to the original one
+            self.table.add_use(symbol, span);
+            return Some(self.capture_var(var, symbol, index));
         }
         if let Some(f) = self.global_functions.get(var) {
+            self.table.add_use(f.name, span);
             return Some(f.name);
         }
         if self.possible_functions.contains(var) {
@@ -675,7 +1488,21 @@ impl<'a> Resolver<'a> {
             return None;
         }
         let msg = format!("unknown value `{}`", var);
-        self.ctx.reporter.error(msg, span).span(span).build();
+        let candidates = self.scopes
+            .iter()
+            .flat_map(|scope| scope.keys())
+            .chain(self.global_functions.keys())
+            .map(String::as_str);
+        let builder = self.ctx
+            .reporter
+            .error(msg, span)
+            .code(codes::UNKNOWN_VALUE);
+        match suggest::find_similar(var, candidates) {
+            Some(similar) => builder
+                .span_note(format!("help: did you mean `{}`?", similar), span)
+                .build(),
+            None => builder.span(span).build(),
+        }
         None
     }
 
@@ -687,79 +1514,134 @@ impl<'a> Resolver<'a> {
 
 fn make_builtin_size_of() -> r::Function {
     let dummy_span = Span {
-        start: Position { line: 0, column: 0 },
-        end: Position { line: 0, column: 0 },
+        start: Position::new(0, 0),
+        end: Position::new(0, 0),
     };
     r::Function {
         complete_span: dummy_span,
         name: r::ItemName {
             name: Spanned::new(::builtins::SIZE_OF, dummy_span),
             type_params: vec![Spanned::new(::builtins::SIZE_OF_TYPE_PARAM, dummy_span)],
+            bounds: vec![None],
         },
         params: Vec::new(),
         return_type: Spanned::new(r::Type::U32, dummy_span),
         body: None,
         fn_type: r::FunctionType::Normal,
+        noreturn: false,
+        inline: r::Inline::Auto,
+        calling_convention: r::CallingConvention::Stack,
     }
 }
 
 fn make_builtin_align_of() -> r::Function {
     let dummy_span = Span {
-        start: Position { line: 0, column: 0 },
-        end: Position { line: 0, column: 0 },
+        start: Position::new(0, 0),
+        end: Position::new(0, 0),
     };
     r::Function {
         complete_span: dummy_span,
         name: r::ItemName {
             name: Spanned::new(::builtins::ALIGN_OF, dummy_span),
             type_params: vec![Spanned::new(::builtins::ALIGN_OF_TYPE_PARAM, dummy_span)],
+            bounds: vec![None],
         },
         params: Vec::new(),
         return_type: Spanned::new(r::Type::U32, dummy_span),
         body: None,
         fn_type: r::FunctionType::Normal,
+        noreturn: false,
+        inline: r::Inline::Auto,
+        calling_convention: r::CallingConvention::Stack,
     }
 }
 
 fn make_builtin_getc() -> r::Function {
     let dummy_span = Span {
-        start: Position { line: 0, column: 0 },
-        end: Position { line: 0, column: 0 },
+        start: Position::new(0, 0),
+        end: Position::new(0, 0),
     };
     r::Function {
         complete_span: dummy_span,
         name: r::ItemName {
             name: Spanned::new(::builtins::GETC, dummy_span),
             type_params: Vec::new(),
+            bounds: Vec::new(),
         },
         params: Vec::new(),
         return_type: Spanned::new(r::Type::I32, dummy_span),
         body: None,
         fn_type: r::FunctionType::Normal,
+        noreturn: false,
+        inline: r::Inline::Auto,
+        calling_convention: r::CallingConvention::Stack,
     }
 }
 
 fn make_builtin_putc() -> r::Function {
     let dummy_span = Span {
-        start: Position { line: 0, column: 0 },
-        end: Position { line: 0, column: 0 },
+        start: Position::new(0, 0),
+        end: Position::new(0, 0),
     };
     r::Function {
         complete_span: dummy_span,
         name: r::ItemName {
             name: Spanned::new(::builtins::PUTC, dummy_span),
             type_params: Vec::new(),
+            bounds: Vec::new(),
         },
         params: vec![
             r::FnParam {
                 mutability: r::Mutability::Const,
                 name: Spanned::new(::builtins::PUTC_PARAM, dummy_span),
                 typ: Spanned::new(r::Type::U8, dummy_span),
+                default: None,
             },
         ],
         return_type: Spanned::new(r::Type::Unit, dummy_span),
         body: None,
         fn_type: r::FunctionType::Normal,
+        noreturn: false,
+        inline: r::Inline::Auto,
+        calling_convention: r::CallingConvention::Stack,
+    }
+}
+
+fn make_builtin_len() -> r::Function {
+    let dummy_span = Span {
+        start: Position::new(0, 0),
+        end: Position::new(0, 0),
+    };
+    let elem_type = r::Type::Concrete(
+        Spanned::new(::builtins::LEN_TYPE_PARAM, dummy_span),
+        Vec::new(),
+    );
+    let slice_type = r::Type::Slice(
+        r::Mutability::Const,
+        Box::new(Spanned::new(elem_type, dummy_span)),
+    );
+    r::Function {
+        complete_span: dummy_span,
+        name: r::ItemName {
+            name: Spanned::new(::builtins::LEN, dummy_span),
+            type_params: vec![Spanned::new(::builtins::LEN_TYPE_PARAM, dummy_span)],
+            bounds: vec![None],
+        },
+        params: vec![
+            r::FnParam {
+                mutability: r::Mutability::Const,
+                name: Spanned::new(::builtins::LEN_PARAM, dummy_span),
+                typ: Spanned::new(slice_type, dummy_span),
+
+                default: None,
+            },
+        ],
+        return_type: Spanned::new(r::Type::U32, dummy_span),
+        body: None,
+        fn_type: r::FunctionType::Normal,
+        noreturn: false,
+        inline: r::Inline::Auto,
+        calling_convention: r::CallingConvention::Stack,
     }
 }
 
@@ -767,6 +1649,20 @@ struct Function {
     name: Symbol,
     name_span: Span,
     param_names: Vec<String>,
+    defaults: Vec<Option<Spanned<r::Expr>>>,
+}
+
+/// Malformed `inline` attributes are reported by `lint_attributes`.
+fn inline_hint(attributes: &[Spanned<p::Attribute>]) -> r::Inline {
+    let mut inline = r::Inline::Auto;
+    for attr in attributes.iter().filter(|attr| attr.name.0 == "inline") {
+        inline = if attr.args.iter().any(|arg| arg.0 == "never") {
+            r::Inline::Never
+        } else {
+            r::Inline::Always
+        };
+    }
+    inline
 }
 
 fn param_list_span(params: &[p::CallParam]) -> Span {
@@ -782,3 +1678,232 @@ fn param_list_span(params: &[p::CallParam]) -> Span {
         None => span,
     }
 }
+
+/// Whether parameters of `main` are `argc: i32` and `argv: **u8`.
+fn is_main_args(params: &[p::FnParam]) -> bool {
+    if params.len() != 2 || !matches!(*params[0].typ, p::Type::I32) {
+        return false;
+    }
+    match *params[1].typ {
+        p::Type::Pointer(p::Mutability::Const, ref inner) => matches!(
+            ***inner,
+            p::Type::Pointer(p::Mutability::Const, ref byte) if matches!(***byte, p::Type::U8)
+        ),
+        _ => false,
+    }
+}
+
+/// Builtin without type parameters, implemented by backends.
+fn make_runtime_builtin(
+    name: Symbol,
+    params: Vec<(Symbol, r::Type)>,
+    return_type: r::Type,
+) -> r::Function {
+    let dummy_span = Span {
+        start: Position::new(0, 0),
+        end: Position::new(0, 0),
+    };
+    let params = params
+        .into_iter()
+        .map(|(name, typ)| r::FnParam {
+            mutability: r::Mutability::Const,
+            name: Spanned::new(name, dummy_span),
+            typ: Spanned::new(typ, dummy_span),
+            default: None,
+        })
+        .collect();
+    r::Function {
+        complete_span: dummy_span,
+        name: r::ItemName {
+            name: Spanned::new(name, dummy_span),
+            type_params: Vec::new(),
+            bounds: Vec::new(),
+        },
+        params,
+        return_type: Spanned::new(return_type, dummy_span),
+        body: None,
+        fn_type: r::FunctionType::Normal,
+        noreturn: false,
+        inline: r::Inline::Auto,
+        calling_convention: r::CallingConvention::Stack,
+    }
+}
+
+fn byte_pointer() -> r::Type {
+    let dummy_span = Span {
+        start: Position::new(0, 0),
+        end: Position::new(0, 0),
+    };
+    r::Type::Pointer(r::Mutability::Mut, Box::new(Spanned::new(r::Type::U8, dummy_span)))
+}
+
+fn make_builtin_alloc() -> r::Function {
+    let params = vec![(::builtins::ALLOC_PARAM, r::Type::U32)];
+    make_runtime_builtin(::builtins::ALLOC, params, byte_pointer())
+}
+
+fn make_builtin_free() -> r::Function {
+    let params = vec![(::builtins::FREE_PARAM, byte_pointer())];
+    make_runtime_builtin(::builtins::FREE, params, r::Type::Unit)
+}
+
+fn make_builtin_realloc() -> r::Function {
+    let params = vec![
+        (::builtins::REALLOC_PTR_PARAM, byte_pointer()),
+        (::builtins::REALLOC_SIZE_PARAM, r::Type::U32),
+    ];
+    make_runtime_builtin(::builtins::REALLOC, params, byte_pointer())
+}
+
+fn make_builtin_bounds_fail() -> r::Function {
+    let params = vec![
+        (::builtins::BOUNDS_FAIL_INDEX_PARAM, r::Type::U32),
+        (::builtins::BOUNDS_FAIL_LEN_PARAM, r::Type::U32),
+    ];
+    let mut function = make_runtime_builtin(::builtins::BOUNDS_FAIL, params, r::Type::Unit);
+    function.noreturn = true;
+    function
+}
+
+fn make_builtin_print_i32() -> r::Function {
+    let params = vec![(::builtins::PRINT_I32_PARAM, r::Type::I32)];
+    make_runtime_builtin(::builtins::PRINT_I32, params, r::Type::Unit)
+}
+
+fn make_builtin_print_u32() -> r::Function {
+    let params = vec![(::builtins::PRINT_U32_PARAM, r::Type::U32)];
+    make_runtime_builtin(::builtins::PRINT_U32, params, r::Type::Unit)
+}
+
+/// `print_str` is implemented with `@write` by the frontend.
+fn make_builtin_print_str() -> r::Function {
+    let params = vec![(::builtins::PRINT_STR_PARAM, byte_slice(r::Mutability::Const))];
+    make_runtime_builtin(::builtins::PRINT_STR, params, r::Type::Unit)
+}
+
+fn make_builtin_write() -> r::Function {
+    let dummy_span = Span {
+        start: Position::new(0, 0),
+        end: Position::new(0, 0),
+    };
+    let byte = Box::new(Spanned::new(r::Type::U8, dummy_span));
+    let params = vec![
+        (::builtins::WRITE_PTR_PARAM, r::Type::Pointer(r::Mutability::Const, byte)),
+        (::builtins::WRITE_LEN_PARAM, r::Type::U32),
+    ];
+    make_runtime_builtin(::builtins::WRITE, params, r::Type::Unit)
+}
+
+fn make_builtin_read_i32() -> r::Function {
+    make_runtime_builtin(::builtins::READ_I32, Vec::new(), r::Type::I32)
+}
+
+/// `read_line` is implemented with `@read_line` by the frontend.
+fn make_builtin_read_line() -> r::Function {
+    let params = vec![(::builtins::READ_LINE_PARAM, byte_slice(r::Mutability::Mut))];
+    make_runtime_builtin(::builtins::READ_LINE, params, r::Type::I32)
+}
+
+fn make_builtin_read() -> r::Function {
+    let params = vec![
+        (::builtins::READ_PTR_PARAM, byte_pointer()),
+        (::builtins::READ_LEN_PARAM, r::Type::U32),
+    ];
+    make_runtime_builtin(::builtins::READ, params, r::Type::I32)
+}
+
+fn byte_slice(mutability: r::Mutability) -> r::Type {
+    let dummy_span = Span {
+        start: Position::new(0, 0),
+        end: Position::new(0, 0),
+    };
+    r::Type::Slice(mutability, Box::new(Spanned::new(r::Type::U8, dummy_span)))
+}
+
+fn make_builtin_str_len() -> r::Function {
+    let params = vec![(::builtins::STR_LEN_PARAM, byte_slice(r::Mutability::Const))];
+    make_runtime_builtin(::builtins::STR_LEN, params, r::Type::U32)
+}
+
+/// `str_eq`, `str_copy` and `str_find` are implemented by the frontend
+/// with runtime builtins of the same name.
+fn make_builtin_str_eq() -> r::Function {
+    let params = vec![
+        (::builtins::STR_EQ_A_PARAM, byte_slice(r::Mutability::Const)),
+        (::builtins::STR_EQ_B_PARAM, byte_slice(r::Mutability::Const)),
+    ];
+    make_runtime_builtin(::builtins::STR_EQ, params, r::Type::Bool)
+}
+
+fn make_builtin_str_copy() -> r::Function {
+    let params = vec![
+        (::builtins::STR_COPY_TO_PARAM, byte_slice(r::Mutability::Mut)),
+        (::builtins::STR_COPY_FROM_PARAM, byte_slice(r::Mutability::Const)),
+    ];
+    make_runtime_builtin(::builtins::STR_COPY, params, r::Type::U32)
+}
+
+fn make_builtin_str_find() -> r::Function {
+    let params = vec![
+        (::builtins::STR_FIND_STRING_PARAM, byte_slice(r::Mutability::Const)),
+        (::builtins::STR_FIND_PATTERN_PARAM, byte_slice(r::Mutability::Const)),
+    ];
+    make_runtime_builtin(::builtins::STR_FIND, params, r::Type::I32)
+}
+
+fn make_runtime_str_builtin(name: Symbol, return_type: r::Type) -> r::Function {
+    let params = vec![
+        (::builtins::RUNTIME_STR_A_PTR_PARAM, byte_pointer()),
+        (::builtins::RUNTIME_STR_A_LEN_PARAM, r::Type::U32),
+        (::builtins::RUNTIME_STR_B_PTR_PARAM, byte_pointer()),
+        (::builtins::RUNTIME_STR_B_LEN_PARAM, r::Type::U32),
+    ];
+    make_runtime_builtin(name, params, return_type)
+}
+
+fn make_builtin_assert() -> r::Function {
+    let params = vec![
+        (::builtins::ASSERT_CONDITION_PARAM, r::Type::Bool),
+        (::builtins::ASSERT_MESSAGE_PARAM, byte_slice(r::Mutability::Const)),
+    ];
+    make_runtime_builtin(::builtins::ASSERT, params, r::Type::Unit)
+}
+
+fn make_builtin_panic() -> r::Function {
+    let params = vec![(::builtins::PANIC_MESSAGE_PARAM, byte_slice(r::Mutability::Const))];
+    let mut function = make_runtime_builtin(::builtins::PANIC, params, r::Type::Unit);
+    function.noreturn = true;
+    function
+}
+
+fn make_builtin_rand_seed() -> r::Function {
+    let params = vec![(::builtins::RAND_SEED_PARAM, r::Type::U32)];
+    make_runtime_builtin(::builtins::RAND_SEED, params, r::Type::Unit)
+}
+
+fn make_builtin_rand_u32() -> r::Function {
+    make_runtime_builtin(::builtins::RAND_U32, Vec::new(), r::Type::U32)
+}
+
+fn make_builtin_time_ms() -> r::Function {
+    make_runtime_builtin(::builtins::TIME_MS, Vec::new(), r::Type::U32)
+}
+
+/// Runtime builtins that stop the program with a message, and the line
+/// and column where it stopped.
+fn make_builtin_fail(name: Symbol, [ptr, len, line, column]: [Symbol; 4]) -> r::Function {
+    let dummy_span = Span {
+        start: Position::new(0, 0),
+        end: Position::new(0, 0),
+    };
+    let byte = Box::new(Spanned::new(r::Type::U8, dummy_span));
+    let params = vec![
+        (ptr, r::Type::Pointer(r::Mutability::Const, byte)),
+        (len, r::Type::U32),
+        (line, r::Type::U32),
+        (column, r::Type::U32),
+    ];
+    let mut function = make_runtime_builtin(name, params, r::Type::Unit);
+    function.noreturn = true;
+    function
+}
@@ -1,5 +1,6 @@
-use std::collections::{HashSet, VecDeque};
-use plank_syntax::position::Spanned;
+use std::collections::{HashMap, VecDeque};
+use plank_errors::codes;
+use plank_syntax::position::{Span, Spanned};
 use ast::cfg::{Block, BlockEnd, Function, Instruction, Program, Type, Value};
 use CompileCtx;
 
@@ -13,48 +14,64 @@ fn has_error_statement(block: &Block) -> bool {
     false
 }
 
+/// The branch on the path to a block, as the span of branch
+/// condition and whether it was true.
+type Branch = (Span, bool);
+
+fn report_missing_return(f: &Function, last_branch: Option<Branch>, ctx: &mut CompileCtx) {
+    let end = f.complete_span.end;
+    let closing_brace = end.backwards(1).span_to(end);
+    let mut builder = ctx.reporter
+        .error("not all paths return a value", f.signature_span)
+        .code(codes::MISSING_RETURN)
+        .span(f.signature_span)
+        .secondary_label("function can reach its end here without returning", closing_brace);
+    if let Some((span, taken)) = last_branch {
+        let msg = format!("when this condition is {}", taken);
+        builder = builder.secondary_label(msg, span);
+    }
+    builder.build();
+}
+
 fn check_function(f: &mut Function, ctx: &mut CompileCtx) {
     let mut queue = VecDeque::new();
-    let mut visited = HashSet::new();
+    // last branch taken on the first path found to each block
+    let mut visited = HashMap::new();
     if let Some(start_block) = f.start_block {
-        queue.push_back(start_block);
+        queue.push_back((start_block, None));
     }
     let allow_no_return = match f.out_type {
         Type::Unit => true,
         _ => false,
     };
 
-    while let Some(block) = queue.pop_front() {
-        if visited.contains(&block) {
+    while let Some((id, last_branch)) = queue.pop_front() {
+        if visited.contains_key(&id) {
             continue;
         }
-        visited.insert(block);
-        let block = f.blocks.get_mut(&block).unwrap();
-        if has_error_statement(block) {
+        visited.insert(id, last_branch);
+        if has_error_statement(&f.blocks[&id]) {
             continue;
         }
-        match block.end {
+        match f.blocks[&id].end {
             BlockEnd::Error if allow_no_return => {
                 // TODO: this is not a very good span for this
                 let spanned = Spanned::new(Value::Unit, f.complete_span);
-                block.end = BlockEnd::Return(spanned);
+                f.blocks.get_mut(&id).unwrap().end = BlockEnd::Return(spanned);
             }
             BlockEnd::Error => {
-                let span = f.complete_span;
-                ctx.reporter
-                    .error("not all paths return a value", span)
-                    .span(span)
-                    .build();
+                report_missing_return(f, last_branch, ctx);
                 return;
             }
-            BlockEnd::Branch(_, a, b) => {
-                queue.push_back(a);
-                queue.push_back(b);
+            BlockEnd::Branch(ref cond, a, b) => {
+                let span = Spanned::span(cond);
+                queue.push_back((a, Some((span, true))));
+                queue.push_back((b, Some((span, false))));
             }
             BlockEnd::Jump(a) => {
-                queue.push_back(a);
+                queue.push_back((a, last_branch));
             }
-            BlockEnd::Return(_) => {}
+            BlockEnd::Return(_) | BlockEnd::Unreachable => {}
         }
     }
 }
@@ -1,10 +1,22 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+use plank_errors::codes;
+use plank_syntax::position::{Span, Spanned};
 use ast::resolved::{Program, Symbol, Type};
 use CompileCtx;
 
 
 type Pair = (Symbol, Symbol);
 
+/// Struct directly containing another struct by value.
+#[derive(Clone)]
+struct Edge {
+    to: Symbol,
+    span: Span,
+    /// Generic structs that `to` was passed to as a type parameter,
+    /// outermost first.
+    via: Vec<Symbol>,
+}
+
 struct Solver {
     node_index: HashMap<Pair, u32>,
     edges: HashMap<u32, HashSet<u32>>,
@@ -107,11 +119,48 @@ impl Solver {
         }
     }
 
-    fn is_recursive(&self, sym: Symbol) -> bool {
-        let node = self.concrete_node(sym, sym);
+    fn holds(&self, pair: Pair) -> bool {
+        let node = self.concrete_node(pair.0, pair.1);
         self.needed_visits[&node] == 0
     }
 
+    fn is_recursive(&self, sym: Symbol) -> bool {
+        self.holds((sym, sym))
+    }
+
+    /// Find structs that appear by value in `typ`, after substituting
+    /// type parameters.
+    fn find_edges(
+        &self,
+        program: &Program,
+        typ: &Spanned<Type>,
+        conditions: &mut Vec<Pair>,
+        via: &mut Vec<Symbol>,
+        edges: &mut Vec<Edge>,
+    ) {
+        if let Type::Concrete(sym, ref params) = **typ {
+            let sym = *sym;
+            if !program.structs.contains_key(&sym) {
+                return;
+            }
+            if conditions.iter().all(|&pair| self.holds(pair)) {
+                edges.push(Edge {
+                    to: sym,
+                    span: Spanned::span(typ),
+                    via: via.clone(),
+                });
+            }
+            let symbols = &program.structs[&sym].name.type_params;
+            via.push(sym);
+            for (typ, &p) in params.iter().zip(symbols.iter()) {
+                conditions.push((sym, *p));
+                self.find_edges(program, typ, conditions, via, edges);
+                conditions.pop();
+            }
+            via.pop();
+        }
+    }
+
     fn add_struct(&mut self, program: &Program, root: Symbol, typ: &Type, acc: &mut HashSet<Pair>) {
         match *typ {
             Type::Wildcard |
@@ -123,7 +172,9 @@ impl Solver {
             Type::U32 |
             Type::Bool |
             Type::Unit |
+            Type::Str |
             Type::Pointer(_, _) |
+            Type::Slice(_, _) |
             Type::Function(_, _) |
             Type::Error => {}
             Type::Concrete(sym, ref params) => {
@@ -164,19 +215,120 @@ pub(crate) fn check_program(program: &mut Program, ctx: &mut CompileCtx) {
         }
     }
     solver.solve();
-    for (&name, s) in &mut program.structs {
-        if solver.is_recursive(name) {
-            let msg = format!(
-                "struct `{}` is recursive",
-                ctx.symbols.get_name(name),
-            );
+    let mut recursive = program.structs
+        .keys()
+        .cloned()
+        .filter(|&name| solver.is_recursive(name))
+        .collect::<Vec<_>>();
+    if recursive.is_empty() {
+        return;
+    }
+    recursive.sort_by_key(|name| Spanned::span(&program.structs[name].name.name).start);
+    let edges = program.structs
+        .iter()
+        .map(|(&name, s)| {
+            let mut edges = Vec::new();
+            for var in &s.fields {
+                solver.find_edges(program, &var.typ, &mut Vec::new(), &mut Vec::new(), &mut edges);
+            }
+            (name, edges)
+        })
+        .collect::<HashMap<_, _>>();
+    for &name in &recursive {
+        report_cycle(name, find_cycle(name, &edges), program, ctx);
+    }
+    for name in recursive {
+        let s = program.structs.get_mut(&name).unwrap();
+        for var in &mut s.fields {
+            *var.typ = Type::Error;
+        }
+    }
+}
+
+/// Find the shortest chain of structs that leads from `start` back to itself.
+fn find_cycle(start: Symbol, edges: &HashMap<Symbol, Vec<Edge>>) -> Option<Vec<(Symbol, Edge)>> {
+    let mut previous = HashMap::new();
+    let mut queue = VecDeque::new();
+    queue.push_back(start);
+    while let Some(from) = queue.pop_front() {
+        for edge in &edges[&from] {
+            if previous.contains_key(&edge.to) {
+                continue;
+            }
+            previous.insert(edge.to, (from, edge.clone()));
+            if edge.to == start {
+                let mut cycle = Vec::new();
+                let mut current = start;
+                loop {
+                    let (from, ref edge) = previous[&current];
+                    cycle.push((from, edge.clone()));
+                    if from == start {
+                        break;
+                    }
+                    current = from;
+                }
+                cycle.reverse();
+                return Some(cycle);
+            }
+            queue.push_back(edge.to);
+        }
+    }
+    None
+}
+
+fn report_cycle(
+    name: Symbol,
+    cycle: Option<Vec<(Symbol, Edge)>>,
+    program: &Program,
+    ctx: &mut CompileCtx,
+) {
+    let name_span = Spanned::span(&program.structs[&name].name.name);
+    let cycle = match cycle {
+        Some(cycle) => cycle,
+        None => {
+            // edges are collected separately from the solver, so don't
+            // rely on them to find a chain for every recursive struct
+            let msg = format!("struct `{}` is recursive", ctx.symbols.get_name(name));
             ctx.reporter
-                .error(msg, s.complete_span)
-                .span(s.complete_span)
+                .error(msg, name_span)
+                .code(codes::RECURSIVE_STRUCT)
+                .primary_label("struct would have infinite size", name_span)
                 .build();
-            for var in &mut s.fields {
-                *var.typ = Type::Error;
-            }
+            return;
+        }
+    };
+    let chain = cycle
+        .iter()
+        .flat_map(|&(from, ref edge)| Some(from).into_iter().chain(edge.via.iter().cloned()))
+        .chain(Some(name))
+        .map(|sym| format!("`{}`", ctx.symbols.get_name(sym)))
+        .collect::<Vec<_>>()
+        .join(" -> ");
+    let msg = format!("struct `{}` is recursive: {}", ctx.symbols.get_name(name), chain);
+    let mut builder = ctx.reporter
+        .error(msg, name_span)
+        .code(codes::RECURSIVE_STRUCT)
+        .primary_label("struct would have infinite size", name_span);
+    for &(from, ref edge) in &cycle {
+        let mut label = format!(
+            "`{}` contains `{}` by value",
+            ctx.symbols.get_name(from),
+            ctx.symbols.get_name(edge.to),
+        );
+        if !edge.via.is_empty() {
+            let via = edge.via
+                .iter()
+                .map(|&sym| format!("`{}`", ctx.symbols.get_name(sym)))
+                .collect::<Vec<_>>()
+                .join(" and ");
+            label += &format!(" through {}", via);
         }
+        builder = builder.secondary_label(label, edge.span);
     }
+    let (_, ref last) = cycle[cycle.len() - 1];
+    let help = format!(
+        "help: use a pointer like `*{}` somewhere in the cycle to break it",
+        ctx.symbols.get_name(last.to),
+    );
+    builder.span_note(help, last.span).build();
 }
@@ -1,11 +1,10 @@
 use std::collections::HashMap;
-use ast::cfg::{Size, Symbol, Type};
-use ast::typed::Struct;
+use std::sync::Arc;
+use plank_ir::Target;
+use ast::cfg::{Mutability, Signedness, Size, Symbol, Type};
+use ast::typed::{Struct, StructKind};
 
 
-const POINTER_SIZE: u32 = ::plank_ir::ir::POINTER_SIZE;
-const FUNCTION_SIZE: u32 = ::plank_ir::ir::FUNCTION_SIZE;
-
 #[derive(Debug, Copy, Clone)]
 pub enum LayoutResult<T> {
     Ok(T),
@@ -32,11 +31,25 @@ impl<T> LayoutResult<T> {
 
 pub struct LayoutEngine<'a> {
     structs: &'a HashMap<Symbol, Struct>,
+    target: Target,
 }
 
 impl<'a> LayoutEngine<'a> {
-    pub fn new(structs: &'a HashMap<Symbol, Struct>) -> Self {
-        LayoutEngine { structs }
+    pub fn new(structs: &'a HashMap<Symbol, Struct>, target: Target) -> Self {
+        LayoutEngine { structs, target }
+    }
+
+    pub fn target(&self) -> &Target {
+        &self.target
+    }
+
+    /// Size of pointers, and also of a function without its environment.
+    pub fn pointer_size(&self) -> u32 {
+        self.target.pointer_size.in_bytes()
+    }
+
+    pub fn pointer_align(&self) -> u32 {
+        self.target.pointer_align()
     }
 
     #[allow(dead_code)]
@@ -50,16 +63,26 @@ impl<'a> LayoutEngine<'a> {
     }
 
     pub fn size_align(&self, ty: &Type) -> LayoutResult<(u32, u32)> {
+        let pointer = self.pointer_size();
+        let pointer_align = self.pointer_align();
+        let int = |size| LayoutResult::Ok((size, self.target.scalar_align(size)));
         match *ty {
             Type::Bool => LayoutResult::Ok((1, 1)),
             Type::Error => LayoutResult::Error,
-            Type::Pointer(_, _) => LayoutResult::Ok((POINTER_SIZE, POINTER_SIZE)),
-            Type::Function(_, _) => LayoutResult::Ok((FUNCTION_SIZE, FUNCTION_SIZE)),
-            Type::Int(_, Size::Bit8) => LayoutResult::Ok((1, 1)),
-            Type::Int(_, Size::Bit16) => LayoutResult::Ok((2, 2)),
-            Type::Int(_, Size::Bit32) => LayoutResult::Ok((4, 4)),
+            Type::Pointer(_, _) => LayoutResult::Ok((pointer, pointer_align)),
+            // slice is a pointer followed by `u32` length
+            Type::Slice(_, _) | Type::Str => {
+                let align = lcm(pointer_align, self.target.scalar_align(4));
+                let size = (pointer + 4).div_ceil(align) * align;
+                LayoutResult::Ok((size, align))
+            }
+            // function is followed by pointer to its environment
+            Type::Function(_, _) => LayoutResult::Ok((pointer * 2, pointer_align)),
+            Type::Int(_, Size::Bit8) => int(1),
+            Type::Int(_, Size::Bit16) => int(2),
+            Type::Int(_, Size::Bit32) => int(4),
             Type::Var(_) => LayoutResult::Error,
-            Type::Unit => LayoutResult::Ok((0, 1)),
+            Type::Unit | Type::Never => LayoutResult::Ok((0, 1)),
             Type::Concrete(sym, ref params) => {
                 let s = match self.structs.get(&sym) {
                     Some(s) => s,
@@ -71,13 +94,18 @@ impl<'a> LayoutEngine<'a> {
                     .cloned()
                     .zip(params.iter().cloned())
                     .collect();
+                let kind = s.kind;
                 s.fields
                     .iter()
                     .map(|field| field.typ.replace(&mapping))
                     .fold(LayoutResult::Ok((0, 1)), |a, b| {
                         match (a, self.size_align(&b)) {
                             (LayoutResult::Ok((s, a)), LayoutResult::Ok((s2, a2))) => {
-                                let s = (s + a2 - 1) / a2 * a2 + s2;
+                                // fields of a union all start at offset 0
+                                let s = match kind {
+                                    StructKind::Struct => (s + a2 - 1) / a2 * a2 + s2,
+                                    StructKind::Union => ::std::cmp::max(s, s2),
+                                };
                                 let a = lcm(a, a2);
                                 LayoutResult::Ok((s, a))
                             }
@@ -97,15 +125,46 @@ impl<'a> LayoutEngine<'a> {
         }
     }
 
+    pub fn get_struct(&self, name: Symbol) -> &Struct {
+        &self.structs[&name]
+    }
+
+    pub fn is_union(&self, ty: &Type) -> bool {
+        match *ty {
+            Type::Concrete(sym, _) => self.structs[&sym].kind == StructKind::Union,
+            _ => false,
+        }
+    }
+
     pub fn field_info(&self, ty: &Type, field: usize) -> (u32, Type) {
         match *ty {
             Type::Unit |
+            Type::Never |
             Type::Bool |
             Type::Error |
             Type::Pointer(_, _) |
-            Type::Function(_, _) |
             Type::Int(_, _) |
             Type::Var(_) => panic!("no fields on type"),
+            // neither function nor its environment have a type that could
+            // be expressed, so pretend that they are opaque pointers
+            Type::Function(_, _) => match field {
+                0 => (0, Type::Pointer(Mutability::Const, Arc::new(Type::Unit))),
+                1 => (self.pointer_size(), Type::Pointer(Mutability::Const, Arc::new(Type::Unit))),
+                _ => panic!("function has only two fields"),
+            },
+            Type::Slice(mutability, ref to) => match field {
+                0 => (0, Type::Pointer(mutability, to.clone())),
+                1 => (self.pointer_size(), Type::Int(Signedness::Unsigned, Size::Bit32)),
+                _ => panic!("slice has only two fields"),
+            },
+            Type::Str => match field {
+                0 => {
+                    let byte = Type::Int(Signedness::Unsigned, Size::Bit8);
+                    (0, Type::Pointer(Mutability::Const, Arc::new(byte)))
+                }
+                1 => (self.pointer_size(), Type::Int(Signedness::Unsigned, Size::Bit32)),
+                _ => panic!("str has only two fields"),
+            },
             Type::Concrete(sym, ref params) => {
                 let s = &self.structs[&sym];
                 debug_assert_eq!(params.len(), s.type_params.len());
@@ -115,6 +174,9 @@ impl<'a> LayoutEngine<'a> {
                     .zip(params.iter().cloned())
                     .collect();
                 debug_assert!(field < s.fields.len());
+                if s.kind == StructKind::Union {
+                    return (0, s.fields[field].typ.replace(&mapping));
+                }
                 let (size, last_size) = s.fields
                     .iter()
                     .take(field + 1)
@@ -125,7 +187,7 @@ impl<'a> LayoutEngine<'a> {
                         (s, s2)
                     });
                 let offset = size - last_size;
-                let typ = s.fields[field].typ.clone();
+                let typ = s.fields[field].typ.replace(&mapping);
                 (offset, typ)
             }
         }
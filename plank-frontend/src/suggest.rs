@@ -0,0 +1,35 @@
+/// Find the candidate that is most similar to `name`, if any of them is
+/// close enough to be a likely typo.
+pub(crate) fn find_similar<'a, I>(name: &str, candidates: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let max_distance = ::std::cmp::max(1, name.chars().count() / 3);
+    candidates
+        .into_iter()
+        .filter(|&candidate| candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|&(distance, _)| distance <= max_distance)
+        // candidates usually come from hash maps, so break ties
+        // by name to keep suggestions deterministic
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance between two strings, counted in chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..(b.len() + 1)).collect::<Vec<_>>();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = prev[j] + if ca == cb { 0 } else { 1 };
+            let insertion = current[j] + 1;
+            let deletion = prev[j + 1] + 1;
+            current[j + 1] = ::std::cmp::min(substitution, ::std::cmp::min(insertion, deletion));
+        }
+        ::std::mem::swap(&mut prev, &mut current);
+    }
+    prev[b.len()]
+}
@@ -0,0 +1,278 @@
+//! Results of name resolution, for tools like editors that need to know
+//! what every name in the source refers to.
+
+use std::collections::HashMap;
+use plank_syntax::position::{Position, Span, Spanned};
+use ast::resolved::Symbol;
+use ast::typed::{Expr, Pattern, Program, Statement, Type, TypedExpr};
+
+
+/// What kind of item a symbol names.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function,
+    Struct,
+    Union,
+    Field,
+    Parameter,
+    TypeParameter,
+    Variable,
+    /// Function that is built into the compiler.
+    Builtin,
+}
+
+/// Where a symbol is defined.
+#[derive(Debug, Clone)]
+pub struct Definition {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// Span of the name in its definition, `None` for builtins.
+    pub span: Option<Span>,
+}
+
+/// Definitions of symbols, and the symbol of every name that refers
+/// to one of them in the source.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    definitions: HashMap<Symbol, Definition>,
+    uses: HashMap<Span, Symbol>,
+}
+
+impl SymbolTable {
+    pub(crate) fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+
+    pub(crate) fn define<S: Into<String>>(
+        &mut self,
+        symbol: Symbol,
+        name: S,
+        kind: SymbolKind,
+        span: Option<Span>,
+    ) {
+        let definition = Definition {
+            name: name.into(),
+            kind,
+            span,
+        };
+        self.definitions.insert(symbol, definition);
+    }
+
+    pub(crate) fn add_use(&mut self, symbol: Symbol, span: Span) {
+        self.uses.insert(span, symbol);
+    }
+
+    /// Add uses of fields in a type checked program. Unlike other
+    /// names, a field can only be resolved once the type of the
+    /// value that it is accessed on is known.
+    pub(crate) fn add_field_uses(&mut self, program: &Program) {
+        let mut walker = FieldWalker {
+            table: self,
+            program,
+        };
+        for f in &program.functions {
+            if let Some(ref body) = f.body {
+                walker.walk_statement(body);
+            }
+        }
+    }
+
+    /// Definition of a symbol, `None` for symbols that the
+    /// compiler made up, like the ones of lambdas.
+    pub fn definition(&self, symbol: Symbol) -> Option<&Definition> {
+        self.definitions.get(&symbol)
+    }
+
+    /// Every name that refers to a symbol, ordered by position.
+    pub fn uses(&self) -> Vec<Spanned<Symbol>> {
+        let mut uses = self.uses
+            .iter()
+            .map(|(&span, &symbol)| Spanned::new(symbol, span))
+            .collect::<Vec<_>>();
+        uses.sort_by_key(|name| (line_column(Spanned::span(name).start), name.0));
+        uses
+    }
+
+    /// Definition of the symbol that a name refers to, given the span of
+    /// the name.
+    pub fn resolve(&self, span: Span) -> Option<&Definition> {
+        self.uses.get(&span).and_then(|&symbol| self.definition(symbol))
+    }
+
+    /// Symbol of the name at given position, which can be either a use of
+    /// the symbol or its definition. Only line and column of the position
+    /// are used.
+    pub fn symbol_at(&self, position: Position) -> Option<Spanned<Symbol>> {
+        let uses = self.uses.iter().map(|(&span, &symbol)| (span, symbol));
+        let definitions = self.definitions
+            .iter()
+            .filter_map(|(&symbol, definition)| definition.span.map(|span| (span, symbol)));
+        uses.chain(definitions)
+            .find(|&(span, _)| contains(span, position))
+            .map(|(span, symbol)| Spanned::new(symbol, span))
+    }
+
+    /// Definition of the symbol at given position, for going to the
+    /// definition of a name.
+    pub fn definition_at(&self, position: Position) -> Option<&Definition> {
+        self.symbol_at(position)
+            .and_then(|symbol| self.definition(Spanned::into_value(symbol)))
+    }
+
+    /// Spans of every name that refers to the same symbol as the name at
+    /// given position, including its definition, ordered by position.
+    /// Empty if there is no name at the position.
+    ///
+    /// # Examples
+    ///
+    /// Fields are referred to by field accesses, destructuring and named
+    /// parameters of the struct constructor, and parameters by named
+    /// parameters of calls:
+    ///
+    /// ```rust
+    /// # extern crate plank_errors;
+    /// # extern crate plank_frontend;
+    /// # extern crate plank_syntax;
+    /// use plank_errors::Reporter;
+    /// use plank_frontend::Options;
+    /// use plank_syntax::position::{Position, Span};
+    ///
+    /// let source = "\
+    /// struct Point { x: i32, y: i32 }
+    /// fn make(x: i32) -> Point { return Point(x: x, y: 0); }
+    /// fn main() {
+    ///     let mut p = make(x: 1);
+    ///     let q = &p;
+    ///     p.x = (*q).x + q.x;
+    ///     let Point { x } = p;
+    ///     p.y = x;
+    /// }";
+    /// let reporter = Reporter::new();
+    /// let tokens = plank_syntax::lex(source, reporter.clone());
+    /// let program = plank_syntax::parse(tokens, reporter.clone());
+    /// let table = plank_frontend::resolve_names(&program, &Options::default(), reporter);
+    /// let starts = |spans: Vec<Span>| {
+    ///     spans.iter().map(|span| (span.start.line, span.start.column)).collect::<Vec<_>>()
+    /// };
+    ///
+    /// // field `x`, from its definition
+    /// let field = vec![(0, 15), (1, 40), (5, 6), (5, 15), (5, 21), (6, 16)];
+    /// assert_eq!(starts(table.references_at(Position::new(0, 15))), field);
+    /// // from a field access on a pointer, which is dereferenced automatically
+    /// assert_eq!(starts(table.references_at(Position::new(5, 21))), field);
+    ///
+    /// // parameter `x` of `make`
+    /// let param = vec![(1, 8), (1, 43), (3, 21)];
+    /// assert_eq!(starts(table.references_at(Position::new(3, 21))), param);
+    ///
+    /// // with the shorthand `Point { x }`, the field and the variable share
+    /// // a span, which is found as the field, but the variable's uses
+    /// // still lead back to it
+    /// assert_eq!(starts(table.references_at(Position::new(6, 16))), field);
+    /// assert_eq!(starts(table.references_at(Position::new(7, 10))), vec![(6, 16), (7, 10)]);
+    /// ```
+    pub fn references_at(&self, position: Position) -> Vec<Span> {
+        let symbol = match self.symbol_at(position) {
+            Some(symbol) => Spanned::into_value(symbol),
+            None => return Vec::new(),
+        };
+        let definition = self.definition(symbol).and_then(|definition| definition.span);
+        let mut references = self.uses
+            .iter()
+            .filter(|&(_, &used)| used == symbol)
+            .map(|(&span, _)| span)
+            .chain(definition)
+            .collect::<Vec<_>>();
+        references.sort_by_key(|span| line_column(span.start));
+        references
+    }
+}
+
+struct FieldWalker<'a> {
+    table: &'a mut SymbolTable,
+    program: &'a Program,
+}
+
+impl<'a> FieldWalker<'a> {
+    fn walk_statement(&mut self, stmt: &Statement) {
+        match *stmt {
+            Statement::Block(ref stmts) => for stmt in stmts {
+                self.walk_statement(stmt);
+            },
+            Statement::Break |
+            Statement::Continue |
+            Statement::Error |
+            Statement::Let(_, _, _, None) => {}
+            Statement::Expr(ref expr) |
+            Statement::Let(_, _, _, Some(ref expr)) |
+            Statement::Return(ref expr) => self.walk_expr(expr),
+            Statement::If(ref cond, ref then, ref else_) => {
+                self.walk_expr(cond);
+                self.walk_statement(then);
+                if let Some(ref stmt) = *else_ {
+                    self.walk_statement(stmt);
+                }
+            }
+            Statement::Loop(ref stmt) => self.walk_statement(stmt),
+            Statement::While(ref cond, ref body) => {
+                self.walk_expr(cond);
+                self.walk_statement(body);
+            }
+            Statement::Match(ref value, ref arms) => {
+                self.walk_expr(value);
+                for arm in arms {
+                    if let Pattern::Value(ref pattern) = *arm.pattern {
+                        self.walk_expr(pattern);
+                    }
+                    self.walk_statement(&arm.body);
+                }
+            }
+        }
+    }
+
+    fn walk_expr(&mut self, expr: &TypedExpr) {
+        match *expr.expr {
+            Expr::Binary(ref a, _, ref b) | Expr::Index(ref a, ref b) => {
+                self.walk_expr(a);
+                self.walk_expr(b);
+            }
+            Expr::Call(ref f, ref params) => {
+                self.walk_expr(f);
+                for param in params {
+                    self.walk_expr(param);
+                }
+            }
+            Expr::Field(ref e, ref field) => {
+                self.walk_expr(e);
+                if let Type::Concrete(symbol, _) = e.typ {
+                    let struct_ = self.program.structs.get(&symbol);
+                    if let Some(field_def) = struct_.and_then(|s| s.fields.get(**field)) {
+                        self.table.add_use(field_def.name, Spanned::span(field));
+                    }
+                }
+            }
+            Expr::Unary(_, ref e) | Expr::Cast(ref e, _) | Expr::Widen(ref e) => {
+                self.walk_expr(e);
+            }
+            Expr::Slice(ref e, ref start, ref end) => {
+                self.walk_expr(e);
+                self.walk_expr(start);
+                self.walk_expr(end);
+            }
+            Expr::Lambda(ref lambda) => self.walk_statement(&lambda.body),
+            Expr::Error | Expr::Name(_, _) | Expr::Literal(_) => {}
+        }
+    }
+}
+
+/// Whether position is inside the span, including its end so that a
+/// cursor right after a name is still on it. Byte offsets are ignored,
+/// because editors usually only know lines and columns.
+fn contains(span: Span, position: Position) -> bool {
+    let position = line_column(position);
+    line_column(span.start) <= position && position <= line_column(span.end)
+}
+
+fn line_column(position: Position) -> (u32, u32) {
+    (position.line, position.column)
+}
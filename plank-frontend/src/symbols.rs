@@ -15,9 +15,74 @@ impl Symbols {
         names.insert(::builtins::ALIGN_OF, "align_of".into());
         names.insert(::builtins::GETC, "@getc".into());
         names.insert(::builtins::PUTC, "@putc".into());
+        names.insert(::builtins::LEN, "len".into());
         names.insert(::builtins::SIZE_OF_TYPE_PARAM, "T".into());
         names.insert(::builtins::ALIGN_OF_TYPE_PARAM, "T".into());
         names.insert(::builtins::PUTC_PARAM, "ch".into());
+        names.insert(::builtins::LEN_TYPE_PARAM, "T".into());
+        names.insert(::builtins::LEN_PARAM, "slice".into());
+        names.insert(::builtins::ALLOC, "@alloc".into());
+        names.insert(::builtins::FREE, "@free".into());
+        names.insert(::builtins::REALLOC, "@realloc".into());
+        names.insert(::builtins::ALLOC_PARAM, "size".into());
+        names.insert(::builtins::FREE_PARAM, "ptr".into());
+        names.insert(::builtins::REALLOC_PTR_PARAM, "ptr".into());
+        names.insert(::builtins::REALLOC_SIZE_PARAM, "size".into());
+        names.insert(::builtins::BOUNDS_FAIL, "@bounds_fail".into());
+        names.insert(::builtins::BOUNDS_FAIL_INDEX_PARAM, "index".into());
+        names.insert(::builtins::BOUNDS_FAIL_LEN_PARAM, "len".into());
+        names.insert(::builtins::PRINT_I32, "@print_i32".into());
+        names.insert(::builtins::PRINT_U32, "@print_u32".into());
+        names.insert(::builtins::PRINT_STR, "print_str".into());
+        names.insert(::builtins::PRINT_I32_PARAM, "value".into());
+        names.insert(::builtins::PRINT_U32_PARAM, "value".into());
+        names.insert(::builtins::PRINT_STR_PARAM, "string".into());
+        names.insert(::builtins::WRITE, "@write".into());
+        names.insert(::builtins::WRITE_PTR_PARAM, "ptr".into());
+        names.insert(::builtins::WRITE_LEN_PARAM, "len".into());
+        names.insert(::builtins::READ_I32, "@read_i32".into());
+        names.insert(::builtins::READ_LINE, "read_line".into());
+        names.insert(::builtins::READ_LINE_PARAM, "buffer".into());
+        names.insert(::builtins::READ, "@read_line".into());
+        names.insert(::builtins::READ_PTR_PARAM, "ptr".into());
+        names.insert(::builtins::READ_LEN_PARAM, "len".into());
+        names.insert(::builtins::STR_LEN, "str_len".into());
+        names.insert(::builtins::STR_EQ, "str_eq".into());
+        names.insert(::builtins::STR_COPY, "str_copy".into());
+        names.insert(::builtins::STR_FIND, "str_find".into());
+        names.insert(::builtins::STR_LEN_PARAM, "string".into());
+        names.insert(::builtins::STR_EQ_A_PARAM, "a".into());
+        names.insert(::builtins::STR_EQ_B_PARAM, "b".into());
+        names.insert(::builtins::STR_COPY_TO_PARAM, "to".into());
+        names.insert(::builtins::STR_COPY_FROM_PARAM, "from".into());
+        names.insert(::builtins::STR_FIND_STRING_PARAM, "string".into());
+        names.insert(::builtins::STR_FIND_PATTERN_PARAM, "pattern".into());
+        names.insert(::builtins::RUNTIME_STR_EQ, "@str_eq".into());
+        names.insert(::builtins::RUNTIME_STR_COPY, "@str_copy".into());
+        names.insert(::builtins::RUNTIME_STR_FIND, "@str_find".into());
+        names.insert(::builtins::RUNTIME_STR_A_PTR_PARAM, "a_ptr".into());
+        names.insert(::builtins::RUNTIME_STR_A_LEN_PARAM, "a_len".into());
+        names.insert(::builtins::RUNTIME_STR_B_PTR_PARAM, "b_ptr".into());
+        names.insert(::builtins::RUNTIME_STR_B_LEN_PARAM, "b_len".into());
+        names.insert(::builtins::ASSERT, "assert".into());
+        names.insert(::builtins::ASSERT_CONDITION_PARAM, "condition".into());
+        names.insert(::builtins::ASSERT_MESSAGE_PARAM, "message".into());
+        names.insert(::builtins::ASSERT_FAIL, "@assert_fail".into());
+        names.insert(::builtins::ASSERT_FAIL_PTR_PARAM, "ptr".into());
+        names.insert(::builtins::ASSERT_FAIL_LEN_PARAM, "len".into());
+        names.insert(::builtins::ASSERT_FAIL_LINE_PARAM, "line".into());
+        names.insert(::builtins::ASSERT_FAIL_COLUMN_PARAM, "column".into());
+        names.insert(::builtins::PANIC, "panic".into());
+        names.insert(::builtins::PANIC_MESSAGE_PARAM, "message".into());
+        names.insert(::builtins::PANIC_FAIL, "@panic_fail".into());
+        names.insert(::builtins::PANIC_FAIL_PTR_PARAM, "ptr".into());
+        names.insert(::builtins::PANIC_FAIL_LEN_PARAM, "len".into());
+        names.insert(::builtins::PANIC_FAIL_LINE_PARAM, "line".into());
+        names.insert(::builtins::PANIC_FAIL_COLUMN_PARAM, "column".into());
+        names.insert(::builtins::RAND_SEED, "@rand_seed".into());
+        names.insert(::builtins::RAND_SEED_PARAM, "seed".into());
+        names.insert(::builtins::RAND_U32, "@rand_u32".into());
+        names.insert(::builtins::TIME_MS, "@time_ms".into());
         Symbols {
             next_symbol: names.len() as u32,
             symbol_names: names,
@@ -34,4 +99,8 @@ impl Symbols {
     pub fn get_name(&self, symbol: Symbol) -> &str {
         &self.symbol_names[&symbol]
     }
+
+    pub fn rename<S: Into<String>>(&mut self, symbol: Symbol, name: S) {
+        self.symbol_names.insert(symbol, name.into());
+    }
 }
@@ -2,26 +2,41 @@ mod rollback_map;
 mod unify;
 
 use std::borrow::{Borrow, Cow};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::rc::Rc;
+use std::sync::Arc;
+use plank_errors::codes;
 use plank_syntax::position::{Span, Spanned};
 use ast::resolved::{self as r, BinaryOp, Mutability, Symbol, UnaryOp};
 use ast::typed::{self as t, Type};
+use suggest;
 use CompileCtx;
-use self::unify::UnifyTable;
+use self::unify::{FunctionMismatch, UnifyTable};
 
 
 #[derive(Debug, Clone)]
 enum Reason {
     IfCondition(Span),
     WhileCondition(Span),
-    LeftOperand(Span),
-    RightOperand(Span),
-    UnaryOperand(Span),
-    FunctionParam(usize, Span),
-    Return(Span),
-    Assign(Span),
+    // spans of the operand and the operator
+    LeftOperand(Span, Span),
+    // spans of the operand and whatever determined its expected type,
+    // which is either the left operand or the operator
+    RightOperand(Span, Span),
+    // spans of the operand and the operator
+    UnaryOperand(Span, Span),
+    // index and span of the argument, and span of the parameter type
+    // (with the kind of parameter) if the called function is known
+    FunctionParam(usize, Span, Option<(Span, &'static str)>),
+    // spans of returned value and function return type
+    Return(Span, Span),
+    // spans of assigned value and the place that determined expected type
+    Assign(Span, Span),
+    // spans of the pattern and the matched value
+    Pattern(Span, Span),
+    // spans of the default value and the parameter type
+    Default(Span, Span),
+    Index(Span),
 }
 
 #[derive(Debug, Clone)]
@@ -45,13 +60,19 @@ impl Scheme {
                     *p = params.into();
                     sym
                 }
-                Type::Bool | Type::Int(_, _) | Type::Error | Type::Var(_) | Type::Unit => return,
-                Type::Pointer(_, ref mut t) => {
-                    walk(Rc::make_mut(t), vars, params);
+                Type::Bool |
+                Type::Int(_, _) |
+                Type::Error |
+                Type::Var(_) |
+                Type::Unit |
+                Type::Never |
+                Type::Str => return,
+                Type::Pointer(_, ref mut t) | Type::Slice(_, ref mut t) => {
+                    walk(Arc::make_mut(t), vars, params);
                     return;
                 }
                 Type::Function(ref mut p, ref mut o) => {
-                    walk(Rc::make_mut(o), vars, params);
+                    walk(Arc::make_mut(o), vars, params);
                     let params = p.iter()
                         .map(|p| {
                             let mut o = p.clone();
@@ -90,7 +111,9 @@ impl<'a> fmt::Display for TypeFormatter<'a> {
         let typ = self.inferer.unifier.shallow_normalize(self.typ);
         match typ {
             Type::Unit => write!(f, "unit"),
+            Type::Never => write!(f, "never"),
             Type::Bool => write!(f, "bool"),
+            Type::Str => write!(f, "str"),
             Type::Concrete(sym, ref params) => {
                 write!(f, "{}", self.inferer.ctx.symbols.get_name(sym))?;
                 if params.len() > 0 {
@@ -164,6 +187,22 @@ impl<'a> fmt::Display for TypeFormatter<'a> {
                     inferer: self.inferer,
                 }
             ),
+            Type::Slice(Mutability::Const, ref typ) => write!(
+                f,
+                "[]{}",
+                TypeFormatter {
+                    typ,
+                    inferer: self.inferer,
+                }
+            ),
+            Type::Slice(Mutability::Mut, ref typ) => write!(
+                f,
+                "[]mut {}",
+                TypeFormatter {
+                    typ,
+                    inferer: self.inferer,
+                }
+            ),
             Type::Var(var) => write!(f, "{}", self.inferer.unifier.describe_var(var)),
         }
     }
@@ -172,23 +211,35 @@ impl<'a> fmt::Display for TypeFormatter<'a> {
 struct Inferer<'a> {
     ctx: &'a mut CompileCtx,
     unifier: UnifyTable,
-    string_type: Type,
-    return_type: Option<Type>,
+    return_type: Option<Spanned<Type>>,
     env: HashMap<Symbol, Scheme>,
     fields: HashMap<Symbol, HashMap<String, (usize, Scheme)>>,
+    /// Spans of parameter types of functions and struct constructors,
+    /// with the kind of parameter for labels.
+    param_spans: HashMap<Symbol, (&'static str, Vec<Span>)>,
+    /// Bounds of type parameters of each function.
+    bounds: HashMap<Symbol, Vec<Option<Spanned<r::Bound>>>>,
+    /// Type parameters of functions that have no bound,
+    /// with where they were declared.
+    unbounded_params: HashMap<Symbol, Span>,
+    /// Spans of default values that failed to type check. Their errors
+    /// are reported once at the definition, so calls that get a copy of
+    /// them don't check it again.
+    failed_defaults: HashSet<Span>,
 }
 
 impl<'a> Inferer<'a> {
     fn new(ctx: &'a mut CompileCtx) -> Self {
-        let char_type = Type::Int(t::Signedness::Unsigned, t::Size::Bit8);
-        let string_type = Type::Pointer(Mutability::Const, Rc::new(char_type));
         Inferer {
             ctx,
             unifier: UnifyTable::new(),
-            string_type,
             return_type: None,
             env: HashMap::new(),
             fields: HashMap::new(),
+            param_spans: HashMap::new(),
+            bounds: HashMap::new(),
+            unbounded_params: HashMap::new(),
+            failed_defaults: HashSet::new(),
         }
     }
 
@@ -200,68 +251,195 @@ impl<'a> Inferer<'a> {
         Type::Var(self.unifier.fresh_int_var())
     }
 
+    fn fresh_str_var(&mut self) -> Type {
+        Type::Var(self.unifier.fresh_str_var())
+    }
+
     fn unify(&mut self, a: &Type, b: &Type, reason: Reason) -> Type {
         match self.unifier.unify(a, b) {
             Ok(ty) => ty,
             Err(()) => {
                 let got = self.format_type(a).to_string();
                 let expected = self.format_type(b).to_string();
+                // most reasons also point at where the expected type came from
+                let mut expected_label = None;
                 let (msg, span) = match reason {
-                    Reason::Assign(span) => {
+                    Reason::Assign(span, expected_span) => {
                         let msg = format!("cannot assign `{}` to `{}`", got, expected);
+                        let label = format!("expected `{}` because of this", expected);
+                        expected_label = Some((label, expected_span));
+                        (msg, span)
+                    }
+                    Reason::Pattern(span, value_span) => {
+                        let msg = format!(
+                            "pattern has type `{}`, but matched value is `{}`",
+                            got,
+                            expected,
+                        );
+                        let label = format!("this has type `{}`", expected);
+                        expected_label = Some((label, value_span));
+                        (msg, span)
+                    }
+                    Reason::Default(span, param_span) => {
+                        let msg = format!(
+                            "default value should be `{}`, but is `{}`",
+                            expected,
+                            got,
+                        );
+                        let label = format!("expected `{}` because of this parameter", expected);
+                        expected_label = Some((label, param_span));
                         (msg, span)
                     }
                     Reason::IfCondition(span) | Reason::WhileCondition(span) => {
                         let msg = format!("condition has type `{}`", got);
                         (msg, span)
                     }
-                    Reason::Return(span) => {
+                    Reason::Return(span, return_type_span) => {
                         let msg = format!(
                             "cannot return `{}` from function returning `{}`",
                             got,
                             expected,
                         );
+                        let label = format!("expected `{}` because of return type", expected);
+                        expected_label = Some((label, return_type_span));
                         (msg, span)
                     }
-                    Reason::UnaryOperand(span) => {
+                    Reason::UnaryOperand(span, op_span) => {
                         let msg = format!("operand should be `{}`, but is `{}`", expected, got,);
+                        let label = format!("expected `{}` because of this operator", expected);
+                        expected_label = Some((label, op_span));
                         (msg, span)
                     }
-                    Reason::LeftOperand(span) => {
+                    Reason::LeftOperand(span, op_span) => {
                         let msg =
                             format!("left operand should be `{}`, but is `{}`", expected, got,);
+                        let label = format!("expected `{}` because of this operator", expected);
+                        expected_label = Some((label, op_span));
                         (msg, span)
                     }
-                    Reason::RightOperand(span) => {
+                    Reason::RightOperand(span, expected_span) => {
                         let msg =
                             format!("right operand should be `{}`, but is `{}`", expected, got,);
+                        let label = format!("expected `{}` because of this", expected);
+                        expected_label = Some((label, expected_span));
                         (msg, span)
                     }
-                    Reason::FunctionParam(mut index, span) => {
-                        index += 1;
-                        let suff = match (index % 100, index % 10) {
-                            (11, _) | (12, _) | (13, _) => "th",
-                            (_, 1) => "st",
-                            (_, 2) => "nd",
-                            (_, 3) => "rd",
-                            _ => "th",
-                        };
+                    Reason::Index(span) => {
+                        let msg = format!("index should be `{}`, but is `{}`", expected, got,);
+                        (msg, span)
+                    }
+                    Reason::FunctionParam(index, span, param_span) => {
+                        if let Some((param_span, kind)) = param_span {
+                            let label =
+                                format!("expected `{}` because of this {}", expected, kind);
+                            expected_label = Some((label, param_span));
+                        }
                         let msg = format!(
-                            "{}{} argument should be `{}`, but is `{}`",
-                            index,
-                            suff,
+                            "{} argument should be `{}`, but is `{}`",
+                            ordinal(index),
                             expected,
                             got,
                         );
                         (msg, span)
                     }
                 };
-                self.ctx.reporter.error(msg, span).span(span).build();
+                // point at the part of a function type that is wrong,
+                // as whole signatures can be hard to compare
+                let mismatch = self.unifier.function_mismatch(a, b).map(|m| match m {
+                    FunctionMismatch::ParamCount(got, expected) => format!(
+                        "function should take {} parameter{}, but takes {}",
+                        expected,
+                        if expected == 1 { "" } else { "s" },
+                        got,
+                    ),
+                    FunctionMismatch::Param(index, got, expected) => format!(
+                        "{} parameter should be `{}`, but is `{}`",
+                        ordinal(index),
+                        self.format_type(&expected),
+                        self.format_type(&got),
+                    ),
+                    FunctionMismatch::Return(got, expected) => format!(
+                        "return type should be `{}`, but is `{}`",
+                        self.format_type(&expected),
+                        self.format_type(&got),
+                    ),
+                });
+                let mut builder = self.ctx
+                    .reporter
+                    .error(msg, span)
+                    .code(codes::TYPE_MISMATCH);
+                if let Some((param, param_span)) = self.missing_bound(a, b) {
+                    let name = self.ctx.symbols.get_name(param);
+                    let help = format!(
+                        "help: to use `{}` like an int, require it with `{}: Numeric`",
+                        name,
+                        name,
+                    );
+                    builder = builder.span_note(help, param_span);
+                }
+                match (expected_label, mismatch) {
+                    (Some((label, expected_span)), mismatch) => {
+                        let found = mismatch.unwrap_or_else(|| format!("found `{}`", got));
+                        builder
+                            .primary_label(found, span)
+                            .secondary_label(label, expected_span)
+                            .build()
+                    }
+                    (None, Some(mismatch)) => builder.primary_label(mismatch, span).build(),
+                    (None, None) => builder
+                        .primary_label(format!("expected `{}`, found `{}`", expected, got), span)
+                        .build(),
+                }
                 Type::Error
             }
         }
     }
 
+    /// Unify type of `expr` with `expected`, and return the expression
+    /// with its new type. If implicit widening is enabled, an int of a
+    /// narrower type is widened instead.
+    fn coerce(
+        &mut self,
+        expr: t::TypedExpr,
+        expected: &Type,
+        reason: Reason,
+    ) -> (t::TypedExpr, Type) {
+        if self.ctx.options.implicit_widening {
+            let from = self.unifier.shallow_normalize(&expr.typ);
+            let to = self.unifier.shallow_normalize(expected);
+            if let (Type::Int(sign1, size1), Type::Int(sign2, size2)) = (from, to) {
+                if sign1 == sign2 && size1 < size2 {
+                    let typ = Type::Int(sign2, size2);
+                    let span = expr.span;
+                    let expr = t::TypedExpr {
+                        expr: Box::new(t::Expr::Widen(expr)),
+                        typ: typ.clone(),
+                        span,
+                    };
+                    return (expr, typ);
+                }
+            }
+        }
+        let typ = self.unify(&expr.typ, expected, reason);
+        (expr, typ)
+    }
+
+    /// Find a type parameter without bound that is used as an int.
+    fn missing_bound(&self, a: &Type, b: &Type) -> Option<(Symbol, Span)> {
+        let a = self.unifier.shallow_normalize(a);
+        let b = self.unifier.shallow_normalize(b);
+        let (param, other) = match (&a, &b) {
+            (&Type::Concrete(param, _), other) | (other, &Type::Concrete(param, _)) => {
+                (param, other)
+            }
+            _ => return None,
+        };
+        match (self.unbounded_params.get(&param), other) {
+            (Some(&span), &Type::Var(var)) if self.unifier.is_int_var(var) => Some((param, span)),
+            _ => None,
+        }
+    }
+
     fn format_type<'b>(&'b self, typ: &'b Type) -> TypeFormatter<'b>
     where
         'a: 'b,
@@ -273,6 +451,7 @@ impl<'a> Inferer<'a> {
         match *typ {
             r::Type::Unit => Type::Unit,
             r::Type::Bool => Type::Bool,
+            r::Type::Str => Type::Str,
             r::Type::Wildcard => self.fresh_var(),
             r::Type::I8 => Type::Int(t::Signedness::Signed, t::Size::Bit8),
             r::Type::U8 => Type::Int(t::Signedness::Unsigned, t::Size::Bit8),
@@ -282,7 +461,11 @@ impl<'a> Inferer<'a> {
             r::Type::U32 => Type::Int(t::Signedness::Unsigned, t::Size::Bit32),
             r::Type::Pointer(mutability, ref typ) => {
                 let typ = self.convert_resolved_type(typ);
-                t::Type::Pointer(mutability, Rc::new(typ))
+                t::Type::Pointer(mutability, Arc::new(typ))
+            }
+            r::Type::Slice(mutability, ref typ) => {
+                let typ = self.convert_resolved_type(typ);
+                t::Type::Slice(mutability, Arc::new(typ))
             }
             r::Type::Concrete(sym, ref params) => {
                 let params = params
@@ -297,7 +480,7 @@ impl<'a> Inferer<'a> {
                     .map(|t| self.convert_resolved_type(t))
                     .collect::<Vec<_>>();
                 let out = self.convert_resolved_type(out);
-                t::Type::Function(params.into(), Rc::new(out))
+                t::Type::Function(params.into(), Arc::new(out))
             }
             r::Type::Error => t::Type::Error,
         }
@@ -306,12 +489,15 @@ impl<'a> Inferer<'a> {
     fn type_name(&self, typ: &Type) -> Cow<'static, str> {
         match *typ {
             Type::Unit => "unit".into(),
+            Type::Never => "never".into(),
             Type::Bool => "a bool".into(),
+            Type::Str => "a string".into(),
             Type::Concrete(sym, _) => format!("struct `{}`", self.ctx.symbols.get_name(sym)).into(),
             Type::Error => "error".into(),
             Type::Function(_, _) => "a function".into(),
             Type::Int(_, _) => "an int".into(),
             Type::Pointer(_, _) => "a pointer".into(),
+            Type::Slice(_, _) => "a slice".into(),
             Type::Var(_) => "type variable".into(),
         }
     }
@@ -324,7 +510,7 @@ impl<'a> Inferer<'a> {
             },
             r::Literal::Bool(_) => Type::Bool,
             r::Literal::Char(_) => Type::Int(t::Signedness::Unsigned, t::Size::Bit8),
-            r::Literal::Str(_) => self.string_type.clone(),
+            r::Literal::Str(_) => self.fresh_str_var(),
             r::Literal::Unit => Type::Unit,
         }
     }
@@ -335,8 +521,47 @@ impl<'a> Inferer<'a> {
         scheme.instantiate(params)
     }
 
+    fn infer_lambda(&mut self, lambda: &r::Lambda, span: Span) -> (t::Expr, Type) {
+        for &(outer, inner) in &lambda.captures {
+            let scheme = self.env[&outer].clone();
+            self.env.insert(inner, scheme);
+        }
+        let mut params = Vec::new();
+        for param in &lambda.params {
+            let typ = self.convert_resolved_type(&param.typ);
+            let scheme = Scheme {
+                vars: Vec::new(),
+                typ: typ.clone(),
+            };
+            self.env.insert(Spanned::into_value(param.name), scheme);
+            params.push(t::FnParam {
+                mutability: param.mutability,
+                name: Spanned::into_value(param.name),
+                typ,
+            });
+        }
+        let return_type = self.convert_resolved_type(&lambda.return_type);
+        let outer_return_type = self.return_type.take();
+        self.return_type = Some(Spanned::new(return_type.clone(), Spanned::span(&lambda.return_type)));
+        let body = Spanned::map_ref(&lambda.body, |s| self.infer_statement(s));
+        self.return_type = outer_return_type;
+        let param_types = params.iter().map(|p| p.typ.clone()).collect::<Vec<_>>();
+        let typ = Type::Function(param_types.into(), Arc::new(return_type.clone()));
+        let signature_span = span.start.span_to(Spanned::span(&lambda.return_type).end);
+        let lambda = t::Lambda {
+            name: lambda.name,
+            signature_span,
+            params,
+            return_type,
+            body,
+            captures: lambda.captures.clone(),
+        };
+        (t::Expr::Lambda(lambda), typ)
+    }
+
     fn infer_expr(&mut self, expr: &Spanned<r::Expr>) -> t::TypedExpr {
         let (typed, typ) = match **expr {
+            r::Expr::Lambda(ref lambda) => self.infer_lambda(lambda, Spanned::span(expr)),
             r::Expr::Binary(ref lhs, op, ref rhs) => {
                 let lhs = self.infer_expr(lhs);
                 let rhs = self.infer_expr(rhs);
@@ -356,8 +581,8 @@ impl<'a> Inferer<'a> {
                     BinaryOp::Less |
                     BinaryOp::LessEqual => (self.fresh_int_var(), Type::Bool),
                     BinaryOp::Assign => {
-                        let reason = Reason::Assign(rhs.span);
-                        let typ = self.unify(&rhs.typ, &lhs.typ, reason);
+                        let reason = Reason::Assign(rhs.span, lhs.span);
+                        let (rhs, typ) = self.coerce(rhs, &lhs.typ, reason);
                         let typed = t::Expr::Binary(lhs, op, rhs);
                         return t::TypedExpr {
                             expr: Box::new(typed),
@@ -366,8 +591,16 @@ impl<'a> Inferer<'a> {
                         };
                     }
                 };
-                let param_type = self.unify(&lhs.typ, &param_type, Reason::LeftOperand(lhs.span));
-                self.unify(&rhs.typ, &param_type, Reason::RightOperand(rhs.span));
+                let op_span = Spanned::span(&op);
+                let param_type =
+                    self.unify(&lhs.typ, &param_type, Reason::LeftOperand(lhs.span, op_span));
+                // operands of `&&` and `||` are always bools,
+                // otherwise right operand has to match the left one
+                let expected_span = match *op {
+                    BinaryOp::And | BinaryOp::Or => op_span,
+                    _ => lhs.span,
+                };
+                self.unify(&rhs.typ, &param_type, Reason::RightOperand(rhs.span, expected_span));
                 (t::Expr::Binary(lhs, op, rhs), out_type)
             }
             r::Expr::Call(ref expr, ref params) => {
@@ -390,18 +623,38 @@ impl<'a> Inferer<'a> {
                         self.ctx
                             .reporter
                             .error(msg, expr.span)
+                            .code(codes::WRONG_ARG_COUNT)
                             .span_note(short_msg, expr.span)
                             .build();
                         (t::Expr::Error, Type::Error)
                     } else {
                         let params = params
                             .iter()
-                            .map(|p| self.infer_expr(p))
+                            .map(|p| if self.failed_defaults.contains(&Spanned::span(p)) {
+                                t::TypedExpr {
+                                    expr: Box::new(t::Expr::Error),
+                                    span: Spanned::span(p),
+                                    typ: Type::Error,
+                                }
+                            } else {
+                                self.infer_expr(p)
+                            })
                             .collect::<Vec<_>>();
-                        for i in 0..params.len() {
-                            let reason = Reason::FunctionParam(i, params[i].span);
-                            self.unify(&params[i].typ, &param_types[i], reason);
-                        }
+                        let param_spans = match *expr.expr {
+                            t::Expr::Name(name, _) => self.param_spans.get(&*name).cloned(),
+                            _ => None,
+                        };
+                        let params = params
+                            .into_iter()
+                            .enumerate()
+                            .map(|(i, param)| {
+                                let param_span = param_spans
+                                    .as_ref()
+                                    .map(|&(kind, ref spans)| (spans[i], kind));
+                                let reason = Reason::FunctionParam(i, param.span, param_span);
+                                self.coerce(param, &param_types[i], reason).0
+                            })
+                            .collect();
                         let expr = t::Expr::Call(expr, params);
                         (expr, (*out_type).clone())
                     },
@@ -410,6 +663,7 @@ impl<'a> Inferer<'a> {
                         self.ctx
                             .reporter
                             .error("cannot infer the type before call", expr.span)
+                            .code(codes::CANNOT_INFER)
                             .span(expr.span)
                             .build();
                         (t::Expr::Error, Type::Error)
@@ -419,6 +673,7 @@ impl<'a> Inferer<'a> {
                         self.ctx
                             .reporter
                             .error(msg, expr.span)
+                            .code(codes::NOT_CALLABLE)
                             .span(expr.span)
                             .build();
                         (t::Expr::Error, Type::Error)
@@ -430,6 +685,30 @@ impl<'a> Inferer<'a> {
                 let expr = self.infer_expr(expr);
                 self.check_field(expr, field)
             }
+            r::Expr::Index(ref expr, ref index) => {
+                let expr = self.infer_expr(expr);
+                let index = self.infer_expr(index);
+                self.check_index(&index);
+                match self.element_type(&expr, "cannot infer the type before indexing") {
+                    Some((_, typ)) => (t::Expr::Index(expr, index), typ),
+                    None => (t::Expr::Error, Type::Error),
+                }
+            }
+            r::Expr::Slice(ref expr, ref start, ref end) => {
+                let expr = self.infer_expr(expr);
+                let start = self.infer_expr(start);
+                let end = self.infer_expr(end);
+                self.check_index(&start);
+                self.check_index(&end);
+                match self.element_type(&expr, "cannot infer the type before slicing") {
+                    Some(_) if self.is_str(&expr.typ) => (t::Expr::Slice(expr, start, end), Type::Str),
+                    Some((mutability, typ)) => {
+                        let typ = Type::Slice(mutability, Arc::new(typ));
+                        (t::Expr::Slice(expr, start, end), typ)
+                    }
+                    None => (t::Expr::Error, Type::Error),
+                }
+            }
             r::Expr::Literal(ref literal) => {
                 let typ = self.infer_literal(literal);
                 let expr = t::Expr::Literal(literal.clone());
@@ -448,17 +727,17 @@ impl<'a> Inferer<'a> {
                 let (param_type, out_type) = match Spanned::into_value(op) {
                     UnaryOp::AddressOf => {
                         let var = self.fresh_var();
-                        (var.clone(), Type::Pointer(Mutability::Const, Rc::new(var)))
+                        (var.clone(), Type::Pointer(Mutability::Const, Arc::new(var)))
                     }
                     UnaryOp::MutAddressOf => {
                         let var = self.fresh_var();
-                        (var.clone(), Type::Pointer(Mutability::Mut, Rc::new(var)))
+                        (var.clone(), Type::Pointer(Mutability::Mut, Arc::new(var)))
                     }
                     UnaryOp::Deref => {
                         let var = self.fresh_var();
                         // if operand is `*mut _` it will be successfully
                         // coerced to `*_`, so we expect const here
-                        (Type::Pointer(Mutability::Const, Rc::new(var.clone())), var)
+                        (Type::Pointer(Mutability::Const, Arc::new(var.clone())), var)
                     }
                     UnaryOp::Minus | UnaryOp::Plus => {
                         let var = self.fresh_int_var();
@@ -466,7 +745,8 @@ impl<'a> Inferer<'a> {
                     }
                     UnaryOp::Not => (Type::Bool, Type::Bool),
                 };
-                self.unify(&expr.typ, &param_type, Reason::UnaryOperand(expr.span));
+                let reason = Reason::UnaryOperand(expr.span, Spanned::span(&op));
+                self.unify(&expr.typ, &param_type, reason);
                 (t::Expr::Unary(op, expr), out_type)
             }
             r::Expr::Cast(ref expr, ref typ) => {
@@ -484,6 +764,55 @@ impl<'a> Inferer<'a> {
         }
     }
 
+    fn check_index(&mut self, index: &t::TypedExpr) {
+        let index_type = Type::Int(t::Signedness::Unsigned, t::Size::Bit32);
+        self.unify(&index.typ, &index_type, Reason::Index(index.span));
+    }
+
+    fn element_type(
+        &mut self,
+        expr: &t::TypedExpr,
+        infer_msg: &'static str,
+    ) -> Option<(Mutability, Type)> {
+        let mut expr_type = self.unifier.shallow_normalize(&expr.typ);
+        if let Type::Var(var) = expr_type {
+            // string literals are indexed as `str` unless told otherwise
+            if self.unifier.is_str_var(var) {
+                expr_type = self.unify(&expr.typ, &Type::Str, Reason::Index(expr.span));
+            }
+        }
+        match expr_type {
+            Type::Pointer(mutability, ref typ) | Type::Slice(mutability, ref typ) => {
+                Some((mutability, (**typ).clone()))
+            }
+            Type::Str => Some((Mutability::Const, Type::Int(t::Signedness::Unsigned, t::Size::Bit8))),
+            Type::Error => None,
+            Type::Var(_) => {
+                self.ctx
+                    .reporter
+                    .error(infer_msg, expr.span)
+                    .code(codes::CANNOT_INFER)
+                    .span(expr.span)
+                    .build();
+                None
+            }
+            typ => {
+                let msg = format!("cannot index {}", self.type_name(&typ));
+                self.ctx
+                    .reporter
+                    .error(msg, expr.span)
+                    .code(codes::NOT_INDEXABLE)
+                    .span(expr.span)
+                    .build();
+                None
+            }
+        }
+    }
+
+    fn is_str(&self, typ: &Type) -> bool {
+        matches!(self.unifier.shallow_normalize(typ), Type::Str)
+    }
+
     fn check_field(&mut self, expr: t::TypedExpr, field: &Spanned<String>) -> (t::Expr, Type) {
         let expr_type = self.unifier.shallow_normalize(&expr.typ);
         match expr_type {
@@ -502,11 +831,21 @@ impl<'a> Inferer<'a> {
                             self.type_name(&expr_type),
                             **field,
                         );
-                        self.ctx
+                        let span = Spanned::span(field);
+                        let similar = self.fields.get(&sym).and_then(|fields| {
+                            let candidates = fields.keys().map(String::as_str);
+                            suggest::find_similar(field, candidates)
+                        });
+                        let builder = self.ctx
                             .reporter
-                            .error(msg, Spanned::span(field))
-                            .span(Spanned::span(field))
-                            .build();
+                            .error(msg, span)
+                            .code(codes::UNKNOWN_FIELD);
+                        match similar {
+                            Some(similar) => builder
+                                .span_note(format!("help: did you mean `{}`?", similar), span)
+                                .build(),
+                            None => builder.span(span).build(),
+                        }
                         (t::Expr::Error, Type::Error)
                     }
                 }
@@ -516,6 +855,7 @@ impl<'a> Inferer<'a> {
                 self.ctx
                     .reporter
                     .error("cannot infer the type before field access", expr.span)
+                    .code(codes::CANNOT_INFER)
                     .span(expr.span)
                     .build();
                 (t::Expr::Error, Type::Error)
@@ -535,6 +875,7 @@ impl<'a> Inferer<'a> {
                 self.ctx
                     .reporter
                     .error(msg, expr.span)
+                    .code(codes::UNKNOWN_FIELD)
                     .span(expr.span)
                     .build();
                 (t::Expr::Error, Type::Error)
@@ -565,17 +906,16 @@ impl<'a> Inferer<'a> {
             r::Statement::Let(mutability, sym, ref typ, ref value) => {
                 let value = value.as_ref().map(|value| self.infer_expr(value));
                 let ty = self.convert_resolved_type(typ);
-                let scheme = if let Some(ref value) = value {
-                    let reason = Reason::Assign(value.span);
-                    Scheme {
-                        vars: Vec::new(),
-                        typ: self.unify(&value.typ, &ty, reason),
-                    }
+                let (value, scheme) = if let Some(value) = value {
+                    let reason = Reason::Assign(value.span, Spanned::span(typ));
+                    let (value, typ) = self.coerce(value, &ty, reason);
+                    (Some(value), Scheme { vars: Vec::new(), typ })
                 } else {
-                    Scheme {
+                    let scheme = Scheme {
                         vars: Vec::new(),
                         typ: ty.clone(),
-                    }
+                    };
+                    (None, scheme)
                 };
                 self.env.insert(Spanned::into_value(sym), scheme);
                 let typ = Spanned::new(ty, Spanned::span(typ));
@@ -588,7 +928,8 @@ impl<'a> Inferer<'a> {
             r::Statement::Return(ref expr) => {
                 let expr = self.infer_expr(expr);
                 let expected = self.return_type.clone().unwrap();
-                self.unify(&expr.typ, &expected, Reason::Return(expr.span));
+                let reason = Reason::Return(expr.span, Spanned::span(&expected));
+                let (expr, _) = self.coerce(expr, &expected, reason);
                 t::Statement::Return(expr)
             }
             r::Statement::While(ref cond, ref body) => {
@@ -597,6 +938,26 @@ impl<'a> Inferer<'a> {
                 self.unify(&cond.typ, &Type::Bool, Reason::WhileCondition(cond.span));
                 t::Statement::While(cond, Box::new(body))
             }
+            r::Statement::Match(ref value, ref arms) => {
+                let value = self.infer_expr(value);
+                let arms = arms
+                    .iter()
+                    .map(|arm| {
+                        let pattern = Spanned::map_ref(&arm.pattern, |p| match *p {
+                            r::Pattern::Wildcard => t::Pattern::Wildcard,
+                            r::Pattern::Value(ref pattern) => {
+                                let pattern = self.infer_expr(pattern);
+                                let reason = Reason::Pattern(pattern.span, value.span);
+                                self.unify(&pattern.typ, &value.typ, reason);
+                                t::Pattern::Value(pattern)
+                            }
+                        });
+                        let body = Spanned::map_ref(&arm.body, |s| self.infer_statement(s));
+                        t::MatchArm { pattern, body }
+                    })
+                    .collect();
+                t::Statement::Match(value, arms)
+            }
             r::Statement::Error => t::Statement::Error,
         }
     }
@@ -617,7 +978,7 @@ impl<'a> Inferer<'a> {
             });
         }
         let return_type = self.convert_resolved_type(&function.return_type);
-        self.return_type = Some(return_type);
+        self.return_type = Some(Spanned::new(return_type, Spanned::span(&function.return_type)));
         let body = function.body.as_ref().map(|s| {
             Spanned::map_ref(s, |s| {
                 let mut stmt = self.infer_statement(s);
@@ -625,12 +986,16 @@ impl<'a> Inferer<'a> {
                 stmt
             })
         });
+        let return_span = Spanned::span(&function.return_type);
         t::Function {
             complete_span: function.complete_span,
+            signature_span: function.complete_span.start.span_to(return_span.end),
             fn_type: function.fn_type,
             params,
-            return_type: self.return_type.take().unwrap(),
+            return_type: Spanned::into_value(self.return_type.take().unwrap()),
             body,
+            inline: function.inline,
+            calling_convention: function.calling_convention,
             name: Spanned::into_value(function.name.name),
             type_params: function.name.type_params.iter().map(|t| **t).collect(),
         }
@@ -659,6 +1024,15 @@ impl<'a> Inferer<'a> {
                 self.normalize_expr(cond);
                 self.normalize_statement(stmt);
             }
+            t::Statement::Match(ref mut value, ref mut arms) => {
+                self.normalize_expr(value);
+                for arm in arms {
+                    if let t::Pattern::Value(ref mut pattern) = *arm.pattern {
+                        self.normalize_expr(pattern);
+                    }
+                    self.normalize_statement(&mut arm.body);
+                }
+            }
             t::Statement::Let(_, _, ref mut typ, ref mut value) => {
                 if let Some(ref mut value) = *value {
                     self.normalize_expr(value);
@@ -670,6 +1044,7 @@ impl<'a> Inferer<'a> {
                         self.ctx
                             .reporter
                             .error("could not completely infer type", span)
+                            .code(codes::CANNOT_INFER)
                             .span(span)
                             .build();
                         **typ = Type::Error;
@@ -679,14 +1054,108 @@ impl<'a> Inferer<'a> {
         }
     }
 
+    fn report_uninferred_params(
+        &mut self,
+        name: Spanned<Symbol>,
+        params: &[Spanned<Type>],
+        uninferred: &[usize],
+    ) {
+        let span = Spanned::span(&name);
+        let vars = &self.env[&*name].vars;
+        let listed = uninferred
+            .iter()
+            .map(|&index| format!("`{}`", self.ctx.symbols.get_name(vars[index])))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let name = self.ctx.symbols.get_name(*name);
+        let plural = if uninferred.len() == 1 { "" } else { "s" };
+        let msg = format!("could not completely infer type of `{}`", name);
+        let label = format!("cannot infer type parameter{} {}", plural, listed);
+        // suggest turbofish with everything that is already known
+        let suggestion = params
+            .iter()
+            .enumerate()
+            .map(|(index, param)| if uninferred.contains(&index) {
+                self.ctx.symbols.get_name(vars[index]).to_string()
+            } else {
+                self.format_type(param).to_string()
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let help = format!(
+            "help: give type parameters explicitly, like `{}::<{}>`",
+            name,
+            suggestion,
+        );
+        self.ctx
+            .reporter
+            .error(msg, span)
+            .code(codes::CANNOT_INFER)
+            .primary_label(label, span)
+            .span_note(help, span)
+            .build();
+    }
+
+    fn check_bounds(&mut self, name: Spanned<Symbol>, params: &[Spanned<Type>]) {
+        let bounds = match self.bounds.get(&*name) {
+            Some(bounds) => bounds.clone(),
+            None => return,
+        };
+        for (index, bound) in bounds.iter().enumerate() {
+            let bound = match *bound {
+                Some(bound) => bound,
+                None => continue,
+            };
+            let typ = &params[index];
+            let satisfied = match **typ {
+                Type::Int(_, _) | Type::Error => true,
+                ref typ => self.unifier.is_numeric_param(typ),
+            };
+            if satisfied {
+                continue;
+            }
+            let var = self.ctx.symbols.get_name(self.env[&*name].vars[index]);
+            let bound_name = match *bound {
+                r::Bound::Numeric => "Numeric",
+            };
+            let typ = self.format_type(typ).to_string();
+            let span = Spanned::span(&name);
+            let msg = format!(
+                "type `{}` does not satisfy bound `{}` of `{}`",
+                typ,
+                bound_name,
+                var,
+            );
+            let bound_label = format!("`{}` is required to be `{}` here", var, bound_name);
+            self.ctx
+                .reporter
+                .error(msg, span)
+                .code(codes::UNSATISFIED_BOUND)
+                .primary_label(format!("`{}` is `{}` here", var, typ), span)
+                .secondary_label(bound_label, Spanned::span(&bound))
+                .build();
+        }
+    }
+
     fn normalize_expr(&mut self, expr: &mut t::TypedExpr) {
         match *expr.expr.as_mut() {
             t::Expr::Binary(ref mut lhs, _, ref mut rhs) => {
                 self.normalize_expr(lhs);
                 self.normalize_expr(rhs);
             }
-            t::Expr::Field(ref mut expr, _) | t::Expr::Unary(_, ref mut expr) => {
+            t::Expr::Field(ref mut expr, _) |
+            t::Expr::Unary(_, ref mut expr) |
+            t::Expr::Widen(ref mut expr) => {
+                self.normalize_expr(expr);
+            }
+            t::Expr::Index(ref mut expr, ref mut index) => {
+                self.normalize_expr(expr);
+                self.normalize_expr(index);
+            }
+            t::Expr::Slice(ref mut expr, ref mut start, ref mut end) => {
                 self.normalize_expr(expr);
+                self.normalize_expr(start);
+                self.normalize_expr(end);
             }
             t::Expr::Call(ref mut expr, ref mut params) => {
                 self.normalize_expr(expr);
@@ -698,21 +1167,43 @@ impl<'a> Inferer<'a> {
                 expr.typ = Type::Error;
                 return;
             }
+            t::Expr::Lambda(ref mut lambda) => {
+                // failures are reported when normalizing type of the lambda itself
+                for param in &mut lambda.params {
+                    match self.unifier.normalize(&param.typ) {
+                        Ok(t) => param.typ = t,
+                        Err(()) => param.typ = Type::Error,
+                    }
+                }
+                match self.unifier.normalize(&lambda.return_type) {
+                    Ok(t) => lambda.return_type = t,
+                    Err(()) => lambda.return_type = Type::Error,
+                }
+                self.normalize_statement(&mut lambda.body);
+            }
             t::Expr::Literal(_) => {}
-            t::Expr::Name(_, ref mut params) => for param in params {
-                match self.unifier.normalize(param) {
-                    Ok(t) => **param = t,
-                    Err(()) => {
-                        let span = Spanned::span(param);
-                        self.ctx
-                            .reporter
-                            .error("could not completely infer type", span)
-                            .span(span)
-                            .build();
+            t::Expr::Name(name, ref mut params) => {
+                let uninferred = params
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, param)| self.unifier.normalize(param).is_err())
+                    .map(|(index, _)| index)
+                    .collect::<Vec<_>>();
+                if !uninferred.is_empty() {
+                    self.report_uninferred_params(name, params, &uninferred);
+                    for param in params {
                         **param = Type::Error;
                     }
+                    // type of the whole expression would also
+                    // be incomplete, so don't report it again
+                    expr.typ = Type::Error;
+                    return;
                 }
-            },
+                for param in params.iter_mut() {
+                    **param = self.unifier.normalize(param).unwrap();
+                }
+                self.check_bounds(name, params);
+            }
             t::Expr::Cast(ref mut expr, ref mut typ) => {
                 self.normalize_expr(expr);
                 match self.unifier.normalize(typ) {
@@ -722,6 +1213,7 @@ impl<'a> Inferer<'a> {
                         self.ctx
                             .reporter
                             .error("could not completely infer type", span)
+                            .code(codes::CANNOT_INFER)
                             .span(span)
                             .build();
                         **typ = Type::Error;
@@ -736,6 +1228,7 @@ impl<'a> Inferer<'a> {
                 self.ctx
                     .reporter
                     .error("could not completely infer type", expr.span)
+                    .code(codes::CANNOT_INFER)
                     .span(expr.span)
                     .build();
             }
@@ -751,22 +1244,59 @@ impl<'a> Inferer<'a> {
         for f in &program.functions {
             self.add_function_to_env(f);
         }
+        for f in &program.functions {
+            self.check_defaults(f);
+        }
         for f in &program.functions {
             functions.push(self.infer_function(f));
         }
         t::Program { structs, functions }
     }
 
+    /// Check default values of parameters against their types, before
+    /// any of the calls that they are copied into.
+    fn check_defaults(&mut self, f: &r::Function) {
+        for param in &f.params {
+            let default = match param.default {
+                Some(ref default) => default,
+                None => continue,
+            };
+            let errors = self.ctx.reporter.error_count();
+            let typ = self.convert_resolved_type(&param.typ);
+            let value = self.infer_expr(default);
+            let reason = Reason::Default(value.span, Spanned::span(&param.typ));
+            self.coerce(value, &typ, reason);
+            if self.ctx.reporter.error_count() > errors {
+                self.failed_defaults.insert(Spanned::span(default));
+            }
+        }
+    }
+
     fn add_function_to_env(&mut self, f: &r::Function) {
         let param_types = f.params
             .iter()
             .map(|p| self.convert_resolved_type(&p.typ))
             .collect::<Vec<_>>();
         let vars = f.name.type_params.iter().map(|p| **p).collect();
-        let return_type = self.convert_resolved_type(&f.return_type);
-        let typ = Type::Function(param_types.into(), Rc::new(return_type));
+        let return_type = if f.noreturn {
+            Type::Never
+        } else {
+            self.convert_resolved_type(&f.return_type)
+        };
+        let typ = Type::Function(param_types.into(), Arc::new(return_type));
         let scheme = Scheme { vars, typ };
         self.env.insert(Spanned::into_value(f.name.name), scheme);
+        let param_spans = f.params.iter().map(|p| Spanned::span(&p.typ)).collect();
+        self.param_spans.insert(Spanned::into_value(f.name.name), ("parameter", param_spans));
+        for (param, bound) in f.name.type_params.iter().zip(&f.name.bounds) {
+            match bound.as_ref().map(|b| **b) {
+                Some(r::Bound::Numeric) => self.unifier.add_numeric_param(**param),
+                None => {
+                    self.unbounded_params.insert(**param, Spanned::span(param));
+                }
+            }
+        }
+        self.bounds.insert(Spanned::into_value(f.name.name), f.name.bounds.clone());
     }
 
     fn convert_struct(&mut self, s: &r::Struct) -> t::Struct {
@@ -784,12 +1314,14 @@ impl<'a> Inferer<'a> {
                 .collect::<Vec<_>>()
                 .into(),
         );
-        let typ = Type::Function(param_types.into(), Rc::new(return_type));
+        let typ = Type::Function(param_types.into(), Arc::new(return_type));
         let scheme = Scheme {
             vars: vars.clone(),
             typ,
         };
         self.env.insert(Spanned::into_value(s.name.name), scheme);
+        let param_spans = s.fields.iter().map(|f| Spanned::span(&f.typ)).collect();
+        self.param_spans.insert(Spanned::into_value(s.name.name), ("field", param_spans));
         let fields = s.fields
             .iter()
             .map(|f| {
@@ -813,6 +1345,7 @@ impl<'a> Inferer<'a> {
         }
         t::Struct {
             complete_span: s.complete_span,
+            kind: s.kind,
             name: Spanned::into_value(s.name.name),
             type_params: vars,
             fields,
@@ -820,6 +1353,19 @@ impl<'a> Inferer<'a> {
     }
 }
 
+/// Format zero based `index` as "1st", "2nd" and so on.
+fn ordinal(index: usize) -> String {
+    let index = index + 1;
+    let suff = match (index % 100, index % 10) {
+        (11, _) | (12, _) | (13, _) => "th",
+        (_, 1) => "st",
+        (_, 2) => "nd",
+        (_, 3) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", index, suff)
+}
+
 pub(crate) fn type_check(program: &r::Program, ctx: &mut CompileCtx) -> t::Program {
     let mut inferer = Inferer::new(ctx);
     inferer.infer_program(program)
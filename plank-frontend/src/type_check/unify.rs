@@ -1,5 +1,6 @@
-use std::rc::Rc;
-use ast::typed::{Mutability, Signedness, Size, Type, TypeVar};
+use std::collections::HashSet;
+use std::sync::Arc;
+use ast::typed::{Mutability, Signedness, Size, Symbol, Type, TypeVar};
 use super::rollback_map::Map;
 
 
@@ -7,11 +8,26 @@ use super::rollback_map::Map;
 enum VarTarget {
     Type(Type),
     Int,
+    Str,
+}
+
+/// First difference between two function types that do not unify.
+#[derive(Debug, Clone)]
+pub enum FunctionMismatch {
+    /// Parameter counts of the actual and expected function.
+    ParamCount(usize, usize),
+    /// Index of the parameter, with its actual and expected type.
+    Param(usize, Type, Type),
+    /// Actual and expected return type.
+    Return(Type, Type),
 }
 
 pub struct UnifyTable {
     var_target: Map<TypeVar, VarTarget>,
     next_var: u32,
+    /// Type parameters with `Numeric` bound, which can be
+    /// the type of int literals and operations.
+    numeric_params: HashSet<Symbol>,
 }
 
 impl UnifyTable {
@@ -19,6 +35,7 @@ impl UnifyTable {
         UnifyTable {
             var_target: Map::new(),
             next_var: 0,
+            numeric_params: HashSet::new(),
         }
     }
 
@@ -36,6 +53,35 @@ impl UnifyTable {
         var
     }
 
+    pub fn fresh_str_var(&mut self) -> TypeVar {
+        let var = TypeVar(self.next_var);
+        self.var_target.insert(var, VarTarget::Str);
+        self.var_target.commit();
+        self.next_var += 1;
+        var
+    }
+
+    pub fn is_str_var(&self, var: TypeVar) -> bool {
+        matches!(self.var_target.get(&var), Some(&VarTarget::Str))
+    }
+
+    pub fn is_int_var(&self, var: TypeVar) -> bool {
+        matches!(self.var_target.get(&var), Some(&VarTarget::Int))
+    }
+
+    pub fn add_numeric_param(&mut self, param: Symbol) {
+        self.numeric_params.insert(param);
+    }
+
+    pub fn is_numeric_param(&self, typ: &Type) -> bool {
+        match *typ {
+            Type::Concrete(sym, ref params) => {
+                params.is_empty() && self.numeric_params.contains(&sym)
+            }
+            _ => false,
+        }
+    }
+
     pub fn unify(&mut self, a: &Type, b: &Type) -> Result<Type, ()> {
         match self.unify_raw(a, b, true) {
             Ok(()) => {
@@ -50,6 +96,28 @@ impl UnifyTable {
         }
     }
 
+    /// Find where function type `a` stops matching `b`. Unlike `unify`,
+    /// this never binds any type variables.
+    pub fn function_mismatch(&mut self, a: &Type, b: &Type) -> Option<FunctionMismatch> {
+        let (ap, a, bp, b) = match (self.shallow_normalize(a), self.shallow_normalize(b)) {
+            (Type::Function(ap, a), Type::Function(bp, b)) => (ap, a, bp, b),
+            _ => return None,
+        };
+        if ap.len() != bp.len() {
+            return Some(FunctionMismatch::ParamCount(ap.len(), bp.len()));
+        }
+        let mut mismatch = ap.iter()
+            .zip(bp.iter())
+            .enumerate()
+            .find(|&(_, (a, b))| self.unify_raw(b, a, true).is_err())
+            .map(|(index, (a, b))| FunctionMismatch::Param(index, a.clone(), b.clone()));
+        if mismatch.is_none() && self.unify_raw(&a, &b, true).is_err() {
+            mismatch = Some(FunctionMismatch::Return((*a).clone(), (*b).clone()));
+        }
+        self.rollback();
+        mismatch
+    }
+
     fn unify_raw(&mut self, a: &Type, b: &Type, allow_coerce: bool) -> Result<(), ()> {
         let a = self.shallow_normalize(a);
         let b = self.shallow_normalize(b);
@@ -90,9 +158,28 @@ impl UnifyTable {
             if allow_coerce => {
                 self.unify_raw(a, b, false)
             }
+            (Type::Slice(m1, ref a), Type::Slice(m2, ref b)) if m1 == m2 => {
+                self.unify_raw(a, b, false)
+            }
+            (Type::Slice(Mutability::Mut, ref a), Type::Slice(Mutability::Const, ref b))
+            if allow_coerce => {
+                self.unify_raw(a, b, false)
+            }
+            // `str` has the same layout as `[]u8`
+            (Type::Str, Type::Slice(Mutability::Const, ref b)) if allow_coerce => {
+                self.unify_raw(&Type::Int(Signedness::Unsigned, Size::Bit8), b, false)
+            }
+            // literal vars cannot become `never`, but
+            // can still default to their usual type
+            (Type::Never, Type::Var(b)) if self.var_target.get(&b).is_some() => Ok(()),
             (Type::Var(a), ty) | (ty, Type::Var(a)) => self.unify_var_type(a, ty),
+            // code after a diverging call is never executed,
+            // so its result can be used as any type
+            (Type::Never, _) if allow_coerce => Ok(()),
             (Type::Bool, Type::Bool) |
             (Type::Unit, Type::Unit) |
+            (Type::Never, Type::Never) |
+            (Type::Str, Type::Str) |
             (Type::Error, _) | (_, Type::Error) => Ok(()),
             (_, _) => Err(()),
         }
@@ -105,7 +192,9 @@ impl UnifyTable {
             (Some(VarTarget::Type(_)), _) | (_, Some(VarTarget::Type(_))) => {
                 panic!("cannot unify non-normalized var")
             }
-            (None, _) | (Some(VarTarget::Int), Some(_)) => {
+            (None, _) |
+            (Some(VarTarget::Int), Some(VarTarget::Int)) |
+            (Some(VarTarget::Str), Some(VarTarget::Str)) => {
                 self.var_target.insert(a, VarTarget::Type(Type::Var(b)));
                 Ok(())
             }
@@ -113,6 +202,7 @@ impl UnifyTable {
                 self.var_target.insert(b, VarTarget::Type(Type::Var(a)));
                 Ok(())
             }
+            (Some(_), Some(_)) => Err(()),
         }
     }
 
@@ -132,10 +222,26 @@ impl UnifyTable {
             }
             (Some(VarTarget::Type(_)), _) => panic!("cannot unify non-normalized var"),
             (_, Type::Var(b)) => self.unify_var_var(v, b),
-            (None, ty) | (Some(VarTarget::Int), ty @ Type::Int(_, _)) => {
+            (None, ty) |
+            (Some(VarTarget::Int), ty @ Type::Int(_, _)) |
+            (Some(VarTarget::Str), ty @ Type::Str) => {
                 self.var_target.insert(v, VarTarget::Type(ty));
                 Ok(())
             }
+            (Some(VarTarget::Int), ref ty) if self.is_numeric_param(ty) => {
+                self.var_target.insert(v, VarTarget::Type(ty.clone()));
+                Ok(())
+            }
+            // string literals can also be used as `*u8` or `[]u8`
+            (Some(VarTarget::Str), ty @ Type::Pointer(Mutability::Const, _)) |
+            (Some(VarTarget::Str), ty @ Type::Slice(Mutability::Const, _)) => {
+                let to = match ty {
+                    Type::Pointer(_, ref to) | Type::Slice(_, ref to) => to.clone(),
+                    _ => unreachable!(),
+                };
+                self.var_target.insert(v, VarTarget::Type(ty));
+                self.unify_raw(&to, &Type::Int(Signedness::Unsigned, Size::Bit8), false)
+            }
             _ => Err(()),
         }
     }
@@ -143,7 +249,12 @@ impl UnifyTable {
     fn occurs(&mut self, var: TypeVar, typ: &Type) -> bool {
         let typ = self.shallow_normalize(typ);
         match typ {
-            Type::Bool | Type::Error | Type::Int(_, _) | Type::Unit => false,
+            Type::Bool |
+            Type::Error |
+            Type::Int(_, _) |
+            Type::Unit |
+            Type::Never |
+            Type::Str => false,
             Type::Concrete(_, ref params) => {
                 for param in params.iter() {
                     if self.occurs(var, param) {
@@ -160,7 +271,7 @@ impl UnifyTable {
                 }
                 self.occurs(var, out)
             }
-            Type::Pointer(_, ref to) => self.occurs(var, to),
+            Type::Pointer(_, ref to) | Type::Slice(_, ref to) => self.occurs(var, to),
             Type::Var(v) => var == v,
         }
     }
@@ -168,7 +279,7 @@ impl UnifyTable {
     fn get_var_type(&self, var: TypeVar) -> Type {
         match self.var_target.get(&var) {
             Some(&VarTarget::Type(ref ty)) => self.shallow_normalize(ty),
-            Some(&VarTarget::Int) | None => Type::Var(var),
+            Some(&VarTarget::Int) | Some(&VarTarget::Str) | None => Type::Var(var),
         }
     }
 
@@ -183,6 +294,8 @@ impl UnifyTable {
         match self.shallow_normalize(a) {
             Type::Bool => Ok(Type::Bool),
             Type::Unit => Ok(Type::Unit),
+            Type::Never => Ok(Type::Never),
+            Type::Str => Ok(Type::Str),
             Type::Concrete(sym, ref params) => {
                 let mut normalized = Vec::new();
                 for param in &**params {
@@ -197,12 +310,16 @@ impl UnifyTable {
                 for param in &**params {
                     normalized.push(self.normalize(param)?);
                 }
-                Ok(Type::Function(normalized.into(), Rc::new(out)))
+                Ok(Type::Function(normalized.into(), Arc::new(out)))
             }
             ty @ Type::Int(_, _) => Ok(ty),
             Type::Pointer(mutability, ref ty) => {
                 let ty = self.normalize(ty)?;
-                Ok(Type::Pointer(mutability, Rc::new(ty)))
+                Ok(Type::Pointer(mutability, Arc::new(ty)))
+            }
+            Type::Slice(mutability, ref ty) => {
+                let ty = self.normalize(ty)?;
+                Ok(Type::Slice(mutability, Arc::new(ty)))
             }
             Type::Var(var) => {
                 match self.var_target.get(&var) {
@@ -210,6 +327,9 @@ impl UnifyTable {
                     Some(&VarTarget::Int) => {
                         return Ok(Type::Int(Signedness::Signed, Size::Bit32));
                     }
+                    Some(&VarTarget::Str) => {
+                        return Ok(Type::Str);
+                    }
                     None => {
                         // there is no sensible default
                         // fall though because of borrowck
@@ -226,6 +346,7 @@ impl UnifyTable {
     pub fn describe_var(&self, var: TypeVar) -> &'static str {
         match self.var_target.get(&var) {
             Some(&VarTarget::Int) => "{int}",
+            Some(&VarTarget::Str) => "{string}",
             _ => "_",
         }
     }
@@ -1,6 +1,8 @@
 use std::collections::HashMap;
+use plank_errors::codes;
 use plank_syntax::position::Spanned;
-use ast::resolved::{Expr, Function, FunctionType, Program, Statement, Struct, Symbol, Type};
+use ast::resolved::{Expr, Function, FunctionType, Pattern, Program, Statement, Struct, Symbol,
+                    Type};
 use CompileCtx;
 
 
@@ -45,6 +47,7 @@ impl<'a> Context<'a> {
                 self.ctx
                     .reporter
                     .error("`extern` functions cannot have type parameters", span)
+                    .code(codes::EXTERN_TYPE_PARAMS)
                     .span(span)
                     .build();
             }
@@ -81,6 +84,7 @@ impl<'a> Context<'a> {
         match **typ {
             Type::Unit |
             Type::Bool |
+            Type::Str |
             Type::Error |
             Type::I8 |
             Type::I16 |
@@ -89,7 +93,7 @@ impl<'a> Context<'a> {
             Type::U16 |
             Type::U32 |
             Type::Wildcard => return,
-            Type::Pointer(_, ref mut typ) => {
+            Type::Pointer(_, ref mut typ) | Type::Slice(_, ref mut typ) => {
                 self.check_type(typ);
                 return;
             }
@@ -114,6 +118,7 @@ impl<'a> Context<'a> {
                     self.ctx
                         .reporter
                         .error(msg, name_span)
+                        .code(codes::WRONG_TYPE_PARAM_COUNT)
                         .span_note(short_msg, name_span)
                         .build();
                 } else {
@@ -151,6 +156,15 @@ impl<'a> Context<'a> {
                 self.check_expr(cond);
                 self.check_statement(body);
             }
+            Statement::Match(ref mut value, ref mut arms) => {
+                self.check_expr(value);
+                for arm in arms {
+                    if let Pattern::Value(ref mut value) = *arm.pattern {
+                        self.check_expr(value);
+                    }
+                    self.check_statement(&mut arm.body);
+                }
+            }
         }
     }
 
@@ -172,6 +186,17 @@ impl<'a> Context<'a> {
                 self.check_expr(expr);
                 return;
             }
+            Expr::Index(ref mut expr, ref mut index) => {
+                self.check_expr(expr);
+                self.check_expr(index);
+                return;
+            }
+            Expr::Slice(ref mut expr, ref mut start, ref mut end) => {
+                self.check_expr(expr);
+                self.check_expr(start);
+                self.check_expr(end);
+                return;
+            }
             Expr::Error | Expr::Literal(_) => return,
             Expr::Name(name, ref mut params) => {
                 let name_span = Spanned::span(&name);
@@ -192,6 +217,7 @@ impl<'a> Context<'a> {
                     self.ctx
                         .reporter
                         .error(msg, name_span)
+                        .code(codes::WRONG_TYPE_PARAM_COUNT)
                         .span_note(short_msg, name_span)
                         .build();
                 } else {
@@ -203,6 +229,14 @@ impl<'a> Context<'a> {
                 self.check_type(typ);
                 return;
             }
+            Expr::Lambda(ref mut lambda) => {
+                for param in &mut lambda.params {
+                    self.check_type(&mut param.typ);
+                }
+                self.check_type(&mut lambda.return_type);
+                self.check_statement(&mut lambda.body);
+                return;
+            }
         }
         **expr = Expr::Error;
     }
@@ -0,0 +1,264 @@
+use std::collections::{HashMap, HashSet};
+use plank_errors::codes;
+use plank_syntax::position::{Span, Spanned};
+use ast::resolved::{Expr, Function, FunctionType, Pattern, Program, Statement, Struct, StructKind,
+                    Symbol, Type};
+use CompileCtx;
+
+
+const BUILTINS: &[Symbol] = &[
+    ::builtins::SIZE_OF,
+    ::builtins::ALIGN_OF,
+    ::builtins::GETC,
+    ::builtins::PUTC,
+    ::builtins::LEN,
+    ::builtins::ALLOC,
+    ::builtins::FREE,
+    ::builtins::REALLOC,
+    ::builtins::BOUNDS_FAIL,
+    ::builtins::PRINT_I32,
+    ::builtins::PRINT_U32,
+    ::builtins::PRINT_STR,
+    ::builtins::WRITE,
+    ::builtins::READ_I32,
+    ::builtins::READ_LINE,
+    ::builtins::READ,
+    ::builtins::STR_LEN,
+    ::builtins::STR_EQ,
+    ::builtins::STR_COPY,
+    ::builtins::STR_FIND,
+    ::builtins::RUNTIME_STR_EQ,
+    ::builtins::RUNTIME_STR_COPY,
+    ::builtins::RUNTIME_STR_FIND,
+    ::builtins::ASSERT,
+    ::builtins::ASSERT_FAIL,
+    ::builtins::PANIC,
+    ::builtins::PANIC_FAIL,
+    ::builtins::RAND_SEED,
+    ::builtins::RAND_U32,
+    ::builtins::TIME_MS,
+];
+
+/// Warn about functions and structs that are not reachable from `main`,
+/// tests or `extern` functions.
+pub(crate) fn check_program(program: &Program, tests: &[String], ctx: &mut CompileCtx) {
+    // unresolved names would make items look unused,
+    // so don't bother if there are errors already
+    if ctx.reporter.has_errors() {
+        return;
+    }
+    let main = program
+        .functions
+        .iter()
+        .find(|f| ctx.symbols.get_name(*f.name.name) == "main");
+    if main.is_none() {
+        return;
+    }
+
+    let mut ctx = Context::new(program, ctx);
+    for fn_ in &program.functions {
+        let name = ctx.ctx.symbols.get_name(*fn_.name.name);
+        let is_test = tests.iter().any(|test| test == name);
+        if name == "main" || is_test || fn_.fn_type == FunctionType::Extern {
+            ctx.mark_used(*fn_.name.name);
+        }
+    }
+    ctx.walk_used();
+    ctx.report_unused();
+}
+
+struct Context<'a> {
+    ctx: &'a mut CompileCtx,
+    functions: HashMap<Symbol, &'a Function>,
+    structs: &'a HashMap<Symbol, Struct>,
+    used: HashSet<Symbol>,
+    queue: Vec<Symbol>,
+}
+
+impl<'a> Context<'a> {
+    fn new(program: &'a Program, ctx: &'a mut CompileCtx) -> Self {
+        let functions = program
+            .functions
+            .iter()
+            .map(|f| (*f.name.name, f))
+            .collect();
+        Context {
+            ctx,
+            functions,
+            structs: &program.structs,
+            used: HashSet::new(),
+            queue: Vec::new(),
+        }
+    }
+
+    fn mark_used(&mut self, symbol: Symbol) {
+        if self.used.insert(symbol) {
+            self.queue.push(symbol);
+        }
+    }
+
+    fn walk_used(&mut self) {
+        while let Some(symbol) = self.queue.pop() {
+            // struct name is also the name of its constructor,
+            // so symbol might refer to both a function and a struct
+            if let Some(&fn_) = self.functions.get(&symbol) {
+                for param in &fn_.params {
+                    self.walk_type(&param.typ);
+                }
+                self.walk_type(&fn_.return_type);
+                if let Some(ref body) = fn_.body {
+                    self.walk_statement(body);
+                }
+            }
+            let structs = self.structs;
+            if let Some(struct_) = structs.get(&symbol) {
+                for field in &struct_.fields {
+                    self.walk_type(&field.typ);
+                }
+            }
+        }
+    }
+
+    fn walk_statement(&mut self, stmt: &Statement) {
+        match *stmt {
+            Statement::If(ref cond, ref then, ref else_) => {
+                self.walk_expr(cond);
+                self.walk_statement(then);
+                if let Some(ref else_) = *else_ {
+                    self.walk_statement(else_);
+                }
+            }
+            Statement::Loop(ref body) => self.walk_statement(body),
+            Statement::While(ref cond, ref body) => {
+                self.walk_expr(cond);
+                self.walk_statement(body);
+            }
+            Statement::Break | Statement::Continue | Statement::Error => {}
+            Statement::Return(ref expr) | Statement::Expr(ref expr) => self.walk_expr(expr),
+            Statement::Let(_, _, ref typ, ref value) => {
+                self.walk_type(typ);
+                if let Some(ref value) = *value {
+                    self.walk_expr(value);
+                }
+            }
+            Statement::Block(ref stmts) => for stmt in stmts {
+                self.walk_statement(stmt);
+            },
+            Statement::Match(ref value, ref arms) => {
+                self.walk_expr(value);
+                for arm in arms {
+                    if let Pattern::Value(ref value) = *arm.pattern {
+                        self.walk_expr(value);
+                    }
+                    self.walk_statement(&arm.body);
+                }
+            }
+        }
+    }
+
+    fn walk_expr(&mut self, expr: &Expr) {
+        match *expr {
+            Expr::Binary(ref lhs, _, ref rhs) | Expr::Index(ref lhs, ref rhs) => {
+                self.walk_expr(lhs);
+                self.walk_expr(rhs);
+            }
+            Expr::Unary(_, ref expr) | Expr::Field(ref expr, _) => self.walk_expr(expr),
+            Expr::Call(ref callee, ref params) => {
+                self.walk_expr(callee);
+                for param in params {
+                    self.walk_expr(param);
+                }
+            }
+            Expr::Slice(ref expr, ref start, ref end) => {
+                self.walk_expr(expr);
+                self.walk_expr(start);
+                self.walk_expr(end);
+            }
+            Expr::Name(ref name, ref params) => {
+                self.mark_used(**name);
+                for param in params {
+                    self.walk_type(param);
+                }
+            }
+            Expr::Cast(ref expr, ref typ) => {
+                self.walk_expr(expr);
+                self.walk_type(typ);
+            }
+            Expr::Lambda(ref lambda) => {
+                for param in &lambda.params {
+                    self.walk_type(&param.typ);
+                }
+                self.walk_type(&lambda.return_type);
+                self.walk_statement(&lambda.body);
+            }
+            Expr::Literal(_) | Expr::Error => {}
+        }
+    }
+
+    fn walk_type(&mut self, typ: &Type) {
+        match *typ {
+            Type::Concrete(ref name, ref params) => {
+                self.mark_used(**name);
+                for param in params {
+                    self.walk_type(param);
+                }
+            }
+            Type::Pointer(_, ref typ) | Type::Slice(_, ref typ) => self.walk_type(typ),
+            Type::Function(ref params, ref out) => {
+                for param in params {
+                    self.walk_type(param);
+                }
+                self.walk_type(out);
+            }
+            Type::Wildcard |
+            Type::I8 |
+            Type::U8 |
+            Type::I16 |
+            Type::U16 |
+            Type::I32 |
+            Type::U32 |
+            Type::Bool |
+            Type::Unit |
+            Type::Str |
+            Type::Error => {}
+        }
+    }
+
+    fn report_unused(&mut self) {
+        let structs = self.structs;
+        for (&symbol, struct_) in structs {
+            if !self.used.contains(&symbol) {
+                let kind = match struct_.kind {
+                    StructKind::Struct => "struct",
+                    StructKind::Union => "union",
+                };
+                let name = self.ctx.symbols.get_name(symbol);
+                let msg = format!("{} `{}` is never used", kind, name);
+                self.report(msg, Spanned::span(&struct_.name.name));
+            }
+        }
+        let mut unused = self.functions
+            .iter()
+            .filter(|&(symbol, _)| !self.used.contains(symbol))
+            // struct constructors were reported as unused structs
+            .filter(|&(symbol, _)| !structs.contains_key(symbol))
+            .filter(|&(symbol, _)| !BUILTINS.contains(symbol))
+            .map(|(_, &fn_)| fn_)
+            .collect::<Vec<_>>();
+        unused.sort_by_key(|f| Spanned::span(&f.name.name).start);
+        for fn_ in unused {
+            let name = self.ctx.symbols.get_name(*fn_.name.name);
+            let msg = format!("function `{}` is never used", name);
+            self.report(msg, Spanned::span(&fn_.name.name));
+        }
+    }
+
+    fn report(&mut self, msg: String, span: Span) {
+        self.ctx
+            .reporter
+            .lint("unused_items", msg, span)
+            .code(codes::UNUSED_ITEM)
+            .span(span)
+            .build();
+    }
+}
@@ -1,3 +1,4 @@
+use plank_errors::codes;
 use plank_errors::reporter::Builder;
 use plank_syntax::position::{Span, Spanned};
 use ast::resolved::{Function, Program, Struct, Type};
@@ -55,6 +56,7 @@ impl<'a> Context<'a> {
         match **typ {
             Type::Unit |
             Type::Bool |
+            Type::Str |
             Type::Error |
             Type::I8 |
             Type::I16 |
@@ -62,7 +64,7 @@ impl<'a> Context<'a> {
             Type::U8 |
             Type::U16 |
             Type::U32 => {}
-            Type::Pointer(_, ref typ) => self.check_type(typ),
+            Type::Pointer(_, ref typ) | Type::Slice(_, ref typ) => self.check_type(typ),
             Type::Function(ref params, ref out) => {
                 for param in params {
                     self.check_type(param);
@@ -84,7 +86,11 @@ impl<'a> Context<'a> {
                     "wildcard types are not allowed in {}s",
                     self.item_kind.unwrap(),
                 );
-                self.ctx.reporter.error(msg, span).span(span)
+                self.ctx
+                    .reporter
+                    .error(msg, span)
+                    .code(codes::WILDCARD_IN_ITEM)
+                    .span(span)
             }
         });
     }
@@ -0,0 +1,148 @@
+//! Calls from interpreted programs to functions of shared libraries,
+//! for `extern "C"` and `extern "stdcall"` functions without a body.
+
+use std::os::raw::c_void;
+use libffi::middle::{Arg, Cif, CodePtr, Type};
+use libloading::os::unix::{Library, Symbol};
+use plank_ir::{debug, ir};
+use Error;
+
+
+/// Libraries that foreign functions are looked up in, together with
+/// the interpreter itself, which brings in the C library.
+pub struct Libraries {
+    this: Library,
+    libraries: Vec<Library>,
+}
+
+impl Libraries {
+    pub fn open(paths: &[String]) -> Result<Libraries, Error> {
+        let mut libraries = Vec::new();
+        for path in paths {
+            match unsafe { Library::new(path) } {
+                Ok(library) => libraries.push(library),
+                Err(err) => {
+                    return Err(Error::ForeignCall(format!("cannot load `{}`: {}", path, err)));
+                }
+            }
+        }
+        Ok(Libraries {
+            this: Library::this(),
+            libraries,
+        })
+    }
+
+    /// Find a function by its C name, in given libraries first.
+    pub fn find(&self, name: &str) -> Option<CodePtr> {
+        let mut name = name.as_bytes().to_vec();
+        name.push(0);
+        self.libraries.iter().chain(Some(&self.this)).find_map(|library| {
+            let symbol: Symbol<*mut c_void> = unsafe { library.get(&name).ok()? };
+            Some(CodePtr(*symbol))
+        })
+    }
+}
+
+/// How a parameter or the result of a foreign function is passed.
+#[derive(Debug, Copy, Clone)]
+pub enum Kind {
+    Int(ir::Signedness, ir::Size),
+    /// Plank address that is given to C as a host pointer.
+    Pointer,
+    Void,
+}
+
+/// Foreign function and how to call it.
+pub struct Function {
+    code: CodePtr,
+    cif: Cif,
+    pub params: Vec<Kind>,
+    pub result: Kind,
+}
+
+impl Function {
+    /// Describe how to call `code` for a function called `name` that
+    /// is declared like `f`. Types come from its debug info, without it
+    /// integers are assumed to be unsigned. Fails if the function passes
+    /// values that are not integers or pointers.
+    pub fn new(name: &str, code: CodePtr, f: &ir::Function) -> Result<Function, Error> {
+        let debug = f.debug.as_ref();
+        let params = f.parameters
+            .iter()
+            .map(|reg| {
+                let variable = debug.and_then(|debug| {
+                    debug.variables.iter().find(|variable| variable.reg == *reg)
+                });
+                match variable {
+                    Some(variable) => kind(name, &variable.typ),
+                    None => unsigned(name, f.registers[reg].size),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let result = match (debug, f.output_layout) {
+            (Some(debug), _) => kind(name, &debug.return_type)?,
+            (None, Some(layout)) => unsigned(name, layout.size)?,
+            (None, None) => Kind::Void,
+        };
+        let cif = Cif::new(params.iter().map(|&param| ffi_type(param)), ffi_type(result));
+        Ok(Function {
+            code,
+            cif,
+            params,
+            result,
+        })
+    }
+
+    /// Call the function with parameters converted to 64 bits. Host
+    /// is little endian, so smaller types are read from the start of
+    /// their value. The result is returned the same way.
+    pub unsafe fn call(&self, params: &[u64]) -> u64 {
+        let args = params.iter().map(Arg::new).collect::<Vec<_>>();
+        // libffi writes whole registers even for smaller results
+        self.cif.call::<u64>(self.code, &args)
+    }
+}
+
+fn kind(function: &str, typ: &debug::Type) -> Result<Kind, Error> {
+    Ok(match *typ {
+        debug::Type::Unit => Kind::Void,
+        debug::Type::Bool => Kind::Int(ir::Signedness::Unsigned, ir::Size::Bit8),
+        debug::Type::Int(sign, size) => Kind::Int(sign, size),
+        debug::Type::Pointer(_) => Kind::Pointer,
+        debug::Type::Composite(ref name) => {
+            let message = format!("`{}` passes `{}`, which is not a scalar", function, name);
+            return Err(Error::ForeignCall(message));
+        }
+    })
+}
+
+fn unsigned(function: &str, size: u32) -> Result<Kind, Error> {
+    let size = match size {
+        1 => ir::Size::Bit8,
+        2 => ir::Size::Bit16,
+        4 => ir::Size::Bit32,
+        8 => ir::Size::Bit64,
+        _ => {
+            let message = format!("`{}` passes a value of {} bytes", function, size);
+            return Err(Error::ForeignCall(message));
+        }
+    };
+    Ok(Kind::Int(ir::Signedness::Unsigned, size))
+}
+
+fn ffi_type(kind: Kind) -> Type {
+    use plank_ir::ir::Signedness::*;
+    use plank_ir::ir::Size::*;
+    match kind {
+        Kind::Int(Signed, Bit8) => Type::i8(),
+        Kind::Int(Signed, Bit16) => Type::i16(),
+        Kind::Int(Signed, Bit32) => Type::i32(),
+        Kind::Int(Signed, Bit64) => Type::i64(),
+        Kind::Int(Unsigned, Bit8) => Type::u8(),
+        Kind::Int(Unsigned, Bit16) => Type::u16(),
+        Kind::Int(Unsigned, Bit32) => Type::u32(),
+        Kind::Int(Unsigned, Bit64) => Type::u64(),
+        Kind::Pointer => Type::pointer(),
+        Kind::Void => Type::void(),
+    }
+}
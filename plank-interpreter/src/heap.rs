@@ -0,0 +1,105 @@
+//! Bookkeeping of memory that programs get from `alloc`.
+
+use std::collections::BTreeMap;
+
+
+/// Blocks start at multiples of this, which is enough for any value.
+pub const BLOCK_ALIGN: u32 = 8;
+
+/// Decides where blocks of a range of addresses go. Contents of the
+/// memory are kept by the user of the heap.
+#[derive(Debug, Clone)]
+pub struct Heap {
+    start: u32,
+    end: u32,
+    /// Requested sizes of live blocks by their address.
+    blocks: BTreeMap<u32, u32>,
+}
+
+/// Space that a block of given size takes up. Empty blocks take up
+/// some space too, so that every block has a different address.
+fn reserved(size: u32) -> u64 {
+    let align = u64::from(BLOCK_ALIGN);
+    u64::from(size.max(1)).div_ceil(align) * align
+}
+
+impl Heap {
+    /// Create a heap between `start` and `end` addresses. `start`
+    /// must be a multiple of `BLOCK_ALIGN`.
+    pub fn new(start: u32, end: u32) -> Heap {
+        assert_eq!(start % BLOCK_ALIGN, 0);
+        Heap {
+            start,
+            end,
+            blocks: BTreeMap::new(),
+        }
+    }
+
+    /// Address after the space of the last live block.
+    pub fn top(&self) -> u32 {
+        match self.blocks.iter().next_back() {
+            Some((&address, &size)) => (u64::from(address) + reserved(size)) as u32,
+            None => self.start,
+        }
+    }
+
+    /// Size of the live block that starts at given address.
+    pub fn block_size(&self, address: u32) -> Option<u32> {
+        self.blocks.get(&address).cloned()
+    }
+
+    /// Whether `len` bytes from `address` are all within one live block.
+    pub fn contains(&self, address: u32, len: u32) -> bool {
+        match self.blocks.range(..=address).next_back() {
+            Some((&start, &size)) => {
+                u64::from(address) + u64::from(len) <= u64::from(start) + u64::from(size)
+            }
+            None => false,
+        }
+    }
+
+    /// Live blocks by their address, with their sizes.
+    pub fn blocks<'a>(&'a self) -> impl Iterator<Item = (u32, u32)> + 'a {
+        self.blocks.iter().map(|(&address, &size)| (address, size))
+    }
+
+    /// Find room for a block of given size, using the first gap that
+    /// is large enough. Returns `None` if there is no room left.
+    pub fn alloc(&mut self, size: u32) -> Option<u32> {
+        let needed = reserved(size);
+        let mut at = u64::from(self.start);
+        for (&address, &block) in &self.blocks {
+            if u64::from(address) - at >= needed {
+                break;
+            }
+            at = u64::from(address) + reserved(block);
+        }
+        if at + needed > u64::from(self.end) {
+            return None;
+        }
+        self.blocks.insert(at as u32, size);
+        Some(at as u32)
+    }
+
+    /// Change the size of a block if it fits at the same address.
+    /// Returns `false` if it does not, and then the block is unchanged.
+    pub fn resize(&mut self, address: u32, size: u32) -> bool {
+        let limit = match self.blocks.range(address + 1..).next() {
+            Some((&next, _)) => u64::from(next),
+            None => u64::from(self.end),
+        };
+        match self.blocks.get_mut(&address) {
+            Some(block) if u64::from(address) + reserved(size) <= limit => {
+                *block = size;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Forget about a block, returning its size. Returns `None` if
+    /// there is no live block at given address.
+    pub fn free(&mut self, address: u32) -> Option<u32> {
+        self.blocks.remove(&address)
+    }
+}
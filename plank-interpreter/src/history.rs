@@ -0,0 +1,66 @@
+//! Bookkeeping for recording recent steps of the interpreter, so that
+//! a trap can be followed back to where values went wrong.
+
+use std::collections::{BTreeMap, VecDeque};
+use {Frame, Step};
+
+
+/// Keeps the last steps that the program made, and memory that
+/// the running step changed.
+#[derive(Debug, Clone)]
+pub struct History {
+    limit: usize,
+    steps: VecDeque<Step>,
+    /// Code that the running step executes.
+    running: Option<Frame>,
+    /// Old values of bytes that the running step changed, by address.
+    changed: BTreeMap<u32, u8>,
+}
+
+impl History {
+    /// Create a history that remembers up to `limit` steps.
+    pub fn new(limit: usize) -> History {
+        History {
+            limit,
+            steps: VecDeque::new(),
+            running: None,
+            changed: BTreeMap::new(),
+        }
+    }
+
+    /// Start recording a step of given code. The previous
+    /// step must be finished first.
+    pub fn start(&mut self, frame: Frame) {
+        assert!(self.running.is_none(), "previous step is not finished");
+        self.running = Some(frame);
+    }
+
+    /// Remember that a byte was changed from given value.
+    /// Only the first change of each byte in a step is kept.
+    pub fn record_write(&mut self, address: u32, old: u8) {
+        if self.running.is_some() {
+            self.changed.entry(address).or_insert(old);
+        }
+    }
+
+    /// Stop recording the running step, returning its code and
+    /// the bytes it changed. Returns `None` if no step is running.
+    pub fn finish(&mut self) -> Option<(Frame, BTreeMap<u32, u8>)> {
+        let frame = self.running.take()?;
+        Some((frame, ::std::mem::take(&mut self.changed)))
+    }
+
+    /// Add a finished step, forgetting the oldest one
+    /// if there are too many.
+    pub fn push(&mut self, step: Step) {
+        self.steps.push_back(step);
+        if self.steps.len() > self.limit {
+            self.steps.pop_front();
+        }
+    }
+
+    /// Remembered steps, most recent first.
+    pub fn steps(&self) -> Vec<Step> {
+        self.steps.iter().rev().cloned().collect()
+    }
+}
@@ -1,18 +1,167 @@
+extern crate plank_errors;
 extern crate plank_ir;
+#[cfg(feature = "ffi")]
+extern crate libffi;
+#[cfg(feature = "ffi")]
+extern crate libloading;
 
-use std::collections::HashMap;
+#[cfg(feature = "ffi")]
+mod ffi;
+pub mod heap;
+mod history;
+pub mod observer;
+mod sanitizer;
+
+use std::collections::{BTreeMap, HashMap};
 use std::io::{self, Read, Write};
-use plank_ir::{ir, Program};
+use std::time::Instant;
+#[cfg(feature = "ffi")]
+use std::rc::Rc;
+use plank_errors::position::Span;
+use plank_ir::{debug, ir, Program};
+use plank_ir::ir::Location;
+use heap::Heap;
+use history::History;
+use sanitizer::Sanitizer;
+
+pub use observer::Observer;
 
 
 #[derive(Debug)]
 pub enum Error {
     BadDeref,
-    DivisionByZero,
+    /// Division or remainder by zero, described like `7 / 0 in i32`,
+    /// with the location of the division if it is known.
+    DivisionByZero(String, Option<Location>),
+    /// Program indexed a slice or string with given index, but it
+    /// had given length. Checked only if the program was compiled
+    /// with bounds checks.
+    IndexOutOfBounds(u32, u32),
+    /// Arithmetic that traps on overflow did, described like
+    /// `127 + 1 in i8`.
+    ArithmeticOverflow(String),
+    /// `assert` failed with given message, at given location if it is known.
+    AssertionFailed(String, Option<Location>),
+    /// `panic` was called with given message, like `AssertionFailed`.
+    Panicked(String, Option<Location>),
     MissingSymbol(ir::Symbol),
+    /// A shared library could not be loaded, or a function from
+    /// one returned a pointer that the program can't use.
+    ForeignCall(String),
     Io(io::Error),
     ReadUndef,
     ExecutedUnreachable,
+    /// Program ran out of fuel given in `Options`.
+    ExecutionLimit,
+    /// Program made more nested calls than `Options` allow.
+    StackOverflow,
+    /// Program needed more memory than `Options` allow.
+    OutOfMemory,
+    /// `free` or `realloc` got a pointer that `alloc` did not return,
+    /// or that was already freed.
+    BadFree,
+    /// Heap blocks were not freed when the program exited, and
+    /// `Options` asked to check for that.
+    MemoryLeak(Vec<Leak>),
+    /// Program computed with memory that was never written to, which
+    /// is only detected with `Options::sanitize`.
+    UninitializedRead,
+    /// Program stopped because of given error. Calls that were
+    /// running at the time are listed innermost first.
+    Trap(Box<Error>, Vec<Frame>),
+    /// Program stopped because of given error, and `Options` asked to
+    /// remember steps before it. Steps are listed most recent first.
+    History(Box<Error>, Vec<Step>),
+}
+
+/// Deep recursion can leave thousands of frames, only the innermost
+/// ones are shown. Leaked blocks are cut short the same way.
+const MAX_PRINTED_FRAMES: usize = 20;
+
+/// Function call that was running when the program failed.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// Source name of the function, or its symbol if it is not known.
+    pub function: String,
+    /// Start of the code that the function was executing, if known.
+    pub location: Option<Location>,
+}
+
+impl ::std::fmt::Display for Frame {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self.location {
+            Some(ref location) => write!(f, "{} at {}", self.function, location),
+            None => write!(f, "{}", self.function),
+        }
+    }
+}
+
+/// Heap block that was still allocated when the program exited.
+#[derive(Debug, Clone)]
+pub struct Leak {
+    pub size: u32,
+    /// Call to `alloc` or `realloc` that returned the block.
+    pub allocated_at: Frame,
+}
+
+impl ::std::fmt::Display for Leak {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "{} bytes allocated in {}", self.size, self.allocated_at)
+    }
+}
+
+/// Instruction or block end that the program executed,
+/// together with memory that it changed.
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub frame: Frame,
+    pub changes: Vec<Change>,
+}
+
+impl ::std::fmt::Display for Step {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "{}", self.frame)?;
+        for (index, change) in self.changes.iter().enumerate() {
+            let separator = if index == 0 { ":" } else { "," };
+            write!(f, "{} {}", separator, change)?;
+        }
+        Ok(())
+    }
+}
+
+/// Value of a variable, register or other memory that a step changed.
+#[derive(Debug, Clone)]
+pub struct Change {
+    /// Source name of the variable, register like `%3` if it is not
+    /// known, or address like `*0x80000000` for other memory.
+    pub place: String,
+    pub old: String,
+    pub new: String,
+}
+
+impl ::std::fmt::Display for Change {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "{} = {} (was {})", self.place, self.new, self.old)
+    }
+}
+
+impl Error {
+    /// The error without a backtrace.
+    pub fn kind(&self) -> &Error {
+        match *self {
+            Error::Trap(ref err, _) | Error::History(ref err, _) => err.kind(),
+            ref err => err,
+        }
+    }
+
+    /// Whether the program stopped because of a bug in it, rather than
+    /// because its input, output or foreign functions failed.
+    pub fn is_trap(&self) -> bool {
+        !matches!(
+            *self.kind(),
+            Error::Io(_) | Error::ForeignCall(_) | Error::MissingSymbol(_)
+        )
+    }
 }
 
 impl ::std::convert::From<io::Error> for Error {
@@ -25,22 +174,273 @@ impl ::std::fmt::Display for Error {
     fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
         match *self {
             Error::BadDeref => write!(f, "dereferenced invalid pointer"),
-            Error::DivisionByZero => write!(f, "division by zero"),
+            Error::DivisionByZero(ref operation, ref location) => {
+                write!(f, "division by zero: {}", operation)?;
+                if let Some(ref location) = *location {
+                    write!(f, " at {}", location)?;
+                }
+                Ok(())
+            }
+            Error::IndexOutOfBounds(index, len) => {
+                write!(f, "index out of bounds: the index is {} but the length is {}", index, len)
+            }
+            Error::ArithmeticOverflow(ref operation) => {
+                write!(f, "arithmetic overflow: {}", operation)
+            }
+            Error::AssertionFailed(ref message, ref location) |
+            Error::Panicked(ref message, ref location) => {
+                if let Error::Panicked(..) = *self {
+                    write!(f, "panicked: {}", message)?;
+                } else {
+                    write!(f, "assertion failed: {}", message)?;
+                }
+                if let Some(ref location) = *location {
+                    write!(f, " at {}", location)?;
+                }
+                Ok(())
+            }
             Error::MissingSymbol(ref sym) => {
                 write!(f, "missing definition for symbol `{}`", sym.0)
             }
+            Error::ForeignCall(ref what) => write!(f, "foreign call failed: {}", what),
             Error::Io(ref err) => {
                 write!(f, "io error: {}", err)
             }
             Error::ReadUndef => write!(f, "read undef value"),
             Error::ExecutedUnreachable => write!(f, "reached unreachable instruction"),
+            Error::ExecutionLimit => write!(f, "execution limit exceeded"),
+            Error::StackOverflow => write!(f, "stack overflow"),
+            Error::OutOfMemory => write!(f, "out of memory"),
+            Error::BadFree => write!(f, "freed invalid pointer"),
+            Error::UninitializedRead => write!(f, "used uninitialized memory"),
+            Error::MemoryLeak(ref leaks) => {
+                let plural = if leaks.len() == 1 { "" } else { "s" };
+                write!(f, "leaked {} heap block{}", leaks.len(), plural)?;
+                for leak in leaks.iter().take(MAX_PRINTED_FRAMES) {
+                    write!(f, "\n    {}", leak)?;
+                }
+                if leaks.len() > MAX_PRINTED_FRAMES {
+                    write!(f, "\n    ... {} more", leaks.len() - MAX_PRINTED_FRAMES)?;
+                }
+                Ok(())
+            }
+            Error::Trap(ref err, ref frames) => {
+                write!(f, "{}", err)?;
+                for frame in frames.iter().take(MAX_PRINTED_FRAMES) {
+                    write!(f, "\n    in {}", frame)?;
+                }
+                if frames.len() > MAX_PRINTED_FRAMES {
+                    write!(f, "\n    ... {} more", frames.len() - MAX_PRINTED_FRAMES)?;
+                }
+                Ok(())
+            }
+            Error::History(ref err, ref steps) => {
+                write!(f, "{}", err)?;
+                let plural = if steps.len() == 1 { "" } else { "s" };
+                write!(f, "\nlast {} step{}, most recent first:", steps.len(), plural)?;
+                for step in steps {
+                    write!(f, "\n    {}", step)?;
+                }
+                Ok(())
+            }
         }
     }
 }
 
+/// Limits for running untrusted programs, and what they get from the
+/// environment.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// How many instructions and block ends the program can execute
+    /// before it is stopped. `None` means no limit.
+    pub fuel: Option<u64>,
+    /// Largest number of calls that can be running at once,
+    /// including `main`. `None` means no limit.
+    pub call_depth: Option<usize>,
+    /// Largest size of program memory in bytes, which holds strings,
+    /// registers of running functions and the heap. `None` means no limit.
+    /// `alloc` returns null instead of going over it.
+    pub memory: Option<usize>,
+    /// Fail with `Error::MemoryLeak` if the program exits
+    /// without freeing all heap blocks.
+    pub check_leaks: bool,
+    /// Stop programs that use memory before writing to it, or that
+    /// access memory outside of a live heap block, string or register,
+    /// even if it happens to be within program memory.
+    pub sanitize: bool,
+    /// Remember memory changed by the last N executed instructions and
+    /// block ends, and list them if the program traps. `None` turns
+    /// recording off.
+    pub history: Option<usize>,
+    /// Arguments of the program, starting with its name. They are
+    /// given to `main` if it takes `argc` and `argv`.
+    pub args: Vec<String>,
+    /// Make `time_ms` count executed instructions and block ends
+    /// instead of real time, a millisecond for every thousand of them,
+    /// so that programs that measure time print the same on every run.
+    pub virtual_time: bool,
+    /// Shared libraries to look up `extern "C"` and `extern "stdcall"`
+    /// functions without a body in, after which the C library is
+    /// searched. Needs the `ffi` feature.
+    ///
+    /// # Examples
+    ///
+    /// Parameters and results are described by debug info, without
+    /// it they are passed as unsigned integers.
+    ///
+    /// ```rust
+    /// # extern crate plank_ir;
+    /// # extern crate plank_interpreter;
+    /// # #[cfg(feature = "ffi")] {
+    /// use plank_ir::debug::{Composite, Function, Type, Variable};
+    /// use plank_ir::ir::{Reg, Signedness, Size, Symbol};
+    /// use plank_interpreter::{run_program_with_options, Error, Options};
+    ///
+    /// fn describe(program: &mut plank_ir::Program, name: &str, params: &[Type], result: Type) {
+    ///     let variables = params.iter().enumerate().map(|(index, typ)| Variable {
+    ///         name: format!("p{}", index),
+    ///         typ: typ.clone(),
+    ///         reg: Reg(index as u32),
+    ///         is_parameter: true,
+    ///     });
+    ///     let function = program.functions.get_mut(&Symbol(format!("fn_{}", name).into()));
+    ///     function.unwrap().debug = Some(Function {
+    ///         name: name.into(),
+    ///         span: None,
+    ///         return_type: result,
+    ///         variables: variables.collect(),
+    ///     });
+    /// }
+    ///
+    /// let source = "
+    /// function builtin_putc(%0)
+    ///     register %0: (size 1, align 1, atomic)
+    ///
+    /// function fn_memset(%0, %1, %2): (size 4, align 4, atomic) extern \"C\"
+    ///     register %0: (size 4, align 4, atomic)
+    ///     register %1: (size 4, align 4, atomic)
+    ///     register %2: (size 4, align 4, atomic)
+    ///
+    /// function fn_abs(%0): (size 4, align 4, atomic) extern \"C\"
+    ///     register %0: (size 4, align 4, atomic)
+    ///
+    /// function fn_malloc(%0): (size 4, align 4, atomic) extern \"C\"
+    ///     register %0: (size 4, align 4, atomic)
+    ///
+    /// function fn_main(): (size 4, align 4, atomic)
+    ///     register %0: (size 2, align 1, composite)
+    ///     register %1: (size 4, align 4, atomic)
+    ///     register %2: (size 4, align 4, atomic)
+    ///     register %3: (size 1, align 1, atomic)
+    ///     register %4: (size 4, align 4, atomic)
+    /// start:
+    ///     goto label_0
+    /// label_0:
+    ///     init %0
+    ///     %1 = address %0[0]
+    ///     %2 = call fn_memset(%1, 66_b32, 2_b32)
+    ///     %3 = deref (%2 + 1)
+    ///     callproc builtin_putc(%3)
+    ///     %4 = call fn_abs(4294967226_b32)
+    ///     return %4
+    /// ";
+    /// let u8_pointer = Type::Pointer(Box::new(Type::Int(Signedness::Unsigned, Size::Bit8)));
+    /// let i32_type = Type::Int(Signedness::Signed, Size::Bit32);
+    /// let u32_type = Type::Int(Signedness::Unsigned, Size::Bit32);
+    /// let mut program = plank_ir::parse_program(source).unwrap();
+    /// let params = [u8_pointer.clone(), i32_type.clone(), u32_type.clone()];
+    /// describe(&mut program, "memset", &params, u8_pointer.clone());
+    /// describe(&mut program, "abs", &[i32_type.clone()], i32_type.clone());
+    ///
+    /// // pointers to program memory go to C and come back
+    /// let mut output = Vec::new();
+    /// let options = Options::default();
+    /// let code = run_program_with_options(&program, &b""[..], &mut output, &options).unwrap();
+    /// assert_eq!((code, &output[..]), (70, &b"B"[..]));
+    ///
+    /// // but other pointers can't be used by the program
+    /// let source = source.replace("call fn_abs(4294967226_b32)", "call fn_malloc(4_b32)");
+    /// let mut program = plank_ir::parse_program(&source).unwrap();
+    /// describe(&mut program, "memset", &params, u8_pointer.clone());
+    /// describe(&mut program, "malloc", &[u32_type.clone()], u8_pointer.clone());
+    /// let err = run_program_with_options(&program, &b""[..], Vec::new(), &options).unwrap_err();
+    /// match *err.kind() {
+    ///     Error::ForeignCall(ref message) => {
+    ///         assert_eq!(message, "`malloc` returned a pointer outside of memory");
+    ///     }
+    ///     ref other => panic!("unexpected error {:?}", other),
+    /// }
+    ///
+    /// // and values that are not scalars can't be passed at all
+    /// let pair = Composite { is_union: false, size: 4, fields: Vec::new() };
+    /// program.debug_types.insert("Pair".into(), pair);
+    /// describe(&mut program, "malloc", &[Type::Composite("Pair".into())], u8_pointer.clone());
+    /// let err = run_program_with_options(&program, &b""[..], Vec::new(), &options).unwrap_err();
+    /// match *err.kind() {
+    ///     Error::ForeignCall(ref message) => {
+    ///         assert_eq!(message, "`malloc` passes `Pair`, which is not a scalar");
+    ///     }
+    ///     ref other => panic!("unexpected error {:?}", other),
+    /// }
+    /// # }
+    /// ```
+    pub libraries: Vec<String>,
+    /// Function that the program starts from instead of `fn_main`. It
+    /// must return an exit code like `main`, and can't take arguments.
+    pub entry: Option<ir::Symbol>,
+}
+
+/// Heap blocks are placed from this address, so that they
+/// don't get in the way when the stack grows.
+const HEAP_START: u32 = 0x8000_0000;
+
+/// State of `rand_u32` before the program calls `rand_seed`,
+/// or after it calls it with zero.
+const RAND_START: u32 = 2_463_534_242;
+
+fn is_builtin(symbol: &ir::Symbol) -> bool {
+    matches!(
+        &*symbol.0,
+        "builtin_getc" | "builtin_putc" | "builtin_alloc" | "builtin_free" | "builtin_realloc" |
+        "builtin_bounds_fail" | "builtin_print_i32" | "builtin_print_u32" | "builtin_write" |
+        "builtin_read_i32" | "builtin_read_line" | "builtin_str_eq" | "builtin_str_copy" |
+        "builtin_str_find" | "builtin_assert_fail" | "builtin_panic_fail" | "builtin_rand_seed" |
+        "builtin_rand_u32" | "builtin_time_ms"
+    )
+}
+
 pub fn run_program<R: Read, W: Write>(program: &Program, input: R, output: W) -> Result<i32, Error> {
+    run_program_with_options(program, input, output, &Options::default())
+}
+
+pub fn run_program_with_options<R: Read, W: Write>(
+    program: &Program,
+    input: R,
+    output: W,
+    options: &Options,
+) -> Result<i32, Error> {
+    run_program_with_observer(program, input, output, options, ())
+}
+
+/// Run a program, telling `observer` about calls, returns, lines
+/// and memory writes as they happen.
+pub fn run_program_with_observer<R: Read, W: Write, O: Observer>(
+    program: &Program,
+    input: R,
+    output: W,
+    options: &Options,
+    observer: O,
+) -> Result<i32, Error> {
     plank_ir::validate_ir(program).expect("invalid ir");
-    Vm::new(program, input, output)?.run()
+    let mut vm = Vm::new(program, input, output, options, observer)?;
+    let exit_code = match vm.run() {
+        Ok(code) => code,
+        Err(err) => return Err(vm.add_history(err)),
+    };
+    if options.check_leaks && vm.heap.blocks().next().is_some() {
+        return Err(Error::MemoryLeak(vm.leaks()));
+    }
+    Ok(exit_code)
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -50,10 +450,26 @@ enum Value {
     Byte(u8),
     Word(u16),
     DoubleWord(u32),
+    QuadWord(u64),
+}
+
+impl Value {
+    /// Size of the value in bytes, if it is known.
+    fn size(&self) -> Option<u32> {
+        match *self {
+            Value::AddressRange(_, len) => Some(len),
+            Value::FromAddress(_) => None,
+            Value::Byte(_) => Some(1),
+            Value::Word(_) => Some(2),
+            Value::DoubleWord(_) => Some(4),
+            Value::QuadWord(_) => Some(8),
+        }
+    }
 }
 
 struct StackFrame<'a> {
     stack_start: usize,
+    symbol: &'a ir::Symbol,
     function: &'a ir::Function,
     registers: HashMap<ir::Reg, u32>,
     current_block: ir::BlockId,
@@ -61,46 +477,103 @@ struct StackFrame<'a> {
     return_address: Option<u32>,
 }
 
-struct Vm<'a, R, W> {
+struct Vm<'a, R, W, O> {
     input: R,
     output: W,
+    observer: O,
+    /// Source line of the code that ran last, so that the observer
+    /// hears only about new lines.
+    line: Option<u32>,
     program: &'a Program,
     memory: Vec<u8>,
     frames: Vec<StackFrame<'a>>,
     current_frame: StackFrame<'a>,
     strings: HashMap<Vec<u8>, u32>,
     symbol_ids: HashMap<ir::Symbol, u32>,
-    symbols_by_id: HashMap<u32, ir::Symbol>,
+    symbols_by_id: HashMap<u32, &'a ir::Symbol>,
+    heap: Heap,
+    /// Contents of the heap, starting from `HEAP_START`.
+    heap_memory: Vec<u8>,
+    /// Where live heap blocks were allocated, by their address.
+    allocated_at: HashMap<u32, Frame>,
+    sanitizer: Option<Sanitizer>,
+    history: Option<History>,
+    fuel: Option<u64>,
+    call_depth: Option<usize>,
+    memory_limit: Option<usize>,
+    /// State of the xorshift generator of `rand_u32`, never zero.
+    rand_state: u32,
+    started: Instant,
+    virtual_time: bool,
+    /// Number of instructions and block ends that were executed.
+    steps: u64,
+    /// Functions of shared libraries that the program calls, and the
+    /// libraries, which must stay loaded while they can be called.
+    #[cfg(feature = "ffi")]
+    foreign: HashMap<&'a ir::Symbol, Rc<ffi::Function>>,
+    #[cfg(feature = "ffi")]
+    _libraries: ffi::Libraries,
 }
 
-impl<'a, R: Read, W: Write> Vm<'a, R, W> {
-    fn new(program: &'a Program, input: R, output: W) -> Result<Self, Error> {
-        let main_symbol = ir::Symbol("main".into());
-        let main = match program.functions.get(&main_symbol) {
-            Some(f) => f,
+impl<'a, R: Read, W: Write, O: Observer> Vm<'a, R, W, O> {
+    fn new(
+        program: &'a Program,
+        input: R,
+        output: W,
+        options: &Options,
+        observer: O,
+    ) -> Result<Self, Error> {
+        let main_symbol = match options.entry {
+            Some(ref entry) => entry.clone(),
+            None => ir::Symbol("fn_main".into()),
+        };
+        let (main_symbol, main) = match program.functions.get_key_value(&main_symbol) {
+            Some(entry) => entry,
             None => return Err(Error::MissingSymbol(main_symbol)),
         };
         let block = match main.start_block {
             Some(block) => block,
-            None => return Err(Error::MissingSymbol(main_symbol)),
+            None => return Err(Error::MissingSymbol(main_symbol.clone())),
         };
         let main_frame = StackFrame {
             stack_start: 4,
+            symbol: main_symbol,
             function: main,
             registers: HashMap::new(),
             current_block: block,
             current_op: 0,
             return_address: Some(0),
         };
+        #[cfg(feature = "ffi")]
+        let libraries = ffi::Libraries::open(&options.libraries)?;
+        #[cfg(feature = "ffi")]
+        let mut foreign = HashMap::new();
+        #[cfg(feature = "ffi")]
+        for (symbol, f) in &program.functions {
+            // like in native code, they are found by their plain name
+            let convention = f.calling_convention != program.target.calling_convention;
+            if f.start_block.is_none() && convention && !is_builtin(symbol) {
+                let name = symbol.0.trim_start_matches("fn_");
+                if let Some(code) = libraries.find(name) {
+                    foreign.insert(symbol, Rc::new(ffi::Function::new(name, code, f)?));
+                }
+            }
+        }
+        #[cfg(not(feature = "ffi"))]
+        {
+            if !options.libraries.is_empty() {
+                let message = "interpreter was built without the `ffi` feature";
+                return Err(Error::ForeignCall(message.into()));
+            }
+        }
         let mut symbol_ids = HashMap::new();
         let mut symbols_by_id = HashMap::new();
         for (index, (symbol, f)) in program.functions.iter().enumerate() {
-            if f.start_block.is_some() ||
-                &*symbol.0 == "builtin_getc" ||
-                &*symbol.0 == "builtin_putc"
-            {
+            #[cfg(feature = "ffi")]
+            let is_builtin = |symbol| is_builtin(symbol) || foreign.contains_key(symbol);
+            if f.start_block.is_some() || is_builtin(symbol) {
                 symbol_ids.insert(symbol.clone(), index as u32);
-                symbols_by_id.insert(index as u32, symbol.clone());
+                symbols_by_id.insert(index as u32, symbol);
             }
         }
         let mut strings = HashMap::new();
@@ -113,9 +586,38 @@ impl<'a, R: Read, W: Write> Vm<'a, R, W> {
                 }
             }
         }
+        // arguments are placed after strings, followed by `argv`
+        let mut arg_addresses = Vec::new();
+        for arg in &options.args {
+            arg_addresses.push(memory.len() as u32);
+            memory.extend(arg.bytes());
+            memory.push(0);
+        }
+        let argv = memory.len() as u32;
+        let pointer_size = program.target.pointer_size.in_bytes();
+        for &address in arg_addresses.iter().chain(Some(&0)) {
+            let bytes = u64::from(address).to_le_bytes();
+            memory.extend(&bytes[..pointer_size as usize]);
+        }
+        let sanitizer = if options.sanitize {
+            let mut sanitizer = Sanitizer::new(HEAP_START, memory.len());
+            for (string, &address) in &strings {
+                sanitizer.add_object(address, string.len() as u32, true);
+            }
+            for (arg, &address) in options.args.iter().zip(&arg_addresses) {
+                sanitizer.add_object(address, arg.len() as u32 + 1, true);
+            }
+            let argv_size = (arg_addresses.len() as u32 + 1) * pointer_size;
+            sanitizer.add_object(argv, argv_size, true);
+            Some(sanitizer)
+        } else {
+            None
+        };
         let mut vm = Vm {
             input,
             output,
+            observer,
+            line: None,
             program,
             memory,
             frames: Vec::new(),
@@ -123,9 +625,40 @@ impl<'a, R: Read, W: Write> Vm<'a, R, W> {
             strings,
             symbol_ids,
             symbols_by_id,
+            heap: Heap::new(HEAP_START, !0),
+            heap_memory: Vec::new(),
+            allocated_at: HashMap::new(),
+            sanitizer,
+            history: options.history.map(History::new),
+            fuel: options.fuel,
+            call_depth: options.call_depth,
+            memory_limit: options.memory,
+            rand_state: RAND_START,
+            started: Instant::now(),
+            virtual_time: options.virtual_time,
+            steps: 0,
+            #[cfg(feature = "ffi")]
+            foreign,
+            #[cfg(feature = "ffi")]
+            _libraries: libraries,
         };
-        let regs = vm.allocate_registers(&vm.current_frame.function.registers);
+        let regs = vm.allocate_registers(&vm.current_frame.function.registers)?;
         vm.current_frame.registers = regs;
+        vm.observer.on_call(function_name(&vm.current_frame), 1);
+        if let [argc_reg, argv_reg] = main.parameters[..] {
+            let argc = (options.args.len() as u32).to_le_bytes();
+            let argv = u64::from(argv).to_le_bytes();
+            let argc_at = vm.current_frame.registers[&argc_reg];
+            let argv_at = vm.current_frame.registers[&argv_reg];
+            for (i, &byte) in argc.iter().enumerate() {
+                vm.set_byte(argc_at + i as u32, byte);
+            }
+            for (i, &byte) in argv[..pointer_size as usize].iter().enumerate() {
+                vm.set_byte(argv_at + i as u32, byte);
+            }
+            vm.observe_write(argc_at, argc.len() as u32);
+            vm.observe_write(argv_at, pointer_size);
+        }
         Ok(vm)
     }
 
@@ -133,16 +666,74 @@ impl<'a, R: Read, W: Write> Vm<'a, R, W> {
         &self.current_frame.function.blocks[&self.current_frame.current_block]
     }
 
-    fn allocate_registers(&mut self, registers: &HashMap<ir::Reg, ir::Layout>) -> HashMap<ir::Reg, u32> {
+    fn allocate_registers(
+        &mut self,
+        registers: &HashMap<ir::Reg, ir::Layout>,
+    ) -> Result<HashMap<ir::Reg, u32>, Error> {
+        let size = registers.values().map(|layout| layout.size as usize).sum::<usize>();
+        let used = self.memory.len() + size;
+        let over_limit = self.memory_limit
+            .is_some_and(|limit| used + self.heap_memory.len() > limit);
+        // the stack must not grow into the heap
+        if over_limit || used > HEAP_START as usize {
+            return Err(Error::OutOfMemory);
+        }
         let mut result = HashMap::new();
         for (&reg, &layout) in registers {
             let at = self.memory.len() as u32;
             for _ in 0..layout.size {
                 self.memory.push(0);
             }
+            if let Some(ref mut sanitizer) = self.sanitizer {
+                sanitizer.add_object(at, layout.size, false);
+            }
             result.insert(reg, at);
         }
-        result
+        Ok(result)
+    }
+
+    /// Start running a called function.
+    fn push_frame(&mut self, frame: StackFrame<'a>) -> Result<(), Error> {
+        // frames that are waiting, current one and the new one
+        if let Some(limit) = self.call_depth {
+            if self.frames.len() + 2 > limit {
+                return Err(Error::StackOverflow);
+            }
+        }
+        self.frames.push(::std::mem::replace(&mut self.current_frame, frame));
+        self.line = None;
+        let depth = self.frames.len() + 1;
+        self.observer.on_call(function_name(&self.current_frame), depth);
+        Ok(())
+    }
+
+    /// Tell the observer that the current function is returning.
+    fn observe_return(&mut self) {
+        self.line = None;
+        let depth = self.frames.len() + 1;
+        self.observer.on_return(function_name(&self.current_frame), depth);
+    }
+
+    /// Tell the observer about code from given span if it
+    /// is on another line than the code that ran before.
+    fn observe_line(&mut self, span: Option<Span>) {
+        if let Some(span) = span {
+            if self.line != Some(span.start.line) {
+                self.line = Some(span.start.line);
+                self.observer.on_statement(function_name(&self.current_frame), span);
+            }
+        }
+    }
+
+    /// Tell the observer about bytes that were written to memory.
+    fn observe_write(&mut self, address: u32, len: u32) {
+        let bytes = if address >= HEAP_START {
+            let at = (address - HEAP_START) as usize;
+            &self.heap_memory[at..at + len as usize]
+        } else {
+            &self.memory[address as usize..(address + len) as usize]
+        };
+        self.observer.on_memory_write(address, bytes);
     }
 
     fn load_8bit(&self, val: &ir::Value) -> u8 {
@@ -165,7 +756,7 @@ impl<'a, R: Read, W: Write> Vm<'a, R, W> {
             ir::Value::Int(i, ir::Size::Bit16) => i as u16,
             ir::Value::Reg(reg) => {
                 let reg_at = self.current_frame.registers[&reg] as usize;
-                if reg_at + 4 <= self.memory.len() {
+                if reg_at + 2 <= self.memory.len() {
                     let b1 = self.memory[reg_at + 0] as u16;
                     let b2 = self.memory[reg_at + 1] as u16;
                     (b2 << 8) | (b1 << 0)
@@ -177,6 +768,487 @@ impl<'a, R: Read, W: Write> Vm<'a, R, W> {
         }
     }
 
+    fn load_64bit(&self, val: &ir::Value) -> u64 {
+        match *val {
+            ir::Value::Int(i, ir::Size::Bit64) => i,
+            ir::Value::Reg(reg) => {
+                let reg_at = self.current_frame.registers[&reg] as usize;
+                if reg_at + 8 <= self.memory.len() {
+                    let mut value = 0;
+                    for i in (0..8).rev() {
+                        value = (value << 8) | u64::from(self.memory[reg_at + i]);
+                    }
+                    value
+                } else {
+                    panic!("register out of bounds")
+                }
+            }
+            ir::Value::Bytes(ref s) => u64::from(self.strings[s]),
+            ir::Value::Symbol(ref sym) => u64::from(self.symbol_ids[sym]),
+            _ => panic!("bad 64 bit value"),
+        }
+    }
+
+    /// Read an integer of given size, zero extended.
+    fn load_int(&self, val: &ir::Value, size: ir::Size) -> u64 {
+        match size {
+            ir::Size::Bit8 => u64::from(self.load_8bit(val)),
+            ir::Size::Bit16 => u64::from(self.load_16bit(val)),
+            ir::Size::Bit32 => u64::from(self.load_32bit(val)),
+            ir::Size::Bit64 => self.load_64bit(val),
+        }
+    }
+
+    /// Read a pointer sized value. Addresses always fit into 32 bits,
+    /// but pointers of 64 bit programs take up 8 bytes.
+    fn load_address(&self, val: &ir::Value) -> u32 {
+        match self.program.target.pointer_size {
+            ir::Size::Bit64 => self.load_64bit(val) as u32,
+            _ => self.load_32bit(val),
+        }
+    }
+
+    /// Find where `len` bytes pointed to by `pointer` (moved by
+    /// `offset`) are. Null and out of bounds pointers are errors.
+    fn deref_address(&self, pointer: &ir::Value, offset: u32, len: u32) -> Result<u32, Error> {
+        let at = self.load_address(pointer).checked_add(offset).ok_or(Error::BadDeref)?;
+        let in_bounds = if at >= HEAP_START {
+            (at - HEAP_START) as usize + len as usize <= self.heap_memory.len()
+        } else {
+            at >= 4 && at as usize + len as usize <= self.memory.len()
+        };
+        let in_object = match self.sanitizer {
+            Some(_) if at >= HEAP_START => self.heap.contains(at, len),
+            Some(ref sanitizer) => sanitizer.is_in_object(at, len),
+            None => true,
+        };
+        if in_bounds && in_object {
+            Ok(at)
+        } else {
+            Err(Error::BadDeref)
+        }
+    }
+
+    fn byte(&self, address: u32) -> u8 {
+        if address >= HEAP_START {
+            self.heap_memory[(address - HEAP_START) as usize]
+        } else {
+            self.memory[address as usize]
+        }
+    }
+
+    fn set_byte(&mut self, address: u32, value: u8) {
+        let byte = if address >= HEAP_START {
+            &mut self.heap_memory[(address - HEAP_START) as usize]
+        } else {
+            &mut self.memory[address as usize]
+        };
+        let old = ::std::mem::replace(byte, value);
+        if let Some(ref mut history) = self.history {
+            history.record_write(address, old);
+        }
+        if let Some(ref mut sanitizer) = self.sanitizer {
+            sanitizer.set_initialized(address, true);
+        }
+    }
+
+    /// Make `heap_memory` as large as live heap blocks need. Returns
+    /// `false` without changing anything if that goes over the limit.
+    fn fit_heap_memory(&mut self) -> bool {
+        let size = (self.heap.top() - HEAP_START) as usize;
+        if let Some(limit) = self.memory_limit {
+            if self.memory.len() + size > limit {
+                return false;
+            }
+        }
+        self.heap_memory.resize(size, 0);
+        if let Some(ref mut sanitizer) = self.sanitizer {
+            sanitizer.resize_heap(size);
+        }
+        true
+    }
+
+    /// Clear new heap memory. The sanitizer still considers it to be
+    /// uninitialized, and history does not list it as changed.
+    fn zero_heap(&mut self, from: u32, to: u32) {
+        for address in from..to {
+            self.heap_memory[(address - HEAP_START) as usize] = 0;
+        }
+        if let Some(ref mut sanitizer) = self.sanitizer {
+            for address in from..to {
+                sanitizer.set_initialized(address, false);
+            }
+        }
+    }
+
+    /// Allocate a zeroed heap block, returns null if there is no room.
+    fn heap_alloc(&mut self, size: u32) -> u32 {
+        let address = match self.heap.alloc(size) {
+            Some(address) => address,
+            None => return 0,
+        };
+        if !self.fit_heap_memory() {
+            self.heap.free(address);
+            return 0;
+        }
+        self.zero_heap(address, address + size);
+        address
+    }
+
+    fn heap_free(&mut self, address: u32) -> Result<(), Error> {
+        if address == 0 {
+            return Ok(());
+        }
+        self.heap.free(address).ok_or(Error::BadFree)?;
+        self.fit_heap_memory();
+        Ok(())
+    }
+
+    /// Resize a heap block, moving it if it does not fit where it is.
+    /// Returns null and leaves the block alone if there is no room.
+    fn heap_realloc(&mut self, address: u32, size: u32) -> Result<u32, Error> {
+        if address == 0 {
+            return Ok(self.heap_alloc(size));
+        }
+        let old_size = self.heap.block_size(address).ok_or(Error::BadFree)?;
+        if self.heap.resize(address, size) {
+            if !self.fit_heap_memory() {
+                self.heap.resize(address, old_size);
+                return Ok(0);
+            }
+            if size > old_size {
+                self.zero_heap(address + old_size, address + size);
+            }
+            return Ok(address);
+        }
+        let new = self.heap_alloc(size);
+        if new != 0 {
+            self.mem_copy(address, new, old_size.min(size));
+            self.heap_free(address)?;
+        }
+        Ok(new)
+    }
+
+    /// Run a builtin that manages the heap, prints values or reports
+    /// failed checks, and write its result to `dest`. Returns `false`
+    /// if `sym` is not such builtin.
+    fn run_runtime_builtin(
+        &mut self,
+        sym: &ir::Symbol,
+        dest: Option<ir::Reg>,
+        params: &[ir::Value],
+    ) -> Result<bool, Error> {
+        #[cfg(feature = "ffi")]
+        {
+            if let Some(function) = self.foreign.get(sym).cloned() {
+                self.call_foreign(sym, &function, dest, params)?;
+                return Ok(true);
+            }
+        }
+        let result = match &*sym.0 {
+            "builtin_alloc" => {
+                let size = self.load_32bit(&params[0]);
+                let address = self.heap_alloc(size);
+                self.record_allocation(address);
+                Some(self.address_value(address))
+            }
+            "builtin_free" => {
+                let address = self.load_address(&params[0]);
+                self.heap_free(address)?;
+                self.allocated_at.remove(&address);
+                None
+            }
+            "builtin_realloc" => {
+                let address = self.load_address(&params[0]);
+                let size = self.load_32bit(&params[1]);
+                let new = self.heap_realloc(address, size)?;
+                if new != 0 {
+                    self.allocated_at.remove(&address);
+                    self.record_allocation(new);
+                }
+                Some(self.address_value(new))
+            }
+            "builtin_bounds_fail" => {
+                let index = self.load_32bit(&params[0]);
+                let len = self.load_32bit(&params[1]);
+                return Err(Error::IndexOutOfBounds(index, len));
+            }
+            "builtin_assert_fail" | "builtin_panic_fail" => {
+                let message = self.read_bytes(&params[0], self.load_32bit(&params[1]))?;
+                let message = String::from_utf8_lossy(&message).into_owned();
+                let line = self.load_32bit(&params[2]);
+                let column = self.load_32bit(&params[3]);
+                let location = if line == 0 {
+                    None
+                } else {
+                    Some(self.program.locate(line, column))
+                };
+                if &*sym.0 == "builtin_panic_fail" {
+                    return Err(Error::Panicked(message, location));
+                }
+                return Err(Error::AssertionFailed(message, location));
+            }
+            "builtin_print_i32" => {
+                let value = self.load_32bit(&params[0]) as i32;
+                write!(self.output, "{}", value)?;
+                None
+            }
+            "builtin_print_u32" => {
+                let value = self.load_32bit(&params[0]);
+                write!(self.output, "{}", value)?;
+                None
+            }
+            "builtin_write" => {
+                let bytes = self.read_bytes(&params[0], self.load_32bit(&params[1]))?;
+                self.output.write_all(&bytes)?;
+                None
+            }
+            "builtin_read_i32" => Some(Value::DoubleWord(self.read_i32()? as u32)),
+            "builtin_rand_seed" => {
+                self.rand_state = match self.load_32bit(&params[0]) {
+                    0 => RAND_START,
+                    seed => seed,
+                };
+                None
+            }
+            "builtin_rand_u32" => {
+                let mut x = self.rand_state;
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                self.rand_state = x;
+                Some(Value::DoubleWord(x))
+            }
+            "builtin_time_ms" => {
+                let ms = if self.virtual_time {
+                    self.steps / 1000
+                } else {
+                    let elapsed = self.started.elapsed();
+                    elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis())
+                };
+                Some(Value::DoubleWord(ms as u32))
+            }
+            "builtin_read_line" => {
+                let len = self.load_32bit(&params[1]);
+                let address = if len > 0 { self.deref_address(&params[0], 0, len)? } else { 0 };
+                let read = self.read_line(address, len)?;
+                Some(Value::DoubleWord(read as u32))
+            }
+            "builtin_str_eq" => {
+                let a = self.read_bytes(&params[0], self.load_32bit(&params[1]))?;
+                let b = self.read_bytes(&params[2], self.load_32bit(&params[3]))?;
+                Some(Value::Byte(if a == b { 1 } else { 0 }))
+            }
+            "builtin_str_copy" => {
+                let len = self.load_32bit(&params[1]).min(self.load_32bit(&params[3]));
+                let from = self.read_bytes(&params[2], len)?;
+                if len > 0 {
+                    let to = self.deref_address(&params[0], 0, len)?;
+                    for (i, &byte) in from.iter().enumerate() {
+                        self.set_byte(to + i as u32, byte);
+                    }
+                    self.observe_write(to, len);
+                }
+                Some(Value::DoubleWord(len))
+            }
+            "builtin_str_find" => {
+                let string = self.read_bytes(&params[0], self.load_32bit(&params[1]))?;
+                let pattern = self.read_bytes(&params[2], self.load_32bit(&params[3]))?;
+                let index = if pattern.len() <= string.len() {
+                    (0..=string.len() - pattern.len())
+                        .find(|&i| string[i..i + pattern.len()] == pattern[..])
+                } else {
+                    None
+                };
+                Some(Value::DoubleWord(index.map_or(!0, |i| i as u32)))
+            }
+            _ => return Ok(false),
+        };
+        if let (Some(dest), Some(value)) = (dest, result) {
+            let (to, len) = self.register_address(dest);
+            self.write_value(to, Some(len), value);
+        }
+        Ok(true)
+    }
+
+    /// Call a function of a shared library. Pointers are given to it
+    /// as addresses in program memory, and ones that it returns must
+    /// point there too. Memory that it writes is not seen by the
+    /// sanitizer, the history or the observer.
+    #[cfg(feature = "ffi")]
+    fn call_foreign(
+        &mut self,
+        sym: &ir::Symbol,
+        function: &ffi::Function,
+        dest: Option<ir::Reg>,
+        params: &[ir::Value],
+    ) -> Result<(), Error> {
+        let mut values = Vec::new();
+        for (&kind, param) in function.params.iter().zip(params) {
+            values.push(match kind {
+                ffi::Kind::Int(_, size) => self.load_int(param, size),
+                ffi::Kind::Pointer => {
+                    let address = self.load_address(param);
+                    self.host_pointer(address).ok_or(Error::BadDeref)? as u64
+                }
+                ffi::Kind::Void => 0,
+            });
+        }
+        let result = unsafe { function.call(&values) };
+        let value = match function.result {
+            ffi::Kind::Int(_, ir::Size::Bit8) => Value::Byte(result as u8),
+            ffi::Kind::Int(_, ir::Size::Bit16) => Value::Word(result as u16),
+            ffi::Kind::Int(_, ir::Size::Bit32) => Value::DoubleWord(result as u32),
+            ffi::Kind::Int(_, ir::Size::Bit64) => Value::QuadWord(result),
+            ffi::Kind::Pointer => match self.program_address(result as usize) {
+                Some(address) => self.address_value(address),
+                None => {
+                    let name = sym.0.trim_start_matches("fn_");
+                    let message = format!("`{}` returned a pointer outside of memory", name);
+                    return Err(Error::ForeignCall(message));
+                }
+            },
+            ffi::Kind::Void => return Ok(()),
+        };
+        if let Some(dest) = dest {
+            let (to, len) = self.register_address(dest);
+            self.write_value(to, Some(len), value);
+        }
+        Ok(())
+    }
+
+    /// Host address of program memory at `address`, which can
+    /// also be just past the end of it.
+    #[cfg(feature = "ffi")]
+    fn host_pointer(&mut self, address: u32) -> Option<*mut u8> {
+        if address == 0 {
+            Some(::std::ptr::null_mut())
+        } else if address >= HEAP_START {
+            let offset = (address - HEAP_START) as usize;
+            if offset <= self.heap_memory.len() {
+                Some(unsafe { self.heap_memory.as_mut_ptr().add(offset) })
+            } else {
+                None
+            }
+        } else if address as usize <= self.memory.len() {
+            Some(unsafe { self.memory.as_mut_ptr().add(address as usize) })
+        } else {
+            None
+        }
+    }
+
+    /// Address in program memory of a host pointer, if it points there.
+    #[cfg(feature = "ffi")]
+    fn program_address(&self, pointer: usize) -> Option<u32> {
+        let memory = self.memory.as_ptr() as usize;
+        let heap = self.heap_memory.as_ptr() as usize;
+        if pointer == 0 {
+            Some(0)
+        } else if pointer >= memory && pointer - memory <= self.memory.len() {
+            Some((pointer - memory) as u32)
+        } else if pointer >= heap && pointer - heap <= self.heap_memory.len() {
+            Some(HEAP_START + (pointer - heap) as u32)
+        } else {
+            None
+        }
+    }
+
+    /// Bytes that a builtin reads from a pointer and a length.
+    fn read_bytes(&self, pointer: &ir::Value, len: u32) -> Result<Vec<u8>, Error> {
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        let address = self.deref_address(pointer, 0, len)?;
+        let bytes = address..address + len;
+        if let Some(ref sanitizer) = self.sanitizer {
+            if !bytes.clone().all(|address| sanitizer.is_initialized(address)) {
+                return Err(Error::UninitializedRead);
+            }
+        }
+        Ok(bytes.map(|address| self.byte(address)).collect())
+    }
+
+    fn read_byte(&mut self) -> Result<Option<u8>, Error> {
+        let mut buf = [0];
+        match self.input.read(&mut buf)? {
+            0 => Ok(None),
+            1 => Ok(Some(buf[0])),
+            _ => panic!("wut"),
+        }
+    }
+
+    /// Skip whitespace and read a decimal number, together with
+    /// the byte after it. Gives 0 if there is no number.
+    fn read_i32(&mut self) -> Result<i32, Error> {
+        let mut byte = self.read_byte()?;
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = byte {
+            byte = self.read_byte()?;
+        }
+        let negative = byte == Some(b'-');
+        if negative {
+            byte = self.read_byte()?;
+        }
+        let mut value = 0i32;
+        while let Some(digit @ b'0'..=b'9') = byte {
+            value = value.wrapping_mul(10).wrapping_add(i32::from(digit - b'0'));
+            byte = self.read_byte()?;
+        }
+        Ok(if negative { value.wrapping_neg() } else { value })
+    }
+
+    /// Read bytes up to the end of the line, storing the ones that fit in
+    /// `len` bytes from `address`. Gives how many were stored, or -1 if
+    /// input has ended.
+    fn read_line(&mut self, address: u32, len: u32) -> Result<i32, Error> {
+        let mut byte = self.read_byte()?;
+        if byte.is_none() {
+            return Ok(-1);
+        }
+        let mut stored = 0;
+        while let Some(b) = byte {
+            if b == b'\n' {
+                break;
+            }
+            if stored < len {
+                self.set_byte(address + stored, b);
+                stored += 1;
+            }
+            byte = self.read_byte()?;
+        }
+        if stored > 0 {
+            self.observe_write(address, stored);
+        }
+        Ok(stored as i32)
+    }
+
+    /// Remember that the running call returned given heap block.
+    fn record_allocation(&mut self, address: u32) {
+        if address != 0 {
+            let block = self.current_block();
+            let span = block.ops[self.current_frame.current_op - 1].span;
+            let frame = describe_frame(self.program, &self.current_frame, span);
+            self.allocated_at.insert(address, frame);
+        }
+    }
+
+    /// Heap blocks that are still allocated, by address.
+    fn leaks(&self) -> Vec<Leak> {
+        self.heap
+            .blocks()
+            .map(|(address, size)| Leak {
+                size,
+                allocated_at: self.allocated_at[&address].clone(),
+            })
+            .collect()
+    }
+
+    fn address_value(&self, address: u32) -> Value {
+        match self.program.target.pointer_size {
+            ir::Size::Bit64 => Value::QuadWord(u64::from(address)),
+            _ => Value::DoubleWord(address),
+        }
+    }
+
     fn load_32bit(&self, val: &ir::Value) -> u32 {
         match *val {
             ir::Value::Int(i, ir::Size::Bit32) => i as u32,
@@ -201,15 +1273,16 @@ impl<'a, R: Read, W: Write> Vm<'a, R, W> {
     fn read_value(&self, val: &ir::Value) -> Result<Value, Error> {
         match *val {
             ir::Value::Undef => Err(Error::ReadUndef),
-            ir::Value::Bytes(ref s) => Ok(Value::DoubleWord(self.strings[s])),
+            ir::Value::Bytes(ref s) => Ok(self.address_value(self.strings[s])),
             ir::Value::Int(i, ir::Size::Bit8) => Ok(Value::Byte(i as u8)),
             ir::Value::Int(i, ir::Size::Bit16) => Ok(Value::Word(i as u16)),
             ir::Value::Int(i, ir::Size::Bit32) => Ok(Value::DoubleWord(i as u32)),
+            ir::Value::Int(i, ir::Size::Bit64) => Ok(Value::QuadWord(i)),
             ir::Value::Reg(reg) => {
                 let (at, size) = self.register_address(reg);
                 Ok(Value::AddressRange(at, size))
             }
-            ir::Value::Symbol(ref sym) => Ok(Value::DoubleWord(self.symbol_ids[sym])),
+            ir::Value::Symbol(ref sym) => Ok(self.address_value(self.symbol_ids[sym])),
         }
     }
 
@@ -230,13 +1303,18 @@ impl<'a, R: Read, W: Write> Vm<'a, R, W> {
             panic!("copy overlap");
         }
         for i in 0..len {
-            self.memory[(to + i) as usize] = self.memory[(from + i) as usize];
+            self.set_byte(to + i, self.byte(from + i));
+            if let Some(ref mut sanitizer) = self.sanitizer {
+                let initialized = sanitizer.is_initialized(from + i);
+                sanitizer.set_initialized(to + i, initialized);
+            }
         }
+        self.observe_write(to, len);
     }
 
     fn mem_cmp(&self, a: u32, b: u32, len: u32) -> bool {
         for i in 0..len {
-            if self.memory[(a + i) as usize] != self.memory[(b + i) as usize] {
+            if self.byte(a + i) != self.byte(b + i) {
                 return false;
             }
         }
@@ -252,41 +1330,51 @@ impl<'a, R: Read, W: Write> Vm<'a, R, W> {
             (Value::AddressRange(a, al), Value::Byte(b)) |
             (Value::Byte(b), Value::AddressRange(a, al)) => {
                 assert_eq!(al, 1);
-                self.memory[a as usize] == b
+                self.byte(a) == b
             }
             (Value::AddressRange(a, al), Value::Word(b)) |
             (Value::Word(b), Value::AddressRange(a, al)) => {
                 assert_eq!(al, 2);
-                self.memory[a as usize + 0] == (b & 0xFF) as u8 && 
-                self.memory[a as usize + 1] == ((b >> 8) & 0xFF) as u8
+                self.byte(a + 0) == (b & 0xFF) as u8 && 
+                self.byte(a + 1) == ((b >> 8) & 0xFF) as u8
             }
             (Value::AddressRange(a, al), Value::DoubleWord(b)) |
             (Value::DoubleWord(b), Value::AddressRange(a, al)) => {
                 assert_eq!(al, 4);
-                self.memory[a as usize + 0] == (b & 0xFF) as u8 && 
-                self.memory[a as usize + 1] == ((b >> 8) & 0xFF) as u8 &&
-                self.memory[a as usize + 2] == ((b >> 16) & 0xFF) as u8 &&
-                self.memory[a as usize + 3] == ((b >> 24) & 0xFF) as u8
+                self.byte(a + 0) == (b & 0xFF) as u8 && 
+                self.byte(a + 1) == ((b >> 8) & 0xFF) as u8 &&
+                self.byte(a + 2) == ((b >> 16) & 0xFF) as u8 &&
+                self.byte(a + 3) == ((b >> 24) & 0xFF) as u8
+            }
+            (Value::AddressRange(a, al), Value::QuadWord(b)) |
+            (Value::QuadWord(b), Value::AddressRange(a, al)) => {
+                assert_eq!(al, 8);
+                (0..8).all(|i| self.byte(a + i as u32) == (b >> (i * 8)) as u8)
             }
             (Value::FromAddress(a), Value::Byte(b)) |
             (Value::Byte(b), Value::FromAddress(a)) => {
-                self.memory[a as usize] == b
+                self.byte(a) == b
             }
             (Value::FromAddress(a), Value::Word(b)) |
             (Value::Word(b), Value::FromAddress(a)) => {
-                self.memory[a as usize + 0] == (b & 0xFF) as u8 && 
-                self.memory[a as usize + 1] == ((b >> 8) & 0xFF) as u8
+                self.byte(a + 0) == (b & 0xFF) as u8 && 
+                self.byte(a + 1) == ((b >> 8) & 0xFF) as u8
             }
             (Value::FromAddress(a), Value::DoubleWord(b)) |
             (Value::DoubleWord(b), Value::FromAddress(a)) => {
-                self.memory[a as usize + 0] == (b & 0xFF) as u8 && 
-                self.memory[a as usize + 1] == ((b >> 8) & 0xFF) as u8 &&
-                self.memory[a as usize + 2] == ((b >> 16) & 0xFF) as u8 &&
-                self.memory[a as usize + 3] == ((b >> 24) & 0xFF) as u8
+                self.byte(a + 0) == (b & 0xFF) as u8 && 
+                self.byte(a + 1) == ((b >> 8) & 0xFF) as u8 &&
+                self.byte(a + 2) == ((b >> 16) & 0xFF) as u8 &&
+                self.byte(a + 3) == ((b >> 24) & 0xFF) as u8
+            }
+            (Value::FromAddress(a), Value::QuadWord(b)) |
+            (Value::QuadWord(b), Value::FromAddress(a)) => {
+                (0..8).all(|i| self.byte(a + i as u32) == (b >> (i * 8)) as u8)
             }
             (Value::Byte(a), Value::Byte(b)) => a == b,
             (Value::Word(a), Value::Word(b)) => a == b,
             (Value::DoubleWord(a), Value::DoubleWord(b)) => a == b,
+            (Value::QuadWord(a), Value::QuadWord(b)) => a == b,
             _ => panic!("invalid cmp"),
         }
     }
@@ -297,23 +1385,31 @@ impl<'a, R: Read, W: Write> Vm<'a, R, W> {
                 if len.is_some() {
                     assert_eq!(len, Some(1));
                 }
-                self.memory[to as usize] = b;
+                self.set_byte(to, b);
             }
             Value::Word(w) => {
                 if len.is_some() {
                     assert_eq!(len, Some(2));
                 }
-                self.memory[to as usize] = (w & 0xFF) as u8;
-                self.memory[(to + 1) as usize] = ((w >> 8) & 0xFF) as u8;
+                self.set_byte(to, (w & 0xFF) as u8);
+                self.set_byte(to + 1, ((w >> 8) & 0xFF) as u8);
             }
             Value::DoubleWord(dw) => {
                 if len.is_some() {
                     assert_eq!(len, Some(4));
                 }
-                self.memory[to as usize] = (dw & 0xFF) as u8;
-                self.memory[(to + 1) as usize] = ((dw >> 8) & 0xFF) as u8;
-                self.memory[(to + 2) as usize] = ((dw >> 16) & 0xFF) as u8;
-                self.memory[(to + 3) as usize] = ((dw >> 24) & 0xFF) as u8;
+                self.set_byte(to, (dw & 0xFF) as u8);
+                self.set_byte(to + 1, ((dw >> 8) & 0xFF) as u8);
+                self.set_byte(to + 2, ((dw >> 16) & 0xFF) as u8);
+                self.set_byte(to + 3, ((dw >> 24) & 0xFF) as u8);
+            }
+            Value::QuadWord(qw) => {
+                if len.is_some() {
+                    assert_eq!(len, Some(8));
+                }
+                for i in 0..8 {
+                    self.set_byte(to + i, (qw >> (i * 8)) as u8);
+                }
             }
             Value::FromAddress(a) => {
                 let len = len.unwrap();
@@ -326,9 +1422,58 @@ impl<'a, R: Read, W: Write> Vm<'a, R, W> {
                 self.mem_copy(a, to, l);
             }
         }
+        // copies tell the observer themselves
+        if let Some(size) = value.size() {
+            if !matches!(value, Value::AddressRange(..)) {
+                self.observe_write(to, size);
+            }
+        }
+    }
+
+    /// With the sanitizer, make sure that a value is initialized
+    /// before using it for more than copying.
+    fn check_initialized(&self, val: &ir::Value) -> Result<(), Error> {
+        if let (Some(sanitizer), &ir::Value::Reg(reg)) = (self.sanitizer.as_ref(), val) {
+            let (at, len) = self.register_address(reg);
+            if !(at..at + len).all(|address| sanitizer.is_initialized(address)) {
+                return Err(Error::UninitializedRead);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check values that an instruction computes with,
+    /// or passes to builtins.
+    fn check_operands(&self, i: &ir::Instruction) -> Result<(), Error> {
+        match *i {
+            ir::Instruction::BinaryOp(_, _, ref a, ref b) => {
+                self.check_initialized(a)?;
+                self.check_initialized(b)
+            }
+            ir::Instruction::UnaryOp(_, _, ref val) |
+            ir::Instruction::DerefLoad(_, ref val, _) |
+            ir::Instruction::DerefStore(ref val, _, _) => self.check_initialized(val),
+            ir::Instruction::Call(_, ref sym, ref params) |
+            ir::Instruction::CallProc(ref sym, ref params) if is_builtin(sym) => {
+                params.iter().try_for_each(|param| self.check_initialized(param))
+            }
+            ir::Instruction::CallVirt(_, ref val, ref params) |
+            ir::Instruction::CallProcVirt(ref val, ref params) => {
+                self.check_initialized(val)?;
+                let address = self.load_address(val);
+                match self.symbols_by_id.get(&address) {
+                    Some(sym) if is_builtin(sym) => {
+                        params.iter().try_for_each(|param| self.check_initialized(param))
+                    }
+                    _ => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        }
     }
 
-    fn run_op(&mut self, i: &ir::Instruction) -> Result<(), Error> {
+    fn run_op(&mut self, i: &'a ir::Instruction) -> Result<(), Error> {
+        self.check_operands(i)?;
         match *i {
             ir::Instruction::Assign(reg, ref val) |
             ir::Instruction::CastAssign(reg, ref val) => {
@@ -361,12 +1506,35 @@ impl<'a, R: Read, W: Write> Vm<'a, R, W> {
                         self.write_value(to, Some(len), res);
                         Ok(())
                     }
+                    ir::BinaryOp::IntOp(op, sign, ir::Size::Bit64) => {
+                        let a = self.load_64bit(a);
+                        let b = self.load_64bit(b);
+                        let res = int_op_64(op, sign, a, b)?;
+                        self.write_value(to, Some(len), res);
+                        Ok(())
+                    }
+                    ir::BinaryOp::Checked(op, overflow, sign, size) => {
+                        let a = self.load_int(a, size);
+                        let b = self.load_int(b, size);
+                        if op == ir::IntOp::Div && b == 0 {
+                            return Err(division_by_zero(op, sign, size, a));
+                        }
+                        let res = match (op.checked(sign, size, a, b), overflow) {
+                            (Ok(res), _) | (Err(res), ir::Overflow::Saturate) => res,
+                            (Err(_), ir::Overflow::Trap) => {
+                                let operation = op.describe(sign, size, a, b);
+                                return Err(Error::ArithmeticOverflow(operation));
+                            }
+                        };
+                        self.write_value(to, Some(len), int_value(res, size));
+                        Ok(())
+                    }
                     ir::BinaryOp::Eq => {
                         assert_eq!(len, 1);
                         let a = self.read_value(a)?;
                         let b = self.read_value(b)?;
                         let res = self.compare_values(a, b) as u8;
-                        self.memory[to as usize] = res;
+                        self.write_value(to, Some(1), Value::Byte(res));
                         Ok(())
                     }
                     ir::BinaryOp::Neq => {
@@ -374,7 +1542,7 @@ impl<'a, R: Read, W: Write> Vm<'a, R, W> {
                         let a = self.read_value(a)?;
                         let b = self.read_value(b)?;
                         let res = !self.compare_values(a, b) as u8;
-                        self.memory[to as usize] = res;
+                        self.write_value(to, Some(1), Value::Byte(res));
                         Ok(())
                     }
                     ir::BinaryOp::BitOp(op, ir::Size::Bit8) => {
@@ -398,25 +1566,33 @@ impl<'a, R: Read, W: Write> Vm<'a, R, W> {
                         self.write_value(to, Some(len), res);
                         Ok(())
                     }
+                    ir::BinaryOp::BitOp(op, ir::Size::Bit64) => {
+                        let a = self.load_64bit(a);
+                        let b = self.load_64bit(b);
+                        let res = bit_op_64(op, a, b);
+                        self.write_value(to, Some(len), res);
+                        Ok(())
+                    }
                 }
             }
             ir::Instruction::Call(dest, ref sym, ref params) => {
                 if "builtin_getc" == &*sym.0 {
                     assert_eq!(params.len(), 0);
-                    let mut buf = [0];
-                    let result = match self.input.read(&mut buf)? {
-                        0 => !0u32,
-                        1 => buf[0] as u32,
-                        _ => panic!("wut"),
+                    let result = match self.read_byte()? {
+                        Some(byte) => byte as u32,
+                        None => !0u32,
                     };
                     let (ret, _) = self.register_address(dest);
                     self.write_value(ret, Some(4), Value::DoubleWord(result));
                     return Ok(());
                 }
+                if self.run_runtime_builtin(sym, Some(dest), params)? {
+                    return Ok(());
+                }
                 let f = &self.program.functions[sym];
                 let (ret, _) = self.register_address(dest);
                 let stack_start = self.memory.len();
-                let registers = self.allocate_registers(&f.registers);
+                let registers = self.allocate_registers(&f.registers)?;
                 assert_eq!(f.parameters.len(), params.len());
                 for (param, val) in f.parameters.iter().zip(params.iter()) {
                     let at = registers[param];
@@ -426,14 +1602,14 @@ impl<'a, R: Read, W: Write> Vm<'a, R, W> {
                 }
                 let frame = StackFrame {
                     stack_start,
+                    symbol: sym,
                     function: f,
                     registers,
                     current_block: f.start_block.unwrap(),
                     current_op: 0,
                     return_address: Some(ret),
                 };
-                self.frames.push(::std::mem::replace(&mut self.current_frame, frame));
-                Ok(())
+                self.push_frame(frame)
             }
             ir::Instruction::CallProc(ref sym, ref params) => {
                 if "builtin_putc" == &*sym.0 {
@@ -442,9 +1618,12 @@ impl<'a, R: Read, W: Write> Vm<'a, R, W> {
                     self.output.write_all(&[val])?;
                     return Ok(());
                 }
+                if self.run_runtime_builtin(sym, None, params)? {
+                    return Ok(());
+                }
                 let f = &self.program.functions[sym];
                 let stack_start = self.memory.len();
-                let registers = self.allocate_registers(&f.registers);
+                let registers = self.allocate_registers(&f.registers)?;
                 assert_eq!(f.parameters.len(), params.len());
                 for (param, val) in f.parameters.iter().zip(params.iter()) {
                     let at = registers[param];
@@ -454,33 +1633,35 @@ impl<'a, R: Read, W: Write> Vm<'a, R, W> {
                 }
                 let frame = StackFrame {
                     stack_start,
+                    symbol: sym,
                     function: f,
                     registers,
                     current_block: f.start_block.unwrap(),
                     current_op: 0,
                     return_address: None,
                 };
-                self.frames.push(::std::mem::replace(&mut self.current_frame, frame));
-                Ok(())
+                self.push_frame(frame)
             }
             ir::Instruction::CallVirt(dest, ref val, ref params) => {
-                let sym = &self.symbols_by_id[&self.load_32bit(val)].clone();
+                let address = self.load_address(val);
+                let sym = *self.symbols_by_id.get(&address).ok_or(Error::BadDeref)?;
                 if "@plank_getc" == &*sym.0 {
                     assert_eq!(params.len(), 0);
-                    let mut buf = [0];
-                    let result = match self.input.read(&mut buf)? {
-                        0 => !0u32,
-                        1 => buf[0] as u32,
-                        _ => panic!("wut"),
+                    let result = match self.read_byte()? {
+                        Some(byte) => byte as u32,
+                        None => !0u32,
                     };
                     let (ret, _) = self.register_address(dest);
                     self.write_value(ret, Some(4), Value::DoubleWord(result));
                     return Ok(());
                 }
+                if self.run_runtime_builtin(sym, Some(dest), params)? {
+                    return Ok(());
+                }
                 let f = &self.program.functions[sym];
                 let (ret, _) = self.register_address(dest);
                 let stack_start = self.memory.len();
-                let registers = self.allocate_registers(&f.registers);
+                let registers = self.allocate_registers(&f.registers)?;
                 assert_eq!(f.parameters.len(), params.len());
                 for (param, val) in f.parameters.iter().zip(params.iter()) {
                     let at = registers[param];
@@ -490,26 +1671,30 @@ impl<'a, R: Read, W: Write> Vm<'a, R, W> {
                 }
                 let frame = StackFrame {
                     stack_start,
+                    symbol: sym,
                     function: f,
                     registers,
                     current_block: f.start_block.ok_or(Error::BadDeref)?,
                     current_op: 0,
                     return_address: Some(ret),
                 };
-                self.frames.push(::std::mem::replace(&mut self.current_frame, frame));
-                Ok(())
+                self.push_frame(frame)
             }
             ir::Instruction::CallProcVirt(ref val, ref params) => {
-                let sym = &self.symbols_by_id[&self.load_32bit(val)].clone();
+                let address = self.load_address(val);
+                let sym = *self.symbols_by_id.get(&address).ok_or(Error::BadDeref)?;
                 if "@plank_putc" == &*sym.0 {
                     assert_eq!(params.len(), 1);
                     let val = self.load_8bit(&params[0]);
                     self.output.write_all(&[val])?;
                     return Ok(());
                 }
+                if self.run_runtime_builtin(sym, None, params)? {
+                    return Ok(());
+                }
                 let f = &self.program.functions[sym];
                 let stack_start = self.memory.len();
-                let registers = self.allocate_registers(&f.registers);
+                let registers = self.allocate_registers(&f.registers)?;
                 assert_eq!(f.parameters.len(), params.len());
                 for (param, val) in f.parameters.iter().zip(params.iter()) {
                     let at = registers[param];
@@ -519,24 +1704,25 @@ impl<'a, R: Read, W: Write> Vm<'a, R, W> {
                 }
                 let frame = StackFrame {
                     stack_start,
+                    symbol: sym,
                     function: f,
                     registers,
                     current_block: f.start_block.ok_or(Error::BadDeref)?,
                     current_op: 0,
                     return_address: None,
                 };
-                self.frames.push(::std::mem::replace(&mut self.current_frame, frame));
-                Ok(())
+                self.push_frame(frame)
             }
             ir::Instruction::DerefLoad(dest, ref address, offset) => {
-                let address = self.load_32bit(address) + offset;
                 let (to, len) = self.register_address(dest);
+                let address = self.deref_address(address, offset, len)?;
                 self.write_value(to, Some(len), Value::FromAddress(address));
                 Ok(())
             }
             ir::Instruction::DerefStore(ref address, offset, ref value) => {
-                let address = self.load_32bit(address) + offset;
                 let value = self.read_value(value)?;
+                let len = value.size().expect("stored value without size");
+                let address = self.deref_address(address, offset, len)?;
                 self.write_value(address, None, value);
                 Ok(())
             }
@@ -558,7 +1744,7 @@ impl<'a, R: Read, W: Write> Vm<'a, R, W> {
             ir::Instruction::TakeAddress(dest, reg, offset) => {
                 let (to, _) = self.register_address(dest);
                 let reg_at = self.current_frame.registers[&reg];
-                let value = Value::DoubleWord(reg_at + offset);
+                let value = self.address_value(reg_at + offset);
                 self.write_value(to, None, value);
                 Ok(())
             }
@@ -580,23 +1766,194 @@ impl<'a, R: Read, W: Write> Vm<'a, R, W> {
                         let res = (!val).wrapping_add(1);
                         self.write_value(to, Some(len), Value::DoubleWord(res));
                     }
+                    ir::Size::Bit64 => {
+                        let val = self.load_64bit(value);
+                        let res = (!val).wrapping_add(1);
+                        self.write_value(to, Some(len), Value::QuadWord(res));
+                    }
                 }
                 Ok(())
             }
+            ir::Instruction::UnaryOp(reg, ir::UnaryOp::Extend(sign, from, size), ref value) => {
+                let (to, len) = self.register_address(reg);
+                let val = match from {
+                    ir::Size::Bit8 => u64::from(self.load_8bit(value)),
+                    ir::Size::Bit16 => u64::from(self.load_16bit(value)),
+                    ir::Size::Bit32 => u64::from(self.load_32bit(value)),
+                    ir::Size::Bit64 => self.load_64bit(value),
+                };
+                let res = from.extend(val, sign, size);
+                let res = match size {
+                    ir::Size::Bit8 => Value::Byte(res as u8),
+                    ir::Size::Bit16 => Value::Word(res as u16),
+                    ir::Size::Bit32 => Value::DoubleWord(res as u32),
+                    ir::Size::Bit64 => Value::QuadWord(res),
+                };
+                self.write_value(to, Some(len), res);
+                Ok(())
+            }
             ir::Instruction::Unreachable => Err(Error::ExecutedUnreachable),
         }
     }
 
+    /// Free registers of the current frame.
+    fn truncate_stack(&mut self) {
+        let len = self.current_frame.stack_start;
+        self.memory.truncate(len);
+        if let Some(ref mut sanitizer) = self.sanitizer {
+            sanitizer.truncate_stack(len);
+        }
+    }
+
+    /// Add a backtrace to an error that happened while running code
+    /// from given span.
+    fn trap(&self, err: Error, span: Option<Span>) -> Error {
+        let mut frames = vec![describe_frame(self.program, &self.current_frame, span)];
+        for frame in self.frames.iter().rev() {
+            let block = &frame.function.blocks[&frame.current_block];
+            // the call that is running was the last executed op
+            let span = block.ops[frame.current_op - 1].span;
+            frames.push(describe_frame(self.program, frame, span));
+        }
+        Error::Trap(Box::new(err), frames)
+    }
+
+    /// With history, finish the previous step and start
+    /// recording one that runs code from given span.
+    fn start_step(&mut self, span: Option<Span>) {
+        if self.history.is_some() {
+            self.finish_step();
+            let frame = describe_frame(self.program, &self.current_frame, span);
+            if let Some(ref mut history) = self.history {
+                history.start(frame);
+            }
+        }
+    }
+
+    /// Add the running step to the history, if there is one.
+    fn finish_step(&mut self) {
+        let (frame, changed) = match self.history.as_mut().and_then(History::finish) {
+            Some(step) => step,
+            None => return,
+        };
+        let changes = self.describe_changes(&changed);
+        if let Some(ref mut history) = self.history {
+            history.push(Step { frame, changes });
+        }
+    }
+
+    /// Group bytes that a step changed into registers and runs of other
+    /// memory. Steps only write to registers of the frame that is running
+    /// after them, because calls pass arguments to the new frame and
+    /// returns write to the caller.
+    fn describe_changes(&self, changed: &BTreeMap<u32, u8>) -> Vec<Change> {
+        let function = self.current_frame.function;
+        let mut registers = BTreeMap::new();
+        let mut runs = Vec::<(u32, u32)>::new();
+        for &address in changed.keys() {
+            // memory of returning functions and freed blocks is gone
+            if !self.is_in_memory(address) {
+                continue;
+            }
+            let register = self.current_frame.registers.iter().find(|&(reg, &at)| {
+                at <= address && address - at < function.registers[reg].size
+            });
+            match register {
+                Some((&reg, &at)) => {
+                    registers.insert(at, reg);
+                }
+                None => match runs.last_mut() {
+                    Some(&mut (start, ref mut len)) if start + *len == address => *len += 1,
+                    _ => runs.push((address, 1)),
+                },
+            }
+        }
+        let old_byte = |address| {
+            changed.get(&address).cloned().unwrap_or_else(|| self.byte(address))
+        };
+        let mut changes = Vec::new();
+        for (at, reg) in registers {
+            let size = function.registers[&reg].size;
+            let old = (at..at + size).map(&old_byte).collect::<Vec<_>>();
+            let new = (at..at + size).map(|address| self.byte(address)).collect::<Vec<_>>();
+            let variable = function.debug.as_ref().and_then(|debug| {
+                debug.variables.iter().find(|variable| variable.reg == reg)
+            });
+            let (place, typ) = match variable {
+                Some(variable) => (variable.name.clone(), Some(&variable.typ)),
+                None => (format!("%{}", reg.0), None),
+            };
+            changes.push(Change {
+                place,
+                old: format_value(&old, typ),
+                new: format_value(&new, typ),
+            });
+        }
+        for (start, len) in runs {
+            let old = (start..start + len).map(&old_byte).collect::<Vec<_>>();
+            let new = (start..start + len).map(|address| self.byte(address)).collect::<Vec<_>>();
+            changes.push(Change {
+                place: format!("*{:#x}", start),
+                old: format_value(&old, None),
+                new: format_value(&new, None),
+            });
+        }
+        changes
+    }
+
+    /// Whether the address is within program memory, not
+    /// necessarily within a live object.
+    fn is_in_memory(&self, address: u32) -> bool {
+        if address >= HEAP_START {
+            ((address - HEAP_START) as usize) < self.heap_memory.len()
+        } else {
+            (address as usize) < self.memory.len()
+        }
+    }
+
+    /// List remembered steps together with a trap,
+    /// if `Options` asked to record them.
+    fn add_history(&mut self, err: Error) -> Error {
+        if let Error::Trap(..) = err {
+            self.finish_step();
+            if let Some(ref history) = self.history {
+                return Error::History(Box::new(err), history.steps());
+            }
+        }
+        err
+    }
+
+    /// Use up fuel for one step, returns `false` if there was none left.
+    fn burn_fuel(&mut self) -> bool {
+        self.steps += 1;
+        match self.fuel {
+            Some(0) => false,
+            Some(ref mut fuel) => {
+                *fuel -= 1;
+                true
+            }
+            None => true,
+        }
+    }
+
     fn run(&mut self) -> Result<i32, Error> {
         loop {
             let block = self.current_block();
             if self.current_frame.current_op == block.ops.len() {
+                if !self.burn_fuel() {
+                    return Err(self.trap(Error::ExecutionLimit, block.end_span));
+                }
+                self.start_step(block.end_span);
+                self.observe_line(block.end_span);
                 match block.end {
                     ir::BlockEnd::Jump(block) => {
                         self.current_frame.current_block = block;
                         self.current_frame.current_op = 0;
                     }
                     ir::BlockEnd::Branch(ref val, a, b) => {
+                        if let Err(err) = self.check_initialized(val) {
+                            return Err(self.trap(err, block.end_span));
+                        }
                         self.current_frame.current_op = 0;
                         if self.load_8bit(val) != 0 {
                             self.current_frame.current_block = a;
@@ -605,10 +1962,21 @@ impl<'a, R: Read, W: Write> Vm<'a, R, W> {
                         }
                     }
                     ir::BlockEnd::Return(ref val) => {
-                        let val = self.read_value(val)?;
+                        // exit code of the program is used by the caller
+                        if self.frames.is_empty() {
+                            if let Err(err) = self.check_initialized(val) {
+                                return Err(self.trap(err, block.end_span));
+                            }
+                        }
+                        let val = match self.read_value(val) {
+                            Ok(val) => val,
+                            Err(err) => return Err(self.trap(err, block.end_span)),
+                        };
                         let len = self.current_frame.function.output_layout.unwrap().size;
                         let to = self.current_frame.return_address.unwrap();
                         self.write_value(to, Some(len), val);
+                        self.observe_return();
+                        self.truncate_stack();
                         match self.frames.pop() {
                             Some(frame) => self.current_frame = frame,
                             None => return Ok({
@@ -622,25 +1990,146 @@ impl<'a, R: Read, W: Write> Vm<'a, R, W> {
                     }
                     ir::BlockEnd::ReturnProc => {
                         assert!(self.current_frame.return_address.is_none());
-                        self.memory.truncate(self.current_frame.stack_start);
+                        self.observe_return();
+                        self.truncate_stack();
                         match self.frames.pop() {
                             Some(frame) => self.current_frame = frame,
                             None => panic!("main did not return a value"),
                         }
                     }
                     ir::BlockEnd::Unreachable => {
-                        return Err(Error::ExecutedUnreachable);
+                        return Err(self.trap(Error::ExecutedUnreachable, block.end_span));
                     }
                 }
             } else {
                 let op = &block.ops[self.current_frame.current_op];
+                if !self.burn_fuel() {
+                    return Err(self.trap(Error::ExecutionLimit, op.span));
+                }
+                self.start_step(op.span);
+                self.observe_line(op.span);
                 self.current_frame.current_op += 1;
-                self.run_op(op)?;
+                match self.run_op(op) {
+                    Ok(()) => {}
+                    Err(err @ Error::Io(_)) => return Err(err),
+                    Err(Error::DivisionByZero(operation, _)) => {
+                        let location = op.span.map(|span| self.program.locate_span(span));
+                        let err = Error::DivisionByZero(operation, location);
+                        return Err(self.trap(err, op.span));
+                    }
+                    Err(err) => return Err(self.trap(err, op.span)),
+                }
             }
         }
     }
 }
 
+/// Source name of the function, or its symbol if it is not known.
+fn function_name<'b>(frame: &'b StackFrame) -> &'b str {
+    match frame.function.debug {
+        Some(ref debug) => &debug.name,
+        None => &frame.symbol.0,
+    }
+}
+
+fn describe_frame(program: &Program, frame: &StackFrame, span: Option<Span>) -> Frame {
+    Frame {
+        function: function_name(frame).to_string(),
+        location: span.map(|span| program.locate_span(span)),
+    }
+}
+
+/// Show a value like the source would if its type is known, and as
+/// a number or a list of bytes otherwise.
+fn format_value(bytes: &[u8], typ: Option<&debug::Type>) -> String {
+    let value = bytes.iter().rev().fold(0, |value, &byte| (value << 8) | u64::from(byte));
+    let bits = bytes.len() as u32 * 8;
+    match (typ, bytes.len()) {
+        (Some(&debug::Type::Bool), 1) => (value != 0).to_string(),
+        (Some(&debug::Type::Int(ir::Signedness::Signed, _)), 1 | 2 | 4 | 8) => {
+            let shift = 64 - bits;
+            (((value << shift) as i64) >> shift).to_string()
+        }
+        (Some(&debug::Type::Pointer(_)), 4 | 8) => format!("{:#x}", value),
+        (_, 1 | 2 | 4 | 8) => value.to_string(),
+        _ => format!("{:?}", bytes),
+    }
+}
+
+fn int_value(value: u64, size: ir::Size) -> Value {
+    match size {
+        ir::Size::Bit8 => Value::Byte(value as u8),
+        ir::Size::Bit16 => Value::Word(value as u16),
+        ir::Size::Bit32 => Value::DoubleWord(value as u32),
+        ir::Size::Bit64 => Value::QuadWord(value),
+    }
+}
+
+fn division_by_zero(op: ir::IntOp, sign: ir::Signedness, size: ir::Size, dividend: u64) -> Error {
+    Error::DivisionByZero(op.describe(sign, size, dividend, 0), None)
+}
+
+fn int_op_64(op: ir::IntOp, sign: ir::Signedness, a: u64, b: u64) -> Result<Value, Error> {
+    match (op, sign) {
+        (ir::IntOp::Add, _) => Ok(Value::QuadWord(a.wrapping_add(b))),
+        (ir::IntOp::Sub, _) => Ok(Value::QuadWord(a.wrapping_sub(b))),
+        (ir::IntOp::Greater, ir::Signedness::Unsigned) => {
+            Ok(Value::Byte((a > b) as u8))
+        }
+        (ir::IntOp::Greater, ir::Signedness::Signed) => {
+            Ok(Value::Byte((a as i64 > b as i64) as u8))
+        }
+        (ir::IntOp::GreaterEq, ir::Signedness::Unsigned) => {
+            Ok(Value::Byte((a >= b) as u8))
+        }
+        (ir::IntOp::GreaterEq, ir::Signedness::Signed) => {
+            Ok(Value::Byte((a as i64 >= b as i64) as u8))
+        }
+        (ir::IntOp::Less, ir::Signedness::Unsigned) => {
+            Ok(Value::Byte((a < b) as u8))
+        }
+        (ir::IntOp::Less, ir::Signedness::Signed) => {
+            Ok(Value::Byte(((a as i64) < b as i64) as u8))
+        }
+        (ir::IntOp::LessEq, ir::Signedness::Unsigned) => {
+            Ok(Value::Byte((a <= b) as u8))
+        }
+        (ir::IntOp::LessEq, ir::Signedness::Signed) => {
+            Ok(Value::Byte((a as i64 <= b as i64) as u8))
+        }
+        (ir::IntOp::Mul, ir::Signedness::Unsigned) => {
+            Ok(Value::QuadWord(a.wrapping_mul(b)))
+        }
+        (ir::IntOp::Mul, ir::Signedness::Signed) => {
+            Ok(Value::QuadWord((a as i64).wrapping_mul(b as i64) as u64))
+        }
+        (ir::IntOp::Div, ir::Signedness::Unsigned) if b == 0 => {
+            Err(division_by_zero(op, sign, ir::Size::Bit64, a))
+        }
+        (ir::IntOp::Div, ir::Signedness::Unsigned) => {
+            Ok(Value::QuadWord(a / b))
+        }
+        (ir::IntOp::Div, ir::Signedness::Signed) if b as i64 == 0 => {
+            Err(division_by_zero(op, sign, ir::Size::Bit64, a))
+        }
+        (ir::IntOp::Div, ir::Signedness::Signed) => {
+            Ok(Value::QuadWord((a as i64).wrapping_div(b as i64) as u64))
+        }
+        (ir::IntOp::Mod, ir::Signedness::Unsigned) if b == 0 => {
+            Err(division_by_zero(op, sign, ir::Size::Bit64, a))
+        }
+        (ir::IntOp::Mod, ir::Signedness::Unsigned) => {
+            Ok(Value::QuadWord(a % b))
+        }
+        (ir::IntOp::Mod, ir::Signedness::Signed) if b as i64 == 0 => {
+            Err(division_by_zero(op, sign, ir::Size::Bit64, a))
+        }
+        (ir::IntOp::Mod, ir::Signedness::Signed) => {
+            Ok(Value::QuadWord((a as i64).wrapping_rem(b as i64) as u64))
+        }
+    }
+}
+
 fn int_op_32(op: ir::IntOp, sign: ir::Signedness, a: u32, b: u32) -> Result<Value, Error> {
     match (op, sign) {
         (ir::IntOp::Add, _) => Ok(Value::DoubleWord(a.wrapping_add(b))),
@@ -676,25 +2165,25 @@ fn int_op_32(op: ir::IntOp, sign: ir::Signedness, a: u32, b: u32) -> Result<Valu
             Ok(Value::DoubleWord((a as i32).wrapping_mul(b as i32) as u32))
         }
         (ir::IntOp::Div, ir::Signedness::Unsigned) if b == 0 => {
-            Err(Error::DivisionByZero)
+            Err(division_by_zero(op, sign, ir::Size::Bit32, u64::from(a)))
         }
         (ir::IntOp::Div, ir::Signedness::Unsigned) => {
             Ok(Value::DoubleWord(a / b))
         }
         (ir::IntOp::Div, ir::Signedness::Signed) if b as i32 == 0 => {
-            Err(Error::DivisionByZero)
+            Err(division_by_zero(op, sign, ir::Size::Bit32, u64::from(a)))
         }
         (ir::IntOp::Div, ir::Signedness::Signed) => {
             Ok(Value::DoubleWord((a as i32).wrapping_div(b as i32) as u32))
         }
         (ir::IntOp::Mod, ir::Signedness::Unsigned) if b == 0 => {
-            Err(Error::DivisionByZero)
+            Err(division_by_zero(op, sign, ir::Size::Bit32, u64::from(a)))
         }
         (ir::IntOp::Mod, ir::Signedness::Unsigned) => {
             Ok(Value::DoubleWord(a % b))
         }
         (ir::IntOp::Mod, ir::Signedness::Signed) if b as i32 == 0 => {
-            Err(Error::DivisionByZero)
+            Err(division_by_zero(op, sign, ir::Size::Bit32, u64::from(a)))
         }
         (ir::IntOp::Mod, ir::Signedness::Signed) => {
             Ok(Value::DoubleWord((a as i32).wrapping_rem(b as i32) as u32))
@@ -737,25 +2226,25 @@ fn int_op_16(op: ir::IntOp, sign: ir::Signedness, a: u16, b: u16) -> Result<Valu
             Ok(Value::Word((a as i16).wrapping_mul(b as i16) as u16))
         }
         (ir::IntOp::Div, ir::Signedness::Unsigned) if b == 0 => {
-            Err(Error::DivisionByZero)
+            Err(division_by_zero(op, sign, ir::Size::Bit16, u64::from(a)))
         }
         (ir::IntOp::Div, ir::Signedness::Unsigned) => {
             Ok(Value::Word(a / b))
         }
         (ir::IntOp::Div, ir::Signedness::Signed) if b as i16 == 0 => {
-            Err(Error::DivisionByZero)
+            Err(division_by_zero(op, sign, ir::Size::Bit16, u64::from(a)))
         }
         (ir::IntOp::Div, ir::Signedness::Signed) => {
             Ok(Value::Word((a as i16).wrapping_div(b as i16) as u16))
         }
         (ir::IntOp::Mod, ir::Signedness::Unsigned) if b == 0 => {
-            Err(Error::DivisionByZero)
+            Err(division_by_zero(op, sign, ir::Size::Bit16, u64::from(a)))
         }
         (ir::IntOp::Mod, ir::Signedness::Unsigned) => {
             Ok(Value::Word(a % b))
         }
         (ir::IntOp::Mod, ir::Signedness::Signed) if b as i16 == 0 => {
-            Err(Error::DivisionByZero)
+            Err(division_by_zero(op, sign, ir::Size::Bit16, u64::from(a)))
         }
         (ir::IntOp::Mod, ir::Signedness::Signed) => {
             Ok(Value::Word((a as i16).wrapping_rem(b as i16) as u16))
@@ -798,25 +2287,25 @@ fn int_op_8(op: ir::IntOp, sign: ir::Signedness, a: u8, b: u8) -> Result<Value,
             Ok(Value::Byte((a as i8).wrapping_mul(b as i8) as u8))
         }
         (ir::IntOp::Div, ir::Signedness::Unsigned) if b == 0 => {
-            Err(Error::DivisionByZero)
+            Err(division_by_zero(op, sign, ir::Size::Bit8, u64::from(a)))
         }
         (ir::IntOp::Div, ir::Signedness::Unsigned) => {
             Ok(Value::Byte(a / b))
         }
         (ir::IntOp::Div, ir::Signedness::Signed) if b as i8 == 0 => {
-            Err(Error::DivisionByZero)
+            Err(division_by_zero(op, sign, ir::Size::Bit8, u64::from(a)))
         }
         (ir::IntOp::Div, ir::Signedness::Signed) => {
             Ok(Value::Byte((a as i8).wrapping_div(b as i8) as u8))
         }
         (ir::IntOp::Mod, ir::Signedness::Unsigned) if b == 0 => {
-            Err(Error::DivisionByZero)
+            Err(division_by_zero(op, sign, ir::Size::Bit8, u64::from(a)))
         }
         (ir::IntOp::Mod, ir::Signedness::Unsigned) => {
             Ok(Value::Byte(a % b))
         }
         (ir::IntOp::Mod, ir::Signedness::Signed) if b as i8 == 0 => {
-            Err(Error::DivisionByZero)
+            Err(division_by_zero(op, sign, ir::Size::Bit8, u64::from(a)))
         }
         (ir::IntOp::Mod, ir::Signedness::Signed) => {
             Ok(Value::Byte((a as i8).wrapping_rem(b as i8) as u8))
@@ -824,11 +2313,23 @@ fn int_op_8(op: ir::IntOp, sign: ir::Signedness, a: u8, b: u8) -> Result<Value,
     }
 }
 
+fn bit_op_64(op: ir::BitOp, a: u64, b: u64) -> Value {
+    match op {
+        ir::BitOp::And => Value::QuadWord(a & b),
+        ir::BitOp::Or => Value::QuadWord(a | b),
+        ir::BitOp::Xor => Value::QuadWord(a ^ b),
+        ir::BitOp::Shl => Value::QuadWord(a.wrapping_shl(b as u32)),
+        ir::BitOp::Shr => Value::QuadWord(a.wrapping_shr(b as u32)),
+    }
+}
+
 fn bit_op_32(op: ir::BitOp, a: u32, b: u32) -> Value {
     match op {
         ir::BitOp::And => Value::DoubleWord(a & b),
         ir::BitOp::Or => Value::DoubleWord(a | b),
         ir::BitOp::Xor => Value::DoubleWord(a ^ b),
+        ir::BitOp::Shl => Value::DoubleWord(a.wrapping_shl(b)),
+        ir::BitOp::Shr => Value::DoubleWord(a.wrapping_shr(b)),
     }
 }
 
@@ -837,6 +2338,8 @@ fn bit_op_16(op: ir::BitOp, a: u16, b: u16) -> Value {
         ir::BitOp::And => Value::Word(a & b),
         ir::BitOp::Or => Value::Word(a | b),
         ir::BitOp::Xor => Value::Word(a ^ b),
+        ir::BitOp::Shl => Value::Word(a.wrapping_shl(u32::from(b))),
+        ir::BitOp::Shr => Value::Word(a.wrapping_shr(u32::from(b))),
     }
 }
 
@@ -845,6 +2348,8 @@ fn bit_op_8(op: ir::BitOp, a: u8, b: u8) -> Value {
         ir::BitOp::And => Value::Byte(a & b),
         ir::BitOp::Or => Value::Byte(a | b),
         ir::BitOp::Xor => Value::Byte(a ^ b),
+        ir::BitOp::Shl => Value::Byte(a.wrapping_shl(u32::from(b))),
+        ir::BitOp::Shr => Value::Byte(a.wrapping_shr(u32::from(b))),
     }
 }
 
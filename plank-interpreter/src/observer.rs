@@ -0,0 +1,52 @@
+//! Hooks that let host code follow what interpreted programs do, for
+//! example to trace or profile them.
+
+use plank_errors::position::Span;
+
+
+/// Receives events while the interpreter runs a program. Methods do
+/// nothing by default, so observers implement only the ones they need.
+///
+/// Functions are named by their source name, or by their symbol if it
+/// is not known. Calls to builtins like `putc` are not reported.
+pub trait Observer {
+    /// A function was called and is about to run. `depth` is the number
+    /// of calls that are running, including this one. `main` is reported
+    /// as called with depth 1 before the program starts.
+    fn on_call(&mut self, _function: &str, _depth: usize) {}
+
+    /// A function returned to its caller, or `main` returned and the
+    /// program exited. `depth` is the same as for the call.
+    fn on_return(&mut self, _function: &str, _depth: usize) {}
+
+    /// Program started running code from another line of the source,
+    /// or came back to a line after a call. `span` is the span of the
+    /// first instruction that runs.
+    fn on_statement(&mut self, _function: &str, _span: Span) {}
+
+    /// Program wrote `bytes` to memory starting at given address.
+    /// Registers of functions are in program memory too, and the
+    /// arguments of `main` are reported as written after it is called.
+    fn on_memory_write(&mut self, _address: u32, _bytes: &[u8]) {}
+}
+
+/// Observer that ignores all events.
+impl Observer for () {}
+
+impl<O: Observer + ?Sized> Observer for &mut O {
+    fn on_call(&mut self, function: &str, depth: usize) {
+        (**self).on_call(function, depth);
+    }
+
+    fn on_return(&mut self, function: &str, depth: usize) {
+        (**self).on_return(function, depth);
+    }
+
+    fn on_statement(&mut self, function: &str, span: Span) {
+        (**self).on_statement(function, span);
+    }
+
+    fn on_memory_write(&mut self, address: u32, bytes: &[u8]) {
+        (**self).on_memory_write(address, bytes);
+    }
+}
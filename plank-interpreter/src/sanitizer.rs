@@ -0,0 +1,86 @@
+//! Bookkeeping for the sanitizer mode of the interpreter, which catches
+//! uses of uninitialized memory and accesses outside of live objects.
+
+use std::collections::BTreeMap;
+
+
+/// Remembers which bytes of program memory were written to, and where
+/// strings and registers of running functions are. Heap blocks are
+/// known from the `Heap`.
+#[derive(Debug, Clone)]
+pub struct Sanitizer {
+    heap_start: u32,
+    /// Whether each byte below `heap_start` was written to.
+    stack: Vec<bool>,
+    /// Same for bytes from `heap_start`.
+    heap: Vec<bool>,
+    /// Sizes of strings and registers by their address.
+    objects: BTreeMap<u32, u32>,
+}
+
+impl Sanitizer {
+    /// Create a sanitizer for a program that has `stack_len`
+    /// uninitialized bytes below the heap.
+    pub fn new(heap_start: u32, stack_len: usize) -> Sanitizer {
+        Sanitizer {
+            heap_start,
+            stack: vec![false; stack_len],
+            heap: Vec::new(),
+            objects: BTreeMap::new(),
+        }
+    }
+
+    /// Start tracking an object below the heap. Memory between
+    /// known objects is uninitialized.
+    pub fn add_object(&mut self, address: u32, size: u32, initialized: bool) {
+        let end = (address + size) as usize;
+        if self.stack.len() < end {
+            self.stack.resize(end, false);
+        }
+        for byte in &mut self.stack[address as usize..end] {
+            *byte = initialized;
+        }
+        if size > 0 {
+            self.objects.insert(address, size);
+        }
+    }
+
+    /// Forget about objects from given address, when
+    /// functions that own them return.
+    pub fn truncate_stack(&mut self, len: usize) {
+        self.stack.truncate(len);
+        self.objects.split_off(&(len as u32));
+    }
+
+    /// Track as many heap bytes as the interpreter keeps.
+    pub fn resize_heap(&mut self, len: usize) {
+        self.heap.resize(len, false);
+    }
+
+    pub fn is_initialized(&self, address: u32) -> bool {
+        if address >= self.heap_start {
+            self.heap[(address - self.heap_start) as usize]
+        } else {
+            self.stack[address as usize]
+        }
+    }
+
+    pub fn set_initialized(&mut self, address: u32, initialized: bool) {
+        if address >= self.heap_start {
+            self.heap[(address - self.heap_start) as usize] = initialized;
+        } else {
+            self.stack[address as usize] = initialized;
+        }
+    }
+
+    /// Whether `len` bytes from `address` are all within one object
+    /// below the heap.
+    pub fn is_in_object(&self, address: u32, len: u32) -> bool {
+        match self.objects.range(..=address).next_back() {
+            Some((&start, &size)) => {
+                u64::from(address) + u64::from(len) <= u64::from(start) + u64::from(size)
+            }
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::{HashMap, HashSet};
+use ir::{Function, BlockId, BlockEnd};
+
+
+fn successors(end: &BlockEnd) -> Vec<BlockId> {
+    match *end {
+        BlockEnd::Jump(a) => vec![a],
+        BlockEnd::Branch(_, a, b) => vec![a, b],
+        BlockEnd::Return(_) |
+        BlockEnd::ReturnProc |
+        BlockEnd::Unreachable => Vec::new(),
+    }
+}
+
+/// Blocks reachable from the start block, in postorder.
+fn postorder(f: &Function) -> Vec<BlockId> {
+    let mut order = Vec::new();
+    let mut visited = HashSet::new();
+    let mut stack = Vec::new();
+    if let Some(start) = f.start_block {
+        visited.insert(start);
+        stack.push((start, 0));
+    }
+    while let Some((id, next)) = stack.pop() {
+        let successors = successors(&f.blocks[&id].end);
+        if next < successors.len() {
+            stack.push((id, next + 1));
+            if visited.insert(successors[next]) {
+                stack.push((successors[next], 0));
+            }
+        } else {
+            order.push(id);
+        }
+    }
+    order
+}
+
+/// Find the immediate dominator of every block reachable from the
+/// start block. Start block itself is not included in the result.
+pub fn immediate_dominators(f: &Function) -> HashMap<BlockId, BlockId> {
+    let order = postorder(f);
+    let index = order
+        .iter()
+        .enumerate()
+        .map(|(index, &id)| (id, index))
+        .collect::<HashMap<_, _>>();
+    let mut predecessors = HashMap::new();
+    for &id in &order {
+        for succ in successors(&f.blocks[&id].end) {
+            predecessors.entry(succ).or_insert_with(Vec::new).push(id);
+        }
+    }
+
+    let start = match order.last() {
+        Some(&start) => start,
+        None => return HashMap::new(),
+    };
+    let mut idom = HashMap::new();
+    idom.insert(start, start);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &id in order.iter().rev().skip(1) {
+            let mut new_idom = None;
+            for &pred in &predecessors[&id] {
+                if !idom.contains_key(&pred) {
+                    continue;
+                }
+                new_idom = Some(match new_idom {
+                    None => pred,
+                    Some(mut other) => {
+                        // walk up the tree until both paths meet
+                        let mut pred = pred;
+                        while pred != other {
+                            while index[&pred] < index[&other] {
+                                pred = idom[&pred];
+                            }
+                            while index[&other] < index[&pred] {
+                                other = idom[&other];
+                            }
+                        }
+                        pred
+                    }
+                });
+            }
+            let new_idom = new_idom.expect("reachable block without processed predecessors");
+            if idom.get(&id) != Some(&new_idom) {
+                idom.insert(id, new_idom);
+                changed = true;
+            }
+        }
+    }
+    idom.remove(&start);
+    idom
+}
@@ -64,7 +64,7 @@ impl<'a> Liveness<'a> {
 
 fn is_dropped(block: &Block, from: usize, reg: Reg) -> bool {
     for op in &block.ops[from..] {
-        if let Instruction::Drop(r) = *op {
+        if let Instruction::Drop(r) = op.instruction {
             if r == reg {
                 return true;
             }
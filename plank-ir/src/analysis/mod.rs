@@ -1,6 +1,7 @@
 pub mod liveness;
 pub mod volatility;
 pub mod usage;
+pub mod dominators;
 
 use ir::{BlockId, Instruction, Reg};
 
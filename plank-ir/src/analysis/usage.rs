@@ -56,7 +56,7 @@ impl<'a> Context<'a> {
                     BlockEnd::Unreachable => {}
                 }
             } else {
-                match block.ops[loc.pos] {
+                match block.ops[loc.pos].instruction {
                     Instruction::Assign(r, ref val) |
                     Instruction::CastAssign(r, ref val) |
                     Instruction::UnaryOp(r, _, ref val) => {
@@ -84,6 +84,10 @@ impl<'a> Context<'a> {
                                 return true;
                             }
                         }
+                        // callee might read register through a pointer
+                        if self.is_volatile_at(reg, loc) {
+                            return true;
+                        }
                         if r == reg {
                             continue;
                         }
@@ -94,6 +98,9 @@ impl<'a> Context<'a> {
                                 return true;
                             }
                         }
+                        if self.is_volatile_at(reg, loc) {
+                            return true;
+                        }
                     }
                     Instruction::CallVirt(r, ref f, ref params) => {
                         for param in params {
@@ -104,6 +111,9 @@ impl<'a> Context<'a> {
                         if is_used_in_val(reg, f) {
                             return true;
                         }
+                        if self.is_volatile_at(reg, loc) {
+                            return true;
+                        }
                         if r == reg {
                             continue;
                         }
@@ -117,6 +127,9 @@ impl<'a> Context<'a> {
                         if is_used_in_val(reg, f) {
                             return true;
                         }
+                        if self.is_volatile_at(reg, loc) {
+                            return true;
+                        }
                     }
                     Instruction::DerefLoad(r, ref val, _) => {
                         if is_used_in_val(reg, val) {
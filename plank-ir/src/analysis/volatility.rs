@@ -37,7 +37,7 @@ impl<'a> Volatility<'a> {
             if referenced_register(instr) == Some(self.reg) {
                 is_volatile = true;
             }
-            if let Instruction::Drop(reg) = *instr {
+            if let Instruction::Drop(reg) = instr.instruction {
                 if reg == self.reg {
                     is_volatile = false;
                 }
@@ -0,0 +1,920 @@
+//! Compact binary encoding of IR programs.
+//!
+//! Encoded program starts with `MAGIC`, a format version and the pointer
+//! size, followed by a table of all symbols used in the program, and then
+//! the functions.
+//! Integers are written as LEB128 varints, and symbols are referred to
+//! by their index in the symbol table. Debug info of functions is
+//! written after each function, and debug types after all functions,
+//! followed by the source files of programs compiled from several.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use ir::{
+    Program, Function, Layout, Inline, Reg, BlockId, Block, BlockEnd, Instruction, Op,
+    Value, Symbol, BinaryOp, UnaryOp, IntOp, BitOp, Overflow, Signedness, Size, SourceFile,
+};
+use target::{CallingConvention, Target};
+use debug;
+use plank_errors::position::{Position, Span};
+
+
+/// Bytes that every encoded program starts with.
+const MAGIC: &[u8] = b"\0plankir";
+
+const VERSION: u8 = 7;
+
+/// How deeply debug types can be nested, so that malformed input
+/// can't overflow the stack when it is decoded.
+const MAX_TYPE_DEPTH: u32 = 256;
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Io(io::Error),
+    /// Input is not an encoded program.
+    BadMagic,
+    /// Program was encoded with an unsupported version of the format.
+    UnsupportedVersion(u8),
+    /// Input is truncated or otherwise malformed.
+    Malformed(&'static str),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::Io(ref err) => write!(f, "{}", err),
+            DecodeError::BadMagic => write!(f, "input is not encoded IR"),
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported IR format version {}", version)
+            }
+            DecodeError::Malformed(what) => write!(f, "malformed IR: {}", what),
+        }
+    }
+}
+
+impl From<io::Error> for DecodeError {
+    fn from(err: io::Error) -> DecodeError {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            DecodeError::Malformed("unexpected end of input")
+        } else {
+            DecodeError::Io(err)
+        }
+    }
+}
+
+type Result<T> = ::std::result::Result<T, DecodeError>;
+
+struct Encoder {
+    out: Vec<u8>,
+    symbols: Vec<Arc<str>>,
+    symbol_indices: HashMap<Arc<str>, u32>,
+}
+
+impl Encoder {
+    fn byte(&mut self, byte: u8) {
+        self.out.push(byte);
+    }
+
+    fn uint(&mut self, mut value: u64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.out.push(byte);
+                break;
+            }
+            self.out.push(byte | 0x80);
+        }
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.byte(value as u8);
+    }
+
+    fn string(&mut self, s: &str) {
+        self.uint(s.len() as u64);
+        self.out.extend_from_slice(s.as_bytes());
+    }
+
+    fn symbol(&mut self, sym: &Symbol) {
+        let next = self.symbols.len() as u32;
+        let index = *self.symbol_indices.entry(sym.0.clone()).or_insert(next);
+        if index == next {
+            self.symbols.push(sym.0.clone());
+        }
+        self.uint(u64::from(index));
+    }
+
+    fn reg(&mut self, reg: Reg) {
+        self.uint(u64::from(reg.0));
+    }
+
+    fn block_id(&mut self, id: BlockId) {
+        self.uint(u64::from(id.0));
+    }
+
+    fn layout(&mut self, layout: Layout) {
+        self.uint(u64::from(layout.size));
+        self.uint(u64::from(layout.align));
+        self.bool(layout.atomic);
+    }
+
+    fn size(&mut self, size: Size) {
+        self.byte(match size {
+            Size::Bit8 => 0,
+            Size::Bit16 => 1,
+            Size::Bit32 => 2,
+            Size::Bit64 => 3,
+        });
+    }
+
+    fn signedness(&mut self, sign: Signedness) {
+        self.byte(match sign {
+            Signedness::Unsigned => 0,
+            Signedness::Signed => 1,
+        });
+    }
+
+    fn value(&mut self, value: &Value) {
+        match *value {
+            Value::Int(n, size) => {
+                self.byte(0);
+                self.uint(n);
+                self.size(size);
+            }
+            Value::Reg(reg) => {
+                self.byte(1);
+                self.reg(reg);
+            }
+            Value::Symbol(ref sym) => {
+                self.byte(2);
+                self.symbol(sym);
+            }
+            Value::Bytes(ref bytes) => {
+                self.byte(3);
+                self.uint(bytes.len() as u64);
+                self.out.extend_from_slice(bytes);
+            }
+            Value::Undef => self.byte(4),
+        }
+    }
+
+    fn values(&mut self, values: &[Value]) {
+        self.uint(values.len() as u64);
+        for value in values {
+            self.value(value);
+        }
+    }
+
+    fn int_op(&mut self, op: IntOp) {
+        self.byte(match op {
+            IntOp::Add => 0,
+            IntOp::Sub => 1,
+            IntOp::Mul => 2,
+            IntOp::Div => 3,
+            IntOp::Mod => 4,
+            IntOp::Less => 5,
+            IntOp::LessEq => 6,
+            IntOp::Greater => 7,
+            IntOp::GreaterEq => 8,
+        });
+    }
+
+    fn binary_op(&mut self, op: BinaryOp) {
+        match op {
+            BinaryOp::IntOp(op, sign, size) => {
+                self.byte(0);
+                self.int_op(op);
+                self.signedness(sign);
+                self.size(size);
+            }
+            BinaryOp::BitOp(op, size) => {
+                self.byte(1);
+                self.byte(match op {
+                    BitOp::And => 0,
+                    BitOp::Or => 1,
+                    BitOp::Xor => 2,
+                    BitOp::Shl => 3,
+                    BitOp::Shr => 4,
+                });
+                self.size(size);
+            }
+            BinaryOp::Eq => self.byte(2),
+            BinaryOp::Neq => self.byte(3),
+            BinaryOp::Checked(op, overflow, sign, size) => {
+                self.byte(4);
+                self.int_op(op);
+                self.byte(match overflow {
+                    Overflow::Trap => 0,
+                    Overflow::Saturate => 1,
+                });
+                self.signedness(sign);
+                self.size(size);
+            }
+        }
+    }
+
+    fn unary_op(&mut self, op: UnaryOp) {
+        match op {
+            UnaryOp::Negate(sign, size) => {
+                self.byte(0);
+                self.signedness(sign);
+                self.size(size);
+            }
+            UnaryOp::Extend(sign, from, to) => {
+                self.byte(1);
+                self.signedness(sign);
+                self.size(from);
+                self.size(to);
+            }
+        }
+    }
+
+    fn instruction(&mut self, instr: &Instruction) {
+        match *instr {
+            Instruction::Unreachable => self.byte(0),
+            Instruction::Nop => self.byte(1),
+            Instruction::Init(r) => {
+                self.byte(2);
+                self.reg(r);
+            }
+            Instruction::Drop(r) => {
+                self.byte(3);
+                self.reg(r);
+            }
+            Instruction::BinaryOp(r, op, ref a, ref b) => {
+                self.byte(4);
+                self.reg(r);
+                self.binary_op(op);
+                self.value(a);
+                self.value(b);
+            }
+            Instruction::UnaryOp(r, op, ref a) => {
+                self.byte(5);
+                self.reg(r);
+                self.unary_op(op);
+                self.value(a);
+            }
+            Instruction::Call(r, ref sym, ref params) => {
+                self.byte(6);
+                self.reg(r);
+                self.symbol(sym);
+                self.values(params);
+            }
+            Instruction::CallProc(ref sym, ref params) => {
+                self.byte(7);
+                self.symbol(sym);
+                self.values(params);
+            }
+            Instruction::CallVirt(r, ref f, ref params) => {
+                self.byte(8);
+                self.reg(r);
+                self.value(f);
+                self.values(params);
+            }
+            Instruction::CallProcVirt(ref f, ref params) => {
+                self.byte(9);
+                self.value(f);
+                self.values(params);
+            }
+            Instruction::DerefStore(ref address, offset, ref value) => {
+                self.byte(10);
+                self.value(address);
+                self.uint(u64::from(offset));
+                self.value(value);
+            }
+            Instruction::DerefLoad(r, ref address, offset) => {
+                self.byte(11);
+                self.reg(r);
+                self.value(address);
+                self.uint(u64::from(offset));
+            }
+            Instruction::Store(r, offset, ref value) => {
+                self.byte(12);
+                self.reg(r);
+                self.uint(u64::from(offset));
+                self.value(value);
+            }
+            Instruction::Load(r, from, offset) => {
+                self.byte(13);
+                self.reg(r);
+                self.reg(from);
+                self.uint(u64::from(offset));
+            }
+            Instruction::TakeAddress(r, of, offset) => {
+                self.byte(14);
+                self.reg(r);
+                self.reg(of);
+                self.uint(u64::from(offset));
+            }
+            Instruction::Assign(r, ref value) => {
+                self.byte(15);
+                self.reg(r);
+                self.value(value);
+            }
+            Instruction::CastAssign(r, ref value) => {
+                self.byte(16);
+                self.reg(r);
+                self.value(value);
+            }
+        }
+    }
+
+    fn position(&mut self, pos: Position) {
+        self.uint(u64::from(pos.line));
+        self.uint(u64::from(pos.column));
+        self.uint(u64::from(pos.offset));
+    }
+
+    fn span(&mut self, span: Option<Span>) {
+        match span {
+            Some(span) => {
+                self.bool(true);
+                self.position(span.start);
+                self.position(span.end);
+            }
+            None => self.bool(false),
+        }
+    }
+
+    fn debug_type(&mut self, typ: &debug::Type) {
+        match *typ {
+            debug::Type::Unit => self.byte(0),
+            debug::Type::Bool => self.byte(1),
+            debug::Type::Int(sign, size) => {
+                self.byte(2);
+                self.signedness(sign);
+                self.size(size);
+            }
+            debug::Type::Pointer(ref to) => {
+                self.byte(3);
+                self.debug_type(to);
+            }
+            debug::Type::Composite(ref name) => {
+                self.byte(4);
+                self.string(name);
+            }
+        }
+    }
+
+    fn debug_function(&mut self, debug: &debug::Function) {
+        self.string(&debug.name);
+        self.span(debug.span);
+        self.debug_type(&debug.return_type);
+        self.uint(debug.variables.len() as u64);
+        for variable in &debug.variables {
+            self.string(&variable.name);
+            self.debug_type(&variable.typ);
+            self.reg(variable.reg);
+            self.bool(variable.is_parameter);
+        }
+    }
+
+    fn composite(&mut self, name: &str, composite: &debug::Composite) {
+        self.string(name);
+        self.bool(composite.is_union);
+        self.uint(u64::from(composite.size));
+        self.uint(composite.fields.len() as u64);
+        for field in &composite.fields {
+            self.string(&field.name);
+            self.uint(u64::from(field.offset));
+            self.debug_type(&field.typ);
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.uint(block.ops.len() as u64);
+        for op in &block.ops {
+            self.instruction(op);
+            self.span(op.span);
+        }
+        match block.end {
+            BlockEnd::Return(ref value) => {
+                self.byte(0);
+                self.value(value);
+            }
+            BlockEnd::ReturnProc => self.byte(1),
+            BlockEnd::Jump(to) => {
+                self.byte(2);
+                self.block_id(to);
+            }
+            BlockEnd::Branch(ref cond, a, b) => {
+                self.byte(3);
+                self.value(cond);
+                self.block_id(a);
+                self.block_id(b);
+            }
+            BlockEnd::Unreachable => self.byte(4),
+        }
+        self.span(block.end_span);
+    }
+
+    fn function(&mut self, name: &Symbol, f: &Function) {
+        self.symbol(name);
+        self.byte(match f.inline {
+            Inline::Auto => 0,
+            Inline::Always => 1,
+            Inline::Never => 2,
+        });
+        self.byte(match f.calling_convention {
+            CallingConvention::Stack => 0,
+            CallingConvention::C => 1,
+            CallingConvention::Stdcall => 2,
+        });
+        self.uint(f.parameters.len() as u64);
+        for &param in &f.parameters {
+            self.reg(param);
+        }
+        match f.output_layout {
+            Some(layout) => {
+                self.bool(true);
+                self.layout(layout);
+            }
+            None => self.bool(false),
+        }
+        // sort everything so that encoding the same program
+        // always gives the same bytes
+        let mut registers = f.registers.iter().collect::<Vec<_>>();
+        registers.sort_by_key(|&(&reg, _)| reg);
+        self.uint(registers.len() as u64);
+        for (&reg, &layout) in registers {
+            self.reg(reg);
+            self.layout(layout);
+        }
+        match f.start_block {
+            Some(start) => {
+                self.bool(true);
+                self.block_id(start);
+            }
+            None => self.bool(false),
+        }
+        let mut blocks = f.blocks.iter().collect::<Vec<_>>();
+        blocks.sort_by_key(|&(&id, _)| id);
+        self.uint(blocks.len() as u64);
+        for (&id, block) in blocks {
+            self.block_id(id);
+            self.block(block);
+        }
+        match f.debug {
+            Some(ref debug) => {
+                self.bool(true);
+                self.debug_function(debug);
+            }
+            None => self.bool(false),
+        }
+    }
+}
+
+/// Write program in the binary format.
+pub fn encode_program<W: Write>(program: &Program, mut out: W) -> io::Result<()> {
+    let mut encoder = Encoder {
+        out: Vec::new(),
+        symbols: Vec::new(),
+        symbol_indices: HashMap::new(),
+    };
+    let mut functions = program.functions.iter().collect::<Vec<_>>();
+    functions.sort_by(|&(a, _), &(b, _)| a.0.cmp(&b.0));
+    encoder.uint(functions.len() as u64);
+    for (name, f) in functions {
+        encoder.function(name, f);
+    }
+    let mut types = program.debug_types.iter().collect::<Vec<_>>();
+    types.sort_by_key(|&(name, _)| name);
+    encoder.uint(types.len() as u64);
+    for (name, composite) in types {
+        encoder.composite(name, composite);
+    }
+    encoder.uint(program.files.len() as u64);
+    for file in &program.files {
+        encoder.string(&file.path);
+        encoder.uint(u64::from(file.start_line));
+    }
+
+    // symbol table has to go first, so encode it separately
+    let body = ::std::mem::take(&mut encoder.out);
+    let symbols = ::std::mem::take(&mut encoder.symbols);
+    encoder.out.extend_from_slice(MAGIC);
+    encoder.byte(VERSION);
+    encoder.size(program.target.pointer_size);
+    encoder.uint(symbols.len() as u64);
+    for sym in symbols {
+        encoder.uint(sym.len() as u64);
+        encoder.out.extend_from_slice(sym.as_bytes());
+    }
+    out.write_all(&encoder.out)?;
+    out.write_all(&body)
+}
+
+struct Decoder<R> {
+    input: R,
+    symbols: Vec<Symbol>,
+}
+
+impl<R: Read> Decoder<R> {
+    fn byte(&mut self) -> Result<u8> {
+        let mut byte = [0];
+        self.input.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    fn uint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            if shift >= 64 || (shift == 63 && byte & 0x7e != 0) {
+                return Err(DecodeError::Malformed("integer too large"));
+            }
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+        }
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let value = self.uint()?;
+        if value > u64::from(u32::MAX) {
+            Err(DecodeError::Malformed("integer too large"))
+        } else {
+            Ok(value as u32)
+        }
+    }
+
+    fn len(&mut self) -> Result<usize> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>> {
+        let len = self.len()?;
+        let mut bytes = Vec::new();
+        // read through `take`, so that a bad length can't allocate a lot up front
+        (&mut self.input).take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() < len {
+            return Err(DecodeError::Malformed("unexpected end of input"));
+        }
+        Ok(bytes)
+    }
+
+    fn bool(&mut self) -> Result<bool> {
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(DecodeError::Malformed("invalid boolean")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        String::from_utf8(self.bytes()?)
+            .map_err(|_| DecodeError::Malformed("string is not valid utf8"))
+    }
+
+    fn symbol(&mut self) -> Result<Symbol> {
+        let index = self.len()?;
+        self.symbols
+            .get(index)
+            .cloned()
+            .ok_or(DecodeError::Malformed("invalid symbol index"))
+    }
+
+    fn reg(&mut self) -> Result<Reg> {
+        Ok(Reg(self.u32()?))
+    }
+
+    fn block_id(&mut self) -> Result<BlockId> {
+        Ok(BlockId(self.u32()?))
+    }
+
+    fn layout(&mut self) -> Result<Layout> {
+        Ok(Layout {
+            size: self.u32()?,
+            align: self.u32()?,
+            atomic: self.bool()?,
+        })
+    }
+
+    fn size(&mut self) -> Result<Size> {
+        match self.byte()? {
+            0 => Ok(Size::Bit8),
+            1 => Ok(Size::Bit16),
+            2 => Ok(Size::Bit32),
+            3 => Ok(Size::Bit64),
+            _ => Err(DecodeError::Malformed("invalid int size")),
+        }
+    }
+
+    fn signedness(&mut self) -> Result<Signedness> {
+        match self.byte()? {
+            0 => Ok(Signedness::Unsigned),
+            1 => Ok(Signedness::Signed),
+            _ => Err(DecodeError::Malformed("invalid signedness")),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        match self.byte()? {
+            0 => Ok(Value::Int(self.uint()?, self.size()?)),
+            1 => Ok(Value::Reg(self.reg()?)),
+            2 => Ok(Value::Symbol(self.symbol()?)),
+            3 => Ok(Value::Bytes(self.bytes()?)),
+            4 => Ok(Value::Undef),
+            _ => Err(DecodeError::Malformed("invalid value")),
+        }
+    }
+
+    fn values(&mut self) -> Result<Vec<Value>> {
+        let len = self.len()?;
+        let mut values = Vec::new();
+        for _ in 0..len {
+            values.push(self.value()?);
+        }
+        Ok(values)
+    }
+
+    fn int_op(&mut self) -> Result<IntOp> {
+        match self.byte()? {
+            0 => Ok(IntOp::Add),
+            1 => Ok(IntOp::Sub),
+            2 => Ok(IntOp::Mul),
+            3 => Ok(IntOp::Div),
+            4 => Ok(IntOp::Mod),
+            5 => Ok(IntOp::Less),
+            6 => Ok(IntOp::LessEq),
+            7 => Ok(IntOp::Greater),
+            8 => Ok(IntOp::GreaterEq),
+            _ => Err(DecodeError::Malformed("invalid int operation")),
+        }
+    }
+
+    fn binary_op(&mut self) -> Result<BinaryOp> {
+        match self.byte()? {
+            0 => {
+                let op = self.int_op()?;
+                Ok(BinaryOp::IntOp(op, self.signedness()?, self.size()?))
+            }
+            1 => {
+                let op = match self.byte()? {
+                    0 => BitOp::And,
+                    1 => BitOp::Or,
+                    2 => BitOp::Xor,
+                    3 => BitOp::Shl,
+                    4 => BitOp::Shr,
+                    _ => return Err(DecodeError::Malformed("invalid bit operation")),
+                };
+                Ok(BinaryOp::BitOp(op, self.size()?))
+            }
+            2 => Ok(BinaryOp::Eq),
+            3 => Ok(BinaryOp::Neq),
+            4 => {
+                let op = match self.int_op()? {
+                    op @ IntOp::Add | op @ IntOp::Sub | op @ IntOp::Mul | op @ IntOp::Div => op,
+                    _ => return Err(DecodeError::Malformed("invalid checked operation")),
+                };
+                let overflow = match self.byte()? {
+                    0 => Overflow::Trap,
+                    1 => Overflow::Saturate,
+                    _ => return Err(DecodeError::Malformed("invalid overflow behavior")),
+                };
+                Ok(BinaryOp::Checked(op, overflow, self.signedness()?, self.size()?))
+            }
+            _ => Err(DecodeError::Malformed("invalid binary operation")),
+        }
+    }
+
+    fn unary_op(&mut self) -> Result<UnaryOp> {
+        match self.byte()? {
+            0 => Ok(UnaryOp::Negate(self.signedness()?, self.size()?)),
+            1 => Ok(UnaryOp::Extend(self.signedness()?, self.size()?, self.size()?)),
+            _ => Err(DecodeError::Malformed("invalid unary operation")),
+        }
+    }
+
+    fn instruction(&mut self) -> Result<Instruction> {
+        Ok(match self.byte()? {
+            0 => Instruction::Unreachable,
+            1 => Instruction::Nop,
+            2 => Instruction::Init(self.reg()?),
+            3 => Instruction::Drop(self.reg()?),
+            4 => {
+                let r = self.reg()?;
+                Instruction::BinaryOp(r, self.binary_op()?, self.value()?, self.value()?)
+            }
+            5 => Instruction::UnaryOp(self.reg()?, self.unary_op()?, self.value()?),
+            6 => Instruction::Call(self.reg()?, self.symbol()?, self.values()?),
+            7 => Instruction::CallProc(self.symbol()?, self.values()?),
+            8 => Instruction::CallVirt(self.reg()?, self.value()?, self.values()?),
+            9 => Instruction::CallProcVirt(self.value()?, self.values()?),
+            10 => Instruction::DerefStore(self.value()?, self.u32()?, self.value()?),
+            11 => Instruction::DerefLoad(self.reg()?, self.value()?, self.u32()?),
+            12 => Instruction::Store(self.reg()?, self.u32()?, self.value()?),
+            13 => Instruction::Load(self.reg()?, self.reg()?, self.u32()?),
+            14 => Instruction::TakeAddress(self.reg()?, self.reg()?, self.u32()?),
+            15 => Instruction::Assign(self.reg()?, self.value()?),
+            16 => Instruction::CastAssign(self.reg()?, self.value()?),
+            _ => return Err(DecodeError::Malformed("invalid instruction")),
+        })
+    }
+
+    fn position(&mut self) -> Result<Position> {
+        Ok(Position::with_offset(self.u32()?, self.u32()?, self.u32()?))
+    }
+
+    fn span(&mut self) -> Result<Option<Span>> {
+        if !self.bool()? {
+            return Ok(None);
+        }
+        let start = self.position()?;
+        let end = self.position()?;
+        if start > end {
+            return Err(DecodeError::Malformed("span ends before it starts"));
+        }
+        Ok(Some(Span::new(start, end)))
+    }
+
+    fn debug_type(&mut self, depth: u32) -> Result<debug::Type> {
+        if depth > MAX_TYPE_DEPTH {
+            return Err(DecodeError::Malformed("debug type is nested too deeply"));
+        }
+        match self.byte()? {
+            0 => Ok(debug::Type::Unit),
+            1 => Ok(debug::Type::Bool),
+            2 => Ok(debug::Type::Int(self.signedness()?, self.size()?)),
+            3 => Ok(debug::Type::Pointer(Box::new(self.debug_type(depth + 1)?))),
+            4 => Ok(debug::Type::Composite(self.string()?)),
+            _ => Err(DecodeError::Malformed("invalid debug type")),
+        }
+    }
+
+    fn debug_function(&mut self) -> Result<debug::Function> {
+        let name = self.string()?;
+        let span = self.span()?;
+        let return_type = self.debug_type(0)?;
+        let count = self.len()?;
+        let mut variables = Vec::new();
+        for _ in 0..count {
+            variables.push(debug::Variable {
+                name: self.string()?,
+                typ: self.debug_type(0)?,
+                reg: self.reg()?,
+                is_parameter: self.bool()?,
+            });
+        }
+        Ok(debug::Function {
+            name,
+            span,
+            return_type,
+            variables,
+        })
+    }
+
+    fn composite(&mut self) -> Result<(String, debug::Composite)> {
+        let name = self.string()?;
+        let is_union = self.bool()?;
+        let size = self.u32()?;
+        let count = self.len()?;
+        let mut fields = Vec::new();
+        for _ in 0..count {
+            fields.push(debug::Field {
+                name: self.string()?,
+                offset: self.u32()?,
+                typ: self.debug_type(0)?,
+            });
+        }
+        Ok((name, debug::Composite { is_union, size, fields }))
+    }
+
+    fn block(&mut self) -> Result<Block> {
+        let len = self.len()?;
+        let mut ops = Vec::new();
+        for _ in 0..len {
+            let instruction = self.instruction()?;
+            ops.push(Op::new(instruction, self.span()?));
+        }
+        let end = match self.byte()? {
+            0 => BlockEnd::Return(self.value()?),
+            1 => BlockEnd::ReturnProc,
+            2 => BlockEnd::Jump(self.block_id()?),
+            3 => BlockEnd::Branch(self.value()?, self.block_id()?, self.block_id()?),
+            4 => BlockEnd::Unreachable,
+            _ => return Err(DecodeError::Malformed("invalid block end")),
+        };
+        Ok(Block { ops, end, end_span: self.span()? })
+    }
+
+    fn function(&mut self) -> Result<(Symbol, Function)> {
+        let name = self.symbol()?;
+        let inline = match self.byte()? {
+            0 => Inline::Auto,
+            1 => Inline::Always,
+            2 => Inline::Never,
+            _ => return Err(DecodeError::Malformed("invalid inline hint")),
+        };
+        let calling_convention = match self.byte()? {
+            0 => CallingConvention::Stack,
+            1 => CallingConvention::C,
+            2 => CallingConvention::Stdcall,
+            _ => return Err(DecodeError::Malformed("invalid calling convention")),
+        };
+        let param_count = self.len()?;
+        let mut parameters = Vec::new();
+        for _ in 0..param_count {
+            parameters.push(self.reg()?);
+        }
+        let output_layout = if self.bool()? {
+            Some(self.layout()?)
+        } else {
+            None
+        };
+        let reg_count = self.len()?;
+        let mut registers = HashMap::new();
+        for _ in 0..reg_count {
+            let reg = self.reg()?;
+            registers.insert(reg, self.layout()?);
+        }
+        let start_block = if self.bool()? {
+            Some(self.block_id()?)
+        } else {
+            None
+        };
+        let block_count = self.len()?;
+        let mut blocks = HashMap::new();
+        for _ in 0..block_count {
+            let id = self.block_id()?;
+            blocks.insert(id, self.block()?);
+        }
+        let debug = if self.bool()? {
+            Some(self.debug_function()?)
+        } else {
+            None
+        };
+        let function = Function {
+            parameters,
+            output_layout,
+            registers,
+            blocks,
+            start_block,
+            inline,
+            calling_convention,
+            debug,
+        };
+        Ok((name, function))
+    }
+}
+
+/// Read program written by `encode_program`. Decoded program
+/// is not validated, use `validate_ir` if it comes from an untrusted source.
+pub fn decode_program<R: Read>(input: R) -> Result<Program> {
+    let mut decoder = Decoder {
+        input,
+        symbols: Vec::new(),
+    };
+    let mut magic = [0; 8];
+    if decoder.input.read_exact(&mut magic).is_err() || &magic[..] != MAGIC {
+        return Err(DecodeError::BadMagic);
+    }
+    let version = decoder.byte()?;
+    if version != VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+    let target = Target::with_pointer_size(decoder.size()?)
+        .ok_or(DecodeError::Malformed("invalid pointer size"))?;
+    let symbol_count = decoder.len()?;
+    for _ in 0..symbol_count {
+        let bytes = decoder.bytes()?;
+        let sym = String::from_utf8(bytes)
+            .map_err(|_| DecodeError::Malformed("symbol is not valid utf8"))?;
+        decoder.symbols.push(Symbol(sym.into()));
+    }
+    let function_count = decoder.len()?;
+    let mut functions = HashMap::new();
+    for _ in 0..function_count {
+        let (name, function) = decoder.function()?;
+        functions.insert(name, function);
+    }
+    let type_count = decoder.len()?;
+    let mut debug_types = HashMap::new();
+    for _ in 0..type_count {
+        let (name, composite) = decoder.composite()?;
+        debug_types.insert(name, composite);
+    }
+    let file_count = decoder.len()?;
+    let mut files = Vec::new();
+    for _ in 0..file_count {
+        let path = decoder.string()?;
+        let start_line = decoder.u32()?;
+        files.push(SourceFile { path, start_line });
+    }
+    let mut rest = [0];
+    if decoder.input.read(&mut rest)? != 0 {
+        return Err(DecodeError::Malformed("trailing data after program"));
+    }
+    Ok(Program {
+        functions,
+        target,
+        debug_types,
+        files,
+    })
+}
@@ -0,0 +1,55 @@
+//! Source level description of a program. It does not change what the
+//! program does, but lets backends emit debug info about it.
+
+use plank_errors::position::Span;
+use ir::{Reg, Signedness, Size};
+
+/// Source level type of a variable.
+#[derive(PartialEq, Eq, Debug, Hash, Clone)]
+pub enum Type {
+    /// Type without any values, like `unit`.
+    Unit,
+    Bool,
+    Int(Signedness, Size),
+    Pointer(Box<Type>),
+    /// Struct, union, slice or function value, which
+    /// is described in `Program::debug_types`.
+    Composite(String),
+}
+
+/// Type that is made of several fields.
+#[derive(Debug, Clone)]
+pub struct Composite {
+    /// All fields of a union start at offset zero.
+    pub is_union: bool,
+    pub size: u32,
+    pub fields: Vec<Field>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub name: String,
+    pub offset: u32,
+    pub typ: Type,
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    /// Name of the function, like `main` or `id<i32>`.
+    pub name: String,
+    /// Span of the function header, `None` for functions that the
+    /// compiler makes up without any source, like builtins.
+    pub span: Option<Span>,
+    pub return_type: Type,
+    /// Parameters in declaration order, then other variables.
+    pub variables: Vec<Variable>,
+}
+
+/// Named variable, and the register that holds it.
+#[derive(Debug, Clone)]
+pub struct Variable {
+    pub name: String,
+    pub typ: Type,
+    pub reg: Reg,
+    pub is_parameter: bool,
+}
@@ -0,0 +1,104 @@
+use std::io::{self, Write};
+use analysis::dominators::immediate_dominators;
+use ir::{self, BlockEnd};
+use printer::emit_block;
+
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Block contents as a left-aligned DOT label.
+fn block_label(id: ir::BlockId, block: &ir::Block) -> io::Result<String> {
+    let mut text = Vec::new();
+    emit_block(block, &mut text)?;
+    let text = String::from_utf8(text).expect("printer wrote invalid utf8");
+    let mut label = format!("label_{}:\\l", id.0);
+    for line in text.lines() {
+        label.push_str(&escape(line));
+        label.push_str("\\l");
+    }
+    Ok(label)
+}
+
+fn emit_function<W: Write>(
+    index: usize,
+    name: &ir::Symbol,
+    f: &ir::Function,
+    dominators: bool,
+    out: &mut W,
+) -> io::Result<()> {
+    let start = match f.start_block {
+        Some(start) => start,
+        None => return Ok(()),
+    };
+    writeln!(out, "    subgraph cluster_{} {{", index)?;
+    writeln!(out, "        label=\"{}\";", escape(&name.0))?;
+    writeln!(out, "        f{}_start [label=\"start\", shape=oval];", index)?;
+    writeln!(out, "        f{}_start -> f{}_{};", index, index, start.0)?;
+
+    let mut blocks = f.blocks.iter().collect::<Vec<_>>();
+    blocks.sort_by_key(|&(&id, _)| id);
+    for &(&id, block) in &blocks {
+        let label = block_label(id, block)?;
+        writeln!(out, "        f{}_{} [label=\"{}\"];", index, id.0, label)?;
+    }
+    for &(&id, block) in &blocks {
+        match block.end {
+            BlockEnd::Jump(a) => {
+                writeln!(out, "        f{}_{} -> f{}_{};", index, id.0, index, a.0)?;
+            }
+            BlockEnd::Branch(_, a, b) => {
+                writeln!(
+                    out,
+                    "        f{}_{} -> f{}_{} [label=\"true\"];",
+                    index,
+                    id.0,
+                    index,
+                    a.0
+                )?;
+                writeln!(
+                    out,
+                    "        f{}_{} -> f{}_{} [label=\"false\"];",
+                    index,
+                    id.0,
+                    index,
+                    b.0
+                )?;
+            }
+            BlockEnd::Return(_) |
+            BlockEnd::ReturnProc |
+            BlockEnd::Unreachable => {}
+        }
+    }
+
+    if dominators {
+        let mut idom = immediate_dominators(f).into_iter().collect::<Vec<_>>();
+        idom.sort();
+        for (id, dominator) in idom {
+            writeln!(
+                out,
+                "        f{}_{} -> f{}_{} [style=dashed, color=blue, constraint=false];",
+                index,
+                dominator.0,
+                index,
+                id.0
+            )?;
+        }
+    }
+    writeln!(out, "    }}")
+}
+
+/// Write control flow graph of every function as a Graphviz graph.
+/// If `dominators` is set, the dominator tree is included as
+/// dashed edges.
+pub fn emit_cfg<W: Write>(program: &ir::Program, dominators: bool, mut out: W) -> io::Result<()> {
+    let mut functions = program.functions.iter().collect::<Vec<_>>();
+    functions.sort_by(|&(a, _), &(b, _)| a.0.cmp(&b.0));
+    writeln!(out, "digraph cfg {{")?;
+    writeln!(out, "    node [shape=box, fontname=monospace];")?;
+    for (index, (name, f)) in functions.into_iter().enumerate() {
+        emit_function(index, name, f, dominators, &mut out)?;
+    }
+    writeln!(out, "}}")
+}
@@ -1,16 +1,81 @@
 use std::collections::HashMap;
-use std::rc::Rc;
+use std::ops;
+use std::sync::Arc;
+use plank_errors::position::Span;
+use debug;
+use target::{CallingConvention, Target};
 
 
-pub const POINTER_SIZE: u32 = 4;
-pub const FUNCTION_SIZE: u32 = 4;
-
 #[derive(PartialEq, Eq, Debug, Hash, Clone)]
-pub struct Symbol(pub Rc<str>);
+pub struct Symbol(pub Arc<str>);
 
 #[derive(Debug, Clone)]
 pub struct Program {
     pub functions: HashMap<Symbol, Function>,
+    /// Machine the program is compiled for. Its pointer size
+    /// decides the size of pointers and function values.
+    pub target: Target,
+    /// Structs and other compound types that `debug::Type::Composite`
+    /// refers to, by their name.
+    pub debug_types: HashMap<String, debug::Composite>,
+    /// Files that the program was compiled from, if there were several.
+    /// Spans count lines in all of them joined together, in this order.
+    pub files: Vec<SourceFile>,
+}
+
+/// One of several source files that a program was compiled from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFile {
+    pub path: String,
+    /// Line of the joined source that the file starts at, counting from 0.
+    pub start_line: u32,
+}
+
+/// Position in the source of a program, with lines and columns
+/// counted from 1. Displayed like `3:5`, or `lib.plk:3:5` if the
+/// program was compiled from several files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: Option<String>,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl ::std::fmt::Display for Location {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        if let Some(ref file) = self.file {
+            write!(f, "{}:", file)?;
+        }
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// Find which of `files` a line of the joined source is in. Returns
+/// the file, unless `files` is empty, and the line within it.
+/// Lines count from 0.
+pub fn locate_line(files: &[SourceFile], line: u32) -> (Option<&SourceFile>, u32) {
+    match files.iter().rev().find(|file| file.start_line <= line) {
+        Some(file) => (Some(file), line - file.start_line),
+        None => (None, line),
+    }
+}
+
+impl Program {
+    /// Location of a line and column of the joined source,
+    /// counting from 1 like the ones passed to `builtin_assert_fail`.
+    pub fn locate(&self, line: u32, column: u32) -> Location {
+        let (file, line) = locate_line(&self.files, line.saturating_sub(1));
+        Location {
+            file: file.map(|file| file.path.clone()),
+            line: line + 1,
+            column,
+        }
+    }
+
+    /// Location of the start of a span.
+    pub fn locate_span(&self, span: Span) -> Location {
+        self.locate(span.start.line + 1, span.start.column + 1)
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -27,6 +92,24 @@ pub struct Function {
     pub registers: HashMap<Reg, Layout>,
     pub blocks: HashMap<BlockId, Block>,
     pub start_block: Option<BlockId>,
+    pub inline: Inline,
+    /// Functions declared with `extern "C"` and similar use the
+    /// given convention, all others use the one of the target.
+    pub calling_convention: CallingConvention,
+    /// Source level description of the function, used to emit
+    /// debug info. Functions parsed from IR text have none.
+    pub debug: Option<debug::Function>,
+}
+
+/// Whether calls to a function should be inlined.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum Inline {
+    /// Let the inliner decide by the size of the function.
+    Auto,
+    /// `#[inline]`, inline regardless of size.
+    Always,
+    /// `#[inline(never)]`
+    Never,
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Copy, Clone)]
@@ -37,8 +120,45 @@ pub struct BlockId(pub u32);
 
 #[derive(Debug, Clone)]
 pub struct Block {
-    pub ops: Vec<Instruction>,
+    pub ops: Vec<Op>,
     pub end: BlockEnd,
+    /// Source code that `end` was compiled from, if any.
+    pub end_span: Option<Span>,
+}
+
+/// Instruction together with the source code it was compiled from.
+#[derive(Debug, Clone)]
+pub struct Op {
+    pub instruction: Instruction,
+    /// `None` if the instruction does not come from source code,
+    /// like ones added by optimizations or written by hand.
+    pub span: Option<Span>,
+}
+
+impl Op {
+    pub fn new(instruction: Instruction, span: Option<Span>) -> Op {
+        Op { instruction, span }
+    }
+}
+
+impl From<Instruction> for Op {
+    fn from(instruction: Instruction) -> Op {
+        Op::new(instruction, None)
+    }
+}
+
+impl ops::Deref for Op {
+    type Target = Instruction;
+
+    fn deref(&self) -> &Instruction {
+        &self.instruction
+    }
+}
+
+impl ops::DerefMut for Op {
+    fn deref_mut(&mut self) -> &mut Instruction {
+        &mut self.instruction
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -100,22 +220,26 @@ pub enum BlockEnd {
 #[derive(Debug, Copy, Clone)]
 pub enum BinaryOp {
     IntOp(IntOp, Signedness, Size),
+    /// `Add`, `Sub`, `Mul` or `Div` that doesn't wrap around when
+    /// the result doesn't fit in its size.
+    Checked(IntOp, Overflow, Signedness, Size),
     BitOp(BitOp, Size),
     Eq,
     Neq,
 }
 
-#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+#[derive(PartialEq, Eq, Debug, Hash, Copy, Clone)]
 pub enum Signedness {
     Unsigned,
     Signed,
 }
 
-#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+#[derive(PartialEq, Eq, Debug, Hash, Copy, Clone)]
 pub enum Size {
     Bit8,
     Bit16,
     Bit32,
+    Bit64,
 }
 
 impl Size {
@@ -124,6 +248,7 @@ impl Size {
             Size::Bit8 => 1,
             Size::Bit16 => 2,
             Size::Bit32 => 4,
+            Size::Bit64 => 8,
         }
     }
 
@@ -132,6 +257,7 @@ impl Size {
             Size::Bit8 => value & 0xff,
             Size::Bit16 => value & 0xffff,
             Size::Bit32 => value & 0xffffffff,
+            Size::Bit64 => value,
         }
     }
 
@@ -140,6 +266,16 @@ impl Size {
             Size::Bit8 => (value as i8) as i64,
             Size::Bit16 => (value as i16) as i64,
             Size::Bit32 => (value as i32) as i64,
+            Size::Bit64 => value as i64,
+        }
+    }
+
+    /// Extend value of this size to size `to`, copying
+    /// the sign bit if the value is signed.
+    pub fn extend(&self, value: u64, sign: Signedness, to: Size) -> u64 {
+        match sign {
+            Signedness::Signed => to.truncate(self.to_signed(value) as u64),
+            Signedness::Unsigned => self.truncate(value),
         }
     }
 }
@@ -157,14 +293,91 @@ pub enum IntOp {
     GreaterEq,
 }
 
+impl IntOp {
+    /// Compute `Add`, `Sub`, `Mul` or `Div` exactly. Returns `Err` with
+    /// the value of given size that is closest to the result if it doesn't
+    /// fit. The divisor must not be zero.
+    pub fn checked(self, sign: Signedness, size: Size, a: u64, b: u64) -> Result<u64, u64> {
+        let exact = |value: u64| match sign {
+            Signedness::Signed => i128::from(size.to_signed(value)),
+            Signedness::Unsigned => i128::from(size.truncate(value)),
+        };
+        let (a, b) = (exact(a), exact(b));
+        let bits = size.in_bytes() * 8;
+        let (min, max) = match sign {
+            Signedness::Signed => (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1),
+            Signedness::Unsigned => (0, (1i128 << bits) - 1),
+        };
+        let result = match self {
+            IntOp::Add => a + b,
+            IntOp::Sub => a - b,
+            // only products of two 64 bit values don't fit in i128
+            IntOp::Mul => a.checked_mul(b).unwrap_or_else(|| {
+                if (a < 0) == (b < 0) {
+                    max + 1
+                } else {
+                    min - 1
+                }
+            }),
+            IntOp::Div => a / b,
+            _ => panic!("{:?} is not checked arithmetic", self),
+        };
+        if result < min {
+            Err(size.truncate(min as u64))
+        } else if result > max {
+            Err(size.truncate(max as u64))
+        } else {
+            Ok(size.truncate(result as u64))
+        }
+    }
+
+    /// Describe arithmetic for error messages, like `127 + 1 in i8`.
+    pub fn describe(self, sign: Signedness, size: Size, a: u64, b: u64) -> String {
+        let symbol = match self {
+            IntOp::Add => '+',
+            IntOp::Sub => '-',
+            IntOp::Mul => '*',
+            IntOp::Div => '/',
+            IntOp::Mod => '%',
+            _ => panic!("{:?} is not arithmetic", self),
+        };
+        let bits = size.in_bytes() * 8;
+        match sign {
+            Signedness::Signed => {
+                let (a, b) = (size.to_signed(a), size.to_signed(b));
+                format!("{} {} {} in i{}", a, symbol, b, bits)
+            }
+            Signedness::Unsigned => {
+                let (a, b) = (size.truncate(a), size.truncate(b));
+                format!("{} {} {} in u{}", a, symbol, b, bits)
+            }
+        }
+    }
+}
+
+/// What checked arithmetic does when the result doesn't fit.
+#[derive(PartialEq, Eq, Debug, Hash, Copy, Clone)]
+pub enum Overflow {
+    /// Stop the program.
+    Trap,
+    /// Use the smallest or the largest value instead.
+    Saturate,
+}
+
 #[derive(PartialEq, Eq, Debug, Copy, Clone)]
 pub enum BitOp {
     And,
     Or,
     Xor,
+    /// Shift left. Shifting by the bit width of the value or more is undefined.
+    Shl,
+    /// Logical shift right. Shifting by the bit width of the value or more is undefined.
+    Shr,
 }
 
 #[derive(Debug, Copy, Clone)]
 pub enum UnaryOp {
     Negate(Signedness, Size),
+    /// Convert int of first size to a wider int of second size.
+    Extend(Signedness, Size, Size),
 }
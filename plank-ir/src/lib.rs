@@ -1,9 +1,21 @@
+extern crate plank_errors;
+
 pub mod ir;
+pub mod debug;
 pub mod optimization;
 pub mod analysis;
 pub mod validation;
+pub mod parallel;
+pub mod target;
 mod printer;
+mod parser;
+mod dot;
+mod binary;
 
 pub use ir::Program;
+pub use target::Target;
 pub use printer::emit_program;
+pub use dot::emit_cfg;
+pub use binary::{encode_program, decode_program, DecodeError};
+pub use parser::{parse_program, ParseError};
 pub use validation::validate_ir;
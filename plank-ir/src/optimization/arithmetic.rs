@@ -1,6 +1,6 @@
 use analysis::Loc;
-use ir::{Program, Instruction, Value, BinaryOp, IntOp};
-use optimization::Rewriter;
+use ir::{Function, Instruction, Value, BinaryOp, IntOp};
+use optimization::{OptLevel, Pass, PassKind, Rewriter};
 
 
 struct Simplifier;
@@ -47,6 +47,12 @@ fn is_immediate(val: &Value) -> bool {
     }
 }
 
-pub fn rewrite(program: &mut Program) {
-    Simplifier.rewrite_program(program);
+pub const PASS: Pass = Pass {
+    name: "arithmetic",
+    level: OptLevel::O1,
+    kind: PassKind::Function(|f, _| rewrite(f)),
+};
+
+pub fn rewrite(f: &mut Function) {
+    Simplifier.rewrite_function(f);
 }
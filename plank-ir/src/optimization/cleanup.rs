@@ -1,14 +1,15 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashSet;
 use analysis::Loc;
-use ir::{Program, Function, Block, BlockId, Instruction, BlockEnd, Value, Reg};
-use super::Rewriter;
+use ir::{Function, Block, BlockId, Instruction, BlockEnd, Value, Reg};
+use super::{OptLevel, Pass, PassKind, Rewriter};
+use super::simplify_cfg;
 
 
 struct RemoveNops;
 
 impl Rewriter for RemoveNops {
     fn rewrite_block(&mut self, _id: BlockId, block: &mut Block) {
-        block.ops.retain(|o| if let Instruction::Nop = *o {
+        block.ops.retain(|o| if let Instruction::Nop = o.instruction {
             false
         } else {
             true
@@ -22,7 +23,7 @@ impl Rewriter for ShortenUnreachable {
     fn rewrite_block(&mut self, _id: BlockId, block: &mut Block) {
         let mut to_keep = None;
         for (index, op) in block.ops.iter().enumerate() {
-            if let Instruction::Unreachable = *op {
+            if let Instruction::Unreachable = op.instruction {
                 to_keep = Some(index);
             }
         }
@@ -33,94 +34,6 @@ impl Rewriter for ShortenUnreachable {
     }
 }
 
-#[derive(Default)]
-struct JoinBlocks {
-    references: HashMap<BlockId, u32>,
-}
-
-impl Rewriter for JoinBlocks {
-    fn rewrite_function(&mut self, f: &mut Function) {
-        self.references.clear();
-        for block in f.blocks.values() {
-            match block.end {
-                BlockEnd::Branch(_, a, b) => {
-                    *self.references.entry(a).or_insert(0) += 1;
-                    *self.references.entry(b).or_insert(0) += 1;
-                }
-                BlockEnd::Jump(a) => {
-                    *self.references.entry(a).or_insert(0) += 1;
-                }
-                BlockEnd::Return(_) |
-                BlockEnd::ReturnProc |
-                BlockEnd::Unreachable => {}
-            }
-        }
-        if let Some(block) = f.start_block {
-            *self.references.entry(block).or_insert(0) += 1;
-        }
-        loop {
-            enum Change {
-                Remove(BlockId),
-                Join(BlockId, BlockId),
-                None,
-            }
-            let mut change = Change::None;
-            for (&id, block) in &f.blocks {
-                if *self.references.entry(id).or_insert(0) == 0 {
-                    change = Change::Remove(id);
-                    break;
-                }
-                if let BlockEnd::Jump(to) = block.end {
-                    if *self.references.entry(to).or_insert(0) == 1 {
-                        change = if id == to {
-                            Change::Remove(id)
-                        } else {
-                            Change::Join(id, to)
-                        };
-                        break;
-                    }
-                }
-            }
-            match change {
-                Change::Join(a, b) => {
-                    let removed = f.blocks.remove(&b).unwrap();
-                    let first = f.blocks.get_mut(&a).unwrap();
-                    first.ops.extend(removed.ops);
-                    match first.end {
-                        BlockEnd::Branch(_, a, b) => {
-                            *self.references.get_mut(&a).unwrap() -= 1;
-                            *self.references.get_mut(&b).unwrap() -= 1;
-                        }
-                        BlockEnd::Jump(a) => {
-                            *self.references.get_mut(&a).unwrap() -= 1;
-                        }
-                        BlockEnd::Return(_) |
-                        BlockEnd::ReturnProc |
-                        BlockEnd::Unreachable => {}
-                    }
-                    first.end = removed.end;
-                }
-                Change::Remove(block) => {
-                    let block = f.blocks.remove(&block).unwrap();
-                    match block.end {
-                        BlockEnd::Branch(_, a, b) => {
-                            *self.references.get_mut(&a).unwrap() -= 1;
-                            *self.references.get_mut(&b).unwrap() -= 1;
-                        }
-                        BlockEnd::Jump(a) => {
-                            *self.references.get_mut(&a).unwrap() -= 1;
-                        }
-                        BlockEnd::Return(_) |
-                        BlockEnd::ReturnProc |
-                        BlockEnd::Unreachable => {}
-                    }
-                }
-                Change::None => break,
-            }
-        }
-    }
-}
-
 #[derive(Default)]
 struct RemoveUnusedRegs {
     used: HashSet<Reg>,
@@ -204,9 +117,15 @@ impl Rewriter for RemoveUnusedRegs {
     }
 }
 
-pub fn rewrite(program: &mut Program) {
-    RemoveNops.rewrite_program(program);
-    JoinBlocks::default().rewrite_program(program);
-    ShortenUnreachable.rewrite_program(program);
-    RemoveUnusedRegs::default().rewrite_program(program);
+pub const PASS: Pass = Pass {
+    name: "cleanup",
+    level: OptLevel::O1,
+    kind: PassKind::Function(|f, _| rewrite(f)),
+};
+
+pub fn rewrite(f: &mut Function) {
+    RemoveNops.rewrite_function(f);
+    simplify_cfg::rewrite(f);
+    ShortenUnreachable.rewrite_function(f);
+    RemoveUnusedRegs::default().rewrite_function(f);
 }
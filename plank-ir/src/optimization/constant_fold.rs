@@ -1,6 +1,7 @@
 use std::collections::{HashMap, HashSet};
 use analysis::{self, Loc};
-use ir::{Function, BinaryOp, BitOp, IntOp, UnaryOp, Signedness, Size, Program, Reg, Block, Instruction, Value, BlockEnd, BlockId};
+use ir::{Function, BinaryOp, BitOp, IntOp, Overflow, UnaryOp, Signedness, Size, Reg, Block, Instruction, Value, BlockEnd, BlockId};
+use super::{OptLevel, Pass, PassKind};
 
 
 #[derive(Debug, Clone)]
@@ -72,8 +73,7 @@ impl<'a> Context<'a> {
                 return total;
             } else {
                 let loc = Loc { block: block_id, pos: pos - 1 };
-                let op = &block.ops[pos - 1];
-                match *op {
+                match block.ops[pos - 1].instruction {
                     Instruction::Assign(r, ref val) |
                     Instruction::CastAssign(r, ref val) if r == reg => return Val::from_ir(val),
                     Instruction::BinaryOp(r, _, _, _) |
@@ -176,7 +176,7 @@ fn rewrite_function(f: &mut Function) {
             for (pos, op) in block.ops.iter_mut().enumerate() {
                 let loc = Loc { block: id, pos };
                 let mut replace_with = None;
-                match *op {
+                match op.instruction {
                     Instruction::Assign(_, ref mut val) |
                     Instruction::CastAssign(_, ref mut val) => {
                         if try_replace_val(val, ctx, loc) {
@@ -256,7 +256,7 @@ fn rewrite_function(f: &mut Function) {
                     }
                 }
                 if let Some(new_op) = replace_with {
-                    *op = new_op;
+                    op.instruction = new_op;
                     changed = true;
                 }
             }
@@ -301,6 +301,14 @@ fn eval_binary_op(op: BinaryOp, a: &Value, b: &Value) -> Option<Value> {
         BinaryOp::BitOp(BitOp::Xor, size) => {
             Some(Value::Int(a ^ b, size))
         }
+        BinaryOp::BitOp(BitOp::Shl, size) if b < u64::from(size.in_bytes() * 8) => {
+            Some(Value::Int(size.truncate(a << b), size))
+        }
+        BinaryOp::BitOp(BitOp::Shr, size) if b < u64::from(size.in_bytes() * 8) => {
+            Some(Value::Int(size.truncate(a) >> b, size))
+        }
+        BinaryOp::BitOp(BitOp::Shl, _) |
+        BinaryOp::BitOp(BitOp::Shr, _) => None,
         BinaryOp::Eq => {
             let res = if a == b { 1 } else { 0 };
             Some(Value::Int(res, Size::Bit8))
@@ -367,37 +375,46 @@ fn eval_binary_op(op: BinaryOp, a: &Value, b: &Value) -> Option<Value> {
                 None
             }
         }
-        BinaryOp::IntOp(IntOp::Greater, Signedness::Unsigned, size) => {
-            Some(Value::Int(if a > b { 1 } else { 0 }, size))
+        BinaryOp::Checked(IntOp::Div, _, _, size) if size.truncate(b) == 0 => None,
+        BinaryOp::Checked(op, overflow, sign, size) => {
+            match (op.checked(sign, size, a, b), overflow) {
+                (Ok(res), _) |
+                (Err(res), Overflow::Saturate) => Some(Value::Int(res, size)),
+                // leave it to trap when the program runs
+                (Err(_), Overflow::Trap) => None,
+            }
+        }
+        BinaryOp::IntOp(IntOp::Greater, Signedness::Unsigned, _) => {
+            Some(Value::Int(if a > b { 1 } else { 0 }, Size::Bit8))
         }
         BinaryOp::IntOp(IntOp::Greater, Signedness::Signed, size) => {
             let a = size.to_signed(a);
             let b = size.to_signed(b);
-            Some(Value::Int(if a > b { 1 } else { 0 }, size))
+            Some(Value::Int(if a > b { 1 } else { 0 }, Size::Bit8))
         }
-        BinaryOp::IntOp(IntOp::Less, Signedness::Unsigned, size) => {
-            Some(Value::Int(if a < b { 1 } else { 0 }, size))
+        BinaryOp::IntOp(IntOp::Less, Signedness::Unsigned, _) => {
+            Some(Value::Int(if a < b { 1 } else { 0 }, Size::Bit8))
         }
         BinaryOp::IntOp(IntOp::Less, Signedness::Signed, size) => {
             let a = size.to_signed(a);
             let b = size.to_signed(b);
-            Some(Value::Int(if a < b { 1 } else { 0 }, size))
+            Some(Value::Int(if a < b { 1 } else { 0 }, Size::Bit8))
         }
-        BinaryOp::IntOp(IntOp::GreaterEq, Signedness::Unsigned, size) => {
-            Some(Value::Int(if a >= b { 1 } else { 0 }, size))
+        BinaryOp::IntOp(IntOp::GreaterEq, Signedness::Unsigned, _) => {
+            Some(Value::Int(if a >= b { 1 } else { 0 }, Size::Bit8))
         }
         BinaryOp::IntOp(IntOp::GreaterEq, Signedness::Signed, size) => {
             let a = size.to_signed(a);
             let b = size.to_signed(b);
-            Some(Value::Int(if a >= b { 1 } else { 0 }, size))
+            Some(Value::Int(if a >= b { 1 } else { 0 }, Size::Bit8))
         }
-        BinaryOp::IntOp(IntOp::LessEq, Signedness::Unsigned, size) => {
-            Some(Value::Int(if a > b { 1 } else { 0 }, size))
+        BinaryOp::IntOp(IntOp::LessEq, Signedness::Unsigned, _) => {
+            Some(Value::Int(if a <= b { 1 } else { 0 }, Size::Bit8))
         }
         BinaryOp::IntOp(IntOp::LessEq, Signedness::Signed, size) => {
             let a = size.to_signed(a);
             let b = size.to_signed(b);
-            Some(Value::Int(if a <= b { 1 } else { 0 }, size))
+            Some(Value::Int(if a <= b { 1 } else { 0 }, Size::Bit8))
         }
     }
 }
@@ -412,11 +429,12 @@ fn eval_unary_op(op: UnaryOp, a: &Value) -> Option<Value> {
             let res = size.truncate((!a).wrapping_add(1));
             Some(Value::Int(res, size))
         }
+        UnaryOp::Extend(sign, from, to) => Some(Value::Int(from.extend(a, sign, to), to)),
     }
 }
 
-pub fn rewrite(program: &mut Program) {
-    for f in program.functions.values_mut() {
-        rewrite_function(f);
-    }
-}
+pub const PASS: Pass = Pass {
+    name: "constant-fold",
+    level: OptLevel::O1,
+    kind: PassKind::Function(|f, _| rewrite_function(f)),
+};
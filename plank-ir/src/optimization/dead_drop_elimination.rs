@@ -1,7 +1,7 @@
 use std::collections::{HashMap, HashSet};
 use analysis::{self, Loc};
-use ir::{Function, Instruction, BlockId, BlockEnd, Program, Reg};
-use super::Rewriter;
+use ir::{Function, Instruction, BlockId, BlockEnd, Reg};
+use super::{OptLevel, Pass, PassKind, Rewriter};
 
 
 struct Liveness<'a> {
@@ -38,7 +38,7 @@ impl<'a> Liveness<'a> {
             if analysis::initialized_register(instr) == Some(self.reg) {
                 is_live = true;
             }
-            if let Instruction::Drop(reg) = *instr {
+            if let Instruction::Drop(reg) = instr.instruction {
                 if reg == self.reg {
                     is_live = false;
                 }
@@ -103,7 +103,13 @@ impl Rewriter for Context {
     }
 }
 
-pub fn rewrite(program: &mut Program) {
+pub const PASS: Pass = Pass {
+    name: "dead-drop-elimination",
+    level: OptLevel::O2,
+    kind: PassKind::Function(|f, _| rewrite(f)),
+};
+
+pub fn rewrite(f: &mut Function) {
     let mut ctx = Context { live: HashMap::new() };
-    ctx.rewrite_program(program);
+    ctx.rewrite_function(f);
 }
@@ -1,53 +1,68 @@
-use ir::{Instruction, Program};
+use ir::{BinaryOp, Function, Instruction, IntOp, Overflow, Value};
 use analysis::{self, Loc, volatility, usage};
+use super::{OptLevel, Pass, PassKind};
 
 
-fn is_call(instr: &Instruction) -> bool {
+/// Whether the instruction does more than write its result.
+fn has_side_effects(instr: &Instruction) -> bool {
     match *instr {
         Instruction::Call(..) |
         Instruction::CallProc(..) |
         Instruction::CallProcVirt(..) |
-        Instruction::CallVirt(..) => true,
+        Instruction::CallVirt(..) |
+        Instruction::BinaryOp(_, BinaryOp::Checked(_, Overflow::Trap, _, _), _, _) => true,
+        // division by zero traps even if the result is not used
+        Instruction::BinaryOp(_, BinaryOp::IntOp(IntOp::Div, _, _), _, ref divisor) |
+        Instruction::BinaryOp(_, BinaryOp::IntOp(IntOp::Mod, _, _), _, ref divisor) |
+        Instruction::BinaryOp(_, BinaryOp::Checked(IntOp::Div, _, _, _), _, ref divisor) => {
+            match *divisor {
+                Value::Int(value, size) => size.truncate(value) == 0,
+                _ => true,
+            }
+        }
         _ => false,
     }
 }
 
-pub fn rewrite(program: &mut Program) {
+pub const PASS: Pass = Pass {
+    name: "dead-store-elimination",
+    level: OptLevel::O2,
+    kind: PassKind::Function(|f, _| rewrite(f)),
+};
+
+pub fn rewrite(f: &mut Function) {
     loop {
         let mut changed_anything = false;
-        for f in program.functions.values_mut() {
-
-            let volatile = &volatility::volatile_locations(f);
-            let mut to_remove = Vec::new();
-            {
-                let ctx = usage::Context::new(f, volatile);
-                for (&id, block) in &f.blocks {
-                    for (pos, instr) in block.ops.iter().enumerate() {
-                        let loc = Loc {
-                            block: id,
-                            pos: pos + 1,
-                        };
-                        let written = match *instr {
-                            Instruction::Store(reg, _, _) => Some(reg),
-                            ref other => analysis::initialized_register(other),
-                        };
-                        if let Some(reg) = written {
-                            if !ctx.is_value_used(loc, reg) {
-                                to_remove.push(Loc {
-                                    block: id,
-                                    pos,
-                                });
-                            }
+        let volatile = &volatility::volatile_locations(f);
+        let mut to_remove = Vec::new();
+        {
+            let ctx = usage::Context::new(f, volatile);
+            for (&id, block) in &f.blocks {
+                for (pos, instr) in block.ops.iter().enumerate() {
+                    let loc = Loc {
+                        block: id,
+                        pos: pos + 1,
+                    };
+                    let written = match instr.instruction {
+                        Instruction::Store(reg, _, _) => Some(reg),
+                        ref other => analysis::initialized_register(other),
+                    };
+                    if let Some(reg) = written {
+                        if !ctx.is_value_used(loc, reg) {
+                            to_remove.push(Loc {
+                                block: id,
+                                pos,
+                            });
                         }
                     }
                 }
             }
-            for loc in to_remove {
-                let block = f.blocks.get_mut(&loc.block).unwrap();
-                if !is_call(&block.ops[loc.pos]) {
-                    changed_anything = true;
-                    block.ops[loc.pos] = Instruction::Nop;
-                }
+        }
+        for loc in to_remove {
+            let block = f.blocks.get_mut(&loc.block).unwrap();
+            if !has_side_effects(&block.ops[loc.pos]) {
+                changed_anything = true;
+                block.ops[loc.pos].instruction = Instruction::Nop;
             }
         }
         if !changed_anything {
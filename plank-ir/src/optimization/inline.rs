@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use ir::{Program, Function, Block, BlockId, BlockEnd, Inline, Instruction, Op, Reg, Symbol, Value};
+use super::{OptLevel, Pass, PassKind, PassOptions};
+
+
+/// Maps registers and blocks of inlined function to the ones
+/// allocated for them in the caller.
+struct Renamer {
+    regs: HashMap<Reg, Reg>,
+    blocks: HashMap<BlockId, BlockId>,
+}
+
+impl Renamer {
+    fn reg(&self, reg: &mut Reg) {
+        *reg = self.regs[reg];
+    }
+
+    fn value(&self, value: &mut Value) {
+        if let Value::Reg(ref mut reg) = *value {
+            self.reg(reg);
+        }
+    }
+
+    fn values(&self, values: &mut [Value]) {
+        for value in values {
+            self.value(value);
+        }
+    }
+
+    fn instruction(&self, instr: &Instruction) -> Instruction {
+        let mut instr = instr.clone();
+        match instr {
+            Instruction::Unreachable |
+            Instruction::Nop => {}
+            Instruction::Init(ref mut r) |
+            Instruction::Drop(ref mut r) => self.reg(r),
+            Instruction::BinaryOp(ref mut r, _, ref mut a, ref mut b) => {
+                self.reg(r);
+                self.value(a);
+                self.value(b);
+            }
+            Instruction::UnaryOp(ref mut r, _, ref mut val) |
+            Instruction::DerefLoad(ref mut r, ref mut val, _) |
+            Instruction::Store(ref mut r, _, ref mut val) |
+            Instruction::Assign(ref mut r, ref mut val) |
+            Instruction::CastAssign(ref mut r, ref mut val) => {
+                self.reg(r);
+                self.value(val);
+            }
+            Instruction::Call(ref mut r, _, ref mut params) => {
+                self.reg(r);
+                self.values(params);
+            }
+            Instruction::CallProc(_, ref mut params) => {
+                self.values(params);
+            }
+            Instruction::CallVirt(ref mut r, ref mut f, ref mut params) => {
+                self.reg(r);
+                self.value(f);
+                self.values(params);
+            }
+            Instruction::CallProcVirt(ref mut f, ref mut params) => {
+                self.value(f);
+                self.values(params);
+            }
+            Instruction::DerefStore(ref mut a, _, ref mut b) => {
+                self.value(a);
+                self.value(b);
+            }
+            Instruction::Load(ref mut r1, ref mut r2, _) |
+            Instruction::TakeAddress(ref mut r1, ref mut r2, _) => {
+                self.reg(r1);
+                self.reg(r2);
+            }
+        }
+        instr
+    }
+}
+
+fn function_size(f: &Function) -> usize {
+    f.blocks.values().map(|block| block.ops.len() + 1).sum()
+}
+
+fn is_recursive(name: &Symbol, f: &Function) -> bool {
+    f.blocks.values().flat_map(|block| &block.ops).any(|op| match op.instruction {
+        Instruction::Call(_, ref sym, _) |
+        Instruction::CallProc(ref sym, _) => sym == name,
+        _ => false,
+    })
+}
+
+/// Replace call at `pos` in block `id` with the body of `callee`. Returns
+/// block that continues after the call, if the callee can return, and
+/// blocks copied from callee.
+fn inline_call(
+    f: &mut Function,
+    id: BlockId,
+    pos: usize,
+    callee: &Function,
+) -> (Option<BlockId>, Vec<BlockId>) {
+    let call = &f.blocks[&id].ops[pos];
+    let (result, args) = match call.instruction {
+        Instruction::Call(r, _, ref args) => (Some(r), args.clone()),
+        Instruction::CallProc(_, ref args) => (None, args.clone()),
+        _ => panic!("inlining a non-call"),
+    };
+    // code added around the inlined body is attributed to the call
+    let span = call.span;
+
+    let first_reg = f.registers
+        .keys()
+        .chain(&f.parameters)
+        .map(|r| r.0 + 1)
+        .max()
+        .unwrap_or(0);
+    let mut callee_regs = callee.registers.iter().collect::<Vec<_>>();
+    callee_regs.sort_by_key(|&(&reg, _)| reg);
+    let mut regs = HashMap::new();
+    for (index, (&reg, &layout)) in callee_regs.into_iter().enumerate() {
+        let new = Reg(first_reg + index as u32);
+        f.registers.insert(new, layout);
+        regs.insert(reg, new);
+    }
+
+    let mut next_block = f.blocks.keys().map(|b| b.0 + 1).max().unwrap_or(0);
+    let after = BlockId(next_block);
+    next_block += 1;
+    let mut callee_blocks = callee.blocks.keys().cloned().collect::<Vec<_>>();
+    callee_blocks.sort();
+    let mut blocks = HashMap::new();
+    for &block in &callee_blocks {
+        blocks.insert(block, BlockId(next_block));
+        next_block += 1;
+    }
+    let renamer = Renamer { regs, blocks };
+
+    // split the block at the call, and pass parameters by assigning them
+    let start = renamer.blocks[&callee.start_block.unwrap()];
+    let rest = {
+        let block = f.blocks.get_mut(&id).unwrap();
+        let rest_ops = block.ops.split_off(pos + 1);
+        block.ops.pop();
+        for (&param, arg) in callee.parameters.iter().zip(args) {
+            block.ops.push(Op::new(Instruction::Assign(renamer.regs[&param], arg), span));
+        }
+        let rest_end = ::std::mem::replace(&mut block.end, BlockEnd::Jump(start));
+        let rest_span = ::std::mem::replace(&mut block.end_span, span);
+        Block {
+            ops: rest_ops,
+            end: rest_end,
+            end_span: rest_span,
+        }
+    };
+    let returns = callee
+        .blocks
+        .values()
+        .any(|block| matches!(block.end, BlockEnd::Return(_) | BlockEnd::ReturnProc));
+    if returns {
+        f.blocks.insert(after, rest);
+    }
+
+    let mut drops = renamer.regs.values().cloned().collect::<Vec<_>>();
+    drops.sort();
+    let mut new_blocks = Vec::new();
+    for old in callee_blocks {
+        let block = &callee.blocks[&old];
+        let mut ops = block.ops
+            .iter()
+            .map(|op| Op::new(renamer.instruction(op), op.span))
+            .collect::<Vec<_>>();
+        let mut end_span = block.end_span;
+        let end = match block.end {
+            BlockEnd::Return(ref value) => {
+                if let Some(result) = result {
+                    let mut value = value.clone();
+                    renamer.value(&mut value);
+                    ops.push(Op::new(Instruction::Assign(result, value), span));
+                }
+                ops.extend(drops.iter().map(|&reg| Op::new(Instruction::Drop(reg), span)));
+                end_span = span;
+                BlockEnd::Jump(after)
+            }
+            BlockEnd::ReturnProc => {
+                ops.extend(drops.iter().map(|&reg| Op::new(Instruction::Drop(reg), span)));
+                end_span = span;
+                BlockEnd::Jump(after)
+            }
+            BlockEnd::Jump(to) => BlockEnd::Jump(renamer.blocks[&to]),
+            BlockEnd::Branch(ref cond, a, b) => {
+                let mut cond = cond.clone();
+                renamer.value(&mut cond);
+                BlockEnd::Branch(cond, renamer.blocks[&a], renamer.blocks[&b])
+            }
+            BlockEnd::Unreachable => BlockEnd::Unreachable,
+        };
+        let new = renamer.blocks[&old];
+        f.blocks.insert(new, Block { ops, end, end_span });
+        new_blocks.push(new);
+    }
+    (if returns { Some(after) } else { None }, new_blocks)
+}
+
+fn inline_calls(
+    name: &Symbol,
+    f: &mut Function,
+    candidates: &HashMap<Symbol, Function>,
+    max_depth: u32,
+) {
+    // how many calls deep is the code in a block, blocks
+    // that were in the function from the start are not included
+    let mut depths = HashMap::new();
+    let mut work = f.blocks.keys().cloned().collect::<Vec<_>>();
+    work.sort_by(|a, b| b.cmp(a));
+    while let Some(id) = work.pop() {
+        let depth = depths.get(&id).cloned().unwrap_or(0);
+        if depth >= max_depth {
+            continue;
+        }
+        let call = f.blocks[&id].ops.iter().enumerate().filter_map(|(pos, op)| {
+            match op.instruction {
+                Instruction::Call(_, ref sym, _) |
+                Instruction::CallProc(ref sym, _) if sym != name => {
+                    candidates.get(sym).map(|callee| (pos, callee))
+                }
+                _ => None,
+            }
+        }).next();
+        if let Some((pos, callee)) = call {
+            let (after, new_blocks) = inline_call(f, id, pos, callee);
+            if let Some(after) = after {
+                depths.insert(after, depth);
+                work.push(after);
+            }
+            for block in new_blocks.into_iter().rev() {
+                depths.insert(block, depth + 1);
+                work.push(block);
+            }
+        }
+    }
+}
+
+pub const PASS: Pass = Pass {
+    name: "inline",
+    level: OptLevel::O2,
+    kind: PassKind::Program(rewrite),
+};
+
+pub fn rewrite(program: &mut Program, options: &PassOptions) {
+    // always inline the original bodies, so that the result
+    // doesn't depend on the order functions are visited in
+    let candidates = program
+        .functions
+        .iter()
+        .filter(|&(name, f)| {
+            f.start_block.is_some() && match f.inline {
+                Inline::Auto => {
+                    function_size(f) <= options.inline_threshold && !is_recursive(name, f)
+                }
+                Inline::Always => true,
+                Inline::Never => false,
+            }
+        })
+        .map(|(name, f)| (name.clone(), f.clone()))
+        .collect::<HashMap<_, _>>();
+    for (name, f) in &mut program.functions {
+        inline_calls(name, f, &candidates, options.inline_depth);
+    }
+}
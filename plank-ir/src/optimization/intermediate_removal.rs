@@ -1,5 +1,6 @@
 use analysis::{self, Loc};
-use ir::{Program, Function, Instruction, BlockId, Value};
+use ir::{Function, Instruction, BlockId, Op, Value};
+use super::{OptLevel, Pass, PassKind};
 
 
 struct Fix {
@@ -15,7 +16,9 @@ fn inspect_function(f: &Function) -> Vec<Fix> {
         for (pos, op) in block.ops.iter().enumerate() {
             if let Some(reg) = analysis::initialized_register(op) {
                 match block.ops.get(pos + 1) {
-                    Some(&Instruction::Assign(_, Value::Reg(r2))) if r2 == reg => {
+                    Some(&Op { instruction: Instruction::Assign(_, Value::Reg(r2)), .. })
+                        if r2 == reg =>
+                    {
                         let loc = Loc { block: id, pos: pos + 2 };
                         if !ctx.is_value_used(loc, r2) {
                             fixes.push(Fix {
@@ -36,12 +39,12 @@ fn rewrite_function(f: &mut Function) {
     let fixes = inspect_function(f);
     for fix in fixes {
         let block = f.blocks.get_mut(&fix.block).unwrap();
-        let reg = match block.ops[fix.init_at + 1] {
+        let reg = match block.ops[fix.init_at + 1].instruction {
             Instruction::Assign(to, _) => to,
             _ => panic!("invalid fix"),
         };
-        block.ops[fix.init_at + 1] = Instruction::Nop;
-        match block.ops[fix.init_at] {
+        block.ops[fix.init_at + 1].instruction = Instruction::Nop;
+        match block.ops[fix.init_at].instruction {
             Instruction::Assign(ref mut r, _) |
             Instruction::BinaryOp(ref mut r, _, _, _) |
             Instruction::Call(ref mut r, _, _) |
@@ -57,8 +60,8 @@ fn rewrite_function(f: &mut Function) {
     }
 }
 
-pub fn rewrite(program: &mut Program) {
-    for f in program.functions.values_mut() {
-        rewrite_function(f);
-    }
-}
+pub const PASS: Pass = Pass {
+    name: "intermediate-removal",
+    level: OptLevel::O2,
+    kind: PassKind::Function(|f, _| rewrite_function(f)),
+};
@@ -5,16 +5,15 @@ mod intermediate_removal;
 mod dead_drop_elimination;
 mod cleanup;
 mod arithmetic;
+mod strength_reduction;
+mod simplify_cfg;
+mod inline;
 
 use analysis::Loc;
 use ir::{Program, Function, BlockId, Block, Instruction};
 
 
 trait Rewriter {
-    fn rewrite_program(&mut self, program: &mut Program) {
-        rewrite_program(self, program);
-    }
-
     fn rewrite_function(&mut self, f: &mut Function) {
         rewrite_function(self, f);
     }
@@ -26,12 +25,6 @@ trait Rewriter {
     fn rewrite_instruction(&mut self, _loc: Loc, _instr: &mut Instruction) {}
 }
 
-fn rewrite_program<R: Rewriter + ?Sized>(r: &mut R, program: &mut Program) {
-    for f in program.functions.values_mut() {
-        r.rewrite_function(f);
-    }
-}
-
 fn rewrite_function<R: Rewriter + ?Sized>(r: &mut R, f: &mut Function) {
     for (&id, block) in &mut f.blocks {
         r.rewrite_block(id, block);
@@ -45,12 +38,155 @@ fn rewrite_block<R: Rewriter + ?Sized>(r: &mut R, id: BlockId, block: &mut Block
     }
 }
 
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone)]
+pub enum OptLevel {
+    /// No optimizations.
+    O0,
+    /// Only local rewrites of single instructions.
+    O1,
+    /// Also passes that need liveness or volatility analysis.
+    O2,
+}
+
+/// An optimization pass. Each pass module declares its own `PASS`.
+#[derive(Debug, Copy, Clone)]
+pub struct Pass {
+    pub name: &'static str,
+    /// Lowest optimization level at which this pass is run.
+    pub level: OptLevel,
+    pub kind: PassKind,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum PassKind {
+    /// Pass that needs to see the whole program, like inlining.
+    Program(fn(&mut Program, &PassOptions)),
+    /// Pass that rewrites each function on its own, so
+    /// functions can be processed in parallel.
+    Function(fn(&mut Function, &PassOptions)),
+}
+
+impl Pass {
+    pub fn run(&self, program: &mut Program, options: &PassOptions) {
+        match self.kind {
+            PassKind::Program(run) => run(program, options),
+            PassKind::Function(run) => {
+                let mut functions = program.functions.values_mut().collect::<Vec<_>>();
+                ::parallel::for_each(&mut functions, |f| run(f, options));
+            }
+        }
+    }
+}
+
+/// All optimization passes, in the order they are run.
+pub const PASSES: &[Pass] = &[
+    inline::PASS,
+    simplify_newtypes::PASS,
+    intermediate_removal::PASS,
+    constant_fold::PASS,
+    arithmetic::PASS,
+    strength_reduction::PASS,
+    simplify_cfg::PASS,
+    dead_store_elimination::PASS,
+    dead_drop_elimination::PASS,
+    cleanup::PASS,
+];
+
+/// Thresholds used by optimization passes.
+#[derive(Debug, Copy, Clone)]
+pub struct PassOptions {
+    /// Functions with at most this many instructions are inlined.
+    pub inline_threshold: usize,
+    /// How many levels of nested calls can be inlined into a function.
+    pub inline_depth: u32,
+}
+
+impl Default for PassOptions {
+    fn default() -> Self {
+        PassOptions {
+            inline_threshold: 12,
+            inline_depth: 3,
+        }
+    }
+}
+
+/// Find an optimization pass by its name.
+pub fn find_pass(name: &str) -> Option<Pass> {
+    PASSES.iter().find(|pass| pass.name == name).cloned()
+}
+
+/// Runs a sequence of optimization passes.
+#[derive(Debug, Clone, Default)]
+pub struct PassManager {
+    passes: Vec<Pass>,
+    options: PassOptions,
+}
+
+impl PassManager {
+    /// Create a pass manager that runs no passes.
+    pub fn new() -> PassManager {
+        PassManager {
+            passes: Vec::new(),
+            options: PassOptions::default(),
+        }
+    }
+
+    /// Create a pass manager with the pipeline for given level.
+    pub fn for_level(level: OptLevel) -> PassManager {
+        let passes = PASSES
+            .iter()
+            .filter(|pass| pass.level <= level && level > OptLevel::O0)
+            .cloned()
+            .collect();
+        PassManager {
+            passes,
+            options: PassOptions::default(),
+        }
+    }
+
+    /// Create a pass manager that runs passes with given names in the
+    /// given order. Returns the first unknown name as an error.
+    pub fn with_passes<'a, I>(names: I) -> Result<PassManager, &'a str>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut manager = PassManager::new();
+        for name in names {
+            manager.add_pass(find_pass(name).ok_or(name)?);
+        }
+        Ok(manager)
+    }
+
+    pub fn add_pass(&mut self, pass: Pass) {
+        self.passes.push(pass);
+    }
+
+    pub fn set_options(&mut self, options: PassOptions) {
+        self.options = options;
+    }
+
+    /// Passes that are run, in order.
+    pub fn passes(&self) -> &[Pass] {
+        &self.passes
+    }
+
+    pub fn options(&self) -> PassOptions {
+        self.options
+    }
+
+    pub fn run(&self, program: &mut Program) {
+        if self.passes.is_empty() {
+            return;
+        }
+        ::validation::check_valid(program, || "before optimization".into());
+        for pass in &self.passes {
+            pass.run(program, &self.options);
+            ::validation::check_valid(program, || format!("after `{}` pass", pass.name));
+        }
+    }
+}
+
+/// Run all optimization passes.
 pub fn optimize(program: &mut Program) {
-    simplify_newtypes::rewrite(program);
-    intermediate_removal::rewrite(program);
-    constant_fold::rewrite(program);
-    arithmetic::rewrite(program);
-    dead_store_elimination::rewrite(program);
-    dead_drop_elimination::rewrite(program);
-    cleanup::rewrite(program);
+    PassManager::for_level(OptLevel::O2).run(program);
 }
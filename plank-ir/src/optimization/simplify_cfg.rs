@@ -0,0 +1,170 @@
+use std::collections::{HashMap, HashSet};
+use ir::{Function, BlockId, BlockEnd};
+use super::{OptLevel, Pass, PassKind, Rewriter};
+
+
+struct ThreadJumps;
+
+impl Rewriter for ThreadJumps {
+    fn rewrite_function(&mut self, f: &mut Function) {
+        let mut forwards = HashMap::new();
+        for (&id, block) in &f.blocks {
+            if let BlockEnd::Jump(to) = block.end {
+                if block.ops.is_empty() && id != to {
+                    forwards.insert(id, to);
+                }
+            }
+        }
+        let target = |mut id| {
+            let mut visited = HashSet::new();
+            while let Some(&next) = forwards.get(&id) {
+                if !visited.insert(id) {
+                    // empty infinite loop, keep jumping into it
+                    break;
+                }
+                id = next;
+            }
+            id
+        };
+        for block in f.blocks.values_mut() {
+            match block.end {
+                BlockEnd::Jump(ref mut a) => {
+                    *a = target(*a);
+                }
+                BlockEnd::Branch(_, ref mut a, ref mut b) => {
+                    *a = target(*a);
+                    *b = target(*b);
+                }
+                BlockEnd::Return(_) |
+                BlockEnd::ReturnProc |
+                BlockEnd::Unreachable => {}
+            }
+        }
+    }
+}
+
+struct RemoveUnreachable;
+
+impl Rewriter for RemoveUnreachable {
+    fn rewrite_function(&mut self, f: &mut Function) {
+        let mut reachable = HashSet::new();
+        let mut stack = f.start_block.into_iter().collect::<Vec<_>>();
+        while let Some(id) = stack.pop() {
+            if !reachable.insert(id) {
+                continue;
+            }
+            match f.blocks[&id].end {
+                BlockEnd::Jump(a) => stack.push(a),
+                BlockEnd::Branch(_, a, b) => {
+                    stack.push(a);
+                    stack.push(b);
+                }
+                BlockEnd::Return(_) |
+                BlockEnd::ReturnProc |
+                BlockEnd::Unreachable => {}
+            }
+        }
+        f.blocks.retain(|id, _| reachable.contains(id));
+    }
+}
+
+#[derive(Default)]
+struct JoinBlocks {
+    references: HashMap<BlockId, u32>,
+}
+
+impl Rewriter for JoinBlocks {
+    fn rewrite_function(&mut self, f: &mut Function) {
+        self.references.clear();
+        for block in f.blocks.values() {
+            match block.end {
+                BlockEnd::Branch(_, a, b) => {
+                    *self.references.entry(a).or_insert(0) += 1;
+                    *self.references.entry(b).or_insert(0) += 1;
+                }
+                BlockEnd::Jump(a) => {
+                    *self.references.entry(a).or_insert(0) += 1;
+                }
+                BlockEnd::Return(_) |
+                BlockEnd::ReturnProc |
+                BlockEnd::Unreachable => {}
+            }
+        }
+        if let Some(block) = f.start_block {
+            *self.references.entry(block).or_insert(0) += 1;
+        }
+        loop {
+            enum Change {
+                Remove(BlockId),
+                Join(BlockId, BlockId),
+                None,
+            }
+            let mut change = Change::None;
+            for (&id, block) in &f.blocks {
+                if *self.references.entry(id).or_insert(0) == 0 {
+                    change = Change::Remove(id);
+                    break;
+                }
+                if let BlockEnd::Jump(to) = block.end {
+                    if *self.references.entry(to).or_insert(0) == 1 {
+                        change = if id == to {
+                            Change::Remove(id)
+                        } else {
+                            Change::Join(id, to)
+                        };
+                        break;
+                    }
+                }
+            }
+            match change {
+                Change::Join(a, b) => {
+                    let removed = f.blocks.remove(&b).unwrap();
+                    let first = f.blocks.get_mut(&a).unwrap();
+                    first.ops.extend(removed.ops);
+                    match first.end {
+                        BlockEnd::Branch(_, a, b) => {
+                            *self.references.get_mut(&a).unwrap() -= 1;
+                            *self.references.get_mut(&b).unwrap() -= 1;
+                        }
+                        BlockEnd::Jump(a) => {
+                            *self.references.get_mut(&a).unwrap() -= 1;
+                        }
+                        BlockEnd::Return(_) |
+                        BlockEnd::ReturnProc |
+                        BlockEnd::Unreachable => {}
+                    }
+                    first.end = removed.end;
+                    first.end_span = removed.end_span;
+                }
+                Change::Remove(block) => {
+                    let block = f.blocks.remove(&block).unwrap();
+                    match block.end {
+                        BlockEnd::Branch(_, a, b) => {
+                            *self.references.get_mut(&a).unwrap() -= 1;
+                            *self.references.get_mut(&b).unwrap() -= 1;
+                        }
+                        BlockEnd::Jump(a) => {
+                            *self.references.get_mut(&a).unwrap() -= 1;
+                        }
+                        BlockEnd::Return(_) |
+                        BlockEnd::ReturnProc |
+                        BlockEnd::Unreachable => {}
+                    }
+                }
+                Change::None => break,
+            }
+        }
+    }
+}
+
+pub const PASS: Pass = Pass {
+    name: "simplify-cfg",
+    level: OptLevel::O1,
+    kind: PassKind::Function(|f, _| rewrite(f)),
+};
+
+pub fn rewrite(f: &mut Function) {
+    ThreadJumps.rewrite_function(f);
+    RemoveUnreachable.rewrite_function(f);
+    JoinBlocks::default().rewrite_function(f);
+}
@@ -1,22 +1,29 @@
 use std::collections::HashMap;
 use analysis::Loc;
-use ir::{Program, Reg, Function, Instruction, Value};
-use optimization::{self as opt, Rewriter};
+use ir::{Reg, Function, Instruction, Value, Program};
+use optimization::{self as opt, OptLevel, Pass, PassKind, Rewriter};
 
 
-#[derive(Default)]
 struct Simplifier {
     reg_size: HashMap<Reg, u32>,
+    pointer_size: u32,
 }
 
 impl Simplifier {
+    fn new(pointer_size: u32) -> Self {
+        Simplifier {
+            reg_size: HashMap::new(),
+            pointer_size,
+        }
+    }
+
     fn value_size(&self, val: &Value) -> u32 {
         match *val {
             Value::Undef => 1,
-            Value::Bytes(_) => ::ir::POINTER_SIZE,
+            Value::Bytes(_) => self.pointer_size,
             Value::Int(_, size) => size.in_bytes(),
             Value::Reg(reg) => self.reg_size[&reg],
-            Value::Symbol(_) => ::ir::FUNCTION_SIZE,
+            Value::Symbol(_) => self.pointer_size,
         }
     }
 }
@@ -51,6 +58,14 @@ impl Rewriter for Simplifier {
     }
 }
 
+pub const PASS: Pass = Pass {
+    name: "simplify-newtypes",
+    level: OptLevel::O1,
+    kind: PassKind::Program(|program, _| rewrite(program)),
+};
+
 pub fn rewrite(program: &mut Program) {
-    Simplifier::default().rewrite_program(program);
+    let pointer_size = program.target.pointer_size.in_bytes();
+    let mut functions = program.functions.values_mut().collect::<Vec<_>>();
+    ::parallel::for_each(&mut functions, |f| Simplifier::new(pointer_size).rewrite_function(f));
 }
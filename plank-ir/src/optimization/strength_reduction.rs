@@ -0,0 +1,133 @@
+use analysis::Loc;
+use ir::{Function, Instruction, Value, Reg, BinaryOp, BitOp, IntOp, Signedness, Size};
+use optimization::{OptLevel, Pass, PassKind, Rewriter};
+
+
+struct Reducer;
+
+impl Rewriter for Reducer {
+    fn rewrite_instruction(&mut self, _loc: Loc, instr: &mut Instruction) {
+        let result = match *instr {
+            Instruction::BinaryOp(r, op, ref a, ref b) => reduce(r, op, a, b),
+            _ => None,
+        };
+        if let Some(new) = result {
+            *instr = new;
+        }
+    }
+}
+
+/// Operation that gives the same result with swapped operands.
+fn swapped(op: BinaryOp) -> Option<BinaryOp> {
+    match op {
+        BinaryOp::IntOp(IntOp::Add, _, _) |
+        BinaryOp::IntOp(IntOp::Mul, _, _) |
+        BinaryOp::Checked(IntOp::Add, _, _, _) |
+        BinaryOp::Checked(IntOp::Mul, _, _, _) |
+        BinaryOp::BitOp(BitOp::And, _) |
+        BinaryOp::BitOp(BitOp::Or, _) |
+        BinaryOp::BitOp(BitOp::Xor, _) |
+        BinaryOp::Eq |
+        BinaryOp::Neq => Some(op),
+        BinaryOp::IntOp(IntOp::Less, sign, size) => {
+            Some(BinaryOp::IntOp(IntOp::Greater, sign, size))
+        }
+        BinaryOp::IntOp(IntOp::LessEq, sign, size) => {
+            Some(BinaryOp::IntOp(IntOp::GreaterEq, sign, size))
+        }
+        BinaryOp::IntOp(IntOp::Greater, sign, size) => {
+            Some(BinaryOp::IntOp(IntOp::Less, sign, size))
+        }
+        BinaryOp::IntOp(IntOp::GreaterEq, sign, size) => {
+            Some(BinaryOp::IntOp(IntOp::LessEq, sign, size))
+        }
+        BinaryOp::IntOp(IntOp::Sub, _, _) |
+        BinaryOp::IntOp(IntOp::Div, _, _) |
+        BinaryOp::IntOp(IntOp::Mod, _, _) |
+        BinaryOp::Checked(_, _, _, _) |
+        BinaryOp::BitOp(BitOp::Shl, _) |
+        BinaryOp::BitOp(BitOp::Shr, _) => None,
+    }
+}
+
+fn reduce(r: Reg, op: BinaryOp, a: &Value, b: &Value) -> Option<Instruction> {
+    // move constants to the right, so that only the right operand has to be checked
+    let (op, a, b, swapped) = match (a, b, swapped(op)) {
+        (&Value::Int(_, _), &Value::Reg(_), Some(swapped)) => (swapped, b, a, true),
+        _ => (op, a, b, false),
+    };
+    let n = match *b {
+        Value::Int(n, size) => size.truncate(n),
+        _ if swapped => return Some(Instruction::BinaryOp(r, op, a.clone(), b.clone())),
+        _ => return None,
+    };
+    let result = match op {
+        BinaryOp::IntOp(IntOp::Add, _, _) |
+        BinaryOp::IntOp(IntOp::Sub, _, _) |
+        BinaryOp::Checked(IntOp::Add, _, _, _) |
+        BinaryOp::Checked(IntOp::Sub, _, _, _) |
+        BinaryOp::BitOp(BitOp::Or, _) |
+        BinaryOp::BitOp(BitOp::Xor, _) |
+        BinaryOp::BitOp(BitOp::Shl, _) |
+        BinaryOp::BitOp(BitOp::Shr, _) if n == 0 => Instruction::Assign(r, a.clone()),
+        BinaryOp::IntOp(IntOp::Mul, _, _) |
+        BinaryOp::IntOp(IntOp::Div, _, _) |
+        BinaryOp::Checked(IntOp::Mul, _, _, _) |
+        BinaryOp::Checked(IntOp::Div, _, _, _) if n == 1 => Instruction::Assign(r, a.clone()),
+        BinaryOp::BitOp(BitOp::And, size) if n == size.truncate(!0) => {
+            Instruction::Assign(r, a.clone())
+        }
+        BinaryOp::IntOp(IntOp::Mul, _, size) |
+        BinaryOp::Checked(IntOp::Mul, _, _, size) |
+        BinaryOp::BitOp(BitOp::And, size) if n == 0 => {
+            Instruction::Assign(r, Value::Int(0, size))
+        }
+        BinaryOp::IntOp(IntOp::Mod, _, size) if n == 1 => {
+            Instruction::Assign(r, Value::Int(0, size))
+        }
+        BinaryOp::IntOp(IntOp::Mul, _, size) if n.is_power_of_two() => {
+            let shift = Value::Int(u64::from(n.trailing_zeros()), size);
+            Instruction::BinaryOp(r, BinaryOp::BitOp(BitOp::Shl, size), a.clone(), shift)
+        }
+        BinaryOp::IntOp(IntOp::Div, Signedness::Unsigned, size) if n.is_power_of_two() => {
+            let shift = Value::Int(u64::from(n.trailing_zeros()), size);
+            Instruction::BinaryOp(r, BinaryOp::BitOp(BitOp::Shr, size), a.clone(), shift)
+        }
+        BinaryOp::IntOp(IntOp::Mod, Signedness::Unsigned, size) if n.is_power_of_two() => {
+            let mask = Value::Int(n - 1, size);
+            Instruction::BinaryOp(r, BinaryOp::BitOp(BitOp::And, size), a.clone(), mask)
+        }
+        // unsigned comparisons with 0 and 1 are just checks for 0
+        BinaryOp::IntOp(IntOp::Greater, Signedness::Unsigned, size) if n == 0 => {
+            Instruction::BinaryOp(r, BinaryOp::Neq, a.clone(), Value::Int(0, size))
+        }
+        BinaryOp::IntOp(IntOp::GreaterEq, Signedness::Unsigned, size) if n == 1 => {
+            Instruction::BinaryOp(r, BinaryOp::Neq, a.clone(), Value::Int(0, size))
+        }
+        BinaryOp::IntOp(IntOp::LessEq, Signedness::Unsigned, size) if n == 0 => {
+            Instruction::BinaryOp(r, BinaryOp::Eq, a.clone(), Value::Int(0, size))
+        }
+        BinaryOp::IntOp(IntOp::Less, Signedness::Unsigned, size) if n == 1 => {
+            Instruction::BinaryOp(r, BinaryOp::Eq, a.clone(), Value::Int(0, size))
+        }
+        BinaryOp::IntOp(IntOp::GreaterEq, Signedness::Unsigned, _) if n == 0 => {
+            Instruction::Assign(r, Value::Int(1, Size::Bit8))
+        }
+        BinaryOp::IntOp(IntOp::Less, Signedness::Unsigned, _) if n == 0 => {
+            Instruction::Assign(r, Value::Int(0, Size::Bit8))
+        }
+        _ if swapped => Instruction::BinaryOp(r, op, a.clone(), b.clone()),
+        _ => return None,
+    };
+    Some(result)
+}
+
+pub const PASS: Pass = Pass {
+    name: "strength-reduction",
+    level: OptLevel::O1,
+    kind: PassKind::Function(|f, _| rewrite(f)),
+};
+
+pub fn rewrite(f: &mut Function) {
+    Reducer.rewrite_function(f);
+}
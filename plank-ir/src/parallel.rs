@@ -0,0 +1,53 @@
+//! Helpers for running independent work on multiple threads.
+
+use std::sync::Mutex;
+use std::thread;
+
+
+fn thread_count() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+/// Call `f` on every item, spreading items over available threads.
+/// Panics in `f` are propagated to the caller.
+pub fn for_each<T, F>(items: &mut [T], f: F)
+where
+    T: Send,
+    F: Fn(&mut T) + Sync,
+{
+    let threads = thread_count().min(items.len());
+    if threads <= 1 {
+        items.iter_mut().for_each(f);
+        return;
+    }
+    // items can take very different amounts of time, so
+    // let each thread pick the next one when it is done
+    let queue = Mutex::new(items.iter_mut());
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let item = queue.lock().unwrap().next();
+                match item {
+                    Some(item) => f(item),
+                    None => break,
+                }
+            });
+        }
+    });
+}
+
+/// Apply `f` to every item on available threads. Results are
+/// returned in the same order as the items.
+pub fn map<T, R, F>(items: &[T], f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let mut results = items.iter().map(|item| (item, None)).collect::<Vec<_>>();
+    for_each(&mut results, |&mut (item, ref mut result)| *result = Some(f(item)));
+    results
+        .into_iter()
+        .map(|(_, result)| result.expect("item was not processed"))
+        .collect()
+}
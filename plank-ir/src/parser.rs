@@ -0,0 +1,677 @@
+//! Parser for the textual IR format, which is produced by `emit_program`.
+//! Lines that start with `//` are comments, and instructions can end
+//! with the span of source code that they were compiled from.
+
+use std::collections::HashMap;
+use std::fmt;
+use plank_errors::position::{Position, Span};
+use ir::{BinaryOp, BitOp, Block, BlockEnd, BlockId, Function, Inline, Instruction, IntOp,
+         Layout, Op, Overflow, Program, Reg, Signedness, Size, Symbol, UnaryOp, Value};
+use target::{self, CallingConvention, Target};
+
+
+#[derive(Debug)]
+pub struct ParseError {
+    /// Line of the error, counting from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+type Result<T> = ::std::result::Result<T, String>;
+
+enum Item {
+    Instruction(Instruction),
+    End(BlockEnd),
+}
+
+struct Line<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Line<'a> {
+    fn skip_whitespace(&mut self) {
+        self.rest = self.rest.trim_start();
+    }
+
+    fn starts_with(&mut self, s: &str) -> bool {
+        self.skip_whitespace();
+        self.rest.starts_with(s)
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        if self.starts_with(s) {
+            self.rest = &self.rest[s.len()..];
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, s: &str) -> Result<()> {
+        if self.eat(s) {
+            Ok(())
+        } else {
+            Err(format!("expected `{}`, found `{}`", s, self.rest))
+        }
+    }
+
+    fn expect_end(&mut self) -> Result<()> {
+        self.skip_whitespace();
+        if self.rest.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("unexpected `{}`", self.rest))
+        }
+    }
+
+    fn word(&mut self) -> &'a str {
+        self.skip_whitespace();
+        let end = self.rest
+            .find(|c: char| !c.is_alphanumeric() && c != '_')
+            .unwrap_or(self.rest.len());
+        let word = &self.rest[..end];
+        self.rest = &self.rest[end..];
+        word
+    }
+
+    /// Remove span from the end of line, like `@ 3:5-3:14`, and return it.
+    fn span(&mut self) -> Result<Option<Span>> {
+        let at = match self.rest.rfind(" @ ") {
+            Some(at) => at,
+            None => return Ok(None),
+        };
+        // strings can contain ` @ ` too, but they can't be followed by a span
+        let (start, end) = match parse_span(self.rest[at + 3..].trim()) {
+            Some(span) => span,
+            None => return Ok(None),
+        };
+        if start > end {
+            return Err("span ends before it starts".into());
+        }
+        self.rest = &self.rest[..at];
+        Ok(Some(Span::new(start, end)))
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let rest = self.rest;
+        if self.word() == keyword {
+            true
+        } else {
+            self.rest = rest;
+            false
+        }
+    }
+
+    fn number(&mut self) -> Result<u64> {
+        self.skip_whitespace();
+        let end = self.rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(self.rest.len());
+        let number = self.rest[..end]
+            .parse()
+            .map_err(|_| format!("expected a number, found `{}`", self.rest))?;
+        self.rest = &self.rest[end..];
+        Ok(number)
+    }
+
+    fn small_number(&mut self) -> Result<u32> {
+        let number = self.number()?;
+        if number > u64::from(u32::MAX) {
+            Err(format!("number `{}` is too big", number))
+        } else {
+            Ok(number as u32)
+        }
+    }
+
+    fn reg(&mut self) -> Result<Reg> {
+        self.expect("%")?;
+        Ok(Reg(self.small_number()?))
+    }
+
+    fn label(&mut self) -> Result<BlockId> {
+        self.expect("label_")?;
+        Ok(BlockId(self.small_number()?))
+    }
+
+    fn offset(&mut self) -> Result<u32> {
+        self.expect("[")?;
+        let offset = self.small_number()?;
+        self.expect("]")?;
+        Ok(offset)
+    }
+
+    fn symbol(&mut self) -> Result<Symbol> {
+        self.skip_whitespace();
+        // symbols of generic functions contain types, like
+        // `fn_apply::<fn(i32,i32)->i32>`, so only stop on
+        // parens and commas that are not nested
+        let mut depth = 0;
+        let mut previous = ' ';
+        let mut end = self.rest.len();
+        for (index, ch) in self.rest.char_indices() {
+            match ch {
+                '(' | ')' | ',' if depth == 0 => {
+                    end = index;
+                    break;
+                }
+                ch if ch.is_whitespace() => {
+                    end = index;
+                    break;
+                }
+                '<' | '(' => depth += 1,
+                '>' if previous != '-' => depth -= 1,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            previous = ch;
+        }
+        if end == 0 {
+            return Err(format!("expected a symbol, found `{}`", self.rest));
+        }
+        let symbol = Symbol(self.rest[..end].into());
+        self.rest = &self.rest[end..];
+        Ok(symbol)
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>> {
+        self.expect("\"")?;
+        let mut bytes = Vec::new();
+        let mut chars = self.rest.char_indices();
+        while let Some((index, ch)) = chars.next() {
+            match ch {
+                '"' => {
+                    self.rest = &self.rest[index + 1..];
+                    return Ok(bytes);
+                }
+                '\\' => match chars.next() {
+                    Some((_, 'x')) => {
+                        let mut byte = 0;
+                        for _ in 0..2 {
+                            let digit = chars
+                                .next()
+                                .and_then(|(_, ch)| ch.to_digit(16))
+                                .ok_or_else(|| "invalid `\\x` escape".to_string())?;
+                            byte = byte * 16 + digit as u8;
+                        }
+                        bytes.push(byte);
+                    }
+                    Some((_, ch @ '"')) | Some((_, ch @ '\\')) => bytes.push(ch as u8),
+                    _ => return Err("invalid escape in string".into()),
+                },
+                _ => {
+                    let mut buffer = [0; 4];
+                    bytes.extend(ch.encode_utf8(&mut buffer).bytes());
+                }
+            }
+        }
+        Err("unterminated string".into())
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        if self.starts_with("%") {
+            Ok(Value::Reg(self.reg()?))
+        } else if self.starts_with("\"") {
+            Ok(Value::Bytes(self.bytes()?))
+        } else if self.rest.starts_with(|c: char| c.is_ascii_digit()) {
+            let value = self.number()?;
+            self.expect("_b")?;
+            let size = match self.number()? {
+                8 => Size::Bit8,
+                16 => Size::Bit16,
+                32 => Size::Bit32,
+                64 => Size::Bit64,
+                size => return Err(format!("invalid int size `{}`", size)),
+            };
+            if value > size.truncate(u64::MAX) {
+                return Err(format!("value `{}` does not fit in {} bits", value, size.in_bytes() * 8));
+            }
+            Ok(Value::Int(value, size))
+        } else {
+            let symbol = self.symbol()?;
+            if &*symbol.0 == "undef" {
+                Ok(Value::Undef)
+            } else {
+                Ok(Value::Symbol(symbol))
+            }
+        }
+    }
+
+    fn values(&mut self) -> Result<Vec<Value>> {
+        self.expect("(")?;
+        let mut values = Vec::new();
+        if self.eat(")") {
+            return Ok(values);
+        }
+        loop {
+            values.push(self.value()?);
+            if self.eat(")") {
+                return Ok(values);
+            }
+            self.expect(",")?;
+        }
+    }
+
+    /// Parse `(value + offset)`.
+    fn address(&mut self) -> Result<(Value, u32)> {
+        self.expect("(")?;
+        let value = self.value()?;
+        self.expect("+")?;
+        let offset = self.small_number()?;
+        self.expect(")")?;
+        Ok((value, offset))
+    }
+
+    fn layout(&mut self) -> Result<Layout> {
+        self.expect("(")?;
+        self.expect("size")?;
+        let size = self.small_number()?;
+        self.expect(",")?;
+        self.expect("align")?;
+        let align = self.small_number()?;
+        self.expect(",")?;
+        let atomic = match self.word() {
+            "atomic" => true,
+            "composite" => false,
+            word => return Err(format!("expected `atomic` or `composite`, found `{}`", word)),
+        };
+        self.expect(")")?;
+        Ok(Layout {
+            size,
+            align,
+            atomic,
+        })
+    }
+
+    fn item(&mut self) -> Result<Item> {
+        if self.starts_with("%") {
+            let reg = self.reg()?;
+            if self.starts_with("[") {
+                let offset = self.offset()?;
+                self.expect("=")?;
+                let value = self.value()?;
+                return Ok(Item::Instruction(Instruction::Store(reg, offset, value)));
+            }
+            self.expect("=")?;
+            return self.assignment(reg).map(Item::Instruction);
+        }
+        let instruction = match self.word() {
+            "nop" => Instruction::Nop,
+            // as a last instruction of a block, this is
+            // turned into a block end by the parser
+            "unreachable" => Instruction::Unreachable,
+            "init" => Instruction::Init(self.reg()?),
+            "drop" => Instruction::Drop(self.reg()?),
+            "callproc" => {
+                let symbol = self.symbol()?;
+                Instruction::CallProc(symbol, self.values()?)
+            }
+            "callprocvirt" => {
+                let value = self.value()?;
+                Instruction::CallProcVirt(value, self.values()?)
+            }
+            "store" => {
+                let (address, offset) = self.address()?;
+                Instruction::DerefStore(address, offset, self.value()?)
+            }
+            "goto" => return Ok(Item::End(BlockEnd::Jump(self.label()?))),
+            "branch" => {
+                let value = self.value()?;
+                let a = self.label()?;
+                let b = self.label()?;
+                return Ok(Item::End(BlockEnd::Branch(value, a, b)));
+            }
+            "return" => {
+                self.skip_whitespace();
+                return Ok(Item::End(if self.rest.is_empty() {
+                    BlockEnd::ReturnProc
+                } else {
+                    BlockEnd::Return(self.value()?)
+                }));
+            }
+            word => return Err(format!("unknown instruction `{}`", word)),
+        };
+        Ok(Item::Instruction(instruction))
+    }
+
+    fn assignment(&mut self, dest: Reg) -> Result<Instruction> {
+        let rest = self.rest;
+        let word = self.word();
+        Ok(match word {
+            "cast" => Instruction::CastAssign(dest, self.value()?),
+            "call" => {
+                let symbol = self.symbol()?;
+                Instruction::Call(dest, symbol, self.values()?)
+            }
+            "callvirt" => {
+                let value = self.value()?;
+                Instruction::CallVirt(dest, value, self.values()?)
+            }
+            "deref" => {
+                let (address, offset) = self.address()?;
+                Instruction::DerefLoad(dest, address, offset)
+            }
+            "address" => {
+                let reg = self.reg()?;
+                Instruction::TakeAddress(dest, reg, self.offset()?)
+            }
+            _ => if let Some(op) = parse_binary_op(word) {
+                let a = self.value()?;
+                Instruction::BinaryOp(dest, op, a, self.value()?)
+            } else if let Some(op) = parse_unary_op(word) {
+                Instruction::UnaryOp(dest, op, self.value()?)
+            } else {
+                self.rest = rest;
+                match self.value()? {
+                    Value::Reg(reg) if self.starts_with("[") => {
+                        Instruction::Load(dest, reg, self.offset()?)
+                    }
+                    value => Instruction::Assign(dest, value),
+                }
+            },
+        })
+    }
+}
+
+/// Parse span like `3:5-3:14` into its start and end.
+fn parse_span(s: &str) -> Option<(Position, Position)> {
+    fn position(s: &str) -> Option<Position> {
+        let mut parts = s.splitn(2, ':');
+        let line = parts.next()?.parse::<u32>().ok()?;
+        let column = parts.next()?.parse::<u32>().ok()?;
+        if line == 0 || column == 0 {
+            return None;
+        }
+        Some(Position::new(line - 1, column - 1))
+    }
+    let mut parts = s.splitn(2, '-');
+    let start = position(parts.next()?)?;
+    let end = position(parts.next()?)?;
+    Some((start, end))
+}
+
+/// Parse size like `32`.
+fn parse_size(s: &str) -> Option<Size> {
+    match s {
+        "8" => Some(Size::Bit8),
+        "16" => Some(Size::Bit16),
+        "32" => Some(Size::Bit32),
+        "64" => Some(Size::Bit64),
+        _ => None,
+    }
+}
+
+/// Parse sign and size like `i32`.
+fn parse_int_type(s: &str) -> Option<(Signedness, Size)> {
+    let sign = match s.chars().next() {
+        Some('i') => Signedness::Signed,
+        Some('u') => Signedness::Unsigned,
+        _ => return None,
+    };
+    parse_size(&s[1..]).map(|size| (sign, size))
+}
+
+fn parse_binary_op(s: &str) -> Option<BinaryOp> {
+    match s {
+        "eq" => return Some(BinaryOp::Eq),
+        "neq" => return Some(BinaryOp::Neq),
+        _ => {}
+    }
+    let split = s.find('_')?;
+    let (name, typ) = (&s[..split], &s[split + 1..]);
+    let bit_op = match name {
+        "and" => Some(BitOp::And),
+        "or" => Some(BitOp::Or),
+        "xor" => Some(BitOp::Xor),
+        "shl" => Some(BitOp::Shl),
+        "shr" => Some(BitOp::Shr),
+        _ => None,
+    };
+    if let Some(op) = bit_op {
+        return parse_size(typ).map(|size| BinaryOp::BitOp(op, size));
+    }
+    let op = match name {
+        "add" => IntOp::Add,
+        "sub" => IntOp::Sub,
+        "mul" => IntOp::Mul,
+        "div" => IntOp::Div,
+        "mod" => IntOp::Mod,
+        "le" => IntOp::Less,
+        "leq" => IntOp::LessEq,
+        "gt" => IntOp::Greater,
+        "geq" => IntOp::GreaterEq,
+        _ => return None,
+    };
+    let checked = matches!(op, IntOp::Add | IntOp::Sub | IntOp::Mul | IntOp::Div);
+    if let Some(typ) = typ.strip_suffix("_trap").filter(|_| checked) {
+        return parse_int_type(typ)
+            .map(|(sign, size)| BinaryOp::Checked(op, Overflow::Trap, sign, size));
+    }
+    if let Some(typ) = typ.strip_suffix("_sat").filter(|_| checked) {
+        return parse_int_type(typ)
+            .map(|(sign, size)| BinaryOp::Checked(op, Overflow::Saturate, sign, size));
+    }
+    parse_int_type(typ).map(|(sign, size)| BinaryOp::IntOp(op, sign, size))
+}
+
+fn parse_unary_op(s: &str) -> Option<UnaryOp> {
+    let mut parts = s.split('_');
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some("neg"), Some(typ), None, None) => {
+            parse_int_type(typ).map(|(sign, size)| UnaryOp::Negate(sign, size))
+        }
+        (Some("extend"), Some(from), Some(to), None) => {
+            let (sign, from) = parse_int_type(from)?;
+            let (to_sign, to) = parse_int_type(to)?;
+            if sign == to_sign {
+                Some(UnaryOp::Extend(sign, from, to))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+type SpannedEnd = (BlockEnd, Option<Span>);
+
+struct Parser {
+    functions: HashMap<Symbol, Function>,
+    /// Function that is being parsed.
+    function: Option<(Symbol, Function)>,
+    /// Block that is being parsed, with its end if it was already seen.
+    block: Option<(BlockId, Vec<Op>, Option<SpannedEnd>)>,
+    /// Whether the previous line was `start:`.
+    in_start: bool,
+    target: Target,
+}
+
+impl Parser {
+    fn parse_line(&mut self, line: &str) -> Result<()> {
+        let mut line = Line { rest: line.trim() };
+        if line.rest.is_empty() || line.rest.starts_with("//") {
+            return Ok(());
+        }
+        if self.in_start {
+            self.in_start = false;
+            if !line.keyword("goto") {
+                return Err("expected `goto` after `start:`".into());
+            }
+            let start = line.label()?;
+            self.current_function()?.start_block = Some(start);
+        } else if line.keyword("pointer_size") {
+            if self.function.is_some() || !self.functions.is_empty() {
+                return Err("`pointer_size` must come before functions".into());
+            }
+            self.target = match line.number()? {
+                32 => target::X86,
+                64 => target::X86_64,
+                size => return Err(format!("invalid pointer size `{}`", size)),
+            };
+        } else if line.keyword("function") {
+            self.finish_function()?;
+            let name = line.symbol()?;
+            let mut parameters = Vec::new();
+            line.expect("(")?;
+            if !line.eat(")") {
+                loop {
+                    parameters.push(line.reg()?);
+                    if line.eat(")") {
+                        break;
+                    }
+                    line.expect(",")?;
+                }
+            }
+            let output_layout = if line.eat(":") {
+                Some(line.layout()?)
+            } else {
+                None
+            };
+            let inline = if !line.keyword("inline") {
+                Inline::Auto
+            } else if line.eat("(") {
+                if !line.keyword("never") {
+                    return Err(format!("expected `never`, found `{}`", line.rest));
+                }
+                line.expect(")")?;
+                Inline::Never
+            } else {
+                Inline::Always
+            };
+            let calling_convention = if line.keyword("extern") {
+                let name = String::from_utf8(line.bytes()?)
+                    .map_err(|_| String::from("calling convention is not valid utf-8"))?;
+                CallingConvention::from_name(&name)
+                    .ok_or_else(|| format!("unknown calling convention `{}`", name))?
+            } else {
+                CallingConvention::Stack
+            };
+            let function = Function {
+                parameters,
+                output_layout,
+                registers: HashMap::new(),
+                blocks: HashMap::new(),
+                start_block: None,
+                inline,
+                calling_convention,
+                debug: None,
+            };
+            self.function = Some((name, function));
+        } else if line.keyword("register") {
+            let reg = line.reg()?;
+            line.expect(":")?;
+            let layout = line.layout()?;
+            if self.current_function()?.registers.insert(reg, layout).is_some() {
+                return Err(format!("register `%{}` is declared twice", reg.0));
+            }
+        } else if line.keyword("start") {
+            line.expect(":")?;
+            self.finish_block()?;
+            self.current_function()?;
+            self.in_start = true;
+        } else if line.starts_with("label_") {
+            let id = line.label()?;
+            line.expect(":")?;
+            self.finish_block()?;
+            self.current_function()?;
+            self.block = Some((id, Vec::new(), None));
+        } else {
+            let span = line.span()?;
+            let item = line.item()?;
+            let (_, ref mut ops, ref mut end) = *self.block
+                .as_mut()
+                .ok_or_else(|| "instruction outside of a block".to_string())?;
+            if end.is_some() {
+                return Err("instruction after the end of block".into());
+            }
+            match item {
+                Item::Instruction(i) => ops.push(Op::new(i, span)),
+                Item::End(e) => *end = Some((e, span)),
+            }
+        }
+        line.expect_end()
+    }
+
+    fn current_function(&mut self) -> Result<&mut Function> {
+        match self.function {
+            Some((_, ref mut function)) => Ok(function),
+            None => Err("expected a function declaration".into()),
+        }
+    }
+
+    fn finish_block(&mut self) -> Result<()> {
+        let (id, mut ops, end) = match self.block.take() {
+            Some(block) => block,
+            None => return Ok(()),
+        };
+        let (end, end_span) = match end {
+            Some(end) => end,
+            None => match ops.pop() {
+                Some(Op { instruction: Instruction::Unreachable, span }) => {
+                    (BlockEnd::Unreachable, span)
+                }
+                _ => return Err(format!("block `label_{}` does not have an end", id.0)),
+            },
+        };
+        let block = Block { ops, end, end_span };
+        if self.current_function()?.blocks.insert(id, block).is_some() {
+            return Err(format!("block `label_{}` is defined twice", id.0));
+        }
+        Ok(())
+    }
+
+    fn finish_function(&mut self) -> Result<()> {
+        self.finish_block()?;
+        if self.in_start {
+            return Err("expected `goto` after `start:`".into());
+        }
+        if let Some((name, function)) = self.function.take() {
+            if !function.blocks.is_empty() && function.start_block.is_none() {
+                return Err(format!("function `{}` has blocks but no `start:`", name.0));
+            }
+            let message = format!("function `{}` is defined twice", name.0);
+            if self.functions.insert(name, function).is_some() {
+                return Err(message);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parse a program in the format of `emit_program`.
+pub fn parse_program(source: &str) -> ::std::result::Result<Program, ParseError> {
+    let mut parser = Parser {
+        functions: HashMap::new(),
+        function: None,
+        block: None,
+        in_start: false,
+        target: Target::default(),
+    };
+    let mut line_count = 0;
+    for (index, line) in source.lines().enumerate() {
+        line_count = index + 1;
+        parser.parse_line(line).map_err(|message| {
+            ParseError {
+                line: line_count,
+                message,
+            }
+        })?;
+    }
+    parser.finish_function().map_err(|message| {
+        ParseError {
+            line: line_count,
+            message,
+        }
+    })?;
+    Ok(Program {
+        functions: parser.functions,
+        target: parser.target,
+        debug_types: HashMap::new(),
+        files: Vec::new(),
+    })
+}
@@ -1,8 +1,21 @@
 use std::io::{self, Write};
+use plank_errors::position::Span;
 use ir;
+use target::{CallingConvention, Target};
 
 pub fn emit_program<W: Write>(program: &ir::Program, mut out: W) -> io::Result<()> {
-    for (name, func) in &program.functions {
+    // sort everything so that output is stable and can be compared
+    let mut functions = program.functions.iter().collect::<Vec<_>>();
+    functions.sort_by(|&(a, _), &(b, _)| a.0.cmp(&b.0));
+    // only the pointer size of the target is written, and
+    // nothing at all for the default target
+    if program.target != Target::default() {
+        write!(out, "pointer_size ")?;
+        emit_size(program.target.pointer_size, &mut out)?;
+        writeln!(out)?;
+        writeln!(out)?;
+    }
+    for (name, func) in functions {
         write!(out, "function {}", name.0)?;
         emit_function(func, &mut out)?;
         writeln!(out)?;
@@ -25,8 +38,18 @@ fn emit_function<W: Write>(func: &ir::Function, out: &mut W) -> io::Result<()> {
         write!(out, ": ")?;
         emit_layout(layout, out)?;
     }
+    match func.inline {
+        ir::Inline::Auto => {}
+        ir::Inline::Always => write!(out, " inline")?,
+        ir::Inline::Never => write!(out, " inline(never)")?,
+    }
+    if func.calling_convention != CallingConvention::Stack {
+        write!(out, " extern \"{}\"", func.calling_convention.name())?;
+    }
     writeln!(out)?;
-    for (&reg, &layout) in &func.registers {
+    let mut registers = func.registers.iter().collect::<Vec<_>>();
+    registers.sort_by_key(|&(&reg, _)| reg);
+    for (&reg, &layout) in registers {
         write!(
             out,
             "    register %{}: ",
@@ -40,47 +63,71 @@ fn emit_function<W: Write>(func: &ir::Function, out: &mut W) -> io::Result<()> {
     }
     writeln!(out, "start:")?;
     writeln!(out, "    goto label_{}", func.start_block.unwrap().0)?;
-    for (id, block) in &func.blocks {
+    let mut blocks = func.blocks.iter().collect::<Vec<_>>();
+    blocks.sort_by_key(|&(&id, _)| id);
+    for (id, block) in blocks {
         writeln!(out, "label_{}:", id.0)?;
-        for op in &block.ops {
-            emit_instruction(op, out)?;
-        }
-        match block.end {
-            ir::BlockEnd::Branch(ref val, a, b) => {
-                write!(out, "    branch ")?;
-                emit_value(val, out)?;
-                writeln!(out, " label_{} label_{}", a.0, b.0)?;
-            }
-            ir::BlockEnd::Jump(id) => {
-                writeln!(out, "    goto label_{}", id.0)?;
-            }
-            ir::BlockEnd::Return(ref val) => {
-                write!(out, "    return ")?;
-                emit_value(val, out)?;
-                writeln!(out)?;
-            }
-            ir::BlockEnd::ReturnProc => {
-                writeln!(out, "    return")?;
-            }
-            ir::BlockEnd::Unreachable => {
-                writeln!(out, "    unreachable")?;
-            }
-        }
+        emit_block(block, out)?;
     }
     Ok(())
 }
 
+/// Write instructions and end of a block, one per line. Each line
+/// ends with the span of source code it was compiled from, if known.
+pub(crate) fn emit_block<W: Write>(block: &ir::Block, out: &mut W) -> io::Result<()> {
+    for op in &block.ops {
+        emit_instruction(op, out)?;
+        emit_span(op.span, out)?;
+    }
+    match block.end {
+        ir::BlockEnd::Branch(ref val, a, b) => {
+            write!(out, "    branch ")?;
+            emit_value(val, out)?;
+            write!(out, " label_{} label_{}", a.0, b.0)?;
+        }
+        ir::BlockEnd::Jump(id) => {
+            write!(out, "    goto label_{}", id.0)?;
+        }
+        ir::BlockEnd::Return(ref val) => {
+            write!(out, "    return ")?;
+            emit_value(val, out)?;
+        }
+        ir::BlockEnd::ReturnProc => {
+            write!(out, "    return")?;
+        }
+        ir::BlockEnd::Unreachable => {
+            write!(out, "    unreachable")?;
+        }
+    }
+    emit_span(block.end_span, out)
+}
+
+/// Write span like ` @ 3:5-3:14`, with lines and columns counted from 1.
+fn emit_span<W: Write>(span: Option<Span>, out: &mut W) -> io::Result<()> {
+    if let Some(span) = span {
+        write!(
+            out,
+            " @ {}:{}-{}:{}",
+            span.start.line + 1,
+            span.start.column + 1,
+            span.end.line + 1,
+            span.end.column + 1,
+        )?;
+    }
+    writeln!(out)
+}
+
 fn emit_instruction<W: Write>(i: &ir::Instruction, out: &mut W) -> io::Result<()> {
     match *i {
         ir::Instruction::Assign(reg, ref val) => {
             write!(out, "    %{} = ", reg.0)?;
             emit_value(val, out)?;
-            writeln!(out)
+            Ok(())
         }
         ir::Instruction::CastAssign(reg, ref val) => {
             write!(out, "    %{} = cast ", reg.0)?;
             emit_value(val, out)?;
-            writeln!(out)
+            Ok(())
         }
         ir::Instruction::BinaryOp(dest, op, ref a, ref b) => {
             write!(out, "    %{} = ", dest.0)?;
@@ -89,59 +136,59 @@ fn emit_instruction<W: Write>(i: &ir::Instruction, out: &mut W) -> io::Result<()
             emit_value(a, out)?;
             write!(out, " ")?;
             emit_value(b, out)?;
-            writeln!(out)
+            Ok(())
         }
         ir::Instruction::Call(dest, ref sym, ref params) => {
             write!(out, "    %{} = call {}", dest.0, sym.0)?;
             emit_params(params, out)?;
-            writeln!(out)
+            Ok(())
         }
         ir::Instruction::CallProc(ref sym, ref params) => {
             write!(out, "    callproc {}", sym.0)?;
             emit_params(params, out)?;
-            writeln!(out)
+            Ok(())
         }
         ir::Instruction::CallVirt(dest, ref val, ref params) => {
             write!(out, "    %{} = callvirt ", dest.0)?;
             emit_value(val, out)?;
             emit_params(params, out)?;
-            writeln!(out)
+            Ok(())
         }
         ir::Instruction::CallProcVirt(ref val, ref params) => {
             write!(out, "    callprocvirt ")?;
             emit_value(val, out)?;
             emit_params(params, out)?;
-            writeln!(out)
+            Ok(())
         }
         ir::Instruction::DerefLoad(dest, ref value, offset) => {
             write!(out, "    %{} = deref (", dest.0)?;
             emit_value(value, out)?;
-            writeln!(out, " + {})", offset)
+            write!(out, " + {})", offset)
         }
         ir::Instruction::DerefStore(ref address, offset, ref value) => {
             write!(out, "    store (")?;
             emit_value(address, out)?;
             write!(out, " + {}) ", offset)?;
             emit_value(value, out)?;
-            writeln!(out)
+            Ok(())
         }
-        ir::Instruction::Drop(reg) => writeln!(out, "    drop %{}", reg.0),
+        ir::Instruction::Drop(reg) => write!(out, "    drop %{}", reg.0),
         ir::Instruction::Init(reg) => {
-            writeln!(out, "    init %{}", reg.0)
+            write!(out, "    init %{}", reg.0)
         }
         ir::Instruction::Load(dest, reg, offset) => {
-            writeln!(out, "    %{} = %{}[{}]", dest.0, reg.0, offset)
+            write!(out, "    %{} = %{}[{}]", dest.0, reg.0, offset)
         }
         ir::Instruction::Nop => {
-            writeln!(out, "    nop")
+            write!(out, "    nop")
         }
         ir::Instruction::Store(dest, offset, ref value) => {
             write!(out, "    %{}[{}] = ", dest.0, offset)?;
             emit_value(value, out)?;
-            writeln!(out)
+            Ok(())
         }
         ir::Instruction::TakeAddress(dest, of, offset) => {
-            writeln!(out, "    %{} = address %{}[{}]", dest.0, of.0, offset)
+            write!(out, "    %{} = address %{}[{}]", dest.0, of.0, offset)
         }
         ir::Instruction::UnaryOp(dest, ir::UnaryOp::Negate(sign, size), ref arg) => {
             write!(out, "    %{} = neg_", dest.0)?;
@@ -149,10 +196,21 @@ fn emit_instruction<W: Write>(i: &ir::Instruction, out: &mut W) -> io::Result<()
             emit_size(size, out)?;
             write!(out, " ")?;
             emit_value(arg, out)?;
-            writeln!(out)
+            Ok(())
+        }
+        ir::Instruction::UnaryOp(dest, ir::UnaryOp::Extend(sign, from, to), ref arg) => {
+            write!(out, "    %{} = extend_", dest.0)?;
+            emit_sign(sign, out)?;
+            emit_size(from, out)?;
+            write!(out, "_")?;
+            emit_sign(sign, out)?;
+            emit_size(to, out)?;
+            write!(out, " ")?;
+            emit_value(arg, out)?;
+            Ok(())
         }
         ir::Instruction::Unreachable => {
-            writeln!(out, "    unreachable")
+            write!(out, "    unreachable")
         }
     }
 }
@@ -164,8 +222,8 @@ fn emit_value<W: Write>(val: &ir::Value, out: &mut W) -> io::Result<()> {
             for &byte in bytes {
                 if byte < 32 || byte > 126 {
                     write!(out, "\\x{:0>2x}", byte)?;
-                } else if byte == b'"' {
-                    write!(out, "\\\"")?;
+                } else if byte == b'"' || byte == b'\\' {
+                    write!(out, "\\{}", byte as char)?;
                 } else {
                     write!(out, "{}", byte as char)?;
                 }
@@ -176,6 +234,7 @@ fn emit_value<W: Write>(val: &ir::Value, out: &mut W) -> io::Result<()> {
             ir::Size::Bit8 => write!(out, "{}_b8", value),
             ir::Size::Bit16 => write!(out, "{}_b16", value),
             ir::Size::Bit32 => write!(out, "{}_b32", value),
+            ir::Size::Bit64 => write!(out, "{}_b64", value),
         },
         ir::Value::Reg(reg) => write!(out, "%{}", reg.0),
         ir::Value::Symbol(ref sym) => write!(out, "{}", sym.0),
@@ -197,6 +256,14 @@ fn emit_binop<W: Write>(op: ir::BinaryOp, out: &mut W) -> io::Result<()> {
             write!(out, "xor_")?;
             emit_size(s, out)
         }
+        ir::BinaryOp::BitOp(ir::BitOp::Shl, s) => {
+            write!(out, "shl_")?;
+            emit_size(s, out)
+        }
+        ir::BinaryOp::BitOp(ir::BitOp::Shr, s) => {
+            write!(out, "shr_")?;
+            emit_size(s, out)
+        }
         ir::BinaryOp::Eq => write!(out, "eq"),
         ir::BinaryOp::Neq => write!(out, "neq"),
         ir::BinaryOp::IntOp(ir::IntOp::Add, sign, size) => {
@@ -244,6 +311,13 @@ fn emit_binop<W: Write>(op: ir::BinaryOp, out: &mut W) -> io::Result<()> {
             emit_sign(sign, out)?;
             emit_size(size, out)
         }
+        ir::BinaryOp::Checked(op, overflow, sign, size) => {
+            emit_binop(ir::BinaryOp::IntOp(op, sign, size), out)?;
+            match overflow {
+                ir::Overflow::Trap => write!(out, "_trap"),
+                ir::Overflow::Saturate => write!(out, "_sat"),
+            }
+        }
     }
 }
 
@@ -259,6 +333,7 @@ fn emit_size<W: Write>(size: ir::Size, out: &mut W) -> io::Result<()> {
         ir::Size::Bit8 => write!(out, "8"),
         ir::Size::Bit16 => write!(out, "16"),
         ir::Size::Bit32 => write!(out, "32"),
+        ir::Size::Bit64 => write!(out, "64"),
     }
 }
 
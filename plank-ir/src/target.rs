@@ -0,0 +1,119 @@
+use ir::Size;
+
+
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum Arch {
+    X86,
+    X86_64,
+}
+
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+/// How functions receive their arguments and return results.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum CallingConvention {
+    /// Arguments are passed on the stack, each one taking up a whole
+    /// number of words. Results are returned in the accumulator, or
+    /// written through a hidden pointer if they don't fit there.
+    Stack,
+    /// Convention used by C compilers for the target: cdecl on x86,
+    /// and System V on x86-64, where the first six arguments are passed
+    /// in registers. Only word sized or smaller scalars can be passed.
+    C,
+    /// Like `C`, except that the callee removes arguments from the
+    /// stack. Same as `C` on x86-64.
+    Stdcall,
+}
+
+impl CallingConvention {
+    /// Name used in `extern "name"` annotations.
+    pub fn name(self) -> &'static str {
+        match self {
+            CallingConvention::Stack => "plank",
+            CallingConvention::C => "C",
+            CallingConvention::Stdcall => "stdcall",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<CallingConvention> {
+        match name {
+            "plank" => Some(CallingConvention::Stack),
+            "C" => Some(CallingConvention::C),
+            "stdcall" => Some(CallingConvention::Stdcall),
+            _ => None,
+        }
+    }
+}
+
+/// Describes the machine that a program is compiled for. Frontend uses
+/// it to lay out types, and backends to decide what code to generate.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub struct Target {
+    pub arch: Arch,
+    /// Size of pointers, and also of functions without their environment.
+    pub pointer_size: Size,
+    pub endianness: Endianness,
+    /// Alignment of the widest scalar. Smaller values are aligned
+    /// to their size, and wider ones only to this.
+    pub max_align: u32,
+    /// Convention of functions defined in the program.
+    pub calling_convention: CallingConvention,
+}
+
+pub const X86: Target = Target {
+    arch: Arch::X86,
+    pointer_size: Size::Bit32,
+    endianness: Endianness::Little,
+    max_align: 4,
+    calling_convention: CallingConvention::Stack,
+};
+
+pub const X86_64: Target = Target {
+    arch: Arch::X86_64,
+    pointer_size: Size::Bit64,
+    endianness: Endianness::Little,
+    max_align: 8,
+    calling_convention: CallingConvention::Stack,
+};
+
+/// All supported targets, the first one is the default.
+pub const TARGETS: &[Target] = &[X86, X86_64];
+
+impl Target {
+    /// Name used to select the target on the command line.
+    pub fn name(&self) -> &'static str {
+        match self.arch {
+            Arch::X86 => "x86",
+            Arch::X86_64 => "x86_64",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Target> {
+        TARGETS.iter().cloned().find(|target| target.name() == name)
+    }
+
+    /// Target with given pointer size. Serialized IR only records the
+    /// pointer size, which currently is enough to tell targets apart.
+    pub fn with_pointer_size(size: Size) -> Option<Target> {
+        TARGETS.iter().cloned().find(|target| target.pointer_size == size)
+    }
+
+    /// Alignment of a scalar taking up `size` bytes.
+    pub fn scalar_align(&self, size: u32) -> u32 {
+        size.min(self.max_align).max(1)
+    }
+
+    pub fn pointer_align(&self) -> u32 {
+        self.scalar_align(self.pointer_size.in_bytes())
+    }
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        TARGETS[0]
+    }
+}
@@ -1,5 +1,7 @@
 use std::collections::{HashMap, HashSet};
+use std::fmt;
 use analysis::Loc;
+use debug;
 use ir::{BinaryOp, Block, BlockEnd, BlockId, Function, Instruction, IntOp, Program, Reg, Symbol,
          UnaryOp, Value};
 
@@ -18,12 +20,186 @@ pub enum Error {
     OutOfBounds(Loc),
     InvalidOpOnAtomic(Loc),
     InvalidReturn,
+    UnknownDebugType(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::BadLayout => write!(f, "register or return value has zero size"),
+            Error::UnknownFunction(ref sym) => write!(f, "unknown function `{}`", sym.0),
+            Error::UnknownRegister(reg) => write!(f, "undeclared register `%{}`", reg.0),
+            Error::UnknownBlock(block) => write!(f, "unknown block `label_{}`", block.0),
+            Error::NonLiveRegUsage(reg, loc) => {
+                write!(f, "register `%{}` might not be initialized {}", reg.0, DisplayLoc(loc))
+            }
+            Error::BadValueSize(loc) => write!(f, "value size mismatch {}", DisplayLoc(loc)),
+            Error::BadParamCount(loc) => {
+                write!(f, "wrong number of arguments {}", DisplayLoc(loc))
+            }
+            Error::BadCall(loc) => {
+                write!(f, "call does not match function return type {}", DisplayLoc(loc))
+            }
+            Error::ZeroSizedVal(loc) => write!(f, "zero sized value {}", DisplayLoc(loc)),
+            Error::OutOfBounds(loc) => {
+                write!(f, "out of bounds register access {}", DisplayLoc(loc))
+            }
+            Error::InvalidOpOnAtomic(loc) => {
+                write!(f, "field access on atomic register {}", DisplayLoc(loc))
+            }
+            Error::InvalidReturn => write!(f, "return does not match function return type"),
+            Error::UnknownDebugType(ref name) => write!(f, "unknown debug type `{}`", name),
+        }
+    }
+}
+
+struct DisplayLoc(Loc);
+
+impl fmt::Display for DisplayLoc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at `label_{}`, instruction {}", (self.0).block.0, (self.0).pos)
+    }
+}
+
+/// Registers and values that are used by an instruction.
+fn operands(i: &Instruction) -> (Vec<Reg>, Vec<&Value>) {
+    match *i {
+        Instruction::Unreachable |
+        Instruction::Nop => (Vec::new(), Vec::new()),
+        Instruction::Init(reg) |
+        Instruction::Drop(reg) => (vec![reg], Vec::new()),
+        Instruction::BinaryOp(reg, _, ref a, ref b) => (vec![reg], vec![a, b]),
+        Instruction::UnaryOp(reg, _, ref value) |
+        Instruction::Assign(reg, ref value) |
+        Instruction::CastAssign(reg, ref value) |
+        Instruction::DerefLoad(reg, ref value, _) |
+        Instruction::Store(reg, _, ref value) => (vec![reg], vec![value]),
+        Instruction::Call(reg, _, ref params) => (vec![reg], params.iter().collect()),
+        Instruction::CallProc(_, ref params) => (Vec::new(), params.iter().collect()),
+        Instruction::CallVirt(reg, ref address, ref params) => {
+            let mut values = vec![address];
+            values.extend(params);
+            (vec![reg], values)
+        }
+        Instruction::CallProcVirt(ref address, ref params) => {
+            let mut values = vec![address];
+            values.extend(params);
+            (Vec::new(), values)
+        }
+        Instruction::DerefStore(ref address, _, ref value) => (Vec::new(), vec![address, value]),
+        Instruction::Load(a, b, _) |
+        Instruction::TakeAddress(a, b, _) => (vec![a, b], Vec::new()),
+    }
+}
+
+/// Check that everything that a function refers to exists. Other
+/// checks (and liveness analysis) assume that this has passed.
+fn validate_structure(program: &Program, function: &Function) -> Result<(), Error> {
+    let check_reg = |reg: Reg| if function.registers.contains_key(&reg) {
+        Ok(())
+    } else {
+        Err(Error::UnknownRegister(reg))
+    };
+    let check_value = |value: &Value| match *value {
+        Value::Reg(reg) => check_reg(reg),
+        Value::Symbol(ref sym) if !program.functions.contains_key(sym) => {
+            Err(Error::UnknownFunction(sym.clone()))
+        }
+        _ => Ok(()),
+    };
+    let check_block = |block: BlockId| if function.blocks.contains_key(&block) {
+        Ok(())
+    } else {
+        Err(Error::UnknownBlock(block))
+    };
+    if let Some(layout) = function.output_layout {
+        if layout.size == 0 {
+            return Err(Error::BadLayout);
+        }
+    }
+    for &layout in function.registers.values() {
+        if layout.size == 0 {
+            return Err(Error::BadLayout);
+        }
+    }
+    for &reg in &function.parameters {
+        check_reg(reg)?;
+    }
+    if let Some(block) = function.start_block {
+        check_block(block)?;
+    }
+    for block in function.blocks.values() {
+        for op in &block.ops {
+            match op.instruction {
+                Instruction::Call(_, ref sym, _) |
+                Instruction::CallProc(ref sym, _) if !program.functions.contains_key(sym) => {
+                    return Err(Error::UnknownFunction(sym.clone()));
+                }
+                _ => {}
+            }
+            let (regs, values) = operands(op);
+            for reg in regs {
+                check_reg(reg)?;
+            }
+            for value in values {
+                check_value(value)?;
+            }
+        }
+        match block.end {
+            BlockEnd::Branch(ref value, a, b) => {
+                check_value(value)?;
+                check_block(a)?;
+                check_block(b)?;
+            }
+            BlockEnd::Jump(block) => check_block(block)?,
+            BlockEnd::Return(ref value) => check_value(value)?,
+            BlockEnd::ReturnProc |
+            BlockEnd::Unreachable => {}
+        }
+    }
+    // variables of debug info can refer to registers that were
+    // optimized out, so only their types are checked
+    if let Some(ref debug) = function.debug {
+        let mut checked = HashSet::new();
+        check_debug_type(program, &debug.return_type, &mut checked)?;
+        for variable in &debug.variables {
+            check_debug_type(program, &variable.typ, &mut checked)?;
+        }
+    }
+    Ok(())
+}
+
+/// Check that composite types used by `typ`, and by their fields,
+/// are described in the program.
+fn check_debug_type<'a>(
+    program: &'a Program,
+    typ: &'a debug::Type,
+    checked: &mut HashSet<&'a str>,
+) -> Result<(), Error> {
+    match *typ {
+        debug::Type::Unit | debug::Type::Bool | debug::Type::Int(_, _) => Ok(()),
+        debug::Type::Pointer(ref to) => check_debug_type(program, to, checked),
+        debug::Type::Composite(ref name) => {
+            if !checked.insert(name) {
+                return Ok(());
+            }
+            let composite = program
+                .debug_types
+                .get(name)
+                .ok_or_else(|| Error::UnknownDebugType(name.clone()))?;
+            for field in &composite.fields {
+                check_debug_type(program, &field.typ, checked)?;
+            }
+            Ok(())
+        }
+    }
 }
 
 struct Context<'a> {
     functions: &'a HashMap<Symbol, Function>,
     function: &'a Function,
     live_locations: HashMap<Reg, HashSet<Loc>>,
+    pointer_size: u32,
 }
 
 impl<'a> Context<'a> {
@@ -32,30 +208,11 @@ impl<'a> Context<'a> {
             functions: &program.functions,
             function,
             live_locations: ::analysis::liveness::live_locations(function),
+            pointer_size: program.target.pointer_size.in_bytes(),
         }
     }
 
     fn validate(&self) -> Result<(), Error> {
-        if let Some(layout) = self.function.output_layout {
-            if layout.size <= 0 {
-                return Err(Error::BadLayout);
-            }
-        }
-        for &layout in self.function.registers.values() {
-            if layout.size <= 0 {
-                return Err(Error::BadLayout);
-            }
-        }
-        for &reg in &self.function.parameters {
-            if !self.function.registers.contains_key(&reg) {
-                return Err(Error::UnknownRegister(reg));
-            }
-        }
-        if let Some(block) = self.function.start_block {
-            if !self.function.blocks.contains_key(&block) {
-                return Err(Error::UnknownBlock(block));
-            }
-        }
         for (&id, block) in &self.function.blocks {
             self.validate_block(id, block)?;
         }
@@ -69,21 +226,11 @@ impl<'a> Context<'a> {
         }
         let loc = Loc { block: id, pos: block.ops.len() };
         match block.end {
-            BlockEnd::Branch(ref val, a, b) => {
+            BlockEnd::Branch(ref val, _, _) => {
                 assert_equal(self.value_size(val), 1, loc)?;
                 self.assert_live_val(val, loc)?;
-                if !self.function.blocks.contains_key(&a) {
-                    return Err(Error::UnknownBlock(a));
-                }
-                if !self.function.blocks.contains_key(&b) {
-                    return Err(Error::UnknownBlock(b));
-                }
-            }
-            BlockEnd::Jump(block) => {
-                if !self.function.blocks.contains_key(&block) {
-                    return Err(Error::UnknownBlock(block));
-                }
             }
+            BlockEnd::Jump(_) => {}
             BlockEnd::Return(ref val) => {
                 self.assert_live_val(val, loc)?;
                 match self.function.output_layout {
@@ -123,7 +270,8 @@ impl<'a> Context<'a> {
                 assert_equal(self.value_size(b), size.in_bytes(), loc)?;
             }
             Instruction::BinaryOp(dest, BinaryOp::BitOp(_, size), ref a, ref b) |
-            Instruction::BinaryOp(dest, BinaryOp::IntOp(_, _, size), ref a, ref b) => {
+            Instruction::BinaryOp(dest, BinaryOp::IntOp(_, _, size), ref a, ref b) |
+            Instruction::BinaryOp(dest, BinaryOp::Checked(_, _, _, size), ref a, ref b) => {
                 self.assert_live_val(a, loc)?;
                 self.assert_live_val(b, loc)?;
                 assert_equal(self.register_size(dest), size.in_bytes(), loc)?;
@@ -152,8 +300,7 @@ impl<'a> Context<'a> {
                 }
             }
             Instruction::CallProc(ref sym, ref params) => {
-                let callee = self.functions.get(sym)
-                    .ok_or_else(|| Error::UnknownFunction(sym.clone()))?;
+                let callee = &self.functions[sym];
                 if callee.output_layout.is_some() {
                     return Err(Error::BadCall(loc));
                 }
@@ -166,29 +313,25 @@ impl<'a> Context<'a> {
             }
             Instruction::CallVirt(_, ref address, ref params) |
             Instruction::CallProcVirt(ref address, ref params) => {
-                assert_equal(self.value_size(address), ::ir::POINTER_SIZE, loc)?;
+                assert_equal(self.value_size(address), self.pointer_size, loc)?;
                 for val in params {
                     self.assert_live_val(val, loc)?;
                 }
             }
             Instruction::DerefLoad(_, ref val, _) => {
                 self.assert_live_val(val, loc)?;
-                assert_equal(self.value_size(val), ::ir::POINTER_SIZE, loc)?;
+                assert_equal(self.value_size(val), self.pointer_size, loc)?;
             }
             Instruction::DerefStore(ref address, _, ref value) => {
                 self.assert_live_val(address, loc)?;
                 self.assert_live_val(value, loc)?;
-                assert_equal(self.value_size(address), ::ir::POINTER_SIZE, loc)?;
+                assert_equal(self.value_size(address), self.pointer_size, loc)?;
                 if self.value_size(value) == 0 {
                     return Err(Error::ZeroSizedVal(loc));
                 }
             }
-            Instruction::Drop(reg) |
-            Instruction::Init(reg) => {
-                if !self.function.registers.contains_key(&reg) {
-                    return Err(Error::UnknownRegister(reg));
-                }
-            }
+            Instruction::Drop(_) |
+            Instruction::Init(_) => {}
             Instruction::Load(dest, reg, offset) => {
                 self.assert_live(reg, loc)?;
                 if self.function.registers[&reg].atomic {
@@ -215,7 +358,7 @@ impl<'a> Context<'a> {
             }
             Instruction::TakeAddress(dest, reg, offset) => {
                 self.assert_live(reg, loc)?;
-                assert_equal(self.register_size(dest), ::ir::POINTER_SIZE, loc)?;
+                assert_equal(self.register_size(dest), self.pointer_size, loc)?;
                 let reg_size = self.register_size(reg);
                 if offset >= reg_size {
                     return Err(Error::OutOfBounds(loc));
@@ -226,6 +369,11 @@ impl<'a> Context<'a> {
                 assert_equal(self.register_size(dest), size.in_bytes(), loc)?;
                 assert_equal(self.value_size(value), size.in_bytes(), loc)?;
             }
+            Instruction::UnaryOp(dest, UnaryOp::Extend(_, from, to), ref value) => {
+                self.assert_live_val(value, loc)?;
+                assert_equal(self.register_size(dest), to.in_bytes(), loc)?;
+                assert_equal(self.value_size(value), from.in_bytes(), loc)?;
+            }
             Instruction::Unreachable => {}
         }
         Ok(())
@@ -238,10 +386,10 @@ impl<'a> Context<'a> {
     fn value_size(&self, value: &Value) -> u32 {
         match *value {
             Value::Undef => 1,
-            Value::Bytes(_) => ::ir::POINTER_SIZE,
+            Value::Bytes(_) => self.pointer_size,
             Value::Int(_, size) => size.in_bytes(),
             Value::Reg(reg) => self.register_size(reg),
-            Value::Symbol(_) => ::ir::FUNCTION_SIZE,
+            Value::Symbol(_) => self.pointer_size,
         }
     }
 
@@ -281,6 +429,13 @@ fn assert_equal_counts(cnt1: usize, cnt2: usize, loc: Loc) -> Result<(), Error>
 }
 
 pub fn validate_ir(program: &Program) -> Result<(), (&::ir::Symbol, Error)> {
+    // calls look at parameters of called functions,
+    // so check structure of all of them first
+    for (sym, f) in &program.functions {
+        if let Err(e) = validate_structure(program, f) {
+            return Err((sym, e));
+        }
+    }
     for (sym, f) in &program.functions {
         let ctx = Context::new(program, f);
         if let Err(e) = ctx.validate() {
@@ -289,3 +444,13 @@ pub fn validate_ir(program: &Program) -> Result<(), (&::ir::Symbol, Error)> {
     }
     Ok(())
 }
+
+/// In debug builds, panic if ir is invalid, so that bugs in
+/// ir construction or optimizations are caught as soon as they happen.
+pub fn check_valid<F: FnOnce() -> String>(program: &Program, when: F) {
+    if cfg!(debug_assertions) {
+        if let Err((sym, err)) = validate_ir(program) {
+            panic!("invalid ir in `{}` {}: {}", sym.0, when(), err);
+        }
+    }
+}
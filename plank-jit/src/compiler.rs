@@ -0,0 +1,1142 @@
+use std::collections::{HashMap, HashSet};
+use cranelift_codegen::entity::EntityRef;
+use cranelift_codegen::ir::{self as cl, types, AbiParam, InstBuilder, MemFlags, Type};
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::isa::{CallConv, TargetFrontendConfig};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module};
+use plank_ir::ir::{
+    self, BinaryOp, BitOp, BlockEnd, Instruction, IntOp, Layout, Overflow, Reg, Signedness,
+    Size, Symbol, UnaryOp, Value,
+};
+use {Error, Runtime, HEAP_START, MEMORY_SIZE};
+
+
+/// Program compiled to native code.
+pub struct Compiled {
+    module: Option<JITModule>,
+    main: *const u8,
+    /// Pointer size of `argv` if `main` takes `argc` and `argv`.
+    main_args: Option<Size>,
+    // referenced by compiled code when calling function pointers
+    _table: Box<[*const u8]>,
+}
+
+impl Compiled {
+    /// Run `main`, giving it `argc` and `argv` if it takes them. Memory
+    /// and runtime given to `compile_program` must be still alive.
+    pub unsafe fn run_main(&self, argc: u32, argv: u32) -> i32 {
+        let code = match self.main_args {
+            None => {
+                let main: extern "C" fn() -> u32 = ::std::mem::transmute(self.main);
+                main()
+            }
+            Some(Size::Bit64) => {
+                let main: extern "C" fn(u32, u64) -> u32 = ::std::mem::transmute(self.main);
+                main(argc, u64::from(argv))
+            }
+            Some(_) => {
+                let main: extern "C" fn(u32, u32) -> u32 = ::std::mem::transmute(self.main);
+                main(argc, argv)
+            }
+        };
+        code as i32
+    }
+}
+
+impl Drop for Compiled {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+fn int_type(size: u32) -> Type {
+    match size {
+        1 => types::I8,
+        2 => types::I16,
+        4 => types::I32,
+        8 => types::I64,
+        _ => panic!("no integer type of size {}", size),
+    }
+}
+
+fn is_integer(layout: Layout) -> bool {
+    layout.atomic && matches!(layout.size, 1 | 2 | 4 | 8)
+}
+
+fn is_builtin(name: &Symbol) -> bool {
+    host_function(name).is_some()
+}
+
+/// Host function that implements a builtin. Builtins other than `getc`
+/// and `putc` get their arguments as 64 bit integers after the runtime.
+fn host_function(name: &Symbol) -> Option<i64> {
+    match &*name.0 {
+        "builtin_getc" => Some(::host_getc as *const () as i64),
+        "builtin_putc" => Some(::host_putc as *const () as i64),
+        "builtin_alloc" => Some(::host_alloc as *const () as i64),
+        "builtin_free" => Some(::host_free as *const () as i64),
+        "builtin_realloc" => Some(::host_realloc as *const () as i64),
+        "builtin_bounds_fail" => Some(::host_bounds_fail as *const () as i64),
+        "builtin_print_i32" => Some(::host_print_i32 as *const () as i64),
+        "builtin_print_u32" => Some(::host_print_u32 as *const () as i64),
+        "builtin_write" => Some(::host_write as *const () as i64),
+        "builtin_read_i32" => Some(::host_read_i32 as *const () as i64),
+        "builtin_read_line" => Some(::host_read_line as *const () as i64),
+        "builtin_str_eq" => Some(::host_str_eq as *const () as i64),
+        "builtin_str_copy" => Some(::host_str_copy as *const () as i64),
+        "builtin_str_find" => Some(::host_str_find as *const () as i64),
+        "builtin_assert_fail" => Some(::host_assert_fail as *const () as i64),
+        "builtin_panic_fail" => Some(::host_panic_fail as *const () as i64),
+        "builtin_rand_seed" => Some(::host_rand_seed as *const () as i64),
+        "builtin_rand_u32" => Some(::host_rand_u32 as *const () as i64),
+        "builtin_time_ms" => Some(::host_time_ms as *const () as i64),
+        _ => None,
+    }
+}
+
+/// Integer values are passed directly, and composite values are passed
+/// by host address. Composite results are written to an address given
+/// as the first parameter.
+fn make_signature<I>(call_conv: CallConv, params: I, output: Option<Layout>) -> cl::Signature
+where
+    I: IntoIterator<Item = Option<Layout>>,
+{
+    let mut sig = cl::Signature::new(call_conv);
+    match output {
+        Some(layout) if is_integer(layout) => {
+            sig.returns.push(AbiParam::new(int_type(layout.size)));
+        }
+        Some(_) => sig.params.push(AbiParam::new(types::I64)),
+        None => {}
+    }
+    for param in params {
+        let ty = match param {
+            Some(layout) if is_integer(layout) => int_type(layout.size),
+            Some(_) => types::I64,
+            None => types::I8,
+        };
+        sig.params.push(AbiParam::new(ty));
+    }
+    sig
+}
+
+fn function_signature(call_conv: CallConv, f: &ir::Function) -> cl::Signature {
+    let params = f.parameters.iter().map(|reg| Some(f.registers[reg]));
+    make_signature(call_conv, params, f.output_layout)
+}
+
+fn operands(i: &Instruction) -> Vec<&Value> {
+    match *i {
+        Instruction::Init(_) |
+        Instruction::Drop(_) |
+        Instruction::Nop |
+        Instruction::Unreachable |
+        Instruction::Load(_, _, _) |
+        Instruction::TakeAddress(_, _, _) => Vec::new(),
+        Instruction::BinaryOp(_, _, ref a, ref b) |
+        Instruction::DerefStore(ref a, _, ref b) => vec![a, b],
+        Instruction::UnaryOp(_, _, ref a) |
+        Instruction::DerefLoad(_, ref a, _) |
+        Instruction::Store(_, _, ref a) |
+        Instruction::Assign(_, ref a) |
+        Instruction::CastAssign(_, ref a) => vec![a],
+        Instruction::Call(_, _, ref args) |
+        Instruction::CallProc(_, ref args) => args.iter().collect(),
+        Instruction::CallVirt(_, ref a, ref args) |
+        Instruction::CallProcVirt(ref a, ref args) => {
+            let mut values = vec![a];
+            values.extend(args);
+            values
+        }
+    }
+}
+
+fn block_values(block: &ir::Block) -> Vec<&Value> {
+    let mut values = block.ops.iter().flat_map(|op| operands(op)).collect::<Vec<_>>();
+    match block.end {
+        BlockEnd::Return(ref a) |
+        BlockEnd::Branch(ref a, _, _) => values.push(a),
+        BlockEnd::ReturnProc |
+        BlockEnd::Jump(_) |
+        BlockEnd::Unreachable => {}
+    }
+    values
+}
+
+/// Addresses and other constants used by compiled code.
+struct Globals<'a> {
+    program: &'a ir::Program,
+    /// Values of function symbols, which are indices in the function table.
+    symbol_ids: HashMap<Symbol, u32>,
+    strings: HashMap<&'a [u8], u32>,
+    pointer: Type,
+    memory: i64,
+    runtime: i64,
+    table: i64,
+    table_len: u32,
+    config: TargetFrontendConfig,
+}
+
+impl<'a> Globals<'a> {
+    fn value_layout(&self, f: &ir::Function, value: &Value) -> Option<Layout> {
+        let target = &self.program.target;
+        let scalar = |size: u32| Layout {
+            size,
+            align: target.scalar_align(size),
+            atomic: true,
+        };
+        match *value {
+            Value::Reg(reg) => Some(f.registers[&reg]),
+            Value::Int(_, size) => Some(scalar(size.in_bytes())),
+            Value::Symbol(_) | Value::Bytes(_) => Some(scalar(target.pointer_size.in_bytes())),
+            Value::Undef => None,
+        }
+    }
+}
+
+struct FunctionCompiler<'a, 'b> {
+    globals: &'a Globals<'a>,
+    f: &'a ir::Function,
+    builder: FunctionBuilder<'b>,
+    functions: HashMap<Symbol, cl::FuncRef>,
+    vars: HashMap<Reg, Variable>,
+    /// Offsets of registers that live in memory from the start of the frame.
+    slots: HashMap<Reg, u32>,
+    blocks: HashMap<ir::BlockId, cl::Block>,
+    traps: HashMap<u32, cl::Block>,
+    unwind: cl::Block,
+    /// Frame offset in memory and host address.
+    frame: Option<(cl::Value, cl::Value)>,
+    stack_pointer: Option<cl::Value>,
+    output_address: Option<cl::Value>,
+}
+
+impl<'a, 'b> FunctionCompiler<'a, 'b> {
+    fn iconst(&mut self, ty: Type, value: u64) -> cl::Value {
+        let mask = if ty.bits() == 64 { !0 } else { (1u64 << ty.bits()) - 1 };
+        self.builder.ins().iconst(ty, (value & mask) as i64)
+    }
+
+    fn resize(&mut self, value: cl::Value, ty: Type) -> cl::Value {
+        let from = self.builder.func.dfg.value_type(value);
+        if from.bits() == ty.bits() {
+            value
+        } else if from.bits() > ty.bits() {
+            self.builder.ins().ireduce(ty, value)
+        } else {
+            self.builder.ins().uextend(ty, value)
+        }
+    }
+
+    fn reg_address(&mut self, reg: Reg, offset: u32) -> cl::Value {
+        let slot = self.slots[&reg];
+        let (_, host) = self.frame.expect("register in memory without a frame");
+        self.builder.ins().iadd_imm(host, i64::from(slot + offset))
+    }
+
+    fn trap(&mut self, code: u32) -> cl::Block {
+        let builder = &mut self.builder;
+        *self.traps.entry(code).or_insert_with(|| builder.create_block())
+    }
+
+    /// Jump to `trap` if `condition` is nonzero.
+    fn trap_if(&mut self, condition: cl::Value, code: u32) {
+        let trap = self.trap(code);
+        let next = self.builder.create_block();
+        self.builder.ins().brif(condition, trap, &[], next, &[]);
+        self.builder.switch_to_block(next);
+    }
+
+    fn jump_to_trap(&mut self, code: u32) {
+        let trap = self.trap(code);
+        self.builder.ins().jump(trap, &[]);
+        // following code is unreachable, but still needs a block
+        let next = self.builder.create_block();
+        self.builder.switch_to_block(next);
+    }
+
+    fn check_error(&mut self) {
+        let runtime = self.globals.runtime;
+        let runtime = self.builder.ins().iconst(types::I64, runtime);
+        let flags = MemFlags::trusted();
+        let error = self.builder.ins().load(types::I32, flags, runtime, ::ERROR_OFFSET);
+        let next = self.builder.create_block();
+        let unwind = self.unwind;
+        self.builder.ins().brif(error, unwind, &[], next, &[]);
+        self.builder.switch_to_block(next);
+    }
+
+    fn read(&mut self, value: &Value, ty: Type) -> cl::Value {
+        match *value {
+            Value::Reg(reg) => {
+                if let Some(&var) = self.vars.get(&reg) {
+                    let value = self.builder.use_var(var);
+                    self.resize(value, ty)
+                } else {
+                    let address = self.reg_address(reg, 0);
+                    self.builder.ins().load(ty, MemFlags::new(), address, 0)
+                }
+            }
+            Value::Int(value, _) => self.iconst(ty, value),
+            Value::Symbol(ref sym) => {
+                let id = self.globals.symbol_ids[sym];
+                self.iconst(ty, u64::from(id))
+            }
+            Value::Bytes(ref bytes) => {
+                let offset = self.globals.strings[&bytes[..]];
+                self.iconst(ty, u64::from(offset))
+            }
+            Value::Undef => self.iconst(ty, 0),
+        }
+    }
+
+    fn write(&mut self, reg: Reg, value: cl::Value) {
+        if let Some(&var) = self.vars.get(&reg) {
+            let ty = int_type(self.f.registers[&reg].size);
+            let value = self.resize(value, ty);
+            self.builder.def_var(var, value);
+        } else {
+            let address = self.reg_address(reg, 0);
+            self.builder.ins().store(MemFlags::new(), value, address, 0);
+        }
+    }
+
+    fn copy(&mut self, to: cl::Value, from: cl::Value, size: u32) {
+        if size > 0 {
+            let size = self.builder.ins().iconst(types::I64, i64::from(size));
+            let config = self.globals.config;
+            self.builder.call_memmove(config, to, from, size);
+        }
+    }
+
+    /// Write value to given host address.
+    fn store(&mut self, address: cl::Value, value: &Value) {
+        let layout = match self.globals.value_layout(self.f, value) {
+            Some(layout) => layout,
+            None => return,
+        };
+        match *value {
+            Value::Reg(reg) if !is_integer(layout) => {
+                let from = self.reg_address(reg, 0);
+                self.copy(address, from, layout.size);
+            }
+            _ => {
+                let value = self.read(value, int_type(layout.size));
+                self.builder.ins().store(MemFlags::new(), value, address, 0);
+            }
+        }
+    }
+
+    /// Host address where given value can be found.
+    fn value_address(&mut self, value: &Value, size: u32) -> cl::Value {
+        match *value {
+            Value::Reg(reg) if self.slots.contains_key(&reg) => self.reg_address(reg, 0),
+            _ => {
+                let data = cl::StackSlotData::new(cl::StackSlotKind::ExplicitSlot, size, 3);
+                let slot = self.builder.create_sized_stack_slot(data);
+                let address = self.builder.ins().stack_addr(types::I64, slot, 0);
+                self.store(address, value);
+                address
+            }
+        }
+    }
+
+    /// Convert pointer to a host address, checking that `size` bytes
+    /// at `offset` from it are inside program memory.
+    fn deref(&mut self, pointer: &Value, offset: u32, size: u32) -> cl::Value {
+        let ty = self.globals.pointer;
+        let pointer = self.read(pointer, ty);
+        let pointer = self.resize(pointer, types::I64);
+        let limit = i64::from(MEMORY_SIZE) - i64::from(offset) - i64::from(size);
+        if limit < 0 {
+            self.jump_to_trap(::ERROR_BAD_DEREF);
+        } else {
+            let cond = IntCC::UnsignedGreaterThan;
+            let outside = self.builder.ins().icmp_imm(cond, pointer, limit);
+            self.trap_if(outside, ::ERROR_BAD_DEREF);
+        }
+        let base = self.globals.memory + i64::from(offset);
+        self.builder.ins().iadd_imm(pointer, base)
+    }
+
+    fn call_args(&mut self, params: &[Option<Layout>], args: &[Value]) -> Vec<cl::Value> {
+        let mut values = Vec::new();
+        for (&layout, arg) in params.iter().zip(args) {
+            let value = match layout {
+                Some(layout) if is_integer(layout) => self.read(arg, int_type(layout.size)),
+                Some(layout) => self.value_address(arg, layout.size),
+                None => self.iconst(types::I8, 0),
+            };
+            values.push(value);
+        }
+        values
+    }
+
+    fn finish_call(&mut self, call: cl::Inst, dest: Option<Reg>) {
+        if let Some(reg) = dest {
+            if is_integer(self.f.registers[&reg]) {
+                let result = self.builder.inst_results(call)[0];
+                self.write(reg, result);
+            }
+        }
+        self.check_error();
+    }
+
+    fn output_args(&mut self, dest: Option<Reg>) -> Vec<cl::Value> {
+        match dest {
+            Some(reg) if !is_integer(self.f.registers[&reg]) => vec![self.reg_address(reg, 0)],
+            _ => Vec::new(),
+        }
+    }
+
+    fn call(&mut self, dest: Option<Reg>, sym: &Symbol, args: &[Value]) {
+        let callee = &self.globals.program.functions[sym];
+        let params = callee.parameters
+            .iter()
+            .map(|reg| Some(callee.registers[reg]))
+            .collect::<Vec<_>>();
+        let mut values = self.output_args(dest);
+        values.extend(self.call_args(&params, args));
+        let function = self.functions[sym];
+        let call = self.builder.ins().call(function, &values);
+        self.finish_call(call, dest);
+    }
+
+    fn call_virtual(&mut self, dest: Option<Reg>, function: &Value, args: &[Value]) {
+        let ty = self.globals.pointer;
+        let id = self.read(function, ty);
+        let id = self.resize(id, types::I64);
+        let cond = IntCC::UnsignedGreaterThanOrEqual;
+        let table_len = i64::from(self.globals.table_len);
+        let outside = self.builder.ins().icmp_imm(cond, id, table_len);
+        self.trap_if(outside, ::ERROR_BAD_DEREF);
+        let offset = self.builder.ins().imul_imm(id, 8);
+        let table = self.globals.table;
+        let entry = self.builder.ins().iadd_imm(offset, table);
+        let address = self.builder.ins().load(types::I64, MemFlags::trusted(), entry, 0);
+        let missing = self.builder.ins().icmp_imm(IntCC::Equal, address, 0);
+        self.trap_if(missing, ::ERROR_BAD_DEREF);
+
+        let params = args
+            .iter()
+            .map(|arg| self.globals.value_layout(self.f, arg))
+            .collect::<Vec<_>>();
+        let output = dest.map(|reg| self.f.registers[&reg]);
+        let call_conv = self.builder.func.signature.call_conv;
+        let sig = make_signature(call_conv, params.iter().cloned(), output);
+        let sig = self.builder.import_signature(sig);
+        let mut values = self.output_args(dest);
+        values.extend(self.call_args(&params, args));
+        let call = self.builder.ins().call_indirect(sig, address, &values);
+        self.finish_call(call, dest);
+    }
+
+    fn binary_op(&mut self, op: BinaryOp, a: &Value, b: &Value) -> cl::Value {
+        match op {
+            BinaryOp::IntOp(op, sign, size) => {
+                let ty = int_type(size.in_bytes());
+                let a = self.read(a, ty);
+                let b = self.read(b, ty);
+                match op {
+                    IntOp::Add => self.builder.ins().iadd(a, b),
+                    IntOp::Sub => self.builder.ins().isub(a, b),
+                    IntOp::Mul => self.builder.ins().imul(a, b),
+                    IntOp::Div | IntOp::Mod => self.division(op, sign, ty, a, b),
+                    IntOp::Less |
+                    IntOp::LessEq |
+                    IntOp::Greater |
+                    IntOp::GreaterEq => {
+                        let cond = match (op, sign) {
+                            (IntOp::Less, Signedness::Unsigned) => IntCC::UnsignedLessThan,
+                            (IntOp::Less, Signedness::Signed) => IntCC::SignedLessThan,
+                            (IntOp::LessEq, Signedness::Unsigned) => {
+                                IntCC::UnsignedLessThanOrEqual
+                            }
+                            (IntOp::LessEq, Signedness::Signed) => IntCC::SignedLessThanOrEqual,
+                            (IntOp::Greater, Signedness::Unsigned) => IntCC::UnsignedGreaterThan,
+                            (IntOp::Greater, Signedness::Signed) => IntCC::SignedGreaterThan,
+                            (_, Signedness::Unsigned) => IntCC::UnsignedGreaterThanOrEqual,
+                            (_, Signedness::Signed) => IntCC::SignedGreaterThanOrEqual,
+                        };
+                        self.builder.ins().icmp(cond, a, b)
+                    }
+                }
+            }
+            BinaryOp::Checked(op, overflow, sign, size) => {
+                let ty = int_type(size.in_bytes());
+                let a = self.read(a, ty);
+                let b = self.read(b, ty);
+                self.checked(op, overflow, sign, size, a, b)
+            }
+            BinaryOp::BitOp(op, size) => {
+                let ty = int_type(size.in_bytes());
+                let a = self.read(a, ty);
+                let b = self.read(b, ty);
+                // shift amount is taken modulo bit width, like in the interpreter
+                match op {
+                    BitOp::And => self.builder.ins().band(a, b),
+                    BitOp::Or => self.builder.ins().bor(a, b),
+                    BitOp::Xor => self.builder.ins().bxor(a, b),
+                    BitOp::Shl => self.builder.ins().ishl(a, b),
+                    BitOp::Shr => self.builder.ins().ushr(a, b),
+                }
+            }
+            BinaryOp::Eq | BinaryOp::Neq => {
+                let cond = match op {
+                    BinaryOp::Eq => IntCC::Equal,
+                    _ => IntCC::NotEqual,
+                };
+                let layout = self.globals.value_layout(self.f, a)
+                    .or_else(|| self.globals.value_layout(self.f, b))
+                    .expect("comparing two undefined values");
+                if is_integer(layout) {
+                    let ty = int_type(layout.size);
+                    let a = self.read(a, ty);
+                    let b = self.read(b, ty);
+                    self.builder.ins().icmp(cond, a, b)
+                } else if layout.size == 0 {
+                    let equal = match op {
+                        BinaryOp::Eq => 1,
+                        _ => 0,
+                    };
+                    self.iconst(types::I8, equal)
+                } else {
+                    let a = self.value_address(a, layout.size);
+                    let b = self.value_address(b, layout.size);
+                    let size = self.builder.ins().iconst(types::I64, i64::from(layout.size));
+                    let config = self.globals.config;
+                    let diff = self.builder.call_memcmp(config, a, b, size);
+                    self.builder.ins().icmp_imm(cond, diff, 0)
+                }
+            }
+        }
+    }
+
+    fn checked(
+        &mut self,
+        op: IntOp,
+        overflow: Overflow,
+        sign: Signedness,
+        size: Size,
+        a: cl::Value,
+        b: cl::Value,
+    ) -> cl::Value {
+        let ty = int_type(size.in_bytes());
+        let bits = i64::from(ty.bits());
+        let min = 1u64 << (bits - 1);
+        let (result, overflowed) = match (op, sign) {
+            (IntOp::Add, Signedness::Unsigned) => self.builder.ins().uadd_overflow(a, b),
+            (IntOp::Add, Signedness::Signed) => self.builder.ins().sadd_overflow(a, b),
+            (IntOp::Sub, Signedness::Unsigned) => self.builder.ins().usub_overflow(a, b),
+            (IntOp::Sub, Signedness::Signed) => self.builder.ins().ssub_overflow(a, b),
+            (IntOp::Mul, Signedness::Unsigned) => self.builder.ins().umul_overflow(a, b),
+            (IntOp::Mul, Signedness::Signed) => self.builder.ins().smul_overflow(a, b),
+            (IntOp::Div, Signedness::Unsigned) => {
+                let result = self.division(op, sign, ty, a, b);
+                (result, self.iconst(types::I8, 0))
+            }
+            (IntOp::Div, Signedness::Signed) => {
+                // only the smallest value divided by -1 doesn't fit
+                let result = self.division(op, sign, ty, a, b);
+                let smallest = self.iconst(ty, min);
+                let is_smallest = self.builder.ins().icmp(IntCC::Equal, a, smallest);
+                let minus_one = self.iconst(ty, !0);
+                let is_minus_one = self.builder.ins().icmp(IntCC::Equal, b, minus_one);
+                (result, self.builder.ins().band(is_smallest, is_minus_one))
+            }
+            _ => panic!("{:?} is not checked arithmetic", op),
+        };
+        match overflow {
+            Overflow::Saturate => {
+                let limit = match (op, sign) {
+                    (IntOp::Sub, Signedness::Unsigned) => self.iconst(ty, 0),
+                    (_, Signedness::Unsigned) => self.iconst(ty, !0),
+                    (IntOp::Add, Signedness::Signed) | (IntOp::Sub, Signedness::Signed) => {
+                        // wrapped result has the opposite sign of the real one
+                        let negative = self.builder.ins().sshr_imm(result, bits - 1);
+                        let smallest = self.iconst(ty, min);
+                        self.builder.ins().bxor(negative, smallest)
+                    }
+                    (IntOp::Mul, Signedness::Signed) => {
+                        let signs = self.builder.ins().bxor(a, b);
+                        let negative = self.builder.ins().sshr_imm(signs, bits - 1);
+                        let largest = self.iconst(ty, min - 1);
+                        self.builder.ins().bxor(negative, largest)
+                    }
+                    _ => self.iconst(ty, min - 1),
+                };
+                self.builder.ins().select(overflowed, limit, result)
+            }
+            Overflow::Trap => {
+                let fail = self.builder.create_block();
+                let next = self.builder.create_block();
+                self.builder.set_cold_block(fail);
+                self.builder.ins().brif(overflowed, fail, &[], next, &[]);
+                self.builder.switch_to_block(fail);
+                let runtime = self.builder.ins().iconst(types::I64, self.globals.runtime);
+                let host = ::host_overflow as *const () as i64;
+                let host = self.builder.ins().iconst(types::I64, host);
+                let operation = ::encode_operation(op, sign, size) as i64;
+                let operation = self.builder.ins().iconst(types::I64, operation);
+                let a = self.resize(a, types::I64);
+                let b = self.resize(b, types::I64);
+                let call_conv = self.builder.func.signature.call_conv;
+                let mut sig = cl::Signature::new(call_conv);
+                sig.params.extend((0..4).map(|_| AbiParam::new(types::I64)));
+                let sig = self.builder.import_signature(sig);
+                self.builder.ins().call_indirect(sig, host, &[runtime, operation, a, b]);
+                let unwind = self.unwind;
+                self.builder.ins().jump(unwind, &[]);
+                self.builder.switch_to_block(next);
+                result
+            }
+        }
+    }
+
+    fn division(&mut self, op: IntOp, sign: Signedness, ty: Type, a: cl::Value, b: cl::Value)
+        -> cl::Value
+    {
+        let zero = self.builder.ins().icmp_imm(IntCC::Equal, b, 0);
+        self.trap_if(zero, ::ERROR_DIVISION_BY_ZERO);
+        match sign {
+            Signedness::Unsigned if op == IntOp::Div => self.builder.ins().udiv(a, b),
+            Signedness::Unsigned => self.builder.ins().urem(a, b),
+            Signedness::Signed => {
+                // native division traps on overflow, while the interpreter wraps
+                let minus_one = self.iconst(ty, !0);
+                let is_minus_one = self.builder.ins().icmp(IntCC::Equal, b, minus_one);
+                let one = self.iconst(ty, 1);
+                let divisor = self.builder.ins().select(is_minus_one, one, b);
+                if op == IntOp::Div {
+                    let quotient = self.builder.ins().sdiv(a, divisor);
+                    let negated = self.builder.ins().ineg(a);
+                    self.builder.ins().select(is_minus_one, negated, quotient)
+                } else {
+                    let remainder = self.builder.ins().srem(a, divisor);
+                    let zero = self.iconst(ty, 0);
+                    self.builder.ins().select(is_minus_one, zero, remainder)
+                }
+            }
+        }
+    }
+
+    fn unary_op(&mut self, op: UnaryOp, value: &Value) -> cl::Value {
+        match op {
+            UnaryOp::Negate(_, size) => {
+                let value = self.read(value, int_type(size.in_bytes()));
+                self.builder.ins().ineg(value)
+            }
+            UnaryOp::Extend(sign, from, to) => {
+                let value = self.read(value, int_type(from.in_bytes()));
+                let ty = int_type(to.in_bytes());
+                if from == to {
+                    value
+                } else if sign == Signedness::Signed {
+                    self.builder.ins().sextend(ty, value)
+                } else {
+                    self.builder.ins().uextend(ty, value)
+                }
+            }
+        }
+    }
+
+    fn compile_instruction(&mut self, i: &Instruction) {
+        match *i {
+            Instruction::Init(_) |
+            Instruction::Drop(_) |
+            Instruction::Nop |
+            Instruction::Assign(_, Value::Undef) |
+            Instruction::CastAssign(_, Value::Undef) => {}
+            Instruction::Unreachable => self.jump_to_trap(::ERROR_UNREACHABLE),
+            Instruction::BinaryOp(reg, op, ref a, ref b) => {
+                let result = self.binary_op(op, a, b);
+                self.write(reg, result);
+            }
+            Instruction::UnaryOp(reg, op, ref value) => {
+                let result = self.unary_op(op, value);
+                self.write(reg, result);
+            }
+            Instruction::Call(reg, ref sym, ref args) => self.call(Some(reg), sym, args),
+            Instruction::CallProc(ref sym, ref args) => self.call(None, sym, args),
+            Instruction::CallVirt(reg, ref value, ref args) => {
+                self.call_virtual(Some(reg), value, args)
+            }
+            Instruction::CallProcVirt(ref value, ref args) => {
+                self.call_virtual(None, value, args)
+            }
+            Instruction::DerefStore(ref pointer, offset, ref value) => {
+                if let Some(layout) = self.globals.value_layout(self.f, value) {
+                    let address = self.deref(pointer, offset, layout.size);
+                    self.store(address, value);
+                }
+            }
+            Instruction::DerefLoad(reg, ref pointer, offset) => {
+                let size = self.f.registers[&reg].size;
+                let address = self.deref(pointer, offset, size);
+                self.load(reg, address);
+            }
+            Instruction::Store(reg, offset, ref value) => {
+                let address = self.reg_address(reg, offset);
+                self.store(address, value);
+            }
+            Instruction::Load(to, from, offset) => {
+                let address = self.reg_address(from, offset);
+                self.load(to, address);
+            }
+            Instruction::TakeAddress(to, reg, offset) => {
+                let slot = self.slots[&reg];
+                let (frame, _) = self.frame.expect("register in memory without a frame");
+                let address = self.builder.ins().iadd_imm(frame, i64::from(slot + offset));
+                self.write(to, address);
+            }
+            Instruction::Assign(reg, ref value) |
+            Instruction::CastAssign(reg, ref value) => {
+                if let Some(&var) = self.vars.get(&reg) {
+                    let ty = int_type(self.f.registers[&reg].size);
+                    let value = self.read(value, ty);
+                    self.builder.def_var(var, value);
+                } else {
+                    let address = self.reg_address(reg, 0);
+                    self.store(address, value);
+                }
+            }
+        }
+    }
+
+    /// Load register from given host address.
+    fn load(&mut self, reg: Reg, address: cl::Value) {
+        let layout = self.f.registers[&reg];
+        if let Some(&var) = self.vars.get(&reg) {
+            let ty = int_type(layout.size);
+            let value = self.builder.ins().load(ty, MemFlags::new(), address, 0);
+            self.builder.def_var(var, value);
+        } else {
+            let to = self.reg_address(reg, 0);
+            self.copy(to, address, layout.size);
+        }
+    }
+
+    fn restore_stack(&mut self) {
+        if let Some(stack_pointer) = self.stack_pointer {
+            let runtime = self.builder.ins().iconst(types::I64, self.globals.runtime);
+            let flags = MemFlags::trusted();
+            let offset = ::STACK_POINTER_OFFSET;
+            self.builder.ins().store(flags, stack_pointer, runtime, offset);
+        }
+    }
+
+    fn compile_block_end(&mut self, end: &BlockEnd) {
+        match *end {
+            BlockEnd::Return(ref value) => {
+                let layout = self.f.output_layout.expect("returned value from a procedure");
+                if is_integer(layout) {
+                    let value = self.read(value, int_type(layout.size));
+                    self.restore_stack();
+                    self.builder.ins().return_(&[value]);
+                } else {
+                    let address = self.output_address.expect("no output address");
+                    self.store(address, value);
+                    self.restore_stack();
+                    self.builder.ins().return_(&[]);
+                }
+            }
+            BlockEnd::ReturnProc => {
+                self.restore_stack();
+                self.builder.ins().return_(&[]);
+            }
+            BlockEnd::Jump(block) => {
+                let block = self.blocks[&block];
+                self.builder.ins().jump(block, &[]);
+            }
+            BlockEnd::Branch(ref value, a, b) => {
+                let value = self.read(value, types::I8);
+                let a = self.blocks[&a];
+                let b = self.blocks[&b];
+                self.builder.ins().brif(value, a, &[], b, &[]);
+            }
+            BlockEnd::Unreachable => {
+                let trap = self.trap(::ERROR_UNREACHABLE);
+                self.builder.ins().jump(trap, &[]);
+            }
+        }
+    }
+
+    /// Allocate frame in memory for registers that need it and
+    /// move parameters to their registers.
+    fn compile_prologue(&mut self, frame_size: Option<u32>) {
+        let entry = self.builder.create_block();
+        self.builder.append_block_params_for_function_params(entry);
+        self.builder.switch_to_block(entry);
+        let mut params = self.builder.block_params(entry).to_vec().into_iter();
+        if let Some(layout) = self.f.output_layout {
+            if !is_integer(layout) {
+                self.output_address = params.next();
+            }
+        }
+
+        let runtime = self.builder.ins().iconst(types::I64, self.globals.runtime);
+        let flags = MemFlags::trusted();
+        // loaded before any branch to unwinding, which restores it
+        let stack_pointer = if frame_size.is_some() {
+            let offset = ::STACK_POINTER_OFFSET;
+            Some(self.builder.ins().load(types::I64, flags, runtime, offset))
+        } else {
+            None
+        };
+        let offset = ::NATIVE_STACK_LIMIT_OFFSET;
+        let limit = self.builder.ins().load(types::I64, flags, runtime, offset);
+        let native = self.builder.ins().get_stack_pointer(types::I64);
+        let overflow = self.builder.ins().icmp(IntCC::UnsignedLessThan, native, limit);
+        self.trap_if(overflow, ::ERROR_STACK_OVERFLOW);
+
+        if let (Some(frame_size), Some(stack_pointer)) = (frame_size, stack_pointer) {
+            let offset = ::STACK_POINTER_OFFSET;
+            let frame = self.builder.ins().iadd_imm(stack_pointer, 7);
+            let frame = self.builder.ins().band_imm(frame, !7);
+            let end = self.builder.ins().iadd_imm(frame, i64::from(frame_size));
+            let cond = IntCC::UnsignedGreaterThan;
+            let overflow = self.builder.ins().icmp_imm(cond, end, i64::from(HEAP_START));
+            self.stack_pointer = Some(stack_pointer);
+            self.trap_if(overflow, ::ERROR_STACK_OVERFLOW);
+            self.builder.ins().store(flags, end, runtime, offset);
+            let host = self.builder.ins().iadd_imm(frame, self.globals.memory);
+            self.frame = Some((frame, host));
+        }
+
+        for (reg, value) in self.f.parameters.iter().zip(params) {
+            let layout = self.f.registers[reg];
+            if is_integer(layout) {
+                self.write(*reg, value);
+            } else {
+                let to = self.reg_address(*reg, 0);
+                self.copy(to, value, layout.size);
+            }
+        }
+    }
+
+    fn compile_epilogue(&mut self) {
+        let mut traps = self.traps.iter().map(|(&code, &block)| (code, block)).collect::<Vec<_>>();
+        traps.sort_by_key(|&(code, _)| code);
+        let unwind = self.unwind;
+        for (code, block) in traps {
+            self.builder.switch_to_block(block);
+            let runtime = self.builder.ins().iconst(types::I64, self.globals.runtime);
+            let code = self.builder.ins().iconst(types::I32, i64::from(code));
+            let flags = MemFlags::trusted();
+            self.builder.ins().store(flags, code, runtime, ::ERROR_OFFSET);
+            self.builder.ins().jump(unwind, &[]);
+        }
+
+        self.builder.switch_to_block(unwind);
+        self.restore_stack();
+        match self.f.output_layout {
+            Some(layout) if is_integer(layout) => {
+                let zero = self.iconst(int_type(layout.size), 0);
+                self.builder.ins().return_(&[zero]);
+            }
+            _ => {
+                self.builder.ins().return_(&[]);
+            }
+        }
+    }
+}
+
+/// Registers that are accessed by address and must be stored in memory.
+fn addressed_registers(f: &ir::Function) -> HashSet<Reg> {
+    let mut registers = HashSet::new();
+    for block in f.blocks.values() {
+        for i in &block.ops {
+            match i.instruction {
+                Instruction::Store(reg, _, _) |
+                Instruction::Load(_, reg, _) |
+                Instruction::TakeAddress(_, reg, _) => {
+                    registers.insert(reg);
+                }
+                _ => {}
+            }
+        }
+    }
+    registers
+}
+
+fn compile_function(
+    globals: &Globals,
+    module: &mut JITModule,
+    ids: &HashMap<Symbol, FuncId>,
+    f: &ir::Function,
+    ctx: &mut cranelift_codegen::Context,
+    builder_ctx: &mut FunctionBuilderContext,
+) {
+    let start = f.start_block.expect("compiling function without a body");
+
+    let mut functions = HashMap::new();
+    for block in f.blocks.values() {
+        for i in &block.ops {
+            match i.instruction {
+                Instruction::Call(_, ref sym, _) |
+                Instruction::CallProc(ref sym, _) if !functions.contains_key(sym) => {
+                    let func = module.declare_func_in_func(ids[sym], &mut ctx.func);
+                    functions.insert(sym.clone(), func);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let addressed = addressed_registers(f);
+    let mut registers = f.registers.iter().collect::<Vec<_>>();
+    registers.sort_by_key(|&(&reg, _)| reg);
+    let mut vars = HashMap::new();
+    let mut slots = HashMap::new();
+    let mut frame_size = 0u32;
+    for (&reg, &layout) in registers {
+        if is_integer(layout) && !addressed.contains(&reg) {
+            vars.insert(reg, Variable::new(reg.0 as usize));
+        } else {
+            let align = ::std::cmp::max(layout.align, 1);
+            frame_size = frame_size.div_ceil(align) * align;
+            slots.insert(reg, frame_size);
+            frame_size += layout.size;
+        }
+    }
+
+    let mut builder = FunctionBuilder::new(&mut ctx.func, builder_ctx);
+    for (&reg, &var) in &vars {
+        builder.declare_var(var, int_type(f.registers[&reg].size));
+    }
+    let mut blocks = HashMap::new();
+    for &id in f.blocks.keys() {
+        blocks.insert(id, builder.create_block());
+    }
+    let unwind = builder.create_block();
+    let mut compiler = FunctionCompiler {
+        globals,
+        f,
+        builder,
+        functions,
+        vars,
+        slots,
+        blocks,
+        traps: HashMap::new(),
+        unwind,
+        frame: None,
+        stack_pointer: None,
+        output_address: None,
+    };
+    let frame_size = if compiler.slots.is_empty() {
+        None
+    } else {
+        Some(frame_size)
+    };
+    compiler.compile_prologue(frame_size);
+    let start = compiler.blocks[&start];
+    compiler.builder.ins().jump(start, &[]);
+
+    let mut ids = f.blocks.keys().cloned().collect::<Vec<_>>();
+    ids.sort();
+    for id in ids {
+        let block = &f.blocks[&id];
+        let cl_block = compiler.blocks[&id];
+        compiler.builder.switch_to_block(cl_block);
+        for i in &block.ops {
+            compiler.compile_instruction(i);
+        }
+        compiler.compile_block_end(&block.end);
+    }
+    compiler.compile_epilogue();
+    compiler.builder.seal_all_blocks();
+    compiler.builder.finalize();
+}
+
+/// Body of a builtin, which calls the host.
+fn compile_builtin(
+    globals: &Globals,
+    name: &Symbol,
+    ctx: &mut cranelift_codegen::Context,
+    builder_ctx: &mut FunctionBuilderContext,
+) {
+    let call_conv = ctx.func.signature.call_conv;
+    let returns = ctx.func.signature.returns.clone();
+    let mut builder = FunctionBuilder::new(&mut ctx.func, builder_ctx);
+    let entry = builder.create_block();
+    builder.append_block_params_for_function_params(entry);
+    builder.switch_to_block(entry);
+    let runtime = builder.ins().iconst(types::I64, globals.runtime);
+    let host = host_function(name).expect("not a builtin");
+    let host = builder.ins().iconst(types::I64, host);
+    if &*name.0 == "builtin_getc" {
+        let mut sig = cl::Signature::new(call_conv);
+        sig.params.push(AbiParam::new(types::I64));
+        sig.returns.push(AbiParam::new(types::I32));
+        let sig = builder.import_signature(sig);
+        let call = builder.ins().call_indirect(sig, host, &[runtime]);
+        let result = builder.inst_results(call)[0];
+        builder.ins().return_(&[result]);
+    } else if &*name.0 == "builtin_putc" {
+        let mut sig = cl::Signature::new(call_conv);
+        sig.params.push(AbiParam::new(types::I64));
+        sig.params.push(AbiParam::new(types::I32));
+        let sig = builder.import_signature(sig);
+        let byte = builder.block_params(entry)[0];
+        let byte = builder.ins().uextend(types::I32, byte);
+        builder.ins().call_indirect(sig, host, &[runtime, byte]);
+        builder.ins().return_(&[]);
+    } else {
+        let mut sig = cl::Signature::new(call_conv);
+        let mut args = vec![runtime];
+        for param in builder.block_params(entry).to_vec() {
+            let arg = if builder.func.dfg.value_type(param) == types::I64 {
+                param
+            } else {
+                builder.ins().uextend(types::I64, param)
+            };
+            args.push(arg);
+        }
+        sig.params.extend(args.iter().map(|_| AbiParam::new(types::I64)));
+        if !returns.is_empty() {
+            sig.returns.push(AbiParam::new(types::I64));
+        }
+        let sig = builder.import_signature(sig);
+        let call = builder.ins().call_indirect(sig, host, &args);
+        let results = builder.inst_results(call).to_vec();
+        let results = results
+            .into_iter()
+            .zip(&returns)
+            .map(|(value, ret)| {
+                if ret.value_type == types::I64 {
+                    value
+                } else {
+                    builder.ins().ireduce(ret.value_type, value)
+                }
+            })
+            .collect::<Vec<_>>();
+        builder.ins().return_(&results);
+    }
+    builder.seal_all_blocks();
+    builder.finalize();
+}
+
+fn compile_error<E: ::std::fmt::Display>(err: E) -> Error {
+    Error::Compile(err.to_string())
+}
+
+/// Compile program to native code. String literals are written to
+/// `memory`, and compiled code refers to `memory` and `runtime`
+/// directly, so they must not move until the program finishes.
+pub fn compile_program(program: &ir::Program, memory: &mut [u8], runtime: &mut Runtime)
+    -> Result<Compiled, Error>
+{
+    let mut flag_builder = settings::builder();
+    flag_builder.set("opt_level", "speed").map_err(compile_error)?;
+    let isa = cranelift_native::builder()
+        .map_err(compile_error)?
+        .finish(settings::Flags::new(flag_builder))
+        .map_err(compile_error)?;
+    let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+    let call_conv = module.isa().default_call_conv();
+
+    let mut functions = program.functions
+        .iter()
+        .filter(|&(name, f)| f.start_block.is_some() || is_builtin(name))
+        .collect::<Vec<_>>();
+    functions.sort_by_key(|&(name, _)| name.0.clone());
+    let mut ids = HashMap::new();
+    let mut symbol_ids = HashMap::new();
+    for (index, &(name, f)) in functions.iter().enumerate() {
+        let sig = function_signature(call_conv, f);
+        let id = module
+            .declare_function(&name.0, Linkage::Local, &sig)
+            .map_err(compile_error)?;
+        ids.insert(name.clone(), id);
+        symbol_ids.insert(name.clone(), index as u32);
+    }
+
+    // the first 4 bytes are left for the result of `main`, like in the interpreter
+    let mut strings = HashMap::new();
+    let mut used = 4;
+    for &(_, f) in &functions {
+        for block in f.blocks.values() {
+            for value in block_values(block) {
+                match *value {
+                    Value::Bytes(ref bytes) if !strings.contains_key(&bytes[..]) => {
+                        let end = used + bytes.len();
+                        if end > HEAP_START as usize {
+                            return Err(Error::StackOverflow);
+                        }
+                        memory[used..end].copy_from_slice(bytes);
+                        strings.insert(&bytes[..], used as u32);
+                        used = end;
+                    }
+                    Value::Symbol(ref sym) if !symbol_ids.contains_key(sym) => {
+                        return Err(Error::MissingSymbol(sym.clone()));
+                    }
+                    _ => {}
+                }
+            }
+            for i in &block.ops {
+                match i.instruction {
+                    Instruction::Call(_, ref sym, _) |
+                    Instruction::CallProc(ref sym, _) if !ids.contains_key(sym) => {
+                        return Err(Error::MissingSymbol(sym.clone()));
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    runtime.stack_pointer = used as u64;
+
+    let table = vec![::std::ptr::null(); functions.len()].into_boxed_slice();
+    let globals = Globals {
+        program,
+        symbol_ids,
+        strings,
+        pointer: int_type(program.target.pointer_size.in_bytes()),
+        memory: memory.as_mut_ptr() as i64,
+        runtime: runtime as *mut Runtime as i64,
+        table: table.as_ptr() as i64,
+        table_len: functions.len() as u32,
+        config: module.target_config(),
+    };
+
+    let mut ctx = module.make_context();
+    let mut builder_ctx = FunctionBuilderContext::new();
+    for &(name, f) in &functions {
+        ctx.func.signature = function_signature(call_conv, f);
+        if f.start_block.is_some() {
+            compile_function(&globals, &mut module, &ids, f, &mut ctx, &mut builder_ctx);
+        } else {
+            compile_builtin(&globals, name, &mut ctx, &mut builder_ctx);
+        }
+        module.define_function(ids[name], &mut ctx).map_err(compile_error)?;
+        module.clear_context(&mut ctx);
+    }
+    module.finalize_definitions().map_err(compile_error)?;
+
+    let mut table = table;
+    for (index, &(name, _)) in functions.iter().enumerate() {
+        table[index] = module.get_finalized_function(ids[name]);
+    }
+    let main_symbol = Symbol("fn_main".into());
+    let main_args = match program.functions[&main_symbol].parameters.len() {
+        0 => None,
+        _ => Some(program.target.pointer_size),
+    };
+    let main = match (ids.get(&main_symbol), program.functions[&main_symbol].output_layout) {
+        (Some(&id), Some(layout)) if is_integer(layout) && layout.size == 4 => {
+            module.get_finalized_function(id)
+        }
+        _ => return Err(Error::MissingSymbol(main_symbol)),
+    };
+    Ok(Compiled {
+        module: Some(module),
+        main,
+        main_args,
+        _table: table,
+    })
+}
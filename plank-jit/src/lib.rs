@@ -0,0 +1,552 @@
+extern crate cranelift_codegen;
+extern crate cranelift_frontend;
+extern crate cranelift_jit;
+extern crate cranelift_module;
+extern crate cranelift_native;
+extern crate plank_interpreter;
+extern crate plank_ir;
+
+mod compiler;
+
+use std::io::{self, Read, Write};
+use std::time::Instant;
+use plank_interpreter::heap::Heap;
+use plank_ir::{ir, Program};
+
+
+#[derive(Debug)]
+pub enum Error {
+    BadDeref,
+    DivisionByZero,
+    /// Failed bounds check, with the index and the length.
+    IndexOutOfBounds(u32, u32),
+    /// Arithmetic that traps on overflow did, described like `127 + 1 in i8`.
+    ArithmeticOverflow(String),
+    /// `assert` failed, with its message and position like `x > 0 at 3:5`.
+    AssertionFailed(String),
+    /// `panic` was called, with its message and position like `AssertionFailed`.
+    Panicked(String),
+    MissingSymbol(ir::Symbol),
+    Io(io::Error),
+    ExecutedUnreachable,
+    StackOverflow,
+    BadFree,
+    /// Cranelift failed to compile the program.
+    Compile(String),
+}
+
+impl Error {
+    /// Whether the program stopped because of a bug in it, rather than
+    /// because it could not be compiled or its input or output failed.
+    pub fn is_trap(&self) -> bool {
+        !matches!(*self, Error::Io(_) | Error::Compile(_) | Error::MissingSymbol(_))
+    }
+}
+
+impl ::std::convert::From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl ::std::fmt::Display for Error {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match *self {
+            Error::BadDeref => write!(f, "dereferenced invalid pointer"),
+            Error::DivisionByZero => write!(f, "division by zero"),
+            Error::IndexOutOfBounds(index, len) => {
+                write!(f, "index out of bounds: the index is {} but the length is {}", index, len)
+            }
+            Error::ArithmeticOverflow(ref what) => write!(f, "arithmetic overflow: {}", what),
+            Error::AssertionFailed(ref what) => write!(f, "assertion failed: {}", what),
+            Error::Panicked(ref what) => write!(f, "panicked: {}", what),
+            Error::MissingSymbol(ref sym) => {
+                write!(f, "missing definition for symbol `{}`", sym.0)
+            }
+            Error::Io(ref err) => {
+                write!(f, "io error: {}", err)
+            }
+            Error::ExecutedUnreachable => write!(f, "reached unreachable instruction"),
+            Error::StackOverflow => write!(f, "stack overflow"),
+            Error::BadFree => write!(f, "freed invalid pointer"),
+            Error::Compile(ref msg) => write!(f, "failed to compile: {}", msg),
+        }
+    }
+}
+
+/// Size of memory given to the program. Registers that need an address
+/// and string literals live there, and pointers are offsets into it, so
+/// programs behave the same as in the interpreter.
+const MEMORY_SIZE: u32 = 1 << 26;
+
+/// The stack takes up memory below this, and the heap above it.
+const HEAP_START: u32 = MEMORY_SIZE / 2;
+
+/// State of `rand_u32` before the program calls `rand_seed`,
+/// or after it calls it with zero.
+const RAND_START: u32 = 2_463_534_242;
+
+// Error codes written to `Runtime::error` by compiled code.
+const NO_ERROR: u32 = 0;
+const ERROR_BAD_DEREF: u32 = 1;
+const ERROR_DIVISION_BY_ZERO: u32 = 2;
+const ERROR_UNREACHABLE: u32 = 3;
+const ERROR_STACK_OVERFLOW: u32 = 4;
+const ERROR_IO: u32 = 5;
+const ERROR_BAD_FREE: u32 = 6;
+const ERROR_INDEX_OUT_OF_BOUNDS: u32 = 7;
+const ERROR_OVERFLOW: u32 = 8;
+const ERROR_ASSERTION: u32 = 9;
+const ERROR_PANIC: u32 = 10;
+
+/// Native stack that compiled code may use, in bytes. Programs that
+/// recurse deeper fail with `Error::StackOverflow`.
+const NATIVE_STACK_SIZE: u64 = 1 << 20;
+
+/// State shared between compiled code and the host. Compiled code
+/// accesses the first three fields directly.
+#[repr(C)]
+struct Runtime<'a> {
+    /// Offset of the first unused byte of memory.
+    stack_pointer: u64,
+    /// Set when the program fails. Compiled code returns immediately
+    /// when it sees an error after a call.
+    error: u32,
+    /// Compiled code fails when the native stack pointer goes below this.
+    native_stack_limit: u64,
+    input: &'a mut dyn Read,
+    output: &'a mut dyn Write,
+    io_error: Option<io::Error>,
+    heap: Heap,
+    /// Host address of program memory.
+    memory: *mut u8,
+    /// Index and length of a failed bounds check.
+    bounds_failure: (u32, u32),
+    /// Description of arithmetic that overflowed.
+    overflow: String,
+    /// Message and position of a failed assert or a panic.
+    assertion: String,
+    /// Program that is running, to find the files that positions are in.
+    program: &'a Program,
+    /// State of the xorshift generator of `rand_u32`, never zero.
+    rand_state: u32,
+    /// When `main` was called, for `time_ms`.
+    started: Instant,
+}
+
+const STACK_POINTER_OFFSET: i32 = 0;
+const ERROR_OFFSET: i32 = 8;
+const NATIVE_STACK_LIMIT_OFFSET: i32 = 16;
+
+extern "C" fn host_getc(runtime: *mut Runtime) -> u32 {
+    let runtime = unsafe { &mut *runtime };
+    match runtime.read_byte() {
+        Some(byte) => u32::from(byte),
+        None => !0u32,
+    }
+}
+
+extern "C" fn host_putc(runtime: *mut Runtime, byte: u32) {
+    let runtime = unsafe { &mut *runtime };
+    runtime.write(&[byte as u8]);
+}
+
+impl<'a> Runtime<'a> {
+    /// Read a byte of input, giving `None` at the end
+    /// of input or if reading fails.
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut buf = [0];
+        match self.input.read(&mut buf) {
+            Ok(0) => None,
+            Ok(_) => Some(buf[0]),
+            Err(err) => {
+                self.io_error = Some(err);
+                self.error = ERROR_IO;
+                None
+            }
+        }
+    }
+
+    /// Range of program memory that a builtin gets as a pointer and a
+    /// length. Invalid ranges set an error and give `None`.
+    fn range(&mut self, address: u64, len: u64) -> Option<::std::ops::Range<usize>> {
+        let (address, len) = (address as u32 as usize, len as u32 as usize);
+        if len == 0 {
+            Some(0..0)
+        } else if address == 0 || address + len > MEMORY_SIZE as usize {
+            self.error = ERROR_BAD_DEREF;
+            None
+        } else {
+            Some(address..address + len)
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        if let Err(err) = self.output.write_all(bytes) {
+            self.io_error = Some(err);
+            self.error = ERROR_IO;
+        }
+    }
+
+    fn memory(&mut self) -> &mut [u8] {
+        unsafe { ::std::slice::from_raw_parts_mut(self.memory, MEMORY_SIZE as usize) }
+    }
+
+    /// Clear contents of a heap block, like the interpreter does.
+    fn zero(&mut self, from: u32, to: u32) {
+        for byte in &mut self.memory()[from as usize..to as usize] {
+            *byte = 0;
+        }
+    }
+
+    fn alloc(&mut self, size: u32) -> u32 {
+        match self.heap.alloc(size) {
+            Some(address) => {
+                self.zero(address, address + size);
+                address
+            }
+            None => 0,
+        }
+    }
+
+    fn free(&mut self, address: u32) {
+        if address != 0 && self.heap.free(address).is_none() {
+            self.error = ERROR_BAD_FREE;
+        }
+    }
+
+    fn realloc(&mut self, address: u32, size: u32) -> u32 {
+        if address == 0 {
+            return self.alloc(size);
+        }
+        let old_size = match self.heap.block_size(address) {
+            Some(size) => size,
+            None => {
+                self.error = ERROR_BAD_FREE;
+                return 0;
+            }
+        };
+        if self.heap.resize(address, size) {
+            if size > old_size {
+                self.zero(address + old_size, address + size);
+            }
+            return address;
+        }
+        let new = self.alloc(size);
+        if new != 0 {
+            let len = old_size.min(size) as usize;
+            let from = address as usize;
+            self.memory().copy_within(from..from + len, new as usize);
+            self.heap.free(address);
+        }
+        new
+    }
+}
+
+extern "C" fn host_alloc(runtime: *mut Runtime, size: u64) -> u64 {
+    let runtime = unsafe { &mut *runtime };
+    u64::from(runtime.alloc(size as u32))
+}
+
+extern "C" fn host_free(runtime: *mut Runtime, address: u64) {
+    let runtime = unsafe { &mut *runtime };
+    runtime.free(address as u32);
+}
+
+extern "C" fn host_realloc(runtime: *mut Runtime, address: u64, size: u64) -> u64 {
+    let runtime = unsafe { &mut *runtime };
+    u64::from(runtime.realloc(address as u32, size as u32))
+}
+
+extern "C" fn host_bounds_fail(runtime: *mut Runtime, index: u64, len: u64) {
+    let runtime = unsafe { &mut *runtime };
+    runtime.bounds_failure = (index as u32, len as u32);
+    runtime.error = ERROR_INDEX_OUT_OF_BOUNDS;
+}
+
+extern "C" fn host_assert_fail(
+    runtime: *mut Runtime,
+    address: u64,
+    len: u64,
+    line: u64,
+    column: u64,
+) {
+    fail(runtime, ERROR_ASSERTION, address, len, line, column);
+}
+
+extern "C" fn host_panic_fail(
+    runtime: *mut Runtime,
+    address: u64,
+    len: u64,
+    line: u64,
+    column: u64,
+) {
+    fail(runtime, ERROR_PANIC, address, len, line, column);
+}
+
+fn fail(runtime: *mut Runtime, error: u32, address: u64, len: u64, line: u64, column: u64) {
+    let runtime = unsafe { &mut *runtime };
+    if let Some(range) = runtime.range(address, len) {
+        let mut assertion = String::from_utf8_lossy(&runtime.memory()[range]).into_owned();
+        if line != 0 {
+            let location = runtime.program.locate(line as u32, column as u32);
+            assertion.push_str(&format!(" at {}", location));
+        }
+        runtime.assertion = assertion;
+        runtime.error = error;
+    }
+}
+
+extern "C" fn host_print_i32(runtime: *mut Runtime, value: u64) {
+    let runtime = unsafe { &mut *runtime };
+    runtime.write((value as i32).to_string().as_bytes());
+}
+
+extern "C" fn host_print_u32(runtime: *mut Runtime, value: u64) {
+    let runtime = unsafe { &mut *runtime };
+    runtime.write((value as u32).to_string().as_bytes());
+}
+
+extern "C" fn host_write(runtime: *mut Runtime, address: u64, len: u64) {
+    let runtime = unsafe { &mut *runtime };
+    if let Some(range) = runtime.range(address, len) {
+        let bytes = runtime.memory()[range].to_vec();
+        runtime.write(&bytes);
+    }
+}
+
+extern "C" fn host_read_i32(runtime: *mut Runtime) -> u64 {
+    let runtime = unsafe { &mut *runtime };
+    let mut byte = runtime.read_byte();
+    while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = byte {
+        byte = runtime.read_byte();
+    }
+    let negative = byte == Some(b'-');
+    if negative {
+        byte = runtime.read_byte();
+    }
+    let mut value = 0i32;
+    while let Some(digit @ b'0'..=b'9') = byte {
+        value = value.wrapping_mul(10).wrapping_add(i32::from(digit - b'0'));
+        byte = runtime.read_byte();
+    }
+    let value = if negative { value.wrapping_neg() } else { value };
+    u64::from(value as u32)
+}
+
+extern "C" fn host_rand_seed(runtime: *mut Runtime, seed: u64) {
+    let runtime = unsafe { &mut *runtime };
+    runtime.rand_state = match seed as u32 {
+        0 => RAND_START,
+        seed => seed,
+    };
+}
+
+extern "C" fn host_rand_u32(runtime: *mut Runtime) -> u64 {
+    let runtime = unsafe { &mut *runtime };
+    let mut x = runtime.rand_state;
+    x ^= x << 13;
+    x ^= x >> 17;
+    x ^= x << 5;
+    runtime.rand_state = x;
+    u64::from(x)
+}
+
+extern "C" fn host_time_ms(runtime: *mut Runtime) -> u64 {
+    let runtime = unsafe { &mut *runtime };
+    let elapsed = runtime.started.elapsed();
+    let ms = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis());
+    u64::from(ms as u32)
+}
+
+extern "C" fn host_read_line(runtime: *mut Runtime, address: u64, len: u64) -> u64 {
+    let runtime = unsafe { &mut *runtime };
+    let range = match runtime.range(address, len) {
+        Some(range) => range,
+        None => return 0,
+    };
+    let mut byte = runtime.read_byte();
+    if byte.is_none() {
+        return u64::from(!0u32);
+    }
+    let mut stored = 0;
+    while let Some(b) = byte {
+        if b == b'\n' {
+            break;
+        }
+        if stored < range.len() {
+            runtime.memory()[range.start + stored] = b;
+            stored += 1;
+        }
+        byte = runtime.read_byte();
+    }
+    stored as u64
+}
+
+extern "C" fn host_str_eq(runtime: *mut Runtime, a: u64, a_len: u64, b: u64, b_len: u64) -> u64 {
+    let runtime = unsafe { &mut *runtime };
+    match (runtime.range(a, a_len), runtime.range(b, b_len)) {
+        (Some(a), Some(b)) => {
+            let memory = runtime.memory();
+            u64::from(memory[a] == memory[b])
+        }
+        _ => 0,
+    }
+}
+
+extern "C" fn host_str_copy(
+    runtime: *mut Runtime,
+    to: u64,
+    to_len: u64,
+    from: u64,
+    from_len: u64,
+) -> u64 {
+    let runtime = unsafe { &mut *runtime };
+    let len = (to_len as u32).min(from_len as u32);
+    match (runtime.range(to, u64::from(len)), runtime.range(from, u64::from(len))) {
+        (Some(to), Some(from)) => {
+            runtime.memory().copy_within(from, to.start);
+            u64::from(len)
+        }
+        _ => 0,
+    }
+}
+
+extern "C" fn host_str_find(
+    runtime: *mut Runtime,
+    string: u64,
+    string_len: u64,
+    pattern: u64,
+    pattern_len: u64,
+) -> u64 {
+    let runtime = unsafe { &mut *runtime };
+    match (runtime.range(string, string_len), runtime.range(pattern, pattern_len)) {
+        (Some(string), Some(pattern)) if pattern.len() <= string.len() => {
+            let memory = runtime.memory();
+            let (string, pattern) = (&memory[string], &memory[pattern]);
+            let index = (0..=string.len() - pattern.len())
+                .find(|&i| string[i..i + pattern.len()] == *pattern);
+            u64::from(index.map_or(!0, |i| i as u32))
+        }
+        _ => u64::from(!0u32),
+    }
+}
+
+/// Checked operations are passed to `host_overflow` as one integer.
+fn encode_operation(op: ir::IntOp, sign: ir::Signedness, size: ir::Size) -> u64 {
+    let op = match op {
+        ir::IntOp::Add => 0,
+        ir::IntOp::Sub => 1,
+        ir::IntOp::Mul => 2,
+        ir::IntOp::Div => 3,
+        _ => panic!("{:?} is not checked arithmetic", op),
+    };
+    let sign = match sign {
+        ir::Signedness::Unsigned => 0,
+        ir::Signedness::Signed => 1,
+    };
+    op | sign << 4 | u64::from(size.in_bytes()) << 8
+}
+
+fn decode_operation(code: u64) -> (ir::IntOp, ir::Signedness, ir::Size) {
+    let op = match code & 0xf {
+        0 => ir::IntOp::Add,
+        1 => ir::IntOp::Sub,
+        2 => ir::IntOp::Mul,
+        _ => ir::IntOp::Div,
+    };
+    let sign = match (code >> 4) & 0xf {
+        0 => ir::Signedness::Unsigned,
+        _ => ir::Signedness::Signed,
+    };
+    let size = match code >> 8 {
+        1 => ir::Size::Bit8,
+        2 => ir::Size::Bit16,
+        4 => ir::Size::Bit32,
+        _ => ir::Size::Bit64,
+    };
+    (op, sign, size)
+}
+
+extern "C" fn host_overflow(runtime: *mut Runtime, operation: u64, a: u64, b: u64) {
+    let runtime = unsafe { &mut *runtime };
+    let (op, sign, size) = decode_operation(operation);
+    runtime.overflow = op.describe(sign, size, a, b);
+    runtime.error = ERROR_OVERFLOW;
+}
+
+/// Compile program to native code and run it. Returns exit code
+/// in the same way as `plank_interpreter::run_program`.
+pub fn run_program<R: Read, W: Write>(program: &Program, input: R, output: W) -> Result<i32, Error> {
+    run_program_with_args(program, input, output, &[])
+}
+
+/// Same as `run_program`, but gives `args` to `main` if it takes `argc`
+/// and `argv`. The first argument should be the name of the program.
+pub fn run_program_with_args<R: Read, W: Write>(
+    program: &Program,
+    mut input: R,
+    mut output: W,
+    args: &[String],
+) -> Result<i32, Error> {
+    let mut memory = vec![0u8; MEMORY_SIZE as usize];
+    let stack_marker = 0u8;
+    let stack_top = &stack_marker as *const u8 as u64;
+    let mut runtime = Runtime {
+        stack_pointer: 0,
+        error: NO_ERROR,
+        native_stack_limit: stack_top.saturating_sub(NATIVE_STACK_SIZE),
+        input: &mut input,
+        output: &mut output,
+        io_error: None,
+        heap: Heap::new(HEAP_START, MEMORY_SIZE),
+        memory: memory.as_mut_ptr(),
+        bounds_failure: (0, 0),
+        overflow: String::new(),
+        assertion: String::new(),
+        program,
+        rand_state: RAND_START,
+        started: Instant::now(),
+    };
+    let compiled = compiler::compile_program(program, &mut memory, &mut runtime)?;
+    // arguments are placed after strings, followed by `argv`
+    let pointer_size = program.target.pointer_size.in_bytes() as usize;
+    let mut used = runtime.stack_pointer as usize;
+    let mut arg_addresses = Vec::new();
+    for arg in args {
+        if used + arg.len() + 1 > HEAP_START as usize {
+            return Err(Error::StackOverflow);
+        }
+        arg_addresses.push(used as u64);
+        memory[used..used + arg.len()].copy_from_slice(arg.as_bytes());
+        used += arg.len() + 1;
+    }
+    let argv = used;
+    if used + (args.len() + 1) * pointer_size > HEAP_START as usize {
+        return Err(Error::StackOverflow);
+    }
+    for &address in arg_addresses.iter().chain(Some(&0)) {
+        memory[used..used + pointer_size].copy_from_slice(&address.to_le_bytes()[..pointer_size]);
+        used += pointer_size;
+    }
+    runtime.stack_pointer = used as u64;
+    runtime.started = Instant::now();
+    let exit_code = unsafe { compiled.run_main(args.len() as u32, argv as u32) };
+    match runtime.error {
+        NO_ERROR => Ok(exit_code),
+        ERROR_BAD_DEREF => Err(Error::BadDeref),
+        ERROR_DIVISION_BY_ZERO => Err(Error::DivisionByZero),
+        ERROR_UNREACHABLE => Err(Error::ExecutedUnreachable),
+        ERROR_STACK_OVERFLOW => Err(Error::StackOverflow),
+        ERROR_BAD_FREE => Err(Error::BadFree),
+        ERROR_INDEX_OUT_OF_BOUNDS => {
+            let (index, len) = runtime.bounds_failure;
+            Err(Error::IndexOutOfBounds(index, len))
+        }
+        ERROR_OVERFLOW => Err(Error::ArithmeticOverflow(runtime.overflow.clone())),
+        ERROR_ASSERTION => Err(Error::AssertionFailed(runtime.assertion.clone())),
+        ERROR_PANIC => Err(Error::Panicked(runtime.assertion.clone())),
+        ERROR_IO => Err(Error::Io(runtime.io_error.take().expect("io error was not saved"))),
+        code => panic!("unknown error code: {}", code),
+    }
+}
@@ -119,31 +119,44 @@ fn publish_diagnostics<R, W>(source: &str, doc: Url, transport: &mut Transport<R
     transport.send_message(&string).expect("failed to write message");
 }
 
+struct LspSink<'a> {
+    source: &'a str,
+    diagnostics: Vec<lst::Diagnostic>,
+}
+
+impl<'a> plank_errors::DiagnosticSink for LspSink<'a> {
+    fn emit(&mut self, diagnostic: &plank_errors::reporter::Diagnostic) {
+        if let Some(d) = convert_diagnostic(self.source, diagnostic) {
+            self.diagnostics.push(d);
+        }
+    }
+}
+
 fn make_diagnostics(source: &str) -> Vec<lst::Diagnostic> {
     let reporter = plank_errors::Reporter::new();
     let tokens = plank_syntax::lex(source, reporter.clone());
     let ast = plank_syntax::parse(tokens, reporter.clone());
     let _ = plank_frontend::compile(&ast, reporter.clone());
-    reporter
-        .get_diagnostics()
-        .into_iter()
-        .filter_map(convert_diagnostic)
-        .collect()
+    let mut sink = LspSink {
+        source,
+        diagnostics: Vec::new(),
+    };
+    reporter.emit_to(&mut sink);
+    sink.diagnostics
 }
 
-fn convert_diagnostic(d: plank_errors::reporter::Diagnostic) -> Option<lst::Diagnostic> {
-    fn convert_pos(pos: plank_errors::position::Position) -> lst::Position {
-        lst::Position {
-            line: u64::from(pos.line),
-            character: u64::from(pos.column),
-        }
-    }
-    fn convert_range(range: plank_errors::position::Span) -> lst::Range {
-        lst::Range {
-            start: convert_pos(range.start),
-            end: convert_pos(range.end),
-        }
-    }
+fn convert_diagnostic(
+    source: &str,
+    d: &plank_errors::reporter::Diagnostic,
+) -> Option<lst::Diagnostic> {
+    let convert_pos = |pos: plank_errors::position::Position| lst::Position {
+        line: u64::from(pos.line),
+        character: u64::from(pos.utf16_column(source)),
+    };
+    let convert_range = |range: plank_errors::position::Span| lst::Range {
+        start: convert_pos(range.start),
+        end: convert_pos(range.end),
+    };
     let primary_span = match d.primary_span {
         Some(span) => span,
         None => return None,
@@ -151,12 +164,13 @@ fn convert_diagnostic(d: plank_errors::reporter::Diagnostic) -> Option<lst::Diag
     let severity = match d.severity {
         plank_errors::reporter::Severity::Error => lst::DiagnosticSeverity::Error,
         plank_errors::reporter::Severity::Warning => lst::DiagnosticSeverity::Warning,
+        plank_errors::reporter::Severity::Note => lst::DiagnosticSeverity::Information,
     };
     Some(lst::Diagnostic {
         range: convert_range(primary_span),
         severity: Some(severity),
-        code: None,
+        code: d.code.map(|code| lst::NumberOrString::String(code.into())),
         source: Some("plank".into()),
-        message: d.message,
+        message: d.message.clone(),
     })
 }
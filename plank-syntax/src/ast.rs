@@ -1,4 +1,5 @@
 use position::{Span, Spanned};
+use tokens::Trivia;
 
 
 #[derive(PartialEq, PartialOrd, Eq, Ord, Hash, Debug, Clone)]
@@ -43,7 +44,7 @@ pub enum Signedness {
     Unsigned,
 }
 
-#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Copy, Clone)]
 pub enum Size {
     Bit8,
     Bit16,
@@ -56,9 +57,12 @@ pub enum Expr {
     Unary(Spanned<UnaryOp>, Box<Spanned<Expr>>),
     Call(Box<Spanned<Expr>>, Vec<CallParam>),
     Field(Box<Spanned<Expr>>, Spanned<Ident>),
+    Index(Box<Spanned<Expr>>, Box<Spanned<Expr>>),
+    Slice(Box<Spanned<Expr>>, Box<Spanned<Expr>>, Box<Spanned<Expr>>),
     Name(Spanned<Ident>, Vec<Spanned<Type>>),
     Literal(Literal),
     Cast(Box<Spanned<Expr>>, Spanned<Type>),
+    Lambda(Vec<FnParam>, Box<Spanned<Type>>, Box<Spanned<Statement>>),
     Error,
 }
 
@@ -115,11 +119,39 @@ pub enum Statement {
     Continue,
     Return(Spanned<Expr>),
     Let(Mutability, Spanned<Ident>, Option<Spanned<Type>>, Option<Spanned<Expr>>),
+    LetPattern(StructPattern, Spanned<Expr>),
+    Match(Spanned<Expr>, Vec<MatchArm>),
     Block(Vec<Spanned<Statement>>),
     Expr(Spanned<Expr>),
     Error,
 }
 
+#[derive(Debug, Clone)]
+pub struct StructPattern {
+    pub name: Spanned<Ident>,
+    pub fields: Vec<FieldPattern>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Spanned<Pattern>,
+    pub body: Spanned<Statement>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Wildcard,
+    /// A literal, or a negated number literal.
+    Value(Spanned<Expr>),
+}
+
+#[derive(Debug, Clone)]
+pub struct FieldPattern {
+    pub field: Spanned<Ident>,
+    pub mutability: Mutability,
+    pub binding: Spanned<Ident>,
+}
+
 #[derive(Debug, Clone)]
 pub enum Type {
     Wildcard,
@@ -133,6 +165,7 @@ pub enum Type {
     Unit,
     Concrete(Spanned<Ident>, Vec<Spanned<Type>>),
     Pointer(Mutability, Box<Spanned<Type>>),
+    Slice(Mutability, Box<Spanned<Type>>),
     Function(Vec<Spanned<Type>>, Box<Spanned<Type>>),
     Error,
 }
@@ -141,6 +174,8 @@ pub enum Type {
 pub struct ItemName {
     pub name: Spanned<Ident>,
     pub type_params: Vec<Spanned<Ident>>,
+    /// Bound of each type parameter, like `Numeric` in `T: Numeric`.
+    pub bounds: Vec<Option<Spanned<Ident>>>,
 }
 
 #[derive(PartialEq, PartialOrd, Eq, Ord, Hash, Debug, Copy, Clone)]
@@ -154,12 +189,23 @@ pub struct FnParam {
     pub mutability: Mutability,
     pub name: Spanned<Ident>,
     pub typ: Spanned<Type>,
+    pub default: Option<Spanned<Expr>>,
+}
+
+/// An attribute on an item, like `#[allow(dead_code)]`.
+#[derive(Debug, Clone)]
+pub struct Attribute {
+    pub name: Spanned<Ident>,
+    pub args: Vec<Spanned<Ident>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Function {
+    pub attributes: Vec<Spanned<Attribute>>,
     pub complete_span: Span,
     pub fn_type: FunctionType,
+    /// Calling convention named after `extern`, like `"C"`.
+    pub calling_convention: Option<Spanned<String>>,
     pub name: ItemName,
     pub params: Vec<FnParam>,
     pub return_type: Spanned<Type>,
@@ -172,9 +218,18 @@ pub struct Field {
     pub typ: Spanned<Type>,
 }
 
+#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
+pub enum StructKind {
+    Struct,
+    /// All fields of a union start at the same address.
+    Union,
+}
+
 #[derive(Debug, Clone)]
 pub struct Struct {
+    pub attributes: Vec<Spanned<Attribute>>,
     pub complete_span: Span,
+    pub kind: StructKind,
     pub name: ItemName,
     pub fields: Vec<Field>,
 }
@@ -185,4 +240,10 @@ pub struct Program {
     pub functions: Vec<Function>,
     pub possible_structs: Vec<Ident>,
     pub possible_functions: Vec<Ident>,
+    /// Paths of files imported with `import "path";`, relative
+    /// to the file that imports them.
+    pub imports: Vec<Spanned<String>>,
+    /// Comments and whitespace, in source order. Empty unless the
+    /// program was parsed with `parse_with_trivia`.
+    pub trivia: Vec<Spanned<Trivia>>,
 }
@@ -0,0 +1,704 @@
+//! Formatting of source code in the canonical style.
+
+use plank_errors::Reporter;
+use ast::*;
+use lexer::lex_with_trivia;
+use parser::parse_with_trivia;
+use position::{Position, Span, Spanned};
+use tokens::Trivia;
+
+
+/// Format source code in the canonical style: four spaces of indentation,
+/// one statement, field or match arm per line, spaces around binary
+/// operators and only the parentheses that are needed. Comments are kept,
+/// and so are single blank lines between statements and items. Long
+/// lines are not wrapped. Returns `None` if the source has syntax errors,
+/// which are reported to `reporter`.
+///
+/// # Examples
+///
+/// ```rust
+/// # extern crate plank_errors;
+/// # extern crate plank_syntax;
+/// let source = "fn main()->i32{return (1+2)*3; // nine\n}";
+/// let formatted = plank_syntax::format_source(source, plank_errors::Reporter::new());
+/// let expected = "fn main() -> i32 {\n    return (1 + 2) * 3; // nine\n}\n";
+/// assert_eq!(formatted.unwrap(), expected);
+/// ```
+///
+/// Comments from inside of signatures and expressions are moved after
+/// the code around them, so that line comments never hide code that
+/// followed them. Formatting the result again changes nothing.
+///
+/// ```rust
+/// # extern crate plank_errors;
+/// # extern crate plank_syntax;
+/// use plank_syntax::format_source;
+///
+/// let source = "\
+/// fn f(a: i32, /* between */ b: i32) -> i32 {
+///     let c = f(1, // after argument
+///         2);
+///     if c == 0 {
+///         // in empty block
+///     }
+///     return a + /* inside */ b; }
+/// ";
+/// let expected = "\
+/// fn f(a: i32, b: i32) -> i32 { /* between */
+///     let c = f(1, 2);
+///     // after argument
+///     if c == 0 {
+///         // in empty block
+///     }
+///     return a + b; /* inside */
+/// }
+/// ";
+/// let formatted = format_source(source, plank_errors::Reporter::new()).unwrap();
+/// assert_eq!(formatted, expected);
+/// assert_eq!(format_source(&formatted, plank_errors::Reporter::new()).unwrap(), expected);
+/// ```
+pub fn format_source(source: &str, reporter: Reporter) -> Option<String> {
+    let (tokens, trivia) = lex_with_trivia(source, reporter.clone());
+    let program = parse_with_trivia(tokens, trivia, reporter.clone());
+    if reporter.has_errors() {
+        return None;
+    }
+    let comments = program
+        .trivia
+        .iter()
+        .filter_map(|trivia| match **trivia {
+            Trivia::LineComment(ref text) | Trivia::BlockComment(ref text) => {
+                Some((text.replace("\r\n", "\n"), Spanned::span(trivia)))
+            }
+            Trivia::Whitespace(_) => None,
+        })
+        .collect();
+    let mut formatter = Formatter {
+        source,
+        output: String::new(),
+        indent: 0,
+        comments,
+        next_comment: 0,
+        last_line: None,
+        at_block_start: true,
+    };
+    formatter.program(&program);
+    Some(formatter.output)
+}
+
+/// How tightly expressions bind, from the loosest.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone)]
+enum Precedence {
+    Lowest,
+    Assignment,
+    Or,
+    And,
+    Equation,
+    Comparision,
+    Addition,
+    Multiplication,
+    Cast,
+    Prefix,
+    CallOrField,
+    Atom,
+}
+
+impl Precedence {
+    fn of_operator(op: BinaryOp) -> Precedence {
+        match op {
+            BinaryOp::Assign => Precedence::Assignment,
+            BinaryOp::Or => Precedence::Or,
+            BinaryOp::And => Precedence::And,
+            BinaryOp::Equal | BinaryOp::NotEqual => Precedence::Equation,
+            BinaryOp::Less
+            | BinaryOp::LessEqual
+            | BinaryOp::Greater
+            | BinaryOp::GreaterEqual => Precedence::Comparision,
+            BinaryOp::Add | BinaryOp::Subtract => Precedence::Addition,
+            BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::Modulo => {
+                Precedence::Multiplication
+            }
+        }
+    }
+
+    fn of_expr(expr: &Expr) -> Precedence {
+        match *expr {
+            Expr::Binary(_, op, _) => Precedence::of_operator(*op),
+            Expr::Cast(..) => Precedence::Cast,
+            // calling a lambda right away needs parentheses to be readable
+            Expr::Unary(..) | Expr::Lambda(..) => Precedence::Prefix,
+            Expr::Call(..) | Expr::Field(..) | Expr::Index(..) | Expr::Slice(..) => {
+                Precedence::CallOrField
+            }
+            Expr::Name(..) | Expr::Literal(_) | Expr::Error => Precedence::Atom,
+        }
+    }
+
+    fn one_higher(self) -> Precedence {
+        use self::Precedence::*;
+        match self {
+            Lowest => Assignment,
+            Assignment => Or,
+            Or => And,
+            And => Equation,
+            Equation => Comparision,
+            Comparision => Addition,
+            Addition => Multiplication,
+            Multiplication => Cast,
+            Cast => Prefix,
+            Prefix => CallOrField,
+            CallOrField | Atom => Atom,
+        }
+    }
+}
+
+enum Item<'a> {
+    Import(&'a Spanned<String>),
+    Struct(&'a Struct),
+    Function(&'a Function),
+}
+
+impl<'a> Item<'a> {
+    fn span(&self) -> Span {
+        let (attributes, span) = match *self {
+            Item::Import(path) => return Spanned::span(path),
+            Item::Struct(s) => (&s.attributes, s.complete_span),
+            Item::Function(f) => (&f.attributes, f.complete_span),
+        };
+        match attributes.first() {
+            Some(attribute) => Spanned::span(attribute).merge(span),
+            None => span,
+        }
+    }
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    output: String,
+    indent: usize,
+    /// Text and span of every comment, in source order.
+    comments: Vec<(String, Span)>,
+    /// First comment that was not printed yet.
+    next_comment: usize,
+    /// Source line where the last printed code or comment ends.
+    last_line: Option<u32>,
+    /// Whether nothing was printed in the current block yet, so
+    /// that blocks don't start with a blank line.
+    at_block_start: bool,
+}
+
+impl<'a> Formatter<'a> {
+    /// Start a line, keeping one blank line before it if the source has
+    /// blank lines between `line` and what was printed before.
+    fn new_line(&mut self, line: u32) {
+        let blank = match self.last_line {
+            Some(last) => !self.at_block_start && line > last + 1,
+            None => false,
+        };
+        if !self.output.is_empty() {
+            self.output.push('\n');
+            if blank {
+                self.output.push('\n');
+            }
+        }
+        for _ in 0..self.indent {
+            self.output.push_str("    ");
+        }
+        self.at_block_start = false;
+    }
+
+    /// Print comments that come before `position`. A comment that starts
+    /// on the line where printed code ends is put after that code, and
+    /// other comments are put on lines of their own.
+    fn comments_before(&mut self, position: Position) {
+        while let Some(&(_, span)) = self.comments.get(self.next_comment) {
+            if span.start.offset >= position.offset {
+                break;
+            }
+            if self.last_line == Some(span.start.line) {
+                self.output.push(' ');
+            } else {
+                self.new_line(span.start.line);
+            }
+            self.output.push_str(&self.comments[self.next_comment].0);
+            // comments from inside of expressions come after code
+            // that ends below them
+            self.last_line = Some(self.last_line.map_or(span.end.line, |l| l.max(span.end.line)));
+            self.next_comment += 1;
+        }
+    }
+
+    /// Start a line for code that spans `span` in the source.
+    fn begin(&mut self, span: Span) {
+        self.comments_before(span.start);
+        self.new_line(span.start.line);
+    }
+
+    /// Open a block with `{` that is on `line` in the source.
+    fn open(&mut self, line: u32) {
+        self.output.push('{');
+        self.last_line = Some(line);
+        self.indent += 1;
+        self.at_block_start = true;
+    }
+
+    /// Close a block with `}` at `end` in the source.
+    fn close(&mut self, end: Position) {
+        let first_comment = self.next_comment;
+        self.comments_before(end);
+        self.indent -= 1;
+        if !self.at_block_start || self.next_comment > first_comment {
+            self.output.push('\n');
+            for _ in 0..self.indent {
+                self.output.push_str("    ");
+            }
+        }
+        self.output.push('}');
+        self.last_line = Some(end.line);
+        self.at_block_start = false;
+    }
+
+    fn text(&self, span: Span) -> &'a str {
+        &self.source[span.byte_range()]
+    }
+
+    fn program(&mut self, program: &Program) {
+        let mut items = program
+            .imports
+            .iter()
+            .map(Item::Import)
+            .chain(program.structs.iter().map(Item::Struct))
+            .chain(program.functions.iter().map(Item::Function))
+            .collect::<Vec<_>>();
+        items.sort_by_key(|item| item.span().start);
+        for item in items {
+            let span = item.span();
+            self.begin(span);
+            match item {
+                Item::Import(path) => {
+                    let text = self.text(Spanned::span(path));
+                    self.output.push_str("import ");
+                    self.output.push_str(text);
+                    self.output.push(';');
+                }
+                Item::Struct(s) => self.structure(s),
+                Item::Function(f) => self.function(f),
+            }
+            self.last_line = Some(span.end.line);
+        }
+        let end = Position::with_offset(u32::MAX, 0, u32::MAX);
+        self.comments_before(end);
+        if !self.output.is_empty() {
+            self.output.push('\n');
+        }
+    }
+
+    /// Print attributes of an item on lines of their own, and start
+    /// the line of the item, which starts at `item` in the source.
+    fn attributes(&mut self, attributes: &[Spanned<Attribute>], item: Span) {
+        if attributes.is_empty() {
+            return;
+        }
+        for (index, attribute) in attributes.iter().enumerate() {
+            if index > 0 {
+                self.begin(Spanned::span(attribute));
+            }
+            self.output.push_str("#[");
+            self.output.push_str(&attribute.name.0);
+            if !attribute.args.is_empty() {
+                self.output.push('(');
+                for (index, arg) in attribute.args.iter().enumerate() {
+                    if index > 0 {
+                        self.output.push_str(", ");
+                    }
+                    self.output.push_str(&arg.0);
+                }
+                self.output.push(')');
+            }
+            self.output.push(']');
+            self.last_line = Some(Spanned::span(attribute).end.line);
+        }
+        self.begin(item);
+    }
+
+    fn item_name(&mut self, name: &ItemName) {
+        self.output.push_str(&name.name.0);
+        if name.type_params.is_empty() {
+            return;
+        }
+        self.output.push('<');
+        for (index, (param, bound)) in name.type_params.iter().zip(&name.bounds).enumerate() {
+            if index > 0 {
+                self.output.push_str(", ");
+            }
+            self.output.push_str(&param.0);
+            if let Some(ref bound) = *bound {
+                self.output.push_str(": ");
+                self.output.push_str(&bound.0);
+            }
+        }
+        self.output.push('>');
+    }
+
+    fn structure(&mut self, s: &Struct) {
+        self.attributes(&s.attributes, s.complete_span);
+        self.output.push_str(match s.kind {
+            StructKind::Struct => "struct ",
+            StructKind::Union => "union ",
+        });
+        self.item_name(&s.name);
+        self.output.push(' ');
+        self.open(Spanned::span(&s.name.name).end.line);
+        for field in &s.fields {
+            let span = Spanned::span(&field.name).merge(Spanned::span(&field.typ));
+            self.begin(span);
+            self.output.push_str(&field.name.0);
+            self.output.push_str(": ");
+            self.typ(&field.typ);
+            self.output.push(',');
+            self.last_line = Some(span.end.line);
+        }
+        self.close(s.complete_span.end);
+    }
+
+    fn function(&mut self, f: &Function) {
+        self.attributes(&f.attributes, f.complete_span);
+        if let FunctionType::Extern = f.fn_type {
+            self.output.push_str("extern ");
+            if let Some(ref convention) = f.calling_convention {
+                self.output.push_str(self.text(Spanned::span(convention)));
+                self.output.push(' ');
+            }
+        }
+        self.output.push_str("fn ");
+        self.item_name(&f.name);
+        self.params(&f.params);
+        self.return_type(&f.return_type);
+        match f.body {
+            Some(ref body) => {
+                self.output.push(' ');
+                self.statement(body);
+            }
+            None => self.output.push(';'),
+        }
+    }
+
+    fn params(&mut self, params: &[FnParam]) {
+        self.output.push('(');
+        for (index, param) in params.iter().enumerate() {
+            if index > 0 {
+                self.output.push_str(", ");
+            }
+            if let Mutability::Mut = param.mutability {
+                self.output.push_str("mut ");
+            }
+            self.output.push_str(&param.name.0);
+            self.output.push_str(": ");
+            self.typ(&param.typ);
+            if let Some(ref default) = param.default {
+                self.output.push_str(" = ");
+                self.expr(default, Precedence::Lowest);
+            }
+        }
+        self.output.push(')');
+    }
+
+    /// Return type of a function, which is left out if it is `unit`.
+    fn return_type(&mut self, typ: &Type) {
+        if let Type::Unit = *typ {
+            return;
+        }
+        self.output.push_str(" -> ");
+        self.typ(typ);
+    }
+
+    fn types(&mut self, types: &[Spanned<Type>]) {
+        for (index, typ) in types.iter().enumerate() {
+            if index > 0 {
+                self.output.push_str(", ");
+            }
+            self.typ(typ);
+        }
+    }
+
+    fn typ(&mut self, typ: &Type) {
+        let name = match *typ {
+            Type::Wildcard => "_",
+            Type::I8 => "i8",
+            Type::U8 => "u8",
+            Type::I16 => "i16",
+            Type::U16 => "u16",
+            Type::I32 => "i32",
+            Type::U32 => "u32",
+            Type::Bool => "bool",
+            Type::Unit => "unit",
+            Type::Error => "?",
+            Type::Concrete(ref name, ref params) => {
+                self.output.push_str(&name.0);
+                if !params.is_empty() {
+                    self.output.push('<');
+                    self.types(params);
+                    self.output.push('>');
+                }
+                return;
+            }
+            Type::Pointer(mutability, ref to) | Type::Slice(mutability, ref to) => {
+                self.output.push_str(match *typ {
+                    Type::Pointer(..) => "*",
+                    _ => "[]",
+                });
+                if let Mutability::Mut = mutability {
+                    self.output.push_str("mut ");
+                }
+                self.typ(to);
+                return;
+            }
+            Type::Function(ref params, ref out) => {
+                self.output.push_str("fn(");
+                self.types(params);
+                self.output.push(')');
+                self.return_type(out);
+                return;
+            }
+        };
+        self.output.push_str(name);
+    }
+
+    fn block(&mut self, statements: &[Spanned<Statement>], span: Span) {
+        self.open(span.start.line);
+        for statement in statements {
+            let span = Spanned::span(statement);
+            self.begin(span);
+            self.statement(statement);
+            self.last_line = Some(span.end.line);
+        }
+        self.close(span.end);
+    }
+
+    fn statement(&mut self, statement: &Spanned<Statement>) {
+        match **statement {
+            Statement::Block(ref statements) => {
+                self.block(statements, Spanned::span(statement));
+            }
+            Statement::If(ref cond, ref then, ref else_) => {
+                self.output.push_str("if ");
+                self.expr(cond, Precedence::Lowest);
+                self.output.push(' ');
+                self.statement(then);
+                if let Some(ref else_) = *else_ {
+                    self.output.push_str(" else ");
+                    self.statement(else_);
+                }
+            }
+            Statement::Loop(ref body) => {
+                self.output.push_str("loop ");
+                self.statement(body);
+            }
+            Statement::While(ref cond, ref body) => {
+                self.output.push_str("while ");
+                self.expr(cond, Precedence::Lowest);
+                self.output.push(' ');
+                self.statement(body);
+            }
+            Statement::Break => self.output.push_str("break;"),
+            Statement::Continue => self.output.push_str("continue;"),
+            Statement::Return(ref value) => {
+                // `return;` returns a unit spanning the keyword
+                if Spanned::span(value).start == Spanned::span(statement).start {
+                    self.output.push_str("return;");
+                } else {
+                    self.output.push_str("return ");
+                    self.expr(value, Precedence::Lowest);
+                    self.output.push(';');
+                }
+            }
+            Statement::Let(mutability, ref name, ref typ, ref value) => {
+                self.output.push_str(match mutability {
+                    Mutability::Const => "let ",
+                    Mutability::Mut => "let mut ",
+                });
+                self.output.push_str(&name.0);
+                if let Some(ref typ) = *typ {
+                    self.output.push_str(": ");
+                    self.typ(typ);
+                }
+                if let Some(ref value) = *value {
+                    self.output.push_str(" = ");
+                    self.expr(value, Precedence::Lowest);
+                }
+                self.output.push(';');
+            }
+            Statement::LetPattern(ref pattern, ref value) => {
+                self.output.push_str("let ");
+                self.output.push_str(&pattern.name.0);
+                self.output.push_str(" {");
+                for (index, field) in pattern.fields.iter().enumerate() {
+                    self.output.push_str(if index > 0 { ", " } else { " " });
+                    if field.field.0 == field.binding.0 {
+                        if let Mutability::Mut = field.mutability {
+                            self.output.push_str("mut ");
+                        }
+                        self.output.push_str(&field.field.0);
+                    } else {
+                        self.output.push_str(&field.field.0);
+                        self.output.push_str(": ");
+                        if let Mutability::Mut = field.mutability {
+                            self.output.push_str("mut ");
+                        }
+                        self.output.push_str(&field.binding.0);
+                    }
+                }
+                self.output.push_str(if pattern.fields.is_empty() { "} = " } else { " } = " });
+                self.expr(value, Precedence::Lowest);
+                self.output.push(';');
+            }
+            Statement::Match(ref value, ref arms) => {
+                self.output.push_str("match ");
+                self.expr(value, Precedence::Lowest);
+                self.output.push(' ');
+                self.open(Spanned::span(value).end.line);
+                for arm in arms {
+                    let span = Spanned::span(&arm.pattern).merge(Spanned::span(&arm.body));
+                    self.begin(span);
+                    match *arm.pattern {
+                        Pattern::Wildcard => self.output.push('_'),
+                        Pattern::Value(ref value) => self.expr(value, Precedence::Lowest),
+                    }
+                    self.output.push_str(" => ");
+                    self.statement(&arm.body);
+                    self.last_line = Some(span.end.line);
+                }
+                self.close(Spanned::span(statement).end);
+            }
+            Statement::Expr(ref expr) => {
+                self.expr(expr, Precedence::Lowest);
+                self.output.push(';');
+            }
+            Statement::Error => self.output.push('?'),
+        }
+    }
+
+    /// Print an expression, in parentheses if it binds
+    /// less tightly than `precedence`.
+    fn expr(&mut self, expr: &Spanned<Expr>, precedence: Precedence) {
+        let parenthesized = Precedence::of_expr(expr) < precedence;
+        if parenthesized {
+            self.output.push('(');
+        }
+        match **expr {
+            Expr::Binary(ref lhs, op, ref rhs) => {
+                let precedence = Precedence::of_operator(*op);
+                // assignment is the only right associative operator
+                let (lhs_precedence, rhs_precedence) = match *op {
+                    BinaryOp::Assign => (precedence.one_higher(), precedence),
+                    _ => (precedence, precedence.one_higher()),
+                };
+                self.expr(lhs, lhs_precedence);
+                self.output.push_str(match *op {
+                    BinaryOp::Add => " + ",
+                    BinaryOp::Subtract => " - ",
+                    BinaryOp::Multiply => " * ",
+                    BinaryOp::Divide => " / ",
+                    BinaryOp::Modulo => " % ",
+                    BinaryOp::Less => " < ",
+                    BinaryOp::LessEqual => " <= ",
+                    BinaryOp::Greater => " > ",
+                    BinaryOp::GreaterEqual => " >= ",
+                    BinaryOp::Equal => " == ",
+                    BinaryOp::NotEqual => " != ",
+                    BinaryOp::And => " && ",
+                    BinaryOp::Or => " || ",
+                    BinaryOp::Assign => " = ",
+                });
+                self.expr(rhs, rhs_precedence);
+            }
+            Expr::Unary(op, ref value) => {
+                self.output.push_str(match *op {
+                    UnaryOp::Not => "!",
+                    UnaryOp::Minus => "-",
+                    UnaryOp::Plus => "+",
+                    UnaryOp::Deref => "*",
+                    UnaryOp::AddressOf => "&",
+                    UnaryOp::MutAddressOf => "&mut ",
+                });
+                // `& &x` would be lexed as `&&` without the space
+                if let Expr::Unary(inner, _) = ***value {
+                    let address_of = |op| op == UnaryOp::AddressOf || op == UnaryOp::MutAddressOf;
+                    if *op == UnaryOp::AddressOf && address_of(*inner) {
+                        self.output.push(' ');
+                    }
+                }
+                self.expr(value, Precedence::Prefix);
+            }
+            Expr::Call(ref callee, ref params) => {
+                self.expr(callee, Precedence::CallOrField);
+                self.output.push('(');
+                for (index, param) in params.iter().enumerate() {
+                    if index > 0 {
+                        self.output.push_str(", ");
+                    }
+                    match *param {
+                        CallParam::Named(ref name, ref value) => {
+                            self.output.push_str(&name.0);
+                            self.output.push_str(": ");
+                            self.expr(value, Precedence::Lowest);
+                        }
+                        CallParam::Unnamed(ref value) => self.expr(value, Precedence::Lowest),
+                    }
+                }
+                self.output.push(')');
+            }
+            Expr::Field(ref value, ref field) => {
+                self.expr(value, Precedence::CallOrField);
+                self.output.push('.');
+                self.output.push_str(&field.0);
+            }
+            Expr::Index(ref value, ref index) => {
+                self.expr(value, Precedence::CallOrField);
+                self.output.push('[');
+                self.expr(index, Precedence::Lowest);
+                self.output.push(']');
+            }
+            Expr::Slice(ref value, ref start, ref end) => {
+                self.expr(value, Precedence::CallOrField);
+                self.output.push('[');
+                self.expr(start, Precedence::Lowest);
+                self.output.push_str("..");
+                self.expr(end, Precedence::Lowest);
+                self.output.push(']');
+            }
+            Expr::Name(ref name, ref type_params) => {
+                self.output.push_str(&name.0);
+                if !type_params.is_empty() {
+                    self.output.push_str("::<");
+                    self.types(type_params);
+                    self.output.push('>');
+                }
+            }
+            // literals are printed as they were written, so
+            // that number bases and escapes are kept
+            Expr::Literal(Literal::Unit) => self.output.push_str("unit"),
+            Expr::Literal(_) => {
+                let text = self.text(Spanned::span(expr));
+                self.output.push_str(text);
+            }
+            Expr::Cast(ref value, ref typ) => {
+                self.expr(value, Precedence::Cast);
+                self.output.push_str(" as ");
+                self.typ(typ);
+            }
+            Expr::Lambda(ref params, ref return_type, ref body) => {
+                self.output.push_str("fn");
+                self.params(params);
+                self.return_type(return_type);
+                self.output.push(' ');
+                self.statement(body);
+            }
+            Expr::Error => self.output.push('?'),
+        }
+        if parenthesized {
+            self.output.push(')');
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Token classification for syntax highlighting.
+
+use plank_errors::Reporter;
+use lexer::lex_with_trivia;
+use position::{Span, Spanned};
+use tokens::{Keyword, Token, Trivia};
+
+
+#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
+pub enum HighlightKind {
+    Keyword,
+    Type,
+    Literal,
+    Comment,
+    Operator,
+    Punctuation,
+    Identifier,
+}
+
+/// Splits source into highlighted regions, in source order. Lexing
+/// errors are not reported, and invalid tokens and whitespace are
+/// not included in the result.
+///
+/// # Examples
+///
+/// ```rust
+/// use plank_syntax::{highlight, HighlightKind};
+///
+/// let source = "let s: str = \"a\"; // b\nf(s, 1u8) @";
+/// let kinds = highlight(source)
+///     .into_iter()
+///     .map(|(span, kind)| (&source[span.byte_range()], kind))
+///     .collect::<Vec<_>>();
+/// assert_eq!(kinds, vec![
+///     ("let", HighlightKind::Keyword),
+///     ("s", HighlightKind::Identifier),
+///     (":", HighlightKind::Punctuation),
+///     ("str", HighlightKind::Type),
+///     ("=", HighlightKind::Operator),
+///     ("\"a\"", HighlightKind::Literal),
+///     (";", HighlightKind::Punctuation),
+///     ("// b", HighlightKind::Comment),
+///     ("f", HighlightKind::Identifier),
+///     ("(", HighlightKind::Punctuation),
+///     ("s", HighlightKind::Identifier),
+///     (",", HighlightKind::Punctuation),
+///     ("1u8", HighlightKind::Literal),
+///     (")", HighlightKind::Punctuation),
+/// ]);
+/// ```
+pub fn highlight(source: &str) -> Vec<(Span, HighlightKind)> {
+    let (tokens, trivia) = lex_with_trivia(source, Reporter::new());
+    let mut result = tokens
+        .iter()
+        .filter_map(|tok| token_kind(tok).map(|kind| (Spanned::span(tok), kind)))
+        .chain(trivia.iter().filter_map(|trivia| match **trivia {
+            Trivia::LineComment(_) | Trivia::BlockComment(_) => {
+                Some((Spanned::span(trivia), HighlightKind::Comment))
+            }
+            Trivia::Whitespace(_) => None,
+        }))
+        .collect::<Vec<_>>();
+    result.sort_by_key(|&(span, _)| span.start);
+    result
+}
+
+fn token_kind(tok: &Token) -> Option<HighlightKind> {
+    Some(match *tok {
+        // `str` is not a keyword, but it names a built-in type
+        Token::Ident(ref name) if name == "str" => HighlightKind::Type,
+        Token::Ident(_) | Token::Underscore => HighlightKind::Identifier,
+        Token::Number(_) | Token::Bool(_) | Token::Char(_) | Token::Str(_) => {
+            HighlightKind::Literal
+        }
+        Token::Keyword(Keyword::Bool) |
+        Token::Keyword(Keyword::I8) |
+        Token::Keyword(Keyword::U8) |
+        Token::Keyword(Keyword::I16) |
+        Token::Keyword(Keyword::U16) |
+        Token::Keyword(Keyword::I32) |
+        Token::Keyword(Keyword::U32) |
+        Token::Keyword(Keyword::Unit) => HighlightKind::Type,
+        Token::Keyword(_) => HighlightKind::Keyword,
+        Token::LeftParen |
+        Token::RightParen |
+        Token::LeftBrace |
+        Token::RightBrace |
+        Token::LeftBracket |
+        Token::RightBracket |
+        Token::Comma |
+        Token::Colon |
+        Token::DoubleColon |
+        Token::Semicolon |
+        Token::Hash |
+        Token::Dot => HighlightKind::Punctuation,
+        Token::Error => return None,
+        _ => HighlightKind::Operator,
+    })
+}
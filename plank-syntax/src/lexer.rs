@@ -1,37 +1,68 @@
 use std::str::Chars;
+use unicode_xid::UnicodeXID;
+use plank_errors::codes;
 use plank_errors::Reporter;
 use position::{Position, Span, Spanned};
-use tokens::{Keyword, Number, Token};
+use tokens::{Keyword, Number, Token, Trivia};
 use ast::{Signedness, Size};
 
 
+const BYTE_ORDER_MARK: char = '\u{feff}';
+
 pub fn lex(source: &str, reporter: Reporter) -> Vec<Spanned<Token>> {
+    Lexer::new(source, reporter).collect()
+}
+
+/// Same as `lex`, but also returns comments and whitespace
+/// between tokens.
+pub fn lex_with_trivia(
+    source: &str,
+    reporter: Reporter,
+) -> (Vec<Spanned<Token>>, Vec<Spanned<Trivia>>) {
     let mut lexer = Lexer::new(source, reporter);
-    let mut tokens = Vec::new();
-    while let Some(tok) = lexer.next_token() {
-        tokens.push(tok);
-    }
-    tokens
+    lexer.trivia = Some(Vec::new());
+    let tokens = lexer.by_ref().collect();
+    (tokens, lexer.trivia.unwrap_or_default())
 }
 
-struct Lexer<'a> {
+/// Lexer that produces tokens on demand. Can be passed directly to
+/// `parse` to lex and parse the source in one pass.
+pub struct Lexer<'a> {
+    source: &'a str,
     chars: Chars<'a>,
     next_char: Option<char>,
     current_pos: Position,
+    /// Byte offset of the start of `source`.
+    start_offset: u32,
     reporter: Reporter,
-    previous_error: bool,
+    trivia: Option<Vec<Spanned<Trivia>>>,
 }
 
 impl<'a> Lexer<'a> {
-    fn new(source: &'a str, reporter: Reporter) -> Self {
+    pub fn new(source: &'a str, reporter: Reporter) -> Self {
+        Lexer::starting_at(source, Position::new(0, 0), reporter)
+    }
+
+    /// Lexer for source that starts at given position of a larger
+    /// text, like a file that is compiled together with other files.
+    /// Tokens and errors point into that text.
+    pub fn starting_at(source: &'a str, start: Position, reporter: Reporter) -> Self {
         let mut chars = source.chars();
-        let next_char = chars.next();
+        let mut next_char = chars.next();
+        let mut current_pos = start;
+        if next_char == Some(BYTE_ORDER_MARK) {
+            // BOM does not take up a column
+            next_char = chars.next();
+            current_pos.offset += BYTE_ORDER_MARK.len_utf8() as u32;
+        }
         Lexer {
+            source,
             chars,
             next_char,
-            current_pos: Position::new(0, 0),
+            current_pos,
+            start_offset: start.offset,
             reporter,
-            previous_error: false,
+            trivia: None,
         }
     }
 
@@ -44,15 +75,38 @@ impl<'a> Lexer<'a> {
             Some('\n') => {
                 self.current_pos.line += 1;
                 self.current_pos.column = 0;
+                self.current_pos.offset += 1;
+            }
+            Some('\r') if self.chars.as_str().starts_with('\n') => {
+                // `\r\n` is a single line break, so `\r`
+                // does not take up a column
+                self.current_pos.offset += 1;
             }
-            Some(_) => {
+            Some(ch) => {
                 self.current_pos.column += 1;
+                self.current_pos.offset += ch.len_utf8() as u32;
             }
             None => {}
         }
         self.next_char = self.chars.next();
     }
 
+    fn add_trivia<F>(&mut self, start: Position, f: F)
+    where
+        F: FnOnce(String) -> Trivia,
+    {
+        if self.trivia.is_none() {
+            return;
+        }
+        let span = start.span_to(self.current_pos);
+        let range = span.byte_range();
+        let start = self.start_offset as usize;
+        let text = self.source[range.start - start..range.end - start].to_string();
+        if let Some(ref mut trivia) = self.trivia {
+            trivia.push(Spanned::new(f(text), span));
+        }
+    }
+
     fn check(&mut self, ch: char) -> bool {
         if self.peek() == Some(ch) {
             self.advance();
@@ -91,6 +145,7 @@ impl<'a> Lexer<'a> {
             let span = start.span_to(self.current_pos.forward(1));
             self.reporter
                 .error(format!("unknown token: `{}`", first), span)
+                .code(codes::UNKNOWN_CHAR)
                 .span_note(format!("maybe you wanted `{}{}`?", first, ch), span)
                 .build();
             Token::Error
@@ -107,11 +162,35 @@ impl<'a> Lexer<'a> {
         Spanned::new(tok, span)
     }
 
+    fn lex_equals(&mut self) -> Spanned<Token> {
+        let start = self.current_pos;
+        self.advance();
+        let tok = if self.check('=') {
+            Token::Equal
+        } else if self.check('>') {
+            Token::FatArrow
+        } else {
+            Token::Assign
+        };
+        let span = start.span_to(self.current_pos);
+        Spanned::new(tok, span)
+    }
+
+    fn skip_whitespace(&mut self) {
+        let start = self.current_pos;
+        while let Some(' ') | Some('\t') | Some('\r') | Some('\n') = self.peek() {
+            self.advance();
+        }
+        self.add_trivia(start, Trivia::Whitespace);
+    }
+
     fn skip_line_comment(&mut self) {
+        // newline is left for `skip_whitespace`
         loop {
-            match self.consume() {
+            match self.peek() {
                 Some('\n') | None => break,
-                _ => {}
+                Some('\r') if self.chars.as_str().starts_with('\n') => break,
+                _ => self.advance(),
             }
         }
     }
@@ -138,6 +217,7 @@ impl<'a> Lexer<'a> {
                 None => {
                     self.reporter
                         .error("unterminated block comment", opener_span)
+                        .code(codes::UNTERMINATED_COMMENT)
                         .span(opener_span)
                         .build();
                     break;
@@ -152,9 +232,11 @@ impl<'a> Lexer<'a> {
         self.advance();
         if self.check('/') {
             self.skip_line_comment();
+            self.add_trivia(start, Trivia::LineComment);
             None
         } else if self.check('*') {
             self.skip_block_comment();
+            self.add_trivia(start, Trivia::BlockComment);
             None
         } else {
             let span = start.span_to(self.current_pos);
@@ -167,7 +249,7 @@ impl<'a> Lexer<'a> {
         let start = self.current_pos;
         loop {
             match self.peek() {
-                Some(ch) if ch.is_alphanumeric() => {
+                Some(ch) if ch.is_alphanumeric() || ch == '_' => {
                     string.push(ch);
                     self.advance();
                 }
@@ -179,7 +261,12 @@ impl<'a> Lexer<'a> {
             Ok(num) => Token::Number(num),
             Err(err) => {
                 let msg = err.to_string();
-                self.reporter.error(msg, span).span(span).build();
+                self
+                    .reporter
+                    .error(msg, span)
+                    .code(codes::INVALID_NUMBER)
+                    .span(span)
+                    .build();
                 Token::Error
             }
         };
@@ -237,6 +324,7 @@ impl<'a> Lexer<'a> {
                                         self.current_pos.span_to(self.current_pos.forward(1));
                                     self.reporter
                                         .error("`x` should be followed by two hex digits", span)
+                                        .code(codes::INVALID_ESCAPE)
                                         .span(span)
                                         .build();
                                 }
@@ -246,16 +334,24 @@ impl<'a> Lexer<'a> {
                             let span = escape_start.span_to(self.current_pos);
                             self.reporter
                                 .error("invalid escape sequence", span)
+                                .code(codes::INVALID_ESCAPE)
                                 .span(span)
                                 .build();
                         }
                     }
                 }
                 Some('\n') | Some('\r') | None => {
+                    // literal ends at the end of line, so that
+                    // the following lines are lexed normally
                     let span = start_pos.span_to(self.current_pos);
+                    let quote_span = start_pos.span_to(start_pos.forward(1));
+                    let end_span = self.current_pos.span_to(self.current_pos.forward(1));
+                    let what = if closing == '"' { "string" } else { "char literal" };
                     self.reporter
-                        .error("unterminated string", span)
-                        .span(span)
+                        .error(format!("unterminated {}", what), quote_span)
+                        .code(codes::UNTERMINATED_LITERAL)
+                        .span_note(format!("{} starts here", what), quote_span)
+                        .span_note(format!("expected closing `{}`", closing), end_span)
                         .build();
                     return Spanned::new(None, span);
                 }
@@ -270,6 +366,7 @@ impl<'a> Lexer<'a> {
                         let span = start.span_to(self.current_pos);
                         self.reporter
                             .error(format!("unknown char in string (codepoint: {})", ch), span)
+                            .code(codes::INVALID_LITERAL_CHAR)
                             .span(span)
                             .build();
                     }
@@ -294,6 +391,7 @@ impl<'a> Lexer<'a> {
                         format!("char literal must have 1 char, but it has {}", value.len()),
                         span,
                     )
+                    .code(codes::CHAR_LITERAL_LENGTH)
                     .span(span)
                     .build();
                 Spanned::new(Token::Error, span)
@@ -305,94 +403,149 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    fn next_raw_token(&mut self) -> Option<Spanned<Token>> {
+    fn next_token(&mut self) -> Option<Spanned<Token>> {
         loop {
-            return Some(match self.peek() {
-                Some(' ') | Some('\t') | Some('\r') | Some('\n') => {
-                    self.advance();
+            let ch = self.peek()?;
+            return Some(match token_start(ch) {
+                Some(TokenStart::Whitespace) => {
+                    self.skip_whitespace();
                     continue;
                 }
-                Some(ch) if ch.is_digit(10) => self.lex_number(),
-                Some(ch) if is_ident_char(ch) => self.lex_name(),
-                Some('.') => self.single_char(Token::Dot),
-                Some(',') => self.single_char(Token::Comma),
-                Some(';') => self.single_char(Token::Semicolon),
-                Some('*') => self.single_char(Token::Star),
-                Some('+') => self.single_char(Token::Plus),
-                Some('%') => self.single_char(Token::Percent),
-                Some('(') => self.single_char(Token::LeftParen),
-                Some(')') => self.single_char(Token::RightParen),
-                Some('{') => self.single_char(Token::LeftBrace),
-                Some('}') => self.single_char(Token::RightBrace),
-                Some('|') => self.two_char('|', Token::Or),
-                Some('!') => self.test_second('=', Token::Not, Token::NotEqual),
-                Some('&') => self.test_second('&', Token::Ampersand, Token::And),
-                Some(':') => self.test_second(':', Token::Colon, Token::DoubleColon),
-                Some('<') => self.test_second('=', Token::Less, Token::LessEqual),
-                Some('>') => self.test_second('=', Token::Greater, Token::GreaterEqual),
-                Some('-') => self.test_second('>', Token::Minus, Token::Arrow),
-                Some('=') => self.test_second('=', Token::Assign, Token::Equal),
-                Some('/') => if let Some(tok) = self.slash_or_comment() {
+                Some(TokenStart::Number) => self.lex_number(),
+                Some(TokenStart::Name) => self.lex_name(),
+                Some(TokenStart::Single(tok)) => self.single_char(tok),
+                Some(TokenStart::Pair(second, tok)) => self.two_char(second, tok),
+                Some(TokenStart::OneOrTwo(second, if_one, if_two)) => {
+                    self.test_second(second, if_one, if_two)
+                }
+                Some(TokenStart::Equals) => self.lex_equals(),
+                Some(TokenStart::Slash) => if let Some(tok) = self.slash_or_comment() {
                     tok
                 } else {
                     continue;
                 },
-                Some('"') => self.lex_string(),
-                Some('\'') => self.lex_char(),
-                Some(ch) => {
-                    let tok = self.single_char(Token::Error);
-                    if !self.previous_error {
-                        let codepoint = ch as u32;
-                        let msg = if 32 <= codepoint && codepoint < 127 {
-                            format!("unknown char: `{}`", ch)
-                        } else {
-                            format!("unknown char (codepoint: {})", codepoint)
-                        };
-                        let span = Spanned::span(&tok);
-                        self.reporter.error(msg, span).span(span).build();
-                    }
-                    tok
-                }
-                None => return None,
+                Some(TokenStart::Str) => self.lex_string(),
+                Some(TokenStart::Char) => self.lex_char(),
+                None => self.lex_unknown(),
             });
         }
     }
 
-    fn next_token(&mut self) -> Option<Spanned<Token>> {
-        while let Some(tok) = self.next_raw_token() {
-            if Spanned::value(&tok) == &Token::Error {
-                // don't report two lexing errors in a row
-                if !self.previous_error {
-                    return Some(tok);
-                }
-            } else {
-                self.previous_error = false;
-                return Some(tok);
+    /// Lexes a run of unknown chars into a single error token, so
+    /// that pasted garbage does not produce an error for every char.
+    fn lex_unknown(&mut self) -> Spanned<Token> {
+        let start = self.current_pos;
+        let mut chars = Vec::new();
+        while let Some(ch) = self.peek() {
+            if !chars.is_empty() && token_start(ch).is_some() {
+                break;
             }
+            chars.push(ch);
+            self.advance();
         }
-        None
+        let msg = if chars.len() == 1 {
+            let codepoint = chars[0] as u32;
+            if 32 <= codepoint && codepoint < 127 {
+                format!("unknown char: `{}`", chars[0])
+            } else {
+                format!("unknown char (codepoint: {})", codepoint)
+            }
+        } else if chars.iter().any(|ch| ch.is_control()) {
+            format!("{} unknown chars", chars.len())
+        } else {
+            let string = chars.iter().collect::<String>();
+            format!("unknown chars: `{}`", string)
+        };
+        let span = start.span_to(self.current_pos);
+        self
+            .reporter
+            .error(msg, span)
+            .code(codes::UNKNOWN_CHAR)
+            .span(span)
+            .build();
+        Spanned::new(Token::Error, span)
     }
 }
 
-fn is_ident_char(ch: char) -> bool {
-    ch as u32 <= 0x7f && {
-        let byte = ch as u8;
-        (byte >= b'a' && byte <= b'z')
-        || (byte >= b'A' && byte <= b'Z')
-        || (byte >= b'0' && byte <= b'9')
-        || byte == b'_'
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Spanned<Token>;
+
+    fn next(&mut self) -> Option<Spanned<Token>> {
+        self.next_token()
     }
 }
 
+/// How `next_token` lexes a token that starts with some char.
+enum TokenStart {
+    Whitespace,
+    Number,
+    Name,
+    Single(Token),
+    /// The char must be followed by the given one.
+    Pair(char, Token),
+    /// First token, or the second one if followed by the given char.
+    OneOrTwo(char, Token, Token),
+    Equals,
+    Slash,
+    Str,
+    Char,
+}
+
+/// How to lex a token that starts with `ch`, or `None` if no token
+/// or whitespace can start with it.
+fn token_start(ch: char) -> Option<TokenStart> {
+    Some(match ch {
+        ' ' | '\t' | '\r' | '\n' => TokenStart::Whitespace,
+        ch if ch.is_digit(10) => TokenStart::Number,
+        ch if is_ident_start(ch) => TokenStart::Name,
+        '.' => TokenStart::OneOrTwo('.', Token::Dot, Token::DoubleDot),
+        ',' => TokenStart::Single(Token::Comma),
+        ';' => TokenStart::Single(Token::Semicolon),
+        '#' => TokenStart::Single(Token::Hash),
+        '*' => TokenStart::Single(Token::Star),
+        '+' => TokenStart::Single(Token::Plus),
+        '%' => TokenStart::Single(Token::Percent),
+        '(' => TokenStart::Single(Token::LeftParen),
+        ')' => TokenStart::Single(Token::RightParen),
+        '{' => TokenStart::Single(Token::LeftBrace),
+        '}' => TokenStart::Single(Token::RightBrace),
+        '[' => TokenStart::Single(Token::LeftBracket),
+        ']' => TokenStart::Single(Token::RightBracket),
+        '|' => TokenStart::Pair('|', Token::Or),
+        '!' => TokenStart::OneOrTwo('=', Token::Not, Token::NotEqual),
+        '&' => TokenStart::OneOrTwo('&', Token::Ampersand, Token::And),
+        ':' => TokenStart::OneOrTwo(':', Token::Colon, Token::DoubleColon),
+        '<' => TokenStart::OneOrTwo('=', Token::Less, Token::LessEqual),
+        '>' => TokenStart::OneOrTwo('=', Token::Greater, Token::GreaterEqual),
+        '-' => TokenStart::OneOrTwo('>', Token::Minus, Token::Arrow),
+        '=' => TokenStart::Equals,
+        '/' => TokenStart::Slash,
+        '"' => TokenStart::Str,
+        '\'' => TokenStart::Char,
+        _ => return None,
+    })
+}
+
+fn is_ident_start(ch: char) -> bool {
+    ch == '_' || UnicodeXID::is_xid_start(ch)
+}
+
+fn is_ident_char(ch: char) -> bool {
+    UnicodeXID::is_xid_continue(ch)
+}
+
 fn keyword(s: &str) -> Option<Token> {
     match s {
         "extern" => Some(Token::Keyword(Keyword::Extern)),
+        "import" => Some(Token::Keyword(Keyword::Import)),
         "fn" => Some(Token::Keyword(Keyword::Fn)),
         "struct" => Some(Token::Keyword(Keyword::Struct)),
+        "union" => Some(Token::Keyword(Keyword::Union)),
         "if" => Some(Token::Keyword(Keyword::If)),
         "else" => Some(Token::Keyword(Keyword::Else)),
         "loop" => Some(Token::Keyword(Keyword::Loop)),
         "while" => Some(Token::Keyword(Keyword::While)),
+        "match" => Some(Token::Keyword(Keyword::Match)),
         "continue" => Some(Token::Keyword(Keyword::Continue)),
         "break" => Some(Token::Keyword(Keyword::Break)),
         "let" => Some(Token::Keyword(Keyword::Let)),
@@ -418,31 +571,71 @@ enum ParseNumberError {
     BadInt,
     TooLarge,
     InvalidSuffix,
+    InvalidDigit(char, u32),
+    MissingDigits(&'static str),
+    UppercasePrefix(char),
 }
 
 impl ::std::fmt::Display for ParseNumberError {
     fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
-        let msg = match *self {
-            ParseNumberError::BadInt => "invalid int literal",
-            ParseNumberError::TooLarge => "int literal is too big",
-            ParseNumberError::InvalidSuffix => "suffix should be primitive numeric type",
-        };
-        write!(f, "{}", msg)
+        match *self {
+            ParseNumberError::BadInt => write!(f, "invalid int literal"),
+            ParseNumberError::TooLarge => write!(f, "int literal is too big"),
+            ParseNumberError::InvalidSuffix => {
+                write!(f, "suffix should be primitive numeric type")
+            }
+            ParseNumberError::InvalidDigit(ch, radix) => {
+                write!(f, "invalid digit `{}` for a base {} literal", ch, radix)
+            }
+            ParseNumberError::MissingDigits(prefix) => {
+                write!(f, "missing digits after `{}` prefix", prefix)
+            }
+            ParseNumberError::UppercasePrefix(ch) => write!(
+                f,
+                "base prefix `0{}` must be lowercase: `0{}`",
+                ch,
+                ch.to_ascii_lowercase()
+            ),
+        }
     }
 }
 
-fn parse_number(s: &str) -> Result<Number, ParseNumberError> {
-    fn is_letter(c: char) -> bool {
-        let byte = c as u8;
-        (byte >= b'a' && byte <= b'z') || (byte >= b'A' && byte <= b'Z')
+fn split_radix(s: &str) -> Result<(u32, &str), ParseNumberError> {
+    let mut chars = s.chars();
+    if chars.next() != Some('0') {
+        return Ok((10, s));
+    }
+    let (radix, prefix) = match chars.next() {
+        Some('x') => (16, "0x"),
+        Some('o') => (8, "0o"),
+        Some('b') => (2, "0b"),
+        Some(ch @ 'X') | Some(ch @ 'O') | Some(ch @ 'B') => {
+            return Err(ParseNumberError::UppercasePrefix(ch))
+        }
+        _ => return Ok((10, s)),
+    };
+    let rest = s.get(2..).unwrap();
+    let has_digits = rest
+        .trim_start_matches('_')
+        .starts_with(|c: char| c.is_digit(radix) || c.is_ascii_digit());
+    if !has_digits {
+        return Err(ParseNumberError::MissingDigits(prefix));
     }
-    match s.find(is_letter) {
+    Ok((radix, rest))
+}
+
+fn parse_number(s: &str) -> Result<Number, ParseNumberError> {
+    let (radix, s) = split_radix(s)?;
+    // hex digits are letters too, so suffix starts at
+    // first letter that is not a valid digit
+    let is_suffix_start = |c: char| c.is_ascii_alphabetic() && !c.is_digit(radix);
+    match s.find(is_suffix_start) {
         Some(index) => {
             // we have a suffix
             // parse prefix as number, parse rest as bit count
             let prefix = s.get(..index).unwrap();
             let suffix = s.get(index..).unwrap();
-            let value = parse_simple_number(prefix)?;
+            let value = parse_simple_number(prefix, radix)?;
             let typ = Some(match suffix {
                 "i8" => (Signedness::Signed, Size::Bit8),
                 "u8" => (Signedness::Unsigned, Size::Bit8),
@@ -459,7 +652,7 @@ fn parse_number(s: &str) -> Result<Number, ParseNumberError> {
         }
         None => {
             // no suffix, parse simple number
-            let value = parse_simple_number(s)?;
+            let value = parse_simple_number(s, radix)?;
             Ok(Number {
                 value,
                 typ: None,
@@ -468,11 +661,11 @@ fn parse_number(s: &str) -> Result<Number, ParseNumberError> {
     }
 }
 
-fn parse_simple_number(s: &str) -> Result<u64, ParseNumberError> {
-    if s.is_empty() {
+fn parse_simple_number(s: &str, radix: u32) -> Result<u64, ParseNumberError> {
+    if !s.contains(|c: char| c != '_') {
         return Err(ParseNumberError::BadInt);
     }
-    if s.len() > 1 && s.starts_with('0') {
+    if radix == 10 && s.len() > 1 && s.starts_with('0') {
         // we have a leading zero, and number is longer than one digit
         return Err(ParseNumberError::BadInt);
     }
@@ -481,13 +674,16 @@ fn parse_simple_number(s: &str) -> Result<u64, ParseNumberError> {
         if ch == '_' {
             continue;
         }
-        match ch.to_digit(10) {
+        match ch.to_digit(radix) {
             Some(digit) => {
                 result = result
-                    .checked_mul(10)
+                    .checked_mul(u64::from(radix))
                     .and_then(|n| n.checked_add(u64::from(digit)))
                     .ok_or(ParseNumberError::TooLarge)?;
             }
+            None if ch.is_ascii_digit() => {
+                return Err(ParseNumberError::InvalidDigit(ch, radix));
+            }
             None => {
                 return Err(ParseNumberError::BadInt);
             }
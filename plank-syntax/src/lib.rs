@@ -1,11 +1,16 @@
 extern crate plank_errors;
+extern crate unicode_xid;
 
 
 pub mod ast;
 pub mod position;
 pub mod tokens;
+mod format;
+mod highlight;
 mod lexer;
 mod parser;
 
-pub use lexer::lex;
-pub use parser::parse;
+pub use format::format_source;
+pub use highlight::{highlight, HighlightKind};
+pub use lexer::{lex, lex_with_trivia, Lexer};
+pub use parser::{parse, parse_expr, parse_statement, parse_type, parse_with_trivia};
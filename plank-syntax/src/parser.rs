@@ -1,9 +1,12 @@
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{HashMap, HashSet};
+use std::iter::Peekable;
+use plank_errors::codes;
 use plank_errors::Reporter;
-use ast::{BinaryOp, CallParam, Expr, Function, FunctionType, Ident, ItemName, Literal, Program,
-          Statement, Struct, Type, UnaryOp, Field, FnParam, Mutability};
+use ast::{Attribute, BinaryOp, CallParam, Expr, Function, FunctionType, Ident, ItemName, Literal,
+          Program, Statement, Struct, StructKind, Type, UnaryOp, Field, FnParam, Mutability,
+          StructPattern, FieldPattern, MatchArm, Pattern};
 use position::{Position, Span, Spanned};
-use tokens::{Keyword, Token, TokenKind};
+use tokens::{Keyword, Token, TokenKind, Trivia};
 
 
 macro_rules! parse_infix {
@@ -16,7 +19,103 @@ macro_rules! parse_infix {
     }}
 }
 
-pub fn parse(tokens: Vec<Spanned<Token>>, reporter: Reporter) -> Program {
+pub fn parse<I>(tokens: I, reporter: Reporter) -> Program
+where
+    I: IntoIterator<Item = Spanned<Token>>,
+{
+    new_parser(tokens, reporter).parse_program()
+}
+
+/// Same as `parse`, but keeps trivia produced by `lex_with_trivia`
+/// in the resulting program.
+pub fn parse_with_trivia<I>(tokens: I, trivia: Vec<Spanned<Trivia>>, reporter: Reporter) -> Program
+where
+    I: IntoIterator<Item = Spanned<Token>>,
+{
+    let mut program = parse(tokens, reporter);
+    program.trivia = trivia;
+    program
+}
+
+/// Parses a single expression. All tokens must be part of the
+/// expression. On error `Expr::Error` is returned.
+pub fn parse_expr<I>(tokens: I, reporter: Reporter) -> Spanned<Expr>
+where
+    I: IntoIterator<Item = Spanned<Token>>,
+{
+    let mut parser = new_parser(tokens, reporter);
+    let start = parser.peek_span();
+    let expr = match parser.parse_expr() {
+        Ok(expr) => expr,
+        Err(()) => Spanned::new(Expr::Error, parser.span_from(start)),
+    };
+    parser.expect_end();
+    expr
+}
+
+/// Parses a single type. All tokens must be part of the type.
+/// On error `Type::Error` is returned.
+pub fn parse_type<I>(tokens: I, reporter: Reporter) -> Spanned<Type>
+where
+    I: IntoIterator<Item = Spanned<Token>>,
+{
+    let mut parser = new_parser(tokens, reporter);
+    let start = parser.peek_span();
+    let typ = match parser.parse_type() {
+        Ok(typ) => typ,
+        Err(()) => Spanned::new(Type::Error, parser.span_from(start)),
+    };
+    parser.expect_end();
+    typ
+}
+
+/// Parses a single statement, including the trailing `;` if the
+/// statement needs one. All tokens must be part of the statement.
+/// On error `Statement::Error` is returned.
+///
+/// Spans of `else if` arms end with their own block, while the
+/// statement itself spans the whole chain.
+///
+/// # Examples
+///
+/// ```rust
+/// # extern crate plank_errors;
+/// # extern crate plank_syntax;
+/// use plank_syntax::ast::Statement;
+/// use plank_syntax::position::{Position, Spanned};
+///
+/// let source = "if a { x; } else if b { y; } else { z; }";
+/// let reporter = plank_errors::Reporter::new();
+/// let tokens = plank_syntax::lex(source, reporter.clone());
+/// let stmt = plank_syntax::parse_statement(tokens, reporter);
+/// assert_eq!(Spanned::span(&stmt).end, Position::with_offset(0, 40, 40));
+/// if let Statement::If(_, _, Some(ref else_if)) = *stmt {
+///     let span = Spanned::span(else_if);
+///     assert_eq!(span.start, Position::with_offset(0, 17, 17));
+///     assert_eq!(span.end, Position::with_offset(0, 28, 28));
+/// } else {
+///     panic!("expected an if statement");
+/// }
+/// ```
+pub fn parse_statement<I>(tokens: I, reporter: Reporter) -> Spanned<Statement>
+where
+    I: IntoIterator<Item = Spanned<Token>>,
+{
+    let mut parser = new_parser(tokens, reporter);
+    let start = parser.peek_span();
+    let stmt = match parser.parse_statement() {
+        PartialResult::Ok(stmt) | PartialResult::Partial(stmt) => stmt,
+        PartialResult::Error => Spanned::new(Statement::Error, parser.span_from(start)),
+    };
+    parser.expect_end();
+    stmt
+}
+
+fn new_parser<'a, I>(tokens: I, reporter: Reporter) -> Parser<'a>
+where
+    I: IntoIterator<Item = Spanned<Token>>,
+    I::IntoIter: 'a,
+{
     let mut parser = Parser::new(tokens, reporter);
 
     parser.prefix(TokenKind::Literal, &LiteralParser);
@@ -34,9 +133,11 @@ pub fn parse(tokens: Vec<Spanned<Token>>, reporter: Reporter) -> Program {
     );
     parser.prefix(TokenKind::Token(Token::Not), &UnaryOpParser(UnaryOp::Not));
     parser.prefix(TokenKind::Token(Token::LeftParen), &ParenthesisedParser);
+    parser.prefix(TokenKind::Token(Token::Keyword(Keyword::Fn)), &LambdaParser);
 
     parser.infix(TokenKind::Token(Token::LeftParen), &CallParser);
     parser.infix(TokenKind::Token(Token::Dot), &FieldParser);
+    parser.infix(TokenKind::Token(Token::LeftBracket), &IndexParser);
     parser.infix(TokenKind::Token(Token::Keyword(Keyword::As)), &CastParser);
 
     parse_infix!(parser, And, And, And, true);
@@ -54,11 +155,21 @@ pub fn parse(tokens: Vec<Spanned<Token>>, reporter: Reporter) -> Program {
     parse_infix!(parser, NotEqual, NotEqual, Equation, true);
     parse_infix!(parser, Assign, Assign, Assignment, false);
 
-    parser.parse_program()
+    parser
 }
 
 type ParseResult<T> = Result<T, ()>;
 
+/// Type parameters of an item, and the bound of each parameter.
+type GenericParams = (Vec<Spanned<Ident>>, Vec<Option<Spanned<Ident>>>);
+
+/// Maximum number of nested expressions, types or blocks. Parser
+/// is recursive, so without this limit deeply nested input would
+/// overflow the stack.
+const MAX_NESTING_DEPTH: usize = 256;
+
+const CALL_PARAM_END: &[Token] = &[Token::Comma, Token::RightParen];
+
 enum PartialResult<T, U> {
     Ok(T),
     Partial(U),
@@ -79,7 +190,9 @@ enum Expectation {
     Expression,
     Operator,
     Type,
+    Pattern,
     Token(TokenKind),
+    EndOfInput,
 }
 
 impl ::std::fmt::Display for Expectation {
@@ -88,7 +201,9 @@ impl ::std::fmt::Display for Expectation {
             Expectation::Operator => write!(f, "operator"),
             Expectation::Expression => write!(f, "expression"),
             Expectation::Type => write!(f, "type"),
+            Expectation::Pattern => write!(f, "pattern"),
             Expectation::Token(ref tok) => write!(f, "{}", tok),
+            Expectation::EndOfInput => write!(f, "end of input"),
         }
     }
 }
@@ -97,21 +212,24 @@ struct Parser<'a> {
     reporter: Reporter,
     expected: HashSet<Expectation>,
     expected2: HashSet<Expectation>,
-    tokens: VecDeque<Spanned<Token>>,
+    tokens: Peekable<Box<Iterator<Item = Spanned<Token>> + 'a>>,
     next_token: Option<Spanned<Token>>,
     prev_span: Option<Span>,
     prefix_parsers: HashMap<TokenKind, &'a PrefixParser>,
     infix_parsers: HashMap<TokenKind, &'a InfixParser>,
     last_line_completed: bool,
+    depth: usize,
 }
 
 impl<'a> Parser<'a> {
     fn new<I>(tokens: I, reporter: Reporter) -> Self
     where
         I: IntoIterator<Item = Spanned<Token>>,
+        I::IntoIter: 'a,
     {
-        let mut tokens = tokens.into_iter().collect::<VecDeque<_>>();
-        let next_token = tokens.pop_front();
+        let tokens: Box<Iterator<Item = Spanned<Token>> + 'a> = Box::new(tokens.into_iter());
+        let mut tokens = tokens.peekable();
+        let next_token = tokens.next();
         Parser {
             reporter,
             tokens,
@@ -122,6 +240,7 @@ impl<'a> Parser<'a> {
             expected: HashSet::new(),
             expected2: HashSet::new(),
             last_line_completed: false,
+            depth: 0,
         }
     }
 
@@ -183,15 +302,16 @@ impl<'a> Parser<'a> {
         let span = self.peek_span();
         let builder = self.reporter
             .error(format!("{}, got {}.", expected, got), span)
-            .span_note(format!("unexpected {}", got), span);
+            .code(codes::UNEXPECTED_TOKEN)
+            .primary_label(format!("unexpected {}", got), span);
         if let Some((span, msg)) = helper {
-            builder.span_note(msg, span).build();
+            builder.secondary_label(msg, span).build();
         } else if !self.last_line_completed && self.prev_span.is_some()
             && self.prev_span.unwrap().end.line < self.peek_span().start.line
         {
             let last_pos = self.prev_span.unwrap().end;
             let help_span = last_pos.forward(1).span_to(last_pos.forward(2));
-            builder.span_note(expected, help_span).build();
+            builder.secondary_label(expected, help_span).build();
         } else {
             builder.build();
         }
@@ -232,6 +352,44 @@ impl<'a> Parser<'a> {
         }
     }
 
+    fn nested<T, F>(&mut self, what: &str, f: F) -> ParseResult<T>
+    where
+        F: FnOnce(&mut Self) -> ParseResult<T>,
+    {
+        if self.depth >= MAX_NESTING_DEPTH {
+            self.report_too_deep(what);
+            return Err(());
+        }
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn report_too_deep(&mut self, what: &str) {
+        let span = self.peek_span();
+        let msg = format!("maximum nesting depth is {}", MAX_NESTING_DEPTH);
+        self.reporter
+            .error(format!("{} is nested too deeply", what), span)
+            .code(codes::NESTED_TOO_DEEPLY)
+            .span_note(msg, span)
+            .build();
+    }
+
+    fn expect_end(&mut self) {
+        if !self.is_at_end() {
+            self.expected.insert(Expectation::EndOfInput);
+            self.emit_error(None);
+        }
+    }
+
+    fn span_from(&self, start: Span) -> Span {
+        match self.prev_span {
+            Some(span) if span.end > start.start => start.merge(span),
+            _ => start,
+        }
+    }
+
     fn previous_span(&self) -> Span {
         self.prev_span.expect("no previous token")
     }
@@ -252,8 +410,8 @@ impl<'a> Parser<'a> {
         self.next_token.as_ref().map(Spanned::value)
     }
 
-    fn peek2(&self) -> Option<&Token> {
-        self.tokens.front().map(Spanned::value)
+    fn peek2(&mut self) -> Option<&Token> {
+        self.tokens.peek().map(Spanned::value)
     }
 
     fn check(&mut self, tok: Token) -> bool {
@@ -266,12 +424,23 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Consume `struct` or `union` keyword, returning which one it was.
+    fn check_struct_keyword(&mut self) -> Option<StructKind> {
+        if self.check(Token::Keyword(Keyword::Struct)) {
+            Some(StructKind::Struct)
+        } else if self.check(Token::Keyword(Keyword::Union)) {
+            Some(StructKind::Union)
+        } else {
+            None
+        }
+    }
+
     fn consume(&mut self) -> ParseResult<Spanned<Token>> {
         self.last_line_completed = false;
         match self.next_token.take() {
             Some(tok) => {
                 self.expected = ::std::mem::replace(&mut self.expected2, HashSet::new());
-                self.next_token = self.tokens.pop_front();
+                self.next_token = self.tokens.next();
                 self.prev_span = Some(Spanned::span(&tok));
                 Ok(tok)
             }
@@ -292,6 +461,21 @@ impl<'a> Parser<'a> {
         self.next_token.is_none()
     }
 
+    /// Consume a string literal, like the `"C"` in `extern "C" fn`.
+    fn check_string(&mut self) -> Option<Spanned<String>> {
+        self.expected.insert(Expectation::Token(TokenKind::Literal));
+        match self.peek() {
+            Some(&Token::Str(_)) => {}
+            _ => return None,
+        }
+        let tok = self.consume().expect("token disappeared");
+        let span = Spanned::span(&tok);
+        match Spanned::into_value(tok) {
+            Token::Str(s) => Some(Spanned::new(String::from_utf8_lossy(&s).into_owned(), span)),
+            _ => unreachable!(),
+        }
+    }
+
     fn check_ident(&mut self) -> Option<Spanned<Ident>> {
         self.expected.insert(Expectation::Token(TokenKind::Ident));
         match self.peek() {
@@ -323,7 +507,9 @@ impl<'a> Parser<'a> {
         loop {
             match self.peek() {
                 Some(&Token::Keyword(Keyword::Struct)) |
+                Some(&Token::Keyword(Keyword::Union)) |
                 Some(&Token::Keyword(Keyword::Extern)) |
+                Some(&Token::Keyword(Keyword::Import)) |
                 None => {
                     return;
                 }
@@ -333,6 +519,11 @@ impl<'a> Parser<'a> {
                         return;
                     }
                 }
+                Some(&Token::Hash) => {
+                    if let Some(&Token::LeftBracket) = self.peek2() {
+                        return;
+                    }
+                }
                 _ => {}
             }
             self.consume().expect("token disappeared");
@@ -345,6 +536,7 @@ impl<'a> Parser<'a> {
                 Some(&Token::Keyword(Keyword::If)) |
                 Some(&Token::Keyword(Keyword::Loop)) |
                 Some(&Token::Keyword(Keyword::While)) |
+                Some(&Token::Keyword(Keyword::Match)) |
                 Some(&Token::Keyword(Keyword::Break)) |
                 Some(&Token::Keyword(Keyword::Continue)) |
                 Some(&Token::Keyword(Keyword::Let)) |
@@ -358,7 +550,9 @@ impl<'a> Parser<'a> {
                         return Err(());
                     }
                 }
-                Some(&Token::Keyword(Keyword::Struct)) | None => {
+                Some(&Token::Keyword(Keyword::Struct)) |
+                Some(&Token::Keyword(Keyword::Union)) |
+                None => {
                     return Err(());
                 }
                 _ => {}
@@ -376,13 +570,22 @@ impl<'a> Parser<'a> {
             functions: Vec::new(),
             possible_structs: Vec::new(),
             possible_functions: Vec::new(),
+            imports: Vec::new(),
+            trivia: Vec::new(),
         };
         loop {
             self.last_line_completed = true;
-            if self.is_at_end() {
+            let attributes = match self.parse_attributes() {
+                Ok(attributes) => attributes,
+                Err(()) => {
+                    self.synchronize_item();
+                    continue;
+                }
+            };
+            if self.is_at_end() && attributes.is_empty() {
                 return program;
-            } else if self.check(Token::Keyword(Keyword::Struct)) {
-                match self.parse_struct() {
+            } else if let Some(kind) = self.check_struct_keyword() {
+                match self.parse_struct(kind, attributes) {
                     PartialResult::Ok(s) => program.structs.push(s),
                     PartialResult::Partial(name) => {
                         program.possible_structs.push(name);
@@ -394,7 +597,7 @@ impl<'a> Parser<'a> {
                 }
             } else if self.check(Token::Keyword(Keyword::Fn)) {
                 let start_span = self.previous_span();
-                match self.parse_function(start_span, FunctionType::Normal) {
+                match self.parse_function(start_span, FunctionType::Normal, None, attributes) {
                     PartialResult::Ok(f) => program.functions.push(f),
                     PartialResult::Partial(name) => {
                         program.possible_functions.push(name);
@@ -406,10 +609,12 @@ impl<'a> Parser<'a> {
                 }
             } else if self.check(Token::Keyword(Keyword::Extern)) {
                 let start_span = self.previous_span();
+                let convention = self.check_string();
                 if self.expect(Token::Keyword(Keyword::Fn)).is_err() {
                     self.synchronize_item();
                 } else {
-                    match self.parse_function(start_span, FunctionType::Extern) {
+                    let fn_type = FunctionType::Extern;
+                    match self.parse_function(start_span, fn_type, convention, attributes) {
                         PartialResult::Ok(f) => program.functions.push(f),
                         PartialResult::Partial(name) => {
                             program.possible_functions.push(name);
@@ -420,6 +625,11 @@ impl<'a> Parser<'a> {
                         }
                     }
                 }
+            } else if self.check(Token::Keyword(Keyword::Import)) {
+                match self.parse_import() {
+                    Ok(path) => program.imports.push(path),
+                    Err(()) => self.synchronize_item(),
+                }
             } else {
                 self.emit_error(None);
                 self.synchronize_item();
@@ -427,7 +637,46 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn parse_struct(&mut self) -> PartialResult<Struct, Ident> {
+    fn parse_import(&mut self) -> ParseResult<Spanned<String>> {
+        let path = match self.check_string() {
+            Some(path) => path,
+            None => {
+                self.emit_error(None);
+                return Err(());
+            }
+        };
+        self.expect(Token::Semicolon)?;
+        Ok(path)
+    }
+
+    fn parse_attributes(&mut self) -> ParseResult<Vec<Spanned<Attribute>>> {
+        let mut attributes = Vec::new();
+        while self.check(Token::Hash) {
+            let start_span = self.previous_span();
+            self.expect(Token::LeftBracket)?;
+            let name = self.consume_ident()?;
+            let mut args = Vec::new();
+            if self.check(Token::LeftParen) {
+                while !self.check(Token::RightParen) {
+                    args.push(self.consume_ident()?);
+                    if self.check(Token::RightParen) {
+                        break;
+                    }
+                    self.expect(Token::Comma)?;
+                }
+            }
+            self.expect(Token::RightBracket)?;
+            let span = start_span.merge(self.previous_span());
+            attributes.push(Spanned::new(Attribute { name, args }, span));
+        }
+        Ok(attributes)
+    }
+
+    fn parse_struct(
+        &mut self,
+        kind: StructKind,
+        attributes: Vec<Spanned<Attribute>>,
+    ) -> PartialResult<Struct, Ident> {
         let start_span = self.previous_span();
         let name = match self.parse_item_name() {
             PartialResult::Ok(name) => name,
@@ -437,7 +686,13 @@ impl<'a> Parser<'a> {
         match self.parse_struct_fields() {
             Ok(fields) => {
                 let complete_span = start_span.merge(self.previous_span());
-                PartialResult::Ok(Struct { name, fields, complete_span })
+                PartialResult::Ok(Struct {
+                    attributes,
+                    kind,
+                    name,
+                    fields,
+                    complete_span,
+                })
             }
             Err(()) => {
                 PartialResult::Partial(Spanned::into_value(name.name))
@@ -462,7 +717,13 @@ impl<'a> Parser<'a> {
         Ok(fields)
     }
 
-    fn parse_function(&mut self, start_span: Span, fn_type: FunctionType) -> PartialResult<Function, Ident> {
+    fn parse_function(
+        &mut self,
+        start_span: Span,
+        fn_type: FunctionType,
+        calling_convention: Option<Spanned<String>>,
+        attributes: Vec<Spanned<Attribute>>,
+    ) -> PartialResult<Function, Ident> {
         let name = match self.parse_item_name() {
             PartialResult::Ok(name) => name,
             PartialResult::Partial(name) => return PartialResult::Partial(name),
@@ -472,8 +733,10 @@ impl<'a> Parser<'a> {
             Ok((params, return_type, body)) => {
                 let complete_span = start_span.merge(self.previous_span());
                 PartialResult::Ok(Function {
+                    attributes,
                     complete_span,
                     fn_type,
+                    calling_convention,
                     name,
                     params,
                     return_type,
@@ -515,7 +778,17 @@ impl<'a> Parser<'a> {
             let name = self.consume_ident()?;
             self.expect(Token::Colon)?;
             let typ = self.parse_type()?;
-            params.push(FnParam { mutability, name, typ });
+            let default = if self.check(Token::Assign) {
+                Some(self.parse_expr_or_recover(CALL_PARAM_END)?)
+            } else {
+                None
+            };
+            params.push(FnParam {
+                mutability,
+                name,
+                typ,
+                default,
+            });
             if self.check(Token::RightParen) {
                 break;
             }
@@ -530,27 +803,41 @@ impl<'a> Parser<'a> {
             Err(()) => return PartialResult::Error,
         };
         match self.parse_generic_params() {
-            Ok(type_params) => PartialResult::Ok(ItemName { name, type_params }),
+            Ok((type_params, bounds)) => PartialResult::Ok(ItemName {
+                name,
+                type_params,
+                bounds,
+            }),
             Err(()) => PartialResult::Partial(Spanned::into_value(name)),
         }
     }
 
-    fn parse_generic_params(&mut self) -> ParseResult<Vec<Spanned<Ident>>> {
+    fn parse_generic_params(&mut self) -> ParseResult<GenericParams> {
+        let mut type_params = Vec::new();
+        let mut bounds = Vec::new();
         if self.check(Token::Less) {
             let open_span = self.previous_span();
-            let mut type_params = Vec::new();
-            type_params.push(self.consume_ident()?);
-            while self.check(Token::Comma) {
+            loop {
                 type_params.push(self.consume_ident()?);
+                if self.check(Token::Colon) {
+                    bounds.push(Some(self.consume_ident()?));
+                } else {
+                    bounds.push(None);
+                }
+                if !self.check(Token::Comma) || self.next_is_closing_angle() {
+                    break;
+                }
             }
             self.expect_closing(Token::Greater, open_span)?;
-            Ok(type_params)
-        } else {
-            Ok(Vec::new())
         }
+        Ok((type_params, bounds))
     }
 
     fn parse_type(&mut self) -> ParseResult<Spanned<Type>> {
+        self.nested("type", Parser::parse_type_inner)
+    }
+
+    fn parse_type_inner(&mut self) -> ParseResult<Spanned<Type>> {
         self.expected.insert(Expectation::Type);
         if self.check(Token::Keyword(Keyword::Unit)) {
             let span = self.previous_span();
@@ -567,6 +854,18 @@ impl<'a> Parser<'a> {
             let span = start.merge(Spanned::span(&typ));
             let typ = Type::Pointer(mutability, Box::new(typ));
             Ok(Spanned::new(typ, span))
+        } else if self.check(Token::LeftBracket) {
+            let start = self.previous_span();
+            self.expect_closing(Token::RightBracket, start)?;
+            let mutability = if self.check(Token::Keyword(Keyword::Mut)) {
+                Mutability::Mut
+            } else {
+                Mutability::Const
+            };
+            let typ = self.parse_type()?;
+            let span = start.merge(Spanned::span(&typ));
+            let typ = Type::Slice(mutability, Box::new(typ));
+            Ok(Spanned::new(typ, span))
         } else if self.check(Token::Keyword(Keyword::Fn)) {
             let start = self.previous_span();
             self.expect(Token::LeftParen)?;
@@ -627,10 +926,17 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Checks for `>` without consuming it, used to allow
+    /// trailing comma in type parameter lists.
+    fn next_is_closing_angle(&mut self) -> bool {
+        self.expected.insert(Expectation::Token(TokenKind::Token(Token::Greater)));
+        self.peek() == Some(&Token::Greater)
+    }
+
     fn parse_type_params(&mut self) -> ParseResult<Vec<Spanned<Type>>> {
         let mut types = Vec::new();
         types.push(self.parse_type()?);
-        while self.check(Token::Comma) {
+        while self.check(Token::Comma) && !self.next_is_closing_angle() {
             types.push(self.parse_type()?);
         }
         Ok(types)
@@ -645,6 +951,12 @@ impl<'a> Parser<'a> {
             } else {
                 Mutability::Const
             };
+            let is_pattern = mutability == Mutability::Const
+                && self.peek().map(Token::kind) == Some(TokenKind::Ident)
+                && self.peek2() == Some(&Token::LeftBrace);
+            if is_pattern {
+                return self.parse_let_pattern(start).into();
+            }
             let name = match self.consume_ident() {
                 Ok(ident) => ident,
                 Err(()) => return PartialResult::Error,
@@ -681,12 +993,14 @@ impl<'a> Parser<'a> {
             Ok(Spanned::new(stmt, span))
         } else if self.check(Token::Keyword(Keyword::While)) {
             let start = self.previous_span();
-            let cond = self.parse_expr()?;
+            let cond = self.parse_condition()?;
             self.expect(Token::LeftBrace)?;
             let body = self.parse_block()?;
             let span = start.merge(self.previous_span());
             let stmt = Statement::While(cond, Box::new(body));
             Ok(Spanned::new(stmt, span))
+        } else if self.check(Token::Keyword(Keyword::Match)) {
+            self.parse_match()
         } else if self.check(Token::Keyword(Keyword::Break)) {
             let span = self.previous_span();
             self.expect_semicolon()?;
@@ -700,7 +1014,7 @@ impl<'a> Parser<'a> {
             let value = if self.check(Token::Semicolon) {
                 Spanned::new(Expr::Literal(Literal::Unit), start)
             } else {
-                let value = self.parse_expr()?;
+                let value = self.parse_expr_or_recover(&[Token::Semicolon])?;
                 self.expect_semicolon()?;
                 value
             };
@@ -709,7 +1023,7 @@ impl<'a> Parser<'a> {
         } else if self.check(Token::LeftBrace) {
             self.parse_block().into()
         } else {
-            let expr = self.parse_expr()?;
+            let expr = self.parse_expr_or_recover(&[Token::Semicolon])?;
             self.expect_semicolon()?;
             let span = Spanned::span(&expr);
             let stmt = Statement::Expr(expr);
@@ -717,6 +1031,44 @@ impl<'a> Parser<'a> {
         }
     }
 
+    fn parse_let_pattern(&mut self, start: Span) -> ParseResult<Spanned<Statement>> {
+        let name = self.consume_ident()?;
+        self.expect(Token::LeftBrace)?;
+        let open_span = self.previous_span();
+        let mut fields = Vec::new();
+        while !self.check(Token::RightBrace) {
+            let mut mutability = if self.check(Token::Keyword(Keyword::Mut)) {
+                Mutability::Mut
+            } else {
+                Mutability::Const
+            };
+            let field = self.consume_ident()?;
+            let binding = if mutability == Mutability::Const && self.check(Token::Colon) {
+                if self.check(Token::Keyword(Keyword::Mut)) {
+                    mutability = Mutability::Mut;
+                }
+                self.consume_ident()?
+            } else {
+                field.clone()
+            };
+            fields.push(FieldPattern {
+                field,
+                mutability,
+                binding,
+            });
+            if self.check(Token::RightBrace) {
+                break;
+            }
+            self.expect_closing(Token::Comma, open_span)?;
+        }
+        self.expect(Token::Assign)?;
+        let value = self.parse_expr_or_recover(&[Token::Semicolon])?;
+        self.expect_semicolon()?;
+        let span = start.merge(self.previous_span());
+        let pattern = StructPattern { name, fields };
+        Ok(Spanned::new(Statement::LetPattern(pattern, value), span))
+    }
+
     fn parse_let_end(&mut self) -> ParseResult<(Option<Spanned<Type>>, Option<Spanned<Expr>>)> {
         let typ = if self.check(Token::Colon) {
             Some(self.parse_type()?)
@@ -727,7 +1079,7 @@ impl<'a> Parser<'a> {
             None
         } else {
             self.expect(Token::Assign)?;
-            let value = self.parse_expr()?;
+            let value = self.parse_expr_or_recover(&[Token::Semicolon])?;
             self.expect_semicolon()?;
             Some(value)
         };
@@ -735,26 +1087,130 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_if(&mut self) -> ParseResult<Spanned<Statement>> {
-        let start = self.previous_span();
-        let cond = self.parse_expr()?;
-        self.expect(Token::LeftBrace)?;
-        let then = self.parse_block()?;
-        let else_ = if self.check(Token::Keyword(Keyword::Else)) {
-            if self.check(Token::Keyword(Keyword::If)) {
-                Some(Box::new(self.parse_if()?))
-            } else {
+        // `else if` chains are parsed iteratively, so that
+        // long chains do not count towards nesting depth
+        let mut arms = Vec::new();
+        let else_ = loop {
+            let start = self.previous_span();
+            let cond = self.parse_condition()?;
+            self.expect(Token::LeftBrace)?;
+            let then = self.parse_block()?;
+            arms.push((start, cond, then));
+            if !self.check(Token::Keyword(Keyword::Else)) {
+                break None;
+            }
+            if !self.check(Token::Keyword(Keyword::If)) {
                 self.expect(Token::LeftBrace)?;
-                Some(Box::new(self.parse_block()?))
+                break Some(Box::new(self.parse_block()?));
             }
-        } else {
-            None
         };
+        let end = self.previous_span();
+        let stmt = arms
+            .into_iter()
+            .enumerate()
+            .rev()
+            .fold(else_, |else_, (index, (start, cond, then))| {
+                // the first arm is the whole statement, `else if` arms only
+                // span to the end of their own block so that diagnostics
+                // about them don't cover the rest of the chain
+                let span = if index == 0 {
+                    start.merge(end)
+                } else {
+                    start.merge(Spanned::span(&then))
+                };
+                let stmt = Statement::If(cond, Box::new(then), else_);
+                Some(Box::new(Spanned::new(stmt, span)))
+            })
+            .expect("if statement without arms");
+        Ok(*stmt)
+    }
+
+    fn parse_match(&mut self) -> ParseResult<Spanned<Statement>> {
+        let start = self.previous_span();
+        let value = self.parse_expr_or_recover(&[Token::LeftBrace])?;
+        self.expect(Token::LeftBrace)?;
+        let mut arms = Vec::new();
+        while !self.check(Token::RightBrace) {
+            let pattern = self.parse_pattern()?;
+            self.expect(Token::FatArrow)?;
+            self.expect(Token::LeftBrace)?;
+            let body = self.parse_block()?;
+            arms.push(MatchArm { pattern, body });
+            // arm bodies are blocks, so commas between arms are optional
+            self.check(Token::Comma);
+        }
         let span = start.merge(self.previous_span());
-        let stmt = Statement::If(cond, Box::new(then), else_);
-        Ok(Spanned::new(stmt, span))
+        Ok(Spanned::new(Statement::Match(value, arms), span))
+    }
+
+    fn parse_pattern(&mut self) -> ParseResult<Spanned<Pattern>> {
+        self.expected.insert(Expectation::Pattern);
+        match self.peek() {
+            Some(&Token::Underscore) => {
+                let span = Spanned::span(&self.consume()?);
+                Ok(Spanned::new(Pattern::Wildcard, span))
+            }
+            Some(&Token::Number(_)) | Some(&Token::Bool(_)) | Some(&Token::Char(_)) => {
+                let literal = LiteralParser.parse(self)?;
+                let span = Spanned::span(&literal);
+                Ok(Spanned::new(Pattern::Value(literal), span))
+            }
+            Some(&Token::Minus) => {
+                let op = Spanned::map(self.consume()?, |_| UnaryOp::Minus);
+                self.expected.insert(Expectation::Token(TokenKind::Literal));
+                if let Some(&Token::Number(_)) = self.peek() {
+                    let literal = LiteralParser.parse(self)?;
+                    let span = Spanned::span(&op).merge(Spanned::span(&literal));
+                    let value = Spanned::new(Expr::Unary(op, Box::new(literal)), span);
+                    Ok(Spanned::new(Pattern::Value(value), span))
+                } else {
+                    self.emit_error(None);
+                    Err(())
+                }
+            }
+            _ => {
+                self.emit_error(None);
+                Err(())
+            }
+        }
+    }
+
+    fn parse_condition(&mut self) -> ParseResult<Spanned<Expr>> {
+        let cond = self.parse_expr_or_recover(&[Token::LeftBrace])?;
+        if let Expr::Binary(_, op, _) = *cond {
+            if *op == BinaryOp::Assign {
+                let op_span = Spanned::span(&op);
+                let msg = "assignment used as a condition";
+                self.reporter
+                    .lint("assign_in_condition", msg, Spanned::span(&cond))
+                    .code(codes::ASSIGN_IN_CONDITION)
+                    .span_note("help: use `==` to compare values", op_span)
+                    .build();
+            }
+        }
+        Ok(cond)
     }
 
     fn parse_block(&mut self) -> ParseResult<Spanned<Statement>> {
+        if self.depth >= MAX_NESTING_DEPTH {
+            // skip the whole block, otherwise recovery would
+            // try to parse each inner block as a statement
+            self.report_too_deep("block");
+            let mut open_braces = 1;
+            while open_braces > 0 {
+                match self.consume().map(Spanned::into_value) {
+                    Ok(Token::LeftBrace) => open_braces += 1,
+                    Ok(Token::RightBrace) => open_braces -= 1,
+                    Ok(_) => {}
+                    Err(()) => break,
+                }
+            }
+            return Err(());
+        }
+        self.nested("block", Parser::parse_block_inner)
+    }
+
+    fn parse_block_inner(&mut self) -> ParseResult<Spanned<Statement>> {
         let start = self.previous_span();
         let mut statements = Vec::new();
         while !self.check(Token::RightBrace) {
@@ -780,7 +1236,57 @@ impl<'a> Parser<'a> {
         self.pratt_parse(Precedence::Lowest)
     }
 
+    /// Parse expression, and if that fails skip to one of the
+    /// `terminators`, leaving `Expr::Error` in place of expression.
+    fn parse_expr_or_recover(&mut self, terminators: &[Token]) -> ParseResult<Spanned<Expr>> {
+        let start = self.peek_span();
+        match self.parse_expr() {
+            Ok(expr) => Ok(expr),
+            Err(()) => {
+                self.skip_to_terminator(terminators)?;
+                let span = match self.prev_span {
+                    Some(prev) if prev.end > start.start => start.merge(prev),
+                    _ => start,
+                };
+                Ok(Spanned::new(Expr::Error, span))
+            }
+        }
+    }
+
+    fn skip_to_terminator(&mut self, terminators: &[Token]) -> ParseResult<()> {
+        let mut depth = 0;
+        loop {
+            let item_next = self.peek() == Some(&Token::Keyword(Keyword::Fn))
+                && self.peek2().map(Token::kind) == Some(TokenKind::Ident);
+            match self.peek() {
+                None => return Err(()),
+                Some(tok) if depth == 0 && terminators.contains(tok) => return Ok(()),
+                Some(&Token::LeftParen) |
+                Some(&Token::LeftBracket) |
+                Some(&Token::LeftBrace) => depth += 1,
+                Some(&Token::RightBrace) if depth == 0 => return Err(()),
+                // stray closing parens and brackets are just skipped
+                Some(&Token::RightParen) |
+                Some(&Token::RightBracket) |
+                Some(&Token::RightBrace) if depth > 0 => depth -= 1,
+                Some(&Token::Semicolon) |
+                Some(&Token::Keyword(Keyword::Let)) |
+                Some(&Token::Keyword(Keyword::Return)) |
+                Some(&Token::Keyword(Keyword::Struct)) |
+                Some(&Token::Keyword(Keyword::Union)) if depth == 0 => return Err(()),
+                // don't skip over next function, but allow lambdas
+                Some(&Token::Keyword(Keyword::Fn)) if depth == 0 && item_next => return Err(()),
+                _ => {}
+            }
+            self.consume().expect("token disappeared");
+        }
+    }
+
     fn pratt_parse(&mut self, prec: Precedence) -> ParseResult<Spanned<Expr>> {
+        self.nested("expression", |parser| parser.pratt_parse_inner(prec))
+    }
+
+    fn pratt_parse_inner(&mut self, prec: Precedence) -> ParseResult<Spanned<Expr>> {
         self.expected.insert(Expectation::Expression);
         let mut expr = self.peek()
             .map(|tok| tok.kind())
@@ -902,10 +1408,11 @@ impl InfixParser for CallParser {
             if ident_next && parser.peek2() == Some(&Token::Colon) {
                 let name = parser.consume_ident().expect("expected ident");
                 parser.expect(Token::Colon).expect("expected ':'");
-                let value = parser.parse_expr()?;
+                let value = parser.parse_expr_or_recover(CALL_PARAM_END)?;
                 params.push(CallParam::Named(name, value));
             } else {
-                params.push(CallParam::Unnamed(parser.parse_expr()?));
+                let value = parser.parse_expr_or_recover(CALL_PARAM_END)?;
+                params.push(CallParam::Unnamed(value));
             }
             if parser.check(Token::RightParen) {
                 break;
@@ -934,6 +1441,34 @@ impl InfixParser for FieldParser {
     }
 }
 
+struct IndexParser;
+
+impl InfixParser for IndexParser {
+    fn precedence(&self) -> Precedence {
+        Precedence::CallOrField
+    }
+
+    fn parse(&self, parser: &mut Parser, value: Spanned<Expr>) -> ParseResult<Spanned<Expr>> {
+        parser
+            .expect(Token::LeftBracket)
+            .expect("expected left bracket");
+        let open_span = parser.previous_span();
+        let index = parser.parse_expr_or_recover(&[Token::RightBracket, Token::DoubleDot])?;
+        let end = if parser.check(Token::DoubleDot) {
+            Some(parser.parse_expr_or_recover(&[Token::RightBracket])?)
+        } else {
+            None
+        };
+        parser.expect_closing(Token::RightBracket, open_span)?;
+        let span = Spanned::span(&value).merge(parser.previous_span());
+        let expr = match end {
+            Some(end) => Expr::Slice(Box::new(value), Box::new(index), Box::new(end)),
+            None => Expr::Index(Box::new(value), Box::new(index)),
+        };
+        Ok(Spanned::new(expr, span))
+    }
+}
+
 struct CastParser;
 
 impl InfixParser for CastParser {
@@ -1022,13 +1557,33 @@ impl PrefixParser for LiteralParser {
     }
 }
 
+struct LambdaParser;
+
+impl PrefixParser for LambdaParser {
+    fn parse(&self, parser: &mut Parser) -> ParseResult<Spanned<Expr>> {
+        let tok = parser.consume().expect("token disappeared");
+        parser.expect(Token::LeftParen)?;
+        let params = parser.parse_function_params()?;
+        let return_type = if parser.check(Token::Arrow) {
+            parser.parse_type()?
+        } else {
+            Spanned::new(Type::Unit, parser.previous_span())
+        };
+        parser.expect(Token::LeftBrace)?;
+        let body = parser.parse_block()?;
+        let span = Spanned::span(&tok).merge(Spanned::span(&body));
+        let expr = Expr::Lambda(params, Box::new(return_type), Box::new(body));
+        Ok(Spanned::new(expr, span))
+    }
+}
+
 struct ParenthesisedParser;
 
 impl PrefixParser for ParenthesisedParser {
     fn parse(&self, parser: &mut Parser) -> ParseResult<Spanned<Expr>> {
         let tok = parser.consume().expect("token disappeared");
         let open_span = Spanned::span(&tok);
-        let expr = parser.parse_expr()?;
+        let expr = parser.parse_expr_or_recover(&[Token::RightParen])?;
         parser.expect_closing(Token::RightParen, open_span)?;
         Ok(expr)
     }
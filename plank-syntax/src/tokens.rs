@@ -23,11 +23,15 @@ pub enum Token {
     Percent,
     Ampersand,
     Dot,
+    DoubleDot,
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Arrow,
+    FatArrow,
     And,
     Or,
     Comma,
@@ -35,6 +39,7 @@ pub enum Token {
     Colon,
     DoubleColon,
     Semicolon,
+    Hash,
     Not,
     Assign,
     Error,
@@ -74,11 +79,15 @@ impl fmt::Display for Token {
             Token::Percent => write!(f, "%"),
             Token::Ampersand => write!(f, "&"),
             Token::Dot => write!(f, "."),
+            Token::DoubleDot => write!(f, ".."),
             Token::LeftParen => write!(f, "("),
             Token::RightParen => write!(f, ")"),
             Token::LeftBrace => write!(f, "{{"),
             Token::RightBrace => write!(f, "}}"),
+            Token::LeftBracket => write!(f, "["),
+            Token::RightBracket => write!(f, "]"),
             Token::Arrow => write!(f, "->"),
+            Token::FatArrow => write!(f, "=>"),
             Token::And => write!(f, "&&"),
             Token::Or => write!(f, "||"),
             Token::Comma => write!(f, ","),
@@ -86,6 +95,7 @@ impl fmt::Display for Token {
             Token::Colon => write!(f, ":"),
             Token::DoubleColon => write!(f, "::"),
             Token::Semicolon => write!(f, ";"),
+            Token::Hash => write!(f, "#"),
             Token::Assign => write!(f, "="),
             Token::Not => write!(f, "!"),
             Token::Error => write!(f, "?"),
@@ -93,15 +103,27 @@ impl fmt::Display for Token {
     }
 }
 
+/// Source text that does not produce tokens. Each variant holds
+/// the exact text, including comment delimiters.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Trivia {
+    Whitespace(String),
+    LineComment(String),
+    BlockComment(String),
+}
+
 #[derive(PartialEq, PartialOrd, Eq, Ord, Hash, Debug, Copy, Clone)]
 pub enum Keyword {
     Extern,
+    Import,
     Fn,
     Struct,
+    Union,
     If,
     Else,
     Loop,
     While,
+    Match,
     Continue,
     Break,
     Let,
@@ -122,12 +144,15 @@ impl fmt::Display for Keyword {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Keyword::Extern => write!(f, "extern"),
+            Keyword::Import => write!(f, "import"),
             Keyword::Fn => write!(f, "fn"),
             Keyword::Struct => write!(f, "struct"),
+            Keyword::Union => write!(f, "union"),
             Keyword::If => write!(f, "if"),
             Keyword::Else => write!(f, "else"),
             Keyword::Loop => write!(f, "loop"),
             Keyword::While => write!(f, "while"),
+            Keyword::Match => write!(f, "match"),
             Keyword::Continue => write!(f, "continue"),
             Keyword::Break => write!(f, "break"),
             Keyword::Let => write!(f, "let"),
@@ -183,6 +208,7 @@ impl TokenKind {
             TokenKind::Token(Token::Star) |
             TokenKind::Token(Token::Ampersand) |
             TokenKind::Token(Token::LeftParen) |
+            TokenKind::Token(Token::Keyword(Keyword::Fn)) |
             TokenKind::Token(Token::Not) => true,
             _ => false,
         }
@@ -192,6 +218,7 @@ impl TokenKind {
         match *self {
             TokenKind::Token(Token::Keyword(Keyword::Unit)) |
             TokenKind::Token(Token::Star) |
+            TokenKind::Token(Token::LeftBracket) |
             TokenKind::Token(Token::Keyword(Keyword::Fn)) |
             TokenKind::Token(Token::Underscore) |
             TokenKind::Token(Token::Keyword(Keyword::I8)) |
@@ -1,6 +1,8 @@
 use std::collections::{HashMap, HashSet};
 use plank_ir::analysis::{self, Loc};
-use plank_ir::ir::{Reg, Function, Instruction, Value, UnaryOp, BinaryOp, IntOp, Program, Block, BlockId, BlockEnd, Signedness, Size, BitOp};
+use plank_ir::target::{Arch, CallingConvention};
+use plank_ir::ir::{Reg, Function, Instruction, Value, UnaryOp, BinaryOp, IntOp, Program, Block, BlockId, BlockEnd, Signedness, Size, BitOp, Symbol, Overflow};
+use plank_errors::position::{Position, Span};
 use x86;
 
 
@@ -24,7 +26,7 @@ fn can_be_flag(reg: Reg, f: &Function, live: &HashSet<Loc>) -> bool {
                 block: id,
                 pos: index,
             };
-            match *op {
+            match op.instruction {
                 Instruction::BinaryOp(r, BinaryOp::IntOp(IntOp::Greater, _, _), _, _) |
                 Instruction::BinaryOp(r, BinaryOp::IntOp(IntOp::GreaterEq, _, _), _, _) |
                 Instruction::BinaryOp(r, BinaryOp::IntOp(IntOp::Less, _, _), _, _) |
@@ -41,6 +43,7 @@ fn can_be_flag(reg: Reg, f: &Function, live: &HashSet<Loc>) -> bool {
                     }
                 }
                 Instruction::Assign(r, _) |
+                Instruction::BinaryOp(r, BinaryOp::Checked(..), _, _) |
                 Instruction::Call(r, _, _) |
                 Instruction::CallVirt(r, _, _) |
                 Instruction::CastAssign(r, _) |
@@ -91,7 +94,7 @@ fn can_be_flag(reg: Reg, f: &Function, live: &HashSet<Loc>) -> bool {
     true
 }
 
-fn generate_constraints(f: &Function) -> Constraints {
+fn generate_constraints(f: &Function, word: u32) -> Constraints {
     let mut constraints = Constraints::default();
     let liveness = analysis::liveness::live_locations(f);
     for (&r1, locs1) in &liveness {
@@ -105,7 +108,7 @@ fn generate_constraints(f: &Function) -> Constraints {
     }
     for &param in &f.parameters {
         let size = f.registers[&param].size;
-        let size = (size + 3) / 4 * 4;
+        let size = (size + word - 1) / word * word;
         for (&reg, &layout) in &f.registers {
             if layout.size > size {
                 constraints.intersect.insert((param, reg));
@@ -114,20 +117,22 @@ fn generate_constraints(f: &Function) -> Constraints {
         }
     }
     for (&reg, &layout) in &f.registers {
-        if layout.size > 4 {
+        if layout.size > word {
             constraints.not_register.insert(reg);
         }
     }
     for block in f.blocks.values() {
         for op in &block.ops {
-            match *op {
+            match op.instruction {
                 Instruction::TakeAddress(_, r, _) |
                 Instruction::Load(_, r, _) |
                 Instruction::Store(r, _, _) => {
                     constraints.not_register.insert(r);
                 }
                 Instruction::BinaryOp(r, BinaryOp::IntOp(IntOp::Add, _, _), _, Value::Reg(r2)) |
-                Instruction::BinaryOp(r, BinaryOp::IntOp(IntOp::Sub, _, _), _, Value::Reg(r2)) => {
+                Instruction::BinaryOp(r, BinaryOp::IntOp(IntOp::Sub, _, _), _, Value::Reg(r2)) |
+                Instruction::BinaryOp(r, BinaryOp::Checked(IntOp::Add, ..), _, Value::Reg(r2)) |
+                Instruction::BinaryOp(r, BinaryOp::Checked(IntOp::Sub, ..), _, Value::Reg(r2)) => {
                     constraints.intersect.insert((r, r2));
                     constraints.intersect.insert((r2, r));
                 }
@@ -157,11 +162,13 @@ fn generate_bonuses(f: &Function) -> Vec<Bonus> {
     let mut bonuses = Vec::new();
     for block in f.blocks.values() {
         for op in &block.ops {
-            match *op {
+            match op.instruction {
                 // no bonus for these, because we have to go trough eax anyways
                 Instruction::BinaryOp(_, BinaryOp::IntOp(IntOp::Mul, _, _), _, _) |
                 Instruction::BinaryOp(_, BinaryOp::IntOp(IntOp::Div, _, _), _, _) |
                 Instruction::BinaryOp(_, BinaryOp::IntOp(IntOp::Mod, _, _), _, _) |
+                Instruction::BinaryOp(_, BinaryOp::Checked(IntOp::Mul, _, _, _), _, _) |
+                Instruction::BinaryOp(_, BinaryOp::Checked(IntOp::Div, _, _, _), _, _) |
                 // for these it's pointless to place operand and result in same position
                 Instruction::BinaryOp(_, BinaryOp::Eq, _, _) |
                 Instruction::BinaryOp(_, BinaryOp::Neq, _, _) |
@@ -191,6 +198,7 @@ struct Emitter {
     functions: Vec<Vec<x86::Instruction>>,
     strings: Vec<Vec<u8>>,
     current_function: Vec<x86::Instruction>,
+    debug_functions: Vec<Option<x86::FunctionDebugInfo>>,
 }
 
 impl Emitter {
@@ -259,8 +267,8 @@ impl Location {
     }
 }
 
-fn allocate_locations(f: &Function) -> (HashMap<Reg, Location>, u32) {
-    let constraints = generate_constraints(f);
+fn allocate_locations(f: &Function, word: u32) -> (HashMap<Reg, Location>, u32) {
+    let constraints = generate_constraints(f, word);
     let bonuses = generate_bonuses(f);
     let mut priority = f
         .registers
@@ -279,7 +287,8 @@ fn allocate_locations(f: &Function) -> (HashMap<Reg, Location>, u32) {
         }
     }
     let mut locations = HashMap::new();
-    let mut param_location = 4;
+    // parameters are above the return address
+    let mut param_location = word;
     let mut location_candidates = vec![
         Location::Flags(x86::Condition::Equal),
         Location::Ebx,
@@ -291,7 +300,7 @@ fn allocate_locations(f: &Function) -> (HashMap<Reg, Location>, u32) {
         let loc = Location::Param(param_location);
         locations.insert(param, loc);
         location_candidates.push(loc);
-        param_location += (f.registers[&param].size + 3) / 4 * 4;
+        param_location += (f.registers[&param].size + word - 1) / word * word;
     }
     let mut visit_order = f
         .registers
@@ -312,11 +321,13 @@ fn allocate_locations(f: &Function) -> (HashMap<Reg, Location>, u32) {
                 continue;
             }
             if (loc.is_same(Location::Ebx) || loc.is_same(Location::Ecx)) &&
-                (size != 1 && size != 2 && size != 4)
+                (size != 1 && size != 2 && size != 4 && size != word)
             {
                 continue;
             }
-            if (loc.is_same(Location::Edi) || loc.is_same(Location::Esi)) && size != 4 {
+            if (loc.is_same(Location::Edi) || loc.is_same(Location::Esi)) &&
+                (size != 4 && size != word)
+            {
                 continue;
             }
             let mut is_ok = true;
@@ -361,11 +372,11 @@ fn allocate_locations(f: &Function) -> (HashMap<Reg, Location>, u32) {
         locations.insert(reg, loc);
     }
     let mut slot_sizes = (0..next_stack_slot)
-        .map(|i| (i, 4))
+        .map(|i| (i, word))
         .collect::<HashMap<_, _>>();
     for (&reg, &loc) in &locations {
         if let Location::Stack(slot) = loc {
-            let size = (f.registers[&reg].size + 3) / 4 * 4;
+            let size = (f.registers[&reg].size + word - 1) / word * word;
             let cur = slot_sizes.get_mut(&slot).unwrap();
             if *cur < size {
                 *cur = size;
@@ -417,6 +428,7 @@ fn order_blocks(f: &Function) -> Vec<BlockId> {
 }
 
 struct FnCompiler<'a> {
+    program: &'a Program,
     f: &'a Function,
     locations: HashMap<Reg, Location>,
     block_labels: HashMap<BlockId, x86::Label>,
@@ -426,17 +438,30 @@ struct FnCompiler<'a> {
     stack_size: u32,
     referenced_blocks: HashSet<BlockId>,
     backup_space: u32,
+    /// Size of pointers and stack slots, 4 or 8 bytes.
+    word: u32,
+    /// Keep a frame pointer and mark source positions
+    /// of instructions, so that debug info can be emitted.
+    debug: bool,
+    last_position: Option<Position>,
 }
 
 impl<'a> FnCompiler<'a> {
-    fn new(f: &'a Function, emitter: &'a mut Emitter) -> Self {
-        let (locations, stack_size) = allocate_locations(f);
+    fn new(
+        program: &'a Program,
+        f: &'a Function,
+        emitter: &'a mut Emitter,
+        word: u32,
+        debug: bool,
+    ) -> Self {
+        let (locations, stack_size) = allocate_locations(f, word);
         let block_labels = f
             .blocks
             .keys()
             .map(|&id| (id, emitter.make_label()))
             .collect::<HashMap<_, _>>();
         FnCompiler {
+            program,
             f,
             locations,
             block_labels,
@@ -446,6 +471,39 @@ impl<'a> FnCompiler<'a> {
             stack_size,
             referenced_blocks: HashSet::new(),
             backup_space: 0,
+            word,
+            debug,
+            last_position: None,
+        }
+    }
+
+    /// Part of `register` holding `size` bytes, which
+    /// can be at most the size of a word.
+    fn register(&self, register: x86::Register, size: u32) -> x86::Register {
+        if size > self.word {
+            panic!("{} byte values are not supported on this target", size);
+        }
+        register.resize(size)
+    }
+
+    fn stack_pointer(&self) -> x86::Register {
+        self.register(x86::Register::Esp, self.word)
+    }
+
+    fn calling_convention(&self, f: &Symbol) -> CallingConvention {
+        self.program
+            .functions
+            .get(f)
+            .map_or(CallingConvention::Stack, |f| f.calling_convention)
+    }
+
+    /// Functions with a foreign calling convention are linked by their
+    /// plank name, without the `fn_` prefix or any escaping.
+    fn function_label(&self, f: &Symbol) -> x86::Label {
+        if self.calling_convention(f) == CallingConvention::Stack {
+            x86::Label::Named(f.0.clone())
+        } else {
+            x86::Label::Foreign(f.0.trim_start_matches("fn_").into())
         }
     }
 
@@ -455,25 +513,31 @@ impl<'a> FnCompiler<'a> {
 
     fn emit_function_intro(&mut self) {
         let mut backup = 0;
-        if self.is_location_used(Location::Ebx) {
-            self.emitter.emit(x86::Instruction::Push(x86::Rm::Register(x86::Register::Ebx)));
-            backup += 4;
+        if self.debug {
+            let frame = self.register(x86::Register::Ebp, self.word);
+            let stack = self.stack_pointer();
+            self.emitter.emit(x86::Instruction::Push(x86::Rm::Register(frame)));
+            self.emitter.emit(x86::Instruction::Mov(
+                x86::TwoArgs::RegRm(frame, x86::Rm::Register(stack)),
+            ));
+            backup += self.word;
         }
-        if self.is_location_used(Location::Ecx) {
-            self.emitter.emit(x86::Instruction::Push(x86::Rm::Register(x86::Register::Ecx)));
-            backup += 4;
-        }
-        if self.is_location_used(Location::Esi) {
-            self.emitter.emit(x86::Instruction::Push(x86::Rm::Register(x86::Register::Esi)));
-            backup += 4;
-        }
-        if self.is_location_used(Location::Edi) {
-            self.emitter.emit(x86::Instruction::Push(x86::Rm::Register(x86::Register::Edi)));
-            backup += 4;
+        let saved = [
+            (Location::Ebx, x86::Register::Ebx),
+            (Location::Ecx, x86::Register::Ecx),
+            (Location::Esi, x86::Register::Esi),
+            (Location::Edi, x86::Register::Edi),
+        ];
+        for &(loc, reg) in &saved {
+            if self.is_location_used(loc) {
+                let reg = self.register(reg, self.word);
+                self.emitter.emit(x86::Instruction::Push(x86::Rm::Register(reg)));
+                backup += self.word;
+            }
         }
         if self.stack_size > 0 {
             let args = x86::TwoArgs::RmImm(
-                x86::Rm::Register(x86::Register::Esp),
+                x86::Rm::Register(self.stack_pointer()),
                 x86::Immediate::Constant(u64::from(self.stack_size)),
             );
             self.emitter.emit(x86::Instruction::Sub(args));
@@ -482,47 +546,45 @@ impl<'a> FnCompiler<'a> {
         self.backup_space += backup;
     }
 
-    fn go_to_block(&mut self, id: BlockId) -> BlockEnd {
-        let block = &self.f.blocks[&id];
-        for op in &block.ops {
-            match *op {
-                Instruction::Init(_) |
-                Instruction::Drop(_) |
-                Instruction::Nop => {}
-                _ => return BlockEnd::Jump(id),
+    /// Follow jumps through blocks that have no code. Stops at a block
+    /// that was already seen, so that empty loops like `loop {}` jump
+    /// to themselves.
+    fn go_to_block(&mut self, mut id: BlockId) -> BlockEnd {
+        let mut seen = HashSet::new();
+        while seen.insert(id) {
+            let block = &self.f.blocks[&id];
+            if !is_empty_block(block) {
+                return BlockEnd::Jump(id);
+            }
+            match block.end {
+                BlockEnd::Jump(to) => id = to,
+                ref other => return other.clone(),
             }
         }
-        match block.end {
-            BlockEnd::Jump(to) => self.go_to_block(to),
-            ref other => other.clone(), 
-        }
+        BlockEnd::Jump(id)
     }
 
-    fn get_block_label(&mut self, id: BlockId) -> x86::Label {
-        let block = &self.f.blocks[&id];
-        for op in &block.ops {
-            match *op {
-                Instruction::Init(_) |
-                Instruction::Drop(_) |
-                Instruction::Nop => {}
-                _ => {
-                    self.referenced_blocks.insert(id);
-                    return self.block_labels[&id].clone();
-                }
-            }
-        }
-        match block.end {
-            BlockEnd::Jump(to) => self.get_block_label(to),
-            _ => {
-                self.referenced_blocks.insert(id);
-                return self.block_labels[&id].clone();
+    fn get_block_label(&mut self, mut id: BlockId) -> x86::Label {
+        let mut seen = HashSet::new();
+        while seen.insert(id) {
+            let block = &self.f.blocks[&id];
+            match block.end {
+                BlockEnd::Jump(to) if is_empty_block(block) => id = to,
+                _ => break,
             }
         }
+        self.referenced_blocks.insert(id);
+        self.block_labels[&id].clone()
     }
 
     fn emit_block(&mut self, block: &Block) {
         for op in &block.ops {
-            match *op {
+            // spans of builtins are empty, and point to no source
+            match op.span {
+                Some(span) if span.start != span.end => self.emit_source_position(span.start),
+                _ => {}
+            }
+            match op.instruction {
                 Instruction::Drop(_) |
                 Instruction::Init(_) |
                 Instruction::Nop => {}
@@ -562,14 +624,35 @@ impl<'a> FnCompiler<'a> {
                 Instruction::UnaryOp(to, op, ref arg) => {
                     self.emit_unary_op(to, op, arg);
                 }
+                Instruction::BinaryOp(to, checked @ BinaryOp::Checked(..), ref a, ref b) => {
+                    self.emit_checked_op(to, checked, a, b, op.span);
+                }
                 Instruction::BinaryOp(to, op, ref a, ref b) => {
                     self.emit_binary_op(to, op, a, b);
                 }
+                Instruction::Call(reg, ref f, ref args) if self.is_foreign(f) => {
+                    self.emit_foreign_call(f, args);
+                    let result = self.register(x86::Register::Eax, self.f.registers[&reg].size);
+                    let to = self.to_rm(reg);
+                    self.emit_move(x86::Rm::Register(result), to, 4);
+                }
+                // the prelude reports failed bounds checks together with their
+                // position, as there is no backtrace to find it from
+                Instruction::CallProc(ref f, ref args) if &*f.0 == "builtin_bounds_fail" => {
+                    let (line, column) = reported_position(op.span);
+                    let mut args = args.clone();
+                    args.push(Value::Int(u64::from(line), Size::Bit32));
+                    args.push(Value::Int(u64::from(column), Size::Bit32));
+                    self.emit_proc_call(f, &args);
+                }
+                Instruction::CallProc(ref f, ref args) if self.is_foreign(f) => {
+                    self.emit_foreign_call(f, args);
+                }
                 Instruction::Call(reg, ref f, ref args) => {
                     let additional_stack = self.emit_call_args(args);
                     if additional_stack > 0 {
                         self.emitter.emit(x86::Instruction::Sub(x86::TwoArgs::RmImm(
-                            x86::Rm::Register(x86::Register::Esp),
+                            x86::Rm::Register(self.stack_pointer()),
                             x86::Immediate::Constant(u64::from(additional_stack)),
                         )));
                     }
@@ -577,41 +660,22 @@ impl<'a> FnCompiler<'a> {
                     self.emitter.emit(x86::Instruction::Call(f));
                     if additional_stack > 0 {
                         self.emitter.emit(x86::Instruction::Add(x86::TwoArgs::RmImm(
-                            x86::Rm::Register(x86::Register::Esp),
+                            x86::Rm::Register(self.stack_pointer()),
                             x86::Immediate::Constant(u64::from(additional_stack)),
                         )));
                     }
-                    let result = match self.f.registers[&reg].size {
-                        1 => x86::Register::Al,
-                        2 => x86::Register::Ax,
-                        4 => x86::Register::Eax,
-                        _ => panic!("cannot return through eax"),
-                    };
+                    let result = self.register(x86::Register::Eax, self.f.registers[&reg].size);
                     let to = self.to_rm(reg);
                     self.emit_move(x86::Rm::Register(result), to, 4);
                 }
                 Instruction::CallProc(ref f, ref args) => {
-                    let additional_stack = self.emit_call_args(args);
-                    if additional_stack > 0 {
-                        self.emitter.emit(x86::Instruction::Sub(x86::TwoArgs::RmImm(
-                            x86::Rm::Register(x86::Register::Esp),
-                            x86::Immediate::Constant(u64::from(additional_stack)),
-                        )));
-                    }
-                    let f = x86::Immediate::Label(x86::Label::Named(f.0.clone()));
-                    self.emitter.emit(x86::Instruction::Call(f));
-                    if additional_stack > 0 {
-                        self.emitter.emit(x86::Instruction::Add(x86::TwoArgs::RmImm(
-                            x86::Rm::Register(x86::Register::Esp),
-                            x86::Immediate::Constant(u64::from(additional_stack)),
-                        )));
-                    }
+                    self.emit_proc_call(f, args);
                 }
                 Instruction::CallProcVirt(ref f, ref args) => {
                     let additional_stack = self.emit_call_args(args);
                     if additional_stack > 0 {
                         self.emitter.emit(x86::Instruction::Sub(x86::TwoArgs::RmImm(
-                            x86::Rm::Register(x86::Register::Esp),
+                            x86::Rm::Register(self.stack_pointer()),
                             x86::Immediate::Constant(u64::from(additional_stack)),
                         )));
                     }
@@ -626,7 +690,7 @@ impl<'a> FnCompiler<'a> {
                     }
                     if additional_stack > 0 {
                         self.emitter.emit(x86::Instruction::Add(x86::TwoArgs::RmImm(
-                            x86::Rm::Register(x86::Register::Esp),
+                            x86::Rm::Register(self.stack_pointer()),
                             x86::Immediate::Constant(u64::from(additional_stack)),
                         )));
                     }
@@ -635,7 +699,7 @@ impl<'a> FnCompiler<'a> {
                     let additional_stack = self.emit_call_args(args);
                     if additional_stack > 0 {
                         self.emitter.emit(x86::Instruction::Sub(x86::TwoArgs::RmImm(
-                            x86::Rm::Register(x86::Register::Esp),
+                            x86::Rm::Register(self.stack_pointer()),
                             x86::Immediate::Constant(u64::from(additional_stack)),
                         )));
                     }
@@ -650,24 +714,20 @@ impl<'a> FnCompiler<'a> {
                     }
                     if additional_stack > 0 {
                         self.emitter.emit(x86::Instruction::Add(x86::TwoArgs::RmImm(
-                            x86::Rm::Register(x86::Register::Esp),
+                            x86::Rm::Register(self.stack_pointer()),
                             x86::Immediate::Constant(u64::from(additional_stack)),
                         )));
                     }
-                    let result = match self.f.registers[&reg].size {
-                        1 => x86::Register::Al,
-                        2 => x86::Register::Ax,
-                        4 => x86::Register::Eax,
-                        _ => panic!("cannot return through eax"),
-                    };
+                    let result = self.register(x86::Register::Eax, self.f.registers[&reg].size);
                     let to = self.to_rm(reg);
                     self.emit_move(x86::Rm::Register(result), to, 4);
                 }
                 Instruction::DerefLoad(reg, ref address, offset) => {
                     let layout = self.f.registers[&reg];
-                    self.emit_assign(x86::Rm::Register(x86::Register::Eax), address, 4);
+                    let address_reg = self.register(x86::Register::Eax, self.word);
+                    self.emit_assign(x86::Rm::Register(address_reg), address, 4);
                     let from = x86::Memory {
-                        register: x86::Register::Eax,
+                        register: address_reg,
                         offset: offset as i32,
                         ptr_size: layout.size,
                     };
@@ -675,40 +735,23 @@ impl<'a> FnCompiler<'a> {
                     self.emit_move(x86::Rm::Memory(from), to, layout.align);
                 }
                 Instruction::DerefStore(ref address, offset, ref val) => {
-                    self.emit_assign(x86::Rm::Register(x86::Register::Eax), address, 4);
-                    let (to, align) = match *val {
+                    let address_reg = self.register(x86::Register::Eax, self.word);
+                    self.emit_assign(x86::Rm::Register(address_reg), address, 4);
+                    let (size, align) = match *val {
                         Value::Reg(r) => {
                             let layout = self.f.registers[&r];
-                            (x86::Memory {
-                                register: x86::Register::Eax,
-                                offset: offset as i32,
-                                ptr_size: layout.size,
-                            }, layout.align)
-                        }
-                        Value::Bytes(_) |
-                        Value::Symbol(_) |
-                        Value::Int(_, Size::Bit32) => {
-                            (x86::Memory {
-                                register: x86::Register::Eax,
-                                offset: offset as i32,
-                                ptr_size: 4,
-                            }, 4)
-                        }
-                        Value::Int(_, Size::Bit16) => {
-                            (x86::Memory {
-                                register: x86::Register::Eax,
-                                offset: offset as i32,
-                                ptr_size: 2,
-                            }, 2)
-                        }
-                        Value::Int(_, Size::Bit8) => {
-                            (x86::Memory {
-                                register: x86::Register::Eax,
-                                offset: offset as i32,
-                                ptr_size: 1,
-                            }, 1)
+                            (layout.size, layout.align)
                         }
                         Value::Undef => panic!("got undef value"),
+                        _ => {
+                            let size = self.value_size(val);
+                            (size, size)
+                        }
+                    };
+                    let to = x86::Memory {
+                        register: address_reg,
+                        offset: offset as i32,
+                        ptr_size: size,
                     };
                     let to = x86::Rm::Memory(to);
                     self.emit_assign(to, val, align);
@@ -722,6 +765,14 @@ impl<'a> FnCompiler<'a> {
         if let Value::Reg(from) = *from {
             let from = self.to_rm(from);
             self.emit_move(from, to, align);
+        } else if let (x86::Rm::Memory(mem), true) = (to, self.needs_register(from)) {
+            let temp = self.register(x86::Register::Edx, mem.ptr_size);
+            let imm = self.to_immediate(from);
+            self.emitter.emit(x86::Instruction::Mov(x86::TwoArgs::RmImm(
+                x86::Rm::Register(temp),
+                imm,
+            )));
+            self.emitter.emit(x86::Instruction::Mov(x86::TwoArgs::RmReg(to, temp)));
         } else {
             let args = x86::TwoArgs::RmImm(
                 to,
@@ -731,6 +782,37 @@ impl<'a> FnCompiler<'a> {
         }
     }
 
+    /// Whether `value` is a constant that does not fit into an
+    /// instruction, which only takes sign extended 32 bit immediates.
+    fn needs_register(&self, value: &Value) -> bool {
+        match *value {
+            Value::Int(val, Size::Bit64) => val as i64 != i64::from(val as i32),
+            _ => false,
+        }
+    }
+
+    /// Arguments for an instruction that combines `rm` with a
+    /// constant. Constants that do not fit into the instruction
+    /// are loaded to `temp` first.
+    fn immediate_args(
+        &mut self,
+        rm: x86::Rm,
+        value: &Value,
+        temp: x86::Register,
+    ) -> x86::TwoArgs {
+        let imm = self.to_immediate(value);
+        if self.needs_register(value) {
+            let temp = self.register(temp, 8);
+            self.emitter.emit(x86::Instruction::Mov(x86::TwoArgs::RmImm(
+                x86::Rm::Register(temp),
+                imm,
+            )));
+            x86::TwoArgs::RmReg(rm, temp)
+        } else {
+            x86::TwoArgs::RmImm(rm, imm)
+        }
+    }
+
     fn emit_move(&mut self, from: x86::Rm, to: x86::Rm, align: u32) {
         match (to, from) {
             (x86::Rm::Memory(mem), x86::Rm::Register(reg)) => {
@@ -768,9 +850,31 @@ impl<'a> FnCompiler<'a> {
                         gcd(b, a % b)
                     }
                 }
-                let block = gcd((align + 3) % 4 + 1, m1.ptr_size).min(m1.ptr_size).min(4);
+                let word = self.word;
+                let block = gcd((align + word - 1) % word + 1, m1.ptr_size)
+                    .min(m1.ptr_size)
+                    .min(word);
                 match block {
                     0 => {}
+                    8 => {
+                        let mm1 = x86::Memory { ptr_size: 8, .. m1 };
+                        let mm2 = x86::Memory { ptr_size: 8, .. m2 };
+                        let args = x86::TwoArgs::RegRm(
+                            x86::Register::Rdx,
+                            x86::Rm::Memory(mm2),
+                        );
+                        self.emitter.emit(x86::Instruction::Mov(args));
+                        let args = x86::TwoArgs::RmReg(
+                            x86::Rm::Memory(mm1),
+                            x86::Register::Rdx,
+                        );
+                        self.emitter.emit(x86::Instruction::Mov(args));
+                        m1.ptr_size -= 8;
+                        m2.ptr_size -= 8;
+                        m1.offset += 8;
+                        m2.offset += 8;
+                        self.emit_move(x86::Rm::Memory(m2), x86::Rm::Memory(m1), align);
+                    }
                     1 => {
                         let mm1 = x86::Memory { ptr_size: 1, .. m1 };
                         let mm2 = x86::Memory { ptr_size: 1, .. m2 };
@@ -833,39 +937,54 @@ impl<'a> FnCompiler<'a> {
         }
     }
 
+    fn emit_source_position(&mut self, position: Position) {
+        if self.debug && self.last_position != Some(position) {
+            self.emitter.emit(x86::Instruction::SourcePosition(position));
+            self.last_position = Some(position);
+        }
+    }
+
+    /// Where variables are kept, relative to the frame pointer
+    /// that is set up by the function intro.
+    fn variable_locations(&self) -> HashMap<Reg, x86::VariableLocation> {
+        let mut result = HashMap::new();
+        for (&reg, &loc) in &self.locations {
+            let location = match loc {
+                Location::Ebx => x86::VariableLocation::Register(x86::Register::Ebx),
+                Location::Ecx => x86::VariableLocation::Register(x86::Register::Ecx),
+                Location::Esi => x86::VariableLocation::Register(x86::Register::Esi),
+                Location::Edi => x86::VariableLocation::Register(x86::Register::Edi),
+                Location::Stack(offset) => {
+                    let offset = self.word as i32 - self.backup_space as i32 - offset as i32;
+                    x86::VariableLocation::Frame(offset)
+                }
+                Location::Param(offset) => {
+                    x86::VariableLocation::Frame((offset + self.word) as i32)
+                }
+                Location::Flags(_) => continue,
+            };
+            result.insert(reg, location);
+        }
+        result
+    }
+
     fn to_rm(&self, reg: Reg) -> x86::Rm {
         let size = self.f.registers[&reg].size;
         match self.locations[&reg] {
-            Location::Ebx => x86::Rm::Register(match size {
-                1 => x86::Register::Bl,
-                2 => x86::Register::Bx,
-                4 => x86::Register::Ebx,
-                _ => panic!("invalid register contents"),
-            }),
-            Location::Ecx => x86::Rm::Register(match size {
-                1 => x86::Register::Cl,
-                2 => x86::Register::Cx,
-                4 => x86::Register::Ecx,
-                _ => panic!("invalid register contents"),
-            }),
-            Location::Edi => {
-                debug_assert_eq!(size, 4);
-                x86::Rm::Register(x86::Register::Edi)
-            }
-            Location::Esi => {
-                debug_assert_eq!(size, 4);
-                x86::Rm::Register(x86::Register::Esi)
-            }
+            Location::Ebx => x86::Rm::Register(self.register(x86::Register::Ebx, size)),
+            Location::Ecx => x86::Rm::Register(self.register(x86::Register::Ecx, size)),
+            Location::Edi => x86::Rm::Register(self.register(x86::Register::Edi, size)),
+            Location::Esi => x86::Rm::Register(self.register(x86::Register::Esi, size)),
             Location::Stack(offset) => {
                 x86::Rm::Memory(x86::Memory {
-                    register: x86::Register::Esp,
+                    register: self.stack_pointer(),
                     offset: self.stack_size as i32 - self.backup_space as i32 - offset as i32,
                     ptr_size: size,
                 })
             }
             Location::Param(offset) => {
                 x86::Rm::Memory(x86::Memory {
-                    register: x86::Register::Esp,
+                    register: self.stack_pointer(),
                     offset: (offset + self.stack_size) as i32,
                     ptr_size: size,
                 })
@@ -879,7 +998,7 @@ impl<'a> FnCompiler<'a> {
             Value::Bytes(ref bytes) => self.emitter.make_string(bytes),
             Value::Int(val, _) => x86::Immediate::Constant(val),
             Value::Reg(_) => panic!("register cannot be immediate"),
-            Value::Symbol(ref sym) => x86::Immediate::Label(x86::Label::Named(sym.0.clone())),
+            Value::Symbol(ref sym) => x86::Immediate::Label(self.function_label(sym)),
             Value::Undef => panic!("got undef value"),
         }
     }
@@ -976,37 +1095,19 @@ impl<'a> FnCompiler<'a> {
             }
             BlockEnd::Return(ref val) => {
                 match *val {
-                    Value::Bytes(_) |
-                    Value::Symbol(_) => {
-                        self.emit_assign(x86::Rm::Register(x86::Register::Eax), val, 4);
-                    }
                     Value::Undef => panic!("got undef value"),
-                    Value::Int(val, size) => {
-                        let dest = match size {
-                            Size::Bit8 => x86::Register::Al,
-                            Size::Bit16 => x86::Register::Ax,
-                            Size::Bit32 => x86::Register::Eax,
-                        };
-                        self.emitter.emit(x86::Instruction::Mov(x86::TwoArgs::RmImm(
-                            x86::Rm::Register(dest),
-                            x86::Immediate::Constant(val),
-                        )));
-                    }
-                    Value::Reg(r) => {
-                        let size = self.f.registers[&r].size;
-                        let dest = match size {
-                            1 => x86::Register::Al,
-                            2 => x86::Register::Ax,
-                            4 => x86::Register::Eax,
-                            _ => panic!("cannot return value of size {}", size),
-                        };
+                    Value::Bytes(_) |
+                    Value::Symbol(_) |
+                    Value::Int(_, _) |
+                    Value::Reg(_) => {
+                        let dest = self.register(x86::Register::Eax, self.value_size(val));
                         self.emit_assign(x86::Rm::Register(dest), val, 4);
                     }
                 }
                 self.stack_size -= self.backup_space;
                 if self.stack_size > 0 {
                     self.emitter.emit(x86::Instruction::Add(x86::TwoArgs::RmImm(
-                        x86::Rm::Register(x86::Register::Esp),
+                        x86::Rm::Register(self.stack_pointer()),
                         x86::Immediate::Constant(u64::from(self.stack_size)),
                     )));
                 }
@@ -1018,7 +1119,7 @@ impl<'a> FnCompiler<'a> {
                 self.stack_size -= self.backup_space;
                 if self.stack_size > 0 {
                     self.emitter.emit(x86::Instruction::Add(x86::TwoArgs::RmImm(
-                        x86::Rm::Register(x86::Register::Esp),
+                        x86::Rm::Register(self.stack_pointer()),
                         x86::Immediate::Constant(u64::from(self.stack_size)),
                     )));
                 }
@@ -1033,17 +1134,21 @@ impl<'a> FnCompiler<'a> {
     }
 
     fn emit_register_restores(&mut self) {
-        if self.is_location_used(Location::Edi) {
-            self.emitter.emit(x86::Instruction::Pop(x86::Rm::Register(x86::Register::Edi)));
-        }
-        if self.is_location_used(Location::Esi) {
-            self.emitter.emit(x86::Instruction::Pop(x86::Rm::Register(x86::Register::Esi)));
-        }
-        if self.is_location_used(Location::Ecx) {
-            self.emitter.emit(x86::Instruction::Pop(x86::Rm::Register(x86::Register::Ecx)));
+        let saved = [
+            (Location::Edi, x86::Register::Edi),
+            (Location::Esi, x86::Register::Esi),
+            (Location::Ecx, x86::Register::Ecx),
+            (Location::Ebx, x86::Register::Ebx),
+        ];
+        for &(loc, reg) in &saved {
+            if self.is_location_used(loc) {
+                let reg = self.register(reg, self.word);
+                self.emitter.emit(x86::Instruction::Pop(x86::Rm::Register(reg)));
+            }
         }
-        if self.is_location_used(Location::Ebx) {
-            self.emitter.emit(x86::Instruction::Pop(x86::Rm::Register(x86::Register::Ebx)));
+        if self.debug {
+            let frame = self.register(x86::Register::Ebp, self.word);
+            self.emitter.emit(x86::Instruction::Pop(x86::Rm::Register(frame)));
         }
     }
 
@@ -1053,7 +1158,7 @@ impl<'a> FnCompiler<'a> {
             x86::Rm::Memory(mem) => mem,
         };
         address.offset += offset as i32;
-        address.ptr_size = 4;
+        address.ptr_size = self.word;
         match self.to_rm(to) {
             x86::Rm::Register(reg) => {
                 self.emitter.emit(x86::Instruction::Lea(
@@ -1062,13 +1167,14 @@ impl<'a> FnCompiler<'a> {
                 ));
             }
             x86::Rm::Memory(mem) => {
+                let temp = self.register(x86::Register::Edx, self.word);
                 self.emitter.emit(x86::Instruction::Lea(
-                    x86::Register::Edx,
+                    temp,
                     address,
                 ));
                 let args = x86::TwoArgs::RmReg(
                     x86::Rm::Memory(mem),
-                    x86::Register::Edx,
+                    temp,
                 );
                 self.emitter.emit(x86::Instruction::Mov(args));
             }
@@ -1082,11 +1188,75 @@ impl<'a> FnCompiler<'a> {
                 self.emit_assign(to, arg, 4);
                 self.emitter.emit(x86::Instruction::Neg(to));
             }
+            UnaryOp::Extend(sign, from, size) => {
+                let to = self.to_rm(to);
+                let from_reg = match *arg {
+                    Value::Reg(r) => self.to_rm(r),
+                    Value::Int(val, _) => {
+                        let val = from.extend(val, sign, size);
+                        self.emit_assign(to, &Value::Int(val, size), 4);
+                        return;
+                    }
+                    _ => panic!("cannot extend {:?}", arg),
+                };
+                let temp = match size {
+                    Size::Bit16 => x86::Register::Ax,
+                    Size::Bit64 => self.register(x86::Register::Eax, 8),
+                    _ => x86::Register::Eax,
+                };
+                let op = match (sign, from) {
+                    // writing to a 32 bit register clears the upper half
+                    (Signedness::Unsigned, Size::Bit32) => {
+                        x86::Instruction::Mov(x86::TwoArgs::RegRm(x86::Register::Eax, from_reg))
+                    }
+                    (Signedness::Signed, _) => x86::Instruction::MovSX(temp, from_reg),
+                    (Signedness::Unsigned, _) => x86::Instruction::MovZX(temp, from_reg),
+                };
+                self.emitter.emit(op);
+                self.emit_move(x86::Rm::Register(temp), to, 4);
+            }
         }
     }
 
     fn emit_binary_op(&mut self, to: Reg, op: BinaryOp, a: &Value, b: &Value) {
         match op {
+            BinaryOp::Checked(_, _, _, _) => self.emit_checked_op(to, op, a, b, None),
+            BinaryOp::BitOp(BitOp::Shl, size) |
+            BinaryOp::BitOp(BitOp::Shr, size) => {
+                let acc = self.register(x86::Register::Eax, size.in_bytes());
+                let count = self.register(x86::Register::Ecx, size.in_bytes());
+                let shift = match op {
+                    BinaryOp::BitOp(BitOp::Shl, _) =>
+                        x86::Instruction::Shl as fn(_) -> x86::Instruction,
+                    _ => x86::Instruction::Shr as fn(_) -> x86::Instruction,
+                };
+                self.emit_assign(x86::Rm::Register(acc), a, 4);
+                if let Value::Reg(r) = *b {
+                    // count has to be in cl, which might hold another
+                    // register, so keep ecx in edx meanwhile
+                    let saved = self.register(x86::Register::Ecx, self.word);
+                    let temp = self.register(x86::Register::Edx, self.word);
+                    self.emitter.emit(x86::Instruction::Mov(x86::TwoArgs::RegRm(
+                        temp,
+                        x86::Rm::Register(saved),
+                    )));
+                    let b = self.to_rm(r);
+                    self.emit_move(b, x86::Rm::Register(count), 4);
+                    self.emitter.emit(shift(x86::TwoArgs::RmReg(
+                        x86::Rm::Register(acc),
+                        x86::Register::Cl,
+                    )));
+                    self.emitter.emit(x86::Instruction::Mov(x86::TwoArgs::RegRm(
+                        saved,
+                        x86::Rm::Register(temp),
+                    )));
+                } else {
+                    let count = self.to_immediate(b);
+                    self.emitter.emit(shift(x86::TwoArgs::RmImm(x86::Rm::Register(acc), count)));
+                }
+                let to = self.to_rm(to);
+                self.emit_move(x86::Rm::Register(acc), to, 4);
+            }
             BinaryOp::BitOp(op, _) => {
                 let dest = self.locations[&to];
                 let to = self.to_rm(to);
@@ -1107,12 +1277,7 @@ impl<'a> FnCompiler<'a> {
                             x86::TwoArgs::RmReg(to, r)
                         }
                         (x86::Rm::Memory(to), x86::Rm::Memory(r)) => {
-                            let temp = match r.ptr_size {
-                                1 => x86::Register::Dl,
-                                2 => x86::Register::Dx,
-                                4 => x86::Register::Edx,
-                                _ => panic!("bad param size"),
-                            };
+                            let temp = self.register(x86::Register::Edx, r.ptr_size);
                             let args = x86::TwoArgs::RegRm(
                                 temp,
                                 x86::Rm::Memory(r),
@@ -1125,12 +1290,13 @@ impl<'a> FnCompiler<'a> {
                         }
                     }
                 } else {
-                    x86::TwoArgs::RmImm(to, self.to_immediate(b))
+                    self.immediate_args(to, b, x86::Register::Edx)
                 };
                 match op {
                     BitOp::And => self.emitter.emit(x86::Instruction::And(args)),
                     BitOp::Or => self.emitter.emit(x86::Instruction::Or(args)),
                     BitOp::Xor => self.emitter.emit(x86::Instruction::Xor(args)),
+                    BitOp::Shl | BitOp::Shr => unreachable!(),
                 }
             }
             BinaryOp::IntOp(IntOp::Greater, _, size) |
@@ -1163,11 +1329,7 @@ impl<'a> FnCompiler<'a> {
                         *c = cond;
                     }
                 }
-                let temp = match size {
-                    Size::Bit8 => x86::Register::Dl,
-                    Size::Bit16 => x86::Register::Dx,
-                    Size::Bit32 => x86::Register::Edx,
-                };
+                let temp = self.register(x86::Register::Edx, size.in_bytes());
                 match (a, b) {
                     (&Value::Reg(a), &Value::Reg(b)) => {
                         let a = self.to_rm(a);
@@ -1195,18 +1357,14 @@ impl<'a> FnCompiler<'a> {
                         self.emitter.emit(x86::Instruction::Cmp(args));
                     }
                     (&Value::Reg(a), imm) => {
-                        let args = x86::TwoArgs::RmImm(
-                            self.to_rm(a),
-                            self.to_immediate(imm),
-                        );
+                        let a = self.to_rm(a);
+                        let args = self.immediate_args(a, imm, x86::Register::Edx);
                         self.emitter.emit(x86::Instruction::Cmp(args));
                     }
                     (imma, immb) => {
                         self.emit_assign(x86::Rm::Register(temp), imma, 4);
-                        let args = x86::TwoArgs::RmImm(
-                            x86::Rm::Register(temp),
-                            self.to_immediate(immb),
-                        );
+                        let temp = x86::Rm::Register(temp);
+                        let args = self.immediate_args(temp, immb, x86::Register::Eax);
                         self.emitter.emit(x86::Instruction::Cmp(args));
                     }
                 }
@@ -1230,11 +1388,7 @@ impl<'a> FnCompiler<'a> {
                             x86::TwoArgs::RmReg(to, r)
                         }
                         (x86::Rm::Memory(to), x86::Rm::Memory(r)) => {
-                            let temp = match size {
-                                Size::Bit8 => x86::Register::Dl,
-                                Size::Bit16 => x86::Register::Dx,
-                                Size::Bit32 => x86::Register::Edx,
-                            };
+                            let temp = self.register(x86::Register::Edx, size.in_bytes());
                             let args = x86::TwoArgs::RegRm(
                                 temp,
                                 x86::Rm::Memory(r),
@@ -1247,10 +1401,7 @@ impl<'a> FnCompiler<'a> {
                         }
                     }
                 } else {
-                    x86::TwoArgs::RmImm(
-                        to,
-                        self.to_immediate(b),
-                    )
+                    self.immediate_args(to, b, x86::Register::Edx)
                 };
                 match op {
                     BinaryOp::IntOp(IntOp::Add, _, _) => {
@@ -1263,11 +1414,8 @@ impl<'a> FnCompiler<'a> {
                 }
             }
             BinaryOp::IntOp(IntOp::Mul, Signedness::Unsigned, size) => {
-                let (areg, temp) = match size {
-                    Size::Bit8 => (x86::Register::Al, x86::Register::Dl),
-                    Size::Bit16 => (x86::Register::Ax, x86::Register::Dx),
-                    Size::Bit32 => (x86::Register::Eax, x86::Register::Edx),
-                };
+                let areg = self.register(x86::Register::Eax, size.in_bytes());
+                let temp = self.register(x86::Register::Edx, size.in_bytes());
                 self.emit_assign(x86::Rm::Register(areg), a, 4);
                 let arg = if let Value::Reg(b) = *b {
                     self.to_rm(b)
@@ -1281,11 +1429,8 @@ impl<'a> FnCompiler<'a> {
             }
             BinaryOp::IntOp(IntOp::Mul, Signedness::Signed, size) => {
                 // TODO: make use of `imul reg, rm`
-                let (areg, temp) = match size {
-                    Size::Bit8 => (x86::Register::Al, x86::Register::Dl),
-                    Size::Bit16 => (x86::Register::Ax, x86::Register::Dx),
-                    Size::Bit32 => (x86::Register::Eax, x86::Register::Edx),
-                };
+                let areg = self.register(x86::Register::Eax, size.in_bytes());
+                let temp = self.register(x86::Register::Edx, size.in_bytes());
                 self.emit_assign(x86::Rm::Register(areg), a, 4);
                 let arg = if let Value::Reg(b) = *b {
                     self.to_rm(b)
@@ -1360,13 +1505,12 @@ impl<'a> FnCompiler<'a> {
             BinaryOp::IntOp(IntOp::Div, Signedness::Signed, _) |
             BinaryOp::IntOp(IntOp::Mod, Signedness::Unsigned, _) |
             BinaryOp::IntOp(IntOp::Mod, Signedness::Signed, _) => {
-                let (areg, dreg) = match op {
-                    BinaryOp::IntOp(_, _, Size::Bit16) =>
-                        (x86::Register::Ax, x86::Register::Dx),
-                    BinaryOp::IntOp(_, _, Size::Bit32) =>
-                        (x86::Register::Eax, x86::Register::Edx),
+                let size = match op {
+                    BinaryOp::IntOp(_, _, size) => size.in_bytes(),
                     _ => panic!("shit, got {:?}", op),
                 };
+                let areg = self.register(x86::Register::Eax, size);
+                let dreg = self.register(x86::Register::Edx, size);
                 let signed = match op {
                     BinaryOp::IntOp(_, Signedness::Unsigned, _) => false,
                     BinaryOp::IntOp(_, Signedness::Signed, _) => true,
@@ -1385,11 +1529,25 @@ impl<'a> FnCompiler<'a> {
                         imm,
                     )));
                 }
+                // divisor constant is stored below the stack before
+                // edx is set, because large constants go through edx
+                let divisor = if let Value::Reg(r) = *b {
+                    self.to_rm(r)
+                } else {
+                    // TODO: check if temporary register is available and use that
+                    let temp = x86::Rm::Memory(x86::Memory {
+                        register: self.stack_pointer(),
+                        offset: -(size as i32),
+                        ptr_size: size,
+                    });
+                    self.emit_assign(temp, b, 4);
+                    temp
+                };
                 if signed {
-                    if areg == x86::Register::Ax {
-                        self.emitter.emit(x86::Instruction::Cwd);
-                    } else {
-                        self.emitter.emit(x86::Instruction::Cdq);
+                    match size {
+                        2 => self.emitter.emit(x86::Instruction::Cwd),
+                        4 => self.emitter.emit(x86::Instruction::Cdq),
+                        _ => self.emitter.emit(x86::Instruction::Cqo),
                     }
                 } else {
                     self.emitter.emit(x86::Instruction::Xor(x86::TwoArgs::RegRm(
@@ -1408,19 +1566,7 @@ impl<'a> FnCompiler<'a> {
                         (x86::Instruction::Idiv as fn(_) -> x86::Instruction, true),
                     _ => panic!("shit, got {:?}", op),
                 };
-                if let Value::Reg(r) = *b {
-                    let rm = self.to_rm(r);
-                    self.emitter.emit(op(rm));
-                } else {
-                    // TODO: check if temporary register is available and use that
-                    let temp = x86::Rm::Memory(x86::Memory {
-                        register: x86::Register::Esp,
-                        offset: -4,
-                        ptr_size: if areg == x86::Register::Ax { 2 } else { 4 },
-                    });
-                    self.emit_assign(temp, b, 4);
-                    self.emitter.emit(op(temp));
-                }
+                self.emitter.emit(op(divisor));
                 let to = self.to_rm(to);
                 let from = if from_dx { dreg } else { areg };
                 self.emit_move(x86::Rm::Register(from), to, 4);
@@ -1439,31 +1585,15 @@ impl<'a> FnCompiler<'a> {
                         let br = self.to_rm(br);
                         self.emit_compare(ar, br, eq);
                     } else {
-                        let imm = self.to_immediate(b);
-                        self.emitter.emit(x86::Instruction::Cmp(x86::TwoArgs::RmImm(
-                            ar,
-                            imm,
-                        )));
+                        let args = self.immediate_args(ar, b, x86::Register::Edx);
+                        self.emitter.emit(x86::Instruction::Cmp(args));
                     }
                 } else {
-                    let temp = match *a {
-                        Value::Bytes(_) => x86::Register::Edx,
-                        Value::Int(_, Size::Bit8) => x86::Register::Dl,
-                        Value::Int(_, Size::Bit16) => x86::Register::Dx,
-                        Value::Int(_, Size::Bit32) => x86::Register::Edx,
-                        Value::Symbol(_) => x86::Register::Edx,
-                        _ => panic!("shit, got {:?}", a),
-                    };
-                    let a = self.to_immediate(a);
-                    let b = self.to_immediate(b);
-                    self.emitter.emit(x86::Instruction::Mov(x86::TwoArgs::RmImm(
-                        x86::Rm::Register(temp),
-                        a,
-                    )));
-                    self.emitter.emit(x86::Instruction::Cmp(x86::TwoArgs::RmImm(
-                        x86::Rm::Register(temp),
-                        b,
-                    )));
+                    let temp = self.register(x86::Register::Edx, self.value_size(a));
+                    self.emit_assign(x86::Rm::Register(temp), a, 4);
+                    let temp = x86::Rm::Register(temp);
+                    let args = self.immediate_args(temp, b, x86::Register::Eax);
+                    self.emitter.emit(x86::Instruction::Cmp(args));
                 }
                 let cond = if eq {
                     x86::Condition::Equal
@@ -1480,6 +1610,192 @@ impl<'a> FnCompiler<'a> {
         }
     }
 
+    /// Arithmetic that doesn't wrap around. Overflow that traps is reported
+    /// by the prelude together with the operands and `span`.
+    fn emit_checked_op(
+        &mut self,
+        to: Reg,
+        op: BinaryOp,
+        a: &Value,
+        b: &Value,
+        span: Option<Span>,
+    ) {
+        let (op, overflow, sign, size) = match op {
+            BinaryOp::Checked(op, overflow, sign, size) => (op, overflow, sign, size),
+            _ => panic!("not checked arithmetic: {:?}", op),
+        };
+        let (min, max) = match sign {
+            Signedness::Unsigned => (0, size.truncate(!0)),
+            Signedness::Signed => {
+                let max = size.truncate(!0) >> 1;
+                (max + 1, max)
+            }
+        };
+        // mul and imul set both carry and overflow flags
+        let fits = match sign {
+            Signedness::Unsigned => x86::Condition::AboveEqual,
+            Signedness::Signed => x86::Condition::NoOverflow,
+        };
+        let end = self.emitter.make_label();
+        match op {
+            IntOp::Add | IntOp::Sub if overflow == Overflow::Trap => {
+                // checked in a scratch register first, so that the operands
+                // can still be reported if the result doesn't fit
+                let acc = self.register(x86::Register::Eax, size.in_bytes());
+                self.emit_assign(x86::Rm::Register(acc), a, 4);
+                let args = if let Value::Reg(b) = *b {
+                    x86::TwoArgs::RegRm(acc, self.to_rm(b))
+                } else {
+                    self.immediate_args(x86::Rm::Register(acc), b, x86::Register::Edx)
+                };
+                match op {
+                    IntOp::Add => self.emitter.emit(x86::Instruction::Add(args)),
+                    _ => self.emitter.emit(x86::Instruction::Sub(args)),
+                }
+                self.emitter.emit(x86::Instruction::Jcc(fits, end.clone()));
+                self.emit_overflow_fail(op, sign, size, a, b, span);
+                self.emitter.emit(x86::Instruction::Label(end));
+                self.emit_binary_op(to, BinaryOp::IntOp(op, sign, size), a, b);
+            }
+            IntOp::Add | IntOp::Sub => {
+                self.emit_binary_op(to, BinaryOp::IntOp(op, sign, size), a, b);
+                let to = self.to_rm(to);
+                self.emitter.emit(x86::Instruction::Jcc(fits, end.clone()));
+                match (overflow, sign) {
+                    (Overflow::Trap, _) => unreachable!(),
+                    (Overflow::Saturate, Signedness::Unsigned) => {
+                        let limit = if op == IntOp::Add { max } else { min };
+                        self.emit_assign(to, &Value::Int(limit, size), 4);
+                    }
+                    (Overflow::Saturate, Signedness::Signed) => {
+                        // wrapped result has the opposite sign of the real one
+                        let zero = x86::Immediate::Constant(0);
+                        self.emitter.emit(x86::Instruction::Cmp(x86::TwoArgs::RmImm(to, zero)));
+                        self.emit_assign(to, &Value::Int(max, size), 4);
+                        let negative = x86::Instruction::Jcc(x86::Condition::Less, end.clone());
+                        self.emitter.emit(negative);
+                        self.emit_assign(to, &Value::Int(min, size), 4);
+                    }
+                }
+                self.emitter.emit(x86::Instruction::Label(end));
+            }
+            IntOp::Mul => {
+                let areg = self.register(x86::Register::Eax, size.in_bytes());
+                let temp = self.register(x86::Register::Edx, size.in_bytes());
+                self.emit_assign(x86::Rm::Register(areg), a, 4);
+                let arg = if let Value::Reg(b) = *b {
+                    self.to_rm(b)
+                } else {
+                    self.emit_assign(x86::Rm::Register(temp), b, 4);
+                    x86::Rm::Register(temp)
+                };
+                match sign {
+                    Signedness::Unsigned => self.emitter.emit(x86::Instruction::Mul(arg)),
+                    Signedness::Signed => self.emitter.emit(x86::Instruction::Imul(arg)),
+                }
+                self.emitter.emit(x86::Instruction::Jcc(fits, end.clone()));
+                let acc = x86::Rm::Register(areg);
+                match (overflow, sign) {
+                    // mul only wrote to eax and edx, so the operands are intact
+                    (Overflow::Trap, _) => self.emit_overflow_fail(op, sign, size, a, b, span),
+                    (Overflow::Saturate, Signedness::Unsigned) => {
+                        self.emit_assign(acc, &Value::Int(max, size), 4);
+                    }
+                    (Overflow::Saturate, Signedness::Signed) => {
+                        // high half of the product has the sign of the real result
+                        let high = if size == Size::Bit8 { x86::Register::Ah } else { temp };
+                        let zero = x86::Immediate::Constant(0);
+                        let args = x86::TwoArgs::RmImm(x86::Rm::Register(high), zero);
+                        self.emitter.emit(x86::Instruction::Cmp(args));
+                        self.emit_assign(acc, &Value::Int(min, size), 4);
+                        let negative = x86::Instruction::Jcc(x86::Condition::Less, end.clone());
+                        self.emitter.emit(negative);
+                        self.emit_assign(acc, &Value::Int(max, size), 4);
+                    }
+                }
+                self.emitter.emit(x86::Instruction::Label(end));
+                let args = x86::TwoArgs::RmReg(self.to_rm(to), areg);
+                self.emitter.emit(x86::Instruction::Mov(args));
+            }
+            IntOp::Div if sign == Signedness::Signed => {
+                // idiv faults when the result doesn't fit, so division by -1
+                // is done with neg instead, which sets the overflow flag
+                let minus_one = size.truncate(!0);
+                let divide = self.emitter.make_label();
+                match *b {
+                    Value::Int(value, _) if size.truncate(value) != minus_one => {
+                        self.emit_binary_op(to, BinaryOp::IntOp(op, sign, size), a, b);
+                        return;
+                    }
+                    Value::Int(_, _) => {}
+                    Value::Reg(r) => {
+                        let r = self.to_rm(r);
+                        let imm = x86::Immediate::Constant(minus_one);
+                        self.emitter.emit(x86::Instruction::Cmp(x86::TwoArgs::RmImm(r, imm)));
+                        let other = x86::Condition::NotEqual;
+                        self.emitter.emit(x86::Instruction::Jcc(other, divide.clone()));
+                    }
+                    _ => panic!("dividing by {:?}", b),
+                }
+                let to_rm = self.to_rm(to);
+                self.emit_assign(to_rm, a, 4);
+                self.emitter.emit(x86::Instruction::Neg(to_rm));
+                self.emitter.emit(x86::Instruction::Jcc(fits, end.clone()));
+                match overflow {
+                    // only the smallest value divided by -1 gets here
+                    Overflow::Trap => {
+                        let a = Value::Int(min, size);
+                        let b = Value::Int(minus_one, size);
+                        self.emit_overflow_fail(op, sign, size, &a, &b, span);
+                    }
+                    Overflow::Saturate => self.emit_assign(to_rm, &Value::Int(max, size), 4),
+                }
+                if let Value::Reg(_) = *b {
+                    self.emitter.emit(x86::Instruction::Jmp(end.clone()));
+                    self.emitter.emit(x86::Instruction::Label(divide));
+                    self.emit_binary_op(to, BinaryOp::IntOp(op, sign, size), a, b);
+                }
+                self.emitter.emit(x86::Instruction::Label(end));
+            }
+            _ => self.emit_binary_op(to, BinaryOp::IntOp(op, sign, size), a, b),
+        }
+    }
+
+    /// Calls `builtin_overflow_fail` in the prelude, which writes a message
+    /// like `arithmetic overflow: 2147483647 + 1 in i32` and exits.
+    fn emit_overflow_fail(
+        &mut self,
+        op: IntOp,
+        sign: Signedness,
+        size: Size,
+        a: &Value,
+        b: &Value,
+        span: Option<Span>,
+    ) {
+        let symbol = match op {
+            IntOp::Add => b'+',
+            IntOp::Sub => b'-',
+            IntOp::Mul => b'*',
+            IntOp::Div => b'/',
+            _ => panic!("{:?} doesn't overflow", op),
+        };
+        let kind = match sign {
+            Signedness::Signed => b'i',
+            Signedness::Unsigned => b'u',
+        };
+        let (line, column) = reported_position(span);
+        let args = [
+            a.clone(),
+            b.clone(),
+            Value::Int(u64::from(symbol), Size::Bit32),
+            Value::Int(u64::from(kind), Size::Bit32),
+            Value::Int(u64::from(size.in_bytes() * 8), Size::Bit32),
+            Value::Int(u64::from(line), Size::Bit32),
+            Value::Int(u64::from(column), Size::Bit32),
+        ];
+        self.emit_proc_call(&Symbol("builtin_overflow_fail".into()), &args);
+    }
+
     fn emit_compare(&mut self, a: x86::Rm, b: x86::Rm, eq: bool) {
         match (a, b) {
             (x86::Rm::Memory(mem), x86::Rm::Register(reg)) => {
@@ -1507,18 +1823,22 @@ impl<'a> FnCompiler<'a> {
                 debug_assert_eq!(m1.ptr_size, m2.ptr_size);
                 let end_label = self.emitter.make_label();
                 while m1.ptr_size > 0 {
+                    let chunk = if m1.ptr_size >= self.word { self.word } else { 4 };
+                    let temp = self.register(x86::Register::Edx, chunk);
                     let args = x86::TwoArgs::RegRm(
-                        x86::Register::Edx,
-                        x86::Rm::Memory(m2),
+                        temp,
+                        x86::Rm::Memory(x86::Memory { ptr_size: chunk, .. m2 }),
                     );
                     self.emitter.emit(x86::Instruction::Mov(args));
                     let args = x86::TwoArgs::RmReg(
-                        x86::Rm::Memory(m1),
-                        x86::Register::Edx,
+                        x86::Rm::Memory(x86::Memory { ptr_size: chunk, .. m1 }),
+                        temp,
                     );
                     self.emitter.emit(x86::Instruction::Cmp(args));
-                    m1.ptr_size -= 4;
-                    m2.ptr_size -= 4;
+                    m1.ptr_size -= chunk;
+                    m2.ptr_size -= chunk;
+                    m1.offset += chunk as i32;
+                    m2.offset += chunk as i32;
                     if m1.ptr_size > 0 {
                         self.emitter.emit(x86::Instruction::Jcc(
                             if eq {
@@ -1536,66 +1856,165 @@ impl<'a> FnCompiler<'a> {
     }
 
     fn emit_call_args(&mut self, args: &[Value]) -> u32 {
-        let mut total_size = 0u32;
-        for arg in args {
-            match *arg {
-                Value::Bytes(_) |
-                Value::Int(_, _) |
-                Value::Symbol(_) => total_size += 4,
-                Value::Reg(r) => total_size += (self.f.registers[&r].size + 3) / 4 * 4,
-                Value::Undef => panic!("got undef"),
-            }
-        }
+        // every argument takes up a whole number of words
+        let word = self.word;
+        let padded_size = |size: u32| (size + word - 1) / word * word;
+        let total_size = args
+            .iter()
+            .map(|arg| padded_size(self.value_size(arg)))
+            .sum::<u32>();
         let mut offset = 0;
         for arg in args {
-            let to = match *arg {
-                Value::Bytes(_) |
-                Value::Int(_, Size::Bit32) |
-                Value::Symbol(_) => {
-                    offset += 4;
-                    x86::Memory {
-                        register: x86::Register::Esp,
-                        offset: (offset - 4) as i32 - total_size as i32,
-                        ptr_size: 4,
-                    }
-                }
-                Value::Int(_, Size::Bit16) => {
-                    offset += 4;
-                    x86::Memory {
-                        register: x86::Register::Esp,
-                        offset: (offset - 4) as i32 - total_size as i32,
-                        ptr_size: 2,
-                    }
-                }
-                Value::Int(_, Size::Bit8) => {
-                    offset += 4;
-                    x86::Memory {
-                        register: x86::Register::Esp,
-                        offset: (offset - 4) as i32 - total_size as i32,
-                        ptr_size: 1,
-                    }
-                }
-                Value::Reg(r) => {
-                    let size = self.f.registers[&r].size;
-                    let padded = (size + 3) / 4 * 4;
-                    offset += padded;
-                    x86::Memory {
-                        register: x86::Register::Esp,
-                        offset: (offset - padded) as i32 - total_size as i32,
-                        ptr_size: size,
-                    }
-                }
-                Value::Undef => panic!("got undef"),
+            let size = self.value_size(arg);
+            let to = x86::Memory {
+                register: self.stack_pointer(),
+                offset: offset as i32 - total_size as i32,
+                ptr_size: size,
             };
+            offset += padded_size(size);
             self.emit_assign(x86::Rm::Memory(to), arg, 4);
         }
         total_size
     }
 
+    fn emit_proc_call(&mut self, f: &Symbol, args: &[Value]) {
+        let additional_stack = self.emit_call_args(args);
+        if additional_stack > 0 {
+            self.emitter.emit(x86::Instruction::Sub(x86::TwoArgs::RmImm(
+                x86::Rm::Register(self.stack_pointer()),
+                x86::Immediate::Constant(u64::from(additional_stack)),
+            )));
+        }
+        let f = x86::Immediate::Label(x86::Label::Named(f.0.clone()));
+        self.emitter.emit(x86::Instruction::Call(f));
+        if additional_stack > 0 {
+            self.emitter.emit(x86::Instruction::Add(x86::TwoArgs::RmImm(
+                x86::Rm::Register(self.stack_pointer()),
+                x86::Immediate::Constant(u64::from(additional_stack)),
+            )));
+        }
+    }
+
+    fn is_foreign(&self, f: &Symbol) -> bool {
+        self.calling_convention(f) != CallingConvention::Stack
+    }
+
+    /// Calls a function with a foreign calling convention. Arguments
+    /// are first stored below the stack pointer as for other calls, and
+    /// then moved to where the callee expects them, with the stack aligned
+    /// to 16 bytes. Registers that plank functions preserve, but foreign
+    /// ones don't, are saved next to the arguments.
+    fn emit_foreign_call(&mut self, f: &Symbol, args: &[Value]) {
+        use x86::Register::*;
+        let word = self.word;
+        let total_size = self.emit_call_args(args);
+        let arg_registers: &[x86::Register] = match word {
+            8 => &[Rdi, Rsi, Rdx, Rcx, R8, R9],
+            _ => &[],
+        };
+        let saved_registers: &[(Location, x86::Register)] = match word {
+            8 => &[(Location::Ecx, Rcx), (Location::Esi, Rsi), (Location::Edi, Rdi)],
+            _ => &[(Location::Ecx, Ecx)],
+        };
+        let saved = saved_registers
+            .iter()
+            .filter(|&&(loc, _)| self.is_location_used(loc))
+            .map(|&(_, reg)| reg)
+            .collect::<Vec<_>>();
+        let in_registers = args.len().min(arg_registers.len());
+        let stack_args_size = (args.len() - in_registers) as u32 * word;
+        let saved_offset = stack_args_size;
+        let old_sp_offset = saved_offset + saved.len() as u32 * word;
+
+        let sp = self.stack_pointer();
+        let old_sp = self.register(Eax, word);
+        let scratch = self.register(Edx, word);
+        // every argument is a scalar, and so takes up exactly one word
+        let arg_memory = args
+            .iter()
+            .enumerate()
+            .map(|(index, arg)| {
+                let size = self.value_size(arg);
+                assert!(size <= word, "foreign function takes a {} byte argument", size);
+                x86::Memory {
+                    register: old_sp,
+                    offset: (index as u32 * word) as i32 - total_size as i32,
+                    ptr_size: size,
+                }
+            })
+            .collect::<Vec<_>>();
+        let at = |offset: u32, ptr_size: u32| {
+            x86::Rm::Memory(x86::Memory {
+                register: sp,
+                offset: offset as i32,
+                ptr_size,
+            })
+        };
+
+        let reserved = total_size + old_sp_offset + word;
+        let mov = x86::TwoArgs::RegRm(old_sp, x86::Rm::Register(sp));
+        self.emitter.emit(x86::Instruction::Mov(mov));
+        self.emitter.emit(x86::Instruction::Sub(x86::TwoArgs::RmImm(
+            x86::Rm::Register(sp),
+            x86::Immediate::Constant(u64::from(reserved)),
+        )));
+        let align_mask = if word == 8 { !15 } else { u64::from(!15u32) };
+        self.emitter.emit(x86::Instruction::And(x86::TwoArgs::RmImm(
+            x86::Rm::Register(sp),
+            x86::Immediate::Constant(align_mask),
+        )));
+        for (index, &reg) in saved.iter().enumerate() {
+            let to = at(saved_offset + index as u32 * word, word);
+            self.emitter.emit(x86::Instruction::Mov(x86::TwoArgs::RmReg(to, reg)));
+        }
+        let to = at(old_sp_offset, word);
+        self.emitter.emit(x86::Instruction::Mov(x86::TwoArgs::RmReg(to, old_sp)));
+        for index in in_registers..arg_memory.len() {
+            self.emit_load_extended(scratch, arg_memory[index]);
+            let to = at((index - in_registers) as u32 * word, word);
+            self.emitter.emit(x86::Instruction::Mov(x86::TwoArgs::RmReg(to, scratch)));
+        }
+        for (index, &reg) in arg_registers.iter().enumerate().take(in_registers) {
+            self.emit_load_extended(reg, arg_memory[index]);
+        }
+        if word == 8 {
+            // variadic functions take the number of vector registers used in al
+            let mov = x86::TwoArgs::RmImm(x86::Rm::Register(Eax), x86::Immediate::Constant(0));
+            self.emitter.emit(x86::Instruction::Mov(mov));
+        }
+        let label = self.function_label(f);
+        self.emitter.emit(x86::Instruction::Call(x86::Immediate::Label(label)));
+
+        // with stdcall the callee removes its arguments from the stack
+        let popped = match (self.calling_convention(f), word) {
+            (CallingConvention::Stdcall, 4) => stack_args_size,
+            _ => 0,
+        };
+        for (index, &reg) in saved.iter().enumerate() {
+            let from = at(saved_offset - popped + index as u32 * word, word);
+            self.emitter.emit(x86::Instruction::Mov(x86::TwoArgs::RegRm(reg, from)));
+        }
+        let from = at(old_sp_offset - popped, word);
+        self.emitter.emit(x86::Instruction::Mov(x86::TwoArgs::RegRm(sp, from)));
+    }
+
+    /// Loads a value into `to`, zero extending values narrower than 32 bits.
+    fn emit_load_extended(&mut self, to: x86::Register, from: x86::Memory) {
+        let from_size = from.ptr_size;
+        let from = x86::Rm::Memory(from);
+        match from_size {
+            1 | 2 => self.emitter.emit(x86::Instruction::MovZX(to.resize(4), from)),
+            size => {
+                let args = x86::TwoArgs::RegRm(to.resize(size), from);
+                self.emitter.emit(x86::Instruction::Mov(args));
+            }
+        }
+    }
+
     fn value_size(&self, val: &Value) -> u32 {
         match *val {
             Value::Bytes(_) |
-            Value::Symbol(_) => 4,
+            Value::Symbol(_) => self.word,
             Value::Int(_, size) => size.in_bytes(),
             Value::Reg(r) => self.f.registers[&r].size,
             Value::Undef => panic!("got undef"),
@@ -1603,9 +2022,39 @@ impl<'a> FnCompiler<'a> {
     }
 }
 
-fn compile_function(f: &Function, emitter: &mut Emitter) {
-    let mut compiler = FnCompiler::new(f, emitter);
+/// Whether block has no instructions that generate code.
+fn is_empty_block(block: &Block) -> bool {
+    block.ops.iter().all(|op| match op.instruction {
+        Instruction::Init(_) | Instruction::Drop(_) | Instruction::Nop => true,
+        _ => false,
+    })
+}
+
+/// Line and column of `span` counting from 1, as the prelude reports
+/// them. Zero means that the position is unknown.
+fn reported_position(span: Option<Span>) -> (u32, u32) {
+    match span {
+        Some(span) => (span.start.line + 1, span.start.column + 1),
+        None => (0, 0),
+    }
+}
+
+fn compile_function(
+    program: &Program,
+    f: &Function,
+    emitter: &mut Emitter,
+    word: u32,
+    debug: bool,
+) -> Option<x86::FunctionDebugInfo> {
+    let mut compiler = FnCompiler::new(program, f, emitter, word, debug);
     compiler.emit_function_intro();
+    let debug_info = match f.debug {
+        Some(ref function) if debug => Some(x86::FunctionDebugInfo {
+            function: function.clone(),
+            locations: compiler.variable_locations(),
+        }),
+        _ => None,
+    };
     let blocks = order_blocks(f);
     for (index, &id) in blocks.iter().enumerate() {
         if index > 0 && !compiler.referenced_blocks.contains(&id) {
@@ -1616,20 +2065,38 @@ fn compile_function(f: &Function, emitter: &mut Emitter) {
         compiler.emitter.emit(x86::Instruction::Label(compiler.block_labels[&id].clone()));
         compiler.emit_block(&f.blocks[&id]);
     }
+    debug_info
 }
 
-pub fn compile_program(program: &Program) -> x86::Program {
+/// Compile program to assembly. If `source` is given, the
+/// program also gets debug info that refers to that file.
+pub fn compile_program(program: &Program, source: Option<x86::SourceFile>) -> x86::Program {
+    let mode = match program.target.arch {
+        Arch::X86 => x86::Mode::Bits32,
+        Arch::X86_64 => x86::Mode::Bits64,
+    };
+    let word = program.target.pointer_size.in_bytes();
     let mut emitter = Emitter::default();
     for (name, f) in &program.functions {
         if f.start_block.is_some() {
             emitter.emit(x86::Instruction::Label(x86::Label::Named(name.0.clone())));
-            compile_function(f, &mut emitter);
+            let debug = compile_function(program, f, &mut emitter, word, source.is_some());
             let f = ::std::mem::replace(&mut emitter.current_function, Vec::new());
             emitter.functions.push(f);
+            emitter.debug_functions.push(debug);
         }
     }
+    let functions = emitter.debug_functions;
+    let debug = source.map(|source| x86::DebugInfo {
+        source,
+        functions,
+        types: program.debug_types.clone(),
+    });
     x86::Program {
+        mode,
         functions: emitter.functions,
         strings: emitter.strings,
+        debug,
+        files: program.files.clone(),
     }
 }
@@ -0,0 +1,462 @@
+use std::collections::HashMap;
+use std::io;
+use gimli::{self, constants, Encoding, Format, LittleEndian};
+use gimli::write::{Address, AttributeValue, CallFrameInstruction, CommonInformationEntry};
+use gimli::write::{DwarfUnit, EndianVec, Expression, FileId, FrameDescriptionEntry, FrameTable};
+use gimli::write::{LineProgram, LineString, RelocateWriter, RelocationTarget, Sections};
+use gimli::write::UnitEntryId;
+use object::{RelocationEncoding, RelocationFlags, RelocationKind, SectionKind};
+use object::write::{Object, Relocation, SectionId};
+use plank_ir::debug;
+use plank_ir::ir::Signedness;
+use printer::label_name;
+use x86::{self, DebugInfo, Instruction, Mode, Register, Rm, TwoArgs, VariableLocation};
+
+
+/// Where a function was placed in the text section.
+pub struct FunctionCode {
+    pub start: u64,
+    pub end: u64,
+    /// Offset of each instruction of the function.
+    pub offsets: Vec<u64>,
+}
+
+/// Section data written by gimli, together with places
+/// that must be relocated when the object is linked.
+#[derive(Clone)]
+struct DebugSection {
+    data: EndianVec<LittleEndian>,
+    relocations: Vec<gimli::write::Relocation>,
+}
+
+impl RelocateWriter for DebugSection {
+    type Writer = EndianVec<LittleEndian>;
+
+    fn writer(&self) -> &Self::Writer {
+        &self.data
+    }
+
+    fn writer_mut(&mut self) -> &mut Self::Writer {
+        &mut self.data
+    }
+
+    fn relocate(&mut self, relocation: gimli::write::Relocation) {
+        self.relocations.push(relocation);
+    }
+}
+
+/// Symbol that addresses in the debug info are relative
+/// to. It stands for the start of the text section.
+const TEXT_SYMBOL: usize = 0;
+
+fn text_address(offset: u64) -> Address {
+    Address::Symbol {
+        symbol: TEXT_SYMBOL,
+        addend: offset as i64,
+    }
+}
+
+fn string(value: &str) -> AttributeValue {
+    AttributeValue::String(value.as_bytes().to_vec())
+}
+
+fn to_io_error<E: ::std::fmt::Display>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err.to_string())
+}
+
+/// Add `.debug_info`, `.debug_line`, `.debug_frame` and
+/// related sections, which describe the code in section `text`.
+pub fn write_debug_sections(
+    object: &mut Object,
+    text: SectionId,
+    asm: &x86::Program,
+    debug: &DebugInfo,
+    code: &[FunctionCode],
+) -> io::Result<()> {
+    let word = match asm.mode {
+        Mode::Bits32 => 4,
+        Mode::Bits64 => 8,
+    };
+    let encoding = Encoding {
+        format: Format::Dwarf32,
+        version: 4,
+        address_size: word,
+    };
+    let mut builder = Builder {
+        dwarf: DwarfUnit::new(encoding),
+        types: &debug.types,
+        type_entries: HashMap::new(),
+        mode: asm.mode,
+    };
+    let files = builder.start_unit(asm, debug, code);
+    for ((f, info), code) in asm.functions.iter().zip(&debug.functions).zip(code) {
+        if let Some(ref info) = *info {
+            builder.add_function(f, info, code, &files);
+        }
+    }
+    let frames = frame_table(asm, code, encoding);
+
+    let section = DebugSection {
+        data: EndianVec::new(LittleEndian),
+        relocations: Vec::new(),
+    };
+    let mut sections = Sections::new(section);
+    builder.dwarf.write(&mut sections).map_err(to_io_error)?;
+    frames.write_debug_frame(&mut sections.debug_frame).map_err(to_io_error)?;
+
+    let mut ids = HashMap::new();
+    sections.for_each(|id, section| -> io::Result<()> {
+        if !section.data.slice().is_empty() {
+            let name = id.name().as_bytes().to_vec();
+            let section_id = object.add_section(Vec::new(), name, SectionKind::Debug);
+            object.set_section_data(section_id, section.data.slice().to_vec(), 1);
+            ids.insert(id, section_id);
+        }
+        Ok(())
+    })?;
+    sections.for_each(|id, section| -> io::Result<()> {
+        for relocation in &section.relocations {
+            let symbol = match relocation.target {
+                RelocationTarget::Symbol(TEXT_SYMBOL) => object.section_symbol(text),
+                RelocationTarget::Symbol(symbol) => panic!("unknown debug symbol {}", symbol),
+                RelocationTarget::Section(target) => object.section_symbol(ids[&target]),
+            };
+            object.add_relocation(ids[&id], Relocation {
+                offset: relocation.offset as u64,
+                symbol,
+                addend: relocation.addend,
+                flags: RelocationFlags::Generic {
+                    kind: RelocationKind::Absolute,
+                    encoding: RelocationEncoding::Generic,
+                    size: relocation.size * 8,
+                },
+            }).map_err(to_io_error)?;
+        }
+        Ok(())
+    })
+}
+
+struct Builder<'a> {
+    dwarf: DwarfUnit,
+    types: &'a HashMap<String, debug::Composite>,
+    type_entries: HashMap<debug::Type, UnitEntryId>,
+    mode: Mode,
+}
+
+impl<'a> Builder<'a> {
+    /// Describe the compilation unit, and return the ids of its source
+    /// files together with the line of the joined source that each one
+    /// starts at.
+    fn start_unit(
+        &mut self,
+        asm: &x86::Program,
+        debug: &DebugInfo,
+        code: &[FunctionCode],
+    ) -> Vec<(u32, FileId)> {
+        let source = &debug.source;
+        let encoding = self.dwarf.unit.encoding();
+        let mut program = LineProgram::new(
+            encoding,
+            Default::default(),
+            LineString::String(source.directory.clone().into_bytes()),
+            LineString::String(source.path.clone().into_bytes()),
+            None,
+        );
+        let directory = program.default_directory();
+        let files = if asm.files.is_empty() {
+            let path = LineString::String(source.path.clone().into_bytes());
+            vec![(0, program.add_file(path, directory, None))]
+        } else {
+            asm.files
+                .iter()
+                .map(|file| {
+                    let path = LineString::String(file.path.clone().into_bytes());
+                    (file.start_line, program.add_file(path, directory, None))
+                })
+                .collect()
+        };
+        self.dwarf.unit.line_program = program;
+
+        let end = code.last().map(|code| code.end).unwrap_or(0);
+        let root = self.dwarf.unit.root();
+        let unit = self.dwarf.unit.get_mut(root);
+        let producer = format!("plank {}", env!("CARGO_PKG_VERSION"));
+        unit.set(constants::DW_AT_producer, string(&producer));
+        // debuggers don't know about plank, and C is
+        // the closest language that they do know
+        unit.set(constants::DW_AT_language, AttributeValue::Language(constants::DW_LANG_C99));
+        unit.set(constants::DW_AT_name, string(&source.path));
+        unit.set(constants::DW_AT_comp_dir, string(&source.directory));
+        unit.set(constants::DW_AT_low_pc, AttributeValue::Address(text_address(0)));
+        unit.set(constants::DW_AT_high_pc, AttributeValue::Udata(end));
+        files
+    }
+
+    fn add_function(
+        &mut self,
+        f: &[Instruction],
+        info: &x86::FunctionDebugInfo,
+        code: &FunctionCode,
+        files: &[(u32, FileId)],
+    ) {
+        let function = &info.function;
+        // functions without source, like builtins, are left out of the
+        // line table, and debuggers step over them
+        let declaration = function.span.map(|span| {
+            let (file, line) = locate(files, span.start.line);
+            let program = &mut self.dwarf.unit.line_program;
+            program.begin_sequence(Some(text_address(code.start)));
+            program.row().file = file;
+            program.row().line = u64::from(line) + 1;
+            program.row().column = u64::from(span.start.column) + 1;
+            program.generate_row();
+            for (op, &offset) in f.iter().zip(&code.offsets) {
+                if let Instruction::SourcePosition(pos) = *op {
+                    let (file, line) = locate(files, pos.line);
+                    let row = program.row();
+                    row.address_offset = offset - code.start;
+                    row.file = file;
+                    row.line = u64::from(line) + 1;
+                    row.column = u64::from(pos.column) + 1;
+                    program.generate_row();
+                }
+            }
+            program.end_sequence(code.end - code.start);
+            (file, line)
+        });
+
+        let return_type = self.type_entry(&function.return_type);
+        let mut frame_base = Expression::new();
+        frame_base.op_breg(self.dwarf_register(Register::Ebp), 0);
+        let root = self.dwarf.unit.root();
+        let id = self.dwarf.unit.add(root, constants::DW_TAG_subprogram);
+        let entry = self.dwarf.unit.get_mut(id);
+        entry.set(constants::DW_AT_name, string(&function.name));
+        if let Some(&Instruction::Label(ref label)) = f.first() {
+            entry.set(constants::DW_AT_linkage_name, string(&label_name(label)));
+        }
+        entry.set(constants::DW_AT_external, AttributeValue::Flag(true));
+        match declaration {
+            Some((file, line)) => {
+                entry.set(constants::DW_AT_decl_file, AttributeValue::FileIndex(Some(file)));
+                entry.set(constants::DW_AT_decl_line, AttributeValue::Udata(u64::from(line) + 1));
+            }
+            None => entry.set(constants::DW_AT_artificial, AttributeValue::Flag(true)),
+        }
+        entry.set(constants::DW_AT_low_pc, AttributeValue::Address(text_address(code.start)));
+        entry.set(constants::DW_AT_high_pc, AttributeValue::Udata(code.end - code.start));
+        entry.set(constants::DW_AT_frame_base, AttributeValue::Exprloc(frame_base));
+        if let Some(typ) = return_type {
+            entry.set(constants::DW_AT_type, AttributeValue::UnitRef(typ));
+        }
+
+        for var in &function.variables {
+            let typ = self.type_entry(&var.typ);
+            let tag = if var.is_parameter {
+                constants::DW_TAG_formal_parameter
+            } else {
+                constants::DW_TAG_variable
+            };
+            let location = info.locations.get(&var.reg).map(|&location| {
+                let mut expression = Expression::new();
+                match location {
+                    VariableLocation::Register(reg) => {
+                        expression.op_reg(self.dwarf_register(reg));
+                    }
+                    VariableLocation::Frame(offset) => {
+                        expression.op_fbreg(i64::from(offset));
+                    }
+                }
+                expression
+            });
+            let var_id = self.dwarf.unit.add(id, tag);
+            let entry = self.dwarf.unit.get_mut(var_id);
+            entry.set(constants::DW_AT_name, string(&var.name));
+            if let Some((file, _)) = declaration {
+                entry.set(constants::DW_AT_decl_file, AttributeValue::FileIndex(Some(file)));
+            }
+            if let Some(typ) = typ {
+                entry.set(constants::DW_AT_type, AttributeValue::UnitRef(typ));
+            }
+            if let Some(location) = location {
+                entry.set(constants::DW_AT_location, AttributeValue::Exprloc(location));
+            }
+        }
+    }
+
+    /// Entry that describes `typ`, or `None` if it is `unit`.
+    fn type_entry(&mut self, typ: &debug::Type) -> Option<UnitEntryId> {
+        if let Some(&id) = self.type_entries.get(typ) {
+            return Some(id);
+        }
+        let root = self.dwarf.unit.root();
+        let id = match *typ {
+            debug::Type::Unit => return None,
+            debug::Type::Bool => {
+                self.base_type("bool", constants::DW_ATE_boolean, 1)
+            }
+            debug::Type::Int(sign, size) => {
+                let (prefix, encoding) = match sign {
+                    Signedness::Signed => ("i", constants::DW_ATE_signed),
+                    Signedness::Unsigned => ("u", constants::DW_ATE_unsigned),
+                };
+                let name = format!("{}{}", prefix, size.in_bytes() * 8);
+                self.base_type(&name, encoding, size.in_bytes())
+            }
+            debug::Type::Pointer(ref to) => {
+                let to = self.type_entry(to);
+                let id = self.dwarf.unit.add(root, constants::DW_TAG_pointer_type);
+                let size = self.dwarf.unit.encoding().address_size;
+                let entry = self.dwarf.unit.get_mut(id);
+                entry.set(constants::DW_AT_byte_size, AttributeValue::Data1(size));
+                if let Some(to) = to {
+                    entry.set(constants::DW_AT_type, AttributeValue::UnitRef(to));
+                }
+                id
+            }
+            debug::Type::Composite(ref name) => {
+                let types = self.types;
+                let composite = &types[name];
+                let tag = if composite.is_union {
+                    constants::DW_TAG_union_type
+                } else {
+                    constants::DW_TAG_structure_type
+                };
+                let id = self.dwarf.unit.add(root, tag);
+                let entry = self.dwarf.unit.get_mut(id);
+                entry.set(constants::DW_AT_name, string(name));
+                entry.set(
+                    constants::DW_AT_byte_size,
+                    AttributeValue::Udata(u64::from(composite.size)),
+                );
+                // fields can refer back to this type
+                self.type_entries.insert(typ.clone(), id);
+                for field in &composite.fields {
+                    let typ = self.type_entry(&field.typ);
+                    let member = self.dwarf.unit.add(id, constants::DW_TAG_member);
+                    let entry = self.dwarf.unit.get_mut(member);
+                    entry.set(constants::DW_AT_name, string(&field.name));
+                    entry.set(
+                        constants::DW_AT_data_member_location,
+                        AttributeValue::Udata(u64::from(field.offset)),
+                    );
+                    if let Some(typ) = typ {
+                        entry.set(constants::DW_AT_type, AttributeValue::UnitRef(typ));
+                    }
+                }
+                return Some(id);
+            }
+        };
+        self.type_entries.insert(typ.clone(), id);
+        Some(id)
+    }
+
+    fn base_type(&mut self, name: &str, encoding: constants::DwAte, size: u32) -> UnitEntryId {
+        let root = self.dwarf.unit.root();
+        let id = self.dwarf.unit.add(root, constants::DW_TAG_base_type);
+        let entry = self.dwarf.unit.get_mut(id);
+        entry.set(constants::DW_AT_name, string(name));
+        entry.set(constants::DW_AT_encoding, AttributeValue::Encoding(encoding));
+        entry.set(constants::DW_AT_byte_size, AttributeValue::Udata(u64::from(size)));
+        id
+    }
+
+    fn dwarf_register(&self, register: Register) -> gimli::Register {
+        dwarf_register(self.mode, register)
+    }
+}
+
+/// File that a line of the joined source is in, and the line within
+/// that file. Lines count from 0.
+fn locate(files: &[(u32, FileId)], line: u32) -> (FileId, u32) {
+    let &(start, file) = files
+        .iter()
+        .rev()
+        .find(|&&(start, _)| start <= line)
+        .unwrap_or(&files[0]);
+    (file, line - start)
+}
+
+fn dwarf_register(mode: Mode, register: Register) -> gimli::Register {
+    use x86::Register::*;
+    match (mode, register.resize(4)) {
+        (Mode::Bits32, Eax) => gimli::X86::EAX,
+        (Mode::Bits32, Ecx) => gimli::X86::ECX,
+        (Mode::Bits32, Edx) => gimli::X86::EDX,
+        (Mode::Bits32, Ebx) => gimli::X86::EBX,
+        (Mode::Bits32, Esp) => gimli::X86::ESP,
+        (Mode::Bits32, Ebp) => gimli::X86::EBP,
+        (Mode::Bits32, Esi) => gimli::X86::ESI,
+        (Mode::Bits32, Edi) => gimli::X86::EDI,
+        (Mode::Bits64, Eax) => gimli::X86_64::RAX,
+        (Mode::Bits64, Ecx) => gimli::X86_64::RCX,
+        (Mode::Bits64, Edx) => gimli::X86_64::RDX,
+        (Mode::Bits64, Ebx) => gimli::X86_64::RBX,
+        (Mode::Bits64, Esp) => gimli::X86_64::RSP,
+        (Mode::Bits64, Ebp) => gimli::X86_64::RBP,
+        (Mode::Bits64, Esi) => gimli::X86_64::RSI,
+        (Mode::Bits64, Edi) => gimli::X86_64::RDI,
+        (Mode::Bits64, R8d) => gimli::X86_64::R8,
+        (Mode::Bits64, R9d) => gimli::X86_64::R9,
+        (Mode::Bits64, R10d) => gimli::X86_64::R10,
+        (mode, register) => panic!("no dwarf number for {:?} in {:?}", register, mode),
+    }
+}
+
+/// Describe how to find the caller's frame from any instruction,
+/// which lets debuggers show backtraces. Functions compiled with
+/// debug info keep the canonical frame address at `ebp + 2 * word`.
+fn frame_table(asm: &x86::Program, code: &[FunctionCode], encoding: Encoding) -> FrameTable {
+    let word = i32::from(encoding.address_size);
+    let (stack_pointer, return_address) = match asm.mode {
+        Mode::Bits32 => (gimli::X86::ESP, gimli::X86::RA),
+        Mode::Bits64 => (gimli::X86_64::RSP, gimli::X86_64::RA),
+    };
+    let frame_pointer = dwarf_register(asm.mode, Register::Ebp);
+    let mut table = FrameTable::default();
+    let mut cie = CommonInformationEntry::new(encoding, 1, -word as i8, return_address);
+    cie.add_instruction(CallFrameInstruction::Cfa(stack_pointer, word));
+    cie.add_instruction(CallFrameInstruction::Offset(return_address, -word));
+    let cie = table.add_cie(cie);
+
+    for (f, code) in asm.functions.iter().zip(code) {
+        let mut fde = FrameDescriptionEntry::new(
+            text_address(code.start),
+            (code.end - code.start) as u32,
+        );
+        let mut depth = word;
+        let mut in_prologue = true;
+        for (i, op) in f.iter().enumerate() {
+            let next = code.offsets.get(i + 1).cloned().unwrap_or(code.end);
+            let after = (next - code.start) as u32;
+            match *op {
+                Instruction::Label(_) => {}
+                Instruction::Push(Rm::Register(reg)) if in_prologue => {
+                    depth += word;
+                    if reg.resize(4) == Register::Ebp {
+                        fde.add_instruction(after, CallFrameInstruction::CfaOffset(depth));
+                    }
+                    let reg = dwarf_register(asm.mode, reg);
+                    fde.add_instruction(after, CallFrameInstruction::Offset(reg, -depth));
+                }
+                Instruction::Mov(TwoArgs::RegRm(to, Rm::Register(from)))
+                    if in_prologue &&
+                        to.resize(4) == Register::Ebp &&
+                        from.resize(4) == Register::Esp => {
+                    fde.add_instruction(after, CallFrameInstruction::CfaRegister(frame_pointer));
+                }
+                Instruction::Pop(Rm::Register(reg)) if reg.resize(4) == Register::Ebp => {
+                    fde.add_instruction(after, CallFrameInstruction::RememberState);
+                    fde.add_instruction(after, CallFrameInstruction::Cfa(stack_pointer, word));
+                    fde.add_instruction(after, CallFrameInstruction::Restore(frame_pointer));
+                }
+                Instruction::Ret if next < code.end => {
+                    fde.add_instruction(after, CallFrameInstruction::RestoreState);
+                }
+                _ => in_prologue = false,
+            }
+        }
+        table.add_fde(cie, fde);
+    }
+    table
+}
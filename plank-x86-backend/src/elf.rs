@@ -0,0 +1,1356 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
+use std::sync::Arc;
+use object::{Architecture, BinaryFormat, Endianness, RelocationEncoding, RelocationFlags};
+use object::{RelocationKind, SymbolFlags, SymbolKind, SymbolScope};
+use object::write::{Object, Relocation, StandardSection, Symbol, SymbolSection};
+use dwarf::{self, FunctionCode};
+use encoder::{Encoder, ReferenceKind};
+use printer::label_name;
+use x86::{self, Immediate, Instruction, Label, Memory, Mode, Register, Rm, TwoArgs};
+
+
+/// State of `rand_u32` in the prelude before the program calls
+/// `rand_seed`, or after it calls it with zero.
+const RAND_START: u32 = 2_463_534_242;
+
+/// Write program as a relocatable ELF object file. If `prelude` is set
+/// then it also contains `_start` and builtin functions, the same
+/// ones that `print_prelude` emits, so it can be linked on its own.
+/// Functions without a body become undefined symbols. Debug
+/// sections are added if the program has debug info.
+pub fn write_object<W: Write>(mut to: W, asm: &x86::Program, prelude: bool) -> io::Result<()> {
+    let mut encoder = Encoder::new(asm.mode);
+    if prelude {
+        for op in prelude_instructions(asm.mode).iter().chain(&file_instructions(asm)) {
+            encoder.encode(op);
+        }
+    }
+    let mut functions = Vec::new();
+    for f in &asm.functions {
+        let start = encoder.offset();
+        let mut offsets = Vec::with_capacity(f.len());
+        for op in f {
+            offsets.push(encoder.offset());
+            encoder.encode(op);
+        }
+        functions.push(FunctionCode {
+            start,
+            end: encoder.offset(),
+            offsets,
+        });
+    }
+    let mut code = encoder.finish();
+
+    let architecture = match asm.mode {
+        Mode::Bits32 => Architecture::I386,
+        Mode::Bits64 => Architecture::X86_64,
+    };
+    let mut object = Object::new(BinaryFormat::Elf, architecture, Endianness::Little);
+    let text = object.section_id(StandardSection::Text);
+    let rodata = object.section_id(StandardSection::ReadOnlyData);
+
+    let mut symbols = HashMap::new();
+    for (i, string) in asm.strings.iter().enumerate() {
+        let label = Label::String(i as u32);
+        let symbol = object.add_symbol(local_symbol(&label, SymbolKind::Data));
+        object.add_symbol_data(symbol, rodata, string, 1);
+        symbols.insert(label, symbol);
+    }
+    if prelude {
+        // state of `rand_u32`, and the time when the program started
+        let data = object.section_id(StandardSection::Data);
+        for &(name, value) in &[("builtin_rand_state", RAND_START), ("builtin_time_start", 0)] {
+            let label = Label::Named(Arc::from(name));
+            let symbol = object.add_symbol(local_symbol(&label, SymbolKind::Data));
+            object.add_symbol_data(symbol, data, &value.to_le_bytes(), 4);
+            symbols.insert(label, symbol);
+        }
+        // messages of failed checks
+        let messages: &[(&str, &[u8])] = &[
+            ("builtin_bounds_fail_index", b"index out of bounds: the index is "),
+            ("builtin_bounds_fail_length", b" but the length is "),
+            ("builtin_overflow_fail_message", b"arithmetic overflow: "),
+            ("builtin_overflow_fail_in", b" in "),
+            ("builtin_fail_at", b" at "),
+            ("builtin_assert_fail_message", b"assertion failed: "),
+            ("builtin_panic_fail_message", b"panicked: "),
+        ];
+        let paths = asm.files.iter().enumerate().map(|(index, file)| {
+            (format!("builtin_fail_file_{}", index), file.path.as_bytes())
+        });
+        let messages = messages.iter().map(|&(name, message)| (name.to_string(), message));
+        for (name, message) in messages.chain(paths) {
+            let label = Label::Named(Arc::from(name));
+            let symbol = object.add_symbol(local_symbol(&label, SymbolKind::Data));
+            object.add_symbol_data(symbol, rodata, message, 1);
+            symbols.insert(label, symbol);
+        }
+    }
+
+    // jumps and calls to code in the same section don't need relocations
+    let mut relocations = Vec::new();
+    for reference in &code.references {
+        let at = reference.offset as usize;
+        match (reference.kind, code.labels.get(&reference.label)) {
+            (ReferenceKind::Relative, Some(&target)) => {
+                let relative = target as i64 - (reference.offset as i64 + 4);
+                code.bytes[at..(at + 4)].copy_from_slice(&(relative as i32).to_le_bytes());
+            }
+            _ => relocations.push(reference),
+        }
+    }
+    object.append_section_data(text, &code.bytes, 16);
+
+    if prelude {
+        object.add_symbol(Symbol {
+            name: b"_start".to_vec(),
+            value: 0,
+            size: 0,
+            kind: SymbolKind::Text,
+            scope: SymbolScope::Linkage,
+            weak: false,
+            section: SymbolSection::Section(text),
+            flags: SymbolFlags::None,
+        });
+    }
+    let mut labels = code.labels.iter().collect::<Vec<_>>();
+    labels.sort_by_key(|&(_, &offset)| offset);
+    for (label, &offset) in labels {
+        if let Label::Named(_) = *label {
+            let mut symbol = local_symbol(label, SymbolKind::Text);
+            symbol.value = offset;
+            symbol.section = SymbolSection::Section(text);
+            symbols.insert(label.clone(), object.add_symbol(symbol));
+        }
+    }
+
+    for reference in relocations {
+        let (symbol, addend) = match (&reference.label, code.labels.get(&reference.label)) {
+            (&Label::Unnamed(_), Some(&offset)) => (object.section_symbol(text), offset as i64),
+            (&Label::Unnamed(id), None) => panic!("label_{} is not defined", id),
+            (label, _) => {
+                let symbol = symbols
+                    .entry(label.clone())
+                    .or_insert_with(|| object.add_symbol(undefined_symbol(label)));
+                (*symbol, 0)
+            }
+        };
+        let flags = match reference.kind {
+            ReferenceKind::Relative => RelocationFlags::Generic {
+                kind: RelocationKind::Relative,
+                encoding: RelocationEncoding::Generic,
+                size: 32,
+            },
+            ReferenceKind::Absolute(size) => RelocationFlags::Generic {
+                kind: RelocationKind::Absolute,
+                encoding: match (asm.mode, size) {
+                    (Mode::Bits64, 4) => RelocationEncoding::X86Signed,
+                    _ => RelocationEncoding::Generic,
+                },
+                size: size as u8 * 8,
+            },
+        };
+        // relative references are computed from the end of the
+        // offset, but relocations from its start
+        let addend = match reference.kind {
+            ReferenceKind::Relative => addend - 4,
+            ReferenceKind::Absolute(_) => addend,
+        };
+        object.add_relocation(text, Relocation {
+            offset: reference.offset,
+            symbol,
+            addend,
+            flags,
+        }).map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+    }
+
+    if let Some(ref debug) = asm.debug {
+        dwarf::write_debug_sections(&mut object, text, asm, debug, &functions)?;
+    }
+
+    let bytes = object
+        .write()
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+    to.write_all(&bytes)
+}
+
+fn local_symbol(label: &Label, kind: SymbolKind) -> Symbol {
+    Symbol {
+        name: label_name(label).into_bytes(),
+        value: 0,
+        size: 0,
+        kind,
+        scope: SymbolScope::Compilation,
+        weak: false,
+        section: SymbolSection::Undefined,
+        flags: SymbolFlags::None,
+    }
+}
+
+fn undefined_symbol(label: &Label) -> Symbol {
+    Symbol {
+        name: label_name(label).into_bytes(),
+        value: 0,
+        size: 0,
+        kind: SymbolKind::Text,
+        // hidden symbols could not come from shared libraries such as libc
+        scope: SymbolScope::Dynamic,
+        weak: false,
+        section: SymbolSection::Undefined,
+        flags: SymbolFlags::None,
+    }
+}
+
+/// Same code as `PRELUDE_32` or `PRELUDE_64` in the printer.
+/// It must start with `_start`.
+fn prelude_instructions(mode: Mode) -> Vec<Instruction> {
+    fn label(name: &str) -> Label {
+        Label::Named(Arc::from(name))
+    }
+    fn reg(register: Register) -> Rm {
+        Rm::Register(register)
+    }
+    fn mov(register: Register, value: u64) -> Instruction {
+        Instruction::Mov(TwoArgs::RmImm(reg(register), Immediate::Constant(value)))
+    }
+    fn lea(to: Register, register: Register, offset: i32) -> Instruction {
+        Instruction::Lea(to, Memory { register, offset, ptr_size: 4 })
+    }
+    fn at(register: Register, offset: i32, ptr_size: u32) -> Rm {
+        Rm::Memory(Memory { register, offset, ptr_size })
+    }
+    fn imm(rm: Rm, value: u64) -> TwoArgs {
+        TwoArgs::RmImm(rm, Immediate::Constant(value))
+    }
+    fn address(register: Register, name: &str) -> Instruction {
+        Instruction::Mov(TwoArgs::RmImm(reg(register), Immediate::Label(label(name))))
+    }
+    use x86::Register::*;
+    match mode {
+        Mode::Bits32 => vec![
+            Instruction::Call(Immediate::Label(label("builtin_time_now"))),
+            address(Ecx, "builtin_time_start"),
+            Instruction::Mov(TwoArgs::RmReg(at(Ecx, 0, 4), Eax)),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Esp, 0, 4))),
+            lea(Edx, Esp, 4),
+            Instruction::Push(reg(Edx)),
+            Instruction::Push(reg(Eax)),
+            Instruction::Call(Immediate::Label(label("fn_main"))),
+            Instruction::Mov(TwoArgs::RegRm(Ebx, reg(Eax))),
+            mov(Eax, 1),
+            Instruction::Interrupt(0x80),
+            Instruction::Label(label("builtin_putc")),
+            Instruction::Push(reg(Ebx)),
+            Instruction::Push(reg(Ecx)),
+            mov(Eax, 4),
+            mov(Ebx, 1),
+            lea(Ecx, Esp, 12),
+            mov(Edx, 1),
+            Instruction::Interrupt(0x80),
+            Instruction::Pop(reg(Ecx)),
+            Instruction::Pop(reg(Ebx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_getc")),
+            Instruction::Push(reg(Ebx)),
+            Instruction::Push(reg(Ecx)),
+            Instruction::Sub(TwoArgs::RmImm(reg(Esp), Immediate::Constant(4))),
+            mov(Eax, 3),
+            mov(Ebx, 0),
+            lea(Ecx, Esp, 0),
+            mov(Edx, 1),
+            Instruction::Interrupt(0x80),
+            Instruction::Cmp(imm(reg(Eax), 1)),
+            Instruction::MovZX(Eax, at(Esp, 0, 1)),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_getc_done")),
+            mov(Eax, 0xffff_ffff),
+            Instruction::Label(label("builtin_getc_done")),
+            Instruction::Add(imm(reg(Esp), 4)),
+            Instruction::Pop(reg(Ecx)),
+            Instruction::Pop(reg(Ebx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_print_i32")),
+            Instruction::Push(reg(Ebx)),
+            Instruction::Push(reg(Ecx)),
+            Instruction::Push(reg(Esi)),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Esp, 16, 4))),
+            mov(Esi, 0),
+            Instruction::Test(TwoArgs::RegRm(Eax, reg(Eax))),
+            Instruction::Jcc(x86::Condition::GreaterEqual, label("builtin_print_digits")),
+            Instruction::Neg(reg(Eax)),
+            mov(Esi, 1),
+            Instruction::Jmp(label("builtin_print_digits")),
+            Instruction::Label(label("builtin_print_u32")),
+            Instruction::Push(reg(Ebx)),
+            Instruction::Push(reg(Ecx)),
+            Instruction::Push(reg(Esi)),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Esp, 16, 4))),
+            mov(Esi, 0),
+            Instruction::Label(label("builtin_print_digits")),
+            Instruction::Sub(imm(reg(Esp), 12)),
+            lea(Ecx, Esp, 12),
+            mov(Ebx, 10),
+            Instruction::Label(label("builtin_print_digits_loop")),
+            mov(Edx, 0),
+            Instruction::Div(reg(Ebx)),
+            Instruction::Add(imm(reg(Edx), 48)),
+            Instruction::Sub(imm(reg(Ecx), 1)),
+            Instruction::Mov(TwoArgs::RmReg(at(Ecx, 0, 1), Dl)),
+            Instruction::Test(TwoArgs::RegRm(Eax, reg(Eax))),
+            Instruction::Jcc(x86::Condition::NotEqual, label("builtin_print_digits_loop")),
+            Instruction::Test(TwoArgs::RegRm(Esi, reg(Esi))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_print_digits_write")),
+            Instruction::Sub(imm(reg(Ecx), 1)),
+            Instruction::Mov(imm(at(Ecx, 0, 1), 45)),
+            Instruction::Label(label("builtin_print_digits_write")),
+            mov(Eax, 4),
+            mov(Ebx, 1),
+            lea(Edx, Esp, 12),
+            Instruction::Sub(TwoArgs::RegRm(Edx, reg(Ecx))),
+            Instruction::Interrupt(0x80),
+            Instruction::Add(imm(reg(Esp), 12)),
+            Instruction::Pop(reg(Esi)),
+            Instruction::Pop(reg(Ecx)),
+            Instruction::Pop(reg(Ebx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_write")),
+            Instruction::Push(reg(Ebx)),
+            Instruction::Push(reg(Ecx)),
+            mov(Eax, 4),
+            mov(Ebx, 1),
+            Instruction::Mov(TwoArgs::RegRm(Ecx, at(Esp, 12, 4))),
+            Instruction::Mov(TwoArgs::RegRm(Edx, at(Esp, 16, 4))),
+            Instruction::Interrupt(0x80),
+            Instruction::Pop(reg(Ecx)),
+            Instruction::Pop(reg(Ebx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_read_i32")),
+            Instruction::Push(reg(Ebx)),
+            Instruction::Push(reg(Ecx)),
+            mov(Ebx, 0),
+            mov(Ecx, 0),
+            Instruction::Label(label("builtin_read_i32_skip")),
+            Instruction::Call(Immediate::Label(label("builtin_getc"))),
+            Instruction::Cmp(imm(reg(Eax), 32)),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_read_i32_skip")),
+            Instruction::Cmp(imm(reg(Eax), 9)),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_read_i32_skip")),
+            Instruction::Cmp(imm(reg(Eax), 10)),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_read_i32_skip")),
+            Instruction::Cmp(imm(reg(Eax), 13)),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_read_i32_skip")),
+            Instruction::Cmp(imm(reg(Eax), 45)),
+            Instruction::Jcc(x86::Condition::NotEqual, label("builtin_read_i32_digit")),
+            mov(Ecx, 1),
+            Instruction::Label(label("builtin_read_i32_next")),
+            Instruction::Call(Immediate::Label(label("builtin_getc"))),
+            Instruction::Label(label("builtin_read_i32_digit")),
+            Instruction::Sub(imm(reg(Eax), 48)),
+            Instruction::Cmp(imm(reg(Eax), 9)),
+            Instruction::Jcc(x86::Condition::Above, label("builtin_read_i32_done")),
+            Instruction::Mov(TwoArgs::RegRm(Edx, reg(Ebx))),
+            Instruction::Add(TwoArgs::RegRm(Ebx, reg(Ebx))),
+            Instruction::Add(TwoArgs::RegRm(Ebx, reg(Ebx))),
+            Instruction::Add(TwoArgs::RegRm(Ebx, reg(Edx))),
+            Instruction::Add(TwoArgs::RegRm(Ebx, reg(Ebx))),
+            Instruction::Add(TwoArgs::RegRm(Ebx, reg(Eax))),
+            Instruction::Jmp(label("builtin_read_i32_next")),
+            Instruction::Label(label("builtin_read_i32_done")),
+            Instruction::Mov(TwoArgs::RegRm(Eax, reg(Ebx))),
+            Instruction::Test(TwoArgs::RegRm(Ecx, reg(Ecx))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_read_i32_positive")),
+            Instruction::Neg(reg(Eax)),
+            Instruction::Label(label("builtin_read_i32_positive")),
+            Instruction::Pop(reg(Ecx)),
+            Instruction::Pop(reg(Ebx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_read_line")),
+            Instruction::Push(reg(Ebx)),
+            Instruction::Push(reg(Ecx)),
+            Instruction::Push(reg(Esi)),
+            Instruction::Mov(TwoArgs::RegRm(Ebx, at(Esp, 16, 4))),
+            Instruction::Mov(TwoArgs::RegRm(Ecx, at(Esp, 20, 4))),
+            mov(Esi, 0),
+            Instruction::Call(Immediate::Label(label("builtin_getc"))),
+            Instruction::Test(TwoArgs::RegRm(Eax, reg(Eax))),
+            Instruction::Jcc(x86::Condition::Less, label("builtin_read_line_done")),
+            Instruction::Label(label("builtin_read_line_loop")),
+            Instruction::Test(TwoArgs::RegRm(Eax, reg(Eax))),
+            Instruction::Jcc(x86::Condition::Less, label("builtin_read_line_end")),
+            Instruction::Cmp(imm(reg(Eax), 10)),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_read_line_end")),
+            Instruction::Test(TwoArgs::RegRm(Ecx, reg(Ecx))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_read_line_next")),
+            Instruction::Mov(TwoArgs::RmReg(at(Ebx, 0, 1), Al)),
+            Instruction::Add(imm(reg(Ebx), 1)),
+            Instruction::Sub(imm(reg(Ecx), 1)),
+            Instruction::Add(imm(reg(Esi), 1)),
+            Instruction::Label(label("builtin_read_line_next")),
+            Instruction::Call(Immediate::Label(label("builtin_getc"))),
+            Instruction::Jmp(label("builtin_read_line_loop")),
+            Instruction::Label(label("builtin_read_line_end")),
+            Instruction::Mov(TwoArgs::RegRm(Eax, reg(Esi))),
+            Instruction::Label(label("builtin_read_line_done")),
+            Instruction::Pop(reg(Esi)),
+            Instruction::Pop(reg(Ecx)),
+            Instruction::Pop(reg(Ebx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_str_eq")),
+            Instruction::Push(reg(Ebx)),
+            Instruction::Push(reg(Ecx)),
+            Instruction::Push(reg(Esi)),
+            mov(Eax, 0),
+            Instruction::Mov(TwoArgs::RegRm(Ecx, at(Esp, 20, 4))),
+            Instruction::Cmp(TwoArgs::RegRm(Ecx, at(Esp, 28, 4))),
+            Instruction::Jcc(x86::Condition::NotEqual, label("builtin_str_eq_done")),
+            Instruction::Mov(TwoArgs::RegRm(Ebx, at(Esp, 16, 4))),
+            Instruction::Mov(TwoArgs::RegRm(Esi, at(Esp, 24, 4))),
+            Instruction::Label(label("builtin_str_eq_loop")),
+            Instruction::Test(TwoArgs::RegRm(Ecx, reg(Ecx))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_str_eq_equal")),
+            Instruction::Mov(TwoArgs::RegRm(Dl, at(Ebx, 0, 1))),
+            Instruction::Cmp(TwoArgs::RegRm(Dl, at(Esi, 0, 1))),
+            Instruction::Jcc(x86::Condition::NotEqual, label("builtin_str_eq_done")),
+            Instruction::Add(imm(reg(Ebx), 1)),
+            Instruction::Add(imm(reg(Esi), 1)),
+            Instruction::Sub(imm(reg(Ecx), 1)),
+            Instruction::Jmp(label("builtin_str_eq_loop")),
+            Instruction::Label(label("builtin_str_eq_equal")),
+            mov(Eax, 1),
+            Instruction::Label(label("builtin_str_eq_done")),
+            Instruction::Pop(reg(Esi)),
+            Instruction::Pop(reg(Ecx)),
+            Instruction::Pop(reg(Ebx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_str_copy")),
+            Instruction::Push(reg(Ebx)),
+            Instruction::Push(reg(Ecx)),
+            Instruction::Push(reg(Esi)),
+            Instruction::Mov(TwoArgs::RegRm(Ecx, at(Esp, 20, 4))),
+            Instruction::Cmp(TwoArgs::RegRm(Ecx, at(Esp, 28, 4))),
+            Instruction::Jcc(x86::Condition::BelowEqual, label("builtin_str_copy_counted")),
+            Instruction::Mov(TwoArgs::RegRm(Ecx, at(Esp, 28, 4))),
+            Instruction::Label(label("builtin_str_copy_counted")),
+            Instruction::Mov(TwoArgs::RegRm(Eax, reg(Ecx))),
+            Instruction::Mov(TwoArgs::RegRm(Ebx, at(Esp, 16, 4))),
+            Instruction::Mov(TwoArgs::RegRm(Esi, at(Esp, 24, 4))),
+            Instruction::Cmp(TwoArgs::RegRm(Ebx, reg(Esi))),
+            Instruction::Jcc(x86::Condition::Above, label("builtin_str_copy_backward")),
+            Instruction::Label(label("builtin_str_copy_forward")),
+            Instruction::Test(TwoArgs::RegRm(Ecx, reg(Ecx))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_str_copy_done")),
+            Instruction::Mov(TwoArgs::RegRm(Dl, at(Esi, 0, 1))),
+            Instruction::Mov(TwoArgs::RmReg(at(Ebx, 0, 1), Dl)),
+            Instruction::Add(imm(reg(Ebx), 1)),
+            Instruction::Add(imm(reg(Esi), 1)),
+            Instruction::Sub(imm(reg(Ecx), 1)),
+            Instruction::Jmp(label("builtin_str_copy_forward")),
+            Instruction::Label(label("builtin_str_copy_backward")),
+            Instruction::Add(TwoArgs::RegRm(Ebx, reg(Ecx))),
+            Instruction::Add(TwoArgs::RegRm(Esi, reg(Ecx))),
+            Instruction::Label(label("builtin_str_copy_back")),
+            Instruction::Test(TwoArgs::RegRm(Ecx, reg(Ecx))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_str_copy_done")),
+            Instruction::Sub(imm(reg(Ebx), 1)),
+            Instruction::Sub(imm(reg(Esi), 1)),
+            Instruction::Mov(TwoArgs::RegRm(Dl, at(Esi, 0, 1))),
+            Instruction::Mov(TwoArgs::RmReg(at(Ebx, 0, 1), Dl)),
+            Instruction::Sub(imm(reg(Ecx), 1)),
+            Instruction::Jmp(label("builtin_str_copy_back")),
+            Instruction::Label(label("builtin_str_copy_done")),
+            Instruction::Pop(reg(Esi)),
+            Instruction::Pop(reg(Ecx)),
+            Instruction::Pop(reg(Ebx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_str_find")),
+            Instruction::Push(reg(Ebx)),
+            Instruction::Push(reg(Ecx)),
+            Instruction::Push(reg(Esi)),
+            mov(Eax, 0),
+            Instruction::Label(label("builtin_str_find_candidate")),
+            Instruction::Mov(TwoArgs::RegRm(Ecx, at(Esp, 20, 4))),
+            Instruction::Sub(TwoArgs::RegRm(Ecx, reg(Eax))),
+            Instruction::Cmp(TwoArgs::RegRm(Ecx, at(Esp, 28, 4))),
+            Instruction::Jcc(x86::Condition::Below, label("builtin_str_find_missing")),
+            Instruction::Mov(TwoArgs::RegRm(Ebx, at(Esp, 16, 4))),
+            Instruction::Add(TwoArgs::RegRm(Ebx, reg(Eax))),
+            Instruction::Mov(TwoArgs::RegRm(Esi, at(Esp, 24, 4))),
+            Instruction::Mov(TwoArgs::RegRm(Ecx, at(Esp, 28, 4))),
+            Instruction::Label(label("builtin_str_find_compare")),
+            Instruction::Test(TwoArgs::RegRm(Ecx, reg(Ecx))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_str_find_done")),
+            Instruction::Mov(TwoArgs::RegRm(Dl, at(Ebx, 0, 1))),
+            Instruction::Cmp(TwoArgs::RegRm(Dl, at(Esi, 0, 1))),
+            Instruction::Jcc(x86::Condition::NotEqual, label("builtin_str_find_next")),
+            Instruction::Add(imm(reg(Ebx), 1)),
+            Instruction::Add(imm(reg(Esi), 1)),
+            Instruction::Sub(imm(reg(Ecx), 1)),
+            Instruction::Jmp(label("builtin_str_find_compare")),
+            Instruction::Label(label("builtin_str_find_next")),
+            Instruction::Add(imm(reg(Eax), 1)),
+            Instruction::Jmp(label("builtin_str_find_candidate")),
+            Instruction::Label(label("builtin_str_find_missing")),
+            mov(Eax, 0xffff_ffff),
+            Instruction::Label(label("builtin_str_find_done")),
+            Instruction::Pop(reg(Esi)),
+            Instruction::Pop(reg(Ecx)),
+            Instruction::Pop(reg(Ebx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_alloc")),
+            Instruction::Push(reg(Ebx)),
+            Instruction::Push(reg(Ecx)),
+            Instruction::Sub(imm(reg(Esp), 24)),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Esp, 36, 4))),
+            Instruction::Add(imm(reg(Eax), 16)),
+            Instruction::Jcc(x86::Condition::Below, label("builtin_alloc_fail")),
+            Instruction::Mov(imm(at(Esp, 0, 4), 0)),
+            Instruction::Mov(TwoArgs::RmReg(at(Esp, 4, 4), Eax)),
+            Instruction::Mov(imm(at(Esp, 8, 4), 3)),
+            Instruction::Mov(imm(at(Esp, 12, 4), 34)),
+            Instruction::Mov(imm(at(Esp, 16, 4), 0xffff_ffff)),
+            Instruction::Mov(imm(at(Esp, 20, 4), 0)),
+            mov(Eax, 90),
+            Instruction::Mov(TwoArgs::RegRm(Ebx, reg(Esp))),
+            Instruction::Interrupt(0x80),
+            Instruction::Cmp(imm(reg(Eax), 0xffff_f000)),
+            Instruction::Jcc(x86::Condition::Above, label("builtin_alloc_fail")),
+            Instruction::Mov(TwoArgs::RegRm(Ecx, at(Esp, 4, 4))),
+            Instruction::Mov(TwoArgs::RmReg(at(Eax, 0, 4), Ecx)),
+            Instruction::Add(imm(reg(Eax), 16)),
+            Instruction::Jmp(label("builtin_alloc_done")),
+            Instruction::Label(label("builtin_alloc_fail")),
+            mov(Eax, 0),
+            Instruction::Label(label("builtin_alloc_done")),
+            Instruction::Add(imm(reg(Esp), 24)),
+            Instruction::Pop(reg(Ecx)),
+            Instruction::Pop(reg(Ebx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_free")),
+            Instruction::Push(reg(Ebx)),
+            Instruction::Push(reg(Ecx)),
+            Instruction::Mov(TwoArgs::RegRm(Ebx, at(Esp, 12, 4))),
+            Instruction::Test(TwoArgs::RegRm(Ebx, reg(Ebx))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_free_done")),
+            Instruction::Sub(imm(reg(Ebx), 16)),
+            Instruction::Mov(TwoArgs::RegRm(Ecx, at(Ebx, 0, 4))),
+            mov(Eax, 91),
+            Instruction::Interrupt(0x80),
+            Instruction::Label(label("builtin_free_done")),
+            Instruction::Pop(reg(Ecx)),
+            Instruction::Pop(reg(Ebx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_realloc")),
+            Instruction::Push(reg(Ebx)),
+            Instruction::Push(reg(Ecx)),
+            Instruction::Push(reg(Esi)),
+            Instruction::Push(at(Esp, 20, 4)),
+            Instruction::Call(Immediate::Label(label("builtin_alloc"))),
+            Instruction::Add(imm(reg(Esp), 4)),
+            Instruction::Mov(TwoArgs::RegRm(Esi, at(Esp, 16, 4))),
+            Instruction::Test(TwoArgs::RegRm(Eax, reg(Eax))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_realloc_done")),
+            Instruction::Test(TwoArgs::RegRm(Esi, reg(Esi))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_realloc_done")),
+            Instruction::Mov(TwoArgs::RegRm(Ecx, at(Esi, -16, 4))),
+            Instruction::Sub(imm(reg(Ecx), 16)),
+            Instruction::Cmp(TwoArgs::RegRm(Ecx, at(Esp, 20, 4))),
+            Instruction::Jcc(x86::Condition::BelowEqual, label("builtin_realloc_counted")),
+            Instruction::Mov(TwoArgs::RegRm(Ecx, at(Esp, 20, 4))),
+            Instruction::Label(label("builtin_realloc_counted")),
+            Instruction::Mov(TwoArgs::RegRm(Ebx, reg(Eax))),
+            Instruction::Label(label("builtin_realloc_copy")),
+            Instruction::Test(TwoArgs::RegRm(Ecx, reg(Ecx))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_realloc_free")),
+            Instruction::Mov(TwoArgs::RegRm(Dl, at(Esi, 0, 1))),
+            Instruction::Mov(TwoArgs::RmReg(at(Ebx, 0, 1), Dl)),
+            Instruction::Add(imm(reg(Esi), 1)),
+            Instruction::Add(imm(reg(Ebx), 1)),
+            Instruction::Sub(imm(reg(Ecx), 1)),
+            Instruction::Jmp(label("builtin_realloc_copy")),
+            Instruction::Label(label("builtin_realloc_free")),
+            Instruction::Mov(TwoArgs::RegRm(Ebx, reg(Eax))),
+            Instruction::Push(at(Esp, 16, 4)),
+            Instruction::Call(Immediate::Label(label("builtin_free"))),
+            Instruction::Add(imm(reg(Esp), 4)),
+            Instruction::Mov(TwoArgs::RegRm(Eax, reg(Ebx))),
+            Instruction::Label(label("builtin_realloc_done")),
+            Instruction::Pop(reg(Esi)),
+            Instruction::Pop(reg(Ecx)),
+            Instruction::Pop(reg(Ebx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_panic_fail")),
+            address(Ecx, "builtin_panic_fail_message"),
+            mov(Edx, 10),
+            Instruction::Jmp(label("builtin_assert_fail_prefix")),
+            Instruction::Label(label("builtin_assert_fail")),
+            address(Ecx, "builtin_assert_fail_message"),
+            mov(Edx, 18),
+            Instruction::Label(label("builtin_assert_fail_prefix")),
+            Instruction::Call(Immediate::Label(label("builtin_fail_write"))),
+            Instruction::Mov(TwoArgs::RegRm(Ecx, at(Esp, 4, 4))),
+            Instruction::Mov(TwoArgs::RegRm(Edx, at(Esp, 8, 4))),
+            Instruction::Call(Immediate::Label(label("builtin_fail_write"))),
+            Instruction::Add(imm(reg(Esp), 8)),
+            Instruction::Jmp(label("builtin_fail_position")),
+            Instruction::Label(label("builtin_bounds_fail")),
+            address(Ecx, "builtin_bounds_fail_index"),
+            mov(Edx, 34),
+            Instruction::Call(Immediate::Label(label("builtin_fail_write"))),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Esp, 4, 4))),
+            Instruction::Call(Immediate::Label(label("builtin_fail_u32"))),
+            address(Ecx, "builtin_bounds_fail_length"),
+            mov(Edx, 19),
+            Instruction::Call(Immediate::Label(label("builtin_fail_write"))),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Esp, 8, 4))),
+            Instruction::Call(Immediate::Label(label("builtin_fail_u32"))),
+            Instruction::Add(imm(reg(Esp), 8)),
+            Instruction::Jmp(label("builtin_fail_position")),
+            Instruction::Label(label("builtin_overflow_fail")),
+            address(Ecx, "builtin_overflow_fail_message"),
+            mov(Edx, 21),
+            Instruction::Call(Immediate::Label(label("builtin_fail_write"))),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Esp, 4, 4))),
+            Instruction::Call(Immediate::Label(label("builtin_overflow_fail_operand"))),
+            mov(Edx, 32),
+            Instruction::Call(Immediate::Label(label("builtin_fail_char"))),
+            Instruction::Mov(TwoArgs::RegRm(Edx, at(Esp, 12, 4))),
+            Instruction::Call(Immediate::Label(label("builtin_fail_char"))),
+            mov(Edx, 32),
+            Instruction::Call(Immediate::Label(label("builtin_fail_char"))),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Esp, 8, 4))),
+            Instruction::Call(Immediate::Label(label("builtin_overflow_fail_operand"))),
+            address(Ecx, "builtin_overflow_fail_in"),
+            mov(Edx, 4),
+            Instruction::Call(Immediate::Label(label("builtin_fail_write"))),
+            Instruction::Mov(TwoArgs::RegRm(Edx, at(Esp, 16, 4))),
+            Instruction::Call(Immediate::Label(label("builtin_fail_char"))),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Esp, 20, 4))),
+            Instruction::Call(Immediate::Label(label("builtin_fail_u32"))),
+            Instruction::Add(imm(reg(Esp), 20)),
+            Instruction::Label(label("builtin_fail_position")),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Esp, 4, 4))),
+            Instruction::Test(TwoArgs::RegRm(Eax, reg(Eax))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_fail_exit")),
+            address(Ecx, "builtin_fail_at"),
+            mov(Edx, 4),
+            Instruction::Call(Immediate::Label(label("builtin_fail_write"))),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Esp, 4, 4))),
+            Instruction::Call(Immediate::Label(label("builtin_fail_file"))),
+            Instruction::Push(reg(Eax)),
+            Instruction::Test(TwoArgs::RegRm(Edx, reg(Edx))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_fail_line")),
+            Instruction::Call(Immediate::Label(label("builtin_fail_write"))),
+            mov(Edx, 58),
+            Instruction::Call(Immediate::Label(label("builtin_fail_char"))),
+            Instruction::Label(label("builtin_fail_line")),
+            Instruction::Pop(reg(Eax)),
+            Instruction::Call(Immediate::Label(label("builtin_fail_u32"))),
+            mov(Edx, 58),
+            Instruction::Call(Immediate::Label(label("builtin_fail_char"))),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Esp, 8, 4))),
+            Instruction::Call(Immediate::Label(label("builtin_fail_u32"))),
+            Instruction::Label(label("builtin_fail_exit")),
+            mov(Edx, 10),
+            Instruction::Call(Immediate::Label(label("builtin_fail_char"))),
+            mov(Eax, 1),
+            mov(Ebx, 1),
+            Instruction::Interrupt(0x80),
+            Instruction::Label(label("builtin_fail_char")),
+            Instruction::Sub(imm(reg(Esp), 4)),
+            Instruction::Mov(TwoArgs::RmReg(at(Esp, 0, 1), Dl)),
+            Instruction::Mov(TwoArgs::RegRm(Ecx, reg(Esp))),
+            mov(Edx, 1),
+            Instruction::Call(Immediate::Label(label("builtin_fail_write"))),
+            Instruction::Add(imm(reg(Esp), 4)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_fail_write")),
+            mov(Eax, 4),
+            mov(Ebx, 2),
+            Instruction::Interrupt(0x80),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_fail_u32")),
+            Instruction::Sub(imm(reg(Esp), 16)),
+            lea(Ecx, Esp, 16),
+            mov(Ebx, 10),
+            Instruction::Label(label("builtin_fail_u32_digit")),
+            mov(Edx, 0),
+            Instruction::Div(reg(Ebx)),
+            Instruction::Add(imm(reg(Edx), 48)),
+            Instruction::Sub(imm(reg(Ecx), 1)),
+            Instruction::Mov(TwoArgs::RmReg(at(Ecx, 0, 1), Dl)),
+            Instruction::Test(TwoArgs::RegRm(Eax, reg(Eax))),
+            Instruction::Jcc(x86::Condition::NotEqual, label("builtin_fail_u32_digit")),
+            lea(Edx, Esp, 16),
+            Instruction::Sub(TwoArgs::RegRm(Edx, reg(Ecx))),
+            Instruction::Call(Immediate::Label(label("builtin_fail_write"))),
+            Instruction::Add(imm(reg(Esp), 16)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_overflow_fail_operand")),
+            Instruction::Cmp(imm(at(Esp, 24, 4), 32)),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_overflow_fail_extended")),
+            Instruction::Cmp(imm(at(Esp, 24, 4), 16)),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_overflow_fail_16")),
+            Instruction::MovZX(Eax, reg(Al)),
+            Instruction::Cmp(imm(at(Esp, 20, 4), 117)),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_overflow_fail_extended")),
+            Instruction::MovSX(Eax, reg(Al)),
+            Instruction::Jmp(label("builtin_overflow_fail_extended")),
+            Instruction::Label(label("builtin_overflow_fail_16")),
+            Instruction::MovZX(Eax, reg(Ax)),
+            Instruction::Cmp(imm(at(Esp, 20, 4), 117)),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_overflow_fail_extended")),
+            Instruction::MovSX(Eax, reg(Ax)),
+            Instruction::Label(label("builtin_overflow_fail_extended")),
+            Instruction::Cmp(imm(at(Esp, 20, 4), 117)),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_fail_u32")),
+            Instruction::Test(TwoArgs::RegRm(Eax, reg(Eax))),
+            Instruction::Jcc(x86::Condition::GreaterEqual, label("builtin_fail_u32")),
+            Instruction::Neg(reg(Eax)),
+            Instruction::Push(reg(Eax)),
+            mov(Edx, 45),
+            Instruction::Call(Immediate::Label(label("builtin_fail_char"))),
+            Instruction::Pop(reg(Eax)),
+            Instruction::Jmp(label("builtin_fail_u32")),
+            Instruction::Label(label("builtin_rand_seed")),
+            Instruction::Push(reg(Ecx)),
+            address(Ecx, "builtin_rand_state"),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Esp, 8, 4))),
+            Instruction::Test(TwoArgs::RegRm(Eax, reg(Eax))),
+            Instruction::Jcc(x86::Condition::NotEqual, label("builtin_rand_seed_store")),
+            mov(Eax, u64::from(RAND_START)),
+            Instruction::Label(label("builtin_rand_seed_store")),
+            Instruction::Mov(TwoArgs::RmReg(at(Ecx, 0, 4), Eax)),
+            Instruction::Pop(reg(Ecx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_rand_u32")),
+            Instruction::Push(reg(Ecx)),
+            address(Ecx, "builtin_rand_state"),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Ecx, 0, 4))),
+            Instruction::Mov(TwoArgs::RegRm(Edx, reg(Eax))),
+            Instruction::Shl(imm(reg(Edx), 13)),
+            Instruction::Xor(TwoArgs::RegRm(Eax, reg(Edx))),
+            Instruction::Mov(TwoArgs::RegRm(Edx, reg(Eax))),
+            Instruction::Shr(imm(reg(Edx), 17)),
+            Instruction::Xor(TwoArgs::RegRm(Eax, reg(Edx))),
+            Instruction::Mov(TwoArgs::RegRm(Edx, reg(Eax))),
+            Instruction::Shl(imm(reg(Edx), 5)),
+            Instruction::Xor(TwoArgs::RegRm(Eax, reg(Edx))),
+            Instruction::Mov(TwoArgs::RmReg(at(Ecx, 0, 4), Eax)),
+            Instruction::Pop(reg(Ecx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_time_now")),
+            Instruction::Push(reg(Ebx)),
+            Instruction::Push(reg(Ecx)),
+            Instruction::Sub(imm(reg(Esp), 8)),
+            mov(Eax, 265),
+            mov(Ebx, 1),
+            Instruction::Mov(TwoArgs::RegRm(Ecx, reg(Esp))),
+            Instruction::Interrupt(0x80),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Esp, 4, 4))),
+            mov(Edx, 0),
+            mov(Ecx, 1_000_000),
+            Instruction::Div(reg(Ecx)),
+            Instruction::Mov(TwoArgs::RegRm(Ecx, reg(Eax))),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Esp, 0, 4))),
+            mov(Edx, 1000),
+            Instruction::ImulReg(Eax, reg(Edx)),
+            Instruction::Add(TwoArgs::RegRm(Eax, reg(Ecx))),
+            Instruction::Add(imm(reg(Esp), 8)),
+            Instruction::Pop(reg(Ecx)),
+            Instruction::Pop(reg(Ebx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_time_ms")),
+            Instruction::Call(Immediate::Label(label("builtin_time_now"))),
+            Instruction::Push(reg(Ecx)),
+            address(Ecx, "builtin_time_start"),
+            Instruction::Sub(TwoArgs::RegRm(Eax, at(Ecx, 0, 4))),
+            Instruction::Pop(reg(Ecx)),
+            Instruction::Ret,
+        ],
+        Mode::Bits64 => vec![
+            Instruction::Call(Immediate::Label(label("builtin_time_now"))),
+            address(Rcx, "builtin_time_start"),
+            Instruction::Mov(TwoArgs::RmReg(at(Rcx, 0, 4), Eax)),
+            Instruction::Mov(TwoArgs::RegRm(Rax, at(Rsp, 0, 8))),
+            lea(Rdx, Rsp, 8),
+            Instruction::Push(reg(Rdx)),
+            Instruction::Push(reg(Rax)),
+            Instruction::Call(Immediate::Label(label("fn_main"))),
+            Instruction::Mov(TwoArgs::RegRm(Edi, reg(Eax))),
+            mov(Eax, 60),
+            Instruction::Syscall,
+            Instruction::Label(label("builtin_putc")),
+            Instruction::Push(reg(Rcx)),
+            Instruction::Push(reg(Rsi)),
+            Instruction::Push(reg(Rdi)),
+            mov(Eax, 1),
+            mov(Edi, 1),
+            lea(Rsi, Rsp, 32),
+            mov(Edx, 1),
+            Instruction::Syscall,
+            Instruction::Pop(reg(Rdi)),
+            Instruction::Pop(reg(Rsi)),
+            Instruction::Pop(reg(Rcx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_getc")),
+            Instruction::Push(reg(Rcx)),
+            Instruction::Push(reg(Rsi)),
+            Instruction::Push(reg(Rdi)),
+            Instruction::Sub(TwoArgs::RmImm(reg(Rsp), Immediate::Constant(8))),
+            mov(Eax, 0),
+            mov(Edi, 0),
+            lea(Rsi, Rsp, 0),
+            mov(Edx, 1),
+            Instruction::Syscall,
+            Instruction::Cmp(TwoArgs::RmImm(reg(Rax), Immediate::Constant(1))),
+            Instruction::MovZX(Eax, Rm::Memory(Memory { register: Rsp, offset: 0, ptr_size: 1 })),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_getc_done")),
+            mov(Eax, 0xffff_ffff),
+            Instruction::Label(label("builtin_getc_done")),
+            Instruction::Add(TwoArgs::RmImm(reg(Rsp), Immediate::Constant(8))),
+            Instruction::Pop(reg(Rdi)),
+            Instruction::Pop(reg(Rsi)),
+            Instruction::Pop(reg(Rcx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_print_i32")),
+            Instruction::Push(reg(Rcx)),
+            Instruction::Push(reg(Rsi)),
+            Instruction::Push(reg(Rdi)),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Rsp, 32, 4))),
+            mov(Edi, 0),
+            Instruction::Test(TwoArgs::RegRm(Eax, reg(Eax))),
+            Instruction::Jcc(x86::Condition::GreaterEqual, label("builtin_print_digits")),
+            Instruction::Neg(reg(Eax)),
+            mov(Edi, 1),
+            Instruction::Jmp(label("builtin_print_digits")),
+            Instruction::Label(label("builtin_print_u32")),
+            Instruction::Push(reg(Rcx)),
+            Instruction::Push(reg(Rsi)),
+            Instruction::Push(reg(Rdi)),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Rsp, 32, 4))),
+            mov(Edi, 0),
+            Instruction::Label(label("builtin_print_digits")),
+            Instruction::Sub(imm(reg(Rsp), 16)),
+            lea(Rsi, Rsp, 16),
+            mov(Ecx, 10),
+            Instruction::Label(label("builtin_print_digits_loop")),
+            mov(Edx, 0),
+            Instruction::Div(reg(Ecx)),
+            Instruction::Add(imm(reg(Edx), 48)),
+            Instruction::Sub(imm(reg(Rsi), 1)),
+            Instruction::Mov(TwoArgs::RmReg(at(Rsi, 0, 1), Dl)),
+            Instruction::Test(TwoArgs::RegRm(Eax, reg(Eax))),
+            Instruction::Jcc(x86::Condition::NotEqual, label("builtin_print_digits_loop")),
+            Instruction::Test(TwoArgs::RegRm(Edi, reg(Edi))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_print_digits_write")),
+            Instruction::Sub(imm(reg(Rsi), 1)),
+            Instruction::Mov(imm(at(Rsi, 0, 1), 45)),
+            Instruction::Label(label("builtin_print_digits_write")),
+            mov(Eax, 1),
+            mov(Edi, 1),
+            lea(Rdx, Rsp, 16),
+            Instruction::Sub(TwoArgs::RegRm(Rdx, reg(Rsi))),
+            Instruction::Syscall,
+            Instruction::Add(imm(reg(Rsp), 16)),
+            Instruction::Pop(reg(Rdi)),
+            Instruction::Pop(reg(Rsi)),
+            Instruction::Pop(reg(Rcx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_write")),
+            Instruction::Push(reg(Rcx)),
+            Instruction::Push(reg(Rsi)),
+            Instruction::Push(reg(Rdi)),
+            mov(Eax, 1),
+            mov(Edi, 1),
+            Instruction::Mov(TwoArgs::RegRm(Rsi, at(Rsp, 32, 8))),
+            Instruction::Mov(TwoArgs::RegRm(Edx, at(Rsp, 40, 4))),
+            Instruction::Syscall,
+            Instruction::Pop(reg(Rdi)),
+            Instruction::Pop(reg(Rsi)),
+            Instruction::Pop(reg(Rcx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_read_i32")),
+            Instruction::Push(reg(Rsi)),
+            Instruction::Push(reg(Rdi)),
+            mov(Esi, 0),
+            mov(Edi, 0),
+            Instruction::Label(label("builtin_read_i32_skip")),
+            Instruction::Call(Immediate::Label(label("builtin_getc"))),
+            Instruction::Cmp(imm(reg(Eax), 32)),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_read_i32_skip")),
+            Instruction::Cmp(imm(reg(Eax), 9)),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_read_i32_skip")),
+            Instruction::Cmp(imm(reg(Eax), 10)),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_read_i32_skip")),
+            Instruction::Cmp(imm(reg(Eax), 13)),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_read_i32_skip")),
+            Instruction::Cmp(imm(reg(Eax), 45)),
+            Instruction::Jcc(x86::Condition::NotEqual, label("builtin_read_i32_digit")),
+            mov(Edi, 1),
+            Instruction::Label(label("builtin_read_i32_next")),
+            Instruction::Call(Immediate::Label(label("builtin_getc"))),
+            Instruction::Label(label("builtin_read_i32_digit")),
+            Instruction::Sub(imm(reg(Eax), 48)),
+            Instruction::Cmp(imm(reg(Eax), 9)),
+            Instruction::Jcc(x86::Condition::Above, label("builtin_read_i32_done")),
+            Instruction::Mov(TwoArgs::RegRm(Edx, reg(Esi))),
+            Instruction::Add(TwoArgs::RegRm(Esi, reg(Esi))),
+            Instruction::Add(TwoArgs::RegRm(Esi, reg(Esi))),
+            Instruction::Add(TwoArgs::RegRm(Esi, reg(Edx))),
+            Instruction::Add(TwoArgs::RegRm(Esi, reg(Esi))),
+            Instruction::Add(TwoArgs::RegRm(Esi, reg(Eax))),
+            Instruction::Jmp(label("builtin_read_i32_next")),
+            Instruction::Label(label("builtin_read_i32_done")),
+            Instruction::Mov(TwoArgs::RegRm(Eax, reg(Esi))),
+            Instruction::Test(TwoArgs::RegRm(Edi, reg(Edi))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_read_i32_positive")),
+            Instruction::Neg(reg(Eax)),
+            Instruction::Label(label("builtin_read_i32_positive")),
+            Instruction::Pop(reg(Rdi)),
+            Instruction::Pop(reg(Rsi)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_read_line")),
+            Instruction::Push(reg(Rcx)),
+            Instruction::Push(reg(Rsi)),
+            Instruction::Push(reg(Rdi)),
+            Instruction::Mov(TwoArgs::RegRm(Rsi, at(Rsp, 32, 8))),
+            Instruction::Mov(TwoArgs::RegRm(Ecx, at(Rsp, 40, 4))),
+            mov(Edi, 0),
+            Instruction::Call(Immediate::Label(label("builtin_getc"))),
+            Instruction::Test(TwoArgs::RegRm(Eax, reg(Eax))),
+            Instruction::Jcc(x86::Condition::Less, label("builtin_read_line_done")),
+            Instruction::Label(label("builtin_read_line_loop")),
+            Instruction::Test(TwoArgs::RegRm(Eax, reg(Eax))),
+            Instruction::Jcc(x86::Condition::Less, label("builtin_read_line_end")),
+            Instruction::Cmp(imm(reg(Eax), 10)),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_read_line_end")),
+            Instruction::Test(TwoArgs::RegRm(Ecx, reg(Ecx))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_read_line_next")),
+            Instruction::Mov(TwoArgs::RmReg(at(Rsi, 0, 1), Al)),
+            Instruction::Add(imm(reg(Rsi), 1)),
+            Instruction::Sub(imm(reg(Ecx), 1)),
+            Instruction::Add(imm(reg(Edi), 1)),
+            Instruction::Label(label("builtin_read_line_next")),
+            Instruction::Call(Immediate::Label(label("builtin_getc"))),
+            Instruction::Jmp(label("builtin_read_line_loop")),
+            Instruction::Label(label("builtin_read_line_end")),
+            Instruction::Mov(TwoArgs::RegRm(Eax, reg(Edi))),
+            Instruction::Label(label("builtin_read_line_done")),
+            Instruction::Pop(reg(Rdi)),
+            Instruction::Pop(reg(Rsi)),
+            Instruction::Pop(reg(Rcx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_str_eq")),
+            Instruction::Push(reg(Rcx)),
+            Instruction::Push(reg(Rsi)),
+            Instruction::Push(reg(Rdi)),
+            mov(Eax, 0),
+            Instruction::Mov(TwoArgs::RegRm(Ecx, at(Rsp, 40, 4))),
+            Instruction::Cmp(TwoArgs::RegRm(Ecx, at(Rsp, 56, 4))),
+            Instruction::Jcc(x86::Condition::NotEqual, label("builtin_str_eq_done")),
+            Instruction::Mov(TwoArgs::RegRm(Rdi, at(Rsp, 32, 8))),
+            Instruction::Mov(TwoArgs::RegRm(Rsi, at(Rsp, 48, 8))),
+            Instruction::Label(label("builtin_str_eq_loop")),
+            Instruction::Test(TwoArgs::RegRm(Ecx, reg(Ecx))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_str_eq_equal")),
+            Instruction::Mov(TwoArgs::RegRm(Dl, at(Rdi, 0, 1))),
+            Instruction::Cmp(TwoArgs::RegRm(Dl, at(Rsi, 0, 1))),
+            Instruction::Jcc(x86::Condition::NotEqual, label("builtin_str_eq_done")),
+            Instruction::Add(imm(reg(Rdi), 1)),
+            Instruction::Add(imm(reg(Rsi), 1)),
+            Instruction::Sub(imm(reg(Ecx), 1)),
+            Instruction::Jmp(label("builtin_str_eq_loop")),
+            Instruction::Label(label("builtin_str_eq_equal")),
+            mov(Eax, 1),
+            Instruction::Label(label("builtin_str_eq_done")),
+            Instruction::Pop(reg(Rdi)),
+            Instruction::Pop(reg(Rsi)),
+            Instruction::Pop(reg(Rcx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_str_copy")),
+            Instruction::Push(reg(Rcx)),
+            Instruction::Push(reg(Rsi)),
+            Instruction::Push(reg(Rdi)),
+            Instruction::Mov(TwoArgs::RegRm(Ecx, at(Rsp, 40, 4))),
+            Instruction::Cmp(TwoArgs::RegRm(Ecx, at(Rsp, 56, 4))),
+            Instruction::Jcc(x86::Condition::BelowEqual, label("builtin_str_copy_counted")),
+            Instruction::Mov(TwoArgs::RegRm(Ecx, at(Rsp, 56, 4))),
+            Instruction::Label(label("builtin_str_copy_counted")),
+            Instruction::Mov(TwoArgs::RegRm(Eax, reg(Ecx))),
+            Instruction::Mov(TwoArgs::RegRm(Rdi, at(Rsp, 32, 8))),
+            Instruction::Mov(TwoArgs::RegRm(Rsi, at(Rsp, 48, 8))),
+            Instruction::Cmp(TwoArgs::RegRm(Rdi, reg(Rsi))),
+            Instruction::Jcc(x86::Condition::Above, label("builtin_str_copy_backward")),
+            Instruction::Label(label("builtin_str_copy_forward")),
+            Instruction::Test(TwoArgs::RegRm(Ecx, reg(Ecx))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_str_copy_done")),
+            Instruction::Mov(TwoArgs::RegRm(Dl, at(Rsi, 0, 1))),
+            Instruction::Mov(TwoArgs::RmReg(at(Rdi, 0, 1), Dl)),
+            Instruction::Add(imm(reg(Rdi), 1)),
+            Instruction::Add(imm(reg(Rsi), 1)),
+            Instruction::Sub(imm(reg(Ecx), 1)),
+            Instruction::Jmp(label("builtin_str_copy_forward")),
+            Instruction::Label(label("builtin_str_copy_backward")),
+            Instruction::Add(TwoArgs::RegRm(Rdi, reg(Rcx))),
+            Instruction::Add(TwoArgs::RegRm(Rsi, reg(Rcx))),
+            Instruction::Label(label("builtin_str_copy_back")),
+            Instruction::Test(TwoArgs::RegRm(Ecx, reg(Ecx))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_str_copy_done")),
+            Instruction::Sub(imm(reg(Rdi), 1)),
+            Instruction::Sub(imm(reg(Rsi), 1)),
+            Instruction::Mov(TwoArgs::RegRm(Dl, at(Rsi, 0, 1))),
+            Instruction::Mov(TwoArgs::RmReg(at(Rdi, 0, 1), Dl)),
+            Instruction::Sub(imm(reg(Ecx), 1)),
+            Instruction::Jmp(label("builtin_str_copy_back")),
+            Instruction::Label(label("builtin_str_copy_done")),
+            Instruction::Pop(reg(Rdi)),
+            Instruction::Pop(reg(Rsi)),
+            Instruction::Pop(reg(Rcx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_str_find")),
+            Instruction::Push(reg(Rcx)),
+            Instruction::Push(reg(Rsi)),
+            Instruction::Push(reg(Rdi)),
+            mov(Eax, 0),
+            Instruction::Label(label("builtin_str_find_candidate")),
+            Instruction::Mov(TwoArgs::RegRm(Ecx, at(Rsp, 40, 4))),
+            Instruction::Sub(TwoArgs::RegRm(Ecx, reg(Eax))),
+            Instruction::Cmp(TwoArgs::RegRm(Ecx, at(Rsp, 56, 4))),
+            Instruction::Jcc(x86::Condition::Below, label("builtin_str_find_missing")),
+            Instruction::Mov(TwoArgs::RegRm(Rdi, at(Rsp, 32, 8))),
+            Instruction::Add(TwoArgs::RegRm(Rdi, reg(Rax))),
+            Instruction::Mov(TwoArgs::RegRm(Rsi, at(Rsp, 48, 8))),
+            Instruction::Mov(TwoArgs::RegRm(Ecx, at(Rsp, 56, 4))),
+            Instruction::Label(label("builtin_str_find_compare")),
+            Instruction::Test(TwoArgs::RegRm(Ecx, reg(Ecx))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_str_find_done")),
+            Instruction::Mov(TwoArgs::RegRm(Dl, at(Rdi, 0, 1))),
+            Instruction::Cmp(TwoArgs::RegRm(Dl, at(Rsi, 0, 1))),
+            Instruction::Jcc(x86::Condition::NotEqual, label("builtin_str_find_next")),
+            Instruction::Add(imm(reg(Rdi), 1)),
+            Instruction::Add(imm(reg(Rsi), 1)),
+            Instruction::Sub(imm(reg(Ecx), 1)),
+            Instruction::Jmp(label("builtin_str_find_compare")),
+            Instruction::Label(label("builtin_str_find_next")),
+            Instruction::Add(imm(reg(Eax), 1)),
+            Instruction::Jmp(label("builtin_str_find_candidate")),
+            Instruction::Label(label("builtin_str_find_missing")),
+            mov(Eax, 0xffff_ffff),
+            Instruction::Label(label("builtin_str_find_done")),
+            Instruction::Pop(reg(Rdi)),
+            Instruction::Pop(reg(Rsi)),
+            Instruction::Pop(reg(Rcx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_alloc")),
+            Instruction::Push(reg(Rcx)),
+            Instruction::Push(reg(Rsi)),
+            Instruction::Push(reg(Rdi)),
+            Instruction::Mov(TwoArgs::RegRm(Esi, at(Rsp, 32, 4))),
+            Instruction::Add(imm(reg(Rsi), 16)),
+            mov(Eax, 9),
+            mov(Edi, 0),
+            mov(Edx, 3),
+            mov(R10d, 34),
+            mov(R8, u64::MAX),
+            mov(R9d, 0),
+            Instruction::Syscall,
+            Instruction::Cmp(imm(reg(Rax), (-4096i64) as u64)),
+            Instruction::Jcc(x86::Condition::Above, label("builtin_alloc_fail")),
+            Instruction::Mov(TwoArgs::RmReg(at(Rax, 0, 8), Rsi)),
+            Instruction::Add(imm(reg(Rax), 16)),
+            Instruction::Jmp(label("builtin_alloc_done")),
+            Instruction::Label(label("builtin_alloc_fail")),
+            mov(Eax, 0),
+            Instruction::Label(label("builtin_alloc_done")),
+            Instruction::Pop(reg(Rdi)),
+            Instruction::Pop(reg(Rsi)),
+            Instruction::Pop(reg(Rcx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_free")),
+            Instruction::Push(reg(Rcx)),
+            Instruction::Push(reg(Rsi)),
+            Instruction::Push(reg(Rdi)),
+            Instruction::Mov(TwoArgs::RegRm(Rdi, at(Rsp, 32, 8))),
+            Instruction::Test(TwoArgs::RegRm(Rdi, reg(Rdi))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_free_done")),
+            Instruction::Sub(imm(reg(Rdi), 16)),
+            Instruction::Mov(TwoArgs::RegRm(Rsi, at(Rdi, 0, 8))),
+            mov(Eax, 11),
+            Instruction::Syscall,
+            Instruction::Label(label("builtin_free_done")),
+            Instruction::Pop(reg(Rdi)),
+            Instruction::Pop(reg(Rsi)),
+            Instruction::Pop(reg(Rcx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_realloc")),
+            Instruction::Push(reg(Rcx)),
+            Instruction::Push(reg(Rsi)),
+            Instruction::Push(reg(Rdi)),
+            Instruction::Push(at(Rsp, 40, 8)),
+            Instruction::Call(Immediate::Label(label("builtin_alloc"))),
+            Instruction::Add(imm(reg(Rsp), 8)),
+            Instruction::Mov(TwoArgs::RegRm(Rsi, at(Rsp, 32, 8))),
+            Instruction::Test(TwoArgs::RegRm(Rax, reg(Rax))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_realloc_done")),
+            Instruction::Test(TwoArgs::RegRm(Rsi, reg(Rsi))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_realloc_done")),
+            Instruction::Mov(TwoArgs::RegRm(Rcx, at(Rsi, -16, 8))),
+            Instruction::Sub(imm(reg(Rcx), 16)),
+            Instruction::Mov(TwoArgs::RegRm(Edx, at(Rsp, 40, 4))),
+            Instruction::Cmp(TwoArgs::RegRm(Rcx, reg(Rdx))),
+            Instruction::Jcc(x86::Condition::BelowEqual, label("builtin_realloc_counted")),
+            Instruction::Mov(TwoArgs::RegRm(Rcx, reg(Rdx))),
+            Instruction::Label(label("builtin_realloc_counted")),
+            Instruction::Mov(TwoArgs::RegRm(Rdi, reg(Rax))),
+            Instruction::Label(label("builtin_realloc_copy")),
+            Instruction::Test(TwoArgs::RegRm(Rcx, reg(Rcx))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_realloc_free")),
+            Instruction::Mov(TwoArgs::RegRm(Dl, at(Rsi, 0, 1))),
+            Instruction::Mov(TwoArgs::RmReg(at(Rdi, 0, 1), Dl)),
+            Instruction::Add(imm(reg(Rsi), 1)),
+            Instruction::Add(imm(reg(Rdi), 1)),
+            Instruction::Sub(imm(reg(Rcx), 1)),
+            Instruction::Jmp(label("builtin_realloc_copy")),
+            Instruction::Label(label("builtin_realloc_free")),
+            Instruction::Mov(TwoArgs::RegRm(Rdi, reg(Rax))),
+            Instruction::Push(at(Rsp, 32, 8)),
+            Instruction::Call(Immediate::Label(label("builtin_free"))),
+            Instruction::Add(imm(reg(Rsp), 8)),
+            Instruction::Mov(TwoArgs::RegRm(Rax, reg(Rdi))),
+            Instruction::Label(label("builtin_realloc_done")),
+            Instruction::Pop(reg(Rdi)),
+            Instruction::Pop(reg(Rsi)),
+            Instruction::Pop(reg(Rcx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_panic_fail")),
+            address(Rsi, "builtin_panic_fail_message"),
+            mov(Edx, 10),
+            Instruction::Jmp(label("builtin_assert_fail_prefix")),
+            Instruction::Label(label("builtin_assert_fail")),
+            address(Rsi, "builtin_assert_fail_message"),
+            mov(Edx, 18),
+            Instruction::Label(label("builtin_assert_fail_prefix")),
+            Instruction::Call(Immediate::Label(label("builtin_fail_write"))),
+            Instruction::Mov(TwoArgs::RegRm(Rsi, at(Rsp, 8, 8))),
+            Instruction::Mov(TwoArgs::RegRm(Edx, at(Rsp, 16, 4))),
+            Instruction::Call(Immediate::Label(label("builtin_fail_write"))),
+            Instruction::Add(imm(reg(Rsp), 16)),
+            Instruction::Jmp(label("builtin_fail_position")),
+            Instruction::Label(label("builtin_bounds_fail")),
+            address(Rsi, "builtin_bounds_fail_index"),
+            mov(Edx, 34),
+            Instruction::Call(Immediate::Label(label("builtin_fail_write"))),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Rsp, 8, 4))),
+            Instruction::Call(Immediate::Label(label("builtin_fail_u32"))),
+            address(Rsi, "builtin_bounds_fail_length"),
+            mov(Edx, 19),
+            Instruction::Call(Immediate::Label(label("builtin_fail_write"))),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Rsp, 16, 4))),
+            Instruction::Call(Immediate::Label(label("builtin_fail_u32"))),
+            Instruction::Add(imm(reg(Rsp), 16)),
+            Instruction::Jmp(label("builtin_fail_position")),
+            Instruction::Label(label("builtin_overflow_fail")),
+            address(Rsi, "builtin_overflow_fail_message"),
+            mov(Edx, 21),
+            Instruction::Call(Immediate::Label(label("builtin_fail_write"))),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Rsp, 8, 4))),
+            Instruction::Call(Immediate::Label(label("builtin_overflow_fail_operand"))),
+            mov(Edx, 32),
+            Instruction::Call(Immediate::Label(label("builtin_fail_char"))),
+            Instruction::Mov(TwoArgs::RegRm(Edx, at(Rsp, 24, 4))),
+            Instruction::Call(Immediate::Label(label("builtin_fail_char"))),
+            mov(Edx, 32),
+            Instruction::Call(Immediate::Label(label("builtin_fail_char"))),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Rsp, 16, 4))),
+            Instruction::Call(Immediate::Label(label("builtin_overflow_fail_operand"))),
+            address(Rsi, "builtin_overflow_fail_in"),
+            mov(Edx, 4),
+            Instruction::Call(Immediate::Label(label("builtin_fail_write"))),
+            Instruction::Mov(TwoArgs::RegRm(Edx, at(Rsp, 32, 4))),
+            Instruction::Call(Immediate::Label(label("builtin_fail_char"))),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Rsp, 40, 4))),
+            Instruction::Call(Immediate::Label(label("builtin_fail_u32"))),
+            Instruction::Add(imm(reg(Rsp), 40)),
+            Instruction::Label(label("builtin_fail_position")),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Rsp, 8, 4))),
+            Instruction::Test(TwoArgs::RegRm(Eax, reg(Eax))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_fail_exit")),
+            address(Rsi, "builtin_fail_at"),
+            mov(Edx, 4),
+            Instruction::Call(Immediate::Label(label("builtin_fail_write"))),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Rsp, 8, 4))),
+            Instruction::Call(Immediate::Label(label("builtin_fail_file"))),
+            Instruction::Push(reg(Rax)),
+            Instruction::Test(TwoArgs::RegRm(Edx, reg(Edx))),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_fail_line")),
+            Instruction::Call(Immediate::Label(label("builtin_fail_write"))),
+            mov(Edx, 58),
+            Instruction::Call(Immediate::Label(label("builtin_fail_char"))),
+            Instruction::Label(label("builtin_fail_line")),
+            Instruction::Pop(reg(Rax)),
+            Instruction::Call(Immediate::Label(label("builtin_fail_u32"))),
+            mov(Edx, 58),
+            Instruction::Call(Immediate::Label(label("builtin_fail_char"))),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Rsp, 16, 4))),
+            Instruction::Call(Immediate::Label(label("builtin_fail_u32"))),
+            Instruction::Label(label("builtin_fail_exit")),
+            mov(Edx, 10),
+            Instruction::Call(Immediate::Label(label("builtin_fail_char"))),
+            mov(Eax, 60),
+            mov(Edi, 1),
+            Instruction::Syscall,
+            Instruction::Label(label("builtin_fail_char")),
+            Instruction::Sub(imm(reg(Rsp), 8)),
+            Instruction::Mov(TwoArgs::RmReg(at(Rsp, 0, 1), Dl)),
+            Instruction::Mov(TwoArgs::RegRm(Rsi, reg(Rsp))),
+            mov(Edx, 1),
+            Instruction::Call(Immediate::Label(label("builtin_fail_write"))),
+            Instruction::Add(imm(reg(Rsp), 8)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_fail_write")),
+            mov(Eax, 1),
+            mov(Edi, 2),
+            Instruction::Syscall,
+            Instruction::Ret,
+            Instruction::Label(label("builtin_fail_u32")),
+            Instruction::Sub(imm(reg(Rsp), 16)),
+            lea(Rcx, Rsp, 16),
+            mov(Ebx, 10),
+            Instruction::Label(label("builtin_fail_u32_digit")),
+            mov(Edx, 0),
+            Instruction::Div(reg(Ebx)),
+            Instruction::Add(imm(reg(Edx), 48)),
+            Instruction::Sub(imm(reg(Rcx), 1)),
+            Instruction::Mov(TwoArgs::RmReg(at(Rcx, 0, 1), Dl)),
+            Instruction::Test(TwoArgs::RegRm(Eax, reg(Eax))),
+            Instruction::Jcc(x86::Condition::NotEqual, label("builtin_fail_u32_digit")),
+            Instruction::Mov(TwoArgs::RegRm(Rsi, reg(Rcx))),
+            lea(Rdx, Rsp, 16),
+            Instruction::Sub(TwoArgs::RegRm(Rdx, reg(Rcx))),
+            Instruction::Call(Immediate::Label(label("builtin_fail_write"))),
+            Instruction::Add(imm(reg(Rsp), 16)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_overflow_fail_operand")),
+            Instruction::Cmp(imm(at(Rsp, 48, 4), 32)),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_overflow_fail_extended")),
+            Instruction::Cmp(imm(at(Rsp, 48, 4), 16)),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_overflow_fail_16")),
+            Instruction::MovZX(Eax, reg(Al)),
+            Instruction::Cmp(imm(at(Rsp, 40, 4), 117)),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_overflow_fail_extended")),
+            Instruction::MovSX(Eax, reg(Al)),
+            Instruction::Jmp(label("builtin_overflow_fail_extended")),
+            Instruction::Label(label("builtin_overflow_fail_16")),
+            Instruction::MovZX(Eax, reg(Ax)),
+            Instruction::Cmp(imm(at(Rsp, 40, 4), 117)),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_overflow_fail_extended")),
+            Instruction::MovSX(Eax, reg(Ax)),
+            Instruction::Label(label("builtin_overflow_fail_extended")),
+            Instruction::Cmp(imm(at(Rsp, 40, 4), 117)),
+            Instruction::Jcc(x86::Condition::Equal, label("builtin_fail_u32")),
+            Instruction::Test(TwoArgs::RegRm(Eax, reg(Eax))),
+            Instruction::Jcc(x86::Condition::GreaterEqual, label("builtin_fail_u32")),
+            Instruction::Neg(reg(Eax)),
+            Instruction::Push(reg(Rax)),
+            mov(Edx, 45),
+            Instruction::Call(Immediate::Label(label("builtin_fail_char"))),
+            Instruction::Pop(reg(Rax)),
+            Instruction::Jmp(label("builtin_fail_u32")),
+            Instruction::Label(label("builtin_rand_seed")),
+            Instruction::Push(reg(Rcx)),
+            address(Rcx, "builtin_rand_state"),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Rsp, 16, 4))),
+            Instruction::Test(TwoArgs::RegRm(Eax, reg(Eax))),
+            Instruction::Jcc(x86::Condition::NotEqual, label("builtin_rand_seed_store")),
+            mov(Eax, u64::from(RAND_START)),
+            Instruction::Label(label("builtin_rand_seed_store")),
+            Instruction::Mov(TwoArgs::RmReg(at(Rcx, 0, 4), Eax)),
+            Instruction::Pop(reg(Rcx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_rand_u32")),
+            Instruction::Push(reg(Rcx)),
+            address(Rcx, "builtin_rand_state"),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Rcx, 0, 4))),
+            Instruction::Mov(TwoArgs::RegRm(Edx, reg(Eax))),
+            Instruction::Shl(imm(reg(Edx), 13)),
+            Instruction::Xor(TwoArgs::RegRm(Eax, reg(Edx))),
+            Instruction::Mov(TwoArgs::RegRm(Edx, reg(Eax))),
+            Instruction::Shr(imm(reg(Edx), 17)),
+            Instruction::Xor(TwoArgs::RegRm(Eax, reg(Edx))),
+            Instruction::Mov(TwoArgs::RegRm(Edx, reg(Eax))),
+            Instruction::Shl(imm(reg(Edx), 5)),
+            Instruction::Xor(TwoArgs::RegRm(Eax, reg(Edx))),
+            Instruction::Mov(TwoArgs::RmReg(at(Rcx, 0, 4), Eax)),
+            Instruction::Pop(reg(Rcx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_time_now")),
+            Instruction::Push(reg(Rcx)),
+            Instruction::Push(reg(Rsi)),
+            Instruction::Push(reg(Rdi)),
+            Instruction::Sub(imm(reg(Rsp), 16)),
+            mov(Eax, 228),
+            mov(Edi, 1),
+            Instruction::Mov(TwoArgs::RegRm(Rsi, reg(Rsp))),
+            Instruction::Syscall,
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Rsp, 8, 4))),
+            mov(Edx, 0),
+            mov(Ecx, 1_000_000),
+            Instruction::Div(reg(Ecx)),
+            Instruction::Mov(TwoArgs::RegRm(Ecx, reg(Eax))),
+            Instruction::Mov(TwoArgs::RegRm(Eax, at(Rsp, 0, 4))),
+            mov(Edx, 1000),
+            Instruction::ImulReg(Eax, reg(Edx)),
+            Instruction::Add(TwoArgs::RegRm(Eax, reg(Ecx))),
+            Instruction::Add(imm(reg(Rsp), 16)),
+            Instruction::Pop(reg(Rdi)),
+            Instruction::Pop(reg(Rsi)),
+            Instruction::Pop(reg(Rcx)),
+            Instruction::Ret,
+            Instruction::Label(label("builtin_time_ms")),
+            Instruction::Call(Immediate::Label(label("builtin_time_now"))),
+            Instruction::Push(reg(Rcx)),
+            address(Rcx, "builtin_time_start"),
+            Instruction::Sub(TwoArgs::RegRm(Eax, at(Rcx, 0, 4))),
+            Instruction::Pop(reg(Rcx)),
+            Instruction::Ret,
+        ],
+    }
+}
+
+/// Code of `builtin_fail_file`, which the prelude calls to find the
+/// file that a failed check is in. It gets a line of the joined
+/// source in `eax`, counting from 1, and returns the line within the
+/// file in `eax` together with the path of the file, its address in
+/// `ecx`, or `rsi` in 64-bit mode, and its length in `edx`. The length
+/// is zero if the program was compiled from a single file.
+pub fn file_instructions(asm: &x86::Program) -> Vec<Instruction> {
+    let label = |name: String| Label::Named(Arc::from(name));
+    let constant = |rm: Rm, value: u32| TwoArgs::RmImm(rm, Immediate::Constant(u64::from(value)));
+    let path = match asm.mode {
+        Mode::Bits32 => Register::Ecx,
+        Mode::Bits64 => Register::Rsi,
+    };
+    let mut code = vec![Instruction::Label(label("builtin_fail_file".into()))];
+    // files are checked from the last one, which starts furthest
+    for (index, file) in asm.files.iter().enumerate().rev() {
+        let skip = label(format!("builtin_fail_file_skip_{}", index));
+        if file.start_line != 0 {
+            code.push(Instruction::Cmp(constant(Rm::Register(Register::Eax), file.start_line)));
+            code.push(Instruction::Jcc(x86::Condition::BelowEqual, skip.clone()));
+            code.push(Instruction::Sub(constant(Rm::Register(Register::Eax), file.start_line)));
+        }
+        let name = Immediate::Label(label(format!("builtin_fail_file_{}", index)));
+        code.push(Instruction::Mov(TwoArgs::RmImm(Rm::Register(path), name)));
+        code.push(Instruction::Mov(constant(Rm::Register(Register::Edx), file.path.len() as u32)));
+        code.push(Instruction::Ret);
+        if file.start_line != 0 {
+            code.push(Instruction::Label(skip));
+        }
+    }
+    if asm.files.is_empty() {
+        code.push(Instruction::Mov(constant(Rm::Register(Register::Edx), 0)));
+        code.push(Instruction::Ret);
+    }
+    code
+}
@@ -0,0 +1,484 @@
+use std::collections::HashMap;
+use x86::{self, Condition, Immediate, Instruction, Label, Mode, Register, Rm, TwoArgs};
+
+
+#[derive(Debug, Copy, Clone)]
+pub enum ReferenceKind {
+    /// 32 bit offset from the end of the reference to the label.
+    Relative,
+    /// Address of the label, given size in bytes. 4 byte addresses
+    /// are sign extended when the instruction works with 64 bit values.
+    Absolute(u32),
+}
+
+/// A place in machine code that needs address of a label.
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub offset: u64,
+    pub label: Label,
+    pub kind: ReferenceKind,
+}
+
+/// Encoded machine code. Places that refer to labels are left
+/// zeroed, and are listed in `references`.
+#[derive(Debug)]
+pub struct Code {
+    pub bytes: Vec<u8>,
+    pub labels: HashMap<Label, u64>,
+    pub references: Vec<Reference>,
+}
+
+pub struct Encoder {
+    mode: Mode,
+    code: Code,
+}
+
+impl Encoder {
+    pub fn new(mode: Mode) -> Encoder {
+        Encoder {
+            mode,
+            code: Code {
+                bytes: Vec::new(),
+                labels: HashMap::new(),
+                references: Vec::new(),
+            },
+        }
+    }
+
+    /// Offset where the next instruction will be encoded.
+    pub fn offset(&self) -> u64 {
+        self.code.bytes.len() as u64
+    }
+
+    pub fn finish(self) -> Code {
+        self.code
+    }
+
+    pub fn encode(&mut self, op: &Instruction) {
+        match *op {
+            Instruction::Add(ref args) => self.encode_arithmetic(0, args),
+            Instruction::Or(ref args) => self.encode_arithmetic(1, args),
+            Instruction::And(ref args) => self.encode_arithmetic(4, args),
+            Instruction::Sub(ref args) => self.encode_arithmetic(5, args),
+            Instruction::Xor(ref args) => self.encode_arithmetic(6, args),
+            Instruction::Cmp(ref args) => self.encode_arithmetic(7, args),
+            Instruction::Mov(ref args) => self.encode_mov(args),
+            Instruction::Test(ref args) => self.encode_test(args),
+            Instruction::Shl(ref args) => self.encode_shift(4, args),
+            Instruction::Shr(ref args) => self.encode_shift(5, args),
+            Instruction::Neg(rm) => self.encode_unary(3, rm),
+            Instruction::Mul(rm) => self.encode_unary(4, rm),
+            Instruction::Imul(rm) => self.encode_unary(5, rm),
+            Instruction::Div(rm) => self.encode_unary(6, rm),
+            Instruction::Idiv(rm) => self.encode_unary(7, rm),
+            Instruction::ImulReg(reg, rm) => {
+                self.prefixes(reg.size(), Some(reg), Some(rm));
+                self.bytes(&[0x0f, 0xaf]);
+                self.modrm(register_number(reg), rm);
+            }
+            Instruction::MovSX(reg, rm) => {
+                self.prefixes(reg.size(), Some(reg), Some(rm));
+                match rm_size(rm) {
+                    1 => self.bytes(&[0x0f, 0xbe]),
+                    2 => self.bytes(&[0x0f, 0xbf]),
+                    4 => self.bytes(&[0x63]),
+                    size => panic!("cannot sign extend {} byte value", size),
+                }
+                self.modrm(register_number(reg), rm);
+            }
+            Instruction::MovZX(reg, rm) => {
+                self.prefixes(reg.size(), Some(reg), Some(rm));
+                match rm_size(rm) {
+                    1 => self.bytes(&[0x0f, 0xb6]),
+                    2 => self.bytes(&[0x0f, 0xb7]),
+                    size => panic!("cannot zero extend {} byte value", size),
+                }
+                self.modrm(register_number(reg), rm);
+            }
+            Instruction::Lea(reg, mem) => {
+                let rm = Rm::Memory(mem);
+                self.prefixes(reg.size(), Some(reg), Some(rm));
+                self.bytes(&[0x8d]);
+                self.modrm(register_number(reg), rm);
+            }
+            Instruction::Cwd => self.bytes(&[0x66, 0x99]),
+            Instruction::Cdq => self.bytes(&[0x99]),
+            Instruction::Cqo => self.bytes(&[0x48, 0x99]),
+            Instruction::Setcc(cond, rm) => {
+                self.prefixes(1, None, Some(rm));
+                self.bytes(&[0x0f, 0x90 + condition_code(cond)]);
+                self.modrm(0, rm);
+            }
+            Instruction::Jmp(ref label) => {
+                self.bytes(&[0xe9]);
+                self.reference(label, ReferenceKind::Relative);
+            }
+            Instruction::Jcc(cond, ref label) => {
+                self.bytes(&[0x0f, 0x80 + condition_code(cond)]);
+                self.reference(label, ReferenceKind::Relative);
+            }
+            Instruction::Push(Rm::Register(reg)) => {
+                self.check_stack_operand(reg.size());
+                self.prefixes(0, None, Some(Rm::Register(reg)));
+                self.bytes(&[0x50 + register_number(reg)]);
+            }
+            Instruction::Push(rm) => {
+                self.check_stack_operand(rm_size(rm));
+                self.prefixes(0, None, Some(rm));
+                self.bytes(&[0xff]);
+                self.modrm(6, rm);
+            }
+            Instruction::Pop(Rm::Register(reg)) => {
+                self.check_stack_operand(reg.size());
+                self.prefixes(0, None, Some(Rm::Register(reg)));
+                self.bytes(&[0x58 + register_number(reg)]);
+            }
+            Instruction::Pop(rm) => {
+                self.check_stack_operand(rm_size(rm));
+                self.prefixes(0, None, Some(rm));
+                self.bytes(&[0x8f]);
+                self.modrm(0, rm);
+            }
+            Instruction::Call(Immediate::Label(ref label)) => {
+                self.bytes(&[0xe8]);
+                self.reference(label, ReferenceKind::Relative);
+            }
+            Instruction::Call(Immediate::Constant(_)) => {
+                panic!("cannot call a constant address")
+            }
+            Instruction::CallVirt(rm) => {
+                self.check_stack_operand(rm_size(rm));
+                self.prefixes(0, None, Some(rm));
+                self.bytes(&[0xff]);
+                self.modrm(2, rm);
+            }
+            Instruction::Ret => self.bytes(&[0xc3]),
+            Instruction::Invalid => self.bytes(&[0x0f, 0x0b]),
+            Instruction::Interrupt(number) => self.bytes(&[0xcd, number]),
+            Instruction::Syscall => self.bytes(&[0x0f, 0x05]),
+            Instruction::Label(ref label) => {
+                let offset = self.code.bytes.len() as u64;
+                if self.code.labels.insert(label.clone(), offset).is_some() {
+                    panic!("label {:?} is defined twice", label);
+                }
+            }
+            Instruction::SourcePosition(_) => {}
+        }
+    }
+
+    /// Encodes `add`, `or`, `and`, `sub`, `xor` and `cmp`, which
+    /// differ only by `op` - the opcode extension for immediate forms.
+    fn encode_arithmetic(&mut self, op: u8, args: &TwoArgs) {
+        match *args {
+            TwoArgs::RmReg(rm, reg) => {
+                let size = reg.size();
+                self.prefixes(size, Some(reg), Some(rm));
+                self.bytes(&[op * 8 + if size == 1 { 0 } else { 1 }]);
+                self.modrm(register_number(reg), rm);
+            }
+            TwoArgs::RegRm(reg, rm) => {
+                let size = reg.size();
+                self.prefixes(size, Some(reg), Some(rm));
+                self.bytes(&[op * 8 + if size == 1 { 2 } else { 3 }]);
+                self.modrm(register_number(reg), rm);
+            }
+            TwoArgs::RmImm(rm, ref imm) => {
+                let size = rm_size(rm);
+                self.prefixes(size, None, Some(rm));
+                if size == 1 {
+                    self.bytes(&[0x80]);
+                    self.modrm(op, rm);
+                    self.immediate(imm, 1, size);
+                } else if fits_in_byte(imm, size) {
+                    self.bytes(&[0x83]);
+                    self.modrm(op, rm);
+                    self.immediate(imm, 1, size);
+                } else {
+                    self.bytes(&[0x81]);
+                    self.modrm(op, rm);
+                    self.immediate(imm, size.min(4), size);
+                }
+            }
+        }
+    }
+
+    fn encode_mov(&mut self, args: &TwoArgs) {
+        match *args {
+            TwoArgs::RmReg(rm, reg) => {
+                let size = reg.size();
+                self.prefixes(size, Some(reg), Some(rm));
+                self.bytes(&[if size == 1 { 0x88 } else { 0x89 }]);
+                self.modrm(register_number(reg), rm);
+            }
+            TwoArgs::RegRm(reg, rm) => {
+                let size = reg.size();
+                self.prefixes(size, Some(reg), Some(rm));
+                self.bytes(&[if size == 1 { 0x8a } else { 0x8b }]);
+                self.modrm(register_number(reg), rm);
+            }
+            TwoArgs::RmImm(Rm::Register(reg), ref imm) => {
+                let size = reg.size();
+                let number = register_number(reg);
+                match *imm {
+                    Immediate::Constant(value) if size == 8 => {
+                        if value as i64 == i64::from(value as i32) {
+                            self.prefixes(8, None, Some(Rm::Register(reg)));
+                            self.bytes(&[0xc7]);
+                            self.modrm(0, Rm::Register(reg));
+                            self.immediate(imm, 4, size);
+                        } else if value >> 32 == 0 {
+                            // writing the lower half clears the upper one
+                            self.prefixes(4, None, Some(Rm::Register(reg)));
+                            self.bytes(&[0xb8 + number]);
+                            self.immediate(imm, 4, 4);
+                        } else {
+                            self.prefixes(8, None, Some(Rm::Register(reg)));
+                            self.bytes(&[0xb8 + number]);
+                            self.immediate(imm, 8, size);
+                        }
+                    }
+                    _ => {
+                        self.prefixes(size, None, Some(Rm::Register(reg)));
+                        self.bytes(&[if size == 1 { 0xb0 + number } else { 0xb8 + number }]);
+                        self.immediate(imm, size, size);
+                    }
+                }
+            }
+            TwoArgs::RmImm(rm, ref imm) => {
+                let size = rm_size(rm);
+                self.prefixes(size, None, Some(rm));
+                self.bytes(&[if size == 1 { 0xc6 } else { 0xc7 }]);
+                self.modrm(0, rm);
+                self.immediate(imm, size.min(4), size);
+            }
+        }
+    }
+
+    fn encode_test(&mut self, args: &TwoArgs) {
+        match *args {
+            TwoArgs::RmReg(rm, reg) | TwoArgs::RegRm(reg, rm) => {
+                let size = reg.size();
+                self.prefixes(size, Some(reg), Some(rm));
+                self.bytes(&[if size == 1 { 0x84 } else { 0x85 }]);
+                self.modrm(register_number(reg), rm);
+            }
+            TwoArgs::RmImm(rm, ref imm) => {
+                let size = rm_size(rm);
+                self.prefixes(size, None, Some(rm));
+                self.bytes(&[if size == 1 { 0xf6 } else { 0xf7 }]);
+                self.modrm(0, rm);
+                self.immediate(imm, size.min(4), size);
+            }
+        }
+    }
+
+    fn encode_shift(&mut self, op: u8, args: &TwoArgs) {
+        match *args {
+            TwoArgs::RmReg(rm, Register::Cl) => {
+                let size = rm_size(rm);
+                self.prefixes(size, None, Some(rm));
+                self.bytes(&[if size == 1 { 0xd2 } else { 0xd3 }]);
+                self.modrm(op, rm);
+            }
+            TwoArgs::RmImm(rm, Immediate::Constant(count)) => {
+                let size = rm_size(rm);
+                self.prefixes(size, None, Some(rm));
+                self.bytes(&[if size == 1 { 0xc0 } else { 0xc1 }]);
+                self.modrm(op, rm);
+                self.bytes(&[count as u8]);
+            }
+            ref args => panic!("bad shift operands: {:?}", args),
+        }
+    }
+
+    /// Encodes instructions that take a single r/m operand,
+    /// `op` is the opcode extension.
+    fn encode_unary(&mut self, op: u8, rm: Rm) {
+        let size = rm_size(rm);
+        self.prefixes(size, None, Some(rm));
+        self.bytes(&[if size == 1 { 0xf6 } else { 0xf7 }]);
+        self.modrm(op, rm);
+    }
+
+    fn check_stack_operand(&self, size: u32) {
+        let word = match self.mode {
+            Mode::Bits32 => 4,
+            Mode::Bits64 => 8,
+        };
+        if size != word {
+            panic!("stack operands must be {} bytes, got {}", word, size);
+        }
+    }
+
+    /// Emits operand size, address size and REX prefixes. Operand size
+    /// 0 means that the instruction does not need a prefix for it. `reg`
+    /// is the register in the reg field of ModRM, if there is one.
+    fn prefixes(&mut self, size: u32, reg: Option<Register>, rm: Option<Rm>) {
+        if size == 2 {
+            self.bytes(&[0x66]);
+        }
+        if let Some(Rm::Memory(mem)) = rm {
+            match (self.mode, mem.register.size()) {
+                (Mode::Bits32, 4) | (Mode::Bits64, 8) => {}
+                (Mode::Bits64, 4) => self.bytes(&[0x67]),
+                (_, size) => panic!("cannot address memory with {} byte register", size),
+            }
+        }
+        let mut rex = 0;
+        if size == 8 {
+            if self.mode != Mode::Bits64 {
+                panic!("64 bit operands are only available in 64 bit mode");
+            }
+            // REX.W
+            rex |= 0x48;
+        }
+        if reg.map_or(false, is_extended) {
+            // REX.R
+            rex |= 0x44;
+        }
+        let base = match rm {
+            Some(Rm::Register(reg)) => Some(reg),
+            Some(Rm::Memory(mem)) => Some(mem.register),
+            None => None,
+        };
+        if base.map_or(false, is_extended) {
+            // REX.B
+            rex |= 0x41;
+        }
+        if rex != 0 {
+            if self.mode != Mode::Bits64 {
+                panic!("registers r8 to r10 are only available in 64 bit mode");
+            }
+            self.bytes(&[rex]);
+        }
+    }
+
+    fn modrm(&mut self, reg: u8, rm: Rm) {
+        match rm {
+            Rm::Register(r) => self.bytes(&[0xc0 | (reg << 3) | register_number(r)]),
+            Rm::Memory(mem) => {
+                let base = register_number(mem.register);
+                // base 5 (ebp) without displacement means an absolute address
+                let mode = if mem.offset == 0 && base != 5 {
+                    0
+                } else if mem.offset == i32::from(mem.offset as i8) {
+                    1
+                } else {
+                    2
+                };
+                self.bytes(&[(mode << 6) | (reg << 3) | base]);
+                if base == 4 {
+                    // base 4 (esp) needs SIB byte
+                    self.bytes(&[0x24]);
+                }
+                match mode {
+                    1 => self.bytes(&[mem.offset as u8]),
+                    2 => self.bytes(&(mem.offset as u32).to_le_bytes()),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Emits an immediate of `size` bytes for an instruction
+    /// that works with `operand_size` byte values.
+    fn immediate(&mut self, imm: &Immediate, size: u32, operand_size: u32) {
+        match *imm {
+            Immediate::Constant(value) => {
+                if size == 4 && operand_size == 8 && value as i64 != i64::from(value as i32) {
+                    panic!("immediate {} does not fit in 32 bits", value);
+                }
+                let bytes = value.to_le_bytes();
+                self.bytes(&bytes[..size as usize]);
+            }
+            Immediate::Label(ref label) => {
+                if size != 4 && size != 8 {
+                    panic!("address of {:?} does not fit in {} bytes", label, size);
+                }
+                self.reference(label, ReferenceKind::Absolute(size));
+            }
+        }
+    }
+
+    fn reference(&mut self, label: &Label, kind: ReferenceKind) {
+        let size = match kind {
+            ReferenceKind::Relative => 4,
+            ReferenceKind::Absolute(size) => size,
+        };
+        self.code.references.push(Reference {
+            offset: self.code.bytes.len() as u64,
+            label: label.clone(),
+            kind,
+        });
+        let zeroes = [0; 8];
+        self.bytes(&zeroes[..size as usize]);
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.code.bytes.extend_from_slice(bytes);
+    }
+}
+
+fn fits_in_byte(imm: &Immediate, operand_size: u32) -> bool {
+    match *imm {
+        Immediate::Constant(value) => {
+            let value = match operand_size {
+                2 => i64::from(value as i16),
+                4 => i64::from(value as i32),
+                _ => value as i64,
+            };
+            value == i64::from(value as i8)
+        }
+        Immediate::Label(_) => false,
+    }
+}
+
+fn rm_size(rm: Rm) -> u32 {
+    match rm {
+        Rm::Register(reg) => reg.size(),
+        Rm::Memory(mem) => mem.ptr_size,
+    }
+}
+
+fn register_number(reg: Register) -> u8 {
+    use x86::Register::*;
+    match reg {
+        Rax | Eax | Ax | Al => 0,
+        Rcx | Ecx | Cx | Cl => 1,
+        Rdx | Edx | Dx | Dl => 2,
+        Rbx | Ebx | Bx | Bl => 3,
+        Rsp | Esp | Ah => 4,
+        Rbp | Ebp | Ch => 5,
+        Rsi | Esi | Dh => 6,
+        Rdi | Edi | Bh => 7,
+        R8 | R8d => 0,
+        R9 | R9d => 1,
+        R10 | R10d => 2,
+    }
+}
+
+/// Whether the register needs a REX prefix bit, in addition to its number.
+fn is_extended(reg: Register) -> bool {
+    use x86::Register::*;
+    match reg {
+        R8 | R8d | R9 | R9d | R10 | R10d => true,
+        _ => false,
+    }
+}
+
+fn condition_code(cond: Condition) -> u8 {
+    match cond {
+        x86::Condition::Overflow => 0x0,
+        x86::Condition::NoOverflow => 0x1,
+        x86::Condition::Below => 0x2,
+        x86::Condition::AboveEqual => 0x3,
+        x86::Condition::Equal => 0x4,
+        x86::Condition::NotEqual => 0x5,
+        x86::Condition::BelowEqual => 0x6,
+        x86::Condition::Above => 0x7,
+        x86::Condition::Less => 0xc,
+        x86::Condition::GreaterEqual => 0xd,
+        x86::Condition::LessEqual => 0xe,
+        x86::Condition::Greater => 0xf,
+    }
+}
@@ -1,10 +1,20 @@
+extern crate gimli;
+extern crate object;
+extern crate plank_errors;
 extern crate plank_ir;
 
 mod compiler;
+mod dwarf;
+mod elf;
+mod encoder;
 mod printer;
+mod peephole;
 mod return_fix;
 mod x86;
 
 pub use compiler::compile_program;
+pub use elf::write_object;
 pub use printer::{print_asm, print_prelude};
+pub use peephole::peephole_optimize;
 pub use return_fix::fix_function_returns;
+pub use x86::{Mode, Program, SourceFile};
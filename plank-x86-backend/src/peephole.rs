@@ -0,0 +1,112 @@
+use x86::{self, Instruction, TwoArgs, Rm, Immediate, Condition};
+
+
+/// Try to replace instructions at the start of `ops` with shorter
+/// equivalent ones. Returns how many instructions were replaced,
+/// together with their replacements.
+fn rewrite_at(ops: &[Instruction]) -> Option<(usize, Vec<Instruction>)> {
+    match *ops {
+        // mov eax, eax
+        [Instruction::Mov(TwoArgs::RegRm(a, Rm::Register(b))), ..] |
+        [Instruction::Mov(TwoArgs::RmReg(Rm::Register(a), b)), ..] if a == b => {
+            Some((1, Vec::new()))
+        }
+        // push eax; pop ebx
+        [Instruction::Push(Rm::Register(a)), Instruction::Pop(Rm::Register(b)), ..] => {
+            if a == b {
+                Some((2, Vec::new()))
+            } else {
+                let mov = Instruction::Mov(TwoArgs::RegRm(b, Rm::Register(a)));
+                Some((2, vec![mov]))
+            }
+        }
+        // jmp label_1; label_1:
+        [Instruction::Jmp(ref target), ref rest @ ..] => {
+            let falls_through = rest
+                .iter()
+                .take_while(|op| {
+                    matches!(**op, Instruction::Label(_) | Instruction::SourcePosition(_))
+                })
+                .any(|op| *op == Instruction::Label(target.clone()));
+            if falls_through {
+                Some((1, Vec::new()))
+            } else {
+                None
+            }
+        }
+        // je label_1; jmp label_2; label_1:
+        [
+            Instruction::Jcc(cond, ref skip),
+            Instruction::Jmp(ref target),
+            Instruction::Label(ref next),
+            ..
+        ] if skip == next => {
+            Some((2, vec![Instruction::Jcc(cond.opposite(), target.clone())]))
+        }
+        // sete bl; test bl, bl; jne label_1
+        [Instruction::Setcc(cond, ref rm), ref check, Instruction::Jcc(jump, ref target), ..] => {
+            let checks_zero = match *check {
+                Instruction::Test(TwoArgs::RegRm(a, Rm::Register(b))) => {
+                    a == b && *rm == Rm::Register(a)
+                }
+                Instruction::Cmp(TwoArgs::RmImm(ref checked, Immediate::Constant(0))) => {
+                    checked == rm
+                }
+                _ => false,
+            };
+            // setcc does not change flags, so they are still set by
+            // the comparison. The value is kept in case it is used later.
+            let jump = match jump {
+                Condition::NotEqual => cond,
+                Condition::Equal => cond.opposite(),
+                _ => return None,
+            };
+            if checks_zero {
+                let setcc = Instruction::Setcc(cond, *rm);
+                Some((3, vec![setcc, Instruction::Jcc(jump, target.clone())]))
+            } else {
+                None
+            }
+        }
+        // cmp eax, 0
+        [Instruction::Cmp(TwoArgs::RmImm(Rm::Register(reg), Immediate::Constant(0))), ..] => {
+            let test = Instruction::Test(TwoArgs::RegRm(reg, Rm::Register(reg)));
+            Some((1, vec![test]))
+        }
+        _ => None,
+    }
+}
+
+fn optimize_function(ops: &mut Vec<Instruction>) {
+    loop {
+        let mut changed = false;
+        let mut result = Vec::with_capacity(ops.len());
+        let mut pos = 0;
+        while pos < ops.len() {
+            if let Some((replaced, replacement)) = rewrite_at(&ops[pos..]) {
+                result.extend(replacement);
+                pos += replaced;
+                changed = true;
+            } else {
+                result.push(ops[pos].clone());
+                pos += 1;
+            }
+        }
+        *ops = result;
+        if !changed {
+            return;
+        }
+    }
+}
+
+/// Remove redundant instructions left by the code generator: moves
+/// of a register to itself, push/pop pairs, jumps to the next
+/// instruction, and branches on a value that was just set from flags.
+///
+/// This assumes that flags are never read after a jump, which
+/// holds for code produced by `compile_program`.
+pub fn peephole_optimize(program: &mut x86::Program) {
+    for f in &mut program.functions {
+        optimize_function(f);
+    }
+}
@@ -1,14 +1,40 @@
 use std::io;
 use std::io::prelude::*;
+use plank_ir::ir;
+use elf::file_instructions;
 use x86;
 
 
-pub fn print_prelude<W: Write>(mut to: W) -> io::Result<()> {
-    const PRELUDE: &'static str = "BITS 32
+pub fn print_prelude<W: Write>(mut to: W, asm: &x86::Program) -> io::Result<()> {
+    match asm.mode {
+        x86::Mode::Bits32 => writeln!(to, "{}", PRELUDE_32)?,
+        x86::Mode::Bits64 => writeln!(to, "{}", PRELUDE_64)?,
+    }
+    // paths of source files depend on the program
+    writeln!(to, "SECTION .text\n")?;
+    for op in &file_instructions(asm) {
+        print_instruction(&mut to, op)?;
+    }
+    writeln!(to, "\nSECTION .rodata\n")?;
+    for (index, file) in asm.files.iter().enumerate() {
+        write!(to, "builtin_ufail_ufile_u{} db ", index)?;
+        print_bytes(&mut to, file.path.as_bytes())?;
+    }
+    Ok(())
+}
+
+const PRELUDE_32: &'static str = "BITS 32
 GLOBAL _start
 SECTION .text
 
 _start:
+    call builtin_utime_unow
+    mov ecx, builtin_utime_ustart
+    mov [ecx], eax
+    mov eax, [esp]
+    lea edx, [esp + 4]
+    push edx
+    push eax
     call fn_umain
     mov ebx, eax
     mov eax, 1
@@ -31,41 +57,1261 @@ builtin_ugetc:
     push ecx
     sub esp, 4
     mov eax, 3
-    mov ebx, 2
+    mov ebx, 0
     lea ecx, [esp]
     mov edx, 1
     int 0x80
+    cmp eax, 1
+    movzx eax, byte [esp]
+    je .done
+    mov eax, -1
+.done:
+    add esp, 4
+    pop ecx
+    pop ebx
+    ret
+
+builtin_uprint_ui32:
+    push ebx
+    push ecx
+    push esi
+    mov eax, [esp + 16]
+    mov esi, 0
+    test eax, eax
+    jge builtin_uprint_udigits
+    neg eax
+    mov esi, 1
+    jmp builtin_uprint_udigits
+
+builtin_uprint_uu32:
+    push ebx
+    push ecx
+    push esi
+    mov eax, [esp + 16]
+    mov esi, 0
+
+; digits of eax are written from the end of a buffer
+; on the stack, with a minus sign if esi is set
+builtin_uprint_udigits:
+    sub esp, 12
+    lea ecx, [esp + 12]
+    mov ebx, 10
+.loop:
+    mov edx, 0
+    div ebx
+    add edx, 48
+    sub ecx, 1
+    mov [ecx], dl
+    test eax, eax
+    jne .loop
+    test esi, esi
+    je .write
+    sub ecx, 1
+    mov byte [ecx], 45
+.write:
+    mov eax, 4
+    mov ebx, 1
+    lea edx, [esp + 12]
+    sub edx, ecx
+    int 0x80
+    add esp, 12
+    pop esi
+    pop ecx
+    pop ebx
+    ret
+
+builtin_uwrite:
+    push ebx
+    push ecx
+    mov eax, 4
+    mov ebx, 1
+    mov ecx, [esp + 12]
+    mov edx, [esp + 16]
+    int 0x80
+    pop ecx
+    pop ebx
+    ret
+builtin_uread_ui32:
+    push ebx
+    push ecx
+    mov ebx, 0
+    mov ecx, 0
+.skip:
+    call builtin_ugetc
+    cmp eax, 32
+    je .skip
+    cmp eax, 9
+    je .skip
+    cmp eax, 10
+    je .skip
+    cmp eax, 13
+    je .skip
+    cmp eax, 45
+    jne .digit
+    mov ecx, 1
+.next:
+    call builtin_ugetc
+.digit:
+    sub eax, 48
+    cmp eax, 9
+    ja .done
+    mov edx, ebx
+    add ebx, ebx
+    add ebx, ebx
+    add ebx, edx
+    add ebx, ebx
+    add ebx, eax
+    jmp .next
+.done:
+    mov eax, ebx
+    test ecx, ecx
+    je .positive
+    neg eax
+.positive:
+    pop ecx
+    pop ebx
+    ret
+
+builtin_uread_uline:
+    push ebx
+    push ecx
+    push esi
+    mov ebx, [esp + 16]
+    mov ecx, [esp + 20]
+    mov esi, 0
+    call builtin_ugetc
+    test eax, eax
+    jl .done
+.loop:
+    test eax, eax
+    jl .end
+    cmp eax, 10
+    je .end
+    test ecx, ecx
+    je .next
+    mov [ebx], al
+    add ebx, 1
+    sub ecx, 1
+    add esi, 1
+.next:
+    call builtin_ugetc
+    jmp .loop
+.end:
+    mov eax, esi
+.done:
+    pop esi
+    pop ecx
+    pop ebx
+    ret
+builtin_ustr_ueq:
+    push ebx
+    push ecx
+    push esi
+    mov eax, 0
+    mov ecx, [esp + 20]
+    cmp ecx, [esp + 28]
+    jne .done
+    mov ebx, [esp + 16]
+    mov esi, [esp + 24]
+.loop:
+    test ecx, ecx
+    je .equal
+    mov dl, [ebx]
+    cmp dl, [esi]
+    jne .done
+    add ebx, 1
+    add esi, 1
+    sub ecx, 1
+    jmp .loop
+.equal:
+    mov eax, 1
+.done:
+    pop esi
+    pop ecx
+    pop ebx
+    ret
+
+builtin_ustr_ucopy:
+    push ebx
+    push ecx
+    push esi
+    mov ecx, [esp + 20]
+    cmp ecx, [esp + 28]
+    jbe .counted
+    mov ecx, [esp + 28]
+.counted:
+    mov eax, ecx
+    mov ebx, [esp + 16]
+    mov esi, [esp + 24]
+    cmp ebx, esi
+    ja .backward
+.forward:
+    test ecx, ecx
+    je .done
+    mov dl, [esi]
+    mov [ebx], dl
+    add ebx, 1
+    add esi, 1
+    sub ecx, 1
+    jmp .forward
+; copy from the end, in case the slices overlap
+.backward:
+    add ebx, ecx
+    add esi, ecx
+.back:
+    test ecx, ecx
+    je .done
+    sub ebx, 1
+    sub esi, 1
+    mov dl, [esi]
+    mov [ebx], dl
+    sub ecx, 1
+    jmp .back
+.done:
+    pop esi
+    pop ecx
+    pop ebx
+    ret
+
+builtin_ustr_ufind:
+    push ebx
+    push ecx
+    push esi
+    mov eax, 0
+.candidate:
+    mov ecx, [esp + 20]
+    sub ecx, eax
+    cmp ecx, [esp + 28]
+    jb .missing
+    mov ebx, [esp + 16]
+    add ebx, eax
+    mov esi, [esp + 24]
+    mov ecx, [esp + 28]
+.compare:
+    test ecx, ecx
+    je .done
+    mov dl, [ebx]
+    cmp dl, [esi]
+    jne .next
+    add ebx, 1
+    add esi, 1
+    sub ecx, 1
+    jmp .compare
+.next:
+    add eax, 1
+    jmp .candidate
+.missing:
+    mov eax, -1
+.done:
+    pop esi
+    pop ecx
+    pop ebx
+    ret
+
+; every heap block is mapped on its own, with the size of the
+; mapping kept in the 16 bytes before the returned pointer
+builtin_ualloc:
+    push ebx
+    push ecx
+    sub esp, 24
+    mov eax, [esp + 36]
+    add eax, 16
+    jb .fail
+    mov dword [esp], 0
+    mov [esp + 4], eax
+    mov dword [esp + 8], 3
+    mov dword [esp + 12], 34
+    mov dword [esp + 16], -1
+    mov dword [esp + 20], 0
+    mov eax, 90
+    mov ebx, esp
+    int 0x80
+    cmp eax, -4096
+    ja .fail
+    mov ecx, [esp + 4]
+    mov [eax], ecx
+    add eax, 16
+    jmp .done
+.fail:
+    mov eax, 0
+.done:
+    add esp, 24
+    pop ecx
+    pop ebx
+    ret
+
+builtin_ufree:
+    push ebx
+    push ecx
+    mov ebx, [esp + 12]
+    test ebx, ebx
+    je .done
+    sub ebx, 16
+    mov ecx, [ebx]
+    mov eax, 91
+    int 0x80
+.done:
+    pop ecx
+    pop ebx
+    ret
+
+; the old block is only freed if a new one could be allocated
+builtin_urealloc:
+    push ebx
+    push ecx
+    push esi
+    push dword [esp + 20]
+    call builtin_ualloc
+    add esp, 4
+    mov esi, [esp + 16]
+    test eax, eax
+    je .done
+    test esi, esi
+    je .done
+    mov ecx, [esi - 16]
+    sub ecx, 16
+    cmp ecx, [esp + 20]
+    jbe .counted
+    mov ecx, [esp + 20]
+.counted:
+    mov ebx, eax
+.copy:
+    test ecx, ecx
+    je .free
+    mov dl, [esi]
+    mov [ebx], dl
+    add esi, 1
+    add ebx, 1
+    sub ecx, 1
+    jmp .copy
+.free:
+    mov ebx, eax
+    push dword [esp + 16]
+    call builtin_ufree
+    add esp, 4
+    mov eax, ebx
+.done:
+    pop esi
+    pop ecx
+    pop ebx
+    ret
+
+; same as `builtin_assert_fail`, with another message before the one given
+builtin_upanic_ufail:
+    mov ecx, builtin_upanic_ufail_umessage
+    mov edx, 10
+    jmp builtin_uassert_ufail.prefix
+
+; writes the message of a failed assert and its position to standard
+; error, and exits with status 1 like the C backend does
+builtin_uassert_ufail:
+    mov ecx, builtin_uassert_ufail_umessage
+    mov edx, 18
+.prefix:
+    call builtin_ufail_uwrite
+    mov ecx, [esp + 4]
+    mov edx, [esp + 8]
+    call builtin_ufail_uwrite
+    add esp, 8
+    jmp builtin_ufail_uposition
+
+; writes the index and length of a failed bounds check, and the position
+; of the check, to standard error and exits with status 1
+builtin_ubounds_ufail:
+    mov ecx, builtin_ubounds_ufail_uindex
+    mov edx, 34
+    call builtin_ufail_uwrite
+    mov eax, [esp + 4]
+    call builtin_ufail_uu32
+    mov ecx, builtin_ubounds_ufail_ulength
+    mov edx, 19
+    call builtin_ufail_uwrite
+    mov eax, [esp + 8]
+    call builtin_ufail_uu32
+    add esp, 8
+    jmp builtin_ufail_uposition
+
+; writes the operation and operands of checked arithmetic that doesn't
+; fit in its type, like `builtin_bounds_fail`
+builtin_uoverflow_ufail:
+    mov ecx, builtin_uoverflow_ufail_umessage
+    mov edx, 21
+    call builtin_ufail_uwrite
+    mov eax, [esp + 4]
+    call builtin_uoverflow_ufail_uoperand
+    mov edx, ' '
+    call builtin_ufail_uchar
+    mov edx, [esp + 12]
+    call builtin_ufail_uchar
+    mov edx, ' '
+    call builtin_ufail_uchar
+    mov eax, [esp + 8]
+    call builtin_uoverflow_ufail_uoperand
+    mov ecx, builtin_uoverflow_ufail_uin
+    mov edx, 4
+    call builtin_ufail_uwrite
+    mov edx, [esp + 16]
+    call builtin_ufail_uchar
+    mov eax, [esp + 20]
+    call builtin_ufail_uu32
+    add esp, 20
+
+; writes the line and column in the first two arguments, unless the line
+; is zero, and exits with status 1. The path of the file that the line
+; is in comes first if the program was compiled from several files.
+builtin_ufail_uposition:
+    mov eax, [esp + 4]
+    test eax, eax
+    je builtin_ufail_uexit
+    mov ecx, builtin_ufail_uat
+    mov edx, 4
+    call builtin_ufail_uwrite
+    mov eax, [esp + 4]
+    call builtin_ufail_ufile
+    push eax
+    test edx, edx
+    je .line
+    call builtin_ufail_uwrite
+    mov edx, ':'
+    call builtin_ufail_uchar
+.line:
     pop eax
+    call builtin_ufail_uu32
+    mov edx, ':'
+    call builtin_ufail_uchar
+    mov eax, [esp + 8]
+    call builtin_ufail_uu32
+builtin_ufail_uexit:
+    mov edx, 10
+    call builtin_ufail_uchar
+    mov eax, 1
+    mov ebx, 1
+    int 0x80
+
+; writes the byte in dl to standard error
+builtin_ufail_uchar:
+    sub esp, 4
+    mov [esp], dl
+    mov ecx, esp
+    mov edx, 1
+    call builtin_ufail_uwrite
+    add esp, 4
+    ret
+
+; writes edx bytes at ecx to standard error
+builtin_ufail_uwrite:
+    mov eax, 4
+    mov ebx, 2
+    int 0x80
+    ret
+
+; writes eax in decimal to standard error
+builtin_ufail_uu32:
+    sub esp, 16
+    lea ecx, [esp + 16]
+    mov ebx, 10
+.digit:
+    mov edx, 0
+    div ebx
+    add edx, 48
+    sub ecx, 1
+    mov [ecx], dl
+    test eax, eax
+    jne .digit
+    lea edx, [esp + 16]
+    sub edx, ecx
+    call builtin_ufail_uwrite
+    add esp, 16
+    ret
+
+; writes operand in eax of `builtin_overflow_fail`, extended from its type
+builtin_uoverflow_ufail_uoperand:
+    cmp dword [esp + 24], 32
+    je .extended
+    cmp dword [esp + 24], 16
+    je .bit16
+    movzx eax, al
+    cmp dword [esp + 20], 'u'
+    je .extended
+    movsx eax, al
+    jmp .extended
+.bit16:
+    movzx eax, ax
+    cmp dword [esp + 20], 'u'
+    je .extended
+    movsx eax, ax
+.extended:
+    cmp dword [esp + 20], 'u'
+    je builtin_ufail_uu32
+    test eax, eax
+    jge builtin_ufail_uu32
+    neg eax
+    push eax
+    mov edx, '-'
+    call builtin_ufail_uchar
+    pop eax
+    jmp builtin_ufail_uu32
+
+builtin_urand_useed:
+    push ecx
+    mov ecx, builtin_urand_ustate
+    mov eax, [esp + 8]
+    test eax, eax
+    jne .store
+    mov eax, 2463534242
+.store:
+    mov [ecx], eax
+    pop ecx
+    ret
+
+; xorshift, like in the interpreter
+builtin_urand_uu32:
+    push ecx
+    mov ecx, builtin_urand_ustate
+    mov eax, [ecx]
+    mov edx, eax
+    shl edx, 13
+    xor eax, edx
+    mov edx, eax
+    shr edx, 17
+    xor eax, edx
+    mov edx, eax
+    shl edx, 5
+    xor eax, edx
+    mov [ecx], eax
+    pop ecx
+    ret
+
+; milliseconds of the monotonic clock in eax, wrapping around
+builtin_utime_unow:
+    push ebx
+    push ecx
+    sub esp, 8
+    mov eax, 265
+    mov ebx, 1
+    mov ecx, esp
+    int 0x80
+    mov eax, [esp + 4]
+    mov edx, 0
+    mov ecx, 1000000
+    div ecx
+    mov ecx, eax
+    mov eax, [esp]
+    mov edx, 1000
+    imul eax, edx
+    add eax, ecx
+    add esp, 8
     pop ecx
     pop ebx
     ret
+
+builtin_utime_ums:
+    call builtin_utime_unow
+    push ecx
+    mov ecx, builtin_utime_ustart
+    sub eax, [ecx]
+    pop ecx
+    ret
+
+; state of `rand_u32`, replaced by `rand_seed`, and
+; the time when the program started, for `time_ms`
+SECTION .data
+
+builtin_urand_ustate dd 2463534242
+builtin_utime_ustart dd 0
+
+; messages of failed checks
+SECTION .rodata
+
+builtin_ubounds_ufail_uindex db 'index out of bounds: the index is '
+builtin_ubounds_ufail_ulength db ' but the length is '
+builtin_uoverflow_ufail_umessage db 'arithmetic overflow: '
+builtin_uoverflow_ufail_uin db ' in '
+builtin_ufail_uat db ' at '
+builtin_uassert_ufail_umessage db 'assertion failed: '
+builtin_upanic_ufail_umessage db 'panicked: '
+";
+
+// syscall clobbers rcx, so it is saved together with
+// other registers that functions are expected to preserve
+const PRELUDE_64: &'static str = "BITS 64
+GLOBAL _start
+SECTION .text
+
+_start:
+    call builtin_utime_unow
+    mov rcx, builtin_utime_ustart
+    mov [rcx], eax
+    mov rax, [rsp]
+    lea rdx, [rsp + 8]
+    push rdx
+    push rax
+    call fn_umain
+    mov edi, eax
+    mov eax, 60
+    syscall
+
+builtin_uputc:
+    push rcx
+    push rsi
+    push rdi
+    mov eax, 1
+    mov edi, 1
+    lea rsi, [rsp + 32]
+    mov edx, 1
+    syscall
+    pop rdi
+    pop rsi
+    pop rcx
+    ret
+
+builtin_ugetc:
+    push rcx
+    push rsi
+    push rdi
+    sub rsp, 8
+    mov eax, 0
+    mov edi, 0
+    lea rsi, [rsp]
+    mov edx, 1
+    syscall
+    cmp rax, 1
+    movzx eax, byte [rsp]
+    je .done
+    mov eax, -1
+.done:
+    add rsp, 8
+    pop rdi
+    pop rsi
+    pop rcx
+    ret
+
+builtin_uprint_ui32:
+    push rcx
+    push rsi
+    push rdi
+    mov eax, [rsp + 32]
+    mov edi, 0
+    test eax, eax
+    jge builtin_uprint_udigits
+    neg eax
+    mov edi, 1
+    jmp builtin_uprint_udigits
+
+builtin_uprint_uu32:
+    push rcx
+    push rsi
+    push rdi
+    mov eax, [rsp + 32]
+    mov edi, 0
+
+; digits of eax are written from the end of a buffer
+; on the stack, with a minus sign if edi is set
+builtin_uprint_udigits:
+    sub rsp, 16
+    lea rsi, [rsp + 16]
+    mov ecx, 10
+.loop:
+    mov edx, 0
+    div ecx
+    add edx, 48
+    sub rsi, 1
+    mov [rsi], dl
+    test eax, eax
+    jne .loop
+    test edi, edi
+    je .write
+    sub rsi, 1
+    mov byte [rsi], 45
+.write:
+    mov eax, 1
+    mov edi, 1
+    lea rdx, [rsp + 16]
+    sub rdx, rsi
+    syscall
+    add rsp, 16
+    pop rdi
+    pop rsi
+    pop rcx
+    ret
+
+builtin_uwrite:
+    push rcx
+    push rsi
+    push rdi
+    mov eax, 1
+    mov edi, 1
+    mov rsi, [rsp + 32]
+    mov edx, [rsp + 40]
+    syscall
+    pop rdi
+    pop rsi
+    pop rcx
+    ret
+builtin_uread_ui32:
+    push rsi
+    push rdi
+    mov esi, 0
+    mov edi, 0
+.skip:
+    call builtin_ugetc
+    cmp eax, 32
+    je .skip
+    cmp eax, 9
+    je .skip
+    cmp eax, 10
+    je .skip
+    cmp eax, 13
+    je .skip
+    cmp eax, 45
+    jne .digit
+    mov edi, 1
+.next:
+    call builtin_ugetc
+.digit:
+    sub eax, 48
+    cmp eax, 9
+    ja .done
+    mov edx, esi
+    add esi, esi
+    add esi, esi
+    add esi, edx
+    add esi, esi
+    add esi, eax
+    jmp .next
+.done:
+    mov eax, esi
+    test edi, edi
+    je .positive
+    neg eax
+.positive:
+    pop rdi
+    pop rsi
+    ret
+
+builtin_uread_uline:
+    push rcx
+    push rsi
+    push rdi
+    mov rsi, [rsp + 32]
+    mov ecx, [rsp + 40]
+    mov edi, 0
+    call builtin_ugetc
+    test eax, eax
+    jl .done
+.loop:
+    test eax, eax
+    jl .end
+    cmp eax, 10
+    je .end
+    test ecx, ecx
+    je .next
+    mov [rsi], al
+    add rsi, 1
+    sub ecx, 1
+    add edi, 1
+.next:
+    call builtin_ugetc
+    jmp .loop
+.end:
+    mov eax, edi
+.done:
+    pop rdi
+    pop rsi
+    pop rcx
+    ret
+builtin_ustr_ueq:
+    push rcx
+    push rsi
+    push rdi
+    mov eax, 0
+    mov ecx, [rsp + 40]
+    cmp ecx, [rsp + 56]
+    jne .done
+    mov rdi, [rsp + 32]
+    mov rsi, [rsp + 48]
+.loop:
+    test ecx, ecx
+    je .equal
+    mov dl, [rdi]
+    cmp dl, [rsi]
+    jne .done
+    add rdi, 1
+    add rsi, 1
+    sub ecx, 1
+    jmp .loop
+.equal:
+    mov eax, 1
+.done:
+    pop rdi
+    pop rsi
+    pop rcx
+    ret
+
+builtin_ustr_ucopy:
+    push rcx
+    push rsi
+    push rdi
+    mov ecx, [rsp + 40]
+    cmp ecx, [rsp + 56]
+    jbe .counted
+    mov ecx, [rsp + 56]
+.counted:
+    mov eax, ecx
+    mov rdi, [rsp + 32]
+    mov rsi, [rsp + 48]
+    cmp rdi, rsi
+    ja .backward
+.forward:
+    test ecx, ecx
+    je .done
+    mov dl, [rsi]
+    mov [rdi], dl
+    add rdi, 1
+    add rsi, 1
+    sub ecx, 1
+    jmp .forward
+; copy from the end, in case the slices overlap
+.backward:
+    add rdi, rcx
+    add rsi, rcx
+.back:
+    test ecx, ecx
+    je .done
+    sub rdi, 1
+    sub rsi, 1
+    mov dl, [rsi]
+    mov [rdi], dl
+    sub ecx, 1
+    jmp .back
+.done:
+    pop rdi
+    pop rsi
+    pop rcx
+    ret
+
+builtin_ustr_ufind:
+    push rcx
+    push rsi
+    push rdi
+    mov eax, 0
+.candidate:
+    mov ecx, [rsp + 40]
+    sub ecx, eax
+    cmp ecx, [rsp + 56]
+    jb .missing
+    mov rdi, [rsp + 32]
+    add rdi, rax
+    mov rsi, [rsp + 48]
+    mov ecx, [rsp + 56]
+.compare:
+    test ecx, ecx
+    je .done
+    mov dl, [rdi]
+    cmp dl, [rsi]
+    jne .next
+    add rdi, 1
+    add rsi, 1
+    sub ecx, 1
+    jmp .compare
+.next:
+    add eax, 1
+    jmp .candidate
+.missing:
+    mov eax, -1
+.done:
+    pop rdi
+    pop rsi
+    pop rcx
+    ret
+
+; every heap block is mapped on its own, with the size of the
+; mapping kept in the 16 bytes before the returned pointer
+builtin_ualloc:
+    push rcx
+    push rsi
+    push rdi
+    mov esi, [rsp + 32]
+    add rsi, 16
+    mov eax, 9
+    mov edi, 0
+    mov edx, 3
+    mov r10d, 34
+    mov r8, -1
+    mov r9d, 0
+    syscall
+    cmp rax, -4096
+    ja .fail
+    mov [rax], rsi
+    add rax, 16
+    jmp .done
+.fail:
+    mov eax, 0
+.done:
+    pop rdi
+    pop rsi
+    pop rcx
+    ret
+
+builtin_ufree:
+    push rcx
+    push rsi
+    push rdi
+    mov rdi, [rsp + 32]
+    test rdi, rdi
+    je .done
+    sub rdi, 16
+    mov rsi, [rdi]
+    mov eax, 11
+    syscall
+.done:
+    pop rdi
+    pop rsi
+    pop rcx
+    ret
+
+; the old block is only freed if a new one could be allocated
+builtin_urealloc:
+    push rcx
+    push rsi
+    push rdi
+    push qword [rsp + 40]
+    call builtin_ualloc
+    add rsp, 8
+    mov rsi, [rsp + 32]
+    test rax, rax
+    je .done
+    test rsi, rsi
+    je .done
+    mov rcx, [rsi - 16]
+    sub rcx, 16
+    mov edx, [rsp + 40]
+    cmp rcx, rdx
+    jbe .counted
+    mov rcx, rdx
+.counted:
+    mov rdi, rax
+.copy:
+    test rcx, rcx
+    je .free
+    mov dl, [rsi]
+    mov [rdi], dl
+    add rsi, 1
+    add rdi, 1
+    sub rcx, 1
+    jmp .copy
+.free:
+    mov rdi, rax
+    push qword [rsp + 32]
+    call builtin_ufree
+    add rsp, 8
+    mov rax, rdi
+.done:
+    pop rdi
+    pop rsi
+    pop rcx
+    ret
+
+; same as `builtin_assert_fail`, with another message before the one given
+builtin_upanic_ufail:
+    mov rsi, builtin_upanic_ufail_umessage
+    mov edx, 10
+    jmp builtin_uassert_ufail.prefix
+
+; writes the message of a failed assert and its position to standard
+; error, and exits with status 1 like the C backend does
+builtin_uassert_ufail:
+    mov rsi, builtin_uassert_ufail_umessage
+    mov edx, 18
+.prefix:
+    call builtin_ufail_uwrite
+    mov rsi, [rsp + 8]
+    mov edx, [rsp + 16]
+    call builtin_ufail_uwrite
+    add rsp, 16
+    jmp builtin_ufail_uposition
+
+; writes the index and length of a failed bounds check, and the position
+; of the check, to standard error and exits with status 1
+builtin_ubounds_ufail:
+    mov rsi, builtin_ubounds_ufail_uindex
+    mov edx, 34
+    call builtin_ufail_uwrite
+    mov eax, [rsp + 8]
+    call builtin_ufail_uu32
+    mov rsi, builtin_ubounds_ufail_ulength
+    mov edx, 19
+    call builtin_ufail_uwrite
+    mov eax, [rsp + 16]
+    call builtin_ufail_uu32
+    add rsp, 16
+    jmp builtin_ufail_uposition
+
+; writes the operation and operands of checked arithmetic that doesn't
+; fit in its type, like `builtin_bounds_fail`
+builtin_uoverflow_ufail:
+    mov rsi, builtin_uoverflow_ufail_umessage
+    mov edx, 21
+    call builtin_ufail_uwrite
+    mov eax, [rsp + 8]
+    call builtin_uoverflow_ufail_uoperand
+    mov edx, ' '
+    call builtin_ufail_uchar
+    mov edx, [rsp + 24]
+    call builtin_ufail_uchar
+    mov edx, ' '
+    call builtin_ufail_uchar
+    mov eax, [rsp + 16]
+    call builtin_uoverflow_ufail_uoperand
+    mov rsi, builtin_uoverflow_ufail_uin
+    mov edx, 4
+    call builtin_ufail_uwrite
+    mov edx, [rsp + 32]
+    call builtin_ufail_uchar
+    mov eax, [rsp + 40]
+    call builtin_ufail_uu32
+    add rsp, 40
+
+; writes the line and column in the first two arguments, unless the line
+; is zero, and exits with status 1. The path of the file that the line
+; is in comes first if the program was compiled from several files.
+builtin_ufail_uposition:
+    mov eax, [rsp + 8]
+    test eax, eax
+    je builtin_ufail_uexit
+    mov rsi, builtin_ufail_uat
+    mov edx, 4
+    call builtin_ufail_uwrite
+    mov eax, [rsp + 8]
+    call builtin_ufail_ufile
+    push rax
+    test edx, edx
+    je .line
+    call builtin_ufail_uwrite
+    mov edx, ':'
+    call builtin_ufail_uchar
+.line:
+    pop rax
+    call builtin_ufail_uu32
+    mov edx, ':'
+    call builtin_ufail_uchar
+    mov eax, [rsp + 16]
+    call builtin_ufail_uu32
+builtin_ufail_uexit:
+    mov edx, 10
+    call builtin_ufail_uchar
+    mov eax, 60
+    mov edi, 1
+    syscall
+
+; writes the byte in dl to standard error
+builtin_ufail_uchar:
+    sub rsp, 8
+    mov [rsp], dl
+    mov rsi, rsp
+    mov edx, 1
+    call builtin_ufail_uwrite
+    add rsp, 8
+    ret
+
+; writes edx bytes at rsi to standard error
+builtin_ufail_uwrite:
+    mov eax, 1
+    mov edi, 2
+    syscall
+    ret
+
+; writes eax in decimal to standard error
+builtin_ufail_uu32:
+    sub rsp, 16
+    lea rcx, [rsp + 16]
+    mov ebx, 10
+.digit:
+    mov edx, 0
+    div ebx
+    add edx, 48
+    sub rcx, 1
+    mov [rcx], dl
+    test eax, eax
+    jne .digit
+    mov rsi, rcx
+    lea rdx, [rsp + 16]
+    sub rdx, rcx
+    call builtin_ufail_uwrite
+    add rsp, 16
+    ret
+
+; writes operand in eax of `builtin_overflow_fail`, extended from its type
+builtin_uoverflow_ufail_uoperand:
+    cmp dword [rsp + 48], 32
+    je .extended
+    cmp dword [rsp + 48], 16
+    je .bit16
+    movzx eax, al
+    cmp dword [rsp + 40], 'u'
+    je .extended
+    movsx eax, al
+    jmp .extended
+.bit16:
+    movzx eax, ax
+    cmp dword [rsp + 40], 'u'
+    je .extended
+    movsx eax, ax
+.extended:
+    cmp dword [rsp + 40], 'u'
+    je builtin_ufail_uu32
+    test eax, eax
+    jge builtin_ufail_uu32
+    neg eax
+    push rax
+    mov edx, '-'
+    call builtin_ufail_uchar
+    pop rax
+    jmp builtin_ufail_uu32
+
+builtin_urand_useed:
+    push rcx
+    mov rcx, builtin_urand_ustate
+    mov eax, [rsp + 16]
+    test eax, eax
+    jne .store
+    mov eax, 2463534242
+.store:
+    mov [rcx], eax
+    pop rcx
+    ret
+
+; xorshift, like in the interpreter
+builtin_urand_uu32:
+    push rcx
+    mov rcx, builtin_urand_ustate
+    mov eax, [rcx]
+    mov edx, eax
+    shl edx, 13
+    xor eax, edx
+    mov edx, eax
+    shr edx, 17
+    xor eax, edx
+    mov edx, eax
+    shl edx, 5
+    xor eax, edx
+    mov [rcx], eax
+    pop rcx
+    ret
+
+; milliseconds of the monotonic clock in eax, wrapping around
+builtin_utime_unow:
+    push rcx
+    push rsi
+    push rdi
+    sub rsp, 16
+    mov eax, 228
+    mov edi, 1
+    mov rsi, rsp
+    syscall
+    mov eax, [rsp + 8]
+    mov edx, 0
+    mov ecx, 1000000
+    div ecx
+    mov ecx, eax
+    mov eax, [rsp]
+    mov edx, 1000
+    imul eax, edx
+    add eax, ecx
+    add rsp, 16
+    pop rdi
+    pop rsi
+    pop rcx
+    ret
+
+builtin_utime_ums:
+    call builtin_utime_unow
+    push rcx
+    mov rcx, builtin_utime_ustart
+    sub eax, [rcx]
+    pop rcx
+    ret
+
+; state of `rand_u32`, replaced by `rand_seed`, and
+; the time when the program started, for `time_ms`
+SECTION .data
+
+builtin_urand_ustate dd 2463534242
+builtin_utime_ustart dd 0
+
+; messages of failed checks
+SECTION .rodata
+
+builtin_ubounds_ufail_uindex db 'index out of bounds: the index is '
+builtin_ubounds_ufail_ulength db ' but the length is '
+builtin_uoverflow_ufail_umessage db 'arithmetic overflow: '
+builtin_uoverflow_ufail_uin db ' in '
+builtin_ufail_uat db ' at '
+builtin_uassert_ufail_umessage db 'assertion failed: '
+builtin_upanic_ufail_umessage db 'panicked: '
 ";
-    writeln!(to, "{}", PRELUDE)
-}
 
 pub fn print_asm<W: Write>(mut to: W, asm: &x86::Program) -> io::Result<()> {
+    let foreign = foreign_functions(asm);
+    for name in &foreign {
+        writeln!(to, "EXTERN {}", name)?;
+    }
+    if !foreign.is_empty() {
+        writeln!(to)?;
+    }
     writeln!(to, "SECTION .text\n")?;
     for f in &asm.functions {
         for op in f {
-            print_instruction(&mut to, op)?;
+            match (op, &asm.debug) {
+                (&x86::Instruction::SourcePosition(pos), &Some(ref debug)) => {
+                    let (file, line) = ir::locate_line(&asm.files, pos.line);
+                    let path = file.map_or(&debug.source.path, |file| &file.path);
+                    writeln!(to, "%line {}+0 {}", line + 1, path)?;
+                }
+                _ => print_instruction(&mut to, op)?,
+            }
         }
         writeln!(to)?;
     }
     writeln!(to, "SECTION .rodata\n")?;
     for (i, s) in asm.strings.iter().enumerate() {
         write!(to, "string_{} db ", i)?;
-        let mut first = true;
-        for ch in s {
-            if first {
-                write!(to, "{}", ch)?;
-                first = false;
-            } else {
-                write!(to, ", {}", ch)?;
+        print_bytes(&mut to, s)?;
+    }
+    Ok(())
+}
+
+/// Write bytes as a list of numbers for `db`, and end the line.
+fn print_bytes<W: Write>(to: &mut W, bytes: &[u8]) -> io::Result<()> {
+    let mut first = true;
+    for ch in bytes {
+        if first {
+            write!(to, "{}", ch)?;
+            first = false;
+        } else {
+            write!(to, ", {}", ch)?;
+        }
+    }
+    writeln!(to)
+}
+
+/// Functions defined outside of the program, which must be
+/// declared to the assembler, in order of first use.
+fn foreign_functions(asm: &x86::Program) -> Vec<&str> {
+    let mut names = Vec::new();
+    for op in asm.functions.iter().flat_map(|f| f.iter()) {
+        let imm = match *op {
+            x86::Instruction::Call(ref imm) |
+            x86::Instruction::Mov(x86::TwoArgs::RmImm(_, ref imm)) => imm,
+            _ => continue,
+        };
+        if let x86::Immediate::Label(x86::Label::Foreign(ref name)) = *imm {
+            if !names.contains(&&**name) {
+                names.push(&**name);
             }
         }
-        writeln!(to)?;
     }
-    Ok(())
+    names
 }
 
 fn print_instruction<W: Write>(to: &mut W, i: &x86::Instruction) -> io::Result<()> {
@@ -101,6 +1347,9 @@ fn print_instruction<W: Write>(to: &mut W, i: &x86::Instruction) -> io::Result<(
         x86::Instruction::Cwd => {
             writeln!(to, "    cwd")
         }
+        x86::Instruction::Cqo => {
+            writeln!(to, "    cqo")
+        }
         x86::Instruction::Div(rm) => {
             write!(to, "    div ")?;
             print_rm(to, rm)?;
@@ -138,6 +1387,7 @@ fn print_instruction<W: Write>(to: &mut W, i: &x86::Instruction) -> io::Result<(
             print_label(to, label)?;
             writeln!(to, ":")
         }
+        x86::Instruction::SourcePosition(_) => Ok(()),
         x86::Instruction::Lea(reg, mem) => {
             write!(to, "    lea {}, ", reg_name(reg))?;
             if mem.offset > 0 {
@@ -155,7 +1405,16 @@ fn print_instruction<W: Write>(to: &mut W, i: &x86::Instruction) -> io::Result<(
             writeln!(to)
         }
         x86::Instruction::MovSX(reg, rm) => {
-            write!(to, "    movsx {}, ", reg_name(reg))?;
+            let is_dword = match rm {
+                x86::Rm::Register(reg) => reg == reg.resize(4),
+                x86::Rm::Memory(mem) => mem.ptr_size == 4,
+            };
+            // sign extension from 32 bits has a separate mnemonic
+            if is_dword {
+                write!(to, "    movsxd {}, ", reg_name(reg))?;
+            } else {
+                write!(to, "    movsx {}, ", reg_name(reg))?;
+            }
             print_rm(to, rm)?;
             writeln!(to)
         }
@@ -192,6 +1451,12 @@ fn print_instruction<W: Write>(to: &mut W, i: &x86::Instruction) -> io::Result<(
         x86::Instruction::Ret => {
             writeln!(to, "    ret")
         }
+        x86::Instruction::Interrupt(number) => {
+            writeln!(to, "    int {}", number)
+        }
+        x86::Instruction::Syscall => {
+            writeln!(to, "    syscall")
+        }
         x86::Instruction::Setcc(cond, rm) => {
             write!(to, "    set{} ", cond_name(cond))?;
             print_rm(to, rm)?;
@@ -212,6 +1477,16 @@ fn print_instruction<W: Write>(to: &mut W, i: &x86::Instruction) -> io::Result<(
             print_args(to, args)?;
             writeln!(to)
         }
+        x86::Instruction::Shl(ref args) => {
+            write!(to, "    shl ")?;
+            print_args(to, args)?;
+            writeln!(to)
+        }
+        x86::Instruction::Shr(ref args) => {
+            write!(to, "    shr ")?;
+            print_args(to, args)?;
+            writeln!(to)
+        }
     }
 }
 
@@ -245,9 +1520,17 @@ fn print_label<W: Write>(to: &mut W, label: &x86::Label) -> io::Result<()> {
         x86::Label::Named(ref name) => print_name(to, name),
         x86::Label::Unnamed(id) => write!(to, "label_{}", id),
         x86::Label::String(id) => write!(to, "string_{}", id),
+        x86::Label::Foreign(ref name) => write!(to, "{}", name),
     }
 }
 
+/// Name of the label as it appears in generated assembly.
+pub(crate) fn label_name(label: &x86::Label) -> String {
+    let mut name = Vec::new();
+    print_label(&mut name, label).expect("writing to Vec cannot fail");
+    String::from_utf8(name).expect("label names are ascii")
+}
+
 fn print_name<W: Write>(to: &mut W, name: &str) -> io::Result<()> {
     for ch in name.chars() {
         match ch {
@@ -263,7 +1546,11 @@ fn print_name<W: Write>(to: &mut W, name: &str) -> io::Result<()> {
             '(' => write!(to, "_p")?,
             ')' => write!(to, "_c")?,
             '-' => write!(to, "_d")?,
-            c => panic!("bad label char: '{}', in label: '{}'", c, name),
+            '[' => write!(to, "_b")?,
+            ']' => write!(to, "_e")?,
+            // identifiers can also contain letters outside of ascii,
+            // the trailing `_` ends the hex digits
+            c => write!(to, "_x{:x}_", c as u32)?,
         }
     }
     Ok(())
@@ -281,6 +1568,7 @@ fn print_memory<W: Write>(to: &mut W, memory: x86::Memory) -> io::Result<()> {
         1 => "byte",
         2 => "word",
         4 => "dword",
+        8 => "qword",
         _ => panic!("bad ptr size: {}", memory.ptr_size),
     };
     if memory.offset > 0 {
@@ -294,6 +1582,20 @@ fn print_memory<W: Write>(to: &mut W, memory: x86::Memory) -> io::Result<()> {
 
 fn reg_name(reg: x86::Register) -> &'static str {
     match reg {
+        x86::Register::Rax => "rax",
+        x86::Register::Rbx => "rbx",
+        x86::Register::Rcx => "rcx",
+        x86::Register::Rdx => "rdx",
+        x86::Register::Rbp => "rbp",
+        x86::Register::Rsp => "rsp",
+        x86::Register::Rsi => "rsi",
+        x86::Register::Rdi => "rdi",
+        x86::Register::R8 => "r8",
+        x86::Register::R9 => "r9",
+        x86::Register::R10 => "r10",
+        x86::Register::R8d => "r8d",
+        x86::Register::R9d => "r9d",
+        x86::Register::R10d => "r10d",
         x86::Register::Al => "al",
         x86::Register::Ah => "ah",
         x86::Register::Ax => "ax",
@@ -329,5 +1631,7 @@ fn cond_name(cond: x86::Condition) -> &'static str {
         x86::Condition::Less => "l",
         x86::Condition::LessEqual => "le",
         x86::Condition::NotEqual => "ne",
+        x86::Condition::Overflow => "o",
+        x86::Condition::NoOverflow => "no",
     }
 }
@@ -1,5 +1,6 @@
 use std::collections::HashMap;
-use plank_ir::ir::{self, Program, Function, Instruction, Reg, Layout, Value, BlockEnd};
+use plank_ir::Target;
+use plank_ir::ir::{Program, Function, Instruction, Op, Reg, Layout, Value, BlockEnd};
 
 
 fn fresh_register(regs: &HashMap<Reg, Layout>) -> Reg {
@@ -11,15 +12,15 @@ fn fresh_register(regs: &HashMap<Reg, Layout>) -> Reg {
     Reg(regs.len() as u32)
 }
 
-fn fix_function(f: &mut Function) {
+fn fix_function(f: &mut Function, target: &Target) {
     let output_address = if let Some(layout) = f.output_layout {
         if layout.atomic {
             None
         } else {
             let reg = fresh_register(&f.registers);
             f.registers.insert(reg, Layout {
-                size: ir::POINTER_SIZE,
-                align: ir::POINTER_SIZE,
+                size: target.pointer_size.in_bytes(),
+                align: target.pointer_align(),
                 atomic: true,
             });
             f.parameters.insert(0, reg);
@@ -31,13 +32,13 @@ fn fix_function(f: &mut Function) {
     };
     for block in f.blocks.values_mut() {
         for i in (0..block.ops.len()).rev() {
-            let initializer = match block.ops[i] {
+            let initializer = match block.ops[i].instruction {
                 Instruction::Call(r, _, ref mut params) |
                 Instruction::CallVirt(r, _, ref mut params) if !f.registers[&r].atomic => {
                     let reg = fresh_register(&f.registers);
                     f.registers.insert(reg, Layout {
-                        size: ir::POINTER_SIZE,
-                        align: ir::POINTER_SIZE,
+                        size: target.pointer_size.in_bytes(),
+                        align: target.pointer_align(),
                         atomic: true,
                     });
                     params.insert(0, Value::Reg(reg));
@@ -46,23 +47,24 @@ fn fix_function(f: &mut Function) {
                 _ => None,
             };
             if let Some((param, out)) = initializer {
-                match block.ops[i].clone() {
+                match block.ops[i].instruction.clone() {
                     Instruction::Call(_, f, params) => {
-                        block.ops[i] = Instruction::CallProc(f, params);
+                        block.ops[i].instruction = Instruction::CallProc(f, params);
                     }
                     Instruction::CallVirt(_, f, params) => {
-                        block.ops[i] = Instruction::CallProcVirt(f, params);
+                        block.ops[i].instruction = Instruction::CallProcVirt(f, params);
                     }
                     _ => panic!("shit"),
                 }
-                block.ops.insert(i + 1, Instruction::Drop(param));
-                block.ops.insert(i, Instruction::TakeAddress(param, out, 0));
-                block.ops.insert(i, Instruction::Init(out));
+                let span = block.ops[i].span;
+                block.ops.insert(i + 1, Op::new(Instruction::Drop(param), span));
+                block.ops.insert(i, Op::new(Instruction::TakeAddress(param, out, 0), span));
+                block.ops.insert(i, Op::new(Instruction::Init(out), span));
             }
         }
         match (block.end.clone(), output_address) {
             (BlockEnd::Return(val), Some(reg)) => {
-                block.ops.push(Instruction::DerefStore(Value::Reg(reg), 0, val));
+                block.ops.push(Instruction::DerefStore(Value::Reg(reg), 0, val).into());
                 block.end = BlockEnd::ReturnProc;
             }
             _ => {}
@@ -71,7 +73,8 @@ fn fix_function(f: &mut Function) {
 }
 
 pub fn fix_function_returns(program: &mut Program) {
+    let target = program.target;
     for f in program.functions.values_mut() {
-        fix_function(f);
+        fix_function(f, &target);
     }
 }
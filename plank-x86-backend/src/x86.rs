@@ -1,8 +1,24 @@
-use std::rc::Rc;
+use std::collections::HashMap;
+use std::sync::Arc;
+use plank_errors::position::Position;
+use plank_ir::debug;
+use plank_ir::ir::{self, Reg};
 
 
 #[derive(PartialEq, Eq, Debug, Hash, Copy, Clone)]
 pub enum Register {
+    Rax,
+    Rbx,
+    Rcx,
+    Rdx,
+    Rbp,
+    Rsp,
+    Rsi,
+    Rdi,
+    /// Only available in 64 bit mode.
+    R8,
+    R9,
+    R10,
     Eax,
     Ebx,
     Ecx,
@@ -11,6 +27,9 @@ pub enum Register {
     Esp,
     Esi,
     Edi,
+    R8d,
+    R9d,
+    R10d,
     Ax,
     Bx,
     Cx,
@@ -25,6 +44,70 @@ pub enum Register {
     Dh,
 }
 
+impl Register {
+    /// Size of the register in bytes.
+    pub fn size(self) -> u32 {
+        use self::Register::*;
+        match self {
+            Rax | Rbx | Rcx | Rdx | Rbp | Rsp | Rsi | Rdi | R8 | R9 | R10 => 8,
+            Eax | Ebx | Ecx | Edx | Ebp | Esp | Esi | Edi | R8d | R9d | R10d => 4,
+            Ax | Bx | Cx | Dx => 2,
+            Al | Ah | Bl | Bh | Cl | Ch | Dl | Dh => 1,
+        }
+    }
+
+    /// The part of the same register that holds `size` bytes.
+    pub fn resize(self, size: u32) -> Register {
+        use self::Register::*;
+        let family = match self {
+            Rax | Eax | Ax | Al | Ah => Eax,
+            Rbx | Ebx | Bx | Bl | Bh => Ebx,
+            Rcx | Ecx | Cx | Cl | Ch => Ecx,
+            Rdx | Edx | Dx | Dl | Dh => Edx,
+            Rbp | Ebp => Ebp,
+            Rsp | Esp => Esp,
+            Rsi | Esi => Esi,
+            Rdi | Edi => Edi,
+            R8 | R8d => R8d,
+            R9 | R9d => R9d,
+            R10 | R10d => R10d,
+        };
+        match (family, size) {
+            (Eax, 1) => Al,
+            (Eax, 2) => Ax,
+            (Eax, 4) => Eax,
+            (Eax, 8) => Rax,
+            (Ebx, 1) => Bl,
+            (Ebx, 2) => Bx,
+            (Ebx, 4) => Ebx,
+            (Ebx, 8) => Rbx,
+            (Ecx, 1) => Cl,
+            (Ecx, 2) => Cx,
+            (Ecx, 4) => Ecx,
+            (Ecx, 8) => Rcx,
+            (Edx, 1) => Dl,
+            (Edx, 2) => Dx,
+            (Edx, 4) => Edx,
+            (Edx, 8) => Rdx,
+            (Ebp, 4) => Ebp,
+            (Ebp, 8) => Rbp,
+            (Esp, 4) => Esp,
+            (Esp, 8) => Rsp,
+            (Esi, 4) => Esi,
+            (Esi, 8) => Rsi,
+            (Edi, 4) => Edi,
+            (Edi, 8) => Rdi,
+            (R8d, 4) => R8d,
+            (R8d, 8) => R8,
+            (R9d, 4) => R9d,
+            (R9d, 8) => R9,
+            (R10d, 4) => R10d,
+            (R10d, 8) => R10,
+            (_, size) => panic!("{:?} has no part of size {}", self, size),
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Debug, Hash, Copy, Clone)]
 pub struct Memory {
     pub register: Register,
@@ -63,6 +146,8 @@ pub enum Condition {
     GreaterEqual,
     Less,
     LessEqual,
+    Overflow,
+    NoOverflow,
 }
 
 impl Condition {
@@ -78,6 +163,8 @@ impl Condition {
             Condition::Less => Condition::GreaterEqual,
             Condition::LessEqual => Condition::Greater,
             Condition::NotEqual => Condition::Equal,
+            Condition::Overflow => Condition::NoOverflow,
+            Condition::NoOverflow => Condition::Overflow,
         }
     }
 
@@ -93,6 +180,8 @@ impl Condition {
             Condition::Less => Condition::Greater,
             Condition::LessEqual => Condition::GreaterEqual,
             Condition::NotEqual => panic!("no order opposite for Condition::NotEqual"),
+            Condition::Overflow |
+            Condition::NoOverflow => panic!("no order opposite for {:?}", self),
         }
     }
 }
@@ -100,8 +189,10 @@ impl Condition {
 #[derive(PartialEq, Eq, Debug, Hash, Clone)]
 pub enum Label {
     Unnamed(u32),
-    Named(Rc<str>),
+    Named(Arc<str>),
     String(u32),
+    /// Function defined outside of the program, its name is used as is.
+    Foreign(Arc<str>),
 }
 
 #[derive(PartialEq, Eq, Debug, Hash, Clone)]
@@ -120,8 +211,11 @@ pub enum Instruction {
     And(TwoArgs),
     Or(TwoArgs),
     Xor(TwoArgs),
+    Shl(TwoArgs),
+    Shr(TwoArgs),
     Cwd,
     Cdq,
+    Cqo,
     Setcc(Condition, Rm),
     Jmp(Label),
     Jcc(Condition, Label),
@@ -134,11 +228,59 @@ pub enum Instruction {
     Call(Immediate),
     CallVirt(Rm),
     Ret,
+    Interrupt(u8),
+    Syscall,
     Label(Label),
+    /// Following instructions were compiled from source code at
+    /// this position. It is only used for debug info.
+    SourcePosition(Position),
+}
+
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum Mode {
+    Bits32,
+    Bits64,
 }
 
 #[derive(Debug, Clone)]
 pub struct Program {
+    pub mode: Mode,
     pub functions: Vec<Vec<Instruction>>,
     pub strings: Vec<Vec<u8>>,
+    pub debug: Option<DebugInfo>,
+    /// Files that the program was compiled from, if there were several.
+    /// Failed checks and debug info use them to point into the right one.
+    pub files: Vec<ir::SourceFile>,
+}
+
+/// Source file that a program was compiled from.
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    /// Path to the file, as it was given to the compiler.
+    pub path: String,
+    /// Directory that relative paths start from.
+    pub directory: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct DebugInfo {
+    pub source: SourceFile,
+    /// Description of each of `Program::functions`, in the same order.
+    pub functions: Vec<Option<FunctionDebugInfo>>,
+    pub types: HashMap<String, debug::Composite>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionDebugInfo {
+    pub function: debug::Function,
+    /// Where variables are kept. Variables that are missing
+    /// here were optimized out.
+    pub locations: HashMap<Reg, VariableLocation>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum VariableLocation {
+    Register(Register),
+    /// Offset from the frame pointer, which is kept in `ebp`.
+    Frame(i32),
 }
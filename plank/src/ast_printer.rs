@@ -1,4 +1,5 @@
-use plank_syntax::ast::{Program, Statement, Expr, Function, Struct, Ident, FnParam, Field, Type, FunctionType, BinaryOp, UnaryOp, CallParam, Literal, Signedness, Size, Mutability};
+use plank_syntax::ast::{Attribute, Program, Statement, Expr, Function, Struct, StructKind, Ident, ItemName, FnParam, Field, Type, FunctionType, BinaryOp, UnaryOp, CallParam, Literal, Signedness, Size, Mutability, Pattern};
+use plank_syntax::position::Spanned;
 
 
 #[derive(Copy, Clone)]
@@ -102,6 +103,12 @@ impl Formatter {
     }
 
     fn format_program(&mut self, program: &Program) {
+        for path in &program.imports {
+            self.fmt.start_list();
+            self.fmt.write_symbol("import");
+            self.fmt.write_symbol(&format!("{:?}", **path));
+            self.fmt.end_list();
+        }
         for s in &program.structs {
             self.format_struct(s);
         }
@@ -110,17 +117,49 @@ impl Formatter {
         }
     }
 
+    fn format_type_params(&mut self, name: &ItemName) {
+        self.fmt.start_list();
+        for (type_param, bound) in name.type_params.iter().zip(&name.bounds) {
+            if let Some(ref bound) = *bound {
+                self.fmt.start_list();
+                self.format_ident(type_param);
+                self.format_ident(bound);
+                self.fmt.end_list();
+            } else {
+                self.format_ident(type_param);
+            }
+        }
+        self.fmt.end_list();
+    }
+
     fn format_struct(&mut self, s: &Struct) {
         self.fmt.start_list();
-        self.fmt.write_symbol("def-struct");
+        self.fmt.write_symbol(match s.kind {
+            StructKind::Struct => "def-struct",
+            StructKind::Union => "def-union",
+        });
         self.format_ident(&s.name.name);
+        self.format_type_params(&s.name);
+        self.format_field_list(&s.fields, true);
+        self.format_attributes(&s.attributes);
+        self.fmt.end_list();
+    }
+
+    fn format_attributes(&mut self, attributes: &[Spanned<Attribute>]) {
+        if attributes.is_empty() {
+            return;
+        }
         self.fmt.start_list();
-        for type_param in &s.name.type_params {
-            self.format_ident(type_param);
+        self.fmt.write_symbol("attributes");
+        for attr in attributes {
+            self.fmt.start_list();
+            self.format_ident(&attr.name);
+            for arg in &attr.args {
+                self.format_ident(arg);
+            }
+            self.fmt.end_list();
         }
         self.fmt.end_list();
-        self.format_field_list(&s.fields, true);
-        self.fmt.end_list();
     }
 
     fn format_fn(&mut self, f: &Function) {
@@ -128,22 +167,22 @@ impl Formatter {
         match f.fn_type {
             FunctionType::Extern => {
                 self.fmt.write_symbol("def-extern-fn");
+                if let Some(ref convention) = f.calling_convention {
+                    self.fmt.write_symbol(&format!("{:?}", **convention));
+                }
             }
             FunctionType::Normal => {
                 self.fmt.write_symbol("def-fn");
             }
         }
         self.format_ident(&f.name.name);
-        self.fmt.start_list();
-        for type_param in &f.name.type_params {
-            self.format_ident(type_param);
-        }
-        self.fmt.end_list();
+        self.format_type_params(&f.name);
         self.format_param_list(&f.params, false);
         self.format_type(&f.return_type);
         if let Some(ref body) = f.body {
             self.format_statement(body);
         }
+        self.format_attributes(&f.attributes);
         self.fmt.end_list();
     }
 
@@ -164,6 +203,9 @@ impl Formatter {
             }
             self.format_ident(&p.name);
             self.format_type(&p.typ);
+            if let Some(ref default) = p.default {
+                self.format_expr(default);
+            }
             self.fmt.end_list();
         }
         self.fmt.end_list();
@@ -207,6 +249,18 @@ impl Formatter {
                 self.format_type(typ);
                 self.fmt.end_list();
             }
+            Type::Slice(Mutability::Const, ref typ) => {
+                self.fmt.start_list();
+                self.fmt.write_symbol("slice");
+                self.format_type(typ);
+                self.fmt.end_list();
+            }
+            Type::Slice(Mutability::Mut, ref typ) => {
+                self.fmt.start_list();
+                self.fmt.write_symbol("mutslice");
+                self.format_type(typ);
+                self.fmt.end_list();
+            }
             Type::Function(ref params, ref output) => {
                 self.fmt.start_list();
                 self.fmt.write_symbol("fn");
@@ -288,7 +342,6 @@ impl Formatter {
                         Mutability::Const => "let",
                         Mutability::Mut => "let-mut",
                     });
-                    self.fmt.write_symbol("let-mut");
                     self.format_ident(name);
                     if let Some(ref value) = *value {
                         self.format_expr(value);
@@ -296,12 +349,45 @@ impl Formatter {
                 }
                 self.fmt.end_list();
             }
+            Statement::LetPattern(ref pattern, ref value) => {
+                self.fmt.start_list();
+                self.fmt.write_symbol("let-pattern");
+                self.format_ident(&pattern.name);
+                for field in &pattern.fields {
+                    self.fmt.start_list();
+                    self.format_ident(&field.field);
+                    self.fmt.write_symbol(match field.mutability {
+                        Mutability::Const => "let",
+                        Mutability::Mut => "let-mut",
+                    });
+                    self.format_ident(&field.binding);
+                    self.fmt.end_list();
+                }
+                self.format_expr(value);
+                self.fmt.end_list();
+            }
             Statement::Loop(ref body) => {
                 self.fmt.start_list();
                 self.fmt.write_symbol("loop");
                 self.format_statement(body);
                 self.fmt.end_list();
             }
+            Statement::Match(ref value, ref arms) => {
+                self.fmt.start_list();
+                self.fmt.write_symbol("match");
+                self.fmt.make_list_multiline();
+                self.format_expr(value);
+                for arm in arms {
+                    self.fmt.start_list();
+                    match *arm.pattern {
+                        Pattern::Wildcard => self.fmt.write_symbol("_"),
+                        Pattern::Value(ref value) => self.format_expr(value),
+                    }
+                    self.format_statement(&arm.body);
+                    self.fmt.end_list();
+                }
+                self.fmt.end_list();
+            }
             Statement::Return(ref value) => {
                 self.fmt.start_list();
                 self.fmt.write_symbol("return");
@@ -357,6 +443,21 @@ impl Formatter {
                 self.format_ident(field);
                 self.fmt.end_list();
             }
+            Expr::Index(ref value, ref index) => {
+                self.fmt.start_list();
+                self.fmt.write_symbol("index");
+                self.format_expr(value);
+                self.format_expr(index);
+                self.fmt.end_list();
+            }
+            Expr::Slice(ref value, ref start, ref end) => {
+                self.fmt.start_list();
+                self.fmt.write_symbol("slice");
+                self.format_expr(value);
+                self.format_expr(start);
+                self.format_expr(end);
+                self.fmt.end_list();
+            }
             Expr::Literal(ref lit) => {
                 self.format_literal(lit);
             }
@@ -378,6 +479,14 @@ impl Formatter {
                 self.format_type(typ);
                 self.fmt.end_list();
             }
+            Expr::Lambda(ref params, ref return_type, ref body) => {
+                self.fmt.start_list();
+                self.fmt.write_symbol("lambda");
+                self.format_param_list(params, false);
+                self.format_type(return_type);
+                self.format_statement(body);
+                self.fmt.end_list();
+            }
             Expr::Error => self.fmt.write_symbol("?"),
         }
     }
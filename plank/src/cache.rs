@@ -0,0 +1,357 @@
+//! On-disk cache of compiled programs. Optimized IR is stored in the
+//! binary format together with the warnings of the program, under a
+//! key made from everything that it depends on: frontend options,
+//! optimization passes, the build of the compiler and the keys of
+//! source files.
+//!
+//! An entry holds a whole program, and nothing smaller is cached. Files
+//! share one namespace, generic functions are instantiated where they
+//! are used, lints like `dead_code` look at the whole program and
+//! inlining copies functions into their callers, so what is compiled
+//! from one file depends on the other files too. A program with a
+//! changed file is compiled again from the start.
+//!
+//! Keys are built from keys of single files though: the key of a file
+//! covers its path, its source and the keys of the files that it
+//! imports. Editing a file gives new keys to the files that import it,
+//! directly or through other files, and so to the programs that use
+//! it, while other programs keep their entries.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use plank_errors::codes;
+use plank_errors::position::{Position, Span};
+use plank_errors::reporter::{Diagnostic, Note, NoteStyle, Severity};
+use plank_ir::optimization::PassManager;
+
+
+/// Directory that compiled programs are cached in.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    directory: PathBuf,
+}
+
+/// Program that was stored in the cache, with the diagnostics
+/// that compiling it reported.
+#[derive(Debug)]
+pub struct Entry {
+    pub program: plank_ir::Program,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Cache {
+    /// Use given directory for the cache. It is created when the
+    /// first program is stored.
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Cache {
+        Cache {
+            directory: directory.into(),
+        }
+    }
+
+    /// Find a program that was stored with given key. Entries that
+    /// can't be read or are not valid are treated as missing, and so
+    /// are entries that were changed after they were written, which
+    /// is found with a checksum.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # extern crate plank;
+    /// # extern crate plank_ir;
+    /// use std::fs;
+    /// use plank::Compiler;
+    /// use plank::cache::Cache;
+    ///
+    /// let directory = std::env::temp_dir().join(format!("plank-entry-{}", std::process::id()));
+    /// let cache = Cache::new(&directory);
+    /// let source = "fn main() -> i32 {\n    return 1 + 2;\n}\n";
+    /// let program = Compiler::new().source(source).compile_to_ir().program.unwrap();
+    /// cache.store(7, &program, &[]).unwrap();
+    /// assert!(cache.load(7).is_some());
+    ///
+    /// let path = fs::read_dir(&directory).unwrap().next().unwrap().unwrap().path();
+    /// let bytes = fs::read(&path).unwrap();
+    /// // entries that were cut short, like when the disk filled up
+    /// for len in 0..bytes.len() {
+    ///     fs::write(&path, &bytes[..len]).unwrap();
+    ///     assert!(cache.load(7).is_none());
+    /// }
+    /// // entries with any byte changed
+    /// for index in 0..bytes.len() {
+    ///     let mut corrupt = bytes.clone();
+    ///     corrupt[index] ^= 0x10;
+    ///     fs::write(&path, &corrupt).unwrap();
+    ///     assert!(cache.load(7).is_none());
+    /// }
+    /// fs::write(&path, &bytes).unwrap();
+    /// assert!(cache.load(7).is_some());
+    /// # fs::remove_dir_all(&directory).unwrap();
+    /// ```
+    pub fn load(&self, key: u64) -> Option<Entry> {
+        let bytes = fs::read(self.entry(key)).ok()?;
+        let mut reader = Reader(&bytes[..]);
+        let checksum = reader.take(8)?;
+        if checksum != &checksum_of(reader.0).to_le_bytes()[..] {
+            return None;
+        }
+        let program_len = reader.u32()? as usize;
+        let program = plank_ir::decode_program(reader.take(program_len)?).ok()?;
+        if plank_ir::validate_ir(&program).is_err() {
+            return None;
+        }
+        let mut diagnostics = Vec::new();
+        for _ in 0..reader.u32()? {
+            diagnostics.push(reader.diagnostic()?);
+        }
+        if !reader.0.is_empty() {
+            return None;
+        }
+        Some(Entry {
+            program,
+            diagnostics,
+        })
+    }
+
+    /// Store a program and its diagnostics under given key,
+    /// replacing the old entry.
+    pub fn store(
+        &self,
+        key: u64,
+        program: &plank_ir::Program,
+        diagnostics: &[Diagnostic],
+    ) -> io::Result<()> {
+        let mut encoded = Vec::new();
+        plank_ir::encode_program(program, &mut encoded)?;
+        let mut writer = Writer(Vec::new());
+        writer.u32(encoded.len() as u32);
+        writer.0.extend_from_slice(&encoded);
+        writer.u32(diagnostics.len() as u32);
+        for diagnostic in diagnostics {
+            writer.diagnostic(diagnostic);
+        }
+        let mut bytes = checksum_of(&writer.0).to_le_bytes().to_vec();
+        bytes.extend_from_slice(&writer.0);
+        fs::create_dir_all(&self.directory)?;
+        // write to a temporary file first, so that compilers running
+        // at the same time never see a partially written entry
+        let temporary = self.directory.join(format!("{:016x}.{}.tmp", key, ::std::process::id()));
+        fs::write(&temporary, &bytes)?;
+        fs::rename(&temporary, self.entry(key))
+    }
+
+    fn entry(&self, key: u64) -> PathBuf {
+        self.directory.join(format!("{:016x}.plkb", key))
+    }
+}
+
+/// Key of a source file with given path and source, which imports
+/// files with given keys, in the order of its imports.
+pub fn file_key(path: &str, source: &str, imports: &[u64]) -> u64 {
+    let mut hasher = Fnv::new();
+    hasher.write(path.as_bytes());
+    hasher.write(source.as_bytes());
+    for import in imports {
+        hasher.write(&import.to_le_bytes());
+    }
+    hasher.0
+}
+
+/// Key of a program compiled with given options from files with given
+/// keys, like the ones from `Project::cache_keys`.
+pub fn key(files: &[u64], options: &plank_frontend::Options, passes: &PassManager) -> u64 {
+    let mut hasher = Fnv::new();
+    hasher.write(env!("CARGO_PKG_VERSION").as_bytes());
+    // the compiler is linked into the running executable, so a rebuilt
+    // compiler has a new executable and doesn't use old entries
+    let executable = ::std::env::current_exe().and_then(fs::metadata).and_then(|m| m.modified());
+    hasher.write(format!("{:?}", executable).as_bytes());
+    hasher.write(format!("{:?}", options).as_bytes());
+    for pass in passes.passes() {
+        hasher.write(pass.name.as_bytes());
+    }
+    hasher.write(format!("{:?}", passes.options()).as_bytes());
+    for file in files {
+        hasher.write(&file.to_le_bytes());
+    }
+    hasher.0
+}
+
+/// Checksum of an entry, which is written before it.
+fn checksum_of(bytes: &[u8]) -> u64 {
+    let mut hasher = Fnv::new();
+    hasher.write(bytes);
+    hasher.0
+}
+
+/// FNV-1a hash, which unlike the hasher of the standard library
+/// gives the same result in every build of the compiler.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Fnv {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    /// Add bytes to the hash, followed by their length so that
+    /// different splits of the same bytes hash differently.
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes.iter().chain(&(bytes.len() as u64).to_le_bytes()) {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// Writes diagnostics of an entry. Numbers are little endian, and
+/// strings and lists start with their length.
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.0.extend_from_slice(value.as_bytes());
+    }
+
+    fn optional_string(&mut self, value: Option<&str>) {
+        match value {
+            Some(value) => {
+                self.0.push(1);
+                self.string(value);
+            }
+            None => self.0.push(0),
+        }
+    }
+
+    fn span(&mut self, span: Span) {
+        for position in &[span.start, span.end] {
+            self.u32(position.line);
+            self.u32(position.column);
+            self.u32(position.offset);
+        }
+    }
+
+    fn diagnostic(&mut self, diagnostic: &Diagnostic) {
+        self.0.push(match diagnostic.severity {
+            Severity::Error => 0,
+            Severity::Warning => 1,
+            Severity::Note => 2,
+        });
+        self.string(&diagnostic.message);
+        match diagnostic.primary_span {
+            Some(span) => {
+                self.0.push(1);
+                self.span(span);
+            }
+            None => self.0.push(0),
+        }
+        self.u32(diagnostic.notes.len() as u32);
+        for note in &diagnostic.notes {
+            self.span(note.span);
+            self.optional_string(note.message.as_deref());
+            self.0.push(match note.style {
+                NoteStyle::Primary => 0,
+                NoteStyle::Secondary => 1,
+            });
+        }
+        self.optional_string(diagnostic.lint.as_deref());
+        self.optional_string(diagnostic.code);
+    }
+}
+
+/// Reads what `Writer` wrote, returns `None` if the bytes are not valid.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.0.len() {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let bytes = self.take(4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+
+    fn optional_string(&mut self) -> Option<Option<String>> {
+        match self.byte()? {
+            0 => Some(None),
+            1 => self.string().map(Some),
+            _ => None,
+        }
+    }
+
+    fn span(&mut self) -> Option<Span> {
+        let mut positions = [Position::new(0, 0); 2];
+        for position in &mut positions {
+            let line = self.u32()?;
+            let column = self.u32()?;
+            *position = Position::with_offset(line, column, self.u32()?);
+        }
+        Some(Span {
+            start: positions[0],
+            end: positions[1],
+        })
+    }
+
+    fn diagnostic(&mut self) -> Option<Diagnostic> {
+        let severity = match self.byte()? {
+            0 => Severity::Error,
+            1 => Severity::Warning,
+            2 => Severity::Note,
+            _ => return None,
+        };
+        let message = self.string()?;
+        let primary_span = match self.byte()? {
+            0 => None,
+            1 => Some(self.span()?),
+            _ => return None,
+        };
+        let mut notes = Vec::new();
+        for _ in 0..self.u32()? {
+            let span = self.span()?;
+            let message = self.optional_string()?;
+            let style = match self.byte()? {
+                0 => NoteStyle::Primary,
+                1 => NoteStyle::Secondary,
+                _ => return None,
+            };
+            notes.push(Note {
+                span,
+                message,
+                style,
+            });
+        }
+        let lint = self.optional_string()?;
+        // codes are static strings, so find the one that was stored
+        let code = match self.optional_string()? {
+            Some(code) => Some(*codes::ALL.iter().find(|&&known| known == code)?),
+            None => None,
+        };
+        Some(Diagnostic {
+            message,
+            primary_span,
+            severity,
+            notes,
+            lint,
+            code,
+        })
+    }
+}
@@ -0,0 +1,369 @@
+//! Plank compiler as a library, so that other programs can compile
+//! plank code without running the `plank` command.
+//!
+//! # Examples
+//!
+//! ```rust
+//! # extern crate plank;
+//! # extern crate plank_interpreter;
+//! use plank::{Compiler, Emit};
+//!
+//! let output = Compiler::new()
+//!     .source("fn main() -> i32 { return 7; }")
+//!     .emit(Emit::C)
+//!     .compile();
+//! assert!(output.succeeded());
+//! let program = output.program.unwrap();
+//! let exit_code = plank_interpreter::run_program(&program, &b""[..], Vec::new()).unwrap();
+//! assert_eq!(exit_code, 7);
+//! ```
+
+extern crate plank_errors;
+extern crate plank_syntax;
+extern crate plank_frontend;
+extern crate plank_ir;
+extern crate plank_x86_backend;
+extern crate plank_c_backend;
+
+mod ast_printer;
+pub mod cache;
+pub mod project;
+
+use plank_errors::reporter::{Diagnostic, Severity};
+use plank_errors::Reporter;
+use plank_errors::position::Position;
+use plank_syntax::position::Spanned;
+use cache::Cache;
+use project::Project;
+use plank_ir::optimization::{OptLevel, PassManager};
+
+
+/// What the compiler produces.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Emit {
+    /// Tokens of the source, one per line.
+    Tokens,
+    /// Syntax tree as S-expressions.
+    Ast,
+    /// Program like source code, with names followed by their symbols.
+    Resolved,
+    /// Plank IR in text form.
+    Ir,
+    /// Plank IR in binary form, which can be decoded later.
+    IrBinary,
+    /// Control flow graphs in Graphviz DOT format.
+    Cfg,
+    /// x86 assembly for NASM, including the prelude.
+    Asm,
+    /// C99 source code.
+    C,
+    /// Relocatable ELF object file.
+    Object,
+}
+
+/// Compiles a program. Options are set with builder methods, and
+/// default to an unoptimized build of IR for 32 bit x86.
+#[derive(Debug, Clone)]
+pub struct Compiler {
+    source: String,
+    /// Files that the source was joined from, empty for a single file.
+    files: Vec<plank_ir::ir::SourceFile>,
+    /// Where each of the files starts in the source, so that they are
+    /// parsed separately. Empty if the source is not from a project.
+    file_starts: Vec<Position>,
+    /// Cache keys of the files, `None` if the source is not from a project.
+    file_keys: Option<Vec<u64>>,
+    options: plank_frontend::Options,
+    passes: PassManager,
+    optimize_asm: bool,
+    debug_source: Option<plank_x86_backend::SourceFile>,
+    emit: Emit,
+    error_limit: Option<usize>,
+    cache: Option<Cache>,
+}
+
+/// Result of compiling a program.
+#[derive(Debug)]
+pub struct Output {
+    /// Errors and warnings, ordered by their position in the source.
+    pub diagnostics: Vec<Diagnostic>,
+    /// Optimized IR of the program, which can be given to the
+    /// interpreter or the JIT. Only set if the program was compiled
+    /// to IR or further.
+    pub program: Option<plank_ir::Program>,
+    /// Requested output, `None` if the program has errors.
+    pub artifact: Option<Vec<u8>>,
+}
+
+impl Output {
+    /// Whether the program compiled without errors.
+    pub fn succeeded(&self) -> bool {
+        self.diagnostics.iter().all(|d| d.severity != Severity::Error)
+    }
+}
+
+impl Default for Compiler {
+    fn default() -> Compiler {
+        Compiler::new()
+    }
+}
+
+impl Compiler {
+    /// Create a compiler for an empty program.
+    pub fn new() -> Compiler {
+        Compiler {
+            source: String::new(),
+            files: Vec::new(),
+            file_starts: Vec::new(),
+            file_keys: None,
+            options: plank_frontend::Options::default(),
+            passes: PassManager::new(),
+            optimize_asm: false,
+            debug_source: None,
+            emit: Emit::Ir,
+            error_limit: Some(plank_errors::reporter::DEFAULT_ERROR_LIMIT),
+            cache: None,
+        }
+    }
+
+    /// Set source code of the program.
+    pub fn source<S: Into<String>>(mut self, source: S) -> Self {
+        self.source = source.into();
+        self.files = Vec::new();
+        self.file_starts = Vec::new();
+        self.file_keys = None;
+        self
+    }
+
+    /// Compile the files of a project. Every file is parsed on its own,
+    /// so that syntax errors don't spill into the next file. Runtime
+    /// errors and debug info point into its files, and the cache only
+    /// reuses the program while none of the files have changed.
+    pub fn project(mut self, project: &Project) -> Self {
+        self.source = project.source().into();
+        self.files = project.ir_files();
+        self.file_starts = project.files().iter().map(|file| file.start()).collect();
+        self.file_keys = Some(project.cache_keys());
+        self
+    }
+
+    /// Set what the compiler produces.
+    pub fn emit(mut self, emit: Emit) -> Self {
+        self.emit = emit;
+        self
+    }
+
+    /// Set the machine to compile for.
+    pub fn target(mut self, target: plank_ir::Target) -> Self {
+        self.options.target = target;
+        self
+    }
+
+    /// Set frontend options, including the target.
+    pub fn options(mut self, options: plank_frontend::Options) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Optimize IR with the passes of given level. Assembly is
+    /// optimized too, unless the level is `O0`.
+    pub fn opt_level(mut self, level: OptLevel) -> Self {
+        self.passes = PassManager::for_level(level);
+        self.optimize_asm = level > OptLevel::O0;
+        self
+    }
+
+    /// Optimize IR with given passes instead of a whole level.
+    pub fn passes(mut self, passes: PassManager) -> Self {
+        self.passes = passes;
+        self.optimize_asm = false;
+        self
+    }
+
+    /// Include debug info about given file in assembly and object files.
+    pub fn debug_info(mut self, source: plank_x86_backend::SourceFile) -> Self {
+        self.debug_source = Some(source);
+        self
+    }
+
+    /// Set how many errors are reported before the rest are counted
+    /// in a single diagnostic, `None` to report all of them.
+    pub fn error_limit(mut self, limit: Option<usize>) -> Self {
+        self.error_limit = limit;
+        self
+    }
+
+    /// Reuse optimized IR from `cache` if the same program was compiled
+    /// with the same options before. Warnings are stored with the
+    /// program, and reported again when it is reused.
+    pub fn cache(mut self, cache: Cache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Compile the program to what should be emitted.
+    pub fn compile(&self) -> Output {
+        let reporter = self.new_reporter();
+        let mut program = None;
+        let artifact = self.run(&reporter, &mut program);
+        finish(&reporter, program, artifact)
+    }
+
+    /// Compile the program only to optimized IR, whatever
+    /// would be emitted. The artifact is always `None`.
+    pub fn compile_to_ir(&self) -> Output {
+        let reporter = self.new_reporter();
+        let program = self.build_ir(&reporter);
+        finish(&reporter, program, None)
+    }
+
+    fn new_reporter(&self) -> Reporter {
+        let reporter = Reporter::new();
+        reporter.set_error_limit(self.error_limit);
+        reporter
+    }
+
+    /// Source of every file, with the position where it starts.
+    fn source_files(&self) -> Vec<(&str, Position)> {
+        if self.file_starts.is_empty() {
+            return vec![(&self.source, Position::new(0, 0))];
+        }
+        let mut files = Vec::new();
+        for (index, &start) in self.file_starts.iter().enumerate() {
+            let end = match self.file_starts.get(index + 1) {
+                Some(next) => next.offset as usize,
+                None => self.source.len(),
+            };
+            files.push((&self.source[start.offset as usize..end], start));
+        }
+        files
+    }
+
+    fn lex(&self, reporter: &Reporter) -> Vec<Spanned<plank_syntax::tokens::Token>> {
+        let mut tokens = Vec::new();
+        for (source, start) in self.source_files() {
+            tokens.extend(plank_syntax::Lexer::starting_at(source, start, reporter.clone()));
+        }
+        tokens
+    }
+
+    /// Parse every file on its own, and put their items together.
+    fn parse(&self, reporter: &Reporter) -> plank_syntax::ast::Program {
+        let mut files = self.source_files().into_iter().map(|(source, start)| {
+            let tokens = plank_syntax::Lexer::starting_at(source, start, reporter.clone());
+            plank_syntax::parse(tokens, reporter.clone())
+        });
+        let mut program = files.next().expect("no source files");
+        for file in files {
+            program.structs.extend(file.structs);
+            program.functions.extend(file.functions);
+            program.possible_structs.extend(file.possible_structs);
+            program.possible_functions.extend(file.possible_functions);
+            program.imports.extend(file.imports);
+        }
+        program
+    }
+
+    fn build_ir(&self, reporter: &Reporter) -> Option<plank_ir::Program> {
+        let cached = self.cache.as_ref().map(|cache| {
+            let files = match self.file_keys {
+                Some(ref keys) => keys.clone(),
+                None => vec![cache::file_key("", &self.source, &[])],
+            };
+            (cache, cache::key(&files, &self.options, &self.passes))
+        });
+        if let Some(entry) = cached.and_then(|(cache, key)| cache.load(key)) {
+            for diagnostic in entry.diagnostics {
+                reporter.report(diagnostic);
+            }
+            return Some(entry.program);
+        }
+        let ast = self.parse(reporter);
+        let mut ir = plank_frontend::compile_with_options(&ast, &self.options, reporter.clone())
+            .ok()?;
+        ir.files = self.files.clone();
+        self.passes.run(&mut ir);
+        if let Some((cache, key)) = cached {
+            if !reporter.has_errors() {
+                // failing to write the cache only makes the next build slower
+                let _ = cache.store(key, &ir, &reporter.get_diagnostics());
+            }
+        }
+        Some(ir)
+    }
+
+    /// Compile to what should be emitted, storing the IR in
+    /// `program` if the program was compiled that far.
+    fn run(
+        &self,
+        reporter: &Reporter,
+        program: &mut Option<plank_ir::Program>,
+    ) -> Option<Vec<u8>> {
+        let mut output = Vec::new();
+        match self.emit {
+            Emit::Tokens => {
+                for tok in self.lex(reporter) {
+                    output.extend(format!("{:?}\n", *tok).into_bytes());
+                }
+                return Some(output);
+            }
+            Emit::Ast => {
+                let formatted = ast_printer::format_program(&self.parse(reporter));
+                return Some(format!("{}\n", formatted).into_bytes());
+            }
+            Emit::Resolved => {
+                let ast = self.parse(reporter);
+                let options = &self.options;
+                let resolved = plank_frontend::format_resolved(&ast, options, reporter.clone());
+                return resolved.map(String::into_bytes);
+            }
+            _ => {}
+        }
+        let ir = self.build_ir(reporter)?;
+        let written = match self.emit {
+            Emit::Ir => plank_ir::emit_program(&ir, &mut output),
+            Emit::IrBinary => plank_ir::encode_program(&ir, &mut output),
+            Emit::Cfg => plank_ir::emit_cfg(&ir, false, &mut output),
+            Emit::C => plank_c_backend::emit_program(&ir, &mut output),
+            Emit::Asm => {
+                let asm = self.lower_to_x86(ir.clone());
+                plank_x86_backend::print_prelude(&mut output, &asm)
+                    .and_then(|()| plank_x86_backend::print_asm(&mut output, &asm))
+            }
+            Emit::Object => {
+                let asm = self.lower_to_x86(ir.clone());
+                plank_x86_backend::write_object(&mut output, &asm, true)
+            }
+            Emit::Tokens | Emit::Ast | Emit::Resolved => unreachable!(),
+        };
+        written.expect("writing to memory failed");
+        *program = Some(ir);
+        Some(output)
+    }
+
+    fn lower_to_x86(&self, mut ir: plank_ir::Program) -> plank_x86_backend::Program {
+        plank_x86_backend::fix_function_returns(&mut ir);
+        let mut asm = plank_x86_backend::compile_program(&ir, self.debug_source.clone());
+        if self.optimize_asm {
+            plank_x86_backend::peephole_optimize(&mut asm);
+        }
+        asm
+    }
+}
+
+/// Collect diagnostics of a finished compilation, dropping
+/// its results if there were errors.
+fn finish(
+    reporter: &Reporter,
+    program: Option<plank_ir::Program>,
+    artifact: Option<Vec<u8>>,
+) -> Output {
+    let mut diagnostics = Vec::new();
+    reporter.emit_to(&mut diagnostics);
+    let failed = reporter.has_errors();
+    Output {
+        diagnostics,
+        program: if failed { None } else { program },
+        artifact: if failed { None } else { artifact },
+    }
+}
@@ -1,18 +1,26 @@
 extern crate clap;
+extern crate plank;
 extern crate plank_errors;
 extern crate plank_syntax;
 extern crate plank_frontend;
 extern crate plank_ir;
 extern crate plank_interpreter;
+extern crate plank_jit;
 extern crate plank_x86_backend;
-
-mod ast_printer;
+extern crate plank_c_backend;
 
 use std::convert::From;
+use std::ffi::OsStr;
 use std::io;
+use std::io::IsTerminal;
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
-use plank_errors::Reporter;
+use plank::{Compiler, Emit, Output};
+use plank::cache::Cache;
+use plank::project::{self, LoadError, Project, SourceFile};
+use plank_errors::DiagnosticSink;
+use plank_errors::reporter::{Diagnostic, Level, Severity};
+use plank_ir::optimization::{OptLevel, PassManager, PassOptions};
 
 
 #[derive(Debug)]
@@ -21,6 +29,25 @@ enum Error {
     BuildFail,
     Interpreter(plank_interpreter::Error),
     InterpreterExit(i32),
+    Jit(plank_jit::Error),
+    JitExit(i32),
+    UnknownErrorCode(String),
+    UnknownPass(String),
+    UnknownLint(String),
+    /// Input is compiled IR, but the command needs source code.
+    CompiledInput,
+    BadIr(String),
+    Linker(String),
+    /// An input file could not be read.
+    Read(PathBuf, io::Error),
+    /// Source was given on stdin together with other input files.
+    StdinWithFiles,
+    /// An executable was asked to be written to stdout.
+    ExecutableToStdout,
+    /// Some tests of the program failed.
+    TestFail,
+    /// `--check` found files that are not formatted.
+    Unformatted,
 }
 
 impl From<io::Error> for Error {
@@ -35,13 +62,52 @@ impl From<plank_interpreter::Error> for Error {
     }
 }
 
+impl From<plank_jit::Error> for Error {
+    fn from(err: plank_jit::Error) -> Error {
+        Error::Jit(err)
+    }
+}
+
 #[derive(Debug)]
 enum Command {
     Lex,
     Parse,
+    EmitResolved,
     EmitIr,
+    EmitCfg,
+    EmitIrBinary,
+    EmitC,
+    EmitObject,
+    /// Compile to an object file and link it into given executable.
+    Link(PathBuf),
     Interpret,
+    Jit,
     CompileX86,
+    Explain(String),
+    /// Run functions marked with `#[test]`.
+    Test,
+    /// Format source files, or only check that they are formatted.
+    Format { check: bool },
+}
+
+#[derive(Debug, Copy, Clone)]
+enum ErrorFormat {
+    Human,
+    Sarif,
+}
+
+/// Describes how diagnostics should be reported.
+#[derive(Debug)]
+struct ErrorOutput {
+    format: ErrorFormat,
+    colors: bool,
+    limit: Option<usize>,
+}
+
+enum Input {
+    Source(Project),
+    /// Program compiled to binary IR earlier.
+    Ir(plank_ir::Program),
 }
 
 #[derive(Debug)]
@@ -50,227 +116,939 @@ enum Stream {
     Std,
 }
 
+impl Stream {
+    /// Stream for a path given on the command line, where
+    /// a missing path or `-` means stdin or stdout.
+    fn from_arg(path: Option<&OsStr>) -> Stream {
+        match path {
+            Some(path) if path != "-" => Stream::File(Path::new(path).to_owned()),
+            _ => Stream::Std,
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Params {
     command: Command,
-    optimize: bool,
+    passes: PassManager,
     skip_prelude: bool,
-    input: Stream,
+    /// Run peephole optimizations on generated assembly.
+    optimize_asm: bool,
+    /// Emit debug info about this file with native code.
+    debug_source: Option<plank_x86_backend::SourceFile>,
+    dominators: bool,
+    options: plank_frontend::Options,
+    interpreter: plank_interpreter::Options,
+    linker: String,
+    /// Directory where compiled IR is cached between builds.
+    cache_directory: Option<PathBuf>,
+    error_format: ErrorFormat,
+    colors: bool,
+    error_limit: Option<usize>,
+    /// Input files, or directories with source files.
+    input: Vec<Stream>,
     output: Stream,
 }
 
 type Result<T> = ::std::result::Result<T, Error>;
 
+/// Exit code when the program could not be compiled, or plank
+/// failed for another reason, like missing files.
+const EXIT_FAILURE: i32 = 1;
+/// Exit code when the compiler itself crashed.
+const EXIT_INTERNAL_ERROR: i32 = 2;
+/// Exit code when a program run by plank stopped because of a bug,
+/// like a failed assertion or division by zero.
+const EXIT_TRAP: i32 = 101;
+
+/// Exit code of plank when a program that it ran exited with given
+/// nonzero code. Only the low byte of an exit code reaches the parent
+/// process, so codes where it is zero, like 256, exit with 255 instead,
+/// and don't look like success.
+fn program_exit_code(code: i32) -> i32 {
+    match code & 0xff {
+        0 => 0xff,
+        low => low,
+    }
+}
+
 fn main() {
-    match run() {
+    let result = match ::std::panic::catch_unwind(run) {
+        Ok(result) => result,
+        Err(_) => {
+            // the panic message was already printed by the panic hook
+            eprintln!("error: internal compiler error, this is a bug in plank");
+            ::std::process::exit(EXIT_INTERNAL_ERROR);
+        }
+    };
+    match result {
         Ok(()) => {}
         Err(Error::BuildFail) => {
             eprintln!("error: build failed");
-            ::std::process::exit(1);
+            ::std::process::exit(EXIT_FAILURE);
         }
         Err(Error::Io(err)) => {
             eprintln!("IO error:\n{}", err);
-            ::std::process::exit(1);
+            ::std::process::exit(EXIT_FAILURE);
         }
         Err(Error::Interpreter(ref err)) => {
             eprintln!("Interpreter failed:\n{}", err);
-            ::std::process::exit(1);
+            ::std::process::exit(if err.is_trap() { EXIT_TRAP } else { EXIT_FAILURE });
         }
         Err(Error::InterpreterExit(code)) => {
             eprintln!("Interpreter exited with status code {}", code);
-            ::std::process::exit(code);
+            ::std::process::exit(program_exit_code(code));
+        }
+        Err(Error::Jit(ref err)) => {
+            eprintln!("JIT failed:\n{}", err);
+            ::std::process::exit(if err.is_trap() { EXIT_TRAP } else { EXIT_FAILURE });
+        }
+        Err(Error::JitExit(code)) => {
+            eprintln!("Program exited with status code {}", code);
+            ::std::process::exit(program_exit_code(code));
+        }
+        Err(Error::UnknownErrorCode(code)) => {
+            eprintln!("error: `{}` is not a valid error code", code);
+            ::std::process::exit(EXIT_FAILURE);
+        }
+        Err(Error::UnknownPass(pass)) => {
+            eprintln!("error: unknown optimization pass `{}`", pass);
+            ::std::process::exit(EXIT_FAILURE);
+        }
+        Err(Error::UnknownLint(lint)) => {
+            eprintln!("error: unknown lint `{}`", lint);
+            ::std::process::exit(EXIT_FAILURE);
+        }
+        Err(Error::CompiledInput) => {
+            eprintln!("error: input is compiled IR, this command needs source code");
+            ::std::process::exit(EXIT_FAILURE);
+        }
+        Err(Error::BadIr(err)) => {
+            eprintln!("error: invalid compiled IR: {}", err);
+            ::std::process::exit(EXIT_FAILURE);
+        }
+        Err(Error::Linker(err)) => {
+            eprintln!("error: {}", err);
+            ::std::process::exit(EXIT_FAILURE);
+        }
+        Err(Error::Read(path, err)) => {
+            eprintln!("error: cannot read `{}`: {}", path.display(), err);
+            ::std::process::exit(EXIT_FAILURE);
+        }
+        Err(Error::StdinWithFiles) => {
+            eprintln!("error: source can be read from stdin only if there are no other inputs");
+            ::std::process::exit(EXIT_FAILURE);
+        }
+        Err(Error::ExecutableToStdout) => {
+            eprintln!("error: executables can't be written to stdout, give a file with `-o`");
+            ::std::process::exit(EXIT_FAILURE);
+        }
+        Err(Error::TestFail) => {
+            eprintln!("error: some tests failed");
+            ::std::process::exit(EXIT_TRAP);
+        }
+        Err(Error::Unformatted) => {
+            eprintln!("error: some files are not formatted");
+            ::std::process::exit(EXIT_FAILURE);
         }
     }
 }
 
 fn run() -> Result<()> {
     let params = parse_params()?;
-    let input = read_input(&params.input)?;
+    if let Command::Explain(ref code) = params.command {
+        return explain(code);
+    }
+    let errors = ErrorOutput {
+        format: params.error_format,
+        colors: params.colors,
+        limit: params.error_limit,
+    };
+    if let Command::Format { check } = params.command {
+        return format_files(&params, &errors, check);
+    }
+    let input = read_input(&params.input, &errors)?;
+    // the linker writes the executable itself
+    if let Command::Link(ref executable) = params.command {
+        return link(&input, &errors, &params, executable);
+    }
     match params.output {
         Stream::Std => {
             let stdout = io::stdout();
             let stdout = stdout.lock();
-            run_command(&input, &params.command, params.optimize, params.skip_prelude, stdout)
+            run_command(&input, &errors, &params, stdout)
         }
         Stream::File(ref name) => {
             let file = ::std::fs::File::create(name)?;
-            run_command(&input, &params.command, params.optimize, params.skip_prelude, file)
+            run_command(&input, &errors, &params, file)
         }
     }
 }
 
-fn run_command<W: Write>(input: &str, command: &Command, optimize: bool, skip_prelude: bool, output: W) -> Result<()> {
-    match *command {
-        Command::Lex => lex(input, output),
-        Command::Parse => parse(input, output),
-        Command::EmitIr => emit_ir(input, output, optimize),
-        Command::Interpret => interpret(input, output, optimize),
-        Command::CompileX86 => compile_x86(input, output, optimize, skip_prelude),
+fn run_command<W: Write>(input: &Input, errors: &ErrorOutput, params: &Params, output: W) -> Result<()> {
+    let options = &params.options;
+    match params.command {
+        Command::Lex => {
+            emit_stage(source_code(input)?, errors, Compiler::new().emit(Emit::Tokens), output)
+        }
+        Command::Parse => {
+            emit_stage(source_code(input)?, errors, Compiler::new().emit(Emit::Ast), output)
+        }
+        Command::EmitResolved => {
+            let compiler = Compiler::new().options(options.clone()).emit(Emit::Resolved);
+            emit_stage(source_code(input)?, errors, compiler, output)
+        }
+        Command::EmitIr => emit_ir(build_ir(input, errors, params)?, output),
+        Command::EmitCfg => {
+            emit_cfg(build_ir(input, errors, params)?, output, params.dominators)
+        }
+        Command::EmitIrBinary => {
+            emit_ir_binary(build_ir(input, errors, params)?, output)
+        }
+        Command::EmitC => emit_c(build_ir(input, errors, params)?, output),
+        Command::EmitObject => {
+            let ir = build_ir(input, errors, params)?;
+            emit_object(ir, output, params)
+        }
+        Command::Link(ref executable) => link(input, errors, params, executable),
+        Command::Interpret => {
+            interpret(build_ir(input, errors, params)?, output, &params.interpreter)
+        }
+        Command::Jit => {
+            jit(build_ir(input, errors, params)?, output, &params.interpreter.args)
+        }
+        Command::CompileX86 => {
+            let ir = build_ir(input, errors, params)?;
+            compile_x86(ir, output, params)
+        }
+        Command::Test => run_tests(input, build_ir(input, errors, params)?, output, params),
+        Command::Explain(_) | Command::Format { .. } => {
+            unreachable!("commands without input are run before reading it")
+        }
     }
 }
 
 fn parse_params() -> Result<Params> {
     use clap::{App, Arg};
 
+    // clap does not support optional values glued to short
+    // flags, so handle `-O0`, `-O1` and `-O2` by hand. `plank test`
+    // and `plank fmt` are the same as `plank --test` and `plank --fmt`.
+    let args = ::std::env::args_os().enumerate().map(|(index, arg)| match arg.to_str() {
+        Some("-O0") => "--opt-level=0".into(),
+        Some("-O1") => "--opt-level=1".into(),
+        Some("-O2") => "--opt-level=2".into(),
+        Some("test") if index == 1 => "--test".into(),
+        Some("fmt") if index == 1 => "--fmt".into(),
+        _ => arg,
+    });
     let matches = App::new("Plank compiler")
         .arg(Arg::with_name("lex")
             .long("lex")
             .help("List tokens in input")
-            .conflicts_with_all(&["parse", "emit-ir", "interpret", "emit-asm"]))
+            .conflicts_with_all(&["parse", "emit-ir", "interpret", "jit", "emit-asm", "emit"]))
         .arg(Arg::with_name("parse")
             .long("parse")
             .help("Parse input")
-            .conflicts_with_all(&["lex", "emit-ir", "interpret", "emit-asm"]))
+            .conflicts_with_all(&["lex", "emit-ir", "interpret", "jit", "emit-asm", "emit"]))
         .arg(Arg::with_name("emit-ir")
             .long("emit-ir")
             .help("Compile to plank IR")
-            .conflicts_with_all(&["lex", "parse", "interpret", "emit-asm"]))
+            .conflicts_with_all(&["lex", "parse", "interpret", "jit", "emit-asm", "emit"]))
         .arg(Arg::with_name("interpret")
             .long("interpret")
             .help("Compile to IR and interpret")
-            .conflicts_with_all(&["lex", "parse", "emit-ir", "emit-asm"]))
+            .conflicts_with_all(&["lex", "parse", "emit-ir", "jit", "emit-asm", "emit"]))
+        .arg(Arg::with_name("jit")
+            .long("jit")
+            .help("Compile to IR and run it with a JIT compiler")
+            .conflicts_with_all(&["lex", "parse", "emit-ir", "interpret", "emit-asm", "emit"]))
         .arg(Arg::with_name("emit-asm")
             .long("emit-asm")
             .help("Compile to x86 assembly")
-            .conflicts_with_all(&["lex", "parse", "emit-ir", "interpret"]))
+            .conflicts_with_all(&["lex", "parse", "emit-ir", "interpret", "jit", "emit"]))
+        .arg(Arg::with_name("emit")
+            .long("emit")
+            .takes_value(true)
+            .possible_values(&[
+                "tokens", "ast", "resolved", "ir", "cfg", "ir-binary", "asm", "c", "obj", "exe",
+            ])
+            .help("Emit the output of a compilation stage: tokens (tokens), the syntax tree \
+                   (ast), the program with names resolved to symbols (resolved), plank IR \
+                   (ir), control flow graphs in Graphviz DOT format (cfg), the IR in binary \
+                   format that can be used as input later (ir-binary), x86 assembly (asm), \
+                   C source code (c), an ELF object file (obj), or an executable linked \
+                   with the system linker (exe)")
+            .conflicts_with_all(&["lex", "parse", "emit-ir", "interpret", "jit", "emit-asm"]))
+        .arg(Arg::with_name("dominators")
+            .long("dominators")
+            .requires("emit")
+            .help("Include dominator trees in emitted control flow graphs"))
+        .arg(Arg::with_name("test")
+            .long("test")
+            .help("Run functions marked with `#[test]` in the interpreter")
+            .conflicts_with_all(&[
+                "lex", "parse", "emit-ir", "interpret", "jit", "emit-asm", "emit",
+            ]))
+        .arg(Arg::with_name("fmt")
+            .long("fmt")
+            .help("Format source files in place, or source from stdin to the output")
+            .conflicts_with_all(&[
+                "lex", "parse", "emit-ir", "interpret", "jit", "emit-asm", "emit", "test",
+            ]))
+        .arg(Arg::with_name("check")
+            .long("check")
+            .requires("fmt")
+            .help("List files that are not formatted instead of formatting them"))
+        .arg(Arg::with_name("explain")
+            .long("explain")
+            .takes_value(true)
+            .value_name("CODE")
+            .help("Print detailed explanation of an error code")
+            .conflicts_with_all(&[
+                "lex", "parse", "emit-ir", "interpret", "jit", "emit-asm", "emit",
+            ]))
         .arg(Arg::with_name("optimize")
             .long("optimize")
             .short("O")
-            .help("Perform optimizations on IR"))
+            .help("Perform optimizations on IR, same as -O2"))
+        .arg(Arg::with_name("opt-level")
+            .long("opt-level")
+            .takes_value(true)
+            .value_name("LEVEL")
+            .possible_values(&["0", "1", "2"])
+            .conflicts_with("optimize")
+            .help("Set optimization level, can also be given as -O0, -O1 or -O2"))
+        .arg(Arg::with_name("passes")
+            .long("passes")
+            .takes_value(true)
+            .value_name("PASSES")
+            .conflicts_with_all(&["optimize", "opt-level"])
+            .help("Run given comma separated optimization passes instead"))
+        .arg(Arg::with_name("inline-threshold")
+            .long("inline-threshold")
+            .takes_value(true)
+            .value_name("N")
+            .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+            .help("Inline functions with at most N instructions"))
+        .arg(Arg::with_name("inline-depth")
+            .long("inline-depth")
+            .takes_value(true)
+            .value_name("N")
+            .validator(|n| n.parse::<u32>().map(|_| ()).map_err(|e| e.to_string()))
+            .help("Inline at most N levels of nested calls"))
+        .arg(Arg::with_name("wrapping-literals")
+            .long("wrapping-literals")
+            .help("Wrap out of range integer literals with a warning instead of an error"))
+        .arg(Arg::with_name("implicit-widening")
+            .long("implicit-widening")
+            .help("Implicitly convert ints to wider types with the same signedness"))
+        .arg(Arg::with_name("bounds-checks")
+            .long("bounds-checks")
+            .help("Stop programs that index slices or strings out of bounds"))
+        .arg(Arg::with_name("overflow")
+            .long("overflow")
+            .takes_value(true)
+            .possible_values(&["wrap", "trap", "saturate"])
+            .default_value("wrap")
+            .help("Set what arithmetic does when the result does not fit in its type"))
+        .arg(Arg::with_name("target")
+            .long("target")
+            .takes_value(true)
+            .possible_values(&["x86", "x86_64"])
+            .default_value("x86")
+            .help("Set target architecture, which decides pointer size and generated assembly"))
+        .arg(Arg::with_name("linker")
+            .long("linker")
+            .takes_value(true)
+            .value_name("PATH")
+            .default_value("ld")
+            .help("Set linker used to produce executables"))
+        .arg(Arg::with_name("cache-dir")
+            .long("cache-dir")
+            .takes_value(true)
+            .value_name("DIR")
+            .help("Reuse compiled IR of unchanged programs, storing it in given directory"))
+        .arg(Arg::with_name("debug-info")
+            .short("g")
+            .long("debug-info")
+            .help("Include debug info in emitted assembly, object files and executables"))
         .arg(Arg::with_name("no-prelude")
             .long("no-prelude")
             .help("Don't emit asm prelude"))
+        .arg(Arg::with_name("allow")
+            .short("A")
+            .long("allow")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("LINT")
+            .help("Don't report given lint"))
+        .arg(Arg::with_name("warn")
+            .short("W")
+            .long("warn")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("LINT")
+            .help("Report given lint as a warning"))
+        .arg(Arg::with_name("deny")
+            .short("D")
+            .long("deny")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("LINT")
+            .help("Report given lint as an error"))
+        .arg(Arg::with_name("deny-warnings")
+            .long("deny-warnings")
+            .help("Report all warnings as errors"))
+        .arg(Arg::with_name("error-format")
+            .long("error-format")
+            .takes_value(true)
+            .possible_values(&["human", "sarif"])
+            .default_value("human")
+            .help("Set format of reported diagnostics"))
+        .arg(Arg::with_name("color")
+            .long("color")
+            .takes_value(true)
+            .possible_values(&["auto", "always", "never"])
+            .default_value("auto")
+            .help("Set when to use colors in reported diagnostics"))
+        .arg(Arg::with_name("error-limit")
+            .long("error-limit")
+            .takes_value(true)
+            .value_name("N")
+            .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+            .help("Stop reporting errors after N errors, 0 means no limit"))
+        .arg(Arg::with_name("fuel")
+            .long("fuel")
+            .takes_value(true)
+            .value_name("N")
+            .validator(|n| n.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+            .help("Stop interpreted programs after they execute N instructions"))
+        .arg(Arg::with_name("max-call-depth")
+            .long("max-call-depth")
+            .takes_value(true)
+            .value_name("N")
+            .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+            .help("Stop interpreted programs that make more than N nested calls"))
+        .arg(Arg::with_name("max-memory")
+            .long("max-memory")
+            .takes_value(true)
+            .value_name("BYTES")
+            .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+            .help("Stop interpreted programs that need more than BYTES of memory"))
+        .arg(Arg::with_name("check-leaks")
+            .long("check-leaks")
+            .help("Report heap blocks that interpreted programs did not free"))
+        .arg(Arg::with_name("sanitize")
+            .long("sanitize")
+            .help("Stop interpreted programs that use uninitialized memory or invalid pointers"))
+        .arg(Arg::with_name("history")
+            .long("history")
+            .takes_value(true)
+            .value_name("N")
+            .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|e| e.to_string()))
+            .help("List the last N steps of interpreted programs when they fail"))
+        .arg(Arg::with_name("virtual-time")
+            .long("virtual-time")
+            .help("Make time_ms in interpreted programs count executed instructions"))
+        .arg(Arg::with_name("library")
+            .long("library")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("PATH")
+            .help("Load a shared library for extern functions of interpreted programs"))
         .arg(Arg::with_name("input")
             .index(1)
-            .help("Set input file, uses stdin if none provided"))
+            .multiple(true)
+            .help("Set input files or directories of source files, uses stdin if none \
+                   provided or if it is `-`"))
+        .arg(Arg::with_name("args")
+            .index(2)
+            .multiple(true)
+            .last(true)
+            .help("Arguments given to the program when it is run"))
         .arg(Arg::with_name("output")
             .short("o")
             .long("output")
             .takes_value(true)
-            .help("Set output file, uses stdout if none provided"))
-        .get_matches();
+            .help("Set output file, uses stdout if none provided or if it is `-`"))
+        .get_matches_from(args);
     let default_command = Command::Interpret;
-    let command = if matches.is_present("lex") {
+    let command = if matches.is_present("lex") || matches.value_of("emit") == Some("tokens") {
         Command::Lex
-    } else if matches.is_present("parse") {
+    } else if matches.is_present("parse") || matches.value_of("emit") == Some("ast") {
         Command::Parse
-    } else if matches.is_present("emit-ir") {
+    } else if matches.value_of("emit") == Some("resolved") {
+        Command::EmitResolved
+    } else if matches.is_present("emit-ir") || matches.value_of("emit") == Some("ir") {
         Command::EmitIr
+    } else if matches.value_of("emit") == Some("cfg") {
+        Command::EmitCfg
+    } else if matches.value_of("emit") == Some("ir-binary") {
+        Command::EmitIrBinary
+    } else if matches.value_of("emit") == Some("c") {
+        Command::EmitC
+    } else if matches.value_of("emit") == Some("obj") {
+        Command::EmitObject
+    } else if matches.value_of("emit") == Some("exe") {
+        // by default executable is named after the first input file
+        let executable = match (matches.value_of_os("output"), matches.value_of_os("input")) {
+            (Some(path), _) if path == "-" => {
+                return Err(Error::ExecutableToStdout);
+            }
+            (Some(path), _) => PathBuf::from(path),
+            (None, Some(path)) if path == "-" => PathBuf::from("a.out"),
+            (None, Some(path)) => match Path::new(path).file_stem() {
+                Some(stem) => PathBuf::from(stem),
+                None => PathBuf::from("a.out"),
+            },
+            (None, None) => PathBuf::from("a.out"),
+        };
+        Command::Link(executable)
     } else if matches.is_present("interpret") {
         Command::Interpret
-    } else if matches.is_present("emit-asm") {
+    } else if matches.is_present("jit") {
+        Command::Jit
+    } else if matches.is_present("emit-asm") || matches.value_of("emit") == Some("asm") {
         Command::CompileX86
+    } else if let Some(code) = matches.value_of("explain") {
+        Command::Explain(code.into())
+    } else if matches.is_present("test") {
+        Command::Test
+    } else if matches.is_present("fmt") {
+        Command::Format { check: matches.is_present("check") }
     } else {
         default_command
     };
 
-    let input = match matches.value_of_os("input") {
-        Some(path) => Stream::File(Path::new(path).to_owned()),
-        None => Stream::Std,
-    };
-
-    let output = match matches.value_of_os("output") {
-        Some(path) => Stream::File(Path::new(path).to_owned()),
-        None => Stream::Std,
+    let input = match matches.values_of_os("input") {
+        Some(values) => values.map(|value| Stream::from_arg(Some(value))).collect(),
+        None => vec![Stream::Std],
     };
+    let output = Stream::from_arg(matches.value_of_os("output"));
     
-    let optimize = matches.is_present("optimize");
+    // assembly is only optimized when a whole optimization level is used
+    let (mut passes, optimize_asm) = if let Some(names) = matches.value_of("passes") {
+        let names = names.split(',').filter(|name| !name.is_empty());
+        let passes = PassManager::with_passes(names)
+            .map_err(|name| Error::UnknownPass(name.into()))?;
+        (passes, false)
+    } else {
+        let level = match matches.value_of("opt-level") {
+            Some("1") => OptLevel::O1,
+            Some("2") => OptLevel::O2,
+            Some(_) => OptLevel::O0,
+            None if matches.is_present("optimize") => OptLevel::O2,
+            None => OptLevel::O0,
+        };
+        (PassManager::for_level(level), level > OptLevel::O0)
+    };
+    let mut pass_options = PassOptions::default();
+    if let Some(n) = matches.value_of("inline-threshold") {
+        pass_options.inline_threshold = n.parse().expect("validated by clap");
+    }
+    if let Some(n) = matches.value_of("inline-depth") {
+        pass_options.inline_depth = n.parse().expect("validated by clap");
+    }
+    passes.set_options(pass_options);
     let skip_prelude = matches.is_present("no-prelude");
+    let linker = matches.value_of("linker").expect("linker has a default value").to_string();
+    let dominators = matches.is_present("dominators");
+    let debug_source = if matches.is_present("debug-info") {
+        Some(plank_x86_backend::SourceFile {
+            path: match input[0] {
+                Stream::File(ref path) => path.to_string_lossy().into_owned(),
+                Stream::Std => "stdin".into(),
+            },
+            directory: ::std::env::current_dir()?.to_string_lossy().into_owned(),
+        })
+    } else {
+        None
+    };
+    let options = plank_frontend::Options {
+        wrapping_literals: matches.is_present("wrapping-literals"),
+        implicit_widening: matches.is_present("implicit-widening"),
+        target: matches
+            .value_of("target")
+            .and_then(plank_ir::Target::from_name)
+            .expect("validated by clap"),
+        bounds_checks: matches.is_present("bounds-checks"),
+        overflow: match matches.value_of("overflow") {
+            Some("trap") => Some(plank_ir::ir::Overflow::Trap),
+            Some("saturate") => Some(plank_ir::ir::Overflow::Saturate),
+            _ => None,
+        },
+        lint_levels: lint_levels(&matches)?,
+        deny_warnings: matches.is_present("deny-warnings"),
+        tests: matches!(command, Command::Test),
+    };
+    let interpreter = plank_interpreter::Options {
+        fuel: matches.value_of("fuel").map(|n| n.parse().expect("validated by clap")),
+        call_depth: matches
+            .value_of("max-call-depth")
+            .map(|n| n.parse().expect("validated by clap")),
+        memory: matches.value_of("max-memory").map(|n| n.parse().expect("validated by clap")),
+        check_leaks: matches.is_present("check-leaks"),
+        sanitize: matches.is_present("sanitize"),
+        history: matches.value_of("history").map(|n| n.parse().expect("validated by clap")),
+        args: {
+            // like native programs, the first argument is the program name
+            let name = matches.value_of_lossy("input").unwrap_or("plank".into());
+            let args = matches.values_of_lossy("args").unwrap_or_default();
+            Some(name.into_owned()).into_iter().chain(args).collect()
+        },
+        virtual_time: matches.is_present("virtual-time"),
+        libraries: matches.values_of_lossy("library").unwrap_or_default(),
+        entry: None,
+    };
+    let error_format = match matches.value_of("error-format") {
+        Some("sarif") => ErrorFormat::Sarif,
+        _ => ErrorFormat::Human,
+    };
+    let colors = match matches.value_of("color") {
+        Some("always") => true,
+        Some("never") => false,
+        _ => io::stdout().is_terminal() && ::std::env::var_os("NO_COLOR").is_none(),
+    };
+    let error_limit = match matches.value_of("error-limit") {
+        Some("0") => None,
+        Some(n) => Some(n.parse().expect("validated by clap")),
+        None => Some(plank_errors::reporter::DEFAULT_ERROR_LIMIT),
+    };
 
     Ok(Params {
         command,
-        optimize,
+        passes,
         skip_prelude,
+        optimize_asm,
+        debug_source,
+        dominators,
+        options,
+        interpreter,
+        linker,
+        cache_directory: matches.value_of_os("cache-dir").map(PathBuf::from),
+        error_format,
+        colors,
+        error_limit,
         input,
         output,
     })
 }
 
-fn read_file(name: &Path) -> Result<String> {
+/// Collect lint levels given with `-A`, `-W` and `-D`. If a lint is
+/// given to several of them, the strictest level wins.
+fn lint_levels(matches: &clap::ArgMatches) -> Result<Vec<(String, Level)>> {
+    let flags = [("allow", Level::Allow), ("warn", Level::Warn), ("deny", Level::Deny)];
+    let mut levels = Vec::new();
+    for &(flag, level) in &flags {
+        for lint in matches.values_of(flag).into_iter().flatten() {
+            // lints are also accepted with dashes, like `dead-code`
+            let lint = lint.replace('-', "_");
+            if !plank_frontend::KNOWN_LINTS.contains(&lint.as_str()) {
+                return Err(Error::UnknownLint(lint));
+            }
+            levels.push((lint, level));
+        }
+    }
+    Ok(levels)
+}
+
+fn read_file(name: &Path) -> Result<Vec<u8>> {
     use std::fs::File;
     let mut file = File::open(name)?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
+    let mut contents = Vec::new();
+    file.read_to_end(&mut contents)?;
     Ok(contents)
 }
 
-fn read_stdin() -> Result<String> {
+fn read_stdin() -> Result<Vec<u8>> {
     use std::io::prelude::*;
     let stdin = io::stdin();
     let mut stdin = stdin.lock();
-    let mut input = String::new();
-    stdin.read_to_string(&mut input)?;
+    let mut input = Vec::new();
+    stdin.read_to_end(&mut input)?;
     Ok(input)
 }
 
-fn read_input(stream: &Stream) -> Result<String> {
-    match *stream {
-        Stream::File(ref file) => read_file(file),
-        Stream::Std => read_stdin(),
+fn read_input(streams: &[Stream], errors: &ErrorOutput) -> Result<Input> {
+    let loaded = match *streams {
+        [Stream::File(ref path)] if !path.is_dir() => read_single_input(read_file(path)?, path)?,
+        [Stream::Std] => read_single_input(read_stdin()?, Path::new("stdin"))?,
+        _ => {
+            let mut paths = Vec::new();
+            for stream in streams {
+                match *stream {
+                    Stream::File(ref path) => paths.push(path.clone()),
+                    Stream::Std => return Err(Error::StdinWithFiles),
+                }
+            }
+            Project::open(&paths).map(Input::Source)
+        }
+    };
+    match loaded {
+        Ok(input) => Ok(input),
+        Err(LoadError::Io(path, err)) => Err(Error::Read(path, err)),
+        Err(LoadError::Invalid(files)) => {
+            let files = files
+                .iter()
+                .map(|(file, diagnostics)| (file, &diagnostics[..]))
+                .collect::<Vec<_>>();
+            // paths are left out only if the single input has errors
+            let show_paths = match *streams {
+                [Stream::File(ref path)] => files.iter().any(|&(file, _)| file.path != *path),
+                [Stream::Std] => files.iter().any(|&(file, _)| file.path != Path::new("stdin")),
+                _ => true,
+            };
+            report_diagnostics(errors, &files, show_paths)?;
+            Err(Error::BuildFail)
+        }
     }
 }
 
-fn emit_diagnostics(input: &str, reporter: Reporter) -> Result<()> {
-    let mut diagnostics = reporter.get_diagnostics();
-    diagnostics.sort_by_key(|d| d.primary_span.map(|s| s.start));
-    plank_errors::print_diagnostics(&input, &diagnostics);
-    if reporter.has_errors() {
-        Err(Error::BuildFail)
+type LoadResult = ::std::result::Result<Input, LoadError>;
+
+/// Read a single input file, which can be source code that
+/// imports other files, or compiled IR.
+fn read_single_input(bytes: Vec<u8>, path: &Path) -> Result<LoadResult> {
+    match plank_ir::decode_program(&bytes[..]) {
+        Ok(program) => {
+            if let Err((sym, err)) = plank_ir::validate_ir(&program) {
+                return Err(Error::BadIr(format!("in function `{}`: {}", sym.0, err)));
+            }
+            Ok(Ok(Input::Ir(program)))
+        }
+        Err(plank_ir::DecodeError::BadMagic) => {
+            let source = String::from_utf8(bytes)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            Ok(Project::load(vec![(path.to_owned(), source)]).map(Input::Source))
+        }
+        Err(err) => Err(Error::BadIr(err.to_string())),
+    }
+}
+
+/// Format input files in place, or source from stdin to the output.
+/// With `check`, nothing is written, and files that are not formatted
+/// are listed instead. If any file has syntax errors, no file is changed.
+fn format_files(params: &Params, errors: &ErrorOutput, check: bool) -> Result<()> {
+    let mut files = Vec::new();
+    if let [Stream::Std] = *params.input {
+        let source = String::from_utf8(read_stdin()?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        files.push(SourceFile::new("stdin".into(), source));
+    } else {
+        let mut paths = Vec::new();
+        for stream in &params.input {
+            match *stream {
+                Stream::File(ref path) => paths.push(path.clone()),
+                Stream::Std => return Err(Error::StdinWithFiles),
+            }
+        }
+        let paths = match project::source_paths(&paths) {
+            Ok(paths) => paths,
+            Err(LoadError::Io(path, err)) => return Err(Error::Read(path, err)),
+            Err(LoadError::Invalid(_)) => unreachable!("listing files does not load them"),
+        };
+        for path in paths {
+            match ::std::fs::read_to_string(&path) {
+                Ok(source) => files.push(SourceFile::new(path, source)),
+                Err(err) => return Err(Error::Read(path, err)),
+            }
+        }
+    }
+    let mut formatted = Vec::new();
+    let mut diagnostics = Vec::new();
+    for file in &files {
+        let reporter = plank_errors::Reporter::new();
+        reporter.set_error_limit(errors.limit);
+        formatted.push(plank_syntax::format_source(&file.source, reporter.clone()));
+        let mut reported = Vec::new();
+        reporter.emit_to(&mut reported);
+        // warnings are about what the code does, not how it looks
+        reported.retain(|diagnostic| diagnostic.severity == Severity::Error);
+        if !reported.is_empty() {
+            diagnostics.push((file, reported));
+        }
+    }
+    let reported = diagnostics
+        .iter()
+        .map(|&(file, ref diagnostics)| (file, &diagnostics[..]))
+        .collect::<Vec<_>>();
+    report_diagnostics(errors, &reported, files.len() > 1)?;
+    let mut unformatted = false;
+    for (file, formatted) in files.iter().zip(formatted) {
+        let formatted = formatted.expect("formatting failed without errors");
+        let changed = formatted != file.source;
+        if check {
+            if changed {
+                println!("{} is not formatted", file.path.display());
+                unformatted = true;
+            }
+        } else if let [Stream::Std] = *params.input {
+            match params.output {
+                Stream::Std => io::stdout().write_all(formatted.as_bytes())?,
+                Stream::File(ref path) => ::std::fs::write(path, formatted)?,
+            }
+        } else if changed {
+            ::std::fs::write(&file.path, formatted)?;
+        }
+    }
+    if unformatted {
+        Err(Error::Unformatted)
     } else {
         Ok(())
     }
 }
 
-fn lex<W: Write>(source: &str, mut output: W) -> Result<()> {
-    let reporter = Reporter::new();
-    let tokens = plank_syntax::lex(source, reporter.clone());
-    emit_diagnostics(source, reporter)?;
-    for tok in tokens {
-        output.write_fmt(format_args!("{:?}\n", *tok))?;
+fn source_code(input: &Input) -> Result<&Project> {
+    match *input {
+        Input::Source(ref source) => Ok(source),
+        Input::Ir(_) => Err(Error::CompiledInput),
+    }
+}
+
+/// Compile input to IR, unless it already is, and optimize it.
+fn build_ir(input: &Input, errors: &ErrorOutput, params: &Params) -> Result<plank_ir::Program> {
+    match *input {
+        Input::Source(ref source) => {
+            let mut compiler = Compiler::new()
+                .options(params.options.clone())
+                .passes(params.passes.clone());
+            if let Some(ref directory) = params.cache_directory {
+                compiler = compiler.cache(Cache::new(directory.clone()));
+            }
+            let compiled = compile(source, errors, compiler, Compiler::compile_to_ir)?;
+            Ok(compiled.program.expect("build succeeded but failed to produce IR"))
+        }
+        Input::Ir(ref ir) => {
+            let mut ir = ir.clone();
+            params.passes.run(&mut ir);
+            Ok(ir)
+        }
     }
-    Ok(())
 }
 
-fn parse<W: Write>(source: &str, mut output: W) -> Result<()> {
-    let reporter = Reporter::new();
-    let tokens = plank_syntax::lex(source, reporter.clone());
-    let program = plank_syntax::parse(tokens, reporter.clone());
-    emit_diagnostics(source, reporter)?;
-    let formatted = ast_printer::format_program(&program);
-    output.write_all(formatted.as_bytes())?;
-    output.write_all(b"\n")?;
+/// Compile source code with `compiler` and report diagnostics.
+fn compile<F>(project: &Project, errors: &ErrorOutput, compiler: Compiler, f: F) -> Result<Output>
+where
+    F: FnOnce(&Compiler) -> Output,
+{
+    let compiler = compiler.project(project).error_limit(errors.limit);
+    let compiled = f(&compiler);
+    let files = project.split_diagnostics(&compiled.diagnostics);
+    let files = files
+        .iter()
+        .map(|&(file, ref diagnostics)| (file, &diagnostics[..]))
+        .collect::<Vec<_>>();
+    report_diagnostics(errors, &files, project.files().len() > 1)?;
+    Ok(compiled)
+}
+
+/// Emit a stage of compiling source code that comes before IR.
+fn emit_stage<W: Write>(
+    source: &Project,
+    errors: &ErrorOutput,
+    compiler: Compiler,
+    mut output: W,
+) -> Result<()> {
+    let compiled = compile(source, errors, compiler, Compiler::compile)?;
+    output.write_all(&compiled.artifact.expect("build succeeded but produced nothing"))?;
     Ok(())
 }
 
-fn emit_ir<W: Write>(source: &str, mut output: W, optimize: bool) -> Result<()> {
-    let reporter = Reporter::new();
-    let tokens = plank_syntax::lex(source, reporter.clone());
-    let program = plank_syntax::parse(tokens, reporter.clone());
-    let ir = plank_frontend::compile(&program, reporter.clone());
-    emit_diagnostics(source, reporter)?;
-    let mut ir = ir.expect("no errors but failed to produce IR");
-    if optimize {
-        plank_ir::optimization::optimize(&mut ir);
+/// Report diagnostics of each file. With `show_paths`, the path of the
+/// file is printed before its diagnostics.
+fn report_diagnostics(
+    errors: &ErrorOutput,
+    files: &[(&SourceFile, &[Diagnostic])],
+    show_paths: bool,
+) -> Result<()> {
+    match errors.format {
+        ErrorFormat::Human => {
+            for &(file, diagnostics) in files {
+                if show_paths {
+                    println!("--> {}", file.path.display());
+                }
+                let mut sink = plank_errors::TerminalSink::new(&file.source, errors.colors);
+                for diagnostic in diagnostics {
+                    sink.emit(diagnostic);
+                }
+                sink.finish();
+            }
+        }
+        ErrorFormat::Sarif => {
+            let uris = files
+                .iter()
+                .map(|&(file, _)| file.path.to_string_lossy().replace('\\', "/"))
+                .collect::<Vec<_>>();
+            let files = files
+                .iter()
+                .zip(&uris)
+                .map(|(&(file, diagnostics), uri)| (&uri[..], &file.source[..], diagnostics))
+                .collect::<Vec<_>>();
+            let log = plank_errors::sarif::to_sarif_files(&files);
+            io::stderr().write_all(log.as_bytes())?;
+        }
     }
+    let failed = files
+        .iter()
+        .any(|&(_, diagnostics)| diagnostics.iter().any(|d| d.severity == Severity::Error));
+    if failed {
+        Err(Error::BuildFail)
+    } else {
+        Ok(())
+    }
+}
+
+fn explain(code: &str) -> Result<()> {
+    // accept codes without leading zeroes too, like `E15`
+    let normalized = code.trim_start_matches(&['E', 'e'][..])
+        .parse::<u32>()
+        .map(|num| format!("E{:04}", num))
+        .unwrap_or_else(|_| code.to_string());
+    match plank_errors::codes::explain(&normalized) {
+        Some(explanation) => {
+            print!("{}", explanation);
+            Ok(())
+        }
+        None => Err(Error::UnknownErrorCode(code.into())),
+    }
+}
+
+fn emit_ir<W: Write>(ir: plank_ir::Program, mut output: W) -> Result<()> {
     plank_ir::emit_program(&ir, &mut output)?;
     if let Err((sym, err)) = plank_ir::validate_ir(&ir) {
-        eprintln!("ir validation error in function {:?}: {:?}", sym, err);
+        eprintln!("ir validation error in function `{}`: {}", sym.0, err);
     }
     Ok(())
 }
 
-fn interpret<W: Write>(source: &str, output: W, optimize: bool) -> Result<()> {
-    let reporter = Reporter::new();
-    let tokens = plank_syntax::lex(source, reporter.clone());
-    let program = plank_syntax::parse(tokens, reporter.clone());
-    let ir = plank_frontend::compile(&program, reporter.clone());
-    emit_diagnostics(source, reporter)?;
-    let mut ir = ir.expect("build succeeded but failed to produce IR");
-    if optimize {
-        plank_ir::optimization::optimize(&mut ir);
-    }
-    let input = io::empty();
-    let exit_code = plank_interpreter::run_program(&ir, input, output)?;
+fn emit_cfg<W: Write>(ir: plank_ir::Program, mut output: W, dominators: bool) -> Result<()> {
+    plank_ir::emit_cfg(&ir, dominators, &mut output)?;
+    Ok(())
+}
+
+fn emit_ir_binary<W: Write>(ir: plank_ir::Program, mut output: W) -> Result<()> {
+    plank_ir::encode_program(&ir, &mut output)?;
+    Ok(())
+}
+
+fn emit_c<W: Write>(ir: plank_ir::Program, mut output: W) -> Result<()> {
+    plank_c_backend::emit_program(&ir, &mut output)?;
+    Ok(())
+}
+
+fn interpret<W: Write>(
+    ir: plank_ir::Program,
+    output: W,
+    options: &plank_interpreter::Options,
+) -> Result<()> {
+    let stdin = io::stdin();
+    let input = stdin.lock();
+    let exit_code = plank_interpreter::run_program_with_options(&ir, input, output, options)?;
     if exit_code == 0 {
         Ok(())
     } else {
@@ -278,21 +1056,149 @@ fn interpret<W: Write>(source: &str, output: W, optimize: bool) -> Result<()> {
     }
 }
 
-fn compile_x86<W: Write>(source: &str, mut output: W, optimize: bool, skip_prelude: bool) -> Result<()> {
-    let reporter = Reporter::new();
-    let tokens = plank_syntax::lex(source, reporter.clone());
-    let program = plank_syntax::parse(tokens, reporter.clone());
-    let ir = plank_frontend::compile(&program, reporter.clone());
-    emit_diagnostics(source, reporter)?;
-    let mut ir = ir.expect("build succeeded but failed to produce IR");
-    if optimize {
-        plank_ir::optimization::optimize(&mut ir);
+fn jit<W: Write>(ir: plank_ir::Program, output: W, args: &[String]) -> Result<()> {
+    let stdin = io::stdin();
+    let input = stdin.lock();
+    let exit_code = plank_jit::run_program_with_args(&ir, input, output, args)?;
+    if exit_code == 0 {
+        Ok(())
+    } else {
+        Err(Error::JitExit(exit_code))
     }
-    plank_x86_backend::fix_function_returns(&mut ir);
-    let asm = plank_x86_backend::compile_program(&ir);
-    if !skip_prelude {
-        plank_x86_backend::print_prelude(&mut output)?;
+}
+
+/// Run every test of the program in a new interpreter, with no input,
+/// and print which tests passed. A test fails if it traps.
+fn run_tests<W: Write>(
+    input: &Input,
+    ir: plank_ir::Program,
+    mut output: W,
+    params: &Params,
+) -> Result<()> {
+    const PREFIX: &str = "fn_test::";
+    let mut tests = ir
+        .functions
+        .keys()
+        .filter(|symbol| symbol.0.starts_with(PREFIX))
+        .cloned()
+        .collect::<Vec<_>>();
+    tests.sort_by(|a, b| a.0.cmp(&b.0));
+    let count = tests.len();
+    writeln!(output, "running {} test{}", count, if count == 1 { "" } else { "s" })?;
+    let mut failures = Vec::new();
+    for test in tests {
+        let name = &test.0[PREFIX.len()..];
+        let options = plank_interpreter::Options {
+            entry: Some(test.clone()),
+            args: vec![name.into()],
+            ..params.interpreter.clone()
+        };
+        let mut printed = Vec::new();
+        let result =
+            plank_interpreter::run_program_with_options(&ir, io::empty(), &mut printed, &options);
+        let error = match result {
+            Ok(0) => {
+                writeln!(output, "test {} ... ok", name)?;
+                continue;
+            }
+            Ok(code) => format!("exited with status code {}", code),
+            // the outermost frame is the generated entry that calls
+            // the test, which has nothing to do with the failure
+            Err(plank_interpreter::Error::Trap(err, mut frames)) => {
+                frames.pop();
+                plank_interpreter::Error::Trap(err, frames).to_string()
+            }
+            Err(err) => err.to_string(),
+        };
+        writeln!(output, "test {} ... FAILED", name)?;
+        failures.push((name.to_string(), printed, error));
+    }
+    if !failures.is_empty() {
+        writeln!(output, "\nfailures:")?;
+        for (name, printed, error) in &failures {
+            writeln!(output, "\n---- {} at {} ----", name, test_location(input, &ir, name))?;
+            output.write_all(printed)?;
+            if !printed.is_empty() && !printed.ends_with(b"\n") {
+                writeln!(output)?;
+            }
+            writeln!(output, "{}", error)?;
+        }
+    }
+    let failed = failures.len();
+    let result = if failed == 0 { "ok" } else { "FAILED" };
+    writeln!(output, "\ntest result: {}. {} passed; {} failed", result, count - failed, failed)?;
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::TestFail)
+    }
+}
+
+/// Where a test function is declared, like `path:line:column`.
+fn test_location(input: &Input, ir: &plank_ir::Program, name: &str) -> String {
+    let symbol = plank_ir::ir::Symbol(format!("fn_{}", name).into());
+    let debug = ir.functions.get(&symbol).and_then(|f| f.debug.as_ref());
+    let span = match debug.and_then(|debug| debug.span) {
+        Some(span) => span,
+        None => return "unknown location".into(),
+    };
+    match *input {
+        Input::Source(ref project) => {
+            let (file, start) = project.locate(span.start);
+            format!("{}:{}:{}", file.path.display(), start.line + 1, start.column + 1)
+        }
+        Input::Ir(_) => format!("{}:{}", span.start.line + 1, span.start.column + 1),
+    }
+}
+
+fn compile_x86<W: Write>(ir: plank_ir::Program, mut output: W, params: &Params) -> Result<()> {
+    let asm = lower_to_x86(ir, params);
+    if !params.skip_prelude {
+        plank_x86_backend::print_prelude(&mut output, &asm)?;
     }
     plank_x86_backend::print_asm(output, &asm)?;
     Ok(())
 }
+
+fn emit_object<W: Write>(ir: plank_ir::Program, output: W, params: &Params) -> Result<()> {
+    let asm = lower_to_x86(ir, params);
+    plank_x86_backend::write_object(output, &asm, !params.skip_prelude)?;
+    Ok(())
+}
+
+/// Compile input to an object file in a temporary directory, and link
+/// it into an executable with the system linker.
+fn link(input: &Input, errors: &ErrorOutput, params: &Params, executable: &Path) -> Result<()> {
+    let ir = build_ir(input, errors, params)?;
+    let asm = lower_to_x86(ir, params);
+    let emulation = match asm.mode {
+        plank_x86_backend::Mode::Bits32 => "elf_i386",
+        plank_x86_backend::Mode::Bits64 => "elf_x86_64",
+    };
+    let mut bytes = Vec::new();
+    plank_x86_backend::write_object(&mut bytes, &asm, !params.skip_prelude)?;
+    let object = ::std::env::temp_dir().join(format!("plank-{}.o", ::std::process::id()));
+    ::std::fs::write(&object, bytes)?;
+    let status = ::std::process::Command::new(&params.linker)
+        .arg("-m")
+        .arg(emulation)
+        .arg("-o")
+        .arg(executable)
+        .arg(&object)
+        .status();
+    ::std::fs::remove_file(&object)?;
+    match status {
+        Ok(ref status) if status.success() => Ok(()),
+        Ok(status) => Err(Error::Linker(format!("`{}` failed: {}", params.linker, status))),
+        Err(err) => Err(Error::Linker(format!("failed to run `{}`: {}", params.linker, err))),
+    }
+}
+
+fn lower_to_x86(mut ir: plank_ir::Program, params: &Params) -> plank_x86_backend::Program {
+    plank_x86_backend::fix_function_returns(&mut ir);
+    let mut asm = plank_x86_backend::compile_program(&ir, params.debug_source.clone());
+    if params.optimize_asm {
+        plank_x86_backend::peephole_optimize(&mut asm);
+    }
+    asm
+}
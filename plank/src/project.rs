@@ -0,0 +1,389 @@
+//! Programs made of several source files that import each other with
+//! `import "path";`. Files are parsed one by one and compiled together
+//! as one program. Positions point into the source made by joining the
+//! files, where every file comes after the files it imports.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use plank_errors::Reporter;
+use plank_errors::codes;
+use plank_errors::position::{Position, Span};
+use plank_errors::reporter::Diagnostic;
+use plank_syntax::position::Spanned;
+use cache;
+
+
+/// A source file of a project.
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    /// Path of the file, relative to the current directory if the file
+    /// was given with a relative path.
+    pub path: PathBuf,
+    pub source: String,
+    /// Where the file starts in the source of the whole project.
+    start: Position,
+}
+
+/// Source files of a program, ordered so that files come after
+/// the files they import.
+#[derive(Debug, Clone)]
+pub struct Project {
+    files: Vec<SourceFile>,
+    source: String,
+    /// Cache keys of the files that the project was made from.
+    keys: Vec<u64>,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    /// A file given on the command line could not be read.
+    Io(PathBuf, io::Error),
+    /// Some files import files that can't be read, or import each other
+    /// in a cycle. Diagnostics include syntax errors of those files.
+    Invalid(Vec<(SourceFile, Vec<Diagnostic>)>),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    Loading,
+    /// File was loaded, and has given cache key.
+    Loaded(u64),
+}
+
+struct Loader {
+    files: Vec<SourceFile>,
+    states: HashMap<PathBuf, State>,
+    /// Files that are being loaded, each one imported by the previous.
+    stack: Vec<(PathBuf, PathBuf)>,
+    errors: Vec<(SourceFile, Vec<Diagnostic>)>,
+}
+
+impl Project {
+    /// Read given files, and all `.plk` files in given directories,
+    /// together with the files that they import.
+    pub fn open(paths: &[PathBuf]) -> Result<Project, LoadError> {
+        let mut sources = Vec::new();
+        for path in source_paths(paths)? {
+            match fs::read_to_string(&path) {
+                Ok(source) => sources.push((path, source)),
+                Err(err) => return Err(LoadError::Io(path, err)),
+            }
+        }
+        Project::load(sources)
+    }
+
+    /// Make a project from files that were already read, and the
+    /// files that they import. Imports of a file without a directory,
+    /// like source from stdin, are relative to the current directory.
+    pub fn load(roots: Vec<(PathBuf, String)>) -> Result<Project, LoadError> {
+        let mut loader = Loader {
+            files: Vec::new(),
+            states: HashMap::new(),
+            stack: Vec::new(),
+            errors: Vec::new(),
+        };
+        let mut keys = Vec::new();
+        for (path, source) in roots {
+            let key = loader.load(path, source);
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+        if !loader.errors.is_empty() {
+            return Err(LoadError::Invalid(loader.errors));
+        }
+        let mut source = String::new();
+        let count = loader.files.len();
+        for (index, file) in loader.files.iter_mut().enumerate() {
+            let line = source.matches('\n').count() as u32;
+            file.start = Position::with_offset(line, 0, source.len() as u32);
+            source.push_str(&file.source);
+            if index + 1 < count && !source.ends_with('\n') {
+                source.push('\n');
+            }
+        }
+        Ok(Project {
+            files: loader.files,
+            source,
+            keys,
+        })
+    }
+
+    /// Files of the project, every one after the files it imports.
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    /// Source of the whole project, made by joining its files.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Files of the project as IR lists them, so that runtime errors and
+    /// debug info can point into them. Empty if there is only one file.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # extern crate plank;
+    /// # extern crate plank_interpreter;
+    /// use std::path::PathBuf;
+    /// use plank::Compiler;
+    /// use plank::project::Project;
+    ///
+    /// let util = "fn half(x: i32) -> i32 {\n    return 10 / x;\n}\n";
+    /// let main = "fn main() -> i32 {\n    return half(0);\n}\n";
+    /// let project = Project::load(vec![
+    ///     (PathBuf::from("util.plk"), util.to_string()),
+    ///     (PathBuf::from("main.plk"), main.to_string()),
+    /// ]).unwrap();
+    /// let output = Compiler::new().project(&project).compile_to_ir();
+    /// let program = output.program.unwrap();
+    /// let err = plank_interpreter::run_program(&program, &b""[..], Vec::new()).unwrap_err();
+    /// let message = err.to_string();
+    /// assert!(message.contains("in half at util.plk:2:12"));
+    /// assert!(message.contains("in main at main.plk:2:12"));
+    /// ```
+    pub fn ir_files(&self) -> Vec<plank_ir::ir::SourceFile> {
+        if self.files.len() < 2 {
+            return Vec::new();
+        }
+        self.files
+            .iter()
+            .map(|file| plank_ir::ir::SourceFile {
+                path: file.path.display().to_string(),
+                start_line: file.start.line,
+            })
+            .collect()
+    }
+
+    /// Split diagnostics about the source of the project by the file
+    /// that they point into, changing their spans to point into that
+    /// file. Notes in other files are left out. Diagnostics without a
+    /// span are put in the last file.
+    pub fn split_diagnostics(
+        &self,
+        diagnostics: &[Diagnostic],
+    ) -> Vec<(&SourceFile, Vec<Diagnostic>)> {
+        let mut by_file = vec![Vec::new(); self.files.len()];
+        for diagnostic in diagnostics {
+            let index = match diagnostic.primary_span {
+                Some(span) => self.file_index(span.start),
+                None => match diagnostic.notes.first() {
+                    Some(note) => self.file_index(note.span.start),
+                    None => self.files.len() - 1,
+                },
+            };
+            let file = &self.files[index];
+            let mut diagnostic = diagnostic.clone();
+            diagnostic.primary_span = diagnostic.primary_span.map(|s| rebase_span(s, file.start));
+            diagnostic.notes.retain(|note| self.file_index(note.span.start) == index);
+            for note in &mut diagnostic.notes {
+                note.span = rebase_span(note.span, file.start);
+            }
+            by_file[index].push(diagnostic);
+        }
+        self.files
+            .iter()
+            .zip(by_file)
+            .filter(|(_, diagnostics)| !diagnostics.is_empty())
+            .collect()
+    }
+
+    /// Find the file that a position in the source of the project
+    /// points into, and the same position relative to that file.
+    pub fn locate(&self, position: Position) -> (&SourceFile, Position) {
+        let file = &self.files[self.file_index(position)];
+        (file, rebase(position, file.start))
+    }
+
+    /// Keys that the program of this project is cached under, see
+    /// [`cache::key`](../cache/fn.key.html). There is one key for every
+    /// file that the project was made from, and it changes when that
+    /// file, or any file that it imports, changes.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # extern crate plank;
+    /// # extern crate plank_ir;
+    /// use std::fs;
+    /// use std::path::PathBuf;
+    /// use plank::Compiler;
+    /// use plank::cache::{self, Cache};
+    /// use plank::project::Project;
+    /// use plank_ir::optimization::PassManager;
+    ///
+    /// let directory = std::env::temp_dir().join(format!("plank-keys-{}", std::process::id()));
+    /// fs::create_dir_all(&directory).unwrap();
+    /// let util = directory.join("util.plk");
+    /// let main = directory.join("main.plk");
+    /// fs::write(&util, "fn two() -> i32 {\n    return 2;\n}\n").unwrap();
+    /// fs::write(&main, "import \"util.plk\";\nfn main() -> i32 {\n    return two();\n}\n")
+    ///     .unwrap();
+    /// let cache = Cache::new(directory.join("cache"));
+    /// let key = |project: &Project| {
+    ///     cache::key(&project.cache_keys(), &Default::default(), &PassManager::new())
+    /// };
+    /// let text = |program: &plank_ir::Program| {
+    ///     let mut text = Vec::new();
+    ///     plank_ir::emit_program(program, &mut text).unwrap();
+    ///     text
+    /// };
+    ///
+    /// let project = Project::open(&[main.clone()]).unwrap();
+    /// let output = Compiler::new().project(&project).cache(cache.clone()).compile_to_ir();
+    /// let entry = cache.load(key(&project)).unwrap();
+    /// assert_eq!(text(&entry.program), text(&output.program.unwrap()));
+    ///
+    /// // editing an imported file gives the program a new key
+    /// fs::write(&util, "fn two() -> i32 {\n    return 3;\n}\n").unwrap();
+    /// let edited = Project::open(&[main.clone()]).unwrap();
+    /// assert_ne!(key(&edited), key(&project));
+    /// assert!(cache.load(key(&edited)).is_none());
+    /// # fs::remove_dir_all(&directory).unwrap();
+    /// ```
+    pub fn cache_keys(&self) -> Vec<u64> {
+        self.keys.clone()
+    }
+
+    fn file_index(&self, position: Position) -> usize {
+        self.files
+            .iter()
+            .rposition(|file| file.start.line <= position.line)
+            .unwrap_or(0)
+    }
+}
+
+impl SourceFile {
+    /// A file that is not part of a project.
+    pub fn new(path: PathBuf, source: String) -> SourceFile {
+        SourceFile {
+            path,
+            source,
+            start: Position::new(0, 0),
+        }
+    }
+
+    /// Where the file starts in the source of the whole project.
+    pub fn start(&self) -> Position {
+        self.start
+    }
+}
+
+/// Given files, and all `.plk` files in given directories, sorted
+/// by their names. Only `LoadError::Io` is returned.
+pub fn source_paths(paths: &[PathBuf]) -> Result<Vec<PathBuf>, LoadError> {
+    let mut result = Vec::new();
+    for path in paths {
+        let metadata = fs::metadata(path).map_err(|err| LoadError::Io(path.clone(), err))?;
+        if metadata.is_dir() {
+            let mut files = Vec::new();
+            let entries = fs::read_dir(path).map_err(|err| LoadError::Io(path.clone(), err))?;
+            for entry in entries {
+                let file = entry.map_err(|err| LoadError::Io(path.clone(), err))?.path();
+                if file.extension().is_some_and(|ext| ext == "plk") {
+                    files.push(file);
+                }
+            }
+            files.sort();
+            result.extend(files);
+        } else {
+            result.push(path.clone());
+        }
+    }
+    Ok(result)
+}
+
+impl Loader {
+    /// Load a file and the files it imports, returns its cache key.
+    fn load(&mut self, path: PathBuf, source: String) -> u64 {
+        let key = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        if let Some(&State::Loaded(cache_key)) = self.states.get(&key) {
+            return cache_key;
+        }
+        self.states.insert(key.clone(), State::Loading);
+        self.stack.push((key.clone(), path.clone()));
+        let reporter = Reporter::new();
+        let tokens = plank_syntax::Lexer::new(&source, reporter.clone());
+        let program = plank_syntax::parse(tokens, reporter.clone());
+        let directory = path.parent().unwrap_or_else(|| Path::new("")).to_owned();
+        let mut imported = true;
+        let mut imports = Vec::new();
+        for import in &program.imports {
+            match self.load_import(&directory, import, &reporter) {
+                Some(cache_key) => imports.push(cache_key),
+                None => imported = false,
+            }
+        }
+        self.stack.pop();
+        let cache_key = cache::file_key(&path.display().to_string(), &source, &imports);
+        self.states.insert(key, State::Loaded(cache_key));
+        let file = SourceFile::new(path, source);
+        // syntax errors are left to be reported when the
+        // whole project is compiled, together with other errors
+        if !imported {
+            let mut diagnostics = Vec::new();
+            reporter.emit_to(&mut diagnostics);
+            self.errors.push((file, diagnostics));
+        } else {
+            self.files.push(file);
+        }
+        cache_key
+    }
+
+    /// Load an imported file, returns its cache key, or `None` if the
+    /// import is invalid.
+    fn load_import(
+        &mut self,
+        directory: &Path,
+        import: &Spanned<String>,
+        reporter: &Reporter,
+    ) -> Option<u64> {
+        let span = Spanned::span(import);
+        let path = directory.join(&**import);
+        let key = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        match self.states.get(&key) {
+            Some(&State::Loaded(cache_key)) => Some(cache_key),
+            Some(&State::Loading) => {
+                let start = self.stack.iter().position(|(k, _)| *k == key).unwrap_or(0);
+                let mut cycle = self.stack[start..]
+                    .iter()
+                    .map(|(_, path)| format!("`{}`", path.display()))
+                    .collect::<Vec<_>>();
+                cycle.push(format!("`{}`", path.display()));
+                reporter
+                    .error("files import each other in a cycle", span)
+                    .code(codes::IMPORT_CYCLE)
+                    .span_note(format!("import cycle: {}", cycle.join(" -> ")), span)
+                    .build();
+                None
+            }
+            None => match fs::read_to_string(&path) {
+                Ok(source) => Some(self.load(path, source)),
+                Err(err) => {
+                    reporter
+                        .error(format!("cannot read `{}`: {}", path.display(), err), span)
+                        .code(codes::UNREADABLE_IMPORT)
+                        .span(span)
+                        .build();
+                    None
+                }
+            },
+        }
+    }
+}
+
+fn rebase(position: Position, start: Position) -> Position {
+    Position::with_offset(
+        position.line - start.line,
+        position.column,
+        position.offset.saturating_sub(start.offset),
+    )
+}
+
+fn rebase_span(span: Span, start: Position) -> Span {
+    Span::new(rebase(span.start, start), rebase(span.end, start))
+}
@@ -3,30 +3,86 @@ extern crate plank_syntax;
 extern crate plank_frontend;
 extern crate plank_ir;
 extern crate plank_interpreter;
+extern crate plank_jit;
+extern crate plank_x86_backend;
+extern crate plank_c_backend;
 
+mod recorder;
 mod test_parser;
 
 use std::fs;
 use std::io;
 use std::io::prelude::*;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
 use plank_errors::reporter::Diagnostic;
+use plank_syntax::tokens::Trivia;
 
 
 enum BuildError {
     Fail(Vec<Diagnostic>),
     BadIr(plank_ir::ir::Symbol, plank_ir::validation::Error),
+    BadRoundtrip(String),
 }
 
-fn build_code(source: &str) -> Result<plank_ir::Program, BuildError> {
+fn print_ir(program: &plank_ir::Program) -> String {
+    let mut out = Vec::new();
+    plank_ir::emit_program(program, &mut out).expect("writing to vec failed");
+    String::from_utf8(out).expect("ir is not utf8")
+}
+
+fn print_cfg(program: &plank_ir::Program, dominators: bool) -> String {
+    let mut out = Vec::new();
+    plank_ir::emit_cfg(program, dominators, &mut out).expect("writing to vec failed");
+    String::from_utf8(out).expect("cfg is not utf8")
+}
+
+fn print_asm(program: &plank_ir::Program) -> String {
+    let mut program = program.clone();
+    plank_x86_backend::fix_function_returns(&mut program);
+    let mut asm = plank_x86_backend::compile_program(&program, None);
+    plank_x86_backend::peephole_optimize(&mut asm);
+    let mut out = Vec::new();
+    plank_x86_backend::print_asm(&mut out, &asm).expect("writing to vec failed");
+    String::from_utf8(out).expect("asm is not utf8")
+}
+
+/// Check that printed ir is parsed back to the same program, and
+/// that binary encoding is decoded back to the same program.
+fn check_roundtrip(program: &plank_ir::Program) -> Result<(), String> {
+    let printed = print_ir(program);
+    let parsed = plank_ir::parse_program(&printed)
+        .map_err(|e| format!("{}\n{}", e, printed))?;
+    let reprinted = print_ir(&parsed);
+    if printed != reprinted {
+        return Err(format!("{}\n>> after parsing:\n{}", printed, reprinted));
+    }
+    let mut encoded = Vec::new();
+    plank_ir::encode_program(program, &mut encoded).expect("writing to vec failed");
+    let decoded = plank_ir::decode_program(&encoded[..])
+        .map_err(|e| format!("{}\n{}", e, printed))?;
+    let reprinted = print_ir(&decoded);
+    if printed == reprinted {
+        Ok(())
+    } else {
+        Err(format!("{}\n>> after decoding:\n{}", printed, reprinted))
+    }
+}
+
+fn build_code(
+    source: &str,
+    options: &plank_frontend::Options,
+) -> Result<plank_ir::Program, BuildError> {
     let reporter = plank_errors::Reporter::new();
-    let tokens = plank_syntax::lex(source, reporter.clone());
+    let tokens = plank_syntax::Lexer::new(source, reporter.clone());
     let program = plank_syntax::parse(tokens, reporter.clone());
-    let ir = plank_frontend::compile(&program, reporter.clone()).map_err(|()| {
-        BuildError::Fail(reporter.get_diagnostics())
-    })?;
+    let ir = plank_frontend::compile_with_options(&program, options, reporter.clone())
+        .map_err(|()| BuildError::Fail(reporter.get_diagnostics()))?;
     if let Err((sym, err)) = plank_ir::validate_ir(&ir) {
         return Err(BuildError::BadIr(sym.clone(), err));
     }
+    check_roundtrip(&ir).map_err(BuildError::BadRoundtrip)?;
     Ok(ir)
 }
 
@@ -34,12 +90,57 @@ fn build_code(source: &str) -> Result<plank_ir::Program, BuildError> {
 enum TestResult {
     BuildFail(Vec<Diagnostic>),
     IrValidationFail(plank_ir::ir::Symbol, plank_ir::validation::Error),
+    IrRoundtripFail(String),
+    /// IR was expected to fail validation with an error like this.
+    IrValidationPass(String),
+    /// IR failed validation with a different error than the expected one.
+    IrValidationMismatch(String, plank_ir::validation::Error),
+    IrParseFail(plank_ir::ParseError),
+    /// IR failed to parse with a different error than the expected one.
+    IrParseMismatch(plank_ir::ParseError, Vec<test_parser::Error>),
+    CheckFail(test_parser::Check, String),
     BadBuildPass(Vec<test_parser::Error>),
     BuildErrorMismatch(Vec<Diagnostic>, Vec<test_parser::Error>),
     IoMismatch { expected: Vec<u8>, got: Vec<u8> },
     MalformedTest(test_parser::ParseError),
     InterpreterExit(i32),
     InterpreterError(plank_interpreter::Error),
+    /// Interpreter reported inconsistent events to an observer.
+    ObserverError(String),
+    /// Program was expected to fail at runtime, but exited with given code.
+    NoTrap(i32),
+    /// Runtime error did not match the expected trace line.
+    TrapMismatch(test_parser::Check, plank_interpreter::Error),
+    /// Program compiled with JIT behaved differently than the interpreter.
+    JitIoMismatch { expected: Vec<u8>, got: Vec<u8> },
+    JitExit(i32),
+    JitError(plank_jit::Error),
+    /// Native executable behaved differently than the interpreter.
+    NativeIoMismatch { expected: Vec<u8>, got: Vec<u8> },
+    NativeExit(i32),
+    /// Native executable failed with a different error than the interpreter.
+    NativeTrapMismatch { expected: String, got: String },
+    /// Native executable could not be built or run, or was killed.
+    NativeError(String),
+    /// Program compiled to C behaved differently than the interpreter.
+    CIoMismatch { expected: Vec<u8>, got: Vec<u8> },
+    CExit(i32),
+    /// C code could not be compiled or run, or was killed.
+    CError(String),
+    /// Plank exited with a different code than expected, when
+    /// given these arguments.
+    CommandStatus { args: Vec<String>, expected: i32, got: Option<i32>, output: String },
+    /// Plank given these arguments wrote something else to stdout.
+    CommandIoMismatch { args: Vec<String>, expected: Vec<u8>, got: Vec<u8> },
+    /// Plank could not be built or run.
+    CommandError(String),
+    /// Formatter failed, was not idempotent, changed comments,
+    /// or made a program that does not build.
+    FormatError(String),
+    /// Formatted program behaved differently than the original.
+    FormattedIoMismatch { expected: Vec<u8>, got: Vec<u8> },
+    /// Object file with debug info could not be written or dumped.
+    DebugInfoError(String),
     Ok,
 }
 
@@ -49,7 +150,15 @@ fn match_build_errors(expected: Vec<test_parser::Error>, got: Vec<Diagnostic>) -
         let mut matched = false;
         for actual in &got {
             if let Some(span) = actual.primary_span {
-                if span.start.line == err.line && actual.message.contains(&err.message) {
+                let has_notes = err.notes.iter().all(|expected| {
+                    actual.notes.iter().any(|note| match note.message {
+                        Some(ref message) => message.contains(expected),
+                        None => false,
+                    })
+                });
+                if span.start.line == err.line && actual.message.contains(&err.message)
+                    && has_notes
+                {
                     matched = true;
                     break;
                 }
@@ -66,49 +175,660 @@ fn match_build_errors(expected: Vec<test_parser::Error>, got: Vec<Diagnostic>) -
     }
 }
 
-fn interpret_program(program: plank_ir::Program, input: Vec<u8>, output: Vec<u8>) -> TestResult {
+/// Stop test programs that get stuck instead of hanging the test runner.
+const FUEL: u64 = 1_000_000;
+const CALL_DEPTH: usize = 1000;
+const MEMORY: usize = 16 << 20;
+/// Failing tests list this many steps before the trap,
+/// so that they can check them too.
+const HISTORY: usize = 10;
+
+fn interpreter_options(args: &[String], history: Option<usize>) -> plank_interpreter::Options {
+    plank_interpreter::Options {
+        fuel: Some(FUEL),
+        call_depth: Some(CALL_DEPTH),
+        memory: Some(MEMORY),
+        // tests that pass must also free everything they allocate
+        check_leaks: true,
+        sanitize: true,
+        history,
+        args: args.to_vec(),
+        // so that tests can print how long they ran
+        virtual_time: true,
+        libraries: Vec::new(),
+        entry: None,
+    }
+}
+
+fn run_interpreter(
+    program: &plank_ir::Program,
+    input: &[u8],
+    output: &mut Vec<u8>,
+    args: &[String],
+    history: Option<usize>,
+) -> Result<i32, plank_interpreter::Error> {
+    let options = interpreter_options(args, history);
+    plank_interpreter::run_program_with_options(program, input, output, &options)
+}
+
+fn interpret_program(
+    program: plank_ir::Program,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    args: &[String],
+) -> TestResult {
+    let mut actual_output = Vec::new();
+    match run_interpreter(&program, &input, &mut actual_output, args, None) {
+        Ok(0) if actual_output == output => jit_program(&program, input, output, args),
+        Ok(0) => TestResult::IoMismatch { expected: output, got: actual_output },
+        Ok(code) => TestResult::InterpreterExit(code),
+        Err(e) => TestResult::InterpreterError(e),
+    }
+}
+
+/// Run program in the interpreter with a `Recorder`, and match `checks`
+/// against the events that were reported to it.
+fn observe_program(
+    program: &plank_ir::Program,
+    input: &[u8],
+    args: &[String],
+    checks: Vec<test_parser::Check>,
+) -> TestResult {
+    let options = interpreter_options(args, None);
+    let mut recorder = recorder::Recorder::new(!checks.is_empty());
+    let mut output = Vec::new();
+    let result = plank_interpreter::run_program_with_observer(
+        program,
+        input,
+        &mut output,
+        &options,
+        &mut recorder,
+    );
+    if let Err(e) = result {
+        return TestResult::InterpreterError(e);
+    }
+    match recorder.finish() {
+        Ok(events) => match_checks(checks, &events),
+        Err(e) => TestResult::ObserverError(e),
+    }
+}
+
+/// Runtime errors that native executables report like the interpreter,
+/// before exiting with status 1.
+fn reported_natively(err: &plank_interpreter::Error) -> bool {
+    use plank_interpreter::Error;
+    match *err {
+        Error::Trap(ref err, _) | Error::History(ref err, _) => reported_natively(err),
+        Error::IndexOutOfBounds(..) |
+        Error::ArithmeticOverflow(..) |
+        Error::AssertionFailed(..) |
+        Error::Panicked(..) => true,
+        _ => false,
+    }
+}
+
+/// Run program in the interpreter and check that it fails as expected.
+/// If `native` is given and the failure is reported by native
+/// executables too, it is also compiled and checked to fail the same way.
+fn trap_program(
+    program: plank_ir::Program,
+    native: Option<&plank_ir::Program>,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    trace: Vec<test_parser::Check>,
+    args: &[String],
+) -> TestResult {
+    let mut actual_output = Vec::new();
+    match run_interpreter(&program, &input, &mut actual_output, args, Some(HISTORY)) {
+        Ok(code) => TestResult::NoTrap(code),
+        Err(_) if actual_output != output => {
+            TestResult::IoMismatch { expected: output, got: actual_output }
+        }
+        Err(err) => match match_checks(trace, &err.to_string()) {
+            TestResult::CheckFail(check, _) => TestResult::TrapMismatch(check, err),
+            TestResult::Ok if native.is_some() && reported_natively(&err) => {
+                let message = err.to_string().lines().next().unwrap_or("").to_string();
+                native_trap(native.unwrap(), &input, output, message, args)
+            }
+            result => result,
+        },
+    }
+}
+
+fn jit_program(
+    program: &plank_ir::Program,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    args: &[String],
+) -> TestResult {
     let mut input = ::std::io::Cursor::new(input);
     let mut actual_output = Vec::new();
-    match plank_interpreter::run_program(&program, &mut input, &mut actual_output) {
+    match plank_jit::run_program_with_args(program, &mut input, &mut actual_output, args) {
         Ok(0) if actual_output == output => TestResult::Ok,
-        Ok(0) => TestResult::IoMismatch { expected: output, got: actual_output },
+        Ok(0) => TestResult::JitIoMismatch { expected: output, got: actual_output },
+        Ok(code) => TestResult::JitExit(code),
+        Err(e) => TestResult::JitError(e),
+    }
+}
+
+/// Compile program with the x86 backend and link it with `ld`
+/// into `executable`, returning the output of the linker.
+fn link_native(program: &plank_ir::Program, executable: &Path) -> io::Result<Output> {
+    let mut program = program.clone();
+    plank_x86_backend::fix_function_returns(&mut program);
+    let mut asm = plank_x86_backend::compile_program(&program, None);
+    plank_x86_backend::peephole_optimize(&mut asm);
+    let emulation = match asm.mode {
+        plank_x86_backend::Mode::Bits32 => "elf_i386",
+        plank_x86_backend::Mode::Bits64 => "elf_x86_64",
+    };
+    let mut object = Vec::new();
+    plank_x86_backend::write_object(&mut object, &asm, true)?;
+    let object_path = executable.with_extension("o");
+    fs::write(&object_path, object)?;
+    let linked = Command::new("ld")
+        .arg("-m")
+        .arg(emulation)
+        .arg("-o")
+        .arg(executable)
+        .arg(&object_path)
+        .output();
+    fs::remove_file(&object_path)?;
+    linked
+}
+
+fn run_executable(executable: &Path, input: &[u8], args: &[String]) -> io::Result<Output> {
+    run_command(Command::new(executable).arg0(&args[0]).args(&args[1..]), input)
+}
+
+/// Run a command with given input, and collect its output.
+fn run_command(command: &mut Command, input: &[u8]) -> io::Result<Output> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // programs may exit without reading all of their input
+    let _ = child.stdin.take().expect("stdin is piped").write_all(input);
+    child.wait_with_output()
+}
+
+/// Compile program to a native executable and run it.
+fn run_native(
+    program: &plank_ir::Program,
+    input: &[u8],
+    args: &[String],
+) -> Result<Output, String> {
+    let executable = ::std::env::temp_dir().join(format!("plank-test-{}", ::std::process::id()));
+    match link_native(program, &executable) {
+        Ok(ref linked) if linked.status.success() => {
+            let result = run_executable(&executable, input, args);
+            let _ = fs::remove_file(&executable);
+            result.map_err(|e| e.to_string())
+        }
+        Ok(linked) => Err(String::from_utf8_lossy(&linked.stderr).into()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn native_program(
+    program: &plank_ir::Program,
+    input: &[u8],
+    output: Vec<u8>,
+    args: &[String],
+) -> TestResult {
+    match run_native(program, input, args) {
+        Ok(ref result) if result.status.success() && result.stdout == output => TestResult::Ok,
+        Ok(ref result) if result.status.success() => {
+            TestResult::NativeIoMismatch { expected: output, got: result.stdout.clone() }
+        }
+        Ok(ref result) => match result.status.code() {
+            Some(code) => TestResult::NativeExit(code),
+            None => TestResult::NativeError(format!("{}", result.status)),
+        },
+        Err(e) => TestResult::NativeError(e),
+    }
+}
+
+/// Compile the program to C with the pointer size of the machine that
+/// runs the tests, build it with `cc` in strict C99 mode, and check
+/// that it behaves like the interpreter.
+fn c_program(
+    source: &str,
+    options: &plank_frontend::Options,
+    input: &[u8],
+    output: Vec<u8>,
+    args: &[String],
+) -> TestResult {
+    let mut options = options.clone();
+    if ::std::mem::size_of::<usize>() == 8 {
+        options.target = plank_ir::target::X86_64;
+    }
+    let program = match build_code(source, &options) {
+        Ok(program) => program,
+        Err(BuildError::Fail(e)) => return TestResult::BuildFail(e),
+        Err(BuildError::BadIr(sym, err)) => return TestResult::IrValidationFail(sym, err),
+        Err(BuildError::BadRoundtrip(err)) => return TestResult::IrRoundtripFail(err),
+    };
+    let mut code = Vec::new();
+    plank_c_backend::emit_program(&program, &mut code).expect("writing to vec failed");
+    let executable = ::std::env::temp_dir().join(format!("plank-test-c-{}", ::std::process::id()));
+    let code_path = executable.with_extension("c");
+    let compiled = fs::write(&code_path, code).and_then(|()| {
+        Command::new("cc")
+            .arg("-std=c99")
+            .arg("-o")
+            .arg(&executable)
+            .arg(&code_path)
+            .output()
+    });
+    let _ = fs::remove_file(&code_path);
+    let result = match compiled {
+        Ok(ref compiled) if compiled.status.success() => {
+            let result = run_executable(&executable, input, args);
+            let _ = fs::remove_file(&executable);
+            result
+        }
+        Ok(compiled) => {
+            return TestResult::CError(String::from_utf8_lossy(&compiled.stderr).into())
+        }
+        Err(e) => return TestResult::CError(e.to_string()),
+    };
+    match result {
+        Ok(ref result) if result.status.success() && result.stdout == output => TestResult::Ok,
+        Ok(ref result) if result.status.success() => {
+            TestResult::CIoMismatch { expected: output, got: result.stdout.clone() }
+        }
+        Ok(ref result) => match result.status.code() {
+            Some(code) => TestResult::CExit(code),
+            None => TestResult::CError(format!("{}", result.status)),
+        },
+        Err(e) => TestResult::CError(e.to_string()),
+    }
+}
+
+/// Comments of source code, in order, with Unix line endings.
+fn comments(source: &str) -> Vec<String> {
+    let (_, trivia) = plank_syntax::lex_with_trivia(source, plank_errors::Reporter::new());
+    trivia
+        .into_iter()
+        .filter_map(|trivia| match plank_syntax::position::Spanned::into_value(trivia) {
+            Trivia::LineComment(text) | Trivia::BlockComment(text) => {
+                Some(text.replace("\r\n", "\n").trim_end_matches('\r').to_string())
+            }
+            Trivia::Whitespace(_) => None,
+        })
+        .collect()
+}
+
+/// Format the program, and check that formatting it again changes
+/// nothing, that its comments are kept in the same order, and that
+/// the formatted program behaves the same in the interpreter.
+fn formatted_program(
+    source: &str,
+    options: &plank_frontend::Options,
+    input: &[u8],
+    output: Vec<u8>,
+    args: &[String],
+) -> TestResult {
+    let formatted = match plank_syntax::format_source(source, plank_errors::Reporter::new()) {
+        Some(formatted) => formatted,
+        None => return TestResult::FormatError("program could not be formatted".into()),
+    };
+    match plank_syntax::format_source(&formatted, plank_errors::Reporter::new()) {
+        Some(ref again) if *again == formatted => {}
+        Some(again) => {
+            let message = format!("formatting again changed the program to:\n{}", again);
+            return TestResult::FormatError(message);
+        }
+        None => {
+            let message = format!("formatted program does not parse:\n{}", formatted);
+            return TestResult::FormatError(message);
+        }
+    }
+    if comments(source) != comments(&formatted) {
+        let message = format!("formatting changed the comments:\n{}", formatted);
+        return TestResult::FormatError(message);
+    }
+    let program = match build_code(&formatted, options) {
+        Ok(program) => program,
+        Err(_) => {
+            let message = format!("formatted program does not build:\n{}", formatted);
+            return TestResult::FormatError(message);
+        }
+    };
+    let mut actual_output = Vec::new();
+    match run_interpreter(&program, input, &mut actual_output, args, None) {
+        Ok(0) if actual_output == output => TestResult::Ok,
+        Ok(0) => TestResult::FormattedIoMismatch { expected: output, got: actual_output },
         Ok(code) => TestResult::InterpreterExit(code),
         Err(e) => TestResult::InterpreterError(e),
     }
 }
 
+/// Check that native executable fails with status 1, after writing
+/// `output` and an error that starts with `message`.
+fn native_trap(
+    program: &plank_ir::Program,
+    input: &[u8],
+    output: Vec<u8>,
+    message: String,
+    args: &[String],
+) -> TestResult {
+    match run_native(program, input, args) {
+        Ok(ref result) if result.stdout != output => {
+            TestResult::NativeIoMismatch { expected: output, got: result.stdout.clone() }
+        }
+        Ok(ref result) if result.status.code() == Some(1) => {
+            if result.stderr.starts_with(message.as_bytes()) {
+                TestResult::Ok
+            } else {
+                let got = String::from_utf8_lossy(&result.stderr).into();
+                TestResult::NativeTrapMismatch { expected: message, got }
+            }
+        }
+        Ok(ref result) => match result.status.code() {
+            Some(code) => TestResult::NativeExit(code),
+            None => TestResult::NativeError(format!("{}", result.status)),
+        },
+        Err(e) => TestResult::NativeError(e),
+    }
+}
+
 fn run_test(source: &str) -> TestResult {
     let expectation = match test_parser::parse_test(source) {
         Ok(e) => e,
         Err(e) => return TestResult::MalformedTest(e),
     };
+    let options = match test_parser::parse_options(source) {
+        Ok(options) => options,
+        Err(e) => return TestResult::MalformedTest(e),
+    };
+    let args = test_parser::parse_args(source);
     match expectation {
         test_parser::Expectation::BuildErrors(errors) => {
-            match build_code(source) {
+            match build_code(source, &options) {
                 Ok(_) => TestResult::BadBuildPass(errors),
                 Err(BuildError::Fail(got)) => match_build_errors(errors, got),
                 Err(BuildError::BadIr(sym, err)) => TestResult::IrValidationFail(sym, err),
+                Err(BuildError::BadRoundtrip(err)) => TestResult::IrRoundtripFail(err),
             }
         }
         test_parser::Expectation::Io { input, output} => {
-            match build_code(source) {
-                Ok(program) => interpret_program(program, input, output),
+            match build_code(source, &options) {
+                // hand-written IR tests only run in the interpreter and
+                // JIT, source code is also compiled to native executables
+                Ok(program) => {
+                    let expected = output.clone();
+                    match interpret_program(program.clone(), input.clone(), expected, &args) {
+                        TestResult::Ok => {}
+                        result => return result,
+                    }
+                    let checks = test_parser::parse_checks(source);
+                    match observe_program(&program, &input, &args, checks) {
+                        TestResult::Ok => {}
+                        result => return result,
+                    }
+                    if test_parser::runs_natively(source) {
+                        match native_program(&program, &input, output.clone(), &args) {
+                            TestResult::Ok => {}
+                            result => return result,
+                        }
+                    }
+                    if test_parser::runs_as_c(source) {
+                        match c_program(source, &options, &input, output.clone(), &args) {
+                            TestResult::Ok => {}
+                            result => return result,
+                        }
+                    }
+                    formatted_program(source, &options, &input, output, &args)
+                }
+                Err(BuildError::Fail(e)) => TestResult::BuildFail(e),
+                Err(BuildError::BadIr(sym, err)) => TestResult::IrValidationFail(sym, err),
+                Err(BuildError::BadRoundtrip(err)) => TestResult::IrRoundtripFail(err),
+            }
+        }
+        test_parser::Expectation::Trap { input, output, trace } => {
+            match build_code(source, &options) {
+                Ok(program) => {
+                    let native = if test_parser::runs_natively(source) {
+                        Some(program.clone())
+                    } else {
+                        None
+                    };
+                    trap_program(program, native.as_ref(), input, output, trace, &args)
+                }
                 Err(BuildError::Fail(e)) => TestResult::BuildFail(e),
                 Err(BuildError::BadIr(sym, err)) => TestResult::IrValidationFail(sym, err),
+                Err(BuildError::BadRoundtrip(err)) => TestResult::IrRoundtripFail(err),
             }
         }
         test_parser::Expectation::BuildSuccess => {
-            match build_code(source) {
+            match build_code(source, &options) {
                 Ok(_) => TestResult::Ok,
                 Err(BuildError::Fail(e)) => TestResult::BuildFail(e),
                 Err(BuildError::BadIr(sym, err)) => TestResult::IrValidationFail(sym, err),
+                Err(BuildError::BadRoundtrip(err)) => TestResult::IrRoundtripFail(err),
             }
         }
     }
 }
 
+fn match_checks(checks: Vec<test_parser::Check>, output: &str) -> TestResult {
+    let lines = output.lines().collect::<Vec<_>>();
+    let mut position = 0;
+    let mut negated = Vec::new();
+    for check in checks {
+        if check.negated {
+            negated.push(check);
+            continue;
+        }
+        let found = lines[position..]
+            .iter()
+            .position(|line| line.contains(&check.text));
+        let end = match found {
+            Some(index) => position + index,
+            None => return TestResult::CheckFail(check, output.into()),
+        };
+        for check in negated.drain(..) {
+            if lines[position..end].iter().any(|line| line.contains(&check.text)) {
+                return TestResult::CheckFail(check, output.into());
+            }
+        }
+        position = end + 1;
+    }
+    for check in negated {
+        if lines[position..].iter().any(|line| line.contains(&check.text)) {
+            return TestResult::CheckFail(check, output.into());
+        }
+    }
+    TestResult::Ok
+}
+
+fn run_ir_test(source: &str) -> TestResult {
+    let passes = match test_parser::parse_passes(source) {
+        Ok(passes) => passes,
+        Err(e) => return TestResult::MalformedTest(e),
+    };
+    let emit = match test_parser::parse_emit(source) {
+        Ok(emit) => emit,
+        Err(e) => return TestResult::MalformedTest(e),
+    };
+    let expectation = match test_parser::parse_test(source) {
+        Ok(test_parser::Expectation::BuildErrors(errors)) => {
+            // parsing stops at the first error, so only one can be expected
+            if errors.len() > 1 {
+                return TestResult::MalformedTest(test_parser::ParseError::ErrorsInIr);
+            }
+            return match plank_ir::parse_program(source) {
+                Ok(_) => TestResult::BadBuildPass(errors),
+                Err(e) => {
+                    let err = &errors[0];
+                    if e.line == err.line as usize + 1 && e.message.contains(&err.message) {
+                        TestResult::Ok
+                    } else {
+                        TestResult::IrParseMismatch(e, errors)
+                    }
+                }
+            };
+        }
+        Ok(expectation) => expectation,
+        Err(e) => return TestResult::MalformedTest(e),
+    };
+    let mut program = match plank_ir::parse_program(source) {
+        Ok(program) => program,
+        Err(e) => return TestResult::IrParseFail(e),
+    };
+    if let Some(expected) = test_parser::parse_invalid(source) {
+        return match plank_ir::validate_ir(&program) {
+            Ok(()) => TestResult::IrValidationPass(expected),
+            Err((_, err)) if err.to_string().contains(&expected) => TestResult::Ok,
+            Err((_, err)) => TestResult::IrValidationMismatch(expected, err),
+        };
+    }
+    for pass in passes {
+        if let Err((sym, err)) = plank_ir::validate_ir(&program) {
+            return TestResult::IrValidationFail(sym.clone(), err);
+        }
+        pass.run(&mut program, &Default::default());
+    }
+    if let Err((sym, err)) = plank_ir::validate_ir(&program) {
+        return TestResult::IrValidationFail(sym.clone(), err);
+    }
+    if let Err(err) = check_roundtrip(&program) {
+        return TestResult::IrRoundtripFail(err);
+    }
+    let output = match emit {
+        test_parser::Emit::Ir => print_ir(&program),
+        test_parser::Emit::Cfg { dominators } => print_cfg(&program, dominators),
+        test_parser::Emit::Asm => print_asm(&program),
+    };
+    match match_checks(test_parser::parse_checks(source), &output) {
+        TestResult::Ok => {}
+        fail => return fail,
+    }
+    let args = test_parser::parse_args(source);
+    match expectation {
+        test_parser::Expectation::Io { input, output } => {
+            interpret_program(program, input, output, &args)
+        }
+        test_parser::Expectation::Trap { input, output, trace } => {
+            trap_program(program, None, input, output, trace, &args)
+        }
+        _ => TestResult::Ok,
+    }
+}
+
+/// Compile program to an object file with debug info, and match checks
+/// against the line table and then the rest of the debug info, as
+/// printed by `readelf`. Runs of spaces in the output are joined into
+/// one, so that checks don't depend on how `readelf` lines up columns.
+fn run_debug_info_test(source: &str) -> TestResult {
+    let options = match test_parser::parse_options(source) {
+        Ok(options) => options,
+        Err(e) => return TestResult::MalformedTest(e),
+    };
+    let mut program = match build_code(source, &options) {
+        Ok(program) => program,
+        Err(BuildError::Fail(e)) => return TestResult::BuildFail(e),
+        Err(BuildError::BadIr(sym, err)) => return TestResult::IrValidationFail(sym, err),
+        Err(BuildError::BadRoundtrip(err)) => return TestResult::IrRoundtripFail(err),
+    };
+    plank_x86_backend::fix_function_returns(&mut program);
+    let source_file = plank_x86_backend::SourceFile {
+        path: "test.plk".into(),
+        directory: ".".into(),
+    };
+    let mut asm = plank_x86_backend::compile_program(&program, Some(source_file));
+    plank_x86_backend::peephole_optimize(&mut asm);
+    let mut object = Vec::new();
+    plank_x86_backend::write_object(&mut object, &asm, true).expect("writing to vec failed");
+    let object_name = format!("plank-test-{}.o", ::std::process::id());
+    let object_path = ::std::env::temp_dir().join(object_name);
+    if let Err(e) = fs::write(&object_path, object) {
+        return TestResult::DebugInfoError(e.to_string());
+    }
+    let mut output = String::new();
+    for dump in &["--debug-dump=decodedline", "--debug-dump=info"] {
+        let result = match Command::new("readelf").arg(dump).arg(&object_path).output() {
+            Ok(ref result) if !result.status.success() => {
+                Err(String::from_utf8_lossy(&result.stderr).into_owned())
+            }
+            Ok(result) => Ok(result.stdout),
+            Err(e) => Err(e.to_string()),
+        };
+        let dumped = match result {
+            Ok(dumped) => dumped,
+            Err(e) => {
+                let _ = fs::remove_file(&object_path);
+                return TestResult::DebugInfoError(e);
+            }
+        };
+        for line in String::from_utf8_lossy(&dumped).lines() {
+            output.push_str(&line.split_whitespace().collect::<Vec<_>>().join(" "));
+            output.push('\n');
+        }
+    }
+    let _ = fs::remove_file(&object_path);
+    match_checks(test_parser::parse_checks(source), &output)
+}
+
+/// Build the plank executable with the same profile as the test
+/// runner, and return its path.
+fn build_plank() -> Result<PathBuf, String> {
+    let runner = ::std::env::current_exe().map_err(|e| e.to_string())?;
+    let directory = runner.parent().expect("runner is not in a directory");
+    let cargo = ::std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let mut command = Command::new(cargo);
+    command.args(["build", "--quiet", "--package", "plank"]);
+    if directory.ends_with("release") {
+        command.arg("--release");
+    }
+    match command.status() {
+        Ok(ref status) if status.success() => Ok(directory.join("plank")),
+        Ok(status) => Err(format!("building plank failed: {}", status)),
+        Err(e) => Err(format!("cannot run cargo: {}", e)),
+    }
+}
+
+/// Run plank like a command line test says, and check what it does.
+fn run_command_test(plank: &Result<PathBuf, String>, source: &str, path: &str) -> TestResult {
+    let test = match test_parser::parse_command_test(source, path) {
+        Ok(test) => test,
+        Err(e) => return TestResult::MalformedTest(e),
+    };
+    let plank = match *plank {
+        Ok(ref plank) => plank,
+        Err(ref e) => return TestResult::CommandError(e.clone()),
+    };
+    for args in test.runs {
+        let result = match run_command(Command::new(plank).args(&args), &test.input) {
+            Ok(result) => result,
+            Err(e) => return TestResult::CommandError(e.to_string()),
+        };
+        if let Some(ref output) = test.output {
+            if result.stdout != *output {
+                let (expected, got) = (output.clone(), result.stdout);
+                return TestResult::CommandIoMismatch { args, expected, got };
+            }
+        }
+        let mut output = String::from_utf8_lossy(&result.stdout).into_owned();
+        output.push_str(&String::from_utf8_lossy(&result.stderr));
+        if result.status.code() != Some(test.status) {
+            let (expected, got) = (test.status, result.status.code());
+            return TestResult::CommandStatus { args, expected, got, output };
+        }
+        match match_checks(test.checks.clone(), &output) {
+            TestResult::Ok => {}
+            fail => return fail,
+        }
+    }
+    TestResult::Ok
+}
+
 fn run_tests() -> io::Result<Vec<(String, String, TestResult)>> {
     let mut test_results = Vec::new();
+    let plank = build_plank();
     for dir in TEST_DIRS {
         for entry in fs::read_dir(dir)? {
             let entry = entry?;
@@ -118,7 +838,16 @@ fn run_tests() -> io::Result<Vec<(String, String, TestResult)>> {
             let mut file = fs::File::open(entry.path())?;
             let mut source = String::new();
             file.read_to_string(&mut source)?;
-            let test_result = run_test(&source);
+            let test_result = if entry.path().extension() == Some("plankir".as_ref()) {
+                run_ir_test(&source)
+            } else if *dir == COMMAND_DIR {
+                let path = entry.path();
+                run_command_test(&plank, &source, path.to_str().expect("path is not utf8"))
+            } else if *dir == DEBUG_INFO_DIR {
+                run_debug_info_test(&source)
+            } else {
+                run_test(&source)
+            };
             let test_name = entry.path().display().to_string();
             if let TestResult::Ok = test_result {
                 println!("test {} ... ok", test_name);
@@ -134,18 +863,27 @@ fn run_tests() -> io::Result<Vec<(String, String, TestResult)>> {
 fn print_expected_errors(errors: &[test_parser::Error]) {
     for err in errors {
         println!("at line {}: {}", err.line + 1, err.message);
+        for note in &err.notes {
+            println!("    note: {}", note);
+        }
     }
 }
 
-fn print_output(out: &[u8]) {
-    for &byte in out {
+/// Bytes as text, with unprintable ones written like `\x0A`.
+fn escape(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for &byte in bytes {
         if byte < 32 || byte >= 127 || byte == b'\\' {
-            print!("\\x{:>02X}", byte);
+            text.push_str(&format!("\\x{:>02X}", byte));
         } else {
-            print!("{}", byte as char);
+            text.push(byte as char);
         }
     }
-    println!();
+    text
+}
+
+fn print_output(out: &[u8]) {
+    println!("{}", escape(out));
 }
 
 fn report_results(results: &[(String, String, TestResult)]) {
@@ -190,9 +928,61 @@ fn report_results(results: &[(String, String, TestResult)]) {
                 println!("========================================");
                 println!("test {}", name);
                 println!("ir validation failed");
-                println!("error in symbol {:?}: {:?}", sym, err);
+                println!("error in function `{}`: {}", sym.0, err);
+                println!();
+            }
+            TestResult::IrValidationPass(ref expected) => {
+                println!("========================================");
+                println!("test {}", name);
+                println!("ir passed validation, but should have failed with:");
+                println!("{}", expected);
+                println!();
+            }
+            TestResult::IrValidationMismatch(ref expected, ref err) => {
+                println!("========================================");
+                println!("test {}", name);
+                println!("ir validation error mismatch");
+                println!(">> expected error:");
+                println!("{}", expected);
+                println!(">> actual error:");
+                println!("{}", err);
                 println!();
             }
+            TestResult::IrRoundtripFail(ref err) => {
+                println!("========================================");
+                println!("test {}", name);
+                println!("printed ir was parsed differently");
+                println!("{}", err);
+                println!();
+            }
+            TestResult::IrParseFail(ref err) => {
+                println!("========================================");
+                println!("test {}", name);
+                println!("ir parsing failed");
+                println!("{}", err);
+                println!();
+            }
+            TestResult::IrParseMismatch(ref err, ref expected) => {
+                println!("========================================");
+                println!("test {}", name);
+                println!("ir parse error mismatch");
+                println!(">> expected error:");
+                print_expected_errors(expected);
+                println!(">> actual error:");
+                println!("{}", err);
+                println!();
+            }
+            TestResult::CheckFail(ref check, ref output) => {
+                println!("========================================");
+                println!("test {}", name);
+                if check.negated {
+                    println!("check at line {} matched: {}", check.line + 1, check.text);
+                } else {
+                    println!("check at line {} not matched: {}", check.line + 1, check.text);
+                }
+                println!(">> checked output:");
+                println!("{}", output);
+            }
             TestResult::MalformedTest(ref err) => {
                 println!("========================================");
                 println!("test {}", name);
@@ -213,6 +1003,156 @@ fn report_results(results: &[(String, String, TestResult)]) {
                 println!("{}", err);
                 println!();
             }
+            TestResult::ObserverError(ref err) => {
+                println!("========================================");
+                println!("test {}", name);
+                println!("interpreter reported inconsistent events");
+                println!("{}", err);
+                println!();
+            }
+            TestResult::NoTrap(code) => {
+                println!("========================================");
+                println!("test {}", name);
+                println!("expected a runtime error, but program exited with code {}", code);
+                println!();
+            }
+            TestResult::TrapMismatch(ref check, ref err) => {
+                println!("========================================");
+                println!("test {}", name);
+                println!("trap at line {} not matched: {}", check.line + 1, check.text);
+                println!(">> runtime error:");
+                println!("{}", err);
+                println!();
+            }
+            TestResult::JitIoMismatch { ref expected, ref got } => {
+                println!("========================================");
+                println!("test {}", name);
+                println!("wrong output when compiled with JIT");
+                print!("Expected: ");
+                print_output(expected);
+                print!("Got:      ");
+                print_output(got);
+                println!();
+            }
+            TestResult::JitExit(code) => {
+                println!("========================================");
+                println!("test {}", name);
+                println!("program compiled with JIT exited with code {}", code);
+                println!();
+            }
+            TestResult::JitError(ref err) => {
+                println!("========================================");
+                println!("test {}", name);
+                println!("program compiled with JIT crashed");
+                println!("{}", err);
+                println!();
+            }
+            TestResult::NativeIoMismatch { ref expected, ref got } => {
+                println!("========================================");
+                println!("test {}", name);
+                println!("wrong output when compiled to a native executable");
+                print!("Expected: ");
+                print_output(expected);
+                print!("Got:      ");
+                print_output(got);
+                println!();
+            }
+            TestResult::NativeExit(code) => {
+                println!("========================================");
+                println!("test {}", name);
+                println!("native executable exited with code {}", code);
+                println!();
+            }
+            TestResult::NativeError(ref err) => {
+                println!("========================================");
+                println!("test {}", name);
+                println!("native executable failed");
+                println!("{}", err);
+                println!();
+            }
+            TestResult::NativeTrapMismatch { ref expected, ref got } => {
+                println!("========================================");
+                println!("test {}", name);
+                println!("native executable failed with a different error");
+                println!("Expected: {}", expected);
+                println!("Got:      {}", got.trim_end());
+                println!();
+            }
+            TestResult::CIoMismatch { ref expected, ref got } => {
+                println!("========================================");
+                println!("test {}", name);
+                println!("wrong output when compiled to C");
+                print!("Expected: ");
+                print_output(expected);
+                print!("Got:      ");
+                print_output(got);
+                println!();
+            }
+            TestResult::CExit(code) => {
+                println!("========================================");
+                println!("test {}", name);
+                println!("program compiled to C exited with code {}", code);
+                println!();
+            }
+            TestResult::CError(ref err) => {
+                println!("========================================");
+                println!("test {}", name);
+                println!("program compiled to C failed");
+                println!("{}", err);
+                println!();
+            }
+            TestResult::CommandStatus { ref args, expected, got, ref output } => {
+                println!("========================================");
+                println!("test {}", name);
+                println!("`plank {}` exited with a different code", args.join(" "));
+                println!("Expected: {}", expected);
+                match got {
+                    Some(code) => println!("Got:      {}", code),
+                    None => println!("Got:      killed by a signal"),
+                }
+                println!(">> output:");
+                println!("{}", output);
+            }
+            TestResult::CommandIoMismatch { ref args, ref expected, ref got } => {
+                println!("========================================");
+                println!("test {}", name);
+                println!("wrong output from `plank {}`", args.join(" "));
+                print!("Expected: ");
+                print_output(expected);
+                print!("Got:      ");
+                print_output(got);
+                println!();
+            }
+            TestResult::CommandError(ref err) => {
+                println!("========================================");
+                println!("test {}", name);
+                println!("plank could not be run");
+                println!("{}", err);
+                println!();
+            }
+            TestResult::FormatError(ref err) => {
+                println!("========================================");
+                println!("test {}", name);
+                println!("{}", err);
+                println!();
+            }
+            TestResult::FormattedIoMismatch { ref expected, ref got } => {
+                println!("========================================");
+                println!("test {}", name);
+                println!("wrong output after formatting");
+                print!("Expected: ");
+                print_output(expected);
+                print!("Got:      ");
+                print_output(got);
+                println!();
+            }
+            TestResult::DebugInfoError(ref err) => {
+                println!("========================================");
+                println!("test {}", name);
+                println!("debug info could not be dumped");
+                println!("{}", err);
+                println!();
+            }
         }
     }
     println!("========================================");
@@ -234,4 +1174,15 @@ const TEST_DIRS: &'static [&'static str] = &[
     "./examples",
     "./tests/compile-fail",
     "./tests/pass",
+    "./tests/run-fail",
+    "./tests/ir",
+    COMMAND_DIR,
+    DEBUG_INFO_DIR,
 ];
+
+/// Tests that run the plank executable. Files that they
+/// use are kept in subdirectories.
+const COMMAND_DIR: &str = "./tests/cli";
+
+/// Tests that check debug info of the x86 backend with `readelf`.
+const DEBUG_INFO_DIR: &str = "./tests/debug-info";
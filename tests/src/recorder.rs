@@ -0,0 +1,92 @@
+use plank_errors::position::Span;
+use plank_interpreter::Observer;
+
+
+/// Observer that checks that the interpreter reports events consistently,
+/// and optionally writes them down as lines of text for `// CHECK:` lines.
+pub struct Recorder {
+    /// Functions that are running, outermost first.
+    calls: Vec<String>,
+    /// Line of the last statement that was reported after the
+    /// last call or return.
+    line: Option<u32>,
+    events: Option<Vec<String>>,
+    error: Option<String>,
+}
+
+impl Recorder {
+    pub fn new(keep_events: bool) -> Recorder {
+        Recorder {
+            calls: Vec::new(),
+            line: None,
+            events: if keep_events { Some(Vec::new()) } else { None },
+            error: None,
+        }
+    }
+
+    /// Events that were recorded, one per line, or the first
+    /// inconsistency if there was one.
+    pub fn finish(mut self) -> Result<String, String> {
+        if let Some(function) = self.calls.pop() {
+            self.fail(format!("`{}` never returned", function));
+        }
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.events.map(|events| events.join("\n")).unwrap_or_default()),
+        }
+    }
+
+    fn record(&mut self, event: String) {
+        if let Some(ref mut events) = self.events {
+            events.push(event);
+        }
+    }
+
+    fn fail(&mut self, error: String) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+}
+
+impl Observer for Recorder {
+    fn on_call(&mut self, function: &str, depth: usize) {
+        self.calls.push(function.into());
+        self.line = None;
+        if depth != self.calls.len() {
+            let expected = self.calls.len();
+            self.fail(format!("`{}` was called at depth {}, not {}", function, depth, expected));
+        }
+        self.record(format!("call {} at depth {}", function, depth));
+    }
+
+    fn on_return(&mut self, function: &str, depth: usize) {
+        if self.calls.last().map(String::as_str) != Some(function) {
+            self.fail(format!("`{}` returned, but it is not running", function));
+        } else if depth != self.calls.len() {
+            let expected = self.calls.len();
+            self.fail(format!("`{}` returned at depth {}, not {}", function, depth, expected));
+        }
+        self.calls.pop();
+        self.line = None;
+        self.record(format!("return {} at depth {}", function, depth));
+    }
+
+    fn on_statement(&mut self, function: &str, span: Span) {
+        let line = span.start.line + 1;
+        if self.calls.last().map(String::as_str) != Some(function) {
+            self.fail(format!("`{}` is not running, but ran line {}", function, line));
+        } else if self.line == Some(line) {
+            self.fail(format!("line {} in `{}` was reported twice", line, function));
+        }
+        self.line = Some(line);
+        self.record(format!("line {} in {}", line, function));
+    }
+
+    fn on_memory_write(&mut self, _address: u32, bytes: &[u8]) {
+        if bytes.is_empty() {
+            self.fail("empty memory write was reported".into());
+        }
+        self.record(format!("write \"{}\"", ::escape(bytes)));
+    }
+}
@@ -4,6 +4,12 @@ pub enum ParseError {
     DuplicateBinary(&'static str),
     NoOutput,
     ErrorsAndIo,
+    UnknownOption(String),
+    UnknownPass(String),
+    ErrorsInIr,
+    UnknownEmit(String),
+    MalformedStatus,
+    NoCommand,
 }
 
 impl ::std::fmt::Display for ParseError {
@@ -14,6 +20,12 @@ impl ::std::fmt::Display for ParseError {
             DuplicateBinary(name) => write!(f, "annotation `{}` appears multiple times", name),
             NoOutput => write!(f, "input is provided but not output"),
             ErrorsAndIo => write!(f, "test provides both build errors and io"),
+            UnknownOption(ref option) => write!(f, "unknown compile option `{}`", option),
+            UnknownPass(ref pass) => write!(f, "unknown optimization pass `{}`", pass),
+            ErrorsInIr => write!(f, "ir tests can expect only one parse error"),
+            UnknownEmit(ref emit) => write!(f, "unknown output `{}`", emit),
+            MalformedStatus => write!(f, "annotation `STATUS` is malformed"),
+            NoCommand => write!(f, "command line test has no `RUN` lines"),
         }
     }
 }
@@ -22,6 +34,17 @@ impl ::std::fmt::Display for ParseError {
 pub struct Error {
     pub line: u32,
     pub message: String,
+    /// Texts that must be found in the notes of the error.
+    pub notes: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub line: u32,
+    pub text: String,
+    /// If set, the text must not appear between previous
+    /// and next matching line, instead of being matched.
+    pub negated: bool,
 }
 
 #[derive(Debug)]
@@ -31,20 +54,27 @@ pub enum Expectation {
     /// Build should succeed, and when ran with given
     /// input program should produce given output.
     Io { input: Vec<u8>, output: Vec<u8> },
+    /// Build should succeed, and when ran with given input program should
+    /// produce given output and then fail with an error that matches given
+    /// lines, in order.
+    Trap { input: Vec<u8>, output: Vec<u8>, trace: Vec<Check> },
     /// Build should succeed, but program execution is not tested.
     BuildSuccess,
 }
 
 fn get_errors(source: &str) -> Vec<Error> {
     const ANNOTATION: &'static str = "// ERROR: ";
+    const NOTE: &str = " // NOTE: ";
     let mut errors = Vec::new();
     for (line_num, line) in source.lines().enumerate() {
         if let Some((index, _)) = line.match_indices(ANNOTATION).next() {
             let message_from = index + ANNOTATION.len();
-            let message = line[message_from..].into();
+            let mut parts = line[message_from..].split(NOTE);
+            let message = parts.next().unwrap().into();
             errors.push(Error {
                 line: line_num as u32,
                 message,
+                notes: parts.map(Into::into).collect(),
             })
         }
     }
@@ -93,18 +123,40 @@ fn get_io(source: &str, name: &'static str) -> Result<Option<Vec<u8>>, ParseErro
     Ok(annotation)
 }
 
+fn get_trace(source: &str) -> Vec<Check> {
+    const ANNOTATION: &str = "// TRAP: ";
+    let mut trace = Vec::new();
+    for (line_num, line) in source.lines().enumerate() {
+        if let Some((index, _)) = line.match_indices(ANNOTATION).next() {
+            trace.push(Check {
+                line: line_num as u32,
+                text: line[index + ANNOTATION.len()..].into(),
+                negated: false,
+            });
+        }
+    }
+    trace
+}
+
 pub fn parse_test(source: &str) -> Result<Expectation, ParseError> {
     let errors = get_errors(source);
     let input = get_io(source, "INPUT")?;
     let output = get_io(source, "OUTPUT")?;
-    if output.is_none() && input.is_some() {
+    let trace = get_trace(source);
+    if output.is_none() && input.is_some() && trace.is_empty() {
         return Err(ParseError::NoOutput);
     }
-    if (output.is_some() || input.is_some()) && !errors.is_empty() {
+    if (output.is_some() || input.is_some() || !trace.is_empty()) && !errors.is_empty() {
         return Err(ParseError::ErrorsAndIo);
     }
     Ok(if !errors.is_empty() {
         Expectation::BuildErrors(errors)
+    } else if !trace.is_empty() {
+        Expectation::Trap {
+            input: input.unwrap_or_else(Vec::new),
+            output: output.unwrap_or_else(Vec::new),
+            trace,
+        }
     } else if input.is_some() || output.is_some() {
         Expectation::Io {
             input: input.unwrap_or_else(Vec::new),
@@ -114,3 +166,185 @@ pub fn parse_test(source: &str) -> Result<Expectation, ParseError> {
         Expectation::BuildSuccess
     })
 }
+
+pub fn parse_options(source: &str) -> Result<::plank_frontend::Options, ParseError> {
+    const ANNOTATION: &str = "// OPTIONS: ";
+    let mut options = ::plank_frontend::Options::default();
+    for line in source.lines() {
+        if let Some((index, _)) = line.match_indices(ANNOTATION).next() {
+            for option in line[index + ANNOTATION.len()..].split_whitespace() {
+                // lint levels are given like `allow=dead_code`
+                let mut parts = option.splitn(2, '=');
+                let level = match parts.next() {
+                    Some("allow") => Some(::plank_errors::reporter::Level::Allow),
+                    Some("warn") => Some(::plank_errors::reporter::Level::Warn),
+                    Some("deny") => Some(::plank_errors::reporter::Level::Deny),
+                    _ => None,
+                };
+                if let (Some(level), Some(lint)) = (level, parts.next()) {
+                    options.lint_levels.push((lint.into(), level));
+                    continue;
+                }
+                match option {
+                    "implicit-widening" => options.implicit_widening = true,
+                    "wrapping-literals" => options.wrapping_literals = true,
+                    "target-x86_64" => options.target = ::plank_ir::target::X86_64,
+                    "bounds-checks" => options.bounds_checks = true,
+                    "overflow-trap" => options.overflow = Some(::plank_ir::ir::Overflow::Trap),
+                    "overflow-saturate" => {
+                        options.overflow = Some(::plank_ir::ir::Overflow::Saturate)
+                    }
+                    "deny-warnings" => options.deny_warnings = true,
+                    "tests" => options.tests = true,
+                    _ => return Err(ParseError::UnknownOption(option.into())),
+                }
+            }
+        }
+    }
+    Ok(options)
+}
+
+pub fn parse_passes(source: &str) -> Result<Vec<::plank_ir::optimization::Pass>, ParseError> {
+    const ANNOTATION: &str = "// PASSES: ";
+    let mut passes = Vec::new();
+    for line in source.lines() {
+        if let Some((index, _)) = line.match_indices(ANNOTATION).next() {
+            for name in line[index + ANNOTATION.len()..].split_whitespace() {
+                match ::plank_ir::optimization::find_pass(name) {
+                    Some(pass) => passes.push(pass),
+                    None => return Err(ParseError::UnknownPass(name.into())),
+                }
+            }
+        }
+    }
+    Ok(passes)
+}
+
+/// What the checks of an ir test are matched against.
+#[derive(Debug, Copy, Clone)]
+pub enum Emit {
+    /// Optimized ir.
+    Ir,
+    /// Control flow graph in DOT format, optionally with dominator tree.
+    Cfg { dominators: bool },
+    /// Assembly from the x86 backend, after peephole optimizations.
+    Asm,
+}
+
+pub fn parse_emit(source: &str) -> Result<Emit, ParseError> {
+    const ANNOTATION: &str = "// EMIT: ";
+    let mut emit = Emit::Ir;
+    for line in source.lines() {
+        if let Some((index, _)) = line.match_indices(ANNOTATION).next() {
+            emit = match line[index + ANNOTATION.len()..].trim() {
+                "ir" => Emit::Ir,
+                "cfg" => Emit::Cfg { dominators: false },
+                "cfg-dominators" => Emit::Cfg { dominators: true },
+                "asm" => Emit::Asm,
+                other => return Err(ParseError::UnknownEmit(other.into())),
+            };
+        }
+    }
+    Ok(emit)
+}
+
+/// Validation error that an ir test expects, given with `// INVALID: text`.
+pub fn parse_invalid(source: &str) -> Option<String> {
+    const ANNOTATION: &str = "// INVALID: ";
+    source.lines().find_map(|line| {
+        line.match_indices(ANNOTATION)
+            .next()
+            .map(|(index, _)| line[index + ANNOTATION.len()..].to_string())
+    })
+}
+
+/// Whether source tests are also compiled to native executables. Tests
+/// that can't behave the same outside of the interpreter give the reason
+/// with `// SKIP-NATIVE: reason`.
+pub fn runs_natively(source: &str) -> bool {
+    !source.lines().any(|line| line.contains("// SKIP-NATIVE: "))
+}
+
+/// Whether source tests are also compiled to C. C code is compiled for
+/// the pointer size of the machine that runs the tests, so tests that
+/// depend on the size of pointers are left out with `// SKIP-C: reason`.
+pub fn runs_as_c(source: &str) -> bool {
+    !source.lines().any(|line| line.contains("// SKIP-C: "))
+}
+
+/// Arguments given to `main`, after the program name which is
+/// always `test`.
+pub fn parse_args(source: &str) -> Vec<String> {
+    const ANNOTATION: &str = "// ARGS: ";
+    let mut args = vec!["test".to_string()];
+    for line in source.lines() {
+        if let Some((index, _)) = line.match_indices(ANNOTATION).next() {
+            args.extend(line[index + ANNOTATION.len()..].split_whitespace().map(String::from));
+        }
+    }
+    args
+}
+
+pub fn parse_checks(source: &str) -> Vec<Check> {
+    let mut checks = Vec::new();
+    for (line_num, line) in source.lines().enumerate() {
+        for &(annotation, negated) in &[("// CHECK: ", false), ("// CHECK-NOT: ", true)] {
+            if let Some((index, _)) = line.match_indices(annotation).next() {
+                checks.push(Check {
+                    line: line_num as u32,
+                    text: line[index + annotation.len()..].into(),
+                    negated,
+                });
+            }
+        }
+    }
+    checks
+}
+
+/// How a command line test runs plank, and what it should do.
+#[derive(Debug)]
+pub struct CommandTest {
+    /// Arguments of every run of plank, which all must behave the same.
+    pub runs: Vec<Vec<String>>,
+    pub input: Vec<u8>,
+    /// Exact output to stdout, if the test checks it.
+    pub output: Option<Vec<u8>>,
+    pub status: i32,
+    /// Checks that are matched against stdout, followed by stderr.
+    pub checks: Vec<Check>,
+}
+
+/// Parse a command line test. Arguments are given with `// RUN: args`,
+/// where `%s` stands for `path` of the test file itself, and the
+/// expected exit code with `// STATUS: code`, which defaults to 0.
+pub fn parse_command_test(source: &str, path: &str) -> Result<CommandTest, ParseError> {
+    const RUN: &str = "// RUN: ";
+    const STATUS: &str = "// STATUS: ";
+    let mut runs = Vec::new();
+    let mut status = 0;
+    for line in source.lines() {
+        if let Some((index, _)) = line.match_indices(RUN).next() {
+            let args = line[index + RUN.len()..]
+                .split_whitespace()
+                .map(|arg| arg.replace("%s", path))
+                .collect();
+            runs.push(args);
+        }
+        if let Some((index, _)) = line.match_indices(STATUS).next() {
+            status = line[index + STATUS.len()..]
+                .trim()
+                .parse()
+                .map_err(|_| ParseError::MalformedStatus)?;
+        }
+    }
+    if runs.is_empty() {
+        return Err(ParseError::NoCommand);
+    }
+    Ok(CommandTest {
+        runs,
+        input: get_io(source, "INPUT")?.unwrap_or_else(Vec::new),
+        output: get_io(source, "OUTPUT")?,
+        status,
+        checks: parse_checks(source),
+    })
+}